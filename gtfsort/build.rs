@@ -1,8 +1,79 @@
-use std::env;
+use std::{env, path::Path, process::Command};
 
 use cbindgen::Config;
 
+/// Runs git in the crate directory, returning its trimmed output if it succeeded.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Embeds the build metadata read by gtfsort::version.
+fn embed_build_info() {
+    // source archives have no .git, packagers can set the commit themselves
+    let commit = env::var("GTFSORT_GIT_COMMIT")
+        .ok()
+        .or_else(|| {
+            let commit = git(&["rev-parse", "--short=12", "HEAD"])?;
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])?;
+            Some(match dirty.is_empty() {
+                true => commit,
+                false => commit + "-dirty",
+            })
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GTFSORT_GIT_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=GTFSORT_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
+    println!(
+        "cargo:rustc-env=GTFSORT_PROFILE={}",
+        env::var("PROFILE").unwrap()
+    );
+
+    // every enabled entry of [features], so none can be left out of the version report
+    let mut features = env::vars()
+        .filter_map(|(k, _)| Some(k.strip_prefix("CARGO_FEATURE_")?.to_lowercase()))
+        .filter(|f| f != "default")
+        .collect::<Vec<_>>();
+    features.sort_unstable();
+    println!("cargo:rustc-env=GTFSORT_FEATURES={}", features.join(","));
+
+    // listing paths replaces the default of rerunning on any change in the package, so
+    // the sources read by cbindgen are listed too
+    println!("cargo:rerun-if-env-changed=GTFSORT_GIT_COMMIT");
+    for path in [
+        "build.rs",
+        "Cargo.toml",
+        "cbindgen.toml",
+        "cbindgen_cxx.toml",
+        "src",
+    ] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    for name in ["HEAD", "index"] {
+        if let Some(path) =
+            git(&["rev-parse", "--git-path", name]).filter(|p| Path::new(p).exists())
+        {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    if let Some(path) = git(&["symbolic-ref", "-q", "HEAD"])
+        .and_then(|r| git(&["rev-parse", "--git-path", &r]))
+        .filter(|p| Path::new(p).exists())
+    {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
+
 fn main() {
+    embed_build_info();
+
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
 
     cbindgen::Builder::new()
@@ -1,5 +1,6 @@
 use clap::Parser;
 use flate2::read::GzDecoder;
+use gtfsort::test_utils::synthetic_scaffolds_gtf;
 use reqwest::blocking::{get, ClientBuilder};
 use std::env;
 use std::fs::File;
@@ -32,6 +33,16 @@ pub struct Args {
         default_value = "4"
     )]
     threads: u32,
+    #[clap(
+        long = "scaffolds",
+        help = "Benchmark a synthetic input with this many scaffolds (e.g. 500000) instead of GENCODE, to time the ordering of many contig names"
+    )]
+    scaffolds: Option<usize>,
+    #[clap(
+        long = "compress-lines",
+        help = "Time this commit with --compress-lines never and always instead of comparing against the reference commit, peak memory is in the logged output"
+    )]
+    compress_lines: bool,
     #[clap(help = "Extra arguments to pass to hyperfine")]
     hyperfine_args: Vec<String>,
 }
@@ -201,29 +212,55 @@ fn benchmark() -> Result<(String, String), Box<dyn std::error::Error>> {
 
     std::fs::create_dir_all("tests")?;
 
-    let test_file = Path::new(TEST_FILE);
+    let (test_file, output_ext) = match args.scaffolds {
+        Some(n) => (format!("tests/synthetic_scaffolds_{}.gtf", n), "gtf"),
+        None => (TEST_FILE.to_string(), "gff3"),
+    };
+    let test_file = Path::new(&test_file);
 
     if !test_file.exists() {
-        let mut response = GzDecoder::new(get(TEST_URL)?);
-        let mut file = File::create(test_file)?;
+        match args.scaffolds {
+            Some(n) => std::fs::write(test_file, synthetic_scaffolds_gtf(n, 42))?,
+            None => {
+                let mut response = GzDecoder::new(get(TEST_URL)?);
+                let mut file = File::create(test_file)?;
 
-        std::io::copy(&mut response, &mut file)?;
+                std::io::copy(&mut response, &mut file)?;
+            }
+        }
     }
 
     let current_location = run_git(&["rev-parse", "--abbrev-ref", "HEAD"])
         .map(|s| s.trim().to_string())
         .expect("Failed to get current commit");
 
+    let mut parameters = vec![(
+        "commit".to_string(),
+        vec![format!("this={}", current_location)],
+    )];
+    let (mut sort_args, mut label) = ("", "$short_name");
+    if args.compress_lines {
+        parameters.push((
+            "lines".to_string(),
+            vec!["never".to_string(), "always".to_string()],
+        ));
+        (sort_args, label) = (" --compress-lines {lines}", "$short_name-{lines}");
+    } else {
+        parameters[0]
+            .1
+            .insert(0, format!("ref={}", args.compare_to));
+    }
+
     #[allow(clippy::needless_update)]
     let code =  HyperfineCall {
         warmup: 3,
         min_runs: 5,
         export_csv: Some("tests/benchmark_file.csv".to_string()),
         export_markdown: Some("tests/benchmark_file.md".to_string()),
-        parameters: vec![("commit".to_string(), vec![format!("ref={}", args.compare_to), format!("this={}", current_location)])],
+        parameters,
         setup: Some("short_name=$(echo '{commit}' | cut -d= -f1); git checkout -B benchmark $(echo '{commit}' | cut -d= -f2) && cargo build --release".to_string()),
         cleanup: Some("cargo clean".to_string()),
-        command: format!("short_name=$(echo '{{commit}}' | cut -d= -f1); {} -i '{}' -o tests/output_${{short_name}}.gff3 -t {} 2>&1 | awk -v name=$short_name '{{ print \"[\"name\" -> file] \" $0 }}' | tee -a '{}'", TARGET_EXEC, TEST_FILE, num_threads, STDOUT_FILE),
+        command: format!("short_name=$(echo '{{commit}}' | cut -d= -f1); {} -i '{}' -o tests/output_${{short_name}}.{} -t {}{} 2>&1 | awk -v name={} '{{ print \"[\"name\" -> file] \" $0 }}' | tee -a '{}'", TARGET_EXEC, test_file.display(), output_ext, num_threads, sort_args, label, STDOUT_FILE),
         extras: args.hyperfine_args,
         ..Default::default()
     }.invoke().code().expect("Benchmark terminated unexpectedly");
@@ -52,6 +52,14 @@ std::ostream &operator<<(std::ostream &os, const SortAnnotationRetWrapper &ret)
     {
         const auto &err = *ret.ret->err._0;
         os << "Err: code=" << err.code << ", message=" << err.message;
+        if (err.phase)
+            os << ", phase=" << err.phase;
+        if (err.chrom)
+            os << ", chrom=" << err.chrom;
+        if (err.line >= 0)
+            os << ", line=" << err.line;
+        if (err.byte_offset >= 0)
+            os << ", byte_offset=" << err.byte_offset;
     }
 
     return os;
@@ -151,5 +159,35 @@ int main(int argc, char **argv)
 
     PANIC_IF(!cmp_files(output, output2), "Files are not the same");
 
+    std::cout << "Pulling sorted chunks from string to " << output2 << std::endl;
+
+    std::ofstream chunks_file(output2, std::ios::trunc);
+    PANIC_IF(!chunks_file.good(), "Failed to open output file");
+
+    auto chunks = gtfsort_sorted_chunks_new(GTFSORT_PARSE_MODE_GFF3, input_str.c_str(), 2, ret.ret);
+    PANIC_IF(chunks == nullptr, "Failed to start sorting chunks");
+
+    const char *data;
+    unsigned long len;
+    while (gtfsort_sorted_chunks_next(chunks, &data, &len, ret.ret))
+        chunks_file.write(data, len);
+    gtfsort_sorted_chunks_free(chunks);
+    PANIC_IF(ret.ret->tag != SortAnnotationsRet::Tag::Ok, "Failed to pull sorted chunks");
+
+    std::cout << "Chunks process result: " << ret << std::endl;
+
+    chunks_file.close();
+    PANIC_IF(chunks_file.fail(), "Failed to close output file");
+
+    PANIC_IF(!cmp_files(output, output2), "Chunked output is not the same");
+
+    std::cout << "Reporting invalid arguments, panics " << (gtfsort_panic_aborts() ? "abort" : "are caught") << std::endl;
+
+    PANIC_IF(gtfsort_sort_annotations(input, nullptr, 1, ret.ret), "Expected a null output path to fail");
+    PANIC_IF(ret.ret->tag != SortAnnotationsRet::Tag::Err, "Expected an error for a null output path");
+    PANIC_IF(ret.ret->err._0->code != GTFSORT_ERROR_INVALID_INPUT, "Expected an invalid input error");
+
+    std::cout << "Invalid arguments result: " << ret << std::endl;
+
     return 0;
 }
\ No newline at end of file
@@ -0,0 +1,173 @@
+//! Runs the gtfsort binary the way users do, on small annotations written to a temporary
+//! directory.
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+use gtfsort::temp::TempPath;
+
+const GTF: &str = "chr2\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"B\";\n\
+    chr1\ts\texon\t10\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+    chr1\ts\texon\t30\t40\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\";\n";
+
+fn run(args: &[&str], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gtfsort"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// Runs gtfsort with `input` piped to its stdin, checking that it succeeded.
+fn gtfsort_piped(args: &[&str], dir: &Path, input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gtfsort"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "gtfsort {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn gtfsort(args: &[&str], dir: &Path) -> Output {
+    let output = run(args, dir);
+    assert!(
+        output.status.success(),
+        "gtfsort {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn synthesized_output_verifies() {
+    let dir = TempPath::dir(None, "cli_test").unwrap();
+    std::fs::write(dir.join("in.gtf"), GTF).unwrap();
+
+    gtfsort(
+        &["-i", "in.gtf", "-o", "synth.gtf", "--synthesize-parents"],
+        &dir,
+    );
+    gtfsort(&["-i", "synth.gtf", "-o", "out.gtf", "--verify"], &dir);
+
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out.gtf")).unwrap(),
+        std::fs::read_to_string(dir.join("synth.gtf")).unwrap()
+    );
+}
+
+#[test]
+fn sorted_input_still_gets_its_side_outputs() {
+    let dir = TempPath::dir(None, "cli_test").unwrap();
+    std::fs::write(dir.join("in.gtf"), GTF).unwrap();
+    gtfsort(&["-i", "in.gtf", "-o", "sorted.gtf"], &dir);
+
+    gtfsort(
+        &[
+            "-i",
+            "sorted.gtf",
+            "-o",
+            "out.gtf",
+            "--skip-if-sorted",
+            "--gene-order",
+            "order.tsv",
+            "--quality-report",
+            "quality.json",
+        ],
+        &dir,
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out.gtf")).unwrap(),
+        std::fs::read_to_string(dir.join("sorted.gtf")).unwrap()
+    );
+    let order = std::fs::read_to_string(dir.join("order.tsv")).unwrap();
+    assert!(order.lines().count() > 1, "{}", order);
+    assert!(dir.join("quality.json").exists());
+}
+
+#[test]
+fn invert_needs_an_id_list() {
+    let dir = TempPath::dir(None, "cli_test").unwrap();
+    std::fs::write(dir.join("in.gtf"), GTF).unwrap();
+    std::fs::write(dir.join("ids.txt"), "A.1\n").unwrap();
+
+    let output = run(&["-i", "in.gtf", "-o", "out.gtf", "--invert"], &dir);
+    assert!(!output.status.success());
+    assert!(!dir.join("out.gtf").exists());
+
+    gtfsort(
+        &[
+            "-i",
+            "in.gtf",
+            "-o",
+            "out.gtf",
+            "--transcripts",
+            "ids.txt",
+            "--invert",
+        ],
+        &dir,
+    );
+    let out = std::fs::read_to_string(dir.join("out.gtf")).unwrap();
+    assert!(!out.contains("A.1"), "{}", out);
+}
+
+#[test]
+fn pipes_through_stdin_and_stdout() {
+    let dir = TempPath::dir(None, "cli_test").unwrap();
+    std::fs::write(dir.join("in.gtf"), GTF).unwrap();
+    gtfsort(&["-i", "in.gtf", "-o", "out.gtf"], &dir);
+    let expected = std::fs::read(dir.join("out.gtf")).unwrap();
+
+    // the format of stdin is detected from its attributes
+    let piped = gtfsort_piped(&["-i", "-", "-o", "-"], &dir, GTF);
+    assert_eq!(piped.stdout, expected);
+
+    // stdout is the default output
+    let piped = gtfsort_piped(&["-i", "-", "--format", "gtf"], &dir, GTF);
+    assert_eq!(piped.stdout, expected);
+
+    let to_stdout = gtfsort(&["-i", "in.gtf", "-o", "-"], &dir);
+    assert_eq!(to_stdout.stdout, expected);
+
+    gtfsort_piped(&["-i", "-", "-o", "piped.gtf"], &dir, GTF);
+    assert_eq!(std::fs::read(dir.join("piped.gtf")).unwrap(), expected);
+}
+
+#[test]
+fn pipes_gff3_through_stdin() {
+    const GFF3: &str = "##gff-version 3\n\
+        chr2\ts\tgene\t1\t90\t.\t+\t.\tID=B\n\
+        chr1\ts\texon\t10\t20\t.\t+\t.\tID=e1;Parent=A.1\n\
+        chr1\ts\tmRNA\t10\t40\t.\t+\t.\tID=A.1;Parent=A\n\
+        chr1\ts\tgene\t10\t40\t.\t+\t.\tID=A\n";
+    let dir = TempPath::dir(None, "cli_test").unwrap();
+
+    let piped = gtfsort_piped(&["-i", "-", "-o", "-"], &dir, GFF3);
+    let lines = String::from_utf8(piped.stdout).unwrap();
+    let features = lines
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .map(|l| l.split('\t').nth(2).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(features, ["gene", "mRNA", "exon", "gene"]);
+    assert!(lines.starts_with("##gff-version 3\n"), "{}", lines);
+}
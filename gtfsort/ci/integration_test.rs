@@ -100,3 +100,59 @@ fn test_gencode_m35_subset_prevent_mmap_single_thread() {
 fn test_gencode_m35_subset_prevent_mmap_max_threads() {
     test_gencode_m35_subset_with_n_threads(num_cpus::get(), true);
 }
+
+#[test]
+fn test_synthetic_fixture_file_and_string_agree() {
+    ensure_logger_initialized();
+
+    let chroms = ["chr10", "chr2", "chrX", "chr1"];
+    let input = synthetic_gtf_file(
+        &format!(
+            "{}.gtf",
+            current_func!().replace(|c: char| !c.is_alphanumeric(), "_")
+        ),
+        &chroms,
+        50,
+        42,
+    );
+    assert_eq!(
+        std::fs::read_to_string(&*input).unwrap(),
+        synthetic_gtf(&chroms, 50, 42)
+    );
+
+    let output = TempFile::new(
+        &format!(
+            "{}.sorted.gtf",
+            current_func!().replace(|c: char| !c.is_alphanumeric(), "_")
+        ),
+        true,
+    );
+    sort_annotations(&input, &output, 2).expect("Failed to sort annotations");
+    let from_file = std::fs::read(&*output).unwrap();
+
+    let mut from_string = Vec::new();
+    sort_annotations_string::<b' ', _>(
+        &synthetic_gtf(&chroms, 50, 42),
+        &mut |b| {
+            from_string.extend_from_slice(b);
+            Ok(b.len())
+        },
+        1,
+    )
+    .expect("Failed to sort annotations");
+
+    assert_eq!(crc32_hex(&from_file[..]), crc32_hex(&from_string[..]));
+
+    let text = String::from_utf8(from_file).unwrap();
+    let order =
+        text.lines()
+            .map(|l| l.split('\t').next().unwrap())
+            .fold(Vec::new(), |mut acc, c| {
+                if acc.last() != Some(&c) {
+                    acc.push(c);
+                }
+                acc
+            });
+    assert_eq!(order, ["chr1", "chr2", "chr10", "chrX"]);
+    assert_eq!(text.lines().count(), chroms.len() * 50 * 10);
+}
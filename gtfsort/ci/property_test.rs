@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+
+use gtfsort::{ord::NaturalKey, presorted::is_sorted, sort_annotations_string};
+use proptest::prelude::*;
+
+const CHROMS: [&str; 5] = ["chr1", "chr2", "chr10", "chrX", "KI270728.1"];
+
+/// (chromosome index, transcripts per gene (exon count, coding)).
+type GeneSpec = (usize, Vec<(u32, bool)>);
+
+fn attr(key: &str, value: &str) -> String {
+    format!("{} \"{}\";", key, value)
+}
+
+/// Builds a valid, hierarchically complete GTF from the given gene specs.
+///
+/// Gene starts are unique so that the expected output is fully determined by the input.
+fn build_gtf(genes: &[GeneSpec]) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for (g, (chrom, transcripts)) in genes.iter().enumerate() {
+        let chrom = CHROMS[*chrom];
+        let gene_id = format!("G{}", g);
+        let gene_start = 1_000 + g as u32 * 100_000;
+        let max_exons = transcripts.iter().map(|t| t.0).max().unwrap_or(1);
+        let gene_end = gene_start + max_exons * 1_000 - 1;
+
+        lines.push(format!(
+            "{}\ttest\tgene\t{}\t{}\t.\t+\t.\t{}",
+            chrom,
+            gene_start,
+            gene_end,
+            attr("gene_id", &gene_id)
+        ));
+
+        for (t, (exons, coding)) in transcripts.iter().enumerate() {
+            let transcript_id = format!("{}.{}", gene_id, t);
+            let ids = format!(
+                "{} {}",
+                attr("gene_id", &gene_id),
+                attr("transcript_id", &transcript_id)
+            );
+            let tx_start = gene_start + t as u32;
+            let tx_end = gene_start + exons * 1_000 - 1;
+
+            lines.push(format!(
+                "{}\ttest\ttranscript\t{}\t{}\t.\t+\t.\t{}",
+                chrom, tx_start, tx_end, ids
+            ));
+
+            for e in 0..*exons {
+                let start = if e == 0 {
+                    tx_start
+                } else {
+                    gene_start + e * 1_000
+                };
+                let end = gene_start + e * 1_000 + 499;
+                let number = attr("exon_number", &(e + 1).to_string());
+
+                lines.push(format!(
+                    "{}\ttest\texon\t{}\t{}\t.\t+\t.\t{} {}",
+                    chrom, start, end, ids, number
+                ));
+
+                if *coding {
+                    lines.push(format!(
+                        "{}\ttest\tCDS\t{}\t{}\t.\t+\t0\t{} {}",
+                        chrom,
+                        start + 10,
+                        end,
+                        ids,
+                        number
+                    ));
+                }
+            }
+
+            if *coding {
+                lines.push(format!(
+                    "{}\ttest\tthree_prime_utr\t{}\t{}\t.\t+\t.\t{}",
+                    chrom, tx_end, tx_end, ids
+                ));
+            }
+        }
+    }
+
+    lines
+}
+
+fn sort(input: &str, threads: usize) -> String {
+    let mut output = Vec::new();
+    sort_annotations_string::<b' ', _>(
+        input,
+        &mut |b| {
+            output.extend_from_slice(b);
+            Ok(b.len())
+        },
+        threads,
+    )
+    .expect("Failed to sort annotations");
+
+    String::from_utf8(output).unwrap()
+}
+
+fn attr_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.rsplit('\t')
+        .next()?
+        .split(';')
+        .map(str::trim)
+        .find_map(|kv| kv.strip_prefix(key)?.strip_prefix(' '))
+        .map(|v| v.trim_matches('"'))
+}
+
+/// Asserts that every gene forms one contiguous block and every child follows its transcript.
+fn assert_hierarchical(output: &str) {
+    let mut seen_genes = HashSet::new();
+    let mut current_gene = None;
+    let mut current_transcript = None;
+
+    for line in output.lines() {
+        let feature = line.split('\t').nth(2).unwrap();
+        let gene_id = attr_value(line, "gene_id").unwrap();
+
+        match feature {
+            "gene" => {
+                assert!(seen_genes.insert(gene_id), "gene {} split", gene_id);
+                current_gene = Some(gene_id);
+                current_transcript = None;
+            }
+            "transcript" => {
+                assert_eq!(current_gene, Some(gene_id), "misplaced: {}", line);
+                current_transcript = attr_value(line, "transcript_id");
+            }
+            _ => {
+                assert_eq!(current_gene, Some(gene_id), "misplaced: {}", line);
+                assert_eq!(
+                    current_transcript,
+                    attr_value(line, "transcript_id"),
+                    "misplaced: {}",
+                    line
+                );
+            }
+        }
+    }
+}
+
+fn gene_spec() -> impl Strategy<Value = GeneSpec> {
+    (
+        0..CHROMS.len(),
+        prop::collection::vec((1u32..6, any::<bool>()), 1..4),
+    )
+}
+
+fn shuffled_gtf() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec(gene_spec(), 1..24)
+        .prop_flat_map(|genes| Just(build_gtf(&genes)).prop_shuffle())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn sorting_preserves_lines(lines in shuffled_gtf(), threads in 1usize..5) {
+        let input = lines.join("\n") + "\n";
+        let output = sort(&input, threads);
+
+        let mut expected = lines.clone();
+        let mut got = output.lines().map(str::to_string).collect::<Vec<_>>();
+        expected.sort();
+        got.sort();
+
+        prop_assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn sorting_groups_hierarchy(lines in shuffled_gtf(), threads in 1usize..5) {
+        let input = lines.join("\n") + "\n";
+        let output = sort(&input, threads);
+
+        assert_hierarchical(&output);
+        prop_assert!(is_sorted::<b' '>(&output));
+    }
+
+    #[test]
+    fn sorting_is_idempotent(lines in shuffled_gtf(), threads in 1usize..5) {
+        let input = lines.join("\n") + "\n";
+        let once = sort(&input, threads);
+        let twice = sort(&once, threads);
+
+        prop_assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn natural_key_matches_natord(a in "[a0-9 ._\t\u{a0}é]{0,12}", b in "[a0-9 ._\t\u{a0}é]{0,12}") {
+        prop_assert_eq!(NaturalKey::new(&a).cmp(&NaturalKey::new(&b)), natord::compare(&a, &b));
+    }
+}
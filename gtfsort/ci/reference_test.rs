@@ -0,0 +1,113 @@
+//! Sorts subsets of public annotations in the dialects gtfsort is expected to handle
+//! (GENCODE GTF, RefSeq GFF3, TAIR10 GFF), so format regressions are caught before a
+//! release. The datasets are downloaded on first use, see [REFERENCE_DATASETS].
+//!
+//! ```sh
+//! cargo test --features reference_datasets --test reference_test -- --nocapture
+//! ```
+//!
+//! A dataset without a recorded checksum fails; run once with `GTFSORT_RECORD_CKSUM=1`
+//! from a trusted build and copy the printed values into its `expect_output_cksum`.
+
+use std::io::Read;
+
+use gtfsort::{
+    sort_annotations_string_with_options, sort_annotations_with_options, temp::TempPath,
+    test_utils::*, AnnotationFormat, SortAnnotationsJobResult, SortOptions,
+};
+
+fn sort_string<'a>(
+    input: &'a str,
+    format: AnnotationFormat,
+    options: &SortOptions,
+) -> (Vec<u8>, SortAnnotationsJobResult<'a>) {
+    let mut output = Vec::new();
+    let mut write = |b: &[u8]| {
+        output.extend_from_slice(b);
+        Ok(b.len())
+    };
+    let job = match format {
+        AnnotationFormat::Gtf => {
+            sort_annotations_string_with_options::<b' ', _>(input, &mut write, 2, options)
+        }
+        AnnotationFormat::Gff3 => {
+            sort_annotations_string_with_options::<b'=', _>(input, &mut write, 2, options)
+        }
+    }
+    .expect("Failed to sort annotations");
+    (output, job)
+}
+
+fn check_dataset(dataset: &'static ReferenceDataset) {
+    ensure_logger_initialized();
+
+    dataset.execute_test(|s| {
+        let options = SortOptions {
+            format: Some(dataset.format),
+            ..Default::default()
+        };
+
+        let output = TempPath::file(None, "reference", ".sorted").unwrap();
+        sort_annotations_with_options(s.as_ref(), &output, num_cpus::get(), &options)
+            .expect("Failed to sort annotations");
+        let from_file = std::fs::read(&output).unwrap();
+
+        let input = std::fs::read_to_string(s).unwrap();
+        let (from_string, job) = sort_string(&input, dataset.format, &options);
+        assert!(job.lines.records > 0, "{}: no records", dataset.name);
+        assert_eq!(
+            from_file, from_string,
+            "{}: file and string output differ",
+            dataset.name
+        );
+        assert_eq!(
+            from_file
+                .split(|b| *b == b'\n')
+                .filter(|l| !l.is_empty())
+                .count(),
+            job.lines.records,
+            "{}: records lost or duplicated",
+            dataset.name
+        );
+
+        let sorted = String::from_utf8(from_file).unwrap();
+        let (again, _) = sort_string(&sorted, dataset.format, &options);
+        assert!(
+            again == sorted.as_bytes(),
+            "{}: sorting the output again changed it",
+            dataset.name
+        );
+
+        crc32_hex(sorted.as_bytes())
+    });
+}
+
+#[test]
+fn test_reference_gencode_human_46() {
+    check_dataset(&REFERENCE_GTF_GENCODE_HUMAN_46);
+}
+
+#[test]
+fn test_reference_refseq_grch38_gff3() {
+    check_dataset(&REFERENCE_GFF3_REFSEQ_GRCH38);
+}
+
+#[test]
+fn test_reference_tair10_gff() {
+    check_dataset(&REFERENCE_GFF_TAIR10);
+}
+
+#[test]
+fn test_reference_fixture_transformers() {
+    let gff3 = "##gff-version 3\nChr4\tTAIR10\tgene\t1\t9\t.\t+\t.\tID=A\n\
+        Chr1\tTAIR10\tgene\t1\t9\t.\t+\t.\tID=B\n##FASTA\n>Chr4\nACGT\n";
+
+    let mut out = String::new();
+    OnlyChromosomes::new(stop_at_fasta(gff3.as_bytes()), &["Chr4"])
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(
+        out,
+        "##gff-version 3\nChr4\tTAIR10\tgene\t1\t9\t.\t+\t.\tID=A\n"
+    );
+}
@@ -7,6 +7,8 @@
 #include <stdlib.h>
 
 
+#define GTFSORT_ERROR_INTERNAL 8
+
 #define GTFSORT_ERROR_INVALID_INPUT 1
 
 #define GTFSORT_ERROR_INVALID_OUTPUT 2
@@ -17,14 +19,25 @@
 
 #define GTFSORT_ERROR_IO_ERROR 5
 
+#define GTFSORT_ERROR_MEMORY_LIMIT_EXCEEDED 6
+
 #define GTFSORT_ERROR_PARSE_ERROR 3
 
+#define GTFSORT_ERROR_TIMEOUT 7
+
+#define GTFSORT_ERROR_VERIFICATION_FAILED 9
+
 #define GTFSORT_PARSE_MODE_GFF 2
 
 #define GTFSORT_PARSE_MODE_GFF3 2
 
 #define GTFSORT_PARSE_MODE_GTF 1
 
+/**
+ * Output of a string sort pulled chunk by chunk, see [gtfsort_sorted_chunks_new].
+ */
+typedef struct SortedChunksFFI SortedChunksFFI;
+
 typedef struct SortAnnotationsJobResultFFI {
   const char *input;
   const char *output;
@@ -36,11 +49,23 @@ typedef struct SortAnnotationsJobResultFFI {
   double writing_secs;
   double start_mem_mb;
   double end_mem_mb;
+  bool already_sorted;
 } SortAnnotationsJobResultFFI;
 
+/**
+ * An error of a job. `code` is one of the `GTFSORT_ERROR_*` constants, which keep
+ * their values across releases, see [crate::ErrorCode].
+ *
+ * The context fields tell where the error happened: `phase` and `chrom` are null and
+ * `line` and `byte_offset` are -1 when unknown.
+ */
 typedef struct GtfSortErrorFFI {
   int32_t code;
   const char *message;
+  const char *phase;
+  const char *chrom;
+  int64_t line;
+  int64_t byte_offset;
 } GtfSortErrorFFI;
 
 typedef enum SortAnnotationsRet_Tag {
@@ -70,12 +95,15 @@ void gtfsort_free_sort_annotations_ret(struct SortAnnotationsRet *ret);
 
 /**
  * Initializes the logger with the given log level.
- * The log level must be one of the following: trace, debug, info, warn, error.
+ * The log level must be one of the following: trace, debug, info, warn, error,
+ * unknown levels fall back to info.
+ *
+ * Returns false without changing anything if the host already set a logger.
  *
  * # Safety
  * level must be a valid C string.
  */
-void gtfsort_init_logger(const char *level);
+bool gtfsort_init_logger(const char *level);
 
 /**
  * Allocates a new [SortAnnotationsRet] on the Rust heap.
@@ -86,6 +114,14 @@ void gtfsort_init_logger(const char *level);
  */
 struct SortAnnotationsRet *gtfsort_new_sort_annotations_ret(void);
 
+/**
+ * Returns true if this library was built with `panic = "abort"`, so that a bug in
+ * gtfsort aborts the process instead of being reported as a
+ * [GTFSORT_ERROR_INTERNAL] error. Hosts that need either behavior can check it at
+ * startup.
+ */
+bool gtfsort_panic_aborts(void);
+
 /**
  * Sorts the annotations in the given GTF or GFF3 file and writes the result to the output file.
  *
@@ -137,3 +173,51 @@ bool gtfsort_sort_annotations_gtf_str(uint8_t mode,
                                       size_t threads,
                                       void *caller_data,
                                       struct SortAnnotationsRet *result_ptr);
+
+/**
+ * Stops the sort if it is still running and frees the handle.
+ *
+ * # Safety
+ * chunks must be a handle returned by [gtfsort_sorted_chunks_new], or null.
+ */
+void gtfsort_sorted_chunks_free(struct SortedChunksFFI *chunks);
+
+/**
+ * Starts sorting the annotations in the given GTF or GFF3 string in the background,
+ * returning a handle to pull the sorted output from with [gtfsort_sorted_chunks_next]
+ * instead of receiving it through a callback.
+ *
+ * The mode must be one of the following:
+ * - [GTFSORT_PARSE_MODE_GTF]
+ * - [GTFSORT_PARSE_MODE_GFF3]
+ * - [GTFSORT_PARSE_MODE_GFF]
+ *
+ * The input is copied, so it can be freed once this returns. Returns null if the mode
+ * is invalid or the input is not UTF-8, setting result_ptr (if not null) to the error.
+ *
+ * # Safety
+ * input must be a valid C string. The handle must be freed with
+ * [gtfsort_sorted_chunks_free].
+ */
+struct SortedChunksFFI *gtfsort_sorted_chunks_new(uint8_t mode,
+                                                  const char *input,
+                                                  size_t threads,
+                                                  struct SortAnnotationsRet *result_ptr);
+
+/**
+ * Waits for the next chunk of sorted output. Chunks are not NUL-terminated and may end
+ * in the middle of a line.
+ *
+ * Returns true and points data and len at the chunk, which stays valid until the next
+ * call or [gtfsort_sorted_chunks_free]. Returns false once the output is complete or
+ * the sort failed, setting result_ptr (if not null) to the result of the job, and on
+ * every call after that.
+ *
+ * # Safety
+ * chunks must be a handle returned by [gtfsort_sorted_chunks_new], data and len
+ * valid pointers.
+ */
+bool gtfsort_sorted_chunks_next(struct SortedChunksFFI *chunks,
+                                const char **data,
+                                unsigned long *len,
+                                struct SortAnnotationsRet *result_ptr);
@@ -8,6 +8,8 @@
 #include <new>
 
 
+static const int32_t GTFSORT_ERROR_INTERNAL = 8;
+
 static const int32_t GTFSORT_ERROR_INVALID_INPUT = 1;
 
 static const int32_t GTFSORT_ERROR_INVALID_OUTPUT = 2;
@@ -18,14 +20,23 @@ static const int32_t GTFSORT_ERROR_INVALID_THREADS = 4;
 
 static const int32_t GTFSORT_ERROR_IO_ERROR = 5;
 
+static const int32_t GTFSORT_ERROR_MEMORY_LIMIT_EXCEEDED = 6;
+
 static const int32_t GTFSORT_ERROR_PARSE_ERROR = 3;
 
+static const int32_t GTFSORT_ERROR_TIMEOUT = 7;
+
+static const int32_t GTFSORT_ERROR_VERIFICATION_FAILED = 9;
+
 static const uint8_t GTFSORT_PARSE_MODE_GFF = 2;
 
 static const uint8_t GTFSORT_PARSE_MODE_GFF3 = 2;
 
 static const uint8_t GTFSORT_PARSE_MODE_GTF = 1;
 
+/// Output of a string sort pulled chunk by chunk, see [gtfsort_sorted_chunks_new].
+struct SortedChunksFFI;
+
 struct SortAnnotationsJobResultFFI {
   const char *input;
   const char *output;
@@ -37,6 +48,7 @@ struct SortAnnotationsJobResultFFI {
   double writing_secs;
   double start_mem_mb;
   double end_mem_mb;
+  bool already_sorted;
 
   SortAnnotationsJobResultFFI(const char *const& input,
                               const char *const& output,
@@ -47,7 +59,8 @@ struct SortAnnotationsJobResultFFI {
                               double const& indexing_secs,
                               double const& writing_secs,
                               double const& start_mem_mb,
-                              double const& end_mem_mb)
+                              double const& end_mem_mb,
+                              bool const& already_sorted)
     : input(input),
       output(output),
       threads(threads),
@@ -57,19 +70,37 @@ struct SortAnnotationsJobResultFFI {
       indexing_secs(indexing_secs),
       writing_secs(writing_secs),
       start_mem_mb(start_mem_mb),
-      end_mem_mb(end_mem_mb)
+      end_mem_mb(end_mem_mb),
+      already_sorted(already_sorted)
   {}
 
 };
 
+/// An error of a job. `code` is one of the `GTFSORT_ERROR_*` constants, which keep
+/// their values across releases, see [crate::ErrorCode].
+///
+/// The context fields tell where the error happened: `phase` and `chrom` are null and
+/// `line` and `byte_offset` are -1 when unknown.
 struct GtfSortErrorFFI {
   int32_t code;
   const char *message;
+  const char *phase;
+  const char *chrom;
+  int64_t line;
+  int64_t byte_offset;
 
   GtfSortErrorFFI(int32_t const& code,
-                  const char *const& message)
+                  const char *const& message,
+                  const char *const& phase,
+                  const char *const& chrom,
+                  int64_t const& line,
+                  int64_t const& byte_offset)
     : code(code),
-      message(message)
+      message(message),
+      phase(phase),
+      chrom(chrom),
+      line(line),
+      byte_offset(byte_offset)
   {}
 
 };
@@ -115,11 +146,14 @@ extern "C" {
 void gtfsort_free_sort_annotations_ret(SortAnnotationsRet *ret);
 
 /// Initializes the logger with the given log level.
-/// The log level must be one of the following: trace, debug, info, warn, error.
+/// The log level must be one of the following: trace, debug, info, warn, error,
+/// unknown levels fall back to info.
+///
+/// Returns false without changing anything if the host already set a logger.
 ///
 /// # Safety
 /// level must be a valid C string.
-void gtfsort_init_logger(const char *level);
+bool gtfsort_init_logger(const char *level);
 
 /// Allocates a new [SortAnnotationsRet] on the Rust heap.
 ///
@@ -128,6 +162,12 @@ void gtfsort_init_logger(const char *level);
 /// Do not free the memory using any other method.
 SortAnnotationsRet *gtfsort_new_sort_annotations_ret();
 
+/// Returns true if this library was built with `panic = "abort"`, so that a bug in
+/// gtfsort aborts the process instead of being reported as a
+/// [GTFSORT_ERROR_INTERNAL] error. Hosts that need either behavior can check it at
+/// startup.
+bool gtfsort_panic_aborts();
+
 /// Sorts the annotations in the given GTF or GFF3 file and writes the result to the output file.
 ///
 /// `result_ptr` is a pointer to a [SortAnnotationsRet] that will be set to the result of the operation.
@@ -176,4 +216,46 @@ bool gtfsort_sort_annotations_gtf_str(uint8_t mode,
                                       void *caller_data,
                                       SortAnnotationsRet *result_ptr);
 
+/// Stops the sort if it is still running and frees the handle.
+///
+/// # Safety
+/// chunks must be a handle returned by [gtfsort_sorted_chunks_new], or null.
+void gtfsort_sorted_chunks_free(SortedChunksFFI *chunks);
+
+/// Starts sorting the annotations in the given GTF or GFF3 string in the background,
+/// returning a handle to pull the sorted output from with [gtfsort_sorted_chunks_next]
+/// instead of receiving it through a callback.
+///
+/// The mode must be one of the following:
+/// - [GTFSORT_PARSE_MODE_GTF]
+/// - [GTFSORT_PARSE_MODE_GFF3]
+/// - [GTFSORT_PARSE_MODE_GFF]
+///
+/// The input is copied, so it can be freed once this returns. Returns null if the mode
+/// is invalid or the input is not UTF-8, setting result_ptr (if not null) to the error.
+///
+/// # Safety
+/// input must be a valid C string. The handle must be freed with
+/// [gtfsort_sorted_chunks_free].
+SortedChunksFFI *gtfsort_sorted_chunks_new(uint8_t mode,
+                                           const char *input,
+                                           size_t threads,
+                                           SortAnnotationsRet *result_ptr);
+
+/// Waits for the next chunk of sorted output. Chunks are not NUL-terminated and may end
+/// in the middle of a line.
+///
+/// Returns true and points data and len at the chunk, which stays valid until the next
+/// call or [gtfsort_sorted_chunks_free]. Returns false once the output is complete or
+/// the sort failed, setting result_ptr (if not null) to the result of the job, and on
+/// every call after that.
+///
+/// # Safety
+/// chunks must be a handle returned by [gtfsort_sorted_chunks_new], data and len
+/// valid pointers.
+bool gtfsort_sorted_chunks_next(SortedChunksFFI *chunks,
+                                const char **data,
+                                unsigned long *len,
+                                SortAnnotationsRet *result_ptr);
+
 } // extern "C"
@@ -0,0 +1,389 @@
+//! Sorting of inputs bucketed by chromosome, shared by the windowed ([crate::window]) and
+//! packed ([crate::packed]) backends.
+//!
+//! The backend records where the lines of each chromosome are while reading the input,
+//! then [sort_buckets] copies the lines of one chromosome at a time into memory, sorts
+//! them and appends them to the output, so only the largest chromosome is ever indexed.
+
+use hashbrown::HashMap;
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::{
+    build_index, export,
+    filter::ChromFilter,
+    format::AnnotationFormat,
+    gene_order::GeneOrderWriter,
+    invalid::{self, InvalidLines},
+    parallel_parse_counted,
+    rename::ChromRenamer,
+    rewrite_contents, sort_chroms, stage,
+    validate::{self, ValidatePolicy, ValidationReport},
+    verify::{self, FeatureCounts, Verification},
+    warn_unknown_features, write_obj_sequential, ErrorContext, GtfSortError, Header, LineCounts,
+    OutputTarget, Phase, PhaseProgress, Record, RecordHistogram, ResourceSampler,
+    SortAnnotationsJobResult, SortOptions, Watchdog,
+};
+
+/// Copies the lines of a chromosome into a buffer, each terminated by a newline.
+pub(crate) type ReadChrom<'s> = dyn FnMut(&str, &mut Vec<u8>) -> Result<(), GtfSortError> + 's;
+
+/// Whether `options` can be honoured one chromosome at a time. Offset indexes, rejects
+/// files, manifests and quality metrics need the whole input at once.
+pub(crate) fn supports(options: &SortOptions) -> bool {
+    options.offset_index.is_none()
+        && options.rejects.is_none()
+        && options.manifest.is_none()
+        && options.incremental.is_none()
+        && !options.quality_metrics
+        && !options.coordinate_only
+}
+
+/// Decides which input chromosomes [SortOptions::chrom_filter] keeps while the input is
+/// bucketed. Names are matched after [SortOptions::chrom_rename], as the whole-input sort
+/// renames its lines before parsing them, and each decision is cached per input name.
+pub(crate) struct ChromKeep<'o> {
+    filter: Option<&'o ChromFilter>,
+    renamer: Option<&'o ChromRenamer>,
+    seen: HashMap<String, bool>,
+}
+
+impl<'o> ChromKeep<'o> {
+    pub(crate) fn new(options: &'o SortOptions) -> Self {
+        Self {
+            filter: options.chrom_filter(),
+            renamer: options.chrom_rename.as_ref(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Whether the records of input chromosome `chrom` are kept.
+    pub(crate) fn keep(&mut self, chrom: &str) -> bool {
+        let Some(filter) = self.filter else {
+            return true;
+        };
+        let Some(renamer) = self.renamer else {
+            return filter.keep(chrom);
+        };
+        if let Some(keep) = self.seen.get(chrom) {
+            return *keep;
+        }
+        let keep = filter.keep(&renamer.rename(chrom));
+        self.seen.insert(chrom.to_string(), keep);
+        keep
+    }
+}
+
+/// Sorts and writes the chromosomes in `keys` one at a time, reading each with
+/// `read_chrom`, after the `header` of the input. `ret` must hold the input format and
+/// the parsing time so far. Chromosomes renamed alike by [SortOptions::chrom_rename] are
+/// read and sorted together.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn sort_buckets<'a, W: Write + Send>(
+    keys: &[&str],
+    read_chrom: &mut ReadChrom,
+    output: OutputTarget<'a, W>,
+    options: &SortOptions,
+    mut ret: SortAnnotationsJobResult<'a>,
+    mut lines: LineCounts,
+    parsing_secs: f64,
+    mut header: Header,
+    watchdog: Watchdog,
+    sampler: ResourceSampler,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    if let Some(renamer) = &options.chrom_rename {
+        for line in header.lines.iter_mut() {
+            if let Cow::Owned(renamed) = renamer.rename_line(line) {
+                *line = renamed;
+            }
+        }
+    }
+    // the input chromosomes read under each output name
+    let groups = rename_groups(keys, options);
+    let mut keys = match options.chrom_rename {
+        Some(_) => groups.keys().map(String::as_str).collect::<Vec<_>>(),
+        None => keys.to_vec(),
+    };
+    if let Some(chroms) = options.chrom_filter() {
+        chroms.warn_missing(keys.iter().copied());
+    }
+    sort_chroms(&mut keys, options)?;
+    let format = ret.format;
+
+    // chromosomes are indexed and written one at a time, progress is reported per chromosome
+    let chrom_options = SortOptions {
+        progress: None,
+        ..options.clone()
+    };
+
+    sampler.enter(Phase::Write);
+    let write_progress =
+        PhaseProgress::begin_bytes(options.progress.as_ref(), Phase::Write, keys.len(), None);
+    let (mut indexing_secs, mut writing_secs) = (0.0, 0.0);
+    let mut unknown = HashMap::<String, usize>::new();
+    let mut histograms = Vec::with_capacity(keys.len());
+    let mut malformed = ValidationReport::default();
+    let mut renamed_lines = 0;
+    let mut invalid_lines = InvalidLines::default();
+    // (input, output) with SortOptions::verify
+    let mut counts = options
+        .verify
+        .then(|| (FeatureCounts::default(), FeatureCounts::default()));
+
+    let output_path = match &output {
+        OutputTarget::Path(p) => Some(*p),
+        OutputTarget::Writer(_) => None,
+    };
+    let staged = match (output_path, &options.stage_dir) {
+        (Some(_), Some(dir)) => Some(
+            stage::staging_file(dir)
+                .map_err(|e| GtfSortError::IoError("creating staged output file", e))?,
+        ),
+        _ => None,
+    };
+    let (mut file_out, mut writer_out) = (None, None);
+    let out: &mut dyn Write = match output {
+        OutputTarget::Path(p) => file_out.insert(BufWriter::new(
+            File::create(staged.as_deref().unwrap_or(p))
+                .map_err(|e| GtfSortError::IoError("creating output file", e))?,
+        )),
+        OutputTarget::Writer(w) => writer_out.insert(w),
+    };
+
+    let mut gene_order = match &options.gene_order {
+        Some(path) => Some(
+            GeneOrderWriter::create(path)
+                .map_err(|e| GtfSortError::IoError("writing gene order", e))?,
+        ),
+        None => None,
+    };
+
+    let mut exporter = options
+        .export
+        .map(|export| export.exporter(format.unwrap_or(AnnotationFormat::Gtf)));
+    match exporter.as_deref() {
+        Some(exporter) => export::write_header(exporter, &mut *out, options.newline),
+        None if options.drop_header => Ok(()),
+        None => header.write(&mut *out, options.newline),
+    }
+    .map_err(|e| GtfSortError::IoError("writing output file", e))?;
+
+    let mut extra = export::ExtraOutputs::create(options, format.unwrap_or(AnnotationFormat::Gtf))?;
+
+    let (mut buf, mut part) = (Vec::new(), Vec::new());
+    let mut sort_chrom = |chrom: &&str| -> Result<(), GtfSortError> {
+        let _ctx = crate::panic::enter("sorting chromosome", Some(chrom));
+        match groups.get(*chrom) {
+            Some(group) => {
+                buf.clear();
+                for raw in group {
+                    read_chrom(raw, &mut part)?;
+                    buf.extend_from_slice(&part);
+                }
+            }
+            None => read_chrom(chrom, &mut buf)?,
+        }
+        let contents = std::str::from_utf8(&buf).map_err(|_| {
+            GtfSortError::InvalidInput(format!("lines of {} are not valid UTF-8", chrom))
+        })?;
+
+        let (rewritten, chrom_renamed) = match format {
+            Some(AnnotationFormat::Gff3) => rewrite_contents::<b'='>(contents, options)?,
+            _ => rewrite_contents::<b' '>(contents, options)?,
+        };
+        let contents = rewritten.as_deref().unwrap_or(contents);
+        renamed_lines += chrom_renamed;
+
+        let start = std::time::Instant::now();
+        let (mut records, mut chrom_lines) = match format {
+            Some(AnnotationFormat::Gff3) => {
+                parallel_parse_counted::<b'='>(contents, &watchdog, None)
+            }
+            _ => parallel_parse_counted::<b' '>(contents, &watchdog, None),
+        }
+        .map_err(|e| GtfSortError::ParseError(e).in_phase("parsing"))?;
+        chrom_lines.drop_duplicates(&mut records, options.dedup);
+        histograms.push(RecordHistogram::from_records(&records));
+        if chrom_lines.invalid > 0 {
+            // only lenient jobs get here, strict ones failed while the input was read, and
+            // line numbers are not known here either
+            let chrom_invalid =
+                invalid::scan(
+                    contents,
+                    None,
+                    options.parse_mode,
+                    false,
+                    |l| match format {
+                        Some(AnnotationFormat::Gff3) => Record::parse::<b'='>(l).err(),
+                        _ => Record::parse::<b' '>(l).err(),
+                    },
+                )?;
+            invalid_lines.merge(chrom_invalid);
+        }
+        lines = lines.merge(chrom_lines);
+        if let Some((input, _)) = counts.as_mut() {
+            input.merge(FeatureCounts::from_records(&records));
+        }
+        watchdog.check()?;
+        let (mut index, chrom_unknown) = match format {
+            Some(AnnotationFormat::Gff3) => {
+                build_index::<b'='>(&records, &chrom_options, None, &watchdog)?
+            }
+            _ => build_index::<b' '>(&records, &chrom_options, None, &watchdog)?,
+        };
+        indexing_secs += start.elapsed().as_secs_f64();
+        for (feat, n) in chrom_unknown {
+            *unknown.entry(feat).or_default() += n;
+        }
+        if let Some(policy) = options.validate_output {
+            // line numbers are not known here, lines come from per-chromosome buffers
+            let report = validate::validate_index(&mut index, policy, None);
+            if policy == ValidatePolicy::Error {
+                report.finish(policy)?;
+            }
+            malformed = std::mem::take(&mut malformed).merge(report);
+        }
+
+        let start = std::time::Instant::now();
+        // exports are not counted in the written bytes
+        let written = match exporter.as_deref_mut() {
+            Some(exporter) => index
+                .iter()
+                .try_for_each(|(k, layers)| {
+                    exporter.write_chrom(&mut *out, k, layers, options.newline)
+                })
+                .map(|_| 0),
+            None => {
+                let sizes = index
+                    .iter()
+                    .map(|(k, layers)| (*k, layers.count_line_size_with(options.newline)))
+                    .collect::<Vec<_>>();
+                let size = sizes.iter().map(|(_, n)| *n as u64).sum::<u64>();
+                write_obj_sequential(
+                    &mut *out,
+                    &index,
+                    sizes,
+                    &Header::default(),
+                    &mut None,
+                    options.newline,
+                    &PhaseProgress::disabled(),
+                    &watchdog,
+                )
+                .map(|_| size)
+            }
+        }
+        .map_err(|e| {
+            watchdog
+                .check()
+                .err()
+                .unwrap_or(GtfSortError::IoError("writing output file", e))
+        })?;
+        index
+            .iter()
+            .try_for_each(|(k, layers)| extra.write_chrom(k, layers))?;
+        if let Some((_, output)) = counts.as_mut() {
+            output.merge(FeatureCounts::from_index(&index));
+        }
+        writing_secs += start.elapsed().as_secs_f64();
+
+        if let Some(w) = gene_order.as_mut() {
+            index
+                .iter()
+                .try_for_each(|(k, layers)| w.push_chrom(k, layers))
+                .map_err(|e| GtfSortError::IoError("writing gene order", e))?;
+        }
+
+        write_progress.step_bytes(Some(chrom), written);
+        Ok(())
+    };
+    let result = keys.iter().try_for_each(|chrom| {
+        sort_chrom(chrom).map_err(|e| e.with_context(ErrorContext::default().with_chrom(chrom)))
+    });
+
+    let result = result
+        .and_then(|_| {
+            out.flush()
+                .map_err(|e| GtfSortError::IoError("writing output file", e))
+        })
+        .and_then(|_| {
+            gene_order
+                .map_or(Ok(()), |w| w.finish().map(drop))
+                .map_err(|e| GtfSortError::IoError("writing gene order", e))
+        })
+        .and_then(|_| extra.finish());
+    // close the output before removing or publishing it
+    drop(file_out);
+    let result = match (result, staged, output_path) {
+        (Ok(()), Some(staged), Some(path)) => stage::publish(staged, path)
+            .map(|copied| {
+                if let Some(hash) = copied {
+                    log::info!("Verified staged output copy (fnv1a {:016x})", hash);
+                }
+            })
+            .map_err(|e| GtfSortError::IoError("publishing staged output", e)),
+        (Err(e), None, Some(path)) => {
+            if std::fs::metadata(path).is_ok_and(|m| m.is_file()) {
+                let _ = std::fs::remove_file(path);
+            }
+            Err(e)
+        }
+        (result, ..) => result,
+    };
+    result?;
+    write_progress.finish();
+
+    log::info!("Indexing: {:.2}s", indexing_secs);
+    log::info!("Writing output: {:.2}s", writing_secs);
+    ret.parsing_secs = parsing_secs;
+    ret.indexing_secs = indexing_secs;
+    ret.writing_secs = writing_secs;
+
+    lines.log();
+    ret.lines = lines;
+    invalid::log_samples(&invalid_lines);
+    ret.invalid_lines = invalid_lines;
+    if let Some((input, output)) = counts {
+        let output = verify::output_counts(output_path, || output)?;
+        let verification = Verification::new(input, output);
+        ret.verification = Some(verification.clone());
+        verification
+            .check()
+            .map_err(|e| e.in_phase("verifying output"))?;
+    }
+    if let Some(policy) = options.validate_output {
+        ret.malformed_lines = malformed.malformed;
+        malformed.finish(policy)?;
+    }
+
+    let mut unknown = unknown.into_iter().collect::<Vec<_>>();
+    unknown.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    warn_unknown_features(&unknown);
+    ret.unknown_features = unknown;
+    ret.histogram = RecordHistogram::combine(histograms);
+    ret.histogram.order_chroms(options);
+    ret.renamed_lines = renamed_lines;
+
+    ret.end_mem_mb = Some(crate::max_mem_usage_mb());
+    ret.resources = sampler.finish();
+    crate::progress::report_done(options.progress.as_ref());
+
+    Ok(ret)
+}
+
+/// The input chromosomes in `keys` read under each name given by
+/// [SortOptions::chrom_rename], empty if no renaming is requested.
+fn rename_groups<'k>(keys: &[&'k str], options: &SortOptions) -> HashMap<String, Vec<&'k str>> {
+    let mut groups = HashMap::<String, Vec<&str>>::new();
+    if let Some(renamer) = &options.chrom_rename {
+        for key in keys {
+            groups
+                .entry(renamer.rename(key).into_owned())
+                .or_default()
+                .push(key);
+        }
+    }
+    groups
+}
@@ -0,0 +1,126 @@
+//! Choosing how a file sort holds its input from a memory budget, see
+//! [crate::SortOptions::max_mem_mb].
+//!
+//! The peak resident memory of each input backend is estimated from the input size alone,
+//! before reading it: the whole input indexed at once, its lines kept compressed (see
+//! [crate::packed]) or partitioned into temporary files (see [crate::spill]). The first
+//! one expected to fit is used, spilling to disk if none is. Unlike
+//! [crate::SortOptions::max_rss_mb], the budget never aborts a job.
+
+use std::fmt;
+
+use crate::{packed::LineCompression, SortOptions};
+
+/// Peak resident memory of a whole-input sort as a multiple of the input size, about
+/// 7.7x on GENCODE-like annotations.
+const WHOLE_INPUT_RATIO: f64 = 8.0;
+
+/// Compressed size of the input lines, LZ4 usually packs GTF text 4-6x.
+const COMPRESSED_RATIO: f64 = 0.25;
+
+/// Share of the input on its largest chromosome, chr1 holds about a tenth of a human
+/// annotation.
+const LARGEST_CHROM_SHARE: f64 = 0.15;
+
+/// Lines buffered while partitioning the input, see [crate::spill::SPILL_BUFFER].
+const SPILL_BUFFER_MB: f64 = (crate::spill::SPILL_BUFFER >> 20) as f64;
+
+/// How a file sort holds its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryStrategy {
+    /// Map or read the whole input and index it at once.
+    Whole,
+    /// Keep the lines LZ4-compressed in memory, indexing one chromosome at a time.
+    Compressed,
+    /// Partition the lines by chromosome into temporary files, indexing one at a time.
+    Spilled,
+}
+
+impl MemoryStrategy {
+    /// Estimated peak resident MB of sorting an input of `size` bytes this way.
+    pub fn estimate_mb(self, size: u64) -> f64 {
+        let size_mb = size as f64 / (1024.0 * 1024.0);
+        let largest_chrom = size_mb * LARGEST_CHROM_SHARE * WHOLE_INPUT_RATIO;
+        match self {
+            Self::Whole => size_mb * WHOLE_INPUT_RATIO,
+            Self::Compressed => size_mb * COMPRESSED_RATIO + largest_chrom,
+            Self::Spilled => SPILL_BUFFER_MB.min(size_mb) + largest_chrom,
+        }
+    }
+
+    /// The first strategy expected to fit an input of `size` bytes in `budget_mb`,
+    /// [MemoryStrategy::Spilled] if none is.
+    pub fn choose(size: u64, budget_mb: f64) -> Self {
+        [Self::Whole, Self::Compressed]
+            .into_iter()
+            .find(|s| s.estimate_mb(size) <= budget_mb)
+            .unwrap_or(Self::Spilled)
+    }
+
+    /// The strategy [SortOptions::max_mem_mb] picks for an input of `size` bytes, None if
+    /// no budget is set or the backend was chosen explicitly with
+    /// [SortOptions::low_memory] or [SortOptions::compress_lines].
+    pub fn for_input(size: u64, options: &SortOptions) -> Option<Self> {
+        let budget = options.max_mem_mb?;
+        if options.low_memory || options.compress_lines != LineCompression::Auto {
+            return None;
+        }
+
+        let strategy = Self::choose(size, budget);
+        log::info!(
+            "Sorting {} with a {:.0} MB memory budget, estimated peak {:.0} MB",
+            strategy,
+            budget,
+            strategy.estimate_mb(size)
+        );
+        if strategy.estimate_mb(size) > budget {
+            log::warn!(
+                "the input is expected to need more than the {:.0} MB memory budget even when spilled to disk",
+                budget
+            );
+        }
+        Some(strategy)
+    }
+}
+
+impl fmt::Display for MemoryStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Whole => "in memory",
+            Self::Compressed => "with compressed input lines",
+            Self::Spilled => "with the input spilled to disk",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategy_follows_budget() {
+        let gib = 1 << 30;
+        assert_eq!(MemoryStrategy::choose(gib, 16384.0), MemoryStrategy::Whole);
+        assert_eq!(
+            MemoryStrategy::choose(gib, 4096.0),
+            MemoryStrategy::Compressed
+        );
+        assert_eq!(MemoryStrategy::choose(gib, 1024.0), MemoryStrategy::Spilled);
+        // nothing fits, spilling is the least memory hungry
+        assert_eq!(MemoryStrategy::choose(gib, 64.0), MemoryStrategy::Spilled);
+
+        let options = SortOptions {
+            max_mem_mb: Some(1024.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            MemoryStrategy::for_input(gib, &options),
+            Some(MemoryStrategy::Spilled)
+        );
+        let options = SortOptions {
+            compress_lines: LineCompression::Never,
+            ..options
+        };
+        assert_eq!(MemoryStrategy::for_input(gib, &options), None);
+    }
+}
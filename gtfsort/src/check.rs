@@ -0,0 +1,345 @@
+//! Validation of an annotation without sorting it, for `gtfsort check`.
+//!
+//! The input goes through the same parser and index as a sort but nothing is written.
+//! Instead the [CheckReport] lists the structural problems that would make the sorted
+//! output incomplete or surprising, each with a count and the first few occurrences.
+
+use std::fmt;
+
+use crate::{
+    build_index, json, parallel_parse_counted,
+    rename::{has_chr_prefix, is_primary},
+    sniff,
+    watchdog::Watchdog,
+    AnnotationFormat, GtfSortError, LineCounts, SortOptions,
+};
+
+/// Occurrences quoted for each [Issue].
+pub(crate) const ISSUE_EXAMPLES: usize = 5;
+
+/// A kind of structural problem found by [check].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// Lines that do not parse as a record, or GFF3 records whose `Parent` leads nowhere.
+    InvalidLines,
+    /// Transcripts whose gene has no gene line; they are left out of a sort unless
+    /// `--infer-genes` or `--synthesize-parents` is given.
+    OrphanTranscripts,
+    GenesWithoutTranscripts,
+    /// Transcripts none of whose children is an exon.
+    TranscriptsWithoutExons,
+    /// Records whose end is before their start, or whose start is 0.
+    InvalidCoordinates,
+    /// exon_number values that are not a number (e.g. `3a`), ordered by name instead.
+    NonNumericExonNumber,
+    /// Chromosomes named both with a "chr" prefix (UCSC) and without (Ensembl).
+    MixedChromNaming,
+}
+
+impl IssueKind {
+    /// Every kind, in report order.
+    pub const ALL: [IssueKind; 7] = [
+        Self::InvalidLines,
+        Self::OrphanTranscripts,
+        Self::GenesWithoutTranscripts,
+        Self::TranscriptsWithoutExons,
+        Self::InvalidCoordinates,
+        Self::NonNumericExonNumber,
+        Self::MixedChromNaming,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidLines => "invalid_lines",
+            Self::OrphanTranscripts => "orphan_transcripts",
+            Self::GenesWithoutTranscripts => "genes_without_transcripts",
+            Self::TranscriptsWithoutExons => "transcripts_without_exons",
+            Self::InvalidCoordinates => "invalid_coordinates",
+            Self::NonNumericExonNumber => "non_numeric_exon_number",
+            Self::MixedChromNaming => "mixed_chrom_naming",
+        }
+    }
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.as_str().replace('_', " "))
+    }
+}
+
+/// How often a kind of problem occurs, with its first occurrences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub count: usize,
+    /// Up to [ISSUE_EXAMPLES] occurrences: an ID, a record as `chrom:start-end feature`,
+    /// or a chromosome name.
+    pub examples: Vec<String>,
+}
+
+impl Issue {
+    fn new(kind: IssueKind) -> Self {
+        Self {
+            kind,
+            count: 0,
+            examples: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, example: impl FnOnce() -> String) {
+        self.count += 1;
+        if self.examples.len() < ISSUE_EXAMPLES {
+            self.examples.push(example());
+        }
+    }
+}
+
+/// The problems found in an annotation, see [check].
+#[derive(Debug, Clone)]
+pub struct CheckReport {
+    pub format: AnnotationFormat,
+    pub lines: LineCounts,
+    /// One per [IssueKind], in [IssueKind::ALL] order, including those that did not occur.
+    pub issues: Vec<Issue>,
+}
+
+impl CheckReport {
+    /// Whether no problem was found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.iter().all(|i| i.count == 0)
+    }
+
+    /// The issues that occurred.
+    pub fn found(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|i| i.count > 0)
+    }
+
+    pub fn to_json(&self) -> String {
+        use std::fmt::Write as _;
+
+        let mut s = String::with_capacity(512);
+        write!(
+            s,
+            "{{\"format\":\"{}\",\"ok\":{},\"lines\":{},\"issues\":{{",
+            self.format,
+            self.is_ok(),
+            self.lines.to_json()
+        )
+        .unwrap();
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "\"{}\":{{\"count\":{},\"examples\":[",
+                issue.kind.as_str(),
+                issue.count
+            )
+            .unwrap();
+            for (j, example) in issue.examples.iter().enumerate() {
+                if j > 0 {
+                    s.push(',');
+                }
+                json::string(&mut s, example);
+            }
+            s.push_str("]}");
+        }
+        s.push_str("}}");
+        s
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(
+                f,
+                "{} records read as {}, no problems found",
+                self.lines.records, self.format
+            );
+        }
+        write!(f, "{} records read as {}", self.lines.records, self.format)?;
+        for issue in self.found() {
+            write!(f, "; {}: {}", issue.kind, issue.count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `contents` for structural problems, indexing it as a sort with `options` would.
+/// The format is [SortOptions::format], or detected from the attribute columns.
+pub fn check(contents: &str, options: &SortOptions) -> Result<CheckReport, GtfSortError> {
+    let format = match options.format {
+        Some(format) => format,
+        None => AnnotationFormat::detect(contents).format.ok_or_else(|| {
+            GtfSortError::InvalidInput(
+                "could not detect the format, no attribute column looks like GTF or GFF3"
+                    .to_string(),
+            )
+        })?,
+    };
+
+    match format {
+        AnnotationFormat::Gtf => check_as::<b' '>(contents, options, format),
+        AnnotationFormat::Gff3 => check_as::<b'='>(contents, options, format),
+    }
+}
+
+fn check_as<const SEP: u8>(
+    contents: &str,
+    options: &SortOptions,
+    format: AnnotationFormat,
+) -> Result<CheckReport, GtfSortError> {
+    let mut issues = IssueKind::ALL.map(Issue::new);
+    let [invalid_lines, orphans, genes_without_transcripts, transcripts_without_exons, coords, exon_numbers, naming] =
+        &mut issues;
+
+    let (annotation, fasta) = sniff::split_fasta(contents);
+    let (records, mut lines) =
+        parallel_parse_counted::<SEP>(annotation, &Watchdog::disabled(), options.chrom_filter())
+            .map_err(|e| GtfSortError::ParseError(e).in_phase("parsing"))?;
+    lines.sequence += fasta.lines().count();
+    invalid_lines.count = lines.invalid;
+    if lines.invalid > 0 {
+        let scanned = crate::invalid::scan(
+            annotation,
+            options.chrom_filter(),
+            crate::ParseMode::Lenient,
+            true,
+            |l| crate::Record::parse::<SEP>(l).err(),
+        )?;
+        invalid_lines.examples = scanned
+            .samples
+            .into_iter()
+            .take(ISSUE_EXAMPLES)
+            .map(|l| format!("line {}: {}", l.line_number.unwrap_or_default(), l.reason))
+            .collect();
+    }
+
+    let mut chroms = records.keys().copied().collect::<Vec<_>>();
+    crate::ord::sort_natural(&mut chroms);
+    for chrom in &chroms {
+        for r in &records[chrom] {
+            let record = || format!("{}:{}-{} {}", r.chrom, r.start, r.end, r.feat);
+            if r.start == 0 || r.end < r.start {
+                coords.push(record);
+            }
+            if r.exon_number.is_some_and(|n| n.parse::<u32>().is_err()) {
+                exon_numbers.push(record);
+            }
+        }
+    }
+
+    let (prefixed, bare): (Vec<&str>, Vec<&str>) = chroms
+        .iter()
+        .copied()
+        .filter(|c| is_primary(c))
+        .partition(|c| has_chr_prefix(c));
+    if !prefixed.is_empty() && !bare.is_empty() {
+        for chrom in prefixed.iter().take(1).chain(bare.iter()) {
+            naming.push(|| chrom.to_string());
+        }
+    }
+
+    let (index, _) = build_index::<SEP>(&records, options, None, &Watchdog::disabled())
+        .map_err(|e| e.in_phase("indexing"))?;
+    for chrom in &chroms {
+        let Some(layers) = index.get(chrom) else {
+            continue;
+        };
+        let genes = layers
+            .layer
+            .iter()
+            .map(|(_, gene_id, _)| *gene_id)
+            .collect::<hashbrown::HashSet<_>>();
+
+        for (_, gene_id, _) in layers.layer.iter() {
+            if layers.transcripts(gene_id).next().is_none() {
+                genes_without_transcripts.push(|| gene_id.to_string());
+            }
+            for transcript_id in layers.transcripts(gene_id) {
+                let has_exon = layers
+                    .children(transcript_id)
+                    .any(|l| l.split('\t').nth(2) == Some("exon"));
+                if !has_exon {
+                    transcripts_without_exons.push(|| transcript_id.to_string());
+                }
+            }
+        }
+
+        let mut orphaned = layers
+            .mapper
+            .iter()
+            .filter(|(gene_id, _)| !genes.contains(*gene_id))
+            .flat_map(|(_, transcripts)| transcripts.iter())
+            .collect::<Vec<_>>();
+        orphaned.sort_unstable();
+        for transcript_id in orphaned {
+            orphans.push(|| transcript_id.to_string());
+        }
+    }
+
+    Ok(CheckReport {
+        format,
+        lines,
+        issues: issues.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GTF: &str = "chr1\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+
+    fn issue(report: &CheckReport, kind: IssueKind) -> &Issue {
+        report.issues.iter().find(|i| i.kind == kind).unwrap()
+    }
+
+    #[test]
+    fn clean_input_passes() {
+        let report = check(GTF, &SortOptions::default()).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.lines.records, 3);
+        assert!(report.to_json().contains("\"ok\":true"));
+    }
+
+    #[test]
+    fn structural_problems_are_reported() {
+        let input = format!(
+            "{}chr1\ts\ttranscript\t5\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.2\";\n\
+             chr1\ts\tCDS\t5\t9\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.2\"; exon_number \"2a\";\n\
+             chr1\ts\tgene\t50\t40\t.\t+\t.\tgene_id \"B\";\n\
+             chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+             2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"D\";\n\
+             not a record\n",
+            GTF
+        );
+        let report = check(&input, &SortOptions::default()).unwrap();
+        assert!(!report.is_ok());
+
+        let expect = |kind, count, first: &str| {
+            let issue = issue(&report, kind);
+            assert_eq!(
+                (issue.count, issue.examples[0].as_str()),
+                (count, first),
+                "{}",
+                kind
+            );
+        };
+        expect(IssueKind::InvalidLines, 1, "line 9: Missing source");
+        expect(IssueKind::OrphanTranscripts, 1, "C.1");
+        expect(IssueKind::GenesWithoutTranscripts, 2, "D");
+        expect(IssueKind::TranscriptsWithoutExons, 1, "A.2");
+        expect(IssueKind::InvalidCoordinates, 1, "chr1:50-40 gene");
+        expect(IssueKind::NonNumericExonNumber, 1, "chr1:5-9 CDS");
+        expect(IssueKind::MixedChromNaming, 2, "chr1");
+
+        let json = report.to_json();
+        assert!(json.contains("\"ok\":false"));
+        assert!(json.contains("\"orphan_transcripts\":{\"count\":1,\"examples\":[\"C.1\"]}"));
+    }
+}
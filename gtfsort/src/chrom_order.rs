@@ -0,0 +1,338 @@
+//! The order of the chromosomes in the output: a preset, see [ChromSortOrder], or an
+//! explicit list, e.g. the order of the reference the annotation goes with, see
+//! [ChromOrder].
+
+use hashbrown::HashMap;
+use std::{fmt, path::Path, str::FromStr};
+
+use crate::{
+    color::Colorize,
+    ord::{self, NaturalKey},
+    par::*,
+    rename::{has_chr_prefix, is_primary},
+    ErrorContext, GtfSortError,
+};
+
+/// A preset order of the chromosomes in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChromSortOrder {
+    /// Digit runs compared by value, chr2 before chr10 (see [ord::NaturalKey]).
+    #[default]
+    Natural,
+    /// Plain byte order, chr10 before chr2, as `sort -k1,1` and igvtools write.
+    Lex,
+    /// Numbered chromosomes by number, then X, Y, Z, W, then the mitochondrial genome,
+    /// then unplaced and unlocalized scaffolds, and alternate haplotypes and patches
+    /// last, as in the GRCh38 and GRCm39 references (and their GATK sequence
+    /// dictionaries).
+    Karyotype,
+    /// The order of [crate::SortOptions::chrom_order], which must be set.
+    Custom,
+}
+
+impl ChromSortOrder {
+    /// Sorts `keys` in this order. [ChromSortOrder::Custom] needs the list and sorts
+    /// naturally here.
+    pub fn sort(self, keys: &mut [&str]) {
+        match self {
+            Self::Natural | Self::Custom => ord::sort_natural(keys),
+            Self::Lex => keys.sort_unstable(),
+            Self::Karyotype => {
+                keys.par_sort_by_cached_key(|k| (karyotype_rank(k), NaturalKey::new(k), *k))
+            }
+        }
+    }
+}
+
+impl FromStr for ChromSortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "natural" => Ok(Self::Natural),
+            "lex" | "lexicographic" => Ok(Self::Lex),
+            "karyotype" | "karyotypic" => Ok(Self::Karyotype),
+            "custom" => Ok(Self::Custom),
+            _ => Err(format!(
+                "unknown chromosome order {:?}, expected natural, lex, karyotype or custom",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ChromSortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Natural => "natural",
+            Self::Lex => "lex",
+            Self::Karyotype => "karyotype",
+            Self::Custom => "custom",
+        })
+    }
+}
+
+/// Group of `chrom` in karyotypic order and its number within the group, see
+/// [ChromSortOrder::Karyotype]; names within a group are otherwise in natural order.
+fn karyotype_rank(chrom: &str) -> (u8, u32) {
+    if is_primary(chrom) {
+        let name = match has_chr_prefix(chrom) {
+            true => &chrom[3..],
+            false => chrom,
+        };
+        if let Ok(n) = name.parse() {
+            return (0, n);
+        }
+        return match name.to_ascii_uppercase().as_str() {
+            "X" => (1, 0),
+            "Y" => (1, 1),
+            "Z" => (1, 2),
+            "W" => (1, 3),
+            _ => (2, 0),
+        };
+    }
+
+    // GRCh38 chr1_KI270762v1_alt, chr1_KN196472v1_fix; Ensembl CHR_HSCHR1_1_CTG3 and
+    // the GRCh37 chr6_apd_hap1
+    let lower = chrom.to_ascii_lowercase();
+    if ["_alt", "_fix", "_hap", "_patch"]
+        .iter()
+        .any(|s| lower.contains(s))
+        || chrom.starts_with("CHR_")
+    {
+        (4, 0)
+    } else {
+        (3, 0)
+    }
+}
+
+/// Chromosomes in the order they are written, instead of natural order.
+///
+/// An order file either lists one chromosome per line (the first word of each line,
+/// blank lines and lines starting with '#' are ignored), which also reads a samtools
+/// `.fai`, a UCSC `chrom.sizes` or a bedtools `.genome` file, or is a Picard/GATK
+/// sequence dictionary (`.dict`), whose `@SQ` lines are read in order. Chromosomes of the input
+/// missing from the list are written after the listed ones in natural order, or fail
+/// the job if [ChromOrder::strict] is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChromOrder {
+    ranks: HashMap<Box<str>, usize>,
+    /// Fail on chromosomes missing from the list instead of writing them last.
+    pub strict: bool,
+}
+
+impl ChromOrder {
+    /// Ranks `chroms` in order, failing on repeated chromosomes.
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(chroms: I) -> Result<Self, String> {
+        let mut ranks = HashMap::new();
+        for (i, chrom) in chroms.into_iter().enumerate() {
+            if ranks.insert(chrom.into(), i).is_some() {
+                return Err(format!("chromosome {:?} is listed twice", chrom));
+            }
+        }
+        Ok(Self {
+            ranks,
+            strict: false,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, GtfSortError> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| GtfSortError::IoError("reading chromosome order", e))?;
+        Self::from_list(&contents)
+    }
+
+    /// Reads a plain list or a sequence dictionary, see [ChromOrder].
+    pub fn from_list(contents: &str) -> Result<Self, GtfSortError> {
+        let dict = contents.lines().any(|l| l.starts_with("@SQ"));
+        let lines = contents
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter_map(|(n, l)| match dict {
+                true => l
+                    .strip_prefix("@SQ\t")
+                    .and_then(|tags| tags.split('\t').find_map(|t| t.strip_prefix("SN:")))
+                    .map(|chrom| (n, chrom)),
+                false => (!l.is_empty() && !l.starts_with('#'))
+                    .then(|| (n, l.split_whitespace().next().unwrap())),
+            })
+            .collect::<Vec<_>>();
+
+        if lines.is_empty() {
+            return Err(GtfSortError::InvalidInput(
+                "invalid chromosome order: no chromosomes listed".to_string(),
+            )
+            .in_phase("reading chromosome order"));
+        }
+
+        Self::new(lines.iter().map(|(_, c)| *c)).map_err(|e| {
+            // point at the first repeated chromosome
+            let bad = lines
+                .iter()
+                .enumerate()
+                .find(|(i, (_, c))| lines[..*i].iter().any(|(_, p)| p == c))
+                .map(|(_, (n, _))| *n);
+            let err = GtfSortError::InvalidInput(format!("invalid chromosome order: {}", e));
+            match bad {
+                Some(n) => {
+                    err.with_context(ErrorContext::phase("reading chromosome order").with_line(n))
+                }
+                None => err.in_phase("reading chromosome order"),
+            }
+        })
+    }
+
+    pub fn strict(self, strict: bool) -> Self {
+        Self { strict, ..self }
+    }
+
+    /// Position of `chrom` in the list, if listed.
+    #[inline(always)]
+    pub fn rank(&self, chrom: &str) -> Option<usize> {
+        self.ranks.get(chrom).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranks.is_empty()
+    }
+
+    /// Sorts `keys` by their position in the list, unlisted ones last in natural order.
+    /// Fails if some are unlisted and the order is strict.
+    pub fn sort(&self, keys: &mut [&str]) -> Result<(), GtfSortError> {
+        let mut missing = keys
+            .iter()
+            .filter(|k| self.rank(k).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            ord::sort_natural(&mut missing);
+            let shown = missing
+                .iter()
+                .take(5)
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", ");
+            let more = match missing.len() {
+                n if n > 5 => format!(" and {} more", n - 5),
+                _ => String::new(),
+            };
+            if self.strict {
+                return Err(GtfSortError::InvalidInput(format!(
+                    "{} chromosome(s) of the input are missing from the chromosome order: {}{}",
+                    missing.len(),
+                    shown,
+                    more
+                )));
+            }
+            log::warn!(
+                "{} {} chromosome(s) missing from the chromosome order are written last: {}{}",
+                "Warning:".bright_yellow().bold(),
+                missing.len(),
+                shown,
+                more
+            );
+        }
+
+        let unlisted = missing
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (*k, self.ranks.len() + i))
+            .collect::<HashMap<_, _>>();
+        keys.sort_unstable_by_key(|k| self.rank(k).unwrap_or_else(|| unlisted[k]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_and_dictionaries_are_read() {
+        let list =
+            ChromOrder::from_list("# reference order\nchrM\nchr2 extra columns\n\nchr1\n").unwrap();
+        let dict = ChromOrder::from_list(
+            "@HD\tVN:1.6\n\
+            @SQ\tSN:chrM\tLN:16569\tM5:x\n\
+            @SQ\tSN:chr2\tLN:242193529\n\
+            @SQ\tSN:chr1\tLN:248956422\n",
+        )
+        .unwrap();
+        let fai = ChromOrder::from_list(
+            "chrM\t16569\t6\t60\t61\n\
+            chr2\t242193529\t16876\t60\t61\n\
+            chr1\t248956422\t246253940\t60\t61\n",
+        )
+        .unwrap();
+        assert_eq!(list, dict);
+        assert_eq!(list, fai);
+        assert_eq!(list.rank("chr1"), Some(2));
+
+        let mut keys = vec!["chr10", "chr1", "chr2", "chrX", "chrM", "chr3"];
+        list.sort(&mut keys).unwrap();
+        assert_eq!(keys, ["chrM", "chr2", "chr1", "chr3", "chr10", "chrX"]);
+
+        let err = list.clone().strict(true).sort(&mut keys).unwrap_err();
+        assert!(err.to_string().contains("chr3, chr10, chrX"), "{}", err);
+        let mut listed = vec!["chr1", "chrM"];
+        list.strict(true).sort(&mut listed).unwrap();
+        assert_eq!(listed, ["chrM", "chr1"]);
+
+        let err = ChromOrder::from_list("chr1\nchr2\nchr1\n").unwrap_err();
+        assert_eq!(err.context().line, Some(3));
+        assert!(ChromOrder::from_list("# nothing\n").is_err());
+    }
+    #[test]
+    fn presets_order_reference_contigs() {
+        let mut keys = vec![
+            "chrM",
+            "chr1_KI270762v1_alt",
+            "chrY",
+            "chrUn_KI270302v1",
+            "chr10",
+            "chrX",
+            "chr1_KI270706v1_random",
+            "chr2",
+            "chr1",
+        ];
+        ChromSortOrder::Karyotype.sort(&mut keys);
+        assert_eq!(
+            keys,
+            [
+                "chr1",
+                "chr2",
+                "chr10",
+                "chrX",
+                "chrY",
+                "chrM",
+                "chr1_KI270706v1_random",
+                "chrUn_KI270302v1",
+                "chr1_KI270762v1_alt",
+            ]
+        );
+
+        let mut ensembl = vec!["MT", "CHR_HSCHR1_1_CTG3", "KI270728.1", "Y", "X", "11", "2"];
+        ChromSortOrder::Karyotype.sort(&mut ensembl);
+        assert_eq!(
+            ensembl,
+            ["2", "11", "X", "Y", "MT", "KI270728.1", "CHR_HSCHR1_1_CTG3"]
+        );
+
+        ChromSortOrder::Lex.sort(&mut ensembl);
+        assert_eq!(
+            ensembl,
+            ["11", "2", "CHR_HSCHR1_1_CTG3", "KI270728.1", "MT", "X", "Y"]
+        );
+        ChromSortOrder::Natural.sort(&mut ensembl);
+        assert_eq!(
+            ensembl,
+            ["2", "11", "CHR_HSCHR1_1_CTG3", "KI270728.1", "MT", "X", "Y"]
+        );
+        assert_eq!("Karyotype".parse(), Ok(ChromSortOrder::Karyotype));
+    }
+}
@@ -0,0 +1,187 @@
+//! Pull-based access to the output of a string sort, for hosts that cannot hand a
+//! callback to [crate::sort_annotations_string] or want to consume the output at their
+//! own pace with bounded memory, see [SortedChunks].
+
+use std::{
+    sync::mpsc::{sync_channel, Receiver},
+    thread::JoinHandle,
+};
+
+use crate::{
+    sort_annotations_string_with_options, GtfSortError, SortAnnotationsJobResult, SortOptions,
+    DEFAULT_CHUNK_SIZE,
+};
+
+/// Chunks a [SortedChunks] sorts ahead of the consumer before it blocks.
+pub(crate) const CHUNKS_AHEAD: usize = 4;
+
+enum Message {
+    Chunk(Vec<u8>),
+    Done(Box<Result<SortAnnotationsJobResult<'static>, GtfSortError>>),
+}
+
+/// The output of a sort running in the background, pulled one chunk at a time with
+/// [SortedChunks::next_chunk] or as an [Iterator].
+///
+/// Chunks hold about [DEFAULT_CHUNK_SIZE] bytes (the last one may be shorter) and end
+/// wherever the output was cut, not necessarily at a line break. At most
+/// [CHUNKS_AHEAD] chunks wait for the consumer, so the output is never buffered whole.
+/// Dropping the iterator before the end stops the sort and waits for it to wind down.
+pub struct SortedChunks {
+    rx: Option<Receiver<Message>>,
+    job: Option<JoinHandle<()>>,
+    result: Option<SortAnnotationsJobResult<'static>>,
+}
+
+impl SortedChunks {
+    /// Starts sorting `input`, GTF if `SEP` is `b' '` and GFF3 if it is `b'='`.
+    pub fn spawn<const SEP: u8>(input: String, threads: usize, options: SortOptions) -> Self {
+        let (tx, rx) = sync_channel(CHUNKS_AHEAD);
+
+        let job = std::thread::spawn(move || {
+            let mut chunk = Vec::with_capacity(DEFAULT_CHUNK_SIZE);
+            let result = sort_annotations_string_with_options::<SEP, _>(
+                &input,
+                &mut |b| {
+                    chunk.extend_from_slice(b);
+                    if chunk.len() >= DEFAULT_CHUNK_SIZE {
+                        let full =
+                            std::mem::replace(&mut chunk, Vec::with_capacity(DEFAULT_CHUNK_SIZE));
+                        // the consumer went away, stop sorting
+                        tx.send(Message::Chunk(full))
+                            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+                    }
+                    Ok(b.len())
+                },
+                threads,
+                &options,
+            )
+            .map(|r| r.relabel("[string]", "[chunks]"));
+
+            if result.is_ok() && !chunk.is_empty() && tx.send(Message::Chunk(chunk)).is_err() {
+                return;
+            }
+            let _ = tx.send(Message::Done(Box::new(result)));
+        });
+
+        Self {
+            rx: Some(rx),
+            job: Some(job),
+            result: None,
+        }
+    }
+
+    /// Waits for the next chunk of the output, None once it is complete. An error of the
+    /// sort is returned once, in place of the rest of the output.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, GtfSortError> {
+        let Some(rx) = &self.rx else {
+            return Ok(None);
+        };
+        match rx.recv() {
+            Ok(Message::Chunk(chunk)) => Ok(Some(chunk)),
+            Ok(Message::Done(result)) => {
+                self.rx = None;
+                self.result = Some((*result)?);
+                Ok(None)
+            }
+            // the job only ends without a result if it panicked outside the sort
+            Err(_) => {
+                self.rx = None;
+                if let Some(Err(panic)) = self.job.take().map(JoinHandle::join) {
+                    std::panic::resume_unwind(panic);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Summary of the sort, once all chunks were pulled.
+    pub fn job(&self) -> Option<&SortAnnotationsJobResult<'static>> {
+        self.result.as_ref()
+    }
+}
+
+impl Iterator for SortedChunks {
+    type Item = Result<Vec<u8>, GtfSortError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+impl Drop for SortedChunks {
+    fn drop(&mut self) {
+        // closing the channel fails the next send of the job
+        drop(self.rx.take());
+        if let Some(job) = self.job.take() {
+            let _ = job.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sort_annotations_string;
+
+    fn annotation(genes: usize) -> String {
+        (0..genes)
+            .rev()
+            .map(|i| {
+                let start = i * 100 + 1;
+                format!(
+                    "chr1\ts\tgene\t{start}\t{end}\t.\t+\t.\tgene_id \"G{i}\";\n\
+                    chr1\ts\ttranscript\t{start}\t{end}\t.\t+\t.\tgene_id \"G{i}\"; transcript_id \"T{i}\";\n\
+                    chr1\ts\texon\t{start}\t{end}\t.\t+\t.\tgene_id \"G{i}\"; transcript_id \"T{i}\"; exon_number \"1\";\n",
+                    start = start,
+                    end = start + 50,
+                    i = i
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunks_match_the_callback_output() {
+        let input = annotation(2000);
+        let mut expected = Vec::new();
+        sort_annotations_string::<b' ', _>(
+            &input,
+            &mut |b| {
+                expected.extend_from_slice(b);
+                Ok(b.len())
+            },
+            2,
+        )
+        .unwrap();
+
+        let mut chunks = SortedChunks::spawn::<b' '>(input, 2, SortOptions::default());
+        let mut output = Vec::new();
+        let mut sizes = Vec::new();
+        while let Some(chunk) = chunks.next_chunk().unwrap() {
+            sizes.push(chunk.len());
+            output.extend(chunk);
+        }
+        assert_eq!(output, expected);
+        assert!(sizes.len() > 1);
+        assert!(sizes[..sizes.len() - 1]
+            .iter()
+            .all(|n| *n >= DEFAULT_CHUNK_SIZE && *n < 2 * DEFAULT_CHUNK_SIZE));
+        assert_eq!(chunks.job().unwrap().lines.records, 6000);
+        assert!(chunks.next_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn errors_end_the_chunks() {
+        let mut chunks =
+            SortedChunks::spawn::<b' '>("chr1\x00\x00".to_string(), 1, SortOptions::default());
+        assert!(chunks.next().unwrap().is_err());
+        assert!(chunks.next().is_none());
+        assert!(chunks.job().is_none());
+
+        // dropped half-way, the job stops
+        let mut chunks = SortedChunks::spawn::<b' '>(annotation(5000), 1, SortOptions::default());
+        assert!(chunks.next().unwrap().is_ok());
+        drop(chunks);
+    }
+}
@@ -0,0 +1,35 @@
+//! Colors of log messages, plain text without the `color` feature.
+
+#[cfg(feature = "color")]
+pub use colored::Colorize;
+
+/// The subset of [colored](https://docs.rs/colored)'s `Colorize` used by this crate,
+/// leaving the text as it is.
+#[cfg(not(feature = "color"))]
+pub trait Colorize: Sized {
+    fn bold(self) -> Self {
+        self
+    }
+
+    fn bright_green(self) -> Self {
+        self
+    }
+
+    fn bright_purple(self) -> Self {
+        self
+    }
+
+    fn bright_red(self) -> Self {
+        self
+    }
+
+    fn bright_yellow(self) -> Self {
+        self
+    }
+}
+
+#[cfg(not(feature = "color"))]
+impl Colorize for &str {}
+
+#[cfg(not(feature = "color"))]
+impl Colorize for String {}
@@ -0,0 +1,420 @@
+//! Compression of the sorted output, see [crate::SortOptions::output_compression].
+//!
+//! BGZF (the blocked gzip written by `bgzip`) cuts the output into blocks of at most
+//! 65280 bytes, each a gzip member of its own recording its compressed size,
+//! so that tabix can index the output and seek into it. The blocks are independent, so a
+//! batch of them is deflated in parallel on the job's pool while the lines are written
+//! sequentially, and written out in order. Plain gzip is a single member deflated on the
+//! calling thread. Both need the `gzip` feature.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{par::ThreadPool, GtfSortError, SortOptions};
+
+/// Bytes of output compressed into one BGZF block, as bgzip and htslib cut them.
+#[cfg(feature = "gzip")]
+pub(crate) const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// The empty block closing a BGZF file, which tells readers that it was not truncated.
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// How the sorted output is compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputCompression {
+    /// Plain text.
+    #[default]
+    None,
+    /// A single gzip member.
+    Gzip,
+    /// Blocked gzip, readable by any gzip reader and indexable by tabix.
+    Bgzf,
+}
+
+impl OutputCompression {
+    pub fn is_compressed(self) -> bool {
+        self != Self::None
+    }
+}
+
+impl FromStr for OutputCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "bgzf" | "bgzip" => Ok(Self::Bgzf),
+            _ => Err(format!(
+                "unknown output compression {:?}, expected bgzf, gzip or none",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputCompression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Bgzf => "bgzf",
+        })
+    }
+}
+
+/// Fails if `options` ask for a compressed output this job cannot write.
+pub fn check_options(options: &SortOptions) -> Result<(), GtfSortError> {
+    if !options.output_compression.is_compressed() {
+        return Ok(());
+    }
+    if cfg!(not(feature = "gzip")) {
+        return Err(GtfSortError::InvalidParameter(
+            "compressed output needs the gzip feature, which this build lacks",
+        ));
+    }
+    if options.manifest.is_some() || options.incremental.is_some() {
+        return Err(GtfSortError::InvalidParameter(
+            "manifests record offsets into a plain text output and cannot be combined with output compression",
+        ));
+    }
+    Ok(())
+}
+
+/// Counts the bytes written through it.
+struct Counted<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum Encoder<W: Write> {
+    Plain(Counted<W>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Counted<W>>),
+    #[cfg(feature = "gzip")]
+    Bgzf(BgzfWriter<Counted<W>>),
+}
+
+/// Writes to `W` compressed as asked, see [OutputCompression]. Call
+/// [CompressedWriter::finish] once done, dropping it closes the stream but loses errors.
+pub struct CompressedWriter<W: Write> {
+    encoder: Encoder<W>,
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Fails with [io::ErrorKind::Unsupported] for a compressed output without the `gzip`
+    /// feature, see [check_options].
+    pub fn new(output: W, compression: OutputCompression) -> io::Result<Self> {
+        Self::with_pool(output, compression, None)
+    }
+
+    /// Same as [CompressedWriter::new], deflating BGZF blocks on `pool` rather than on the
+    /// current one.
+    pub fn with_pool(
+        output: W,
+        compression: OutputCompression,
+        pool: Option<Arc<ThreadPool>>,
+    ) -> io::Result<Self> {
+        let output = Counted {
+            inner: output,
+            written: 0,
+        };
+        let encoder = match compression {
+            OutputCompression::None => Encoder::Plain(output),
+            #[cfg(feature = "gzip")]
+            OutputCompression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                output,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "gzip")]
+            OutputCompression::Bgzf => Encoder::Bgzf(BgzfWriter::with_pool(output, pool)),
+            #[cfg(not(feature = "gzip"))]
+            _ => {
+                drop(pool);
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "built without the gzip feature",
+                ));
+            }
+        };
+        Ok(Self { encoder })
+    }
+
+    /// Ends the compressed stream and returns the number of bytes written to the output.
+    pub fn finish(self) -> io::Result<u64> {
+        #[cfg(not(feature = "gzip"))]
+        let Encoder::Plain(mut output) = self.encoder;
+        #[cfg(feature = "gzip")]
+        let mut output = match self.encoder {
+            Encoder::Plain(output) => output,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Bgzf(encoder) => encoder.finish()?,
+        };
+        output.flush()?;
+        Ok(output.written)
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(output) => output.write(buf),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "gzip")]
+            Encoder::Bgzf(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(output) => output.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Bgzf(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Writes BGZF blocks to `W`, deflating a batch of a few blocks per thread of its pool
+/// at once.
+#[cfg(feature = "gzip")]
+pub struct BgzfWriter<W: Write> {
+    /// None once finished.
+    output: Option<W>,
+    pending: Vec<u8>,
+    batch: usize,
+    /// The current pool if not set.
+    pool: Option<Arc<ThreadPool>>,
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> BgzfWriter<W> {
+    /// Blocks deflated per thread in one batch.
+    pub(crate) const BATCH_BLOCKS: usize = 4;
+
+    pub fn new(output: W) -> Self {
+        Self::with_pool(output, None)
+    }
+
+    pub fn with_pool(output: W, pool: Option<Arc<ThreadPool>>) -> Self {
+        let threads = pool
+            .as_ref()
+            .map_or_else(crate::par::current_num_threads, |p| p.current_num_threads());
+        let batch = BGZF_BLOCK_SIZE * Self::BATCH_BLOCKS * threads;
+        Self {
+            output: Some(output),
+            pending: Vec::with_capacity(batch),
+            batch,
+            pool,
+        }
+    }
+
+    /// Writes the pending bytes and the EOF block.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_blocks()?;
+        let mut output = self.output.take().unwrap();
+        output.write_all(&BGZF_EOF)?;
+        Ok(output)
+    }
+
+    /// Compresses the pending bytes, the last block may be short.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        use crate::par::*;
+
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let deflate = || {
+            self.pending
+                .chunks(BGZF_BLOCK_SIZE)
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(bgzf_block)
+                .collect::<Vec<_>>()
+        };
+        let blocks = match &self.pool {
+            Some(pool) => crate::panic::Job::current().install(pool, deflate),
+            None => deflate(),
+        };
+        let output = self.output.as_mut().unwrap();
+        for block in blocks {
+            output.write_all(&block?)?;
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.batch - self.pending.len());
+        self.pending.extend_from_slice(&buf[..n]);
+        if self.pending.len() == self.batch {
+            self.write_blocks()?;
+        }
+        Ok(n)
+    }
+
+    /// Compresses what is pending into a short block, so flush only at the end.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_blocks()?;
+        self.output.as_mut().unwrap().flush()
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() {
+            let _ = self.write_blocks();
+            let _ = self.output.as_mut().unwrap().write_all(&BGZF_EOF);
+        }
+    }
+}
+
+/// One BGZF block holding `data`, at most [BGZF_BLOCK_SIZE] bytes.
+#[cfg(feature = "gzip")]
+fn bgzf_block(data: &[u8]) -> io::Result<Vec<u8>> {
+    use flate2::{write::DeflateEncoder, Compression, Crc};
+
+    const HEADER: [u8; 16] = [
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02,
+        0x00,
+    ];
+    // header, BSIZE, then CRC32 and ISIZE after the deflated data
+    const OVERHEAD: usize = HEADER.len() + 2 + 8;
+
+    let deflate = |level| {
+        let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len()), level);
+        encoder.write_all(data)?;
+        encoder.finish()
+    };
+    let mut deflated = deflate(Compression::default())?;
+    if deflated.len() + OVERHEAD > u16::MAX as usize + 1 {
+        // incompressible data, stored blocks always fit
+        deflated = deflate(Compression::none())?;
+    }
+
+    let mut crc = Crc::new();
+    crc.update(data);
+    let mut block = Vec::with_capacity(deflated.len() + OVERHEAD);
+    block.extend_from_slice(&HEADER);
+    block.extend_from_slice(&((deflated.len() + OVERHEAD - 1) as u16).to_le_bytes());
+    block.extend_from_slice(&deflated);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(block)
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, temp::TempPath};
+    use std::io::Read;
+
+    const GTF: &str = "chr1\ts\tgene\t50\t90\t.\t+\t.\tgene_id \"B\";\n\
+        chr1\ts\tgene\t10\t40\t.\t+\t.\tgene_id \"A\";\n";
+
+    fn sort(compression: OutputCompression) -> Vec<u8> {
+        let mut output = Vec::new();
+        sort_annotations_string_with_options::<b' ', _>(
+            GTF,
+            &mut |b| {
+                output.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+            &SortOptions {
+                output_compression: compression,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        output
+    }
+
+    fn gunzip(bytes: &[u8]) -> String {
+        let mut text = String::new();
+        flate2::read::MultiGzDecoder::new(bytes)
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn bgzf_blocks_hold_the_output() {
+        let text = (0..40_000)
+            .map(|i| format!("line {}\n", i * 7919 % 100_003))
+            .collect::<String>();
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(text.as_bytes()).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        assert!(bytes.ends_with(&BGZF_EOF));
+        assert_eq!(gunzip(&bytes), text);
+        // walk the blocks by their BSIZE, as tabix does
+        let (mut offset, mut blocks) = (0, 0);
+        while offset < bytes.len() {
+            assert_eq!(bytes[offset..offset + 4], [0x1f, 0x8b, 0x08, 0x04]);
+            assert_eq!(bytes[offset + 12..offset + 14], *b"BC");
+            offset += u16::from_le_bytes([bytes[offset + 16], bytes[offset + 17]]) as usize + 1;
+            blocks += 1;
+        }
+        assert_eq!(offset, bytes.len());
+        assert_eq!(blocks, text.len().div_ceil(BGZF_BLOCK_SIZE) + 1);
+    }
+
+    #[test]
+    fn sorted_output_is_compressed() {
+        let plain = String::from_utf8(sort(OutputCompression::None)).unwrap();
+        assert!(plain.starts_with("chr1\ts\tgene\t10\t"));
+        for compression in [OutputCompression::Gzip, OutputCompression::Bgzf] {
+            let bytes = sort(compression);
+            assert_eq!(
+                bytes.ends_with(&BGZF_EOF),
+                compression == OutputCompression::Bgzf
+            );
+            assert_eq!(gunzip(&bytes), plain);
+        }
+
+        let dir = TempPath::dir(None, "compress_test").unwrap();
+        let (input, output) = (dir.join("in.gtf"), dir.join("out.gtf.gz"));
+        std::fs::write(&input, GTF).unwrap();
+        let ret = crate::sort_annotations_with_options(
+            &input,
+            &output,
+            1,
+            &SortOptions {
+                output_compression: OutputCompression::Bgzf,
+                verify: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(ret.output_bytes, std::fs::metadata(&output).unwrap().len());
+        assert_eq!(ret.verification.unwrap().output.records(), 2);
+        assert_eq!(crate::gzip::read_to_string(&output).unwrap(), plain);
+    }
+}
@@ -0,0 +1,131 @@
+//! Coordinate-only sorting, see [crate::SortOptions::coordinate_only].
+//!
+//! Lines are ordered by (chrom, start, end) regardless of the gene hierarchy, which is
+//! all bedtools and tabix need. Only the first, fourth and fifth columns are read, so
+//! the attribute column is never parsed and no [crate::Layers] are built.
+
+use std::io::{self, Write};
+
+use hashbrown::HashMap;
+
+use crate::{
+    filter::ChromFilter,
+    format::Newline,
+    gtf::line_coords,
+    par::*,
+    progress::PhaseProgress,
+    utils::{line_chunks_at, lines_at, watchdog_io_check, LineCounts},
+    watchdog::Watchdog,
+};
+
+/// (start, end, byte offset in the input, line) of the records of each chromosome.
+pub type CoordIndex<'a> = HashMap<&'a str, Vec<(u32, u32, usize, &'a str)>>;
+
+/// Collects the records of `s` by chromosome, skipping comments, blank lines and lines
+/// outside `chroms`. Lines without valid start/end columns are counted as invalid.
+pub fn parse_coords<'a>(
+    s: &'a str,
+    watchdog: &Watchdog,
+    chroms: Option<&ChromFilter>,
+) -> (CoordIndex<'a>, LineCounts) {
+    line_chunks_at(s, current_num_threads())
+        .into_par_iter()
+        .fold(
+            || (CoordIndex::new(), LineCounts::default()),
+            |(mut acc, mut counts), (offset, chunk)| {
+                for (ordinal, line) in
+                    lines_at(chunk, offset).take_while(|_| watchdog.tripped().is_none())
+                {
+                    if !counts.count_non_record(line, chroms) {
+                        let chrom = line.split('\t').next().unwrap_or_default();
+                        match line_coords(line) {
+                            Some((start, end)) => {
+                                counts.records += 1;
+                                acc.entry(chrom)
+                                    .or_default()
+                                    .push((start, end, ordinal, line));
+                            }
+                            None => counts.invalid += 1,
+                        }
+                    }
+                }
+                (acc, counts)
+            },
+        )
+        .reduce(
+            || (CoordIndex::new(), LineCounts::default()),
+            |(mut acc, counts), (map, n)| {
+                for (k, v) in map {
+                    acc.entry(k).or_default().extend(v);
+                }
+                (acc, counts.merge(n))
+            },
+        )
+}
+
+/// Sorts the records of each chromosome by (start, end), ties keep their input order.
+pub fn sort_coords(index: &mut CoordIndex) {
+    index.par_iter_mut().for_each(|(_, records)| {
+        records.sort_unstable_by_key(|(start, end, ordinal, _)| (*start, *end, *ordinal))
+    });
+}
+
+/// Writes the records of `index`, chromosomes in `keys` order.
+pub fn write_coords<W: Write>(
+    output: W,
+    index: &CoordIndex,
+    keys: &[&str],
+    newline: Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
+) -> io::Result<()> {
+    let mut output = io::BufWriter::new(output);
+
+    for chrom in keys {
+        watchdog_io_check(watchdog)?;
+        let mut written = 0;
+        for (_, _, _, line) in index.get(chrom).into_iter().flatten() {
+            output.write_all(line.as_bytes())?;
+            output.write_all(newline.as_bytes())?;
+            written += (line.len() + newline.as_bytes().len()) as u64;
+        }
+        progress.step_bytes(Some(chrom), written);
+    }
+
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_utils::sort_to_string, SortOptions};
+
+    #[test]
+    fn orders_by_chrom_start_end_only() {
+        let input = "chr2\ts\texon\t5\t9\t.\t+\t.\tgene_id \"B\";\n\
+            chr1\ts\tgene\t10\t90\t.\t+\t.\tgene_id \"A\";\n\
+            # comment\n\
+            chr1\ts\texon\t10\t20\t.\t+\t.\tno gene id here\n\
+            chr1\ts\ttranscript\t1\t50\t.\t+\t.\tgene_id \"C\";\n\
+            chr1\ts\texon\tx\t20\t.\t+\t.\tgene_id \"D\";\n";
+
+        let out = sort_to_string::<b' '>(
+            input,
+            &SortOptions {
+                coordinate_only: true,
+                ..Default::default()
+            },
+        );
+
+        let coords = out
+            .lines()
+            .map(|l| {
+                let f = l.split('\t').collect::<Vec<_>>();
+                format!("{}:{}-{}", f[0], f[3], f[4])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            coords,
+            ["chr1:1-50", "chr1:10-20", "chr1:10-90", "chr2:5-9"]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Duplicate records, e.g. the exons of a transcript listed twice in a merged annotation
+//! or a gene defined by two sources, see [crate::SortOptions::dedup].
+//!
+//! Records are compared within their chromosome after parsing, the first one in input
+//! order is kept and the others are counted as [crate::LineCounts::duplicates].
+
+use std::{fmt, str::FromStr};
+
+use hashbrown::HashSet;
+
+use crate::{gtf::line_strand, par::*, ChromRecord, Record};
+
+/// What makes two records duplicates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DedupMode {
+    /// Keep every record.
+    #[default]
+    Off,
+    /// Identical lines, ignoring trailing whitespace.
+    Exact,
+    /// The same feature type, start, end and strand under the same gene and transcript,
+    /// whatever their source, score or other attributes.
+    Coordinates,
+}
+
+impl FromStr for DedupMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(Self::Off),
+            "exact" => Ok(Self::Exact),
+            "coordinates" | "coords" => Ok(Self::Coordinates),
+            _ => Err(format!(
+                "unknown dedup mode {:?}, expected exact, coordinates or off",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DedupMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::Exact => "exact",
+            Self::Coordinates => "coordinates",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Key<'a> {
+    Line(&'a str),
+    Coordinates {
+        feat: &'a str,
+        start: u32,
+        end: u32,
+        strand: &'a str,
+        gene_id: &'a str,
+        transcript_id: &'a str,
+    },
+}
+
+impl<'a> Key<'a> {
+    #[inline(always)]
+    fn of(record: &Record<'a>, mode: DedupMode) -> Self {
+        match mode {
+            DedupMode::Coordinates => Key::Coordinates {
+                feat: record.feat,
+                start: record.start,
+                end: record.end,
+                strand: line_strand(record.line).unwrap_or_default(),
+                gene_id: record.gene_id,
+                transcript_id: record.transcript_id,
+            },
+            // trailing whitespace and \r are not part of the record
+            _ => Key::Line(record.line.trim_end()),
+        }
+    }
+}
+
+/// Drops the records of `records` that duplicate an earlier one of their chromosome,
+/// returning how many were dropped.
+pub fn drop_duplicates(records: &mut ChromRecord, mode: DedupMode) -> usize {
+    if mode == DedupMode::Off {
+        return 0;
+    }
+
+    records
+        .par_iter_mut()
+        .map(|(chrom, records)| {
+            let mut seen = HashSet::with_capacity(records.len());
+            let before = records.len();
+            records.retain(|r| seen.insert(Key::of(r, mode)));
+            let dropped = before - records.len();
+            if dropped > 0 {
+                log::debug!("{} duplicate records on {}", dropped, chrom);
+            }
+            dropped
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, SortOptions};
+
+    const GTF: &str = "chr1\thavana\tgene\t10\t90\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\thavana\ttranscript\t10\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\thavana\texon\t10\t40\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\thavana\texon\t10\t40\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\tpacbio\tgene\t10\t90\t.\t+\t.\tgene_id \"A\"; tag \"pacbio\";\n\
+        chr1\tpacbio\texon\t10\t40\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+
+    fn sort_with(dedup: DedupMode) -> (String, usize) {
+        let mut out = Vec::new();
+        let job = sort_annotations_string_with_options::<b' ', _>(
+            GTF,
+            &mut |b| {
+                out.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+            &SortOptions {
+                dedup,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(job.lines.records + job.lines.duplicates, 6);
+        (String::from_utf8(out).unwrap(), job.lines.duplicates)
+    }
+
+    #[test]
+    fn duplicates_are_dropped_by_mode() {
+        // a gene line given twice writes its whole block twice
+        let (out, duplicates) = sort_with(DedupMode::Off);
+        assert_eq!((out.lines().count(), duplicates), (10, 0));
+
+        let (out, duplicates) = sort_with(DedupMode::Exact);
+        assert_eq!((out.lines().count(), duplicates), (8, 1));
+
+        // the pacbio gene only differs by its source and attributes, its exon by strand
+        let (out, duplicates) = sort_with(DedupMode::Coordinates);
+        assert_eq!((out.lines().count(), duplicates), (4, 2));
+        assert_eq!(out.lines().filter(|l| l.contains("\tgene\t")).count(), 1);
+        assert!(out.lines().next().unwrap().contains("havana"));
+        assert!(out.contains("\t-\t"));
+    }
+}
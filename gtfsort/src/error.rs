@@ -0,0 +1,318 @@
+//! Stable error codes and the context attached to a [GtfSortError].
+//!
+//! The numeric value of each [ErrorCode] is part of the public API: it is what the C
+//! bindings return and what scripts may match on, so existing codes never change and new
+//! kinds of errors get new codes.
+
+use std::fmt;
+
+use crate::GtfSortError;
+
+/// Kind of a [GtfSortError], independent of the context wrapped around it.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    InvalidParameter = -1,
+    InvalidInput = 1,
+    InvalidOutput = 2,
+    ParseError = 3,
+    InvalidThreads = 4,
+    IoError = 5,
+    MemoryLimitExceeded = 6,
+    Timeout = 7,
+    Internal = 8,
+    VerificationFailed = 9,
+}
+
+impl ErrorCode {
+    /// Every code, in discriminant order.
+    pub const ALL: [ErrorCode; 10] = [
+        Self::InvalidParameter,
+        Self::InvalidInput,
+        Self::InvalidOutput,
+        Self::ParseError,
+        Self::InvalidThreads,
+        Self::IoError,
+        Self::MemoryLimitExceeded,
+        Self::Timeout,
+        Self::Internal,
+        Self::VerificationFailed,
+    ];
+
+    /// The stable numeric value of the code.
+    pub fn as_i32(self) -> i32 {
+        self as i32
+    }
+
+    pub fn from_i32(code: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_i32() == code)
+    }
+
+    /// snake_case name of the code, as used in JSON reports and the Python bindings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::InvalidParameter => "invalid_parameter",
+            Self::InvalidInput => "invalid_input",
+            Self::InvalidOutput => "invalid_output",
+            Self::ParseError => "parse_error",
+            Self::InvalidThreads => "invalid_threads",
+            Self::IoError => "io_error",
+            Self::MemoryLimitExceeded => "memory_limit_exceeded",
+            Self::Timeout => "timeout",
+            Self::Internal => "internal",
+            Self::VerificationFailed => "verification_failed",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Where an error happened. Every field is optional, only what is known is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// Step of the job, e.g. "parsing" or "writing output".
+    pub phase: Option<&'static str>,
+    pub chrom: Option<String>,
+    /// 1-based line number in the input.
+    pub line: Option<usize>,
+    /// Offset of the start of that line in the input, in bytes.
+    pub byte_offset: Option<u64>,
+}
+
+impl ErrorContext {
+    pub fn phase(phase: &'static str) -> Self {
+        Self {
+            phase: Some(phase),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_chrom(mut self, chrom: &str) -> Self {
+        self.chrom = Some(chrom.to_string());
+        self
+    }
+
+    pub fn with_line(mut self, line: usize) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    /// Sets the line number and byte offset of `line` in `input`, if `line` is a slice of
+    /// it.
+    pub fn with_location(mut self, input: &str, line: &str) -> Self {
+        if let Some((n, offset)) = locate(input, line) {
+            self.line = Some(n);
+            self.byte_offset = Some(offset as u64);
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Fills the fields not set in `self` from `outer`.
+    fn or(self, outer: &ErrorContext) -> Self {
+        Self {
+            phase: self.phase.or(outer.phase),
+            chrom: self.chrom.or_else(|| outer.chrom.clone()),
+            line: self.line.or(outer.line),
+            byte_offset: self.byte_offset.or(outer.byte_offset),
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sep = "";
+        if let Some(phase) = self.phase {
+            write!(f, "while {}", phase)?;
+            sep = ", ";
+        }
+        if let Some(chrom) = &self.chrom {
+            write!(f, "{}chromosome {:?}", sep, chrom)?;
+            sep = ", ";
+        }
+        if let Some(line) = self.line {
+            write!(f, "{}line {}", sep, line)?;
+            sep = ", ";
+        }
+        if let Some(offset) = self.byte_offset {
+            write!(f, "{}byte {}", sep, offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// 1-based line number and byte offset of `line` in `input`, if it is a slice of it.
+pub fn locate(input: &str, line: &str) -> Option<(usize, usize)> {
+    let offset = (line.as_ptr() as usize).checked_sub(input.as_ptr() as usize)?;
+    (offset + line.len() <= input.len()).then(|| {
+        let n = input.as_bytes()[..offset]
+            .iter()
+            .filter(|b| **b == b'\n')
+            .count();
+        (n + 1, offset)
+    })
+}
+
+impl GtfSortError {
+    /// The stable code of the error, looking through any [GtfSortError::Context].
+    pub fn code(&self) -> ErrorCode {
+        match self.root() {
+            GtfSortError::InvalidInput(_) => ErrorCode::InvalidInput,
+            GtfSortError::InvalidOutput(_) => ErrorCode::InvalidOutput,
+            GtfSortError::ParseError(_) | GtfSortError::InvalidRecord { .. } => {
+                ErrorCode::ParseError
+            }
+            GtfSortError::InvalidThreads(_) => ErrorCode::InvalidThreads,
+            GtfSortError::IoError(..) => ErrorCode::IoError,
+            GtfSortError::InvalidParameter(_) => ErrorCode::InvalidParameter,
+            GtfSortError::MemoryLimitExceeded(..) => ErrorCode::MemoryLimitExceeded,
+            GtfSortError::Timeout(_) => ErrorCode::Timeout,
+            GtfSortError::Internal(_) => ErrorCode::Internal,
+            GtfSortError::VerificationFailed(_) => ErrorCode::VerificationFailed,
+            GtfSortError::Context { .. } => unreachable!("root() unwraps every context"),
+        }
+    }
+
+    /// The error without the [GtfSortError::Context] wrapped around it.
+    pub fn root(&self) -> &GtfSortError {
+        match self {
+            GtfSortError::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// Owned version of [Self::root].
+    pub fn into_root(self) -> GtfSortError {
+        match self {
+            GtfSortError::Context { source, .. } => source.into_root(),
+            e => e,
+        }
+    }
+
+    /// Everything known about where the error happened.
+    pub fn context(&self) -> ErrorContext {
+        match self {
+            GtfSortError::Context { context, source } => source.context().or(context),
+            GtfSortError::Internal(report) => ErrorContext {
+                phase: report.context.phase,
+                chrom: report.context.chrom.clone(),
+                ..Default::default()
+            },
+            _ => ErrorContext::default(),
+        }
+    }
+
+    /// Attaches `context` to the error. Fields already set, closer to the cause, are kept.
+    pub fn with_context(self, context: ErrorContext) -> GtfSortError {
+        match self {
+            _ if context.is_empty() => self,
+            GtfSortError::Context {
+                context: inner,
+                source,
+            } => GtfSortError::Context {
+                context: inner.or(&context),
+                source,
+            },
+            e => GtfSortError::Context {
+                context,
+                source: Box::new(e),
+            },
+        }
+    }
+
+    /// Moves the line number and byte offset of the context down by `lines` and `bytes`,
+    /// for an input that was a slice of a larger one starting after them.
+    pub fn shift_location(self, lines: usize, bytes: usize) -> GtfSortError {
+        match self {
+            GtfSortError::Context {
+                mut context,
+                source,
+            } => {
+                context.line = context.line.map(|n| n + lines);
+                context.byte_offset = context.byte_offset.map(|o| o + bytes as u64);
+                GtfSortError::Context { context, source }
+            }
+            e => e,
+        }
+    }
+
+    /// Shorthand for [Self::with_context] with only a phase.
+    pub fn in_phase(self, phase: &'static str) -> GtfSortError {
+        self.with_context(ErrorContext::phase(phase))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn codes_are_stable() {
+        let codes = ErrorCode::ALL.map(|c| (c.name(), c.as_i32()));
+        assert_eq!(
+            codes,
+            [
+                ("invalid_parameter", -1),
+                ("invalid_input", 1),
+                ("invalid_output", 2),
+                ("parse_error", 3),
+                ("invalid_threads", 4),
+                ("io_error", 5),
+                ("memory_limit_exceeded", 6),
+                ("timeout", 7),
+                ("internal", 8),
+                ("verification_failed", 9),
+            ]
+        );
+        assert!(ErrorCode::ALL
+            .into_iter()
+            .all(|c| ErrorCode::from_i32(c.as_i32()) == Some(c)));
+        assert_eq!(ErrorCode::from_i32(0), None);
+    }
+
+    #[test]
+    fn context_is_chained_and_merged() {
+        let input = "chr1\tline\none\nchr2\tbad\n";
+        let line = input.lines().nth(2).unwrap();
+        let e = GtfSortError::IoError("reading", std::io::Error::other("disk"))
+            .with_context(
+                ErrorContext::phase("parsing")
+                    .with_chrom("chr2")
+                    .with_location(input, line),
+            )
+            .in_phase("sorting");
+
+        assert_eq!(e.code(), ErrorCode::IoError);
+        assert_eq!(
+            e.context(),
+            ErrorContext {
+                phase: Some("parsing"),
+                chrom: Some("chr2".to_string()),
+                line: Some(3),
+                byte_offset: Some(14),
+            }
+        );
+        assert_eq!(
+            e.to_string(),
+            "IO GtfSortError: while reading: disk (while parsing, chromosome \"chr2\", line 3, byte 14)"
+        );
+
+        let mut chain = Vec::new();
+        let mut source = e.source();
+        while let Some(s) = source {
+            chain.push(s.to_string());
+            source = s.source();
+        }
+        assert_eq!(chain.last().unwrap(), "disk");
+        assert!(matches!(e.root(), GtfSortError::IoError("reading", _)));
+    }
+}
@@ -0,0 +1,508 @@
+//! Dry-run explanation of how an input is interpreted, for users who suspect lines went
+//! missing from their output.
+//!
+//! The input goes through the same parser and index as a sort (see [crate::index_records])
+//! but nothing is written. Instead the [Explanation] tells which format was used, which
+//! role every feature type played, which attribute keys the hierarchy was built from,
+//! what happened to records whose parents are missing and in which order chromosomes
+//! would be written.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, Write},
+};
+
+use hashbrown::HashMap;
+
+use crate::{
+    build_index,
+    features::{FeatureMap, LayerMap},
+    format::FormatDetection,
+    gtf::{self, Layer, Record},
+    ord::GeneSortKey,
+    parallel_parse, sniff, sort_chroms,
+    watchdog::Watchdog,
+    AnnotationFormat, GtfSortError, LineCounts, SortOptions,
+};
+
+/// Invalid lines quoted in an [Explanation].
+pub(crate) const INVALID_EXAMPLES: usize = 5;
+
+/// Where the records of a feature type are placed in the output, mirroring the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureRole {
+    /// Starts a gene block.
+    Gene,
+    /// Follows its gene, before its children.
+    Transcript,
+    /// Ordered by exon_number, then by this rank within the exon (see [FeatureMap]).
+    ExonLevel(u8),
+    /// Written after the exon-level features by name, see [gtf::KNOWN_CHILD_FEATURES].
+    Child,
+    /// Placed like [FeatureRole::Child], but reported as an unknown feature type.
+    Unknown,
+}
+
+impl FeatureRole {
+    /// The role of records of type `feat`, unless placed by their GFF3 `Parent` chain.
+    pub fn of(feat: &str, features: &FeatureMap, layers: &LayerMap) -> Self {
+        match layers.layer(feat) {
+            Layer::Gene => Self::Gene,
+            Layer::Transcript => Self::Transcript,
+            Layer::Child => match features.rank(feat) {
+                Some(rank) => Self::ExonLevel(rank),
+                None if gtf::KNOWN_CHILD_FEATURES.contains(&feat) => Self::Child,
+                None => Self::Unknown,
+            },
+        }
+    }
+}
+
+impl fmt::Display for FeatureRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Gene => write!(f, "gene, starts a block"),
+            Self::Transcript => write!(f, "transcript, follows its gene"),
+            Self::ExonLevel(rank) => write!(
+                f,
+                "exon-level, by exon_number then #{} within an exon",
+                rank + 1
+            ),
+            Self::Child => write!(f, "child, after the exon-level features by name"),
+            Self::Unknown => write!(f, "unknown child, after the exon-level features by name"),
+        }
+    }
+}
+
+/// What happened to records whose place in the gene hierarchy is incomplete.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Orphans {
+    /// GFF3 records whose `Parent` chain does not lead to a top-level feature, dropped.
+    pub unresolved_parents: usize,
+    /// Transcripts whose gene has no gene line, dropped with their children.
+    pub transcripts_without_gene: usize,
+    /// Children of the transcripts above.
+    pub children_of_dropped_transcripts: usize,
+    /// Children whose transcript has no transcript line, dropped unless their gene has a
+    /// gene line and [SortOptions::recover_transcripts] is set.
+    pub children_without_transcript: usize,
+    /// Genes without any transcript.
+    pub genes_without_transcripts: usize,
+    /// Transcripts without any child.
+    pub transcripts_without_children: usize,
+    /// Gene and transcript lines synthesized from their children.
+    pub synthesized: usize,
+}
+
+/// How gtfsort interpreted an input, see [explain].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub format: AnnotationFormat,
+    /// None if the format was given rather than detected.
+    pub detection: Option<FormatDetection>,
+    pub lines: LineCounts,
+    /// The first [INVALID_EXAMPLES] invalid lines as (line number, reason).
+    pub invalid_examples: Vec<(usize, String)>,
+    /// Feature types with their role and number of records, most frequent first.
+    pub features: Vec<(String, FeatureRole, usize)>,
+    /// Attribute keys with the number of records carrying them and what the sorter uses
+    /// them for, if anything. Most frequent first.
+    pub attributes: Vec<(String, usize, Option<String>)>,
+    pub orphans: Orphans,
+    /// Chromosomes in output order, with the genes and lines written for each. Those
+    /// without any line written are left out.
+    pub chroms: Vec<(String, usize, usize)>,
+}
+
+impl Explanation {
+    /// Lines the sort would write.
+    pub fn written(&self) -> usize {
+        self.chroms.iter().map(|(_, _, n)| n).sum()
+    }
+
+    /// Writes the explanation as plain text.
+    pub fn write_report<W: Write>(&self, mut w: W) -> io::Result<()> {
+        match &self.detection {
+            Some(detection) => writeln!(w, "Format: {} (detected: {})", self.format, detection)?,
+            None => writeln!(w, "Format: {} (given)", self.format)?,
+        }
+
+        let l = &self.lines;
+        writeln!(
+            w,
+            "\nLines: {} in total, {} records, {} comments, {} blank, {} invalid, {} sequence",
+            l.total(),
+            l.records,
+            l.comments,
+            l.blank,
+            l.invalid,
+            l.sequence
+        )?;
+        for (n, reason) in &self.invalid_examples {
+            writeln!(w, "  invalid line {}: {}", n, reason)?;
+        }
+
+        writeln!(w, "\nFeatures (records, placement):")?;
+        let width = self.features.iter().map(|f| f.0.len()).max().unwrap_or(0);
+        for (feat, role, n) in &self.features {
+            writeln!(w, "  {:width$}  {:>9}  {}", feat, n, role, width = width)?;
+        }
+
+        writeln!(w, "\nAttribute keys (records, use):")?;
+        let width = self.attributes.iter().map(|a| a.0.len()).max().unwrap_or(0);
+        for (key, n, used) in &self.attributes {
+            let used = used.as_deref().unwrap_or("copied as is");
+            writeln!(w, "  {:width$}  {:>9}  {}", key, n, used, width = width)?;
+        }
+
+        let o = &self.orphans;
+        writeln!(w, "\nHierarchy:")?;
+        let outcomes = [
+            (
+                o.unresolved_parents,
+                "GFF3 records whose Parent chain leads nowhere: dropped",
+            ),
+            (
+                o.transcripts_without_gene,
+                "transcripts without a gene line: dropped (see --synthesize-parents)",
+            ),
+            (
+                o.children_of_dropped_transcripts,
+                "children of those transcripts: dropped",
+            ),
+            (
+                o.children_without_transcript,
+                "children without a transcript line: dropped (see --synthesize-parents)",
+            ),
+            (
+                o.genes_without_transcripts,
+                "genes without transcripts: written as a lone gene line",
+            ),
+            (
+                o.transcripts_without_children,
+                "transcripts without children: written as a lone transcript line",
+            ),
+            (o.synthesized, "gene and transcript lines synthesized"),
+        ];
+        for (n, outcome) in outcomes.iter().filter(|(n, _)| *n > 0) {
+            writeln!(w, "  {:>9}  {}", n, outcome)?;
+        }
+        writeln!(
+            w,
+            "  {} of {} records would be written",
+            self.written() - o.synthesized,
+            l.records
+        )?;
+
+        writeln!(w, "\nChromosome order (genes, lines):")?;
+        let width = self.chroms.iter().map(|c| c.0.len()).max().unwrap_or(0);
+        for (i, (chrom, genes, lines)) in self.chroms.iter().enumerate() {
+            writeln!(
+                w,
+                "  {:>4}. {:width$}  {:>9}  {:>9}",
+                i + 1,
+                chrom,
+                genes,
+                lines,
+                width = width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} records read as {}, {} of them would be written on {} chromosomes",
+            self.lines.records,
+            self.format,
+            self.written() - self.orphans.synthesized,
+            self.chroms.len()
+        )
+    }
+}
+
+/// Explains how `contents` would be sorted with `options`. The format is
+/// [SortOptions::format], or detected from the attribute columns.
+pub fn explain(contents: &str, options: &SortOptions) -> Result<Explanation, GtfSortError> {
+    let (format, detection) = match options.format {
+        Some(format) => (format, None),
+        None => {
+            let detection = AnnotationFormat::detect(contents);
+            let format = detection.format.ok_or_else(|| {
+                GtfSortError::InvalidInput(
+                    "could not detect the format, no attribute column looks like GTF or GFF3"
+                        .to_string(),
+                )
+            })?;
+            (format, Some(detection))
+        }
+    };
+
+    match format {
+        AnnotationFormat::Gtf => explain_as::<b' '>(contents, options, format, detection),
+        AnnotationFormat::Gff3 => explain_as::<b'='>(contents, options, format, detection),
+    }
+}
+
+fn explain_as<const SEP: u8>(
+    contents: &str,
+    options: &SortOptions,
+    format: AnnotationFormat,
+    detection: Option<FormatDetection>,
+) -> Result<Explanation, GtfSortError> {
+    let mut lines = LineCounts::default();
+    let mut invalid_examples = Vec::new();
+    let mut features = HashMap::<&str, usize>::new();
+    let mut attributes = HashMap::<&str, usize>::new();
+    let mut keys = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        if lines.count_non_record(line, options.chrom_filter()) {
+            continue;
+        }
+        match Record::parse::<SEP>(line) {
+            Ok(record) => {
+                lines.records += 1;
+                *features.entry(record.feat).or_default() += 1;
+
+                // count each key once per record
+                let attrs = line.splitn(9, '\t').nth(8).unwrap_or_default();
+                keys.clear();
+                keys.extend(gtf::pairs::<SEP>(attrs).map(|(key, _)| key));
+                keys.sort_unstable();
+                keys.dedup();
+                for key in keys.iter() {
+                    *attributes.entry(key).or_default() += 1;
+                }
+            }
+            Err(e) => {
+                lines.invalid += 1;
+                if invalid_examples.len() < INVALID_EXAMPLES {
+                    invalid_examples.push((i + 1, e.into_owned()));
+                }
+            }
+        }
+    }
+
+    let (annotation, _) = sniff::split_fasta(contents);
+    let mut records = parallel_parse::<SEP>(annotation)
+        .map_err(|e| GtfSortError::ParseError(e).in_phase("parsing"))?;
+    if let Some(chroms) = options.chrom_filter() {
+        records.retain(|chrom, _| chroms.keep(chrom));
+    }
+    let resolved = records.values().map(Vec::len).sum::<usize>();
+    let mut orphans = Orphans {
+        unresolved_parents: lines.records.saturating_sub(resolved),
+        ..Default::default()
+    };
+    lines.records -= orphans.unresolved_parents;
+    lines.invalid += orphans.unresolved_parents;
+
+    let (index, _) = build_index::<SEP>(&records, options, None, &Watchdog::disabled())
+        .map_err(|e| e.in_phase("indexing"))?;
+
+    let mut chroms = index.keys().copied().collect::<Vec<_>>();
+    sort_chroms(&mut chroms, options)?;
+    let chroms = chroms
+        .into_iter()
+        .map(|chrom| {
+            let layers = &index[chrom];
+            let synthesized = |line: &Cow<str>| usize::from(matches!(line, Cow::Owned(_)));
+            let mut written = 0;
+
+            for (_, gene_id, line) in layers.layer.iter() {
+                written += 1;
+                orphans.synthesized += synthesized(line);
+                let Some(transcripts) = layers.mapper.get(gene_id) else {
+                    orphans.genes_without_transcripts += 1;
+                    continue;
+                };
+                for transcript_id in transcripts {
+                    written += 1;
+                    orphans.synthesized += layers.helper.get(transcript_id).map_or(0, synthesized);
+                    match layers.inner.get(transcript_id) {
+                        Some(children) => written += children.values().map(Vec::len).sum::<usize>(),
+                        None => orphans.transcripts_without_children += 1,
+                    }
+                }
+            }
+
+            let genes = layers
+                .layer
+                .iter()
+                .map(|(_, gene_id, _)| *gene_id)
+                .collect::<hashbrown::HashSet<_>>();
+            for (gene_id, transcripts) in layers.mapper.iter() {
+                if genes.contains(gene_id) {
+                    continue;
+                }
+                orphans.transcripts_without_gene += transcripts.len();
+                orphans.children_of_dropped_transcripts += transcripts
+                    .iter()
+                    .filter_map(|t| layers.inner.get(t))
+                    .flat_map(|children| children.values())
+                    .map(Vec::len)
+                    .sum::<usize>();
+            }
+            orphans.children_without_transcript += layers
+                .inner
+                .iter()
+                .filter(|(t, _)| !layers.has_parent_line(t))
+                .flat_map(|(_, children)| children.values())
+                .map(Vec::len)
+                .sum::<usize>();
+
+            (chrom.to_string(), layers.layer.len(), written)
+        })
+        // e.g. only holding dropped orphans
+        .filter(|(_, _, written)| *written > 0)
+        .collect();
+
+    let mut features = features
+        .into_iter()
+        .map(|(feat, n)| {
+            let role = FeatureRole::of(feat, &options.feature_map, &options.layer_map);
+            (feat.to_string(), role, n)
+        })
+        .collect::<Vec<_>>();
+    features.sort_unstable_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+    let mut attributes = attributes
+        .into_iter()
+        .map(|(key, n)| (key.to_string(), n, attribute_use::<SEP>(key, options)))
+        .collect::<Vec<_>>();
+    attributes.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    Ok(Explanation {
+        format,
+        detection,
+        lines,
+        invalid_examples,
+        features,
+        attributes,
+        orphans,
+        chroms,
+    })
+}
+
+/// What the sorter reads attribute `key` for, with `options`.
+fn attribute_use<const SEP: u8>(key: &str, options: &SortOptions) -> Option<String> {
+    let used = match key {
+        "gene_id" => "groups records into genes",
+        "transcript_id" => "groups children under their transcript",
+        "exon_number" => "orders the exon-level features of a transcript",
+        "ID" if SEP == b'=' => "names parents, for Parent",
+        "Parent" if SEP == b'=' => "places records under their gene and transcript (first parent)",
+        _ => match &options.gene_sort_key {
+            GeneSortKey::Attribute(attr) if attr == key => "orders genes (--gene-sort-key)",
+            _ => return None,
+        },
+    };
+    Some(used.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chrom_order::ChromSortOrder;
+
+    const GTF: &str = "#!genome-build test\n\
+        chr10\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"B\";\n\
+        chr10\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr10\ts\texon\t1\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr10\ts\tregion\t1\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr2\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\"; TPM \"3\";\n\
+        chr2\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr2\ts\texon\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr2\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+        chr2\ts\texon\t1\t90\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\"; exon_number \"1\";\n\
+        chr2\ts\tCDS\t1\t90\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.9\"; exon_number \"1\";\n\
+        chr2\ts\texon\t1\t90\t.\t+\t.\ttranscript_id \"A.1\";\n";
+
+    #[test]
+    fn orphans_and_roles_are_explained() {
+        let options = SortOptions {
+            gene_sort_key: GeneSortKey::Attribute("TPM".to_string()),
+            ..Default::default()
+        };
+        let explanation = explain(GTF, &options).unwrap();
+
+        assert_eq!(explanation.format, AnnotationFormat::Gtf);
+        assert_eq!(explanation.detection.unwrap().gtf_votes, 11);
+        assert_eq!(
+            (explanation.lines.records, explanation.lines.invalid),
+            (10, 1)
+        );
+        assert_eq!(explanation.invalid_examples[0].0, 12);
+        assert_eq!(
+            explanation.orphans,
+            Orphans {
+                transcripts_without_gene: 1,
+                children_of_dropped_transcripts: 1,
+                children_without_transcript: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            explanation.chroms,
+            [("chr2".to_string(), 1, 3), ("chr10".to_string(), 1, 4)]
+        );
+        assert_eq!(explanation.written(), 7);
+
+        let role = |feat: &str| explanation.features.iter().find(|f| f.0 == feat).unwrap().1;
+        assert_eq!(role("CDS"), FeatureRole::ExonLevel(1));
+        assert_eq!(role("region"), FeatureRole::Unknown);
+        let tpm = explanation
+            .attributes
+            .iter()
+            .find(|a| a.0 == "TPM")
+            .unwrap();
+        assert_eq!(tpm.2.as_deref(), Some("orders genes (--gene-sort-key)"));
+
+        let mut report = Vec::new();
+        explanation.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(
+            report.contains("transcripts without a gene line: dropped"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("7 of 10 records would be written"),
+            "{}",
+            report
+        );
+
+        // the CDS of A.9 can be kept under a synthesized transcript line
+        let recovered = explain(
+            GTF,
+            &SortOptions {
+                recover_transcripts: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(recovered.orphans.children_without_transcript, 0);
+        assert_eq!(recovered.orphans.synthesized, 1);
+        assert_eq!(recovered.written() - recovered.orphans.synthesized, 8);
+
+        // chromosomes are listed in the order they would be written
+        let lex = explain(
+            GTF,
+            &SortOptions {
+                chrom_sort: ChromSortOrder::Lex,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            lex.chroms.iter().map(|c| c.0.as_str()).collect::<Vec<_>>(),
+            ["chr10", "chr2"]
+        );
+    }
+}
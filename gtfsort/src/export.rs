@@ -0,0 +1,624 @@
+//! Output formats derived from the sorted index instead of the annotation itself, see
+//! [crate::SortOptions::export].
+//!
+//! Each format is an [Exporter] fed one chromosome at a time, in output order, so it works
+//! the same on a whole index and on the per-chromosome indexes of a windowed job.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    format::{AnnotationFormat, Newline},
+    gtf::{line_attribute, line_coords, line_strand},
+    GtfSortError, Index, Layers, SortOptions,
+};
+
+/// Header of a SAF file, as expected by featureCounts.
+pub const SAF_HEADER: &str = "GeneID\tChr\tStart\tEnd\tStrand";
+
+/// A format written with [export] in place of the sorted annotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Simplified Annotation Format (GeneID, Chr, Start, End, Strand) for featureCounts
+    /// (`-F SAF`), with one line per distinct exon of each gene or one per gene.
+    Saf(SafLevel),
+    /// Picard refFlat (geneName, name, chrom, strand, txStart, txEnd, cdsStart, cdsEnd,
+    /// exonCount, exonStarts, exonEnds) with one line per transcript, as converted from
+    /// `gtfToGenePred -genePredExt -geneNameAsName2` for CollectRnaSeqMetrics.
+    RefFlat,
+    /// BED12 with one line per transcript named by transcript_id, the CDS as thick part,
+    /// as converted by `genePredToBed`.
+    Bed12,
+}
+
+/// Features listed in a SAF export.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SafLevel {
+    /// The distinct exons of each gene, pooled over its transcripts. featureCounts counts
+    /// reads per GeneID over them, like it does with `-t exon -g gene_id` on the GTF.
+    #[default]
+    Exon,
+    /// The span of each gene line.
+    Gene,
+}
+
+impl ExportFormat {
+    /// The [Exporter] of the format, for an input in `input` format.
+    pub fn exporter(self, input: AnnotationFormat) -> Box<dyn Exporter + Send> {
+        match self {
+            Self::Saf(level) => Box::new(SafExporter { level }),
+            Self::RefFlat => Box::new(RefFlatExporter { input }),
+            Self::Bed12 => Box::new(Bed12Exporter),
+        }
+    }
+
+    /// The format of an output named `*.saf`, `*.refflat` or `*.bed12`/`*.bed`, ignoring
+    /// case.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "saf" => Some(Self::Saf(SafLevel::Exon)),
+            "refflat" => Some(Self::RefFlat),
+            "bed12" | "bed" => Some(Self::Bed12),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "saf" => Ok(Self::Saf(SafLevel::Exon)),
+            "saf-gene" => Ok(Self::Saf(SafLevel::Gene)),
+            "refflat" => Ok(Self::RefFlat),
+            "bed12" => Ok(Self::Bed12),
+            _ => Err(format!(
+                "unknown output format {:?}, expected saf, saf-gene, refflat or bed12",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Saf(SafLevel::Exon) => "saf",
+            Self::Saf(SafLevel::Gene) => "saf-gene",
+            Self::RefFlat => "refflat",
+            Self::Bed12 => "bed12",
+        })
+    }
+}
+
+/// Writes one output format from sorted [Layers].
+pub trait Exporter {
+    /// Lines written once before the first chromosome, without line terminators.
+    fn header(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Writes the genes of one chromosome, in output order.
+    fn write_chrom(
+        &mut self,
+        out: &mut dyn Write,
+        chrom: &str,
+        layers: &Layers,
+        newline: Newline,
+    ) -> io::Result<()>;
+}
+
+/// Rejects options that describe the sorted annotation itself, which an export replaces.
+pub(crate) fn check_options(options: &SortOptions) -> Result<(), GtfSortError> {
+    if options.export.is_some()
+        && (options.coordinate_only || options.manifest.is_some() || options.incremental.is_some())
+    {
+        return Err(GtfSortError::InvalidParameter(
+            "an export format cannot be combined with coordinate-only sorting, manifests or incremental sorting",
+        ));
+    }
+    if !options.extra_outputs.is_empty() && options.coordinate_only {
+        return Err(GtfSortError::InvalidParameter(
+            "coordinate-only sorting builds no index to derive extra outputs from",
+        ));
+    }
+    Ok(())
+}
+
+/// Writes the header of `exporter`, then the chromosomes of `index` in the order of `keys`.
+pub fn export<W: Write>(
+    exporter: &mut dyn Exporter,
+    mut out: W,
+    index: &Index,
+    keys: &[&str],
+    newline: Newline,
+) -> io::Result<()> {
+    write_header(exporter, &mut out, newline)?;
+    for chrom in keys {
+        if let Some(layers) = index.get(chrom) {
+            exporter.write_chrom(&mut out, chrom, layers, newline)?;
+        }
+    }
+    out.flush()
+}
+
+/// The files of [crate::SortOptions::extra_outputs], written from the same index as the
+/// main output. Files not completed with [Self::finish] are removed when dropped, so a
+/// failed job leaves no truncated output behind.
+pub(crate) struct ExtraOutputs {
+    sinks: Vec<ExtraSink>,
+    newline: Newline,
+    finished: bool,
+}
+
+struct ExtraSink {
+    path: PathBuf,
+    exporter: Box<dyn Exporter + Send>,
+    out: BufWriter<File>,
+}
+
+impl ExtraOutputs {
+    /// Creates the files of `options.extra_outputs` and writes their headers.
+    pub(crate) fn create(
+        options: &SortOptions,
+        input: AnnotationFormat,
+    ) -> Result<Self, GtfSortError> {
+        let mut extra = Self {
+            sinks: Vec::with_capacity(options.extra_outputs.len()),
+            newline: options.newline,
+            finished: false,
+        };
+        for (path, format) in options.extra_outputs.iter() {
+            let exporter = format.exporter(input);
+            let out = File::create(path)
+                .map_err(|e| GtfSortError::IoError("creating extra output file", e))?;
+            extra.sinks.push(ExtraSink {
+                path: path.clone(),
+                exporter,
+                out: BufWriter::new(out),
+            });
+            let sink = extra.sinks.last_mut().unwrap();
+            write_header(&*sink.exporter, &mut sink.out, options.newline)
+                .map_err(|e| GtfSortError::IoError("writing extra output file", e))?;
+        }
+        Ok(extra)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Writes the genes of one chromosome to every file.
+    pub(crate) fn write_chrom(&mut self, chrom: &str, layers: &Layers) -> Result<(), GtfSortError> {
+        for sink in self.sinks.iter_mut() {
+            sink.exporter
+                .write_chrom(&mut sink.out, chrom, layers, self.newline)
+                .map_err(|e| GtfSortError::IoError("writing extra output file", e))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the chromosomes of `index` in the order of `keys`, each file on its own
+    /// thread.
+    pub(crate) fn write_index(&mut self, index: &Index, keys: &[&str]) -> Result<(), GtfSortError> {
+        let newline = self.newline;
+        let job = crate::panic::Job::current();
+        std::thread::scope(|s| {
+            let jobs = self
+                .sinks
+                .iter_mut()
+                .map(|sink| {
+                    let job = &job;
+                    s.spawn(move || {
+                        let _ctx = job.enter("writing extra output", None);
+                        for chrom in keys {
+                            if let Some(layers) = index.get(chrom) {
+                                sink.exporter
+                                    .write_chrom(&mut sink.out, chrom, layers, newline)?;
+                            }
+                        }
+                        sink.out.flush()
+                    })
+                })
+                .collect::<Vec<_>>();
+            jobs.into_iter().try_for_each(|job| {
+                job.join()
+                    .unwrap_or_else(|p| std::panic::resume_unwind(p))
+                    .map_err(|e| GtfSortError::IoError("writing extra output file", e))
+            })
+        })
+    }
+
+    /// Flushes the files, keeping them.
+    pub(crate) fn finish(mut self) -> Result<(), GtfSortError> {
+        for sink in self.sinks.iter_mut() {
+            sink.out
+                .flush()
+                .map_err(|e| GtfSortError::IoError("writing extra output file", e))?;
+        }
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for ExtraOutputs {
+    fn drop(&mut self) {
+        if !self.finished {
+            for sink in self.sinks.iter() {
+                let _ = std::fs::remove_file(&sink.path);
+            }
+        }
+    }
+}
+
+pub(crate) fn write_header(
+    exporter: &dyn Exporter,
+    out: &mut dyn Write,
+    newline: Newline,
+) -> io::Result<()> {
+    for line in exporter.header() {
+        out.write_all(line.as_bytes())?;
+        out.write_all(newline.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// See [ExportFormat::Saf].
+pub struct SafExporter {
+    pub level: SafLevel,
+}
+
+impl Exporter for SafExporter {
+    fn header(&self) -> &[&str] {
+        &[SAF_HEADER]
+    }
+
+    fn write_chrom(
+        &mut self,
+        out: &mut dyn Write,
+        chrom: &str,
+        layers: &Layers,
+        newline: Newline,
+    ) -> io::Result<()> {
+        let mut exons = Vec::new();
+        for (_, gene_id, line) in layers.layer.iter() {
+            exons.clear();
+            match self.level {
+                SafLevel::Gene => exons.extend(saf_feature(line)),
+                SafLevel::Exon => {
+                    let children = layers
+                        .mapper
+                        .get(gene_id)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|t| layers.inner.get(t))
+                        .flat_map(|c| c.values().flatten());
+                    exons.extend(
+                        children
+                            .filter(|l| l.split('\t').nth(2) == Some("exon"))
+                            .filter_map(|l| saf_feature(l)),
+                    );
+                    exons.sort_unstable();
+                    exons.dedup();
+                }
+            }
+
+            for (start, end, strand) in exons.iter() {
+                write!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}",
+                    gene_id, chrom, start, end, strand
+                )?;
+                out.write_all(newline.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// (start, end, strand) of a line, with `.` for an unknown strand as featureCounts expects.
+fn saf_feature(line: &str) -> Option<(u32, u32, &str)> {
+    let (start, end) = line_coords(line)?;
+    let strand = match line_strand(line)? {
+        s @ ("+" | "-") => s,
+        _ => ".",
+    };
+    Some((start, end, strand))
+}
+
+/// Exon structure of a transcript as in a UCSC genePred, with 0-based half-open
+/// coordinates except for `exons`, which keep those of the input.
+struct GenePred<'a> {
+    gene_id: &'a str,
+    gene_line: &'a str,
+    transcript_id: &'a str,
+    transcript_line: Option<&'a str>,
+    strand: &'a str,
+    tx_start: u32,
+    tx_end: u32,
+    cds_start: u32,
+    cds_end: u32,
+    exons: &'a [(u32, u32)],
+}
+
+/// Calls `f` with each transcript of `layers` that has exons (or coding features standing
+/// in for them), in output order.
+fn for_each_gene_pred(
+    layers: &Layers,
+    mut f: impl FnMut(&GenePred) -> io::Result<()>,
+) -> io::Result<()> {
+    let (mut exons, mut cds) = (Vec::new(), Vec::new());
+    for (_, gene_id, gene_line) in layers.layer.iter() {
+        for transcript_id in layers.mapper.get(gene_id).into_iter().flatten() {
+            let Some(children) = layers.inner.get(transcript_id) else {
+                continue;
+            };
+            exons.clear();
+            cds.clear();
+            for line in children.values().flatten() {
+                let Some((start, end)) = line_coords(line) else {
+                    continue;
+                };
+                match line.split('\t').nth(2) {
+                    Some("exon") => exons.push((start, end)),
+                    Some("CDS" | "start_codon" | "stop_codon") => cds.push((start, end)),
+                    _ => {}
+                }
+            }
+            exons.sort_unstable();
+            exons.dedup();
+            // like gtfToGenePred, coding features stand in for missing exons, with
+            // codons joined to the CDS they abut
+            if exons.is_empty() {
+                cds.sort_unstable();
+                for &(start, end) in cds.iter() {
+                    match exons.last_mut() {
+                        Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                        _ => exons.push((start, end)),
+                    }
+                }
+            }
+            if exons.is_empty() {
+                continue;
+            }
+
+            let transcript_line = layers.helper.get(transcript_id).map(|l| l.as_ref());
+            let first = children.values().flatten().next().copied();
+            let strand = transcript_line
+                .or(first)
+                .and_then(line_strand)
+                .filter(|s| matches!(*s, "+" | "-"))
+                .unwrap_or("+");
+
+            // 0-based; a start of 0 is malformed but must not underflow
+            let tx_start = exons[0].0.saturating_sub(1);
+            let tx_end = exons.iter().map(|e| e.1).max().unwrap_or(tx_start);
+            let (cds_start, cds_end) =
+                match (cds.iter().map(|c| c.0).min(), cds.iter().map(|c| c.1).max()) {
+                    (Some(start), Some(end)) => (start.saturating_sub(1), end),
+                    _ => (tx_end, tx_end),
+                };
+
+            f(&GenePred {
+                gene_id,
+                gene_line,
+                transcript_id,
+                transcript_line,
+                strand,
+                tx_start,
+                tx_end,
+                cds_start,
+                cds_end,
+                exons: &exons,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// See [ExportFormat::RefFlat].
+pub struct RefFlatExporter {
+    pub input: AnnotationFormat,
+}
+
+impl Exporter for RefFlatExporter {
+    fn write_chrom(
+        &mut self,
+        out: &mut dyn Write,
+        chrom: &str,
+        layers: &Layers,
+        newline: Newline,
+    ) -> io::Result<()> {
+        for_each_gene_pred(layers, |t| {
+            let gene_name = t
+                .transcript_line
+                .and_then(|l| self.attribute(l, "gene_name"))
+                .or_else(|| self.attribute(t.gene_line, "gene_name"))
+                .unwrap_or(t.gene_id);
+
+            write!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+                gene_name,
+                t.transcript_id,
+                chrom,
+                t.strand,
+                t.tx_start,
+                t.tx_end,
+                t.cds_start,
+                t.cds_end,
+                t.exons.len()
+            )?;
+            for (start, _) in t.exons.iter() {
+                write!(out, "{},", start.saturating_sub(1))?;
+            }
+            out.write_all(b"\t")?;
+            for (_, end) in t.exons.iter() {
+                write!(out, "{},", end)?;
+            }
+            out.write_all(newline.as_bytes())
+        })
+    }
+}
+
+/// See [ExportFormat::Bed12].
+pub struct Bed12Exporter;
+
+impl Exporter for Bed12Exporter {
+    fn write_chrom(
+        &mut self,
+        out: &mut dyn Write,
+        chrom: &str,
+        layers: &Layers,
+        newline: Newline,
+    ) -> io::Result<()> {
+        for_each_gene_pred(layers, |t| {
+            write!(
+                out,
+                "{}\t{}\t{}\t{}\t0\t{}\t{}\t{}\t0\t{}\t",
+                chrom,
+                t.tx_start,
+                t.tx_end,
+                t.transcript_id,
+                t.strand,
+                t.cds_start,
+                t.cds_end,
+                t.exons.len()
+            )?;
+            for (start, end) in t.exons.iter() {
+                write!(out, "{},", end - start + 1)?;
+            }
+            out.write_all(b"\t")?;
+            for (start, _) in t.exons.iter() {
+                write!(out, "{},", start.saturating_sub(1) - t.tx_start)?;
+            }
+            out.write_all(newline.as_bytes())
+        })
+    }
+}
+
+impl RefFlatExporter {
+    fn attribute<'a>(&self, line: &'a str, key: &str) -> Option<&'a str> {
+        match self.input {
+            AnnotationFormat::Gtf => line_attribute::<b' '>(line, key),
+            AnnotationFormat::Gff3 => line_attribute::<b'='>(line, key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExportFormat;
+    use crate::{temp::TempPath, test_utils::sort_to_string, SortOptions};
+
+    const GTF: &str = "chr2\ts\tgene\t5\t50\t.\t-\t.\tgene_id \"B\";\n\
+        chr2\ts\ttranscript\t5\t50\t.\t-\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr2\ts\texon\t40\t50\t.\t-\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr2\ts\tCDS\t40\t45\t.\t-\t0\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr2\ts\texon\t5\t10\t.\t-\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"2\";\n\
+        chr1\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t1\t10\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\texon\t60\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\";\n\
+        chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.2\";\n\
+        chr1\ts\texon\t1\t10\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.2\"; exon_number \"1\";\n\
+        chr1\ts\texon\t30\t40\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.2\"; exon_number \"2\";\n";
+
+    fn export_string(input: &str, format: &str) -> String {
+        sort_to_string::<b' '>(
+            input,
+            &SortOptions {
+                export: Some(format.parse().unwrap()),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn saf_lists_distinct_exons_per_gene() {
+        assert_eq!(
+            export_string(GTF, "saf"),
+            "GeneID\tChr\tStart\tEnd\tStrand\n\
+            A\tchr1\t1\t10\t+\n\
+            A\tchr1\t30\t40\t+\n\
+            A\tchr1\t60\t90\t+\n\
+            B\tchr2\t5\t10\t-\n\
+            B\tchr2\t40\t50\t-\n"
+        );
+    }
+
+    #[test]
+    fn saf_gene_lists_gene_spans() {
+        assert_eq!(
+            export_string(GTF, "saf-gene"),
+            "GeneID\tChr\tStart\tEnd\tStrand\nA\tchr1\t1\t90\t+\nB\tchr2\t5\t50\t-\n"
+        );
+    }
+
+    #[test]
+    fn bed12_blocks_are_relative_to_the_transcript() {
+        assert_eq!(
+            export_string(GTF, "bed12"),
+            "chr1\t0\t90\tA.1\t0\t+\t90\t90\t0\t2\t10,31,\t0,59,\n\
+            chr1\t0\t40\tA.2\t0\t+\t40\t40\t0\t2\t10,11,\t0,29,\n\
+            chr2\t4\t50\tB.1\t0\t-\t39\t45\t0\t2\t6,11,\t0,35,\n"
+        );
+    }
+
+    #[test]
+    fn extra_outputs_match_single_exports() {
+        let paths = [".saf", ".bed12"].map(|ext| TempPath::file(None, "extra", ext).unwrap());
+        let options = SortOptions {
+            extra_outputs: paths
+                .iter()
+                .map(|p| {
+                    let format = ExportFormat::from_extension(p.path()).unwrap();
+                    (p.path().to_path_buf(), format)
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let out = sort_to_string::<b' '>(GTF, &options);
+
+        assert!(out.starts_with("chr1\ts\tgene\t"));
+        for (path, format) in paths.iter().zip(["saf", "bed12"]) {
+            assert_eq!(
+                std::fs::read_to_string(path.path()).unwrap(),
+                export_string(GTF, format)
+            );
+        }
+    }
+
+    #[test]
+    fn refflat_matches_gtf_to_gene_pred() {
+        // written by hand, not generated: the expected output of UCSC gtfToGenePred
+        // -genePredExt -geneNameAsName2 on refflat.gtf worked out from its conventions
+        // (0-based starts, the stop codon inside the CDS, cdsStart = cdsEnd = txEnd for
+        // non-coding transcripts), with the name2 column moved first as for Picard
+        assert_eq!(
+            export_string(include_str!("../ci/data/refflat.gtf"), "refflat"),
+            include_str!("../ci/data/refflat.refFlat")
+        );
+    }
+
+    #[test]
+    fn zero_starts_do_not_underflow() {
+        let gtf = "chr1\ts\tgene\t0\t10\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t0\t10\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t0\t10\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tCDS\t0\t9\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.1\";\n";
+        assert_eq!(
+            export_string(gtf, "refflat"),
+            "A\tA.1\tchr1\t+\t0\t10\t0\t9\t1\t0,\t10,\n"
+        );
+        assert!(export_string(gtf, "bed12").starts_with("chr1\t0\t10\tA.1\t"));
+    }
+}
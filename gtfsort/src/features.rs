@@ -0,0 +1,378 @@
+//! Placement of features by their type: the layer of the gene > transcript > children
+//! layout they belong to (see [LayerMap]) and the order of the exon-level features of a
+//! transcript (see [FeatureMap]).
+
+use hashbrown::HashMap;
+use std::path::Path;
+
+use crate::{gtf::Layer, ErrorContext, GtfSortError};
+
+/// Exon-level features in the order they are written within one exon.
+///
+/// GENCODE marks the selenocysteine codons of a CDS with `Selenocysteine` (Ensembl) or
+/// `stop_codon_redefined_as_selenocysteine` lines carrying the exon_number of that CDS,
+/// so they are written right after it.
+pub const DEFAULT_EXON_FEATURES: &[&str] = &[
+    "exon",
+    "CDS",
+    "Selenocysteine",
+    "stop_codon_redefined_as_selenocysteine",
+    "start_codon",
+    "stop_codon",
+];
+
+/// Rank of each exon-level feature within an exon, which orders the children of a
+/// transcript sharing an exon_number (see [crate::ChildKey]). Features not in the map
+/// are written after all exons, by name.
+///
+/// A map file lists one feature per line in the order they should be written, blank
+/// lines and lines starting with '#' are ignored. The default is [DEFAULT_EXON_FEATURES].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureMap {
+    ranks: HashMap<Box<str>, u8>,
+}
+
+impl Default for FeatureMap {
+    fn default() -> Self {
+        Self::new(DEFAULT_EXON_FEATURES.iter().copied()).expect("default features are unique")
+    }
+}
+
+impl FeatureMap {
+    /// Ranks `features` in order, failing on repeated features or more than 256 of them.
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(features: I) -> Result<Self, String> {
+        let mut ranks = HashMap::new();
+        for (i, feat) in features.into_iter().enumerate() {
+            let rank = u8::try_from(i).map_err(|_| "more than 256 features".to_string())?;
+            if ranks.insert(feat.into(), rank).is_some() {
+                return Err(format!("feature {:?} is listed twice", feat));
+            }
+        }
+        Ok(Self { ranks })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, GtfSortError> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| GtfSortError::IoError("reading feature map", e))?;
+        Self::from_list(&contents)
+    }
+
+    pub fn from_list(contents: &str) -> Result<Self, GtfSortError> {
+        let lines = contents
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+            .collect::<Vec<_>>();
+
+        Self::new(lines.iter().map(|(_, l)| *l)).map_err(|e| {
+            // point at the first offending line
+            let bad = lines
+                .iter()
+                .enumerate()
+                .find(|(i, (_, l))| *i > 255 || lines[..*i].iter().any(|(_, p)| p == l))
+                .map(|(_, (n, _))| *n);
+            let err = GtfSortError::InvalidInput(format!("invalid feature map: {}", e));
+            match bad {
+                Some(n) => {
+                    err.with_context(ErrorContext::phase("reading feature map").with_line(n))
+                }
+                None => err.in_phase("reading feature map"),
+            }
+        })
+    }
+
+    /// Rank of `feat` within an exon, if it is an exon-level feature.
+    #[inline(always)]
+    pub fn rank(&self, feat: &str) -> Option<u8> {
+        self.ranks.get(feat).copied()
+    }
+
+    /// Features of the map in rank order.
+    pub fn features(&self) -> Vec<&str> {
+        let mut features = self.ranks.iter().collect::<Vec<_>>();
+        features.sort_unstable_by_key(|(_, rank)| **rank);
+        features.into_iter().map(|(f, _)| f.as_ref()).collect()
+    }
+}
+
+/// Sequence Ontology gene-level feature types, besides `gene`, used in Ensembl, NCBI and
+/// TAIR GFF3 files.
+pub const DEFAULT_GENE_FEATURES: &[&str] = &[
+    "gene",
+    "pseudogene",
+    "ncRNA_gene",
+    "protein_coding_gene",
+    "transposable_element_gene",
+    // TAIR: a transposon, parent of its transposon_fragment lines
+    "transposable_element",
+];
+
+/// Sequence Ontology transcript-level feature types, besides `transcript`, used in
+/// Ensembl, NCBI and TAIR GFF3 files. Ensembl places the immunoglobulin and T-cell
+/// receptor gene segments at this level.
+///
+/// The order ranks them, see [transcript_rank].
+pub const DEFAULT_TRANSCRIPT_FEATURES: &[&str] = &[
+    "transcript",
+    "mRNA",
+    "ncRNA",
+    "lnc_RNA",
+    "lncRNA",
+    "miRNA",
+    "pre_miRNA",
+    "primary_transcript",
+    "rRNA",
+    "tRNA",
+    "snRNA",
+    "snoRNA",
+    "scRNA",
+    "scaRNA",
+    "piRNA",
+    "Y_RNA",
+    "vault_RNA",
+    "RNase_MRP_RNA",
+    "RNase_P_RNA",
+    "SRP_RNA",
+    "telomerase_RNA",
+    "antisense_RNA",
+    "guide_RNA",
+    "misc_RNA",
+    "pseudogenic_transcript",
+    "unconfirmed_transcript",
+    "NMD_transcript_variant",
+    "processed_transcript",
+    "aberrant_processed_transcript",
+    "V_gene_segment",
+    "D_gene_segment",
+    "J_gene_segment",
+    "C_gene_segment",
+];
+
+/// Rank of a transcript-level feature type: its position in [DEFAULT_TRANSCRIPT_FEATURES],
+/// after all of them if mapped from a file. Of the transcript-level lines sharing a
+/// transcript_id, the lowest ranked is its transcript line and the others its children.
+pub fn transcript_rank(feat: &str) -> usize {
+    DEFAULT_TRANSCRIPT_FEATURES
+        .iter()
+        .position(|f| *f == feat)
+        .unwrap_or(DEFAULT_TRANSCRIPT_FEATURES.len())
+}
+
+/// Layer of each feature type, for records placed by their type rather than by their
+/// GFF3 `Parent` chain (see [crate::Record::layer_by]). Types not in the map are children.
+///
+/// A map file lists one feature type per line followed by its layer (`gene`,
+/// `transcript` or `child`), separated by whitespace; blank lines and lines starting with
+/// '#' are ignored. Its entries are added to the defaults ([DEFAULT_GENE_FEATURES] and
+/// [DEFAULT_TRANSCRIPT_FEATURES]), replacing the layer of types listed in both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerMap {
+    layers: HashMap<Box<str>, Layer>,
+}
+
+impl Default for LayerMap {
+    fn default() -> Self {
+        let genes = DEFAULT_GENE_FEATURES.iter().map(|f| (*f, Layer::Gene));
+        let transcripts = DEFAULT_TRANSCRIPT_FEATURES
+            .iter()
+            .map(|f| (*f, Layer::Transcript));
+        Self {
+            layers: genes
+                .chain(transcripts)
+                .map(|(f, l)| (f.into(), l))
+                .collect(),
+        }
+    }
+}
+
+impl LayerMap {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, GtfSortError> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| GtfSortError::IoError("reading feature layers", e))?;
+        Self::from_list(&contents)
+    }
+
+    /// Reads the entries of a map file on top of the defaults, see [LayerMap].
+    pub fn from_list(contents: &str) -> Result<Self, GtfSortError> {
+        let mut map = Self::default();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (feat, layer) = (fields.next().unwrap(), fields.next());
+            let layer = match layer {
+                Some("gene") => Layer::Gene,
+                Some("transcript") => Layer::Transcript,
+                Some("child") => Layer::Child,
+                _ => {
+                    return Err(GtfSortError::InvalidInput(format!(
+                    "invalid feature layers: {:?} needs a layer, one of gene, transcript or child",
+                    feat
+                ))
+                    .with_context(ErrorContext::phase("reading feature layers").with_line(n + 1)))
+                }
+            };
+            map.layers.insert(feat.into(), layer);
+        }
+        Ok(map)
+    }
+
+    /// Layer of `feat`, a child unless mapped.
+    #[inline(always)]
+    pub fn layer(&self, feat: &str) -> Layer {
+        self.layers.get(feat).copied().unwrap_or(Layer::Child)
+    }
+
+    /// Mapped features with their layer, by feature.
+    pub fn layers(&self) -> Vec<(&str, Layer)> {
+        let mut layers = self
+            .layers
+            .iter()
+            .map(|(f, l)| (f.as_ref(), *l))
+            .collect::<Vec<_>>();
+        layers.sort_unstable();
+        layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::sort_to_string, SortOptions};
+
+    const GTF: &str = "chr1\ts\tgene\t1\t100\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\tstop_codon_redefined_as_selenocysteine\t20\t22\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\texon\t60\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\";\n\
+        chr1\ts\tCDS\t10\t50\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\tstart_codon\t10\t12\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\texon\t1\t50\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+
+    fn features(options: &SortOptions) -> Vec<String> {
+        let out = sort_to_string::<b' '>(GTF, options);
+        out.lines()
+            .map(|l| l.split('\t').nth(2).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn selenocysteine_follows_its_cds() {
+        assert_eq!(
+            features(&SortOptions::default()),
+            [
+                "gene",
+                "transcript",
+                "exon",
+                "CDS",
+                "stop_codon_redefined_as_selenocysteine",
+                "start_codon",
+                "exon"
+            ]
+        );
+
+        let options = SortOptions {
+            feature_map: FeatureMap::from_list("# codons first\nstart_codon\nexon\nCDS\n").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            features(&options),
+            [
+                "gene",
+                "transcript",
+                "start_codon",
+                "exon",
+                "CDS",
+                "exon",
+                "stop_codon_redefined_as_selenocysteine"
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_features_are_rejected() {
+        let err = FeatureMap::from_list("exon\nCDS\n\nexon\n").unwrap_err();
+        assert_eq!(err.context().line, Some(4));
+        assert!(err.to_string().contains("\"exon\" is listed twice"));
+        assert_eq!(FeatureMap::default().features(), DEFAULT_EXON_FEATURES);
+    }
+
+    #[test]
+    fn feature_types_are_layered() {
+        let gtf = "chr1\ts\tprocessed_pseudogene\t1\t100\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\texon\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tlnc_RNA\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+        let features = |options: &SortOptions| {
+            let out = sort_to_string::<b' '>(gtf, options);
+            out.lines()
+                .map(|l| l.split('\t').nth(2).unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // without a gene-level line, A.1 cannot be placed
+        assert!(features(&SortOptions::default()).is_empty());
+        let options = SortOptions {
+            layer_map: LayerMap::from_list(
+                "# Ensembl biotype as type\nprocessed_pseudogene gene\n",
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            features(&options),
+            ["processed_pseudogene", "lnc_RNA", "exon"]
+        );
+
+        let err = LayerMap::from_list("\ntRNA\n").unwrap_err();
+        assert_eq!(err.context().line, Some(2));
+        assert_eq!(
+            LayerMap::default().layer("V_gene_segment"),
+            Layer::Transcript
+        );
+        assert_eq!(LayerMap::default().layer("exon"), Layer::Child);
+    }
+
+    fn sorted_features<const SEP: u8>(input: &str) -> Vec<String> {
+        sort_to_string::<SEP>(input, &SortOptions::default())
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split('\t').nth(2).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn transcript_lines_are_chosen_by_rank() {
+        // the miRNA comes first, but a transcript line outranks it
+        let gtf = "chr1\ts\tgene\t1\t100\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\tmiRNA\t10\t30\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\ttranscript\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+        assert_eq!(
+            sorted_features::<b' '>(gtf),
+            ["gene", "transcript", "exon", "miRNA"]
+        );
+        assert!(transcript_rank("transcript") < transcript_rank("miRNA"));
+        assert_eq!(
+            transcript_rank("custom_RNA"),
+            DEFAULT_TRANSCRIPT_FEATURES.len()
+        );
+    }
+
+    #[test]
+    fn tair_transposons_are_gene_level() {
+        let gff = "##gff-version 3\n\
+            Chr1\tTAIR10\ttransposon_fragment\t11897\t11976\t.\t-\t.\tParent=AT1TE00010\n\
+            Chr1\tTAIR10\ttransposable_element\t11897\t11976\t.\t-\t.\tID=AT1TE00010;Name=AT1TE00010\n\
+            Chr1\tTAIR10\tgene\t3631\t5899\t.\t+\t.\tID=AT1G01010;Name=AT1G01010\n";
+        assert_eq!(
+            LayerMap::default().layer("transposable_element"),
+            Layer::Gene
+        );
+        assert_eq!(
+            sorted_features::<b'='>(gff),
+            ["gene", "transposable_element", "transposon_fragment"]
+        );
+    }
+}
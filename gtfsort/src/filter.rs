@@ -0,0 +1,450 @@
+use hashbrown::{HashMap, HashSet};
+use std::{fmt, path::Path, str::FromStr};
+
+use crate::GtfSortError;
+
+/// A set of feature IDs (gene_id, transcript_id, ...) or names (feature types, biotypes)
+/// used to select records for output.
+#[derive(Debug, Clone, Default)]
+pub struct IdFilter {
+    ids: HashSet<String>,
+    invert: bool,
+}
+
+impl IdFilter {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(ids: I, invert: bool) -> Self {
+        Self {
+            ids: ids.into_iter().map(Into::into).collect(),
+            invert,
+        }
+    }
+
+    /// Reads one ID per line, blank lines and lines starting with '#' are ignored.
+    pub fn from_file<P: AsRef<Path>>(path: P, invert: bool) -> Result<Self, GtfSortError> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| GtfSortError::IoError("reading ID list", e))?;
+
+        Ok(Self::from_list(&contents, invert))
+    }
+
+    pub fn from_list(contents: &str, invert: bool) -> Self {
+        Self::new(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#')),
+            invert,
+        )
+    }
+
+    /// Reads a comma-separated list, e.g. `exon,CDS`.
+    pub fn from_comma_list(list: &str) -> Self {
+        Self::new(
+            list.split(',').map(str::trim).filter(|n| !n.is_empty()),
+            false,
+        )
+    }
+
+    #[inline(always)]
+    pub fn keep(&self, id: &str) -> bool {
+        self.ids.contains(id) != self.invert
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// A set of chromosome names used to restrict parsing and output to some contigs.
+///
+/// Names are matched alias-aware: a leading "chr" (in any case) is ignored and "M" is
+/// the same as "MT", so `chr1`, `Chr1` and `1` all select the same contig.
+#[derive(Debug, Clone, Default)]
+pub struct ChromFilter {
+    /// Canonical name to the name as it was requested.
+    names: HashMap<String, String>,
+}
+
+impl ChromFilter {
+    pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(names: I) -> Self {
+        Self {
+            names: names
+                .into_iter()
+                .filter_map(|n| {
+                    let n = n.as_ref().trim();
+                    (!n.is_empty()).then(|| (Self::canonical(n).to_string(), n.to_string()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads a comma-separated list, e.g. `chr1,chr2,chrX`.
+    pub fn from_list(list: &str) -> Self {
+        Self::new(list.split(','))
+    }
+
+    #[inline(always)]
+    fn canonical(name: &str) -> &str {
+        let name = match name.get(..3) {
+            Some(p) if p.eq_ignore_ascii_case("chr") => &name[3..],
+            _ => name,
+        };
+        if name.eq_ignore_ascii_case("m") || name.eq_ignore_ascii_case("mt") {
+            "MT"
+        } else {
+            name
+        }
+    }
+
+    #[inline(always)]
+    pub fn keep(&self, chrom: &str) -> bool {
+        self.names.contains_key(Self::canonical(chrom))
+    }
+
+    /// Whether `line` is a record on one of the selected chromosomes, looking only at
+    /// its first column.
+    #[inline(always)]
+    pub fn keep_line(&self, line: &str) -> bool {
+        line.split_once('\t')
+            .is_some_and(|(chrom, _)| self.keep(chrom))
+    }
+
+    /// The requested names that none of `seen` matched, sorted.
+    pub fn missing<'a, I: IntoIterator<Item = &'a str>>(&self, seen: I) -> Vec<&str> {
+        let seen = seen
+            .into_iter()
+            .map(Self::canonical)
+            .collect::<HashSet<_>>();
+        let mut missing = self
+            .names
+            .iter()
+            .filter(|(n, _)| !seen.contains(n.as_str()))
+            .map(|(_, requested)| requested.as_str())
+            .collect::<Vec<_>>();
+        missing.sort_unstable();
+        missing
+    }
+
+    /// Warns about requested names that none of `seen` matched.
+    pub(crate) fn warn_missing<'a, I: IntoIterator<Item = &'a str>>(&self, seen: I) {
+        let missing = self.missing(seen);
+        if !missing.is_empty() {
+            log::warn!(
+                "No records found for requested chromosomes: {}",
+                missing.join(", ")
+            );
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// How a gene must overlap a [Region] to be kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RegionOverlap {
+    /// The gene shares at least one base with the region.
+    #[default]
+    Any,
+    /// The gene lies entirely inside the region.
+    Within,
+}
+
+impl FromStr for RegionOverlap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "any" => Ok(Self::Any),
+            "within" => Ok(Self::Within),
+            _ => Err(format!(
+                "unknown region overlap {:?}, expected any or within",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RegionOverlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Any => "any",
+            Self::Within => "within",
+        })
+    }
+}
+
+/// A window of one chromosome, used to keep only the genes overlapping it.
+///
+/// Coordinates are 1-based and inclusive, as in the annotation itself. Parsed like
+/// samtools regions: `chr1:1,000,000-2,000,000`, `chr1:1000000` (to the end of the
+/// chromosome) or `chr1` (all of it); thousands separators are allowed.
+#[derive(Debug, Clone)]
+pub struct Region {
+    chrom: String,
+    /// Matches the names of the chromosome, see [ChromFilter].
+    chroms: ChromFilter,
+    start: u32,
+    end: u32,
+    overlap: RegionOverlap,
+}
+
+impl Region {
+    pub fn new(chrom: &str, start: u32, end: u32, overlap: RegionOverlap) -> Self {
+        Self {
+            chrom: chrom.to_string(),
+            chroms: ChromFilter::new([chrom]),
+            start,
+            end,
+            overlap,
+        }
+    }
+
+    pub fn with_overlap(self, overlap: RegionOverlap) -> Self {
+        Self { overlap, ..self }
+    }
+
+    pub fn chrom(&self) -> &str {
+        &self.chrom
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    pub fn overlap(&self) -> RegionOverlap {
+        self.overlap
+    }
+
+    /// The chromosome of the region, to skip the lines of others while parsing.
+    pub fn chrom_filter(&self) -> &ChromFilter {
+        &self.chroms
+    }
+
+    /// Whether a feature on `chrom` spanning `start..=end` overlaps the region as asked.
+    #[inline(always)]
+    pub fn keep(&self, chrom: &str, start: u32, end: u32) -> bool {
+        self.chroms.keep(chrom)
+            && match self.overlap {
+                RegionOverlap::Any => start <= self.end && end >= self.start,
+                RegionOverlap::Within => start >= self.start && end <= self.end,
+            }
+    }
+
+    fn parse_range(range: &str) -> Option<(u32, u32)> {
+        let position = |s: &str| s.trim().replace(',', "").parse::<u32>().ok();
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (position(start)?, position(end)?),
+            None => (position(range)?, u32::MAX),
+        };
+        (start >= 1 && start <= end).then_some((start, end))
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (chrom, (start, end)) = match s.rsplit_once(':') {
+            Some((chrom, range)) => (
+                chrom,
+                Self::parse_range(range).ok_or_else(|| {
+                    format!(
+                        "invalid region {:?}, expected CHROM:START-END with 1 <= START <= END",
+                        s
+                    )
+                })?,
+            ),
+            None => (s, (1, u32::MAX)),
+        };
+        if chrom.is_empty() {
+            return Err(format!("invalid region {:?}, the chromosome is missing", s));
+        }
+        Ok(Self::new(chrom, start, end, RegionOverlap::default()))
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.start, self.end) {
+            (1, u32::MAX) => write!(f, "{}", self.chrom),
+            (start, u32::MAX) => write!(f, "{}:{}", self.chrom, start),
+            (start, end) => write!(f, "{}:{}-{}", self.chrom, start, end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::sort_to_string, SortOptions};
+
+    const GTF: &str = "chr1\ts\tgene\t100\t200\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t100\t200\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t100\t200\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\tgene\t10\t20\t.\t+\t.\tgene_id \"B\";\n\
+        chr1\ts\ttranscript\t10\t20\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr1\ts\texon\t10\t20\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n";
+
+    fn sort_with(options: &SortOptions) -> String {
+        sort_to_string::<b' '>(GTF, options)
+    }
+
+    #[test]
+    fn list_skips_comments_and_blanks() {
+        let f = IdFilter::from_list("# header\nA\n\n  B  \n", false);
+        assert_eq!(f.len(), 2);
+        assert!(f.keep("A") && f.keep("B") && !f.keep("C"));
+
+        let f = IdFilter::from_list("A\n", true);
+        assert!(!f.keep("A") && f.keep("C"));
+    }
+
+    #[test]
+    fn gene_filter_keeps_children() {
+        let out = sort_with(&SortOptions {
+            genes: Some(IdFilter::new(["A"], false)),
+            ..Default::default()
+        });
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.contains("gene_id \"A\"")));
+    }
+
+    #[test]
+    fn gene_filter_inverted() {
+        let out = sort_with(&SortOptions {
+            genes: Some(IdFilter::new(["A"], true)),
+            ..Default::default()
+        });
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.contains("gene_id \"B\"")));
+    }
+
+    #[test]
+    fn transcript_filter_keeps_parents() {
+        let out = sort_with(&SortOptions {
+            transcripts: Some(IdFilter::new(["B.1"], false)),
+            ..Default::default()
+        });
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().next().unwrap().contains("\tgene\t"));
+        assert!(out.lines().all(|l| l.contains("gene_id \"B\"")));
+    }
+
+    #[test]
+    fn feature_filter_keeps_gene_and_transcript_lines() {
+        let input = GTF.replace("chr1\ts\texon\t10\t20\t", "chr1\ts\tCDS\t10\t20\t");
+        let out = sort_to_string::<b' '>(
+            &input,
+            &SortOptions {
+                features: Some(IdFilter::from_comma_list("CDS, start_codon")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(out.lines().count(), 5);
+        assert!(out.lines().all(|l| !l.contains("\texon\t")));
+        assert!(out.lines().nth(2).unwrap().contains("\tCDS\t"));
+    }
+
+    #[test]
+    fn biotype_filter_reads_gene_lines() {
+        let input = GTF
+            .replace(
+                "gene_id \"A\";\n",
+                "gene_id \"A\"; gene_biotype \"lncRNA\";\n",
+            )
+            .replace(
+                "gene_id \"B\";\n",
+                "gene_id \"B\"; gene_type \"protein_coding\";\n",
+            );
+        let out = sort_to_string::<b' '>(
+            &input,
+            &SortOptions {
+                gene_biotypes: Some(IdFilter::from_comma_list("protein_coding")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.contains("gene_id \"B\"")));
+    }
+
+    #[test]
+    fn regions_parse_like_samtools() {
+        let r = "chr1:1,000-2,000".parse::<Region>().unwrap();
+        assert_eq!((r.chrom(), r.start(), r.end()), ("chr1", 1000, 2000));
+        assert_eq!(r.to_string(), "chr1:1000-2000");
+        let r = "1:500".parse::<Region>().unwrap();
+        assert_eq!((r.start(), r.end()), (500, u32::MAX));
+        assert!(r.keep("chr1", 400, 500) && !r.keep("chr2", 400, 500));
+        assert_eq!("chrX".parse::<Region>().unwrap().to_string(), "chrX");
+        assert!("chr1:20-10".parse::<Region>().is_err());
+        assert!("chr1:a-b".parse::<Region>().is_err());
+        assert!(":1-2".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn region_keeps_whole_genes() {
+        let sort_region = |region: &str, overlap, coordinate_only| {
+            sort_with(&SortOptions {
+                region: Some(region.parse::<Region>().unwrap().with_overlap(overlap)),
+                coordinate_only,
+                ..Default::default()
+            })
+        };
+        let out = sort_region("chr1:150-300", RegionOverlap::Any, false);
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.contains("gene_id \"A\"")));
+
+        let out = sort_region("chr1:5-150", RegionOverlap::Any, false);
+        assert_eq!(out.lines().count(), 6);
+        let out = sort_region("chr1:5-150", RegionOverlap::Within, false);
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.contains("gene_id \"B\"")));
+
+        assert!(sort_region("chr2:1-1000", RegionOverlap::Any, false).is_empty());
+        let out = sort_region("chr1:1-20", RegionOverlap::Within, true);
+        assert_eq!(out.lines().count(), 3);
+    }
+
+    #[test]
+    fn chrom_names_are_alias_aware() {
+        let f = ChromFilter::from_list("chr1, chrM,X");
+        assert_eq!(f.len(), 3);
+        assert!(f.keep("1") && f.keep("Chr1") && f.keep("MT") && f.keep("chrX"));
+        assert!(!f.keep("chr10") && !f.keep("chrY"));
+        assert!(f.keep_line("chrM\ts\tgene\t1\t2\t.\t+\t.\tgene_id \"M\";"));
+        assert_eq!(f.missing(["chr1", "M"]), ["X"]);
+        assert_eq!(f.missing(["1"]), ["X", "chrM"]);
+    }
+
+    #[test]
+    fn chrom_filter_skips_other_contigs() {
+        let input = GTF
+            .replace("chr1\ts\tgene\t10\t", "MT\ts\tgene\t10\t")
+            .replace("chr1\ts\ttranscript\t10\t", "MT\ts\ttranscript\t10\t")
+            .replace("chr1\ts\texon\t10\t", "MT\ts\texon\t10\t");
+        let out = sort_to_string::<b' '>(
+            &input,
+            &SortOptions {
+                chromosomes: Some(ChromFilter::from_list("chrM")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.starts_with("MT\t")));
+    }
+}
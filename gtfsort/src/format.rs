@@ -0,0 +1,312 @@
+use std::{fmt, fmt::Write as _, path::Path, str::FromStr};
+
+use crate::json;
+
+/// The annotation dialect of an input, deciding how attributes are split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationFormat {
+    /// `key "value";` attributes.
+    Gtf,
+    /// `key=value;` attributes.
+    Gff3,
+}
+
+impl AnnotationFormat {
+    /// Guesses the format from a file extension (gtf, gff, gff3), in any case and behind a
+    /// compression extension (e.g. `.GTF`, `.gff3.gz`).
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = annotation_extension(path.as_ref())?;
+        if ext.eq_ignore_ascii_case("gtf") {
+            Some(Self::Gtf)
+        } else if ext.eq_ignore_ascii_case("gff") || ext.eq_ignore_ascii_case("gff3") {
+            Some(Self::Gff3)
+        } else {
+            None
+        }
+    }
+
+    /// Explains why [AnnotationFormat::from_extension] could not guess the format of
+    /// `path`, for error messages.
+    pub fn unknown_extension_reason<P: AsRef<Path>>(path: P) -> String {
+        let path = path.as_ref();
+        match annotation_extension(path) {
+            None => format!("{:?} has no file extension", path),
+            Some(ext) => format!(
+                "{:?} has the extension .{}, expected .gtf, .gff or .gff3",
+                path, ext
+            ),
+        }
+    }
+
+    /// The byte separating attribute keys from values.
+    pub fn separator(&self) -> u8 {
+        match self {
+            Self::Gtf => b' ',
+            Self::Gff3 => b'=',
+        }
+    }
+}
+
+/// Extensions of compressed files, looked through by [AnnotationFormat::from_extension].
+pub const COMPRESSION_EXTENSIONS: &[&str] = &["gz", "bgz", "zst", "bz2", "xz"];
+
+/// Whether `path` has one of the [COMPRESSION_EXTENSIONS], in any case.
+pub fn is_compressed_extension<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| {
+            COMPRESSION_EXTENSIONS
+                .iter()
+                .any(|c| e.eq_ignore_ascii_case(c))
+        })
+}
+
+/// The extension naming the annotation format of `path`, the one before a compression
+/// extension if there is one (`gtf` for `a.gtf.gz`).
+fn annotation_extension(path: &Path) -> Option<&str> {
+    if is_compressed_extension(path) {
+        Path::new(path.file_stem()?).extension()?.to_str()
+    } else {
+        path.extension()?.to_str()
+    }
+}
+
+/// How many records [AnnotationFormat::detect] looks at.
+pub(crate) const DETECT_SAMPLE_LINES: usize = 1000;
+
+/// Outcome of sniffing the format of an input from its attribute columns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatDetection {
+    /// The winning format, None if no attribute column could be classified.
+    pub format: Option<AnnotationFormat>,
+    /// Sampled attribute columns in `key "value"` style.
+    pub gtf_votes: usize,
+    /// Sampled attribute columns in `key=value` style.
+    pub gff3_votes: usize,
+    /// The input starts with a `##gff-version` directive.
+    pub gff_version_header: bool,
+}
+
+impl FormatDetection {
+    /// Share of the classified columns agreeing with the detected format, in [0, 1].
+    pub fn confidence(&self) -> f64 {
+        let total = self.gtf_votes + self.gff3_votes;
+        if total == 0 {
+            return 0.0;
+        }
+        self.gtf_votes.max(self.gff3_votes) as f64 / total as f64
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::with_capacity(128);
+        s.push_str("{\"format\":");
+        match self.format {
+            Some(f) => json::string(&mut s, &f.to_string()),
+            None => s.push_str("null"),
+        }
+        write!(
+            s,
+            ",\"gtf_votes\":{},\"gff3_votes\":{},\"gff_version_header\":{},\"confidence\":",
+            self.gtf_votes, self.gff3_votes, self.gff_version_header
+        )
+        .unwrap();
+        json::number(&mut s, self.confidence(), 4);
+        s.push('}');
+        s
+    }
+}
+
+impl FormatDetection {
+    /// The detected format, else the one of the extension of `path`. An input without any
+    /// attribute column to tell the formats apart (e.g. only comments) is read as GTF, as
+    /// it sorts the same either way.
+    pub fn or_fallback(&self, path: &Path) -> Option<AnnotationFormat> {
+        self.format
+            .or_else(|| AnnotationFormat::from_extension(path))
+            .or_else(|| (self.gtf_votes + self.gff3_votes == 0).then_some(AnnotationFormat::Gtf))
+    }
+}
+
+impl fmt::Display for FormatDetection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            Some(format) => write!(f, "{}", format)?,
+            None => write!(f, "unknown format")?,
+        }
+        write!(
+            f,
+            " ({} key=value vs {} key \"value\" attribute columns, confidence {:.1}%)",
+            self.gff3_votes,
+            self.gtf_votes,
+            self.confidence() * 100.0
+        )
+    }
+}
+
+impl AnnotationFormat {
+    /// Guesses the format from the attribute columns of the first
+    /// [DETECT_SAMPLE_LINES] records of `contents`, by counting `key=value` against
+    /// `key "value"` styles. A `##gff-version` directive settles ties.
+    pub fn detect(contents: &str) -> FormatDetection {
+        let mut detection = FormatDetection {
+            format: None,
+            gtf_votes: 0,
+            gff3_votes: 0,
+            gff_version_header: contents.starts_with("##gff-version"),
+        };
+
+        let attributes = contents
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .filter_map(|l| l.splitn(9, '\t').nth(8))
+            .take(DETECT_SAMPLE_LINES);
+
+        for attrs in attributes {
+            let first = attrs.trim_start().split(';').next().unwrap_or("");
+            let eq = first.find('=');
+            let blank = first.find([' ', '\t']);
+            match (eq, blank) {
+                (Some(e), Some(b)) if e < b => detection.gff3_votes += 1,
+                (Some(_), None) => detection.gff3_votes += 1,
+                (_, Some(_)) => detection.gtf_votes += 1,
+                (None, None) => {}
+            }
+        }
+
+        detection.format = match detection.gff3_votes.cmp(&detection.gtf_votes) {
+            std::cmp::Ordering::Greater => Some(Self::Gff3),
+            std::cmp::Ordering::Less => Some(Self::Gtf),
+            std::cmp::Ordering::Equal if detection.gff_version_header => Some(Self::Gff3),
+            std::cmp::Ordering::Equal => None,
+        };
+
+        detection
+    }
+}
+
+impl FromStr for AnnotationFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gtf" => Ok(Self::Gtf),
+            "gff" | "gff3" => Ok(Self::Gff3),
+            _ => Err(format!("unknown format {:?}, expected gtf, gff or gff3", s)),
+        }
+    }
+}
+
+impl fmt::Display for AnnotationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gtf => write!(f, "gtf"),
+            Self::Gff3 => write!(f, "gff3"),
+        }
+    }
+}
+
+/// Line terminator of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Newline {
+    #[default]
+    Lf,
+    /// `\r\n`, for Windows-centric downstream tools.
+    Crlf,
+}
+
+impl Newline {
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::Crlf => b"\r\n",
+        }
+    }
+
+    /// Bytes added after each line.
+    pub fn width(&self) -> usize {
+        self.as_bytes().len()
+    }
+}
+
+impl FromStr for Newline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lf" => Ok(Self::Lf),
+            "crlf" => Ok(Self::Crlf),
+            _ => Err(format!(
+                "unknown newline style {:?}, expected lf or crlf",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Newline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lf => write!(f, "lf"),
+            Self::Crlf => write!(f, "crlf"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_dialects() {
+        let gtf = "chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\"; gene_name \"a=b\";\n";
+        let d = AnnotationFormat::detect(gtf);
+        assert_eq!(
+            (d.format, d.gtf_votes, d.gff3_votes),
+            (Some(AnnotationFormat::Gtf), 1, 0)
+        );
+
+        let gff = "##gff-version 3\nchr1\ts\tgene\t1\t9\t.\t+\t.\tID=A;Name=a b\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n";
+        let d = AnnotationFormat::detect(gff);
+        assert_eq!(d.format, Some(AnnotationFormat::Gff3));
+        assert_eq!(d.confidence(), 0.5);
+        assert!(d
+            .to_json()
+            .starts_with("{\"format\":\"gff3\",\"gtf_votes\":1,"));
+
+        assert_eq!(AnnotationFormat::detect("# nothing\n").format, None);
+
+        let comments = AnnotationFormat::detect("#!genome-build x\n");
+        assert_eq!(
+            comments.or_fallback(Path::new("x.gff3")),
+            Some(AnnotationFormat::Gff3)
+        );
+        assert_eq!(
+            comments.or_fallback(Path::new("x.txt")),
+            Some(AnnotationFormat::Gtf)
+        );
+    }
+
+    #[test]
+    fn extensions_are_case_insensitive_and_see_through_compression() {
+        for (path, format) in [
+            ("a.gtf", Some(AnnotationFormat::Gtf)),
+            ("A.GTF", Some(AnnotationFormat::Gtf)),
+            ("a.gtf.gz", Some(AnnotationFormat::Gtf)),
+            ("a.Gff3.BGZ", Some(AnnotationFormat::Gff3)),
+            ("a.gff", Some(AnnotationFormat::Gff3)),
+            ("/dev/fd/63", None),
+            ("a.gz", None),
+            ("a.bed", None),
+        ] {
+            assert_eq!(AnnotationFormat::from_extension(path), format, "{}", path);
+        }
+
+        assert!(
+            AnnotationFormat::unknown_extension_reason("/dev/fd/63").contains("no file extension")
+        );
+        assert!(AnnotationFormat::unknown_extension_reason("a.bed.gz").contains(".bed,"));
+        assert!(is_compressed_extension("a.gtf.GZ") && !is_compressed_extension("a.gtf"));
+    }
+}
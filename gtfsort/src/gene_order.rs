@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{gtf::line_coords, Layers};
+
+/// Writes the genes of the sorted output as TSV: `gene_id  chrom  start  end  rank`, in
+/// output order, `rank` counting from 1 across all chromosomes.
+///
+/// Rows are added one chromosome at a time, so it can follow a writer that does not
+/// hold the whole index at once.
+pub struct GeneOrderWriter<W: Write> {
+    w: W,
+    rank: usize,
+}
+
+impl GeneOrderWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> GeneOrderWriter<W> {
+    pub fn new(mut w: W) -> io::Result<Self> {
+        writeln!(w, "#gene_id\tchrom\tstart\tend\trank")?;
+        Ok(Self { w, rank: 0 })
+    }
+
+    /// Adds the genes of `chrom`, in the order they are written.
+    pub fn push_chrom(&mut self, chrom: &str, layers: &Layers) -> io::Result<()> {
+        for (start, gene_id, line) in layers.layer.iter() {
+            let end = line_coords(line).map_or(*start, |c| c.1);
+            self.rank += 1;
+            writeln!(
+                self.w,
+                "{}\t{}\t{}\t{}\t{}",
+                gene_id, chrom, start, end, self.rank
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Number of genes written so far.
+    pub fn len(&self) -> usize {
+        self.rank
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rank == 0
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{sort_annotations_string_with_options, SortOptions};
+
+    #[test]
+    fn ranks_follow_the_sorted_output() {
+        let path = crate::temp::TempPath::file(None, "genes", ".tsv").unwrap();
+        let input = "chr2\ts\tgene\t5\t9\t.\t+\t.\tgene_id \"C\";\n\
+            chr2\ts\ttranscript\t5\t9\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+            chr2\ts\texon\t5\t9\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+            chr1\ts\tgene\t50\t90\t.\t+\t.\tgene_id \"B\";\n\
+            chr1\ts\ttranscript\t50\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+            chr1\ts\texon\t50\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+            chr1\ts\tgene\t10\t20\t.\t-\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t10\t20\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t10\t20\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+
+        sort_annotations_string_with_options::<b' ', _>(
+            input,
+            &mut |b| Ok(b.len()),
+            1,
+            &SortOptions {
+                gene_order: Some(path.to_path_buf()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "#gene_id\tchrom\tstart\tend\trank\n\
+            A\tchr1\t10\t20\t1\n\
+            B\tchr1\t50\t90\t2\n\
+            C\tchr2\t5\t9\t3\n"
+        );
+    }
+}
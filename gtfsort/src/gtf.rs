@@ -1,7 +1,30 @@
 mod attr;
+pub mod attr_rewrite;
 use std::borrow::Cow;
 
+use crate::features::{FeatureMap, LayerMap};
+use crate::ord::{ChildKey, CowNaturalSort};
+
 pub use attr::*;
+pub use attr_rewrite::{normalize_attributes, normalize_line_attributes};
+
+/// Longest line accepted by [Record::parse], in bytes.
+///
+/// Longer lines are rejected as malformed instead of being buffered; they are almost
+/// certainly not annotation records (e.g. binary data or a missing newline convention).
+/// Lines below this limit are supported regardless of how long their attribute column is.
+pub(crate) const MAX_LINE_BYTES: usize = 1 << 30;
+
+/// Where a record goes in the gene > transcript > children layout of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum Layer {
+    /// Starts a gene block.
+    Gene,
+    /// Follows its gene, before its children.
+    Transcript,
+    /// Follows its transcript, or its gene if it has none (see [crate::hierarchy]).
+    Child,
+}
 
 #[derive(Debug, PartialEq, Eq, Ord, PartialOrd)]
 pub struct Record<'a> {
@@ -11,8 +34,21 @@ pub struct Record<'a> {
     pub end: u32,
     pub gene_id: &'a str,
     pub transcript_id: &'a str,
-    pub exon_number: &'a str,
+    pub exon_number: Option<&'a str>,
+    /// GFF3 `ID`, empty if not set.
+    pub id: &'a str,
+    /// First GFF3 `Parent`, empty if not set.
+    pub parent: &'a str,
+    /// The attribute column was a bare GTF2.2 group identifier, see [Attribute::bare_group].
+    pub bare_group: bool,
+    /// Set for GFF3 records placed by their `Parent` chain (see
+    /// [crate::hierarchy::resolve_parents]), the others are placed by their feature type,
+    /// see [Record::layer_by].
+    pub layer: Option<Layer>,
     pub line: &'a str,
+    /// Byte offset of `line` in the parsed input, so records that compare equal otherwise
+    /// keep their input order. Zero if parsed on its own.
+    pub ordinal: usize,
 }
 
 impl<'a> Record<'a> {
@@ -22,7 +58,12 @@ impl<'a> Record<'a> {
             return Err("Empty line".into());
         }
 
-        let mut fields = line.split('\t');
+        if line.len() > MAX_LINE_BYTES {
+            return Err("Line too long".into());
+        }
+
+        // the attribute column may itself contain tabs
+        let mut fields = line.splitn(9, '\t');
         let (chrom, _, feat, start, end, _, _, _, attrs_str) = (
             fields.next().ok_or("Missing chrom")?,
             fields.next().ok_or("Missing source")?,
@@ -45,30 +86,150 @@ impl<'a> Record<'a> {
             gene_id: attributes.gene_id(),
             transcript_id: attributes.transcript_id(),
             exon_number: attributes.exon_number(),
+            id: attributes.id(),
+            parent: attributes.parent(),
+            bare_group: attributes.bare_group(),
+            layer: None,
             line,
+            ordinal: 0,
         })
     }
 
+    /// The layer of this record: its place in the `Parent` chain if resolved from it, or
+    /// else the layer `layers` gives its feature type.
     #[inline(always)]
-    pub fn outer_layer(&self) -> (u32, &'a str, &'a str) {
-        (self.start, self.gene_id, self.line)
+    pub fn layer_by(&self, layers: &LayerMap) -> Layer {
+        self.layer.unwrap_or_else(|| layers.layer(self.feat))
     }
 
+    /// A GFF3 child whose `Parent` is its gene (e.g. an exon of an NCBI pseudogene), which
+    /// then stands for its transcript, see [crate::hierarchy::resolve_parents].
     #[inline(always)]
-    pub fn inner_layer(&self) -> (&'a str, char) {
-        (
-            self.exon_number,
-            match self.feat {
-                "exon" => 'a',
-                "CDS" => 'b',
-                "start_codon" => 'c',
-                "stop_codon" => 'd',
-                _ => 'e',
+    pub fn is_direct_child(&self) -> bool {
+        self.layer == Some(Layer::Child)
+            && !self.parent.is_empty()
+            && self.parent == self.gene_id
+            && self.transcript_id == self.gene_id
+    }
+
+    #[inline(always)]
+    pub fn outer_layer(&self) -> (u32, &'a str, Cow<'a, str>) {
+        (self.start, self.gene_id, Cow::Borrowed(self.line))
+    }
+
+    /// Returns the key ordering this record among the children of its transcript, ranking
+    /// exon-level features by `features`.
+    #[inline(always)]
+    pub fn inner_key(&self, features: &FeatureMap) -> ChildKey<'a> {
+        let Some(rank) = features.rank(self.feat) else {
+            return ChildKey::Feature(CowNaturalSort::new(self.feat.into()));
+        };
+
+        match self.exon_number {
+            Some(n) => match n.parse() {
+                Ok(n) => ChildKey::Numbered(n, rank),
+                Err(_) => ChildKey::Labeled(CowNaturalSort::new(n.into()), rank),
             },
-        )
+            None => ChildKey::Unnumbered(rank),
+        }
+    }
+}
+
+/// Children features expected in annotations besides the exon-level ones (see
+/// [crate::features::DEFAULT_EXON_FEATURES]), so they are not reported as unknown. Like
+/// any other child without a rank, they are written after the exon-level features of
+/// their transcript, ordered by feature name (see [crate::ord::ChildKey::Feature]).
+pub const KNOWN_CHILD_FEATURES: &[&str] = &[
+    "UTR",
+    "five_prime_utr",
+    "three_prime_utr",
+    "five_prime_UTR",
+    "three_prime_UTR",
+];
+
+/// Returns true for feature types the sorter knows where to place, ranking exon-level
+/// features by `features`.
+pub fn is_known_feature(feat: &str, features: &FeatureMap) -> bool {
+    matches!(feat, "gene" | "transcript")
+        || features.rank(feat).is_some()
+        || KNOWN_CHILD_FEATURES.contains(&feat)
+}
+
+/// Returns a GTF/GFF line whose attribute column is rewritten by [dedup_attributes], or
+/// the line itself if no key is repeated.
+pub fn dedup_line_attributes<const SEP: u8>(line: &str, policy: DedupAttrs) -> Cow<'_, str> {
+    let Some((columns, attrs)) = line
+        .match_indices('\t')
+        .nth(7)
+        .map(|(i, _)| (&line[..i], &line[i + 1..]))
+    else {
+        return Cow::Borrowed(line);
+    };
+
+    match dedup_attributes::<SEP>(attrs, policy) {
+        Some(attrs) => Cow::Owned(format!("{}\t{}", columns, attrs)),
+        None => Cow::Borrowed(line),
     }
 }
 
+/// Returns the (start, end) columns of a GTF/GFF line.
+pub fn line_coords(line: &str) -> Option<(u32, u32)> {
+    let mut fields = line.split('\t').skip(3);
+    let start = fields.next()?.parse().ok()?;
+    let end = fields.next()?.parse().ok()?;
+    Some((start, end))
+}
+
+/// Returns the strand column of a GTF/GFF line.
+pub fn line_strand(line: &str) -> Option<&str> {
+    line.split('\t').nth(6)
+}
+
+/// Returns the value of attribute `key` of a GTF/GFF line, if set and not empty.
+pub fn line_attribute<'a, const SEP: u8>(line: &'a str, key: &str) -> Option<&'a str> {
+    let attrs = line.splitn(9, '\t').nth(8)?;
+    pairs::<SEP>(attrs)
+        .find(|(k, _)| *k == key)?
+        .1
+        .filter(|v| !v.is_empty())
+}
+
+/// Attributes naming the biotype of a gene: Ensembl GTF, GENCODE and Ensembl GFF3.
+const BIOTYPE_KEYS: [&str; 3] = ["gene_biotype", "gene_type", "biotype"];
+
+/// Returns the biotype of a gene line (e.g. `protein_coding`), from its `gene_biotype`,
+/// `gene_type` or `biotype` attribute.
+pub fn gene_biotype<const SEP: u8>(line: &str) -> Option<&str> {
+    BIOTYPE_KEYS
+        .iter()
+        .find_map(|key| line_attribute::<SEP>(line, key))
+}
+
+/// Returns attribute `key` of a GTF/GFF line as a finite number, e.g. the `TPM` of a
+/// StringTie transcript.
+pub fn numeric_attribute<const SEP: u8>(line: &str, key: &str) -> Option<f64> {
+    line_attribute::<SEP>(line, key)?
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+}
+
+/// Returns a copy of a GTF/GFF line with its start/end columns replaced.
+pub fn replace_line_coords(line: &str, start: u32, end: u32) -> String {
+    let start = start.to_string();
+    let end = end.to_string();
+
+    line.split('\t')
+        .enumerate()
+        .map(|(i, f)| match i {
+            3 => start.as_str(),
+            4 => end.as_str(),
+            _ => f,
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -86,7 +247,7 @@ mod tests {
         assert_eq!(record.start, 2408530);
         assert_eq!(record.gene_id, "ENSG00000157911");
         assert_eq!(record.transcript_id, "ENST00000508384");
-        assert_eq!(record.exon_number, "3");
+        assert_eq!(record.exon_number, Some("3"));
         assert_eq!(record.line, line);
     }
 
@@ -109,4 +270,17 @@ mod tests {
         assert_eq!(gene_id, "ENSG00000157911");
         assert_eq!(line, "1\thavana\tCDS\t2408530\t2408619\t.\t-\t0\tgene_id \"ENSG00000157911\"; gene_version \"11\"; transcript_id \"ENST00000508384\"; transcript_version \"5\"; exon_number \"3\"; gene_name \"PEX10\"; gene_source \"ensembl_havana\"; gene_biotype \"protein_coding\"; transcript_name \"PEX10-205\"; transcript_source \"havana\"; transcript_biotype \"protein_coding\"; protein_id \"ENSP00000464289\"; protein_version \"1\"; tag \"cds_end_NF\"; tag \"mRNA_end_NF\"; transcript_support_level \"3\";");
     }
+
+    #[test]
+    fn replace_coords() {
+        let line = "1\thavana\tgene\t100\t200\t.\t+\t.\tgene_id \"A\";";
+        assert_eq!(line_coords(line), Some((100, 200)));
+
+        let replaced = replace_line_coords(line, 120, 180);
+        assert_eq!(
+            replaced,
+            "1\thavana\tgene\t120\t180\t.\t+\t.\tgene_id \"A\";"
+        );
+        assert_eq!(line_coords(&replaced), Some((120, 180)));
+    }
 }
@@ -1,38 +1,191 @@
+//! Parsing of the attribute column (9th) of GTF and GFF3 lines.
+//!
+//! Attributes are `;`-separated, keys are separated from their values by a space in GTF
+//! and by `=` in GFF3. The parser tolerates the variations found in the wild:
+//!
+//! | Input                                   | GTF                     | GFF3                  |
+//! |-----------------------------------------|-------------------------|-----------------------|
+//! | no trailing `;`                         | accepted                | accepted              |
+//! | several spaces/tabs around fields       | ignored                 | ignored               |
+//! | several spaces/tabs between key, value  | ignored                 | ignored around `=`    |
+//! | tab between key and value               | accepted as separator   | ignored around `=`    |
+//! | quoted values                           | quotes removed          | quotes removed        |
+//! | key-only flags (`pseudo;`)              | value-less in [pairs]   | value-less in [pairs] |
+//! | empty fields (`;;`)                     | skipped                 | skipped               |
+//!
+//! Tabs inside the attribute column are kept by [crate::gtf::Record::parse], which only
+//! splits the first eight columns.
+
 #![allow(dead_code)]
 
+use std::{fmt, str::FromStr};
+
 use thiserror::Error;
 
 macro_rules! extract_field {
-    ($bytes:ident split by $sep:ident to $( $field_name:literal => $output_field:expr; )+) => {
-        $(
-            if let Some(without_key) = $bytes.strip_prefix($field_name) {
-                if let Some(without_eq) = without_key.strip_prefix(&[$sep]) {
-                    let value = unsafe { std::str::from_utf8_unchecked(without_eq) };
-                    *$output_field = Some(value.trim_matches(|c| c == '"'));
-                }
-            }
-        )+
-    };
-    ($bytes:ident split by $sep:literal to $( $field_name:literal => $output_field:expr; )+) => {
+    ($bytes:ident split by $sep:tt to $( $field_name:literal => $output_field:expr; )+) => {
         $(
             if let Some(without_key) = $bytes.strip_prefix($field_name) {
-                if let Some(without_eq) = without_key.strip_prefix(&[$sep]) {
-                    let value = unsafe { std::str::from_utf8_unchecked(without_eq) };
-                    *$output_field = Some(value.trim_matches(|c| c == '"'));
+                if let Some(value) = strip_key_sep::<{ $sep }>(without_key) {
+                    *$output_field = Some(attr_value(value));
                 }
             }
         )+
     };
 }
 
+/// Whether `b` separates a key from its value: `SEP` itself, or a tab in GTF.
+#[inline(always)]
+fn is_key_sep<const SEP: u8>(b: u8) -> bool {
+    b == SEP || (SEP == b' ' && b == b'\t')
+}
+
+#[inline(always)]
+fn is_blank(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+/// Strips the separator (and blanks around it) following a key, None if `bytes` does
+/// not start with one, i.e. the key was only a prefix of a longer key.
+#[inline(always)]
+fn strip_key_sep<const SEP: u8>(bytes: &[u8]) -> Option<&[u8]> {
+    let rest = &bytes[bytes.iter().take_while(|b| is_blank(**b)).count()..];
+    if SEP == b' ' {
+        (rest.len() < bytes.len()).then_some(rest)
+    } else {
+        rest.strip_prefix(&[SEP])
+    }
+}
+
+/// Turns the raw bytes after a key into its value, dropping surrounding blanks and quotes.
+#[inline(always)]
+fn attr_value(bytes: &[u8]) -> &str {
+    let start = bytes
+        .iter()
+        .position(|b| !is_blank(*b))
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|b| !is_blank(*b))
+        .map_or(start, |i| i + 1);
+    // only ASCII bytes are trimmed, so the slice stays on char boundaries
+    let value = unsafe { std::str::from_utf8_unchecked(&bytes[start..end]) };
+    value.trim_matches(|c| c == '"')
+}
+
+/// Splits `bytes` by `BY`, trimming `TRIM` and tabs around every piece.
 #[inline(always)]
 fn split_and_trim_bytes<const BY: u8, const TRIM: u8>(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
     bytes.split(|b| *b == BY).map(|b| {
-        let mut idx = 0;
-        while idx < b.len() && b[idx] == TRIM {
-            idx += 1;
+        let trim = |c: &u8| *c == TRIM || *c == b'\t';
+        let start = b.iter().position(|c| !trim(c)).unwrap_or(b.len());
+        let end = b.iter().rposition(|c| !trim(c)).map_or(start, |i| i + 1);
+        &b[start..end]
+    })
+}
+
+/// Iterates over every attribute of an attribute column, in order, with the same
+/// tolerance as [Attribute::parse].
+///
+/// Key-only flags (e.g. `pseudo;`) are yielded with no value instead of being dropped.
+pub fn pairs<const SEP: u8>(attrs: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    split_and_trim_bytes::<b';', b' '>(attrs.trim_end().as_bytes())
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (key, value) = match field.iter().position(|b| is_key_sep::<SEP>(*b)) {
+                Some(i) => (&field[..i], Some(attr_value(&field[i + 1..]))),
+                None => (field, None),
+            };
+            let key = unsafe { std::str::from_utf8_unchecked(key) };
+            (key.trim_end(), value)
+        })
+}
+
+/// How [dedup_attributes] collapses keys repeated in one attribute column (e.g. several
+/// `tag "..."` entries).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupAttrs {
+    /// Keep one entry whose value joins all values with commas, where the first entry was.
+    #[default]
+    Join,
+    /// Keep the first entry only.
+    First,
+}
+
+impl FromStr for DedupAttrs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "join" => Ok(Self::Join),
+            "first" => Ok(Self::First),
+            _ => Err(format!(
+                "unknown deduplication policy {:?}, expected join or first",
+                s
+            )),
         }
-        &b[idx..]
+    }
+}
+
+impl fmt::Display for DedupAttrs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Join => "join",
+            Self::First => "first",
+        })
+    }
+}
+
+/// Rewrites an attribute column so that every key appears once, following `policy`.
+/// Returns None if no key is repeated, the column is then left as it is.
+///
+/// Entries of keys that are not repeated are copied verbatim, collapsed entries are
+/// written as `key "value"` in GTF and `key=value` in GFF3.
+pub fn dedup_attributes<const SEP: u8>(attrs: &str, policy: DedupAttrs) -> Option<String> {
+    let fields = split_and_trim_bytes::<b';', b' '>(attrs.trim_end().as_bytes())
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            // only ASCII bytes are trimmed, so the slice stays on char boundaries
+            let raw = unsafe { std::str::from_utf8_unchecked(field) };
+            let (key, value) = match field.iter().position(|b| is_key_sep::<SEP>(*b)) {
+                Some(i) => (&raw[..i], Some(attr_value(&field[i + 1..]))),
+                None => (raw, None),
+            };
+            (key.trim_end(), value, raw)
+        })
+        .collect::<Vec<_>>();
+
+    let repeated = |i: usize| fields[..i].iter().any(|f| f.0 == fields[i].0);
+    if !(0..fields.len()).any(repeated) {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(fields.len());
+    for (i, (key, _, raw)) in fields.iter().enumerate() {
+        if repeated(i) {
+            continue;
+        }
+        let mut values = fields[i..]
+            .iter()
+            .filter(|f| f.0 == *key)
+            .filter_map(|f| f.1)
+            .peekable();
+        let collapse = policy == DedupAttrs::Join && fields[i + 1..].iter().any(|f| f.0 == *key);
+        match (collapse, values.peek()) {
+            (true, Some(_)) => {
+                let value = values.collect::<Vec<_>>().join(",");
+                out.push(match SEP {
+                    b'=' => format!("{}={}", key, value),
+                    _ => format!("{} \"{}\"", key, value),
+                });
+            }
+            _ => out.push(raw.to_string()),
+        }
+    }
+
+    Some(match SEP {
+        b'=' => out.join(";"),
+        _ => out.join("; ") + ";",
     })
 }
 
@@ -40,8 +193,22 @@ fn split_and_trim_bytes<const BY: u8, const TRIM: u8>(bytes: &[u8]) -> impl Iter
 pub struct Attribute<'a> {
     gene_id: &'a str,
     transcript_id: &'a str,
-    exon_number: &'a str,
+    exon_number: Option<&'a str>,
     exon_id: &'a str,
+    id: &'a str,
+    parent: &'a str,
+    bare_group: bool,
+}
+
+/// Whether a GTF attribute column is a bare GTF2.2/GFF2 group identifier (e.g. `AB000381`
+/// or `"AB000381"`) instead of `key "value";` pairs.
+#[inline(always)]
+pub(super) fn is_bare_group(attrs: &str) -> bool {
+    let attrs = attrs.trim();
+    !attrs.is_empty()
+        && !attrs
+            .bytes()
+            .any(|b| matches!(b, b';' | b'=') || is_blank(b))
 }
 
 impl<'a> Attribute<'a> {
@@ -51,6 +218,7 @@ impl<'a> Attribute<'a> {
 
             let (mut gene_id, mut transcript_id, mut exon_number, mut exon_id) =
                 (None, None, None, None);
+            let (mut id, mut parent) = (None, None);
 
             for field in field_bytes {
                 extract_field!(
@@ -58,14 +226,47 @@ impl<'a> Attribute<'a> {
                     b"gene_id" => (&mut gene_id);
                     b"transcript_id" => (&mut transcript_id);
                     b"exon_number" => (&mut exon_number);
-                    b"exon_id" => (&mut exon_id););
+                    b"exon_id" => (&mut exon_id);
+                    b"ID" => (&mut id);
+                    b"Parent" => (&mut parent););
+
+                // stop scanning once everything is found, trailing attributes can be huge
+                if gene_id.is_some()
+                    && transcript_id.is_some()
+                    && exon_number.is_some()
+                    && exon_id.is_some()
+                    && (SEP != b'=' || id.is_some())
+                {
+                    break;
+                }
+            }
+
+            // legacy GTF2.2 files may only carry a group identifier, which then
+            // stands for both the gene and its single transcript
+            let bare_group = SEP == b' ' && gene_id.is_none() && is_bare_group(line);
+            if bare_group {
+                let group = Some(attr_value(line.as_bytes()));
+                gene_id = group;
+                transcript_id = group;
             }
 
+            // GFF3 records may only point to their parent, gene_id is then
+            // resolved from the hierarchy once the whole file is parsed
+            let gene_id = match gene_id {
+                Some(gene_id) => gene_id,
+                None if SEP == b'=' && (id.is_some() || parent.is_some()) => "",
+                None => return Err(ParseError::MissingGeneId(line.to_string())),
+            };
+
             Ok(Attribute {
-                gene_id: gene_id.ok_or(ParseError::MissingGeneId(line.to_string()))?,
+                gene_id,
                 transcript_id: transcript_id.unwrap_or("0"),
-                exon_number: exon_number.unwrap_or("z"),
+                exon_number,
                 exon_id: exon_id.unwrap_or("0"),
+                id: id.unwrap_or(""),
+                // only the first of several parents is followed
+                parent: parent.and_then(|p| p.split(',').next()).unwrap_or(""),
+                bare_group,
             })
         } else {
             Err(ParseError::Empty)
@@ -83,7 +284,7 @@ impl<'a> Attribute<'a> {
     }
 
     #[inline(always)]
-    pub fn exon_number(&self) -> &'a str {
+    pub fn exon_number(&self) -> Option<&'a str> {
         self.exon_number
     }
 
@@ -91,6 +292,24 @@ impl<'a> Attribute<'a> {
     pub fn exon_id(&self) -> &'a str {
         self.exon_id
     }
+
+    /// GFF3 `ID`, empty if not set.
+    #[inline(always)]
+    pub fn id(&self) -> &'a str {
+        self.id
+    }
+
+    /// First GFF3 `Parent`, empty if not set.
+    #[inline(always)]
+    pub fn parent(&self) -> &'a str {
+        self.parent
+    }
+
+    /// The column was a bare GTF2.2 group identifier, used as both gene_id and transcript_id.
+    #[inline(always)]
+    pub fn bare_group(&self) -> bool {
+        self.bare_group
+    }
 }
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -124,7 +343,7 @@ mod tests {
 
         assert_eq!(attr.gene_id(), "ABC");
         assert_eq!(attr.transcript_id(), "XYZ");
-        assert_eq!(attr.exon_number(), "1");
+        assert_eq!(attr.exon_number(), Some("1"));
         assert_eq!(attr.exon_id(), "123");
     }
 
@@ -136,6 +355,46 @@ mod tests {
         assert_eq!(result.unwrap_err(), ParseError::MissingGeneId(input));
     }
 
+    #[test]
+    fn repeated_keys_are_collapsed() {
+        let gtf = "gene_id \"A\"; tag \"basic\"; level 2; tag \"CCDS\"; pseudo; pseudo;";
+        assert_eq!(
+            dedup_attributes::<b' '>(gtf, DedupAttrs::Join).unwrap(),
+            "gene_id \"A\"; tag \"basic,CCDS\"; level 2; pseudo;"
+        );
+        assert_eq!(
+            dedup_attributes::<b' '>(gtf, DedupAttrs::First).unwrap(),
+            "gene_id \"A\"; tag \"basic\"; level 2; pseudo;"
+        );
+        assert_eq!(
+            dedup_attributes::<b'='>("ID=A;tag=basic;tag=CCDS", DedupAttrs::Join).unwrap(),
+            "ID=A;tag=basic,CCDS"
+        );
+        assert_eq!(
+            dedup_attributes::<b' '>("gene_id \"A\"; tag \"x\";", DedupAttrs::Join),
+            None
+        );
+        assert_eq!("FIRST".parse(), Ok(DedupAttrs::First));
+        assert!("last".parse::<DedupAttrs>().is_err());
+    }
+
+    #[test]
+    fn bare_group_is_gene_and_transcript() {
+        for input in ["AB000381", " \"AB000381\" "] {
+            let attr = Attribute::parse::<b' '>(input).unwrap();
+            assert_eq!(
+                (attr.gene_id(), attr.transcript_id(), attr.bare_group()),
+                ("AB000381", "AB000381", true)
+            );
+        }
+
+        assert!(Attribute::parse::<b' '>("Sequence AB000381").is_err());
+        assert!(Attribute::parse::<b'='>("AB000381").is_err());
+        assert!(!Attribute::parse::<b' '>("gene_id \"A\";")
+            .unwrap()
+            .bare_group());
+    }
+
     #[test]
     fn get_gencode_pair_from_gene_line() {
         let line = "gene_id \"ENSG00000290825.1\"; gene_type \"lncRNA\"; gene_name \"DDX11L2\"; level 2; tag \"overlaps_pseudogene\";".to_string();
@@ -229,7 +488,59 @@ mod tests {
 
         assert_eq!(attr.gene_id(), "ENSG00000223972.6");
         assert_eq!(attr.transcript_id(), "ENST00000450305.2");
-        assert_eq!(attr.exon_number(), "z");
+        assert_eq!(attr.exon_number(), None);
         assert_eq!(attr.exon_id(), "0");
     }
+
+    type Pairs<'a> = Vec<(&'a str, Option<&'a str>)>;
+
+    fn dialect_records<const SEP: u8>(fixture: &str) -> Vec<(&str, Pairs<'_>)> {
+        fixture
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| {
+                let record = crate::gtf::Record::parse::<SEP>(l).unwrap();
+                let attrs = l.splitn(9, '\t').nth(8).unwrap();
+                (record.gene_id, pairs::<SEP>(attrs).collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gtf_dialects() {
+        let records = dialect_records::<b' '>(include_str!("../../ci/data/attr_dialects.gtf"));
+        let gene_ids = records.iter().map(|r| r.0).collect::<Vec<_>>();
+        assert_eq!(gene_ids, ["G1", "G2", "G3", "G4", "G5", "G6"]);
+
+        for (i, (gene_id, attrs)) in records.iter().enumerate() {
+            let name = attrs.iter().find(|(k, _)| *k == "gene_name").unwrap().1;
+            assert_eq!(name, Some(&"ABCDEF"[i..i + 1]), "{}", gene_id);
+        }
+
+        assert_eq!(records[3].1[0], ("pseudo", None));
+        assert_eq!(records[4].1.len(), 2);
+    }
+
+    #[test]
+    fn gff3_dialects() {
+        let records = dialect_records::<b'='>(include_str!("../../ci/data/attr_dialects.gff3"));
+        let gene_ids = records.iter().map(|r| r.0).collect::<Vec<_>>();
+        assert_eq!(gene_ids, ["G1", "G2", "G3", "G4", "G5", "G6"]);
+
+        for (i, (gene_id, attrs)) in records.iter().enumerate() {
+            let name = attrs.iter().find(|(k, _)| *k == "Name").unwrap().1;
+            assert_eq!(name, Some(&"ABCDEF"[i..i + 1]), "{}", gene_id);
+        }
+
+        assert_eq!(records[3].1[1], ("pseudo", None));
+    }
+
+    #[test]
+    fn keys_are_not_prefixes() {
+        let attr = Attribute::parse::<b' '>("gene_idx \"A\"; gene_id \"B\"").unwrap();
+        assert_eq!(attr.gene_id(), "B");
+
+        let attr = Attribute::parse::<b'='>("gene_idx=A;gene_id=B").unwrap();
+        assert_eq!(attr.gene_id(), "B");
+    }
 }
@@ -0,0 +1,128 @@
+//! Canonical attribute columns, so that two annotations written by different tools can
+//! be diffed line by line after sorting, see [crate::SortOptions::attr_normalize].
+//!
+//! Normalizing a column:
+//!
+//! - writes the [LEADING_KEYS] first, the others following in their input order
+//! - quotes every GTF value (`level 2` becomes `level "2"`) and unquotes GFF3 values
+//! - separates GTF entries by `; ` and ends them with `;`, GFF3 entries by `;` alone
+//! - drops entries repeating an earlier one, key and value (e.g. `tag "basic"` twice)
+//!
+//! Values are otherwise copied as they are, so repeated keys with different values (see
+//! [crate::gtf::dedup_attributes]) and GFF3 percent-encoding are left alone.
+
+use std::borrow::Cow;
+
+use super::attr::{is_bare_group, pairs};
+
+/// Keys written first by [normalize_attributes], in this order.
+pub const LEADING_KEYS: [&str; 5] = ["ID", "Parent", "gene_id", "transcript_id", "exon_number"];
+
+/// Rewrites an attribute column into its canonical form, see [crate::gtf::attr_rewrite].
+/// Empty columns, `.` and bare GTF2.2 group identifiers are returned as they are.
+pub fn normalize_attributes<const SEP: u8>(attrs: &str) -> Cow<'_, str> {
+    let trimmed = attrs.trim();
+    if trimmed.is_empty() || trimmed == "." || (SEP == b' ' && is_bare_group(attrs)) {
+        return Cow::Borrowed(attrs);
+    }
+
+    let mut fields = Vec::<(&str, Option<&str>)>::new();
+    for pair in pairs::<SEP>(attrs) {
+        if !fields.contains(&pair) {
+            fields.push(pair);
+        }
+    }
+    // stable, the other keys keep their input order
+    fields.sort_by_key(|(key, _)| {
+        LEADING_KEYS
+            .iter()
+            .position(|k| k == key)
+            .unwrap_or(LEADING_KEYS.len())
+    });
+
+    let mut out = String::with_capacity(attrs.len() + 8);
+    for (i, (key, value)) in fields.into_iter().enumerate() {
+        match SEP {
+            b'=' => {
+                if i > 0 {
+                    out.push(';');
+                }
+                out.push_str(key);
+                if let Some(value) = value {
+                    out.push('=');
+                    out.push_str(value);
+                }
+            }
+            _ => {
+                if i > 0 {
+                    out.push(' ');
+                }
+                out.push_str(key);
+                if let Some(value) = value {
+                    out.push_str(" \"");
+                    out.push_str(value);
+                    out.push('"');
+                }
+                out.push(';');
+            }
+        }
+    }
+
+    match out == attrs {
+        true => Cow::Borrowed(attrs),
+        false => Cow::Owned(out),
+    }
+}
+
+/// Returns a GTF/GFF line whose attribute column is rewritten by [normalize_attributes],
+/// or the line itself if it is already canonical.
+pub fn normalize_line_attributes<const SEP: u8>(line: &str) -> Cow<'_, str> {
+    let Some((columns, attrs)) = line
+        .match_indices('\t')
+        .nth(7)
+        .map(|(i, _)| (&line[..i], &line[i + 1..]))
+    else {
+        return Cow::Borrowed(line);
+    };
+
+    match normalize_attributes::<SEP>(attrs) {
+        Cow::Owned(attrs) => Cow::Owned(format!("{}\t{}", columns, attrs)),
+        Cow::Borrowed(_) => Cow::Borrowed(line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_are_canonical() {
+        let gtf = "gene_name \"A1\";  level 2;transcript_id \"T\"; tag \"basic\"; \
+            gene_id \"G\"; tag \"basic\"; tag \"CCDS\"; exon_number 1; pseudo";
+        assert_eq!(
+            normalize_attributes::<b' '>(gtf),
+            "gene_id \"G\"; transcript_id \"T\"; exon_number \"1\"; gene_name \"A1\"; \
+            level \"2\"; tag \"basic\"; tag \"CCDS\"; pseudo;"
+        );
+        let canonical = "gene_id \"G\"; transcript_id \"T\"; level \"2\";";
+        assert!(matches!(
+            normalize_attributes::<b' '>(canonical),
+            Cow::Borrowed(_)
+        ));
+
+        let gff = "Name=\"A1\"; Parent=G ;ID=T;tag=basic;tag=basic";
+        assert_eq!(
+            normalize_attributes::<b'='>(gff),
+            "ID=T;Parent=G;Name=A1;tag=basic"
+        );
+
+        for column in ["", ".", "AB000381"] {
+            assert_eq!(normalize_attributes::<b' '>(column), column);
+        }
+        let line = "chr1\ts\texon\t1\t9\t.\t+\t.\tlevel 2; gene_id \"G\";";
+        assert_eq!(
+            normalize_line_attributes::<b' '>(line),
+            "chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"G\"; level \"2\";"
+        );
+    }
+}
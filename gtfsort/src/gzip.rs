@@ -0,0 +1,106 @@
+//! Transparent reading of gzip and bgzip compressed inputs (e.g. `.gtf.gz`). Compressed
+//! inputs are recognized by their magic bytes rather than their extension and decoded
+//! while they are read, so they never have to be unpacked to disk first. A bgzip (BGZF)
+//! file is a series of gzip members, all of which are read. Needs the `gzip` feature.
+
+use std::{fs::File, io::Read, path::Path};
+
+use crate::{sniff, GtfSortError};
+
+/// Magic bytes starting every gzip member, BGZF blocks included.
+pub const MAGIC: &[u8] = b"\x1f\x8b";
+
+/// Annotation text shrinks about 8 to 12 times under gzip, the low end is used to
+/// estimate the decompressed size of an input.
+pub(crate) const EXPANSION_ESTIMATE: u64 = 8;
+
+/// Whether `head`, the first bytes of an input, start a gzip stream.
+pub fn is_gzip(head: &[u8]) -> bool {
+    head.starts_with(MAGIC)
+}
+
+/// Whether the file at `path` is gzip-compressed.
+pub fn is_gzip_file(path: &Path) -> Result<bool, GtfSortError> {
+    let mut head = Vec::with_capacity(MAGIC.len());
+    File::open(path)
+        .and_then(|f| f.take(MAGIC.len() as u64).read_to_end(&mut head))
+        .map_err(|e| GtfSortError::IoError("reading input file", e))?;
+    Ok(is_gzip(&head))
+}
+
+/// Opens the file at `path`, through a decoder if it is gzip-compressed.
+pub fn open(path: &Path) -> Result<Box<dyn Read + Send>, GtfSortError> {
+    let gzipped = is_gzip_file(path)?;
+    let file = File::open(path).map_err(|e| GtfSortError::IoError("opening input file", e))?;
+    match gzipped {
+        false => Ok(Box::new(file)),
+        #[cfg(feature = "gzip")]
+        true => Ok(Box::new(flate2::read::MultiGzDecoder::new(
+            std::io::BufReader::new(file),
+        ))),
+        #[cfg(not(feature = "gzip"))]
+        true => Err(GtfSortError::InvalidInput(format!(
+            "{:?} is gzip-compressed, which this build does not read (built without the gzip feature), please decompress it first",
+            path
+        ))),
+    }
+}
+
+/// Reads the whole file at `path` as text, decompressing it if it is gzip-compressed.
+pub fn read_to_string(path: &Path) -> Result<String, GtfSortError> {
+    if !is_gzip_file(path)? {
+        return std::fs::read_to_string(path)
+            .map_err(|e| GtfSortError::IoError("reading input file", e));
+    }
+
+    let size = std::fs::metadata(path).map_or(0, |m| m.len());
+    let mut contents = Vec::with_capacity((size * EXPANSION_ESTIMATE) as usize);
+    open(path)?
+        .read_to_end(&mut contents)
+        .map_err(|e| GtfSortError::IoError("decompressing input file", e))?;
+    log::info!(
+        "Decompressed {} bytes of gzip input into {} bytes",
+        size,
+        contents.len()
+    );
+
+    sniff::check_text(&contents[..contents.len().min(sniff::SNIFF_BYTES)])?;
+    String::from_utf8(contents).map_err(|e| {
+        GtfSortError::InvalidInput(format!(
+            "the decompressed input is not valid UTF-8 (at byte {})",
+            e.utf8_error().valid_up_to()
+        ))
+    })
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn gz(text: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gzip_members_are_read_in_sequence() {
+        let path = crate::temp::TempPath::file(None, "gzip_test", ".gtf.gz").unwrap();
+        // bgzip writes one member per block
+        let (first, second) = (
+            "chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n",
+            "chr1\ts\tgene\t5\t9\t.\t+\t.\tgene_id \"B\";\n",
+        );
+        std::fs::write(&path, [gz(first), gz(second)].concat()).unwrap();
+
+        assert!(is_gzip_file(&path).unwrap());
+        assert_eq!(read_to_string(&path).unwrap(), [first, second].concat());
+        sniff::check_file(&path).unwrap();
+
+        std::fs::write(&path, gz("\u{0}binary")).unwrap();
+        assert!(sniff::check_file(&path).is_err());
+        assert!(read_to_string(&path).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Comment lines leading the input, e.g. the `##gff-version 3`, `##sequence-region` and
+//! `#!genome-build` pragmas of a GFF3 file, written back at the top of the sorted output
+//! unless [crate::SortOptions::drop_header] is set.
+//!
+//! Only the `#` lines before the first record are kept, in input order. Comments between
+//! records belong to no record and are dropped as before.
+
+use std::io::{self, Write};
+
+use crate::{format::Newline, utils::write_line};
+
+/// The leading comment lines of an input, without their line terminators.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    pub lines: Vec<String>,
+    /// A record was seen, later lines are not part of the header.
+    ended: bool,
+}
+
+impl Header {
+    /// The header of `contents`.
+    pub fn leading(contents: &str) -> Self {
+        let mut header = Self::default();
+        header.push_text(contents);
+        header
+    }
+
+    /// Feeds the next line of the input, returns false once the header ended. Blank
+    /// lines do not end the header but are not kept.
+    pub fn push_line(&mut self, line: &str) -> bool {
+        if self.ended {
+            return false;
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim_start().starts_with('#') {
+            self.lines.push(line.to_string());
+        } else if !line.trim().is_empty() {
+            self.ended = true;
+        }
+        !self.ended
+    }
+
+    /// Feeds the next lines of the input, e.g. one window of it at a time.
+    pub fn push_text(&mut self, text: &str) {
+        for line in text.lines() {
+            if !self.push_line(line) {
+                break;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Bytes [Header::write] writes.
+    pub fn size_with(&self, newline: Newline) -> usize {
+        self.lines.iter().map(|l| l.len() + newline.width()).sum()
+    }
+
+    pub fn write<W: Write + ?Sized>(&self, output: &mut W, newline: Newline) -> io::Result<()> {
+        self.lines
+            .iter()
+            .try_for_each(|l| write_line(output, l, newline))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::sort_to_string, SortOptions};
+
+    const GFF: &str = "##gff-version 3\n\
+        #!genome-build GRCh38.p14\n\
+        \n\
+        ##sequence-region chr1 1 1000\n\
+        chr1\ts\tgene\t50\t90\t.\t+\t.\tID=B\n\
+        # between records\n\
+        chr1\ts\tgene\t10\t40\t.\t+\t.\tID=A\n";
+
+    fn sort(options: &SortOptions) -> String {
+        sort_to_string::<b'='>(GFF, options)
+    }
+
+    #[test]
+    fn leading_comments_are_the_header() {
+        let header = Header::leading(GFF);
+        assert_eq!(
+            header.lines,
+            vec![
+                "##gff-version 3",
+                "#!genome-build GRCh38.p14",
+                "##sequence-region chr1 1 1000"
+            ]
+        );
+        assert_eq!(header.size_with(Newline::Crlf), 75);
+    }
+
+    #[test]
+    fn header_tops_the_sorted_output() {
+        let sorted = sort(&SortOptions::default());
+        let lines = sorted.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[..3], Header::leading(GFF).lines[..]);
+        assert!(lines[3].ends_with("ID=A"));
+
+        let sorted = sort(&SortOptions {
+            drop_header: true,
+            ..Default::default()
+        });
+        assert!(sorted.starts_with("chr1\ts\tgene\t10\t"));
+        assert_eq!(sorted.lines().count(), 2);
+    }
+}
@@ -0,0 +1,224 @@
+use hashbrown::{HashMap, HashSet};
+
+use crate::gtf::{Layer, Record};
+
+/// gene_id/transcript_id a GFF3 `ID` resolves to.
+#[derive(Debug, Clone, Copy)]
+struct Lineage<'a> {
+    gene_id: &'a str,
+    /// None for gene-level features.
+    transcript_id: Option<&'a str>,
+}
+
+impl<'a> Lineage<'a> {
+    fn of(record: &Record<'a>) -> Self {
+        Self {
+            gene_id: record.gene_id,
+            transcript_id: (record.transcript_id != "0").then_some(record.transcript_id),
+        }
+    }
+}
+
+/// Fills in gene_id/transcript_id of the GFF3 records of one chromosome that only
+/// point to their parent through `Parent`, dropping those whose ancestry cannot be
+/// resolved. Returns the number of dropped records.
+///
+/// GFF3 allows children to appear before their parents and the parallel parser does
+/// not keep file order anyway, so resolution happens in two phases: records whose
+/// parent is already known are resolved right away, the others wait in a deferred
+/// queue that is drained once every parent has been seen.
+///
+/// The [Layer] of a resolved record comes from its place in the parent graph rather
+/// than from its feature type, so that e.g. NCBI `mRNA`, `ncRNA` or `pseudogene`
+/// features are laid out like genes and transcripts: top-level features become genes
+/// (gene_id = ID), their children with children of their own transcripts
+/// (transcript_id = ID) and anything below inherits the transcript of its parent.
+/// Children of a gene without children of their own (e.g. the exons of a pseudogene)
+/// take the gene as their transcript and are written right after the gene line, see
+/// [Record::is_direct_child].
+pub fn resolve_parents<'a>(records: &mut Vec<Record<'a>>) -> usize {
+    if records.iter().all(|r| !r.gene_id.is_empty()) {
+        return 0;
+    }
+
+    let parents = records
+        .iter()
+        .filter(|r| !r.parent.is_empty())
+        .map(|r| r.parent)
+        .collect::<HashSet<_>>();
+    let mut resolved: HashMap<&'a str, Lineage<'a>> = HashMap::new();
+    let mut deferred = Vec::new();
+
+    for (i, record) in records.iter_mut().enumerate() {
+        if record.gene_id.is_empty() && !resolve(record, &resolved, &parents) {
+            deferred.push(i);
+        } else if !record.id.is_empty() {
+            resolved.insert(record.id, Lineage::of(record));
+        }
+    }
+
+    // every round resolves at least one more level of the hierarchy
+    loop {
+        let before = deferred.len();
+        deferred.retain(|&i| {
+            let record = &mut records[i];
+            if !resolve(record, &resolved, &parents) {
+                return true;
+            }
+            if !record.id.is_empty() {
+                resolved.insert(record.id, Lineage::of(record));
+            }
+            false
+        });
+
+        if deferred.is_empty() || deferred.len() == before {
+            break;
+        }
+    }
+
+    if !deferred.is_empty() {
+        records.retain(|r| !r.gene_id.is_empty());
+    }
+
+    deferred.len()
+}
+
+fn resolve<'a>(
+    record: &mut Record<'a>,
+    resolved: &HashMap<&'a str, Lineage<'a>>,
+    parents: &HashSet<&'a str>,
+) -> bool {
+    if record.parent.is_empty() {
+        record.gene_id = record.id;
+        record.layer = Some(Layer::Gene);
+        return true;
+    }
+
+    let Some(parent) = resolved.get(record.parent) else {
+        return false;
+    };
+    record.gene_id = parent.gene_id;
+    let has_children = !record.id.is_empty() && parents.contains(record.id);
+    record.layer = Some(match parent.transcript_id {
+        None if has_children => Layer::Transcript,
+        _ => Layer::Child,
+    });
+    if record.transcript_id == "0" {
+        record.transcript_id = match parent.transcript_id {
+            Some(transcript_id) => transcript_id,
+            None if has_children => record.id,
+            None => parent.gene_id,
+        };
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::sort_to_string, utils::parallel_parse};
+
+    const CHILDREN_FIRST: &str = include_str!("../ci/data/children_before_parents.gff3");
+
+    #[test]
+    fn children_before_parents() {
+        let mut records = CHILDREN_FIRST
+            .lines()
+            .filter(|l| l.starts_with("chr1"))
+            .map(|l| Record::parse::<b'='>(l).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(resolve_parents(&mut records), 0);
+
+        let ids = records
+            .iter()
+            .map(|r| (r.id, r.gene_id, r.transcript_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                ("exon:B.1.1", "gene:B", "transcript:B.1"),
+                ("exon:A.1.2", "gene:A", "transcript:A.1"),
+                ("transcript:A.1", "gene:A", "transcript:A.1"),
+                ("exon:A.1.1", "gene:A", "transcript:A.1"),
+                ("gene:B", "gene:B", "0"),
+                ("transcript:B.1", "gene:B", "transcript:B.1"),
+                ("gene:A", "gene:A", "0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn layers_follow_the_parent_graph() {
+        let ncbi = "##gff-version 3\n\
+            NC_1\tRefSeq\tgene\t500\t900\t.\t+\t.\tID=gene-B\n\
+            NC_1\tRefSeq\texon\t700\t900\t.\t+\t.\tID=exon-B-2;Parent=rna-B\n\
+            NC_1\tRefSeq\tmRNA\t500\t900\t.\t+\t.\tID=rna-B;Parent=gene-B\n\
+            NC_1\tRefSeq\texon\t500\t600\t.\t+\t.\tID=exon-B-1;Parent=rna-B\n\
+            NC_1\tRefSeq\tpseudogene\t100\t300\t.\t-\t.\tID=gene-P\n\
+            NC_1\tRefSeq\texon\t100\t300\t.\t-\t.\tID=id-P-1;Parent=gene-P\n";
+        let mut records = ncbi
+            .lines()
+            .skip(1)
+            .map(|l| Record::parse::<b'='>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(resolve_parents(&mut records), 0);
+        let layers = records
+            .iter()
+            .map(|r| (r.feat, r.layer, r.transcript_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layers,
+            vec![
+                ("gene", Some(Layer::Gene), "0"),
+                ("exon", Some(Layer::Child), "rna-B"),
+                ("mRNA", Some(Layer::Transcript), "rna-B"),
+                ("exon", Some(Layer::Child), "rna-B"),
+                ("pseudogene", Some(Layer::Gene), "0"),
+                ("exon", Some(Layer::Child), "gene-P"),
+            ]
+        );
+        assert!(records[5].is_direct_child() && !records[3].is_direct_child());
+
+        let mut out = Vec::new();
+        let job = crate::sort_annotations_string_with_options::<b'=', _>(
+            ncbi,
+            &mut |b| {
+                out.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+            &crate::SortOptions {
+                verify: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(job.unknown_features.is_empty());
+        let feats = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|l| l.split('\t').nth(2).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            feats,
+            ["pseudogene", "exon", "gene", "mRNA", "exon", "exon"]
+        );
+    }
+
+    #[test]
+    fn orphans_are_dropped() {
+        let records = parallel_parse::<b'='>(CHILDREN_FIRST).unwrap();
+        assert_eq!(records["chr1"].len(), 7);
+        assert!(records["chr2"].is_empty());
+    }
+
+    #[test]
+    fn sorts_children_before_parents() {
+        assert_eq!(
+            sort_to_string::<b'='>(CHILDREN_FIRST, &Default::default()),
+            include_str!("../ci/data/children_before_parents.sorted.gff3")
+        );
+    }
+}
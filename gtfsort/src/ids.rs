@@ -0,0 +1,190 @@
+use hashbrown::HashMap;
+use std::fmt;
+
+use crate::gtf::{Attribute, Record};
+
+/// The kind of ID reuse found by [find_id_collisions].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionKind {
+    /// The same transcript line appears twice in a gene.
+    DuplicateTranscript,
+    /// A transcript_id is shared by more than one gene.
+    TranscriptInManyGenes,
+    /// An exon_id appears twice among the exons of a transcript.
+    DuplicateExonId,
+    /// Two children of a transcript share feature and exon_number, so they compete for the
+    /// same slot when ordering the transcript. The reported ID is the feature.
+    DuplicateChildKey,
+}
+
+/// An ID that is reused where it should be unique, with the 1-based lines involved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdCollision<'a> {
+    pub kind: CollisionKind,
+    pub id: &'a str,
+    /// The gene (or transcript, for children) the ID was expected to be unique in.
+    pub parent: &'a str,
+    pub first_line: usize,
+    pub line: usize,
+}
+
+impl fmt::Display for IdCollision<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, scope) = match self.kind {
+            CollisionKind::DuplicateTranscript => ("duplicate transcript", "in gene"),
+            CollisionKind::TranscriptInManyGenes => ("transcript_id", "reused outside gene"),
+            CollisionKind::DuplicateExonId => ("duplicate exon_id", "in transcript"),
+            CollisionKind::DuplicateChildKey => ("feature", "with a repeated exon_number in"),
+        };
+
+        write!(
+            f,
+            "{} {:?} {} {:?} (lines {} and {})",
+            what, self.id, scope, self.parent, self.first_line, self.line
+        )
+    }
+}
+
+/// Scans `contents` without sorting it and reports every ID that is not unique within
+/// its parent: transcript_ids within genes, exon_ids and child slots within transcripts.
+///
+/// Lines that cannot be parsed are skipped, comments are ignored.
+pub fn find_id_collisions<const SEP: u8>(contents: &str) -> Vec<IdCollision<'_>> {
+    // transcript_id -> (gene_id, first line using it)
+    let mut transcript_genes: HashMap<&str, (&str, usize)> = HashMap::new();
+    // transcript_id -> first transcript line
+    let mut transcript_lines: HashMap<&str, usize> = HashMap::new();
+    // (transcript_id, exon_id) -> first line
+    let mut exon_ids: HashMap<(&str, &str), usize> = HashMap::new();
+    // (transcript_id, feature, exon_number) -> first line
+    let mut child_keys: HashMap<(&str, &str, Option<&str>), usize> = HashMap::new();
+
+    let mut collisions = Vec::new();
+
+    for (n, line) in contents.lines().enumerate() {
+        let n = n + 1;
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let record = match Record::parse::<SEP>(line) {
+            Ok(r) if !r.gene_id.is_empty() => r,
+            _ => continue,
+        };
+
+        if record.feat == "gene" {
+            continue;
+        }
+
+        let tid = record.transcript_id;
+        let (gene_id, first) = *transcript_genes.entry(tid).or_insert((record.gene_id, n));
+        if gene_id != record.gene_id {
+            collisions.push(IdCollision {
+                kind: CollisionKind::TranscriptInManyGenes,
+                id: tid,
+                parent: gene_id,
+                first_line: first,
+                line: n,
+            });
+        }
+
+        match record.feat {
+            "transcript" => {
+                let first = *transcript_lines.entry(tid).or_insert(n);
+                if first != n && gene_id == record.gene_id {
+                    collisions.push(IdCollision {
+                        kind: CollisionKind::DuplicateTranscript,
+                        id: tid,
+                        parent: record.gene_id,
+                        first_line: first,
+                        line: n,
+                    });
+                }
+            }
+            "CDS" | "exon" | "start_codon" | "stop_codon" => {
+                let first = *child_keys
+                    .entry((tid, record.feat, record.exon_number))
+                    .or_insert(n);
+                if first != n {
+                    collisions.push(IdCollision {
+                        kind: CollisionKind::DuplicateChildKey,
+                        id: record.feat,
+                        parent: tid,
+                        first_line: first,
+                        line: n,
+                    });
+                }
+
+                if record.feat != "exon" {
+                    continue;
+                }
+
+                let exon_id = line
+                    .splitn(9, '\t')
+                    .nth(8)
+                    .and_then(|a| Attribute::parse::<SEP>(a).ok())
+                    .map(|a| a.exon_id())
+                    .filter(|id| *id != "0");
+
+                if let Some(exon_id) = exon_id {
+                    let first = *exon_ids.entry((tid, exon_id)).or_insert(n);
+                    if first != n {
+                        collisions.push(IdCollision {
+                            kind: CollisionKind::DuplicateExonId,
+                            id: exon_id,
+                            parent: tid,
+                            first_line: first,
+                            line: n,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_ids_pass() {
+        let input = "#header\n\
+            chr1\ts\tgene\t1\t20\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t1\t5\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\"; exon_id \"E1\";\n\
+            chr1\ts\tCDS\t2\t5\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+            chr1\ts\texon\t10\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\"; exon_id \"E2\";\n";
+
+        assert!(find_id_collisions::<b' '>(input).is_empty());
+    }
+
+    #[test]
+    fn collisions_are_reported() {
+        let input = "chr1\ts\ttranscript\t1\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"T\";\n\
+            chr1\ts\ttranscript\t1\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"T\";\n\
+            chr1\ts\texon\t1\t5\t.\t+\t.\tgene_id \"A\"; transcript_id \"T\"; exon_number \"1\"; exon_id \"E1\";\n\
+            chr1\ts\texon\t7\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"T\"; exon_number \"2\"; exon_id \"E1\";\n\
+            chr1\ts\tCDS\t2\t5\t.\t+\t0\tgene_id \"A\"; transcript_id \"T\";\n\
+            chr1\ts\tCDS\t7\t9\t.\t+\t0\tgene_id \"A\"; transcript_id \"T\";\n\
+            chr1\ts\ttranscript\t30\t40\t.\t+\t.\tgene_id \"B\"; transcript_id \"T\";\n";
+
+        let found = find_id_collisions::<b' '>(input)
+            .into_iter()
+            .map(|c| (c.kind, c.id, c.first_line, c.line))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            vec![
+                (CollisionKind::DuplicateTranscript, "T", 1, 2),
+                (CollisionKind::DuplicateExonId, "E1", 3, 4),
+                (CollisionKind::DuplicateChildKey, "CDS", 5, 6),
+                (CollisionKind::TranscriptInManyGenes, "T", 1, 7),
+            ]
+        );
+    }
+}
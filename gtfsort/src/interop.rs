@@ -4,10 +4,19 @@ pub mod c_ffi {
 
     use std::ffi::{c_char, c_ulong, c_void, CStr, CString};
 
+    /// An error of a job. `code` is one of the `GTFSORT_ERROR_*` constants, which keep
+    /// their values across releases, see [crate::ErrorCode].
+    ///
+    /// The context fields tell where the error happened: `phase` and `chrom` are null and
+    /// `line` and `byte_offset` are -1 when unknown.
     #[repr(C)]
     pub struct GtfSortErrorFFI {
         pub code: i32,
         pub message: *const c_char,
+        pub phase: *const c_char,
+        pub chrom: *const c_char,
+        pub line: i64,
+        pub byte_offset: i64,
     }
 
     pub const GTFSORT_ERROR_INVALID_INPUT: i32 = 1;
@@ -15,6 +24,10 @@ pub mod c_ffi {
     pub const GTFSORT_ERROR_PARSE_ERROR: i32 = 3;
     pub const GTFSORT_ERROR_INVALID_THREADS: i32 = 4;
     pub const GTFSORT_ERROR_IO_ERROR: i32 = 5;
+    pub const GTFSORT_ERROR_MEMORY_LIMIT_EXCEEDED: i32 = 6;
+    pub const GTFSORT_ERROR_TIMEOUT: i32 = 7;
+    pub const GTFSORT_ERROR_INTERNAL: i32 = 8;
+    pub const GTFSORT_ERROR_VERIFICATION_FAILED: i32 = 9;
     pub const GTFSORT_ERROR_INVALID_PARAMETER: i32 = -1;
 
     macro_rules! cstr {
@@ -33,31 +46,26 @@ pub mod c_ffi {
 
     impl From<GtfSortError> for GtfSortErrorFFI {
         fn from(e: GtfSortError) -> Self {
-            match e {
-                GtfSortError::InvalidInput(s) => Self {
-                    code: GTFSORT_ERROR_INVALID_INPUT,
-                    message: cstr!(s),
-                },
-                GtfSortError::InvalidOutput(s) => Self {
-                    code: GTFSORT_ERROR_INVALID_OUTPUT,
-                    message: cstr!(s),
-                },
-                GtfSortError::ParseError(s) => Self {
-                    code: GTFSORT_ERROR_PARSE_ERROR,
-                    message: cstr!(s),
-                },
-                GtfSortError::InvalidThreads(s) => Self {
-                    code: GTFSORT_ERROR_INVALID_THREADS,
-                    message: cstr!(s),
-                },
-                GtfSortError::IoError(s, e) => Self {
-                    code: GTFSORT_ERROR_IO_ERROR,
-                    message: cstr!(format!("{}: {}", s, e)),
-                },
-                GtfSortError::InvalidParameter(s) => Self {
-                    code: GTFSORT_ERROR_INVALID_PARAMETER,
-                    message: cstr!(s),
-                },
+            let code = e.code().as_i32();
+            let context = e.context();
+            let message = match e.into_root() {
+                GtfSortError::InvalidInput(s)
+                | GtfSortError::InvalidOutput(s)
+                | GtfSortError::InvalidThreads(s) => cstr!(s),
+                GtfSortError::ParseError(s) | GtfSortError::InvalidParameter(s) => cstr!(s),
+                GtfSortError::IoError(s, e) => cstr!(format!("{}: {}", s, e)),
+                e => cstr!(e.to_string()),
+            };
+
+            Self {
+                code,
+                message,
+                phase: context.phase.map_or(std::ptr::null(), |p| cstr!(p)),
+                chrom: context
+                    .chrom
+                    .map_or(std::ptr::null(), |c| cstr!(c.replace('\0', ""))),
+                line: context.line.map_or(-1, |n| n as i64),
+                byte_offset: context.byte_offset.map_or(-1, |n| n as i64),
             }
         }
     }
@@ -74,10 +82,17 @@ pub mod c_ffi {
         pub writing_secs: f64,
         pub start_mem_mb: f64,
         pub end_mem_mb: f64,
+        pub already_sorted: bool,
     }
 
     impl From<SortAnnotationsJobResult<'_>> for SortAnnotationsJobResultFFI {
         fn from(r: SortAnnotationsJobResult) -> Self {
+            (&r).into()
+        }
+    }
+
+    impl From<&SortAnnotationsJobResult<'_>> for SortAnnotationsJobResultFFI {
+        fn from(r: &SortAnnotationsJobResult) -> Self {
             Self {
                 input: cstr!(r.input),
                 output: cstr!(r.output),
@@ -89,6 +104,7 @@ pub mod c_ffi {
                 writing_secs: r.writing_secs,
                 start_mem_mb: r.start_mem_mb.unwrap_or(f64::NAN),
                 end_mem_mb: r.end_mem_mb.unwrap_or(f64::NAN),
+                already_sorted: r.already_sorted,
             }
         }
     }
@@ -114,6 +130,8 @@ pub mod c_ffi {
                         if !p.is_null() {
                             let p = Box::from_raw(*p);
                             cstr_free!(p.message);
+                            cstr_free!(p.phase);
+                            cstr_free!(p.chrom);
                         }
                     }
                 }
@@ -127,26 +145,83 @@ pub mod c_ffi {
         }
     }
 
+    /// Stores `ret` in `result_ptr`, freeing what it held before, or frees `ret` if
+    /// `result_ptr` is null.
+    unsafe fn set_result(result_ptr: *mut SortAnnotationsRet, mut ret: SortAnnotationsRet) {
+        match unsafe { result_ptr.as_mut() } {
+            Some(r) => {
+                r.clear();
+                *r = ret;
+            }
+            None => ret.clear(),
+        }
+    }
+
+    unsafe fn set_job_result(
+        result_ptr: *mut SortAnnotationsRet,
+        result: Result<SortAnnotationsJobResult, GtfSortError>,
+    ) {
+        let ret = match result {
+            Ok(r) => SortAnnotationsRet::Ok(Box::into_raw(Box::new(r.into()))),
+            Err(e) => SortAnnotationsRet::Err(Box::into_raw(Box::new(e.into()))),
+        };
+        unsafe { set_result(result_ptr, ret) }
+    }
+
+    /// Runs the body of an extern "C" function so that no panic unwinds into the caller,
+    /// which is undefined behavior: a panic is stored in `result_ptr` (if not null) as a
+    /// [GTFSORT_ERROR_INTERNAL] error and `on_panic` is returned instead.
+    ///
+    /// Builds with `panic = "abort"` (see the `ffi_abort` feature) abort the process on a
+    /// panic instead, after logging it.
+    fn guard<T>(result_ptr: *mut SortAnnotationsRet, on_panic: T, f: impl FnOnce() -> T) -> T {
+        match crate::panic::catch_job(|| Ok(f())) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("{}", e);
+                unsafe { set_job_result(result_ptr, Err(e)) };
+                on_panic
+            }
+        }
+    }
+
+    /// A C string argument as UTF-8.
+    unsafe fn utf8<'a>(s: *const c_char, what: &str) -> Result<&'a str, GtfSortError> {
+        if s.is_null() {
+            return Err(GtfSortError::InvalidInput(format!("{} is null", what)));
+        }
+        unsafe { CStr::from_ptr(s) }
+            .to_str()
+            .map_err(|_| GtfSortError::InvalidInput(format!("{} is not UTF-8", what)))
+    }
+
+    /// Returns true if this library was built with `panic = "abort"`, so that a bug in
+    /// gtfsort aborts the process instead of being reported as a
+    /// [GTFSORT_ERROR_INTERNAL] error. Hosts that need either behavior can check it at
+    /// startup.
+    #[no_mangle]
+    pub extern "C" fn gtfsort_panic_aborts() -> bool {
+        cfg!(panic = "abort")
+    }
+
     pub const GTFSORT_PARSE_MODE_GTF: u8 = 1;
     pub const GTFSORT_PARSE_MODE_GFF: u8 = 2;
     pub const GTFSORT_PARSE_MODE_GFF3: u8 = 2;
 
     /// Initializes the logger with the given log level.
-    /// The log level must be one of the following: trace, debug, info, warn, error.
+    /// The log level must be one of the following: trace, debug, info, warn, error,
+    /// unknown levels fall back to info.
+    ///
+    /// Returns false without changing anything if the host already set a logger.
     ///
     /// # Safety
     /// level must be a valid C string.
     #[no_mangle]
-    pub unsafe extern "C" fn gtfsort_init_logger(level: *const c_char) {
-        let level = unsafe { CStr::from_ptr(level).to_str().unwrap_or("info") };
-        match level.to_ascii_lowercase().as_str() {
-            "trace" => simple_logger::init_with_level(log::Level::Trace).unwrap(),
-            "debug" => simple_logger::init_with_level(log::Level::Debug).unwrap(),
-            "info" => simple_logger::init_with_level(log::Level::Info).unwrap(),
-            "warn" => simple_logger::init_with_level(log::Level::Warn).unwrap(),
-            "error" => simple_logger::init_with_level(log::Level::Error).unwrap(),
-            _ => simple_logger::init_with_level(log::Level::Info).unwrap(),
-        }
+    pub unsafe extern "C" fn gtfsort_init_logger(level: *const c_char) -> bool {
+        guard(std::ptr::null_mut(), false, || {
+            let level = unsafe { utf8(level, "level") }.unwrap_or("info");
+            crate::logging::try_init_logger(crate::logging::parse_level(level))
+        })
     }
 
     /// Allocates a new [SortAnnotationsRet] on the Rust heap.
@@ -156,7 +231,9 @@ pub mod c_ffi {
     /// Do not free the memory using any other method.
     #[no_mangle]
     pub unsafe extern "C" fn gtfsort_new_sort_annotations_ret() -> *mut SortAnnotationsRet {
-        Box::into_raw(Box::new(SortAnnotationsRet::Ok(std::ptr::null_mut())))
+        guard(std::ptr::null_mut(), std::ptr::null_mut(), || {
+            Box::into_raw(Box::new(SortAnnotationsRet::Ok(std::ptr::null_mut())))
+        })
     }
 
     /// Frees the [SortAnnotationsRet].
@@ -165,9 +242,11 @@ pub mod c_ffi {
     /// ret must be a valid pointer to a [SortAnnotationsRet] that is allocated by [gtfsort_new_sort_annotations_ret].
     #[no_mangle]
     pub unsafe extern "C" fn gtfsort_free_sort_annotations_ret(ret: *mut SortAnnotationsRet) {
-        let mut b = Box::from_raw(ret);
+        guard(std::ptr::null_mut(), (), || {
+            let mut b = unsafe { Box::from_raw(ret) };
 
-        b.clear();
+            b.clear();
+        })
     }
 
     /// Sorts the annotations in the given GTF or GFF3 file and writes the result to the output file.
@@ -186,24 +265,17 @@ pub mod c_ffi {
         threads: usize,
         result_ptr: *mut SortAnnotationsRet,
     ) -> bool {
-        let input = std::path::PathBuf::from(unsafe { CStr::from_ptr(input).to_str().unwrap() });
-        let output = std::path::PathBuf::from(unsafe { CStr::from_ptr(output).to_str().unwrap() });
-
-        let result = crate::sort_annotations(&input, &output, threads);
-
-        let ok = result.is_ok();
-
-        if !result_ptr.is_null() {
-            unsafe {
-                result_ptr.as_mut().unwrap().clear();
-                *result_ptr = match result {
-                    Ok(r) => SortAnnotationsRet::Ok(Box::into_raw(Box::new(r.into()))),
-                    Err(e) => SortAnnotationsRet::Err(Box::into_raw(Box::new(e.into()))),
-                };
-            }
-        }
+        guard(result_ptr, false, || {
+            let paths = unsafe { utf8(input, "input path") }
+                .and_then(|input| Ok((input, unsafe { utf8(output, "output path") }?)));
+            let result = paths.and_then(|(input, output)| {
+                crate::sort_annotations(input.as_ref(), output.as_ref(), threads)
+            });
 
-        ok
+            let ok = result.is_ok();
+            unsafe { set_job_result(result_ptr, result) };
+            ok
+        })
     }
 
     /// Sorts the annotations in the given GTF or GFF3 string and writes the result chunk by chunk to the output callback.
@@ -242,51 +314,275 @@ pub mod c_ffi {
         caller_data: *mut c_void,
         result_ptr: *mut SortAnnotationsRet,
     ) -> bool {
-        let input = unsafe { CStr::from_ptr(input).to_str().unwrap() };
-
-        let mut output = |str: &[u8]| {
-            let ret = output(
-                caller_data,
-                unsafe { CStr::from_bytes_with_nul_unchecked(str).as_ptr() },
-                str.len() as c_ulong,
-            );
-            match ret.is_null() {
-                true => Ok(str.len()),
-                false => Err(std::io::Error::other(unsafe {
-                    CStr::from_ptr(ret).to_str().unwrap()
-                })),
-            }
-        };
+        guard(result_ptr, false, || {
+            let input = match unsafe { utf8(input, "input") } {
+                Ok(input) => input,
+                Err(e) => {
+                    unsafe { set_job_result(result_ptr, Err(e)) };
+                    return false;
+                }
+            };
 
-        let result = match mode {
-            GTFSORT_PARSE_MODE_GTF => {
-                crate::sort_annotations_string::<b' ', _>(input, &mut output, threads)
+            let mut output = |str: &[u8]| {
+                let ret = output(
+                    caller_data,
+                    unsafe { CStr::from_bytes_with_nul_unchecked(str).as_ptr() },
+                    str.len() as c_ulong,
+                );
+                match ret.is_null() {
+                    true => Ok(str.len()),
+                    false => Err(std::io::Error::other(
+                        unsafe { CStr::from_ptr(ret) }.to_string_lossy(),
+                    )),
+                }
+            };
+
+            let result = match mode {
+                GTFSORT_PARSE_MODE_GTF => {
+                    crate::sort_annotations_string::<b' ', _>(input, &mut output, threads)
+                }
+                GTFSORT_PARSE_MODE_GFF3 => {
+                    crate::sort_annotations_string::<b'=', _>(input, &mut output, threads)
+                }
+                _ => Err(GtfSortError::InvalidParameter("invalid parse mode")),
+            };
+
+            let ok = result.is_ok();
+            unsafe { set_job_result(result_ptr, result) };
+            ok
+        })
+    }
+
+    /// Output of a string sort pulled chunk by chunk, see [gtfsort_sorted_chunks_new].
+    pub struct SortedChunksFFI {
+        chunks: crate::chunks::SortedChunks,
+        /// The chunk last returned, alive until the next call.
+        current: Vec<u8>,
+    }
+
+    /// Starts sorting the annotations in the given GTF or GFF3 string in the background,
+    /// returning a handle to pull the sorted output from with [gtfsort_sorted_chunks_next]
+    /// instead of receiving it through a callback.
+    ///
+    /// The mode must be one of the following:
+    /// - [GTFSORT_PARSE_MODE_GTF]
+    /// - [GTFSORT_PARSE_MODE_GFF3]
+    /// - [GTFSORT_PARSE_MODE_GFF]
+    ///
+    /// The input is copied, so it can be freed once this returns. Returns null if the mode
+    /// is invalid or the input is not UTF-8, setting result_ptr (if not null) to the error.
+    ///
+    /// # Safety
+    /// input must be a valid C string. The handle must be freed with
+    /// [gtfsort_sorted_chunks_free].
+    #[no_mangle]
+    pub unsafe extern "C" fn gtfsort_sorted_chunks_new(
+        mode: u8,
+        input: *const c_char,
+        threads: usize,
+        result_ptr: *mut SortAnnotationsRet,
+    ) -> *mut SortedChunksFFI {
+        guard(result_ptr, std::ptr::null_mut(), || {
+            let fail = |e: GtfSortError| {
+                unsafe { set_job_result(result_ptr, Err(e)) };
+                std::ptr::null_mut()
+            };
+
+            let input = match unsafe { utf8(input, "input") } {
+                Ok(input) => input.to_string(),
+                Err(e) => return fail(e),
+            };
+            let options = crate::SortOptions::default();
+            let chunks = match mode {
+                GTFSORT_PARSE_MODE_GTF => {
+                    crate::chunks::SortedChunks::spawn::<b' '>(input, threads, options)
+                }
+                GTFSORT_PARSE_MODE_GFF3 => {
+                    crate::chunks::SortedChunks::spawn::<b'='>(input, threads, options)
+                }
+                _ => return fail(GtfSortError::InvalidParameter("invalid parse mode")),
+            };
+
+            Box::into_raw(Box::new(SortedChunksFFI {
+                chunks,
+                current: Vec::new(),
+            }))
+        })
+    }
+
+    /// Waits for the next chunk of sorted output. Chunks are not NUL-terminated and may end
+    /// in the middle of a line.
+    ///
+    /// Returns true and points data and len at the chunk, which stays valid until the next
+    /// call or [gtfsort_sorted_chunks_free]. Returns false once the output is complete or
+    /// the sort failed, setting result_ptr (if not null) to the result of the job, and on
+    /// every call after that.
+    ///
+    /// # Safety
+    /// chunks must be a handle returned by [gtfsort_sorted_chunks_new], data and len
+    /// valid pointers.
+    #[no_mangle]
+    pub unsafe extern "C" fn gtfsort_sorted_chunks_next(
+        chunks: *mut SortedChunksFFI,
+        data: *mut *const c_char,
+        len: *mut c_ulong,
+        result_ptr: *mut SortAnnotationsRet,
+    ) -> bool {
+        guard(result_ptr, false, || {
+            let handle = unsafe { &mut *chunks };
+
+            let result = match handle.chunks.next_chunk() {
+                Ok(Some(chunk)) => {
+                    handle.current = chunk;
+                    unsafe {
+                        *data = handle.current.as_ptr() as *const c_char;
+                        *len = handle.current.len() as c_ulong;
+                    }
+                    return true;
+                }
+                Ok(None) => match handle.chunks.job() {
+                    Some(job) => SortAnnotationsRet::Ok(Box::into_raw(Box::new(job.into()))),
+                    // the error was already returned
+                    None => return false,
+                },
+                Err(e) => SortAnnotationsRet::Err(Box::into_raw(Box::new(e.into()))),
+            };
+
+            unsafe { set_result(result_ptr, result) };
+            false
+        })
+    }
+
+    /// Stops the sort if it is still running and frees the handle.
+    ///
+    /// # Safety
+    /// chunks must be a handle returned by [gtfsort_sorted_chunks_new], or null.
+    #[no_mangle]
+    pub unsafe extern "C" fn gtfsort_sorted_chunks_free(chunks: *mut SortedChunksFFI) {
+        guard(std::ptr::null_mut(), (), || {
+            if !chunks.is_null() {
+                drop(unsafe { Box::from_raw(chunks) });
             }
-            GTFSORT_PARSE_MODE_GFF3 => {
-                crate::sort_annotations_string::<b'=', _>(input, &mut output, threads)
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{ErrorCode, ErrorContext};
+
+        #[test]
+        fn error_codes_match_the_public_constants() {
+            let constants = [
+                (GTFSORT_ERROR_INVALID_PARAMETER, ErrorCode::InvalidParameter),
+                (GTFSORT_ERROR_INVALID_INPUT, ErrorCode::InvalidInput),
+                (GTFSORT_ERROR_INVALID_OUTPUT, ErrorCode::InvalidOutput),
+                (GTFSORT_ERROR_PARSE_ERROR, ErrorCode::ParseError),
+                (GTFSORT_ERROR_INVALID_THREADS, ErrorCode::InvalidThreads),
+                (GTFSORT_ERROR_IO_ERROR, ErrorCode::IoError),
+                (
+                    GTFSORT_ERROR_MEMORY_LIMIT_EXCEEDED,
+                    ErrorCode::MemoryLimitExceeded,
+                ),
+                (GTFSORT_ERROR_TIMEOUT, ErrorCode::Timeout),
+                (GTFSORT_ERROR_INTERNAL, ErrorCode::Internal),
+                (
+                    GTFSORT_ERROR_VERIFICATION_FAILED,
+                    ErrorCode::VerificationFailed,
+                ),
+            ];
+            for (constant, code) in constants {
+                assert_eq!(constant, code.as_i32());
             }
-            _ => {
-                unsafe {
-                    *result_ptr = SortAnnotationsRet::Err(Box::into_raw(Box::new(
-                        GtfSortError::InvalidParameter("invalid parse mode").into(),
-                    )));
-                }
-                return false;
+        }
+
+        #[test]
+        fn panics_do_not_cross_the_boundary() {
+            let mut ret = SortAnnotationsRet::Ok(std::ptr::null_mut());
+            let ok = guard(&mut ret, false, || -> bool { panic!("boom") });
+            assert!(!ok);
+            match ret {
+                SortAnnotationsRet::Err(e) => unsafe {
+                    assert_eq!((*e).code, GTFSORT_ERROR_INTERNAL);
+                    assert!(CStr::from_ptr((*e).message)
+                        .to_str()
+                        .unwrap()
+                        .contains("boom"));
+                },
+                SortAnnotationsRet::Ok(_) => panic!("expected an error"),
             }
-        };
+            ret.clear();
 
-        let ok = result.is_ok();
+            // arguments that used to be unwrapped are reported instead
+            let mut ret = SortAnnotationsRet::Ok(std::ptr::null_mut());
+            let input = CString::new(vec![0xff, 0xfe]).unwrap();
+            assert!(!unsafe {
+                gtfsort_sort_annotations(input.as_ptr(), input.as_ptr(), 1, &mut ret)
+            });
+            assert!(matches!(ret, SortAnnotationsRet::Err(e)
+                if unsafe { (*e).code } == GTFSORT_ERROR_INVALID_INPUT));
+            ret.clear();
+            assert!(!gtfsort_panic_aborts());
+        }
 
-        if !result_ptr.is_null() {
-            unsafe {
-                result_ptr.as_mut().unwrap().clear();
-                *result_ptr = match result {
-                    Ok(r) => SortAnnotationsRet::Ok(Box::into_raw(Box::new(r.into()))),
-                    Err(e) => SortAnnotationsRet::Err(Box::into_raw(Box::new(e.into()))),
-                };
+        #[test]
+        fn chunks_are_pulled_through_the_handle() {
+            let input = "chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+                chr2\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+                chr2\ts\texon\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+                chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+                chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+                chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+            let c_input = CString::new(input).unwrap();
+            let mut ret = SortAnnotationsRet::Ok(std::ptr::null_mut());
+
+            let chunks = unsafe {
+                gtfsort_sorted_chunks_new(GTFSORT_PARSE_MODE_GTF, c_input.as_ptr(), 1, &mut ret)
+            };
+            assert!(!chunks.is_null());
+            drop(c_input);
+
+            let mut output = Vec::new();
+            let (mut data, mut len) = (std::ptr::null(), 0);
+            while unsafe { gtfsort_sorted_chunks_next(chunks, &mut data, &mut len, &mut ret) } {
+                output.extend_from_slice(unsafe {
+                    std::slice::from_raw_parts(data as *const u8, len as usize)
+                });
             }
+            unsafe { gtfsort_sorted_chunks_free(chunks) };
+
+            assert!(matches!(ret, SortAnnotationsRet::Ok(p) if !p.is_null()));
+            ret.clear();
+            ret = SortAnnotationsRet::Ok(std::ptr::null_mut());
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.starts_with("chr1\ts\tgene"));
+            assert_eq!(output.len(), input.len());
+
+            let c_input = CString::new(input).unwrap();
+            let chunks = unsafe { gtfsort_sorted_chunks_new(9, c_input.as_ptr(), 1, &mut ret) };
+            assert!(chunks.is_null());
+            assert!(matches!(ret, SortAnnotationsRet::Err(_)));
+            ret.clear();
         }
 
-        ok
+        #[test]
+        fn context_is_passed_to_c() {
+            let e = GtfSortError::InvalidInput("bad record".to_string())
+                .with_context(ErrorContext::phase("parsing").with_line(7));
+            let mut ret = SortAnnotationsRet::Err(Box::into_raw(Box::new(e.into())));
+
+            let SortAnnotationsRet::Err(p) = &ret else {
+                unreachable!()
+            };
+            let err = unsafe { &**p };
+            let string = |p: *const c_char| unsafe { CStr::from_ptr(p).to_str().unwrap() };
+            assert_eq!(err.code, GTFSORT_ERROR_INVALID_INPUT);
+            assert_eq!(string(err.message), "bad record");
+            assert_eq!(string(err.phase), "parsing");
+            assert!(err.chrom.is_null());
+            assert_eq!((err.line, err.byte_offset), (7, -1));
+
+            ret.clear();
+        }
     }
 }
@@ -0,0 +1,250 @@
+//! Lines that do not parse as a record, see [crate::SortOptions::parse_mode].
+//!
+//! The parallel parser only counts them (see [crate::LineCounts::invalid]), so once it
+//! found some the input is scanned again in order to tell which ones, with their line
+//! numbers. Inputs without invalid lines are not scanned again.
+
+use std::{borrow::Cow, fmt::Write as _};
+
+use crate::{
+    filter::ChromFilter, format::AnnotationFormat, json, ErrorContext, GtfSortError, LineCounts,
+    Record,
+};
+
+/// Invalid lines kept as samples in [InvalidLines].
+pub(crate) const INVALID_SAMPLES: usize = 5;
+
+/// What a job does with lines that do not parse as a record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Skip them with a warning, reporting how many and the first few in
+    /// [crate::SortAnnotationsJobResult::invalid_lines].
+    #[default]
+    Lenient,
+    /// Fail the job at the first one, with its line number and content.
+    Strict,
+}
+
+/// A line that does not parse as a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLine {
+    /// 1-based line number in the input, None if the input was sorted one chromosome at a
+    /// time (see [crate::SortOptions::compress_lines]).
+    pub line_number: Option<usize>,
+    pub reason: String,
+    pub line: String,
+}
+
+/// The lines of an input that do not parse as a record, see [scan].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InvalidLines {
+    pub count: usize,
+    /// The first [INVALID_SAMPLES] of them, in input order.
+    pub samples: Vec<InvalidLine>,
+}
+
+impl InvalidLines {
+    /// Adds the lines of `other`, found after those of `self`.
+    pub fn merge(&mut self, other: InvalidLines) {
+        self.count += other.count;
+        let room = INVALID_SAMPLES.saturating_sub(self.samples.len());
+        self.samples.extend(other.samples.into_iter().take(room));
+    }
+
+    /// Moves the line numbers down by `lines`, e.g. for a section that starts after them.
+    pub fn shift(&mut self, lines: usize) {
+        self.samples
+            .iter_mut()
+            .filter_map(|s| s.line_number.as_mut())
+            .for_each(|n| *n += lines);
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = format!("{{\"count\":{},\"samples\":[", self.count);
+        for (i, sample) in self.samples.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            match sample.line_number {
+                Some(n) => write!(s, "{{\"line_number\":{}", n).unwrap(),
+                None => s.push_str("{\"line_number\":null"),
+            }
+            s.push_str(",\"reason\":");
+            json::string(&mut s, &sample.reason);
+            s.push_str(",\"line\":");
+            json::string(&mut s, &sample.line);
+            s.push('}');
+        }
+        s.push_str("]}");
+        s
+    }
+}
+
+/// Finds the lines of `contents` that `check` rejects, skipping comments, blank lines
+/// and lines outside `chroms` as the parser does. `check` returns why a line is invalid.
+///
+/// With [ParseMode::Strict] the first one fails the job instead, located in `contents`
+/// if `numbered`. Without `numbered`, `contents` is not the whole input and the lines
+/// carry no line number.
+pub fn scan<F: Fn(&str) -> Option<Cow<'static, str>>>(
+    contents: &str,
+    chroms: Option<&ChromFilter>,
+    mode: ParseMode,
+    numbered: bool,
+    check: F,
+) -> Result<InvalidLines, GtfSortError> {
+    let mut counts = LineCounts::default();
+    let mut invalid = InvalidLines::default();
+
+    for (i, line) in contents.lines().enumerate() {
+        if counts.count_non_record(line, chroms) {
+            continue;
+        }
+        let Some(reason) = check(line) else {
+            continue;
+        };
+
+        if mode == ParseMode::Strict {
+            let mut context = ErrorContext::phase("parsing");
+            if numbered {
+                context = context.with_location(contents, line);
+            }
+            return Err(strict_error(reason, line, context));
+        }
+
+        invalid.count += 1;
+        if invalid.samples.len() < INVALID_SAMPLES {
+            invalid.samples.push(InvalidLine {
+                line_number: numbered.then_some(i + 1),
+                reason: reason.into_owned(),
+                line: line.to_string(),
+            });
+        }
+    }
+
+    Ok(invalid)
+}
+
+/// Why `line` does not parse as a record of `format`, if it does not.
+pub(crate) fn check_line(format: AnnotationFormat, line: &str) -> Option<Cow<'static, str>> {
+    match format {
+        AnnotationFormat::Gtf => Record::parse::<b' '>(line).err(),
+        AnnotationFormat::Gff3 => Record::parse::<b'='>(line).err(),
+    }
+}
+
+/// The error failing a job with [ParseMode::Strict] at `line`.
+pub(crate) fn strict_error(
+    reason: Cow<'static, str>,
+    line: &str,
+    context: ErrorContext,
+) -> GtfSortError {
+    GtfSortError::InvalidRecord {
+        reason: reason.into_owned(),
+        line: line.to_string(),
+    }
+    .with_context(context)
+}
+
+/// Logs the first samples of `invalid`, after [LineCounts::log] warned about their count.
+pub fn log_samples(invalid: &InvalidLines) {
+    for sample in &invalid.samples {
+        match sample.line_number {
+            Some(n) => log::warn!("  line {}: {}: {:?}", n, sample.reason, sample.line),
+            None => log::warn!("  {}: {:?}", sample.reason, sample.line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, ErrorCode, SortOptions};
+
+    const GTF: &str = "chr1\ts\tgene\t10\t90\t.\t+\t.\tgene_id \"A\";\n\
+        # comment\n\
+        chr1\ts\texon\tx\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\ttranscript\t10\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\n";
+
+    fn sort(options: &SortOptions) -> Result<InvalidLines, GtfSortError> {
+        sort_annotations_string_with_options::<b' ', _>(GTF, &mut |b| Ok(b.len()), 1, options)
+            .map(|ret| ret.invalid_lines)
+    }
+
+    #[test]
+    fn lenient_reports_invalid_lines() {
+        let invalid = sort(&SortOptions::default()).unwrap();
+        assert_eq!(invalid.count, 2);
+        let numbers = invalid
+            .samples
+            .iter()
+            .map(|s| s.line_number)
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![Some(3), Some(5)]);
+        assert_eq!(invalid.samples[1].line, "chr1\ts\texon");
+        assert!(invalid
+            .to_json()
+            .starts_with("{\"count\":2,\"samples\":[{\"line_number\":3,"));
+    }
+
+    #[test]
+    fn strict_fails_at_the_first_invalid_line() {
+        let err = sort(&SortOptions {
+            parse_mode: ParseMode::Strict,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ParseError);
+        assert_eq!(err.context().line, Some(3));
+        assert!(matches!(
+            err.root(),
+            GtfSortError::InvalidRecord { line, .. } if line.starts_with("chr1\ts\texon\tx\t")
+        ));
+    }
+
+    #[test]
+    fn strict_fails_before_writing_in_every_backend() {
+        let dir = crate::temp::TempPath::dir(None, "strict_test").unwrap();
+        let input = dir.join("in.gtf");
+        // chr1 sorts first, so it would be written before chr2 were checked
+        let gene = "chr1\ts\tgene\t10\t90\t.\t+\t.\tgene_id \"A\";\n";
+        let bad = "chr2\ts\texon\tx\t20\t.\t+\t.\tgene_id \"B\";\n";
+        // within the lines the format is detected from, then after them
+        for leading in [1, (1 << 20) / gene.len() + 1] {
+            std::fs::write(&input, format!("{}{}", gene.repeat(leading), bad)).unwrap();
+
+            let mut backends = vec![
+                SortOptions {
+                    low_memory: true,
+                    tmpdir: Some(dir.to_path_buf()),
+                    ..Default::default()
+                },
+                SortOptions {
+                    compress_lines: crate::packed::LineCompression::Always,
+                    ..Default::default()
+                },
+            ];
+            if cfg!(feature = "mmap") {
+                backends.push(SortOptions {
+                    mmap_window: Some(1 << 16),
+                    ..Default::default()
+                });
+            }
+            for options in backends {
+                let options = SortOptions {
+                    parse_mode: ParseMode::Strict,
+                    ..options
+                };
+                let output = dir.join("out.gtf");
+                let Err(err) = crate::sort_annotations_with_options(&input, &output, 1, &options)
+                else {
+                    panic!("{:?} accepted an invalid line", options);
+                };
+                assert_eq!(err.code(), ErrorCode::ParseError);
+                assert_eq!(err.context().line, Some(leading + 1), "{:?}", options);
+                assert!(!output.exists(), "{:?}", options);
+            }
+        }
+    }
+}
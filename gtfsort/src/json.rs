@@ -0,0 +1,63 @@
+//! Writers shared by the JSON reports of the crate (run summaries, check and verify
+//! reports, progress events, ...), which are built by hand so that serde stays optional.
+
+use std::fmt::Write;
+
+/// Appends `v` as a quoted JSON string, escaping quotes, backslashes and control
+/// characters.
+pub(crate) fn string(s: &mut String, v: &str) {
+    s.push('"');
+    for c in v.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            c if c.is_control() => write!(s, "\\u{:04x}", c as u32).unwrap(),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+}
+
+/// Appends `items` as a JSON array of strings, see [string].
+pub(crate) fn strings<T: AsRef<str>>(s: &mut String, items: impl IntoIterator<Item = T>) {
+    s.push('[');
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        string(s, item.as_ref());
+    }
+    s.push(']');
+}
+
+/// Appends `v` with `decimals` decimals, or `null` if missing or not finite, which JSON
+/// cannot represent.
+pub(crate) fn number(s: &mut String, v: impl Into<Option<f64>>, decimals: usize) {
+    match v.into().filter(|v| v.is_finite()) {
+        Some(v) => write!(s, "{:.*}", decimals, v).unwrap(),
+        None => s.push_str("null"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_and_nulls() {
+        let mut s = String::new();
+        string(&mut s, "a\"b\\c\td\u{7f}");
+        s.push(',');
+        strings(&mut s, ["x", "y"]);
+        s.push(',');
+        number(&mut s, 1.23456, 3);
+        s.push(',');
+        number(&mut s, f64::NAN, 3);
+        s.push(',');
+        number(&mut s, None, 3);
+        assert_eq!(
+            s,
+            "\"a\\\"b\\\\c\\u0009d\\u007f\",[\"x\",\"y\"],1.235,null,null"
+        );
+    }
+}
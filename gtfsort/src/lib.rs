@@ -1,36 +1,172 @@
+#[cfg(all(feature = "minimal", any(feature = "parallel", feature = "color")))]
+compile_error!(
+    "the minimal feature needs --no-default-features, it cannot be combined with parallel or color"
+);
+
+#[cfg(all(feature = "ffi_abort", not(panic = "abort")))]
+compile_error!(
+    "the ffi_abort feature needs panic = \"abort\", build with --profile ffi (e.g. cargo rustc --lib --profile ffi --features ffi_abort --crate-type staticlib)"
+);
+
 pub mod gtf;
 
-pub use gtf::Record;
+pub use gtf::{Layer, Record};
+
+mod bucketed;
+
+pub mod budget;
+
+pub mod check;
+
+pub mod chunks;
+
+pub mod chrom_order;
+
+pub mod color;
+
+pub mod compress;
+pub use compress::OutputCompression;
+
+pub mod coords;
+
+pub mod dedup;
+
+pub mod explain;
+
+pub mod export;
+
+pub mod error;
+pub use error::{ErrorCode, ErrorContext};
 
 pub mod ord;
-pub use ord::CowNaturalSort;
+pub use ord::{ChildKey, CowNaturalSort, GeneSortKey};
 
 pub mod utils;
 pub use utils::*;
 
 pub mod interop;
 
-#[cfg(feature = "testing")]
+pub mod filter;
+pub use filter::{ChromFilter, IdFilter, Region, RegionOverlap};
+
+pub mod features;
+pub use features::{FeatureMap, LayerMap};
+
+pub mod format;
+pub use format::AnnotationFormat;
+
+pub mod gene_order;
+
+pub mod gzip;
+
+pub mod header;
+pub use header::Header;
+
+pub mod hierarchy;
+pub mod ids;
+pub mod invalid;
+mod json;
+pub use invalid::{InvalidLines, ParseMode};
+pub mod liftover;
+pub mod lock;
+
+pub mod panic;
+
+pub mod par;
+
+pub mod presorted;
+
+pub mod progress;
+use progress::{Phase, PhaseProgress};
+
+pub mod logging;
+
+pub mod manifest;
+
+pub mod merge;
+pub use merge::sort_annotations_merged;
+
+pub mod offsets;
+
+pub mod options;
+
+pub mod packed;
+pub use options::SortOptions;
+
+pub mod quality;
+
+pub mod rejects;
+
+pub mod rename;
+pub use rename::{ChromNaming, ChromRenamer};
+
+pub mod sampler;
+
+pub mod section;
+use sampler::ResourceSampler;
+
+pub mod sniff;
+
+pub mod sorted;
+pub use sorted::{sort_annotations_iter, SortedAnnotations};
+
+pub mod sorter;
+pub use sorter::{Sorter, SorterBuilder};
+
+pub mod spill;
+
+pub mod stage;
+
+pub mod synth;
+
+pub mod tabix;
+use synth::ParentSpans;
+
+pub mod temp;
+
+pub mod validate;
+
+pub mod verify;
+
+pub mod version;
+
+pub mod watchdog;
+use watchdog::Watchdog;
+
+#[cfg(feature = "mmap")]
+pub mod window;
+
+#[cfg(any(test, feature = "testing"))]
 pub mod test_utils;
 #[cfg(feature = "testing")]
 pub use test_utils::*;
 
-use std::{io, path::PathBuf};
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 use thiserror::Error;
 
 #[cfg(feature = "mmap")]
 use mmap::Madvice;
 #[cfg(feature = "mmap")]
-use std::{borrow::Cow, fs::File};
+use std::fs::File;
 
 #[allow(unused_imports)]
-use colored::Colorize;
-use dashmap::DashMap;
-use rayon::prelude::*;
+use color::Colorize;
+use hashbrown::HashMap;
+use par::*;
 
 #[cfg(feature = "mmap")]
 pub mod mmap;
 
+/// Errors of a sort job. Match on [GtfSortError::code] rather than on the variants, which
+/// may be wrapped in a [GtfSortError::Context].
 #[derive(Debug, Error)]
 pub enum GtfSortError {
     /// The input file does not exist or is not a GTF or GFF3 file.
@@ -45,17 +181,79 @@ pub enum GtfSortError {
     #[error("Parse GtfSortError: {0}")]
     ParseError(&'static str),
 
+    /// A line does not parse as a record, see [invalid::ParseMode::Strict].
+    #[error("Invalid record: {reason}: {line:?}")]
+    InvalidRecord { reason: String, line: String },
+
     /// The number of threads is invalid.
     #[error("Invalid number of threads: {0}")]
     InvalidThreads(String),
 
     /// An IO GtfSortError occurred.
     #[error("IO GtfSortError: while {0}: {1}")]
-    IoError(&'static str, std::io::Error),
+    IoError(&'static str, #[source] std::io::Error),
 
     /// An Invalid Parameter is passed.
     #[error("Invalid parameter: {0}")]
     InvalidParameter(&'static str),
+
+    /// The resident set size went above the configured limit: (used MB, limit MB).
+    #[error(
+        "Memory limit exceeded: resident memory reached {0:.1} MB, above the limit of {1:.1} MB"
+    )]
+    MemoryLimitExceeded(f64, f64),
+
+    /// The job ran longer than the configured timeout, in seconds.
+    #[error("Timeout: the job did not finish within {0:.1} seconds")]
+    Timeout(f64),
+
+    /// Input records are missing from the output, see [SortOptions::verify].
+    #[error("Verification failed: {0}")]
+    VerificationFailed(String),
+
+    /// A panic was caught at the job boundary.
+    #[error("Internal error: {0}")]
+    Internal(Box<panic::PanicReport>),
+
+    /// Another error, with where it happened. See [GtfSortError::context] and
+    /// [GtfSortError::code], which look through it.
+    #[error("{source} ({context})")]
+    Context {
+        context: ErrorContext,
+        source: Box<GtfSortError>,
+    },
+}
+
+impl GtfSortError {
+    /// Returns true if the error comes from the reader of the output going away, e.g. the
+    /// output is piped into `head`. This is usually not worth reporting.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self.root(), GtfSortError::IoError(_, e) if e.kind() == io::ErrorKind::BrokenPipe)
+    }
+}
+
+/// A faster code path that failed during a job and was replaced by a slower one, e.g. a
+/// memory mapped output on a filesystem that does not support it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fallback {
+    /// The code path that failed, e.g. "output memory map".
+    pub what: &'static str,
+    /// Why it failed.
+    pub reason: String,
+    /// Seconds spent in the failed attempt, on top of the path that replaced it.
+    pub lost_secs: f64,
+    /// Bytes written in the failed attempt or copied again because of it.
+    pub extra_bytes: u64,
+}
+
+impl std::fmt::Display for Fallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed ({}), {:.2}s and {} bytes lost",
+            self.what, self.reason, self.lost_secs, self.extra_bytes
+        )
+    }
 }
 
 pub struct SortAnnotationsJobResult<'a> {
@@ -69,21 +267,566 @@ pub struct SortAnnotationsJobResult<'a> {
     pub writing_secs: f64,
     pub start_mem_mb: Option<f64>,
     pub end_mem_mb: Option<f64>,
+    /// The input already followed the sorted layout and was copied through unchanged.
+    pub already_sorted: bool,
+    /// Annotation quality metrics, if requested with [SortOptions::quality_metrics].
+    pub quality: Option<quality::QualityMetrics>,
+    /// RSS/CPU time series, if requested with [SortOptions::sample_interval].
+    pub resources: Option<sampler::ResourceReport>,
+    /// The format the input was parsed as.
+    pub format: Option<AnnotationFormat>,
+    /// How the format was sniffed, if requested with [SortOptions::detect_format].
+    pub format_detection: Option<format::FormatDetection>,
+    /// Feature types the sorter does not know (e.g. mRNA, region) with their number of
+    /// lines, most frequent first. They are written as generic children of their transcript.
+    pub unknown_features: Vec<(String, usize)>,
+    /// Genes copied from the previous output with [SortOptions::incremental], None if
+    /// not requested or the previous manifest could not be used.
+    pub reused_genes: Option<usize>,
+    /// How the input lines were accounted for, all zero if the input was copied through.
+    pub lines: LineCounts,
+    /// Parsed records per chromosome and feature type, empty if the input was copied
+    /// through.
+    pub histogram: RecordHistogram,
+    /// Input lines that do not parse as a record, with the first few of them. See
+    /// [SortOptions::parse_mode].
+    pub invalid_lines: InvalidLines,
+    /// Output lines that failed [SortOptions::validate_output], 0 if not requested.
+    pub malformed_lines: usize,
+    /// Input lines whose chromosome was renamed, see [SortOptions::chrom_rename].
+    pub renamed_lines: usize,
+    /// Size of the sorted output in bytes, 0 if it was not measured (e.g. a coordinate-only
+    /// sort).
+    pub output_bytes: u64,
+    /// Faster code paths that failed and were replaced during the job, in order.
+    pub fallbacks: Vec<Fallback>,
+    /// Record counts of the input and the written output, if requested with
+    /// [SortOptions::verify].
+    pub verification: Option<verify::Verification>,
+}
+
+impl SortAnnotationsJobResult<'_> {
+    /// Detaches the summary from the input it borrows, naming the input and output with
+    /// these labels instead, e.g. to hand it over from the thread owning the input.
+    pub fn relabel<'b>(self, input: &'b str, output: &'b str) -> SortAnnotationsJobResult<'b> {
+        SortAnnotationsJobResult {
+            input,
+            output,
+            threads: self.threads,
+            input_mmaped: self.input_mmaped,
+            output_mmaped: self.output_mmaped,
+            parsing_secs: self.parsing_secs,
+            indexing_secs: self.indexing_secs,
+            writing_secs: self.writing_secs,
+            start_mem_mb: self.start_mem_mb,
+            end_mem_mb: self.end_mem_mb,
+            already_sorted: self.already_sorted,
+            quality: self.quality,
+            resources: self.resources,
+            format: self.format,
+            format_detection: self.format_detection,
+            unknown_features: self.unknown_features,
+            reused_genes: self.reused_genes,
+            lines: self.lines,
+            histogram: self.histogram,
+            invalid_lines: self.invalid_lines,
+            malformed_lines: self.malformed_lines,
+            renamed_lines: self.renamed_lines,
+            output_bytes: self.output_bytes,
+            fallbacks: self.fallbacks,
+            verification: self.verification,
+        }
+    }
+
+    /// Bytes written to disk per byte of output, above 1 if a fallback rewrote or copied
+    /// the output. None if nothing was written.
+    pub fn write_amplification(&self) -> Option<f64> {
+        let extra: u64 = self.fallbacks.iter().map(|f| f.extra_bytes).sum();
+        (self.output_bytes > 0)
+            .then(|| (self.output_bytes + extra) as f64 / self.output_bytes as f64)
+    }
+
+    /// Serializes the job summary, including the nested reports that were requested, as
+    /// a single JSON object. Values that were not measured are `null`.
+    pub fn to_json(&self) -> String {
+        use std::fmt::Write as _;
+
+        fn nested(s: &mut String, v: Option<String>) {
+            s.push_str(v.as_deref().unwrap_or("null"));
+        }
+
+        let mut s = String::with_capacity(512);
+        s.push_str("{\"input\":");
+        json::string(&mut s, self.input);
+        s.push_str(",\"output\":");
+        json::string(&mut s, self.output);
+        write!(s, ",\"threads\":{},\"format\":", self.threads).unwrap();
+        match self.format {
+            Some(f) => json::string(&mut s, &f.to_string()),
+            None => s.push_str("null"),
+        }
+        s.push_str(",\"format_detection\":");
+        nested(&mut s, self.format_detection.map(|d| d.to_json()));
+        write!(s, ",\"already_sorted\":{}", self.already_sorted).unwrap();
+        s.push_str(",\"parsing_secs\":");
+        json::number(&mut s, self.parsing_secs, 4);
+        s.push_str(",\"indexing_secs\":");
+        json::number(&mut s, self.indexing_secs, 4);
+        s.push_str(",\"writing_secs\":");
+        json::number(&mut s, self.writing_secs, 4);
+        s.push_str(",\"start_mem_mb\":");
+        json::number(&mut s, self.start_mem_mb, 4);
+        s.push_str(",\"end_mem_mb\":");
+        json::number(&mut s, self.end_mem_mb, 4);
+        s.push_str(",\"quality\":");
+        nested(&mut s, self.quality.as_ref().map(|q| q.to_json()));
+        s.push_str(",\"resources\":");
+        nested(&mut s, self.resources.as_ref().map(|r| r.to_json()));
+        s.push_str(",\"reused_genes\":");
+        match self.reused_genes {
+            Some(n) => write!(s, "{}", n).unwrap(),
+            None => s.push_str("null"),
+        }
+        s.push_str(",\"lines\":");
+        s.push_str(&self.lines.to_json());
+        s.push_str(",\"invalid_lines\":");
+        s.push_str(&self.invalid_lines.to_json());
+        for (key, counts) in [
+            ("chromosomes", &self.histogram.chromosomes),
+            ("features", &self.histogram.features),
+        ] {
+            write!(s, ",\"{}\":{{", key).unwrap();
+            for (i, (name, n)) in counts.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
+                }
+                json::string(&mut s, name);
+                write!(s, ":{}", n).unwrap();
+            }
+            s.push('}');
+        }
+        write!(s, ",\"malformed_lines\":{}", self.malformed_lines).unwrap();
+        write!(s, ",\"renamed_lines\":{}", self.renamed_lines).unwrap();
+        write!(s, ",\"output_bytes\":{}", self.output_bytes).unwrap();
+        s.push_str(",\"write_amplification\":");
+        json::number(&mut s, self.write_amplification(), 4);
+        s.push_str(",\"fallbacks\":[");
+        for (i, f) in self.fallbacks.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            s.push_str("{\"what\":");
+            json::string(&mut s, f.what);
+            s.push_str(",\"reason\":");
+            json::string(&mut s, &f.reason);
+            s.push_str(",\"lost_secs\":");
+            json::number(&mut s, f.lost_secs, 4);
+            write!(s, ",\"extra_bytes\":{}}}", f.extra_bytes).unwrap();
+        }
+        s.push(']');
+        s.push_str(",\"verification\":");
+        nested(&mut s, self.verification.as_ref().map(|v| v.to_json()));
+        s.push_str(",\"unknown_features\":{");
+        for (i, (feat, n)) in self.unknown_features.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            json::string(&mut s, feat);
+            write!(s, ":{}", n).unwrap();
+        }
+        s.push_str("}}");
+        s
+    }
+
+    /// Serializes the scalar part of the job summary as `key\tvalue` lines, e.g.
+    /// `lines.records`, `chromosomes.chr1` or `features.exon`, for pipelines that read
+    /// tables more easily than JSON. Values that were not measured are `NA`.
+    pub fn to_tsv(&self) -> String {
+        use std::fmt::Write as _;
+
+        fn num(v: Option<f64>) -> String {
+            v.filter(|v| v.is_finite())
+                .map_or_else(|| "NA".to_string(), |v| format!("{:.4}", v))
+        }
+
+        let mut s = String::with_capacity(512);
+        let mut row = |key: &str, value: &dyn std::fmt::Display| {
+            // tabs and newlines in names would break the table
+            let key = key.replace(['\t', '\n', '\r'], " ");
+            writeln!(s, "{}\t{}", key, value).unwrap();
+        };
+        row("input", &self.input);
+        row("output", &self.output);
+        row("threads", &self.threads);
+        row(
+            "format",
+            &self
+                .format
+                .map_or_else(|| "NA".to_string(), |f| f.to_string()),
+        );
+        row("already_sorted", &self.already_sorted);
+        row("parsing_secs", &num(Some(self.parsing_secs)));
+        row("indexing_secs", &num(Some(self.indexing_secs)));
+        row("writing_secs", &num(Some(self.writing_secs)));
+        row("start_mem_mb", &num(self.start_mem_mb));
+        row("end_mem_mb", &num(self.end_mem_mb));
+        let lines = &self.lines;
+        for (key, n) in [
+            ("records", lines.records),
+            ("comments", lines.comments),
+            ("blank", lines.blank),
+            ("skipped", lines.skipped),
+            ("invalid", lines.invalid),
+            ("duplicates", lines.duplicates),
+            ("sequence", lines.sequence),
+            ("surrounding", lines.surrounding),
+        ] {
+            row(&format!("lines.{}", key), &n);
+        }
+        row("malformed_lines", &self.malformed_lines);
+        row("renamed_lines", &self.renamed_lines);
+        row("output_bytes", &self.output_bytes);
+        for (chrom, n) in &self.histogram.chromosomes {
+            row(&format!("chromosomes.{}", chrom), n);
+        }
+        for (feat, n) in &self.histogram.features {
+            row(&format!("features.{}", feat), n);
+        }
+        for (feat, n) in &self.unknown_features {
+            row(&format!("unknown_features.{}", feat), n);
+        }
+        s
+    }
+}
+
+/// Path standing for stdin as the input and for stdout as the output of a file sort, as
+/// in `gtfsort -i - -o -`.
+pub const STDIO_PATH: &str = "-";
+
+/// Whether `path` is [STDIO_PATH].
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO_PATH
 }
 
 pub fn sort_annotations<'a>(
-    input: &'a PathBuf,
-    output: &'a PathBuf,
+    input: &'a Path,
+    output: &'a Path,
+    threads: usize,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    sort_annotations_with_options(input, output, threads, &SortOptions::default())
+}
+
+pub fn sort_annotations_with_options<'a>(
+    input: &'a Path,
+    output: &'a Path,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    check_threads(threads)?;
+    if let Some(logger) = &options.logger {
+        logger.install();
+    }
+    panic::catch_job(|| match is_stdio(output) {
+        true => {
+            sort_file_job(input, OutputTarget::Writer(io::stdout()), threads, options).map(|ret| {
+                let input = ret.input;
+                ret.relabel(input, STDIO_PATH)
+            })
+        }
+        false => sort_file_job::<io::Sink>(input, OutputTarget::Path(output), threads, options),
+    })
+}
+
+/// Sorts a GTF/GFF3 file into any [Write] (a `Vec<u8>`, a socket, a compressing or
+/// encrypting writer, ...).
+///
+/// The output is written sequentially through a buffer, so it is never memory mapped.
+pub fn sort_annotations_to_writer<'a, W: Write + Send>(
+    input: &'a Path,
+    w: W,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    check_threads(threads)?;
+    if let Some(logger) = &options.logger {
+        logger.install();
+    }
+    panic::catch_job(|| sort_file_job(input, OutputTarget::Writer(w), threads, options))
+}
+
+/// Where a file sort job writes to.
+enum OutputTarget<'a, W: Write> {
+    Path(&'a Path),
+    Writer(W),
+}
+
+/// Runs [sort_input] holding the input lock if asked to, and discards the output and
+/// [SortOptions::side_outputs] if the input was written to meanwhile. An input of [STDIO_PATH] is read from stdin.
+fn sort_file_job<'a, W: Write + Send>(
+    input: &'a Path,
+    output: OutputTarget<'a, W>,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    tabix::check_options(options)?;
+    if options.output_compression.is_compressed() {
+        return sort_compressed(input, output, threads, options);
+    }
+    if is_stdio(input) {
+        return sort_stdin(output, threads, options);
+    }
+
+    let _lock = match options.lock_input {
+        true => Some(lock::SharedLock::acquire(input)?),
+        false => None,
+    };
+    let snapshot = lock::InputSnapshot::take(input);
+    let output_path = match &output {
+        OutputTarget::Path(p) => Some(*p),
+        OutputTarget::Writer(_) => None,
+    };
+
+    let ret = match &options.section {
+        Some(markers) => sort_file_section(input, output, threads, options, markers)?,
+        None => sort_input(input, output, threads, options)?,
+    };
+    if let Err(e) = snapshot.map_or(Ok(()), |s| s.verify(input)) {
+        // none of them reflects a single version of the input
+        for path in output_path
+            .into_iter()
+            .chain(options.side_outputs())
+            .filter(|p| p.is_file())
+        {
+            std::fs::remove_file(path).ok();
+        }
+        return Err(e);
+    }
+    Ok(ret)
+}
+
+/// Runs [sort_file_job] writing through a [compress::CompressedWriter], which deflates
+/// BGZF blocks on the job's pool. A path output is staged through [SortOptions::stage_dir]
+/// if set, read back decompressed with [SortOptions::verify] and then indexed if asked.
+fn sort_compressed<'a, W: Write + Send>(
+    input: &'a Path,
+    output: OutputTarget<'a, W>,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    compress::check_options(options)?;
+    let tp = job_pool(threads, options)?;
+    let inner = SortOptions {
+        output_compression: compress::OutputCompression::None,
+        stage_dir: None,
+        index: None,
+        thread_pool: Some(Arc::clone(&tp)),
+        ..options.clone()
+    };
+
+    fn run<'a, O: Write + Send>(
+        input: &'a Path,
+        output: O,
+        threads: usize,
+        options: &SortOptions,
+        compression: compress::OutputCompression,
+    ) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+        let mut out =
+            compress::CompressedWriter::with_pool(output, compression, options.thread_pool.clone())
+                .map_err(|e| GtfSortError::IoError("creating output file", e))?;
+        let writer: Box<dyn Write + Send + '_> = Box::new(&mut out);
+        let mut ret = sort_file_job(input, OutputTarget::Writer(writer), threads, options)?;
+        ret.output_bytes = out.finish().map_err(|e| {
+            GtfSortError::IoError("writing output file", e).in_phase("writing output")
+        })?;
+        Ok(ret)
+    }
+
+    match output {
+        OutputTarget::Path(path) => {
+            let output_label = path.to_str().ok_or(GtfSortError::InvalidOutput(
+                "Invalid output file path".to_string(),
+            ))?;
+            let staged = match &options.stage_dir {
+                Some(dir) => Some(
+                    stage::staging_file(dir)
+                        .map_err(|e| GtfSortError::IoError("creating staged output file", e))?,
+                ),
+                None => None,
+            };
+            let file = std::fs::File::create(staged.as_deref().unwrap_or(path))
+                .map_err(|e| GtfSortError::IoError("creating output file", e))?;
+            let ret = run(
+                input,
+                io::BufWriter::new(file),
+                threads,
+                &inner,
+                options.output_compression,
+            )
+            .inspect_err(|_| {
+                if staged.is_none() && std::fs::metadata(path).is_ok_and(|m| m.is_file()) {
+                    let _ = std::fs::remove_file(path);
+                }
+            })?;
+            if let Some(staged) = staged {
+                if let Some(hash) = stage::publish(staged, path)
+                    .map_err(|e| GtfSortError::IoError("publishing staged output", e))?
+                {
+                    log::info!("Verified staged output copy (fnv1a {:016x})", hash);
+                }
+            }
+
+            let input_label = ret.input;
+            let mut ret = ret.relabel(input_label, output_label);
+            if let Some(verification) = ret.verification.take() {
+                let output = verify::output_counts(Some(path), || verification.output.clone())?;
+                let verification = verify::Verification::new(verification.input, output);
+                ret.verification = Some(verification.clone());
+                verification
+                    .check()
+                    .map_err(|e| e.in_phase("verifying output"))?;
+            }
+            if let Some(format) = options.index {
+                let index = timed("Writing index", None, || tabix::write_index(path, format))
+                    .map_err(|e| e.in_phase("indexing output"))?;
+                log::info!("Wrote {}", index.display());
+            }
+            Ok(ret)
+        }
+        OutputTarget::Writer(_) if options.index.is_some() => Err(GtfSortError::InvalidParameter(
+            "an index is written next to the output file, it cannot be written for a stream",
+        )),
+        OutputTarget::Writer(w) => run(input, w, threads, &inner, options.output_compression),
+    }
+}
+
+/// Sorts stdin, spooled to a file in [SortOptions::tmpdir] first so that it is read like
+/// any input (memory mapped, decompressed if gzipped, ...). Without a format it has no
+/// extension to be guessed from, so it is detected from the contents.
+fn sort_stdin<'a, W: Write + Send>(
+    output: OutputTarget<'a, W>,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    let spooled = temp::TempPath::file(options.tmpdir.as_deref(), "stdin", "")
+        .map_err(|e| GtfSortError::IoError("creating a file in the temporary directory", e))?;
+    let bytes = timed("Reading stdin", None, || {
+        std::fs::File::create(&*spooled).and_then(|mut f| io::copy(&mut io::stdin().lock(), &mut f))
+    })
+    .map_err(|e| GtfSortError::IoError("reading stdin", e))?;
+    log::info!("Read {} bytes from stdin", bytes);
+
+    let output_label = match &output {
+        OutputTarget::Path(p) => p.to_str().unwrap_or_default(),
+        OutputTarget::Writer(_) => "[writer]",
+    };
+    let options = SortOptions {
+        detect_format: options.detect_format || options.format.is_none(),
+        // nobody else writes to the spooled copy
+        lock_input: false,
+        ..options.clone()
+    };
+    let ret = sort_file_job(&spooled, output, threads, &options)?;
+    Ok(ret.relabel(STDIO_PATH, output_label))
+}
+
+/// Sorts the annotation section of `input` (see [SortOptions::section]) with the string
+/// sorter and writes it sequentially between the text around it.
+fn sort_file_section<'a, W: Write + Send>(
+    input: &'a Path,
+    output: OutputTarget<'a, W>,
+    threads: usize,
+    options: &SortOptions,
+    markers: &section::SectionMarkers,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    let input_label = input.to_str().ok_or(GtfSortError::InvalidInput(
+        "Invalid input file path".to_string(),
+    ))?;
+    let gzipped = gzip::is_gzip_file(input)?;
+    if format::is_compressed_extension(input) && !gzipped {
+        return Err(GtfSortError::InvalidInput(format!(
+            "{:?} looks compressed with something other than gzip, which is not supported, please decompress it first",
+            input
+        )));
+    }
+    sniff::check_file(input)?;
+    let contents = gzip::read_to_string(input)?;
+
+    let (format, detection) = match options.format {
+        Some(format) => (format, None),
+        None => {
+            let body = section::split(&contents, markers)?.body;
+            let detection = AnnotationFormat::detect(body);
+            log::info!("Detected {}", detection);
+            let format = detection.or_fallback(input).ok_or_else(|| {
+                GtfSortError::InvalidInput(
+                    "could not detect the format of the annotation section, please set --format"
+                        .to_string(),
+                )
+            })?;
+            (format, Some(detection))
+        }
+    };
+
+    let (output_label, output_path, mut out): (_, _, Box<dyn Write + '_>) = match output {
+        OutputTarget::Path(p) => (
+            p.to_str().ok_or(GtfSortError::InvalidOutput(
+                "Invalid output file path".to_string(),
+            ))?,
+            Some(p),
+            Box::new(io::BufWriter::new(
+                std::fs::File::create(p)
+                    .map_err(|e| GtfSortError::IoError("creating output file", e))?,
+            )),
+        ),
+        OutputTarget::Writer(w) => ("[writer]", None, Box::new(w)),
+    };
+    let mut write = |b: &[u8]| out.write_all(b).map(|_| b.len());
+    let job = match format {
+        AnnotationFormat::Gff3 => sort_string_job::<b'=', _>(
+            &contents,
+            StringOutput::Chunks(&mut write),
+            threads,
+            options,
+        ),
+        AnnotationFormat::Gtf => sort_string_job::<b' ', _>(
+            &contents,
+            StringOutput::Chunks(&mut write),
+            threads,
+            options,
+        ),
+    }
+    .and_then(|job| {
+        out.flush()
+            .map_err(|e| GtfSortError::IoError("writing output file", e))?;
+        Ok(job)
+    })
+    .inspect_err(|_| {
+        if let Some(p) = output_path.filter(|p| p.is_file()) {
+            let _ = std::fs::remove_file(p);
+        }
+    })?;
+
+    let mut ret = job.relabel(input_label, output_label);
+    ret.format_detection = detection;
+    Ok(ret)
+}
+
+fn sort_input<'a, W: Write + Send>(
+    input: &'a Path,
+    output: OutputTarget<'a, W>,
     threads: usize,
+    options: &SortOptions,
 ) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
-    assert!(threads > 0, "Invalid number of threads");
     let mut ret = SortAnnotationsJobResult {
         input: input.to_str().ok_or(GtfSortError::InvalidInput(
             "Invalid input file path".to_string(),
         ))?,
-        output: output.to_str().ok_or(GtfSortError::InvalidOutput(
-            "Invalid output file path".to_string(),
-        ))?,
+        output: match output {
+            OutputTarget::Path(p) => p.to_str().ok_or(GtfSortError::InvalidOutput(
+                "Invalid output file path".to_string(),
+            ))?,
+            OutputTarget::Writer(_) => "[writer]",
+        },
         threads,
         input_mmaped: false,
         output_mmaped: false,
@@ -92,28 +835,126 @@ pub fn sort_annotations<'a>(
         writing_secs: f64::NAN,
         start_mem_mb: None,
         end_mem_mb: None,
+        already_sorted: false,
+        quality: None,
+        resources: None,
+        format: None,
+        format_detection: None,
+        unknown_features: Vec::new(),
+        reused_genes: None,
+        lines: LineCounts::default(),
+        histogram: RecordHistogram::default(),
+        invalid_lines: InvalidLines::default(),
+        malformed_lines: 0,
+        renamed_lines: 0,
+        output_bytes: 0,
+        fallbacks: Vec::new(),
+        verification: None,
     };
 
-    let input_ext = input
-        .extension()
-        .ok_or(GtfSortError::InvalidInput(
-            "Missing input file extension".to_string(),
-        ))?
-        .to_str()
-        .ok_or(GtfSortError::InvalidInput(
-            "Invalid input file extension".to_string(),
-        ))?;
-
-    let tp = rayon::ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build()
-        .expect("Failed to build thread pool");
+    // gzip and bgzip inputs are decompressed while read, see [gzip]
+    let gzipped = gzip::is_gzip_file(input)?;
+    if format::is_compressed_extension(input) && !gzipped {
+        return Err(GtfSortError::InvalidInput(format!(
+            "{:?} looks compressed with something other than gzip, which is not supported, please decompress it first",
+            input
+        )));
+    }
+    sniff::check_file(input)?;
+
+    let unknown_format = || {
+        GtfSortError::InvalidInput(format!(
+            "Unknown input format: {}, please set the format explicitly or let it be detected from the contents",
+            AnnotationFormat::unknown_extension_reason(input)
+        ))
+    };
+    // None: sniffed from the contents once they are read
+    let declared_format = match options.format {
+        Some(format) => Some(format),
+        None if options.detect_format => None,
+        None => Some(AnnotationFormat::from_extension(input).ok_or_else(unknown_format)?),
+    };
+
+    export::check_options(options)?;
+    verify::check_options(options)?;
+    let tp = job_pool(threads, options)?;
+
+    if let (Some(window), true) = (options.mmap_window, gzipped) {
+        log::warn!(
+            "{} a gzip-compressed input cannot be memory-mapped, ignoring the {} byte input window",
+            "Warning:".bright_yellow().bold(),
+            window
+        );
+    } else if let (Some(window), true) = (options.mmap_window, options.no_mmap) {
+        log::warn!(
+            "{} memory maps are disabled, ignoring the {} byte input window",
+            "Warning:".bright_yellow().bold(),
+            window
+        );
+    } else if let Some(window) = options.mmap_window {
+        #[cfg(feature = "mmap")]
+        return panic::Job::current().install(&tp, || {
+            window::sort_windowed(
+                input,
+                output,
+                threads,
+                options,
+                declared_format,
+                ret,
+                window,
+            )
+        });
+
+        #[cfg(not(feature = "mmap"))]
+        log::warn!(
+            "{} built without mmap, ignoring the {} byte input window",
+            "Warning:".bright_yellow().bold(),
+            window
+        );
+    }
+
+    let input_size = std::fs::metadata(input).map_or(0, |m| match gzipped {
+        true => m.len() * gzip::EXPANSION_ESTIMATE,
+        false => m.len(),
+    });
+    let strategy = budget::MemoryStrategy::for_input(input_size, options);
+    if options.low_memory || strategy == Some(budget::MemoryStrategy::Spilled) {
+        if bucketed::supports(options) {
+            return panic::Job::current().install(&tp, || {
+                spill::sort_spilled(input, output, threads, options, declared_format, ret)
+            });
+        } else if options.low_memory {
+            return Err(GtfSortError::InvalidParameter(
+                "low-memory sorting cannot be combined with offset indexes, rejects files, manifests, quality metrics or coordinate-only sorting",
+            ));
+        }
+        log::warn!(
+            "{} not spilling the input to disk, the requested outputs need the whole input",
+            "Warning:".bright_yellow().bold()
+        );
+    }
+
+    if options.compress_lines.applies(input_size, options) {
+        if bucketed::supports(options) {
+            return panic::Job::current().install(&tp, || {
+                packed::sort_packed(input, output, threads, options, declared_format, ret)
+            });
+        } else if options.compress_lines == packed::LineCompression::Always {
+            return Err(GtfSortError::InvalidParameter(
+                "compressed input lines cannot be combined with offset indexes, rejects files, manifests, quality metrics or coordinate-only sorting",
+            ));
+        }
+        log::info!("Not compressing input lines, the requested outputs need the whole input");
+    }
 
-    tp.install(|| {
+    panic::Job::current().install(&tp, || {
         ret.start_mem_mb = Some(max_mem_usage_mb());
 
         log::info!("Using {} threads", threads);
 
+        let watchdog = Watchdog::spawn(options.max_rss_mb, options.timeout);
+        let sampler = ResourceSampler::spawn(options.sample_interval);
+
         #[cfg(feature = "mmap")]
         let f = File::open(input).map_err(|e| GtfSortError::IoError("opening input file", e))?;
 
@@ -123,8 +964,17 @@ pub fn sort_annotations<'a>(
             .map_err(|e| GtfSortError::IoError("getting input file metadata", e))?
             .len();
 
+        #[cfg(feature = "mmap")]
+        let mmap_start = std::time::Instant::now();
         #[cfg(feature = "mmap")]
         let mmap_result = (|| {
+            if gzipped || options.no_mmap {
+                // decompressed or read into memory below instead
+                return Err(GtfSortError::InvalidInput(
+                    "a gzip-compressed input cannot be mapped".to_string(),
+                ));
+            }
+
             #[cfg(feature = "mmap")]
             #[cfg(unix)]
             let contents_map = unsafe {
@@ -158,12 +1008,19 @@ pub fn sort_annotations<'a>(
         #[cfg(feature = "mmap")]
         let contents = match mmap_result.as_ref() {
             Ok(m) => Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(m.as_slice()) }),
+            Err(_) if gzipped || options.no_mmap => Cow::Owned(gzip::read_to_string(input)?),
             Err(e) => {
                 log::warn!(
                     "{} mmap failed, falling back to reading file, error: {}",
                     "Warning:".bright_yellow().bold(),
                     e
                 );
+                ret.fallbacks.push(Fallback {
+                    what: "input memory map",
+                    reason: e.to_string(),
+                    lost_secs: mmap_start.elapsed().as_secs_f64(),
+                    extra_bytes: 0,
+                });
                 std::fs::read_to_string(input)
                     .map_err(|e| GtfSortError::IoError("reading input file", e))
                     .map(Cow::Owned)?
@@ -171,82 +1028,400 @@ pub fn sort_annotations<'a>(
         };
 
         #[cfg(not(feature = "mmap"))]
-        let contents = std::fs::read_to_string(input)
-            .map_err(|e| GtfSortError::IoError("reading input file", e))?;
+        let contents = gzip::read_to_string(input)?;
 
-        let contents_ref = contents.as_ref();
+        let (contents_ref, fasta) = sniff::split_fasta(contents.as_ref());
+        ret.lines.sequence = fasta.lines().count();
 
-        let records = timed("Parsing input", Some(&mut ret.parsing_secs), || {
-            match input_ext {
-                "gff" | "gff3" => parallel_parse::<b'='>(contents_ref),
-                "gtf" => parallel_parse::<b' '>(contents_ref),
-                _ => Err("Unknown file extension, please specify a GTF or GFF3 file"),
+        let format = match declared_format {
+            Some(format) => format,
+            None => {
+                let detection = AnnotationFormat::detect(contents_ref);
+                log::info!("Detected {}", detection);
+                let votes = detection.gtf_votes + detection.gff3_votes;
+                if votes > 0 && detection.confidence() < 0.9 {
+                    log::warn!(
+                        "{} mixed attribute styles, the detected format may be wrong, consider setting --format",
+                        "Warning:".bright_yellow().bold()
+                    );
+                }
+                ret.format_detection = Some(detection);
+                detection.or_fallback(input).ok_or_else(unknown_format)?
             }
-            .map_err(GtfSortError::ParseError)
-        })?;
+        };
+        ret.format = Some(format);
 
-        let index = DashMap::<&str, Layers>::new();
+        let (rewritten, renamed) = match format {
+            AnnotationFormat::Gff3 => rewrite_contents::<b'='>(contents_ref, options)?,
+            AnnotationFormat::Gtf => rewrite_contents::<b' '>(contents_ref, options)?,
+        };
+        let contents_ref = rewritten.as_deref().unwrap_or(contents_ref);
+        ret.renamed_lines = renamed;
 
-        timed("building index", Some(&mut ret.indexing_secs), || {
-            records.par_iter().for_each(|(chrom, lines)| {
-                let mut acc = Layers::default();
+        if options.skip_if_sorted
+            && !options.alters_records()
+            && !options.drop_header
+            && options.manifest.is_none()
+            && options.rejects.is_none()
+            && options.gene_order.is_none()
+            && options.offset_index.is_none()
+            && !options.quality_metrics
+            && !options.verify
+        {
+            let sorted = timed("Checking input order", None, || match format {
+                AnnotationFormat::Gff3 => presorted::is_sorted_with::<b'='>(contents_ref, &options.feature_map),
+                AnnotationFormat::Gtf => presorted::is_sorted_with::<b' '>(contents_ref, &options.feature_map),
+            });
 
-                for line in lines {
-                    match line.feat {
-                        "gene" => {
-                            acc.layer.push(line.outer_layer());
-                        }
-                        "transcript" => {
-                            acc.mapper
-                                .entry(line.gene_id)
-                                .or_default()
-                                .push(line.transcript_id);
-                            acc.helper.entry(line.transcript_id).or_insert(line.line);
-                        }
-                        "CDS" | "exon" | "start_codon" | "stop_codon" => {
-                            let (exon_number, suffix) = line.inner_layer();
-                            acc.inner.entry(line.transcript_id).or_default().insert(
-                                CowNaturalSort::new(format!("{}{}", exon_number, suffix).into()),
-                                vec![line.line],
-                            );
-                        }
-                        _ => {
-                            acc.inner
-                                .entry(line.transcript_id)
-                                .or_default()
-                                .entry(CowNaturalSort::new(line.feat.into()))
-                                .and_modify(|e| {
-                                    e.push(line.line);
-                                })
-                                .or_insert(vec![line.line]);
+            if sorted {
+                log::info!("Input is already sorted, copying it through");
+
+                match output {
+                    OutputTarget::Path(output) => {
+                        let same_file = matches!(
+                            (input.canonicalize(), output.canonicalize()),
+                            (Ok(a), Ok(b)) if a == b
+                        );
+                        if !same_file {
+                            std::fs::write(output, contents_ref)
+                                .map_err(|e| GtfSortError::IoError("copying sorted input", e))?;
                         }
                     }
+                    OutputTarget::Writer(mut w) => w
+                        .write_all(contents_ref.as_bytes())
+                        .and_then(|_| w.flush())
+                        .map_err(|e| GtfSortError::IoError("copying sorted input", e))?,
                 }
 
-                acc.layer.par_sort_unstable_by_key(|x| x.0);
-                index.insert(chrom, acc);
-            })
+                ret.already_sorted = true;
+                ret.output_bytes = contents_ref.len() as u64;
+                ret.end_mem_mb = Some(max_mem_usage_mb());
+                ret.resources = sampler.finish();
+                progress::report_done(options.progress.as_ref());
+                return Ok(ret);
+            }
+        }
+
+        if options.coordinate_only {
+            let output_path = match &output {
+                OutputTarget::Path(p) => Some(*p),
+                OutputTarget::Writer(_) => None,
+            };
+            match output {
+                OutputTarget::Path(p) => std::fs::File::create(p)
+                    .map_err(|e| GtfSortError::IoError("creating output file", e))
+                    .and_then(|f| sort_coordinates(contents_ref, f, &tp, options, &mut ret, &sampler, &watchdog)),
+                OutputTarget::Writer(w) => {
+                    sort_coordinates(contents_ref, w, &tp, options, &mut ret, &sampler, &watchdog)
+                }
+            }
+            .inspect_err(|_| {
+                if let Some(p) = output_path.filter(|p| std::fs::metadata(p).is_ok_and(|m| m.is_file())) {
+                    let _ = std::fs::remove_file(p);
+                }
+            })?;
+
+            drop(contents);
+            #[cfg(feature = "mmap")]
+            if let Ok(m) = mmap_result {
+                m.close()
+                    .map_err(|e| GtfSortError::IoError("syncing memory map", e))?;
+            }
+
+            ret.end_mem_mb = Some(max_mem_usage_mb());
+            ret.resources = sampler.finish();
+            progress::report_done(options.progress.as_ref());
+            return Ok(ret);
+        }
+
+        let chroms = options.chrom_filter();
+        let parse_progress = PhaseProgress::begin_bytes(
+            options.progress.as_ref(),
+            Phase::Parse,
+            1,
+            Some(contents_ref.len() as u64),
+        );
+        let _ctx = panic::enter("parsing", None);
+        let (mut records, mut lines) = timed("Parsing input", Some(&mut ret.parsing_secs), || {
+            match format {
+                AnnotationFormat::Gff3 => parallel_parse_tracked::<b'='>(
+                    contents_ref,
+                    &watchdog,
+                    chroms,
+                    &parse_progress,
+                ),
+                AnnotationFormat::Gtf => parallel_parse_tracked::<b' '>(
+                    contents_ref,
+                    &watchdog,
+                    chroms,
+                    &parse_progress,
+                ),
+            }
+            .map_err(|e| GtfSortError::ParseError(e).in_phase("parsing"))
+        })?;
+        parse_progress.finish();
+        lines.drop_duplicates(&mut records, options.dedup);
+        ret.histogram = RecordHistogram::from_records(&records);
+        ret.histogram.order_chroms(options);
+        let lines = ret.lines.merge(lines);
+        lines.log();
+        ret.lines = lines;
+        match format {
+            AnnotationFormat::Gff3 => check_invalid_lines::<b'='>(contents_ref, options, &mut ret)?,
+            AnnotationFormat::Gtf => check_invalid_lines::<b' '>(contents_ref, options, &mut ret)?,
+        }
+        if let Some(chroms) = chroms {
+            chroms.warn_missing(records.keys().copied());
+        }
+
+        if let Some(path) = &options.offset_index {
+            write_offset_index(path, contents_ref, &records)?;
+        }
+
+        watchdog.check()?;
+
+        let fingerprint = manifest::fingerprint(format, options);
+        let hashes = (options.manifest.is_some() || options.incremental.is_some())
+            .then(|| timed("Hashing genes", None, || manifest::input_hashes(&records, &options.layer_map)));
+        let previous = match &options.incremental {
+            Some(path) => load_previous_output(path, fingerprint, options)?,
+            None => None,
+        };
+        let reuse = previous.as_ref().map(|(m, previous)| {
+            let reuse = m.reusable(hashes.as_ref().unwrap(), previous);
+            log::info!(
+                "Reusing {} of {} genes from the previous output",
+                reuse.len(),
+                hashes.as_ref().unwrap().len()
+            );
+            reuse
         });
+        ret.reused_genes = options
+            .incremental
+            .as_ref()
+            .map(|_| reuse.as_ref().map_or(0, |r| r.len()));
+
+        sampler.enter(Phase::Index);
+        let _ctx = panic::enter("indexing", None);
+        let (mut index, unknown_features) = timed(
+            "Building index",
+            Some(&mut ret.indexing_secs),
+            || match format {
+                AnnotationFormat::Gff3 => {
+                    build_index::<b'='>(&records, options, reuse.as_ref(), &watchdog)
+                }
+                AnnotationFormat::Gtf => {
+                    build_index::<b' '>(&records, options, reuse.as_ref(), &watchdog)
+                }
+            },
+        )
+        .map_err(|e| e.in_phase("indexing"))?;
+        warn_unknown_features(&unknown_features);
+        ret.unknown_features = unknown_features;
+        if let Some(path) = &options.rejects {
+            match format {
+                AnnotationFormat::Gff3 => {
+                    write_rejects::<b'='>(path, contents_ref, &records, &index, options)
+                }
+                AnnotationFormat::Gtf => {
+                    write_rejects::<b' '>(path, contents_ref, &records, &index, options)
+                }
+            }?;
+        }
+        validate_output(&mut index, Some(contents_ref), options, &mut ret)?;
 
-        let mut keys: Vec<&str> = index.iter().map(|x| *x.key()).collect();
-        keys.sort_by(|a, b| natord::compare(a, b));
+        let mut keys: Vec<&str> = index.keys().copied().collect();
+        sort_chroms(&mut keys, options)?;
 
+        if options.quality_metrics {
+            ret.quality = Some(quality_metrics(&index, &keys));
+        }
+
+        watchdog.check()?;
+
+        sampler.enter(Phase::Write);
+        let _ctx = panic::enter("writing", None);
+        let sizes = keys
+            .iter()
+            .map(|chr| (*chr, index.get(chr).unwrap().count_line_size_with(options.newline)))
+            .collect::<Vec<_>>();
+        let header = job_header(contents_ref, options);
+        let output_size = sizes.iter().map(|(_, n)| *n as u64).sum::<u64>()
+            + header.size_with(options.newline) as u64;
+        // exports are not counted, their size is not known ahead
+        let write_progress = PhaseProgress::begin_bytes(
+            options.progress.as_ref(),
+            Phase::Write,
+            keys.len(),
+            options.export.is_none().then_some(output_size),
+        );
+        let output_path = match &output {
+            OutputTarget::Path(p) => Some(*p),
+            OutputTarget::Writer(_) => None,
+        };
+        let staged = match (&output, &options.stage_dir) {
+            (OutputTarget::Path(_), Some(dir)) => Some(
+                stage::staging_file(dir)
+                    .map_err(|e| GtfSortError::IoError("creating staged output file", e))?,
+            ),
+            _ => None,
+        };
+        let mut extra = export::ExtraOutputs::create(options, format)?;
         let mut writing_secs = 0.0;
-        timed("Writing output", Some(&mut writing_secs), || {
-            write_obj(
-                output,
-                &index,
-                keys.iter()
-                    .map(|chr| (*chr, index.get(chr).unwrap().count_line_size()))
-                    .collect::<Vec<_>>(),
-                &mut Some(&mut ret),
-            )
-        })
-        .map_err(|e| GtfSortError::IoError("writing output file", e))?;
+        let (written, extra_written) = std::thread::scope(|s| {
+            let extra_job = (!extra.is_empty()).then(|| {
+                s.spawn(|| {
+                    timed("Writing extra outputs", None, || {
+                        extra.write_index(&index, &keys)
+                    })
+                })
+            });
+            let written = timed("Writing output", Some(&mut writing_secs), || match output {
+            OutputTarget::Path(output) => match options.export {
+                Some(export) => std::fs::File::create(staged.as_deref().unwrap_or(output)).and_then(|f| {
+                    export::export(
+                        &mut *export.exporter(format),
+                        io::BufWriter::new(f),
+                        &index,
+                        &keys,
+                        options.newline,
+                    )
+                }),
+                None if options.no_mmap => std::fs::File::create(staged.as_deref().unwrap_or(output))
+                    .and_then(|f| {
+                        write_obj_sequential(
+                            f,
+                            &index,
+                            sizes,
+                            &header,
+                            &mut Some(&mut ret),
+                            options.newline,
+                            &write_progress,
+                            &watchdog,
+                        )
+                    }),
+                None => write_obj(
+                    staged.as_deref().unwrap_or(output),
+                    &index,
+                    sizes,
+                    &header,
+                    &mut Some(&mut ret),
+                    options.newline,
+                    &write_progress,
+                    &watchdog,
+                ),
+            }
+            .inspect_err(|_| {
+                // do not leave a truncated output behind, but never unlink
+                // devices or pipes such as /dev/stdout
+                if staged.is_none() && std::fs::metadata(output).is_ok_and(|m| m.is_file()) {
+                    let _ = std::fs::remove_file(output);
+                }
+            }),
+            OutputTarget::Writer(w) => match options.export {
+                Some(export) => export::export(
+                    &mut *export.exporter(format),
+                    w,
+                    &index,
+                    &keys,
+                    options.newline,
+                ),
+                None => write_obj_sequential(
+                    w,
+                    &index,
+                    sizes,
+                    &header,
+                    &mut None,
+                    options.newline,
+                    &write_progress,
+                    &watchdog,
+                ),
+            },
+            });
+            let extra_written = extra_job.map_or(Ok(()), |job| {
+                job.join().unwrap_or_else(|p| std::panic::resume_unwind(p))
+            });
+            (written, extra_written)
+        });
+        written.map_err(|e| {
+            watchdog
+                .check()
+                .err()
+                .unwrap_or(GtfSortError::IoError("writing output file", e))
+                .in_phase("writing output")
+        })?;
+        extra_written.map_err(|e| e.in_phase("writing output"))?;
+        ret.output_bytes = match (options.export, staged.as_deref().or(output_path)) {
+            (Some(_), Some(p)) => std::fs::metadata(p).map_or(0, |m| m.len()),
+            (Some(_), None) => 0,
+            (None, _) => output_size,
+        };
+        if let (Some(staged), Some(output)) = (staged, output_path) {
+            // devices and pipes are always copied to, only a failed rename is a fallback
+            let to_file = std::fs::metadata(output).map_or(true, |m| m.is_file());
+            let mut publishing_secs = 0.0;
+            let copied = timed("Publishing staged output", Some(&mut publishing_secs), || {
+                stage::publish(staged, output)
+            })
+            .map_err(|e| GtfSortError::IoError("publishing staged output", e))?;
+            if let Some(hash) = copied {
+                log::info!("Verified staged output copy (fnv1a {:016x})", hash);
+                if to_file {
+                    log::warn!(
+                        "{} the staging directory is on another filesystem than the output, the output was copied instead of renamed ({:.2}s)",
+                        "Warning:".bright_yellow().bold(),
+                        publishing_secs
+                    );
+                    ret.fallbacks.push(Fallback {
+                        what: "staged output rename",
+                        reason: "crosses filesystems".to_string(),
+                        lost_secs: publishing_secs,
+                        extra_bytes: ret.output_bytes,
+                    });
+                }
+            }
+            writing_secs += publishing_secs;
+        }
         ret.writing_secs = writing_secs;
+        write_progress.finish();
+        extra.finish()?;
+
+        if options.verify {
+            let output = verify::output_counts(output_path, || {
+                verify::FeatureCounts::from_index(&index)
+            })?;
+            let verification =
+                verify::Verification::new(verify::FeatureCounts::from_records(&records), output);
+            ret.verification = Some(verification.clone());
+            verification.check().map_err(|e| e.in_phase("verifying output"))?;
+        }
+
+        if let Some(path) = &options.gene_order {
+            write_gene_order(path, &index, &keys)?;
+        }
+
+        if let (Some(path), Some(hashes)) = (&options.manifest, &hashes) {
+            timed("Writing manifest", None, || {
+                // absolute, so that later runs may start elsewhere
+                let output_path = output_path.map(|p| p.canonicalize().unwrap_or(p.into()));
+                manifest::Manifest::from_index(
+                    fingerprint,
+                    output_path.as_deref(),
+                    &index,
+                    &keys,
+                    hashes,
+                    header.size_with(options.newline) as u64,
+                    options.newline,
+                )
+                .write_to(path)
+            })
+            .map_err(|e| GtfSortError::IoError("writing manifest", e))?;
+        }
 
         drop(records);
         drop(index);
+        drop(reuse);
+        drop(hashes);
 
         #[cfg(feature = "mmap")]
         if let Ok(m) = mmap_result {
@@ -255,6 +1430,8 @@ pub fn sort_annotations<'a>(
         }
 
         ret.end_mem_mb = Some(max_mem_usage_mb());
+        ret.resources = sampler.finish();
+        progress::report_done(options.progress.as_ref());
 
         Ok(ret)
     })
@@ -265,11 +1442,346 @@ pub fn sort_annotations_string<'a, const SEP: u8, OF: FnMut(&[u8]) -> io::Result
     output: &mut OF,
     threads: usize,
 ) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
-    assert!(threads > 0, "Invalid number of threads");
-    let mut ret = SortAnnotationsJobResult {
-        input: "[string]",
-        output: "[callback]",
-        threads,
+    sort_annotations_string_with_options::<SEP, OF>(input, output, threads, &SortOptions::default())
+}
+
+pub fn sort_annotations_string_with_options<
+    'a,
+    const SEP: u8,
+    OF: FnMut(&[u8]) -> io::Result<usize>,
+>(
+    input: &'a str,
+    output: &mut OF,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    check_threads(threads)?;
+    if let Some(logger) = &options.logger {
+        logger.install();
+    }
+    panic::catch_job(|| {
+        sort_string_job::<SEP, OF>(input, StringOutput::Chunks(output), threads, options)
+    })
+}
+
+/// One transcript of the sorted output: the transcript line followed by its children
+/// (exons, CDS, UTRs, ...), each terminated by [SortOptions::newline].
+#[derive(Debug, Clone, Copy)]
+pub struct TranscriptBlock<'b> {
+    pub chrom: &'b str,
+    pub gene_id: &'b str,
+    pub transcript_id: &'b str,
+    /// The line of the parent gene, without its line terminator.
+    pub gene_line: &'b str,
+    pub lines: &'b [u8],
+}
+
+/// Same as [sort_annotations_string_with_options], but hands the sorted output to
+/// `on_transcript` one complete transcript at a time, in output order, so consumers can
+/// process transcripts as units without re-parsing. Gene lines are not part of any block
+/// (see [TranscriptBlock::gene_line]), genes without transcripts are not reported.
+///
+/// [SortOptions::skip_if_sorted] is ignored, sorted input is split into blocks as well.
+pub fn sort_annotations_string_by_transcript<
+    'a,
+    const SEP: u8,
+    TF: FnMut(&TranscriptBlock) -> io::Result<()>,
+>(
+    input: &'a str,
+    on_transcript: &mut TF,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    check_threads(threads)?;
+    if let Some(logger) = &options.logger {
+        logger.install();
+    }
+    panic::catch_job(|| {
+        sort_string_job::<SEP, fn(&[u8]) -> io::Result<usize>>(
+            input,
+            StringOutput::Transcripts(on_transcript),
+            threads,
+            options,
+        )
+    })
+}
+
+/// One gene of the sorted output: the gene line followed by its transcripts and their
+/// children, each terminated by [SortOptions::newline].
+#[derive(Debug, Clone, Copy)]
+pub struct GeneBlock<'b> {
+    pub chrom: &'b str,
+    pub gene_id: &'b str,
+    pub lines: &'b [u8],
+}
+
+/// Same as [sort_annotations_string_with_options], but hands the sorted output to
+/// `on_gene` one complete gene at a time, in output order. Unlike
+/// [sort_annotations_string_by_transcript] every record is part of a block, only the
+/// leading comments of the input are not written.
+///
+/// [SortOptions::skip_if_sorted] is ignored, sorted input is split into blocks as well.
+pub fn sort_annotations_string_by_gene<
+    'a,
+    const SEP: u8,
+    GF: FnMut(&GeneBlock) -> io::Result<()>,
+>(
+    input: &'a str,
+    on_gene: &mut GF,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    check_threads(threads)?;
+    if let Some(logger) = &options.logger {
+        logger.install();
+    }
+    panic::catch_job(|| {
+        sort_string_job::<SEP, fn(&[u8]) -> io::Result<usize>>(
+            input,
+            StringOutput::Genes(on_gene),
+            threads,
+            options,
+        )
+    })
+}
+
+/// Sorted output of one input of [sort_many].
+pub struct SortedString<'a> {
+    pub output: Vec<u8>,
+    pub job: SortAnnotationsJobResult<'a>,
+}
+
+/// Sorts many small in-memory annotations concurrently on one pool of `threads` workers,
+/// returning one result per input, in input order.
+///
+/// Building a pool per call dominates the runtime of small inputs (e.g. per-gene
+/// fragments from an assembler), here it is built once and shared by every job.
+pub fn sort_many<'a, const SEP: u8, I: IntoIterator<Item = &'a str>>(
+    inputs: I,
+    threads: usize,
+) -> Result<Vec<Result<SortedString<'a>, GtfSortError>>, GtfSortError> {
+    sort_many_with_options::<SEP, I>(inputs, threads, &SortOptions::default())
+}
+
+/// Same as [sort_many], with options applied to every job. Jobs run on
+/// [SortOptions::thread_pool] if set.
+pub fn sort_many_with_options<'a, const SEP: u8, I: IntoIterator<Item = &'a str>>(
+    inputs: I,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<Vec<Result<SortedString<'a>, GtfSortError>>, GtfSortError> {
+    check_threads(threads)?;
+    let inputs = inputs.into_iter().collect::<Vec<_>>();
+    let tp = job_pool(threads, options)?;
+    let options = SortOptions {
+        thread_pool: Some(Arc::clone(&tp)),
+        ..options.clone()
+    };
+
+    Ok(panic::Job::current().install(&tp, || {
+        inputs
+            .into_par_iter()
+            .map(|input| {
+                let mut output = Vec::new();
+                let job = sort_annotations_string_with_options::<SEP, _>(
+                    input,
+                    &mut |b| {
+                        output.extend_from_slice(b);
+                        Ok(b.len())
+                    },
+                    threads,
+                    &options,
+                )?;
+                Ok(SortedString { output, job })
+            })
+            .collect()
+    }))
+}
+
+/// Groups records parsed with [parallel_parse] into per-chromosome [Layers], applying
+/// the record selection of `options` (genes, transcripts, synthesized parents, ...).
+///
+/// Together with [combine_index] and [write_index_with_options], this sorts input the caller splits
+/// itself, e.g. several files or chunks indexed separately and written once. Runs on the
+/// current rayon pool.
+pub fn index_records<'a, const SEP: u8>(
+    records: &ChromRecord<'a>,
+    options: &SortOptions,
+) -> Result<Index<'a>, GtfSortError> {
+    let (index, unknown_features) =
+        build_index::<SEP>(records, options, None, &Watchdog::disabled())?;
+    warn_unknown_features(&unknown_features);
+    Ok(index)
+}
+
+/// Sorts the chromosomes of the output by [SortOptions::chrom_order] if set, or else by
+/// [SortOptions::chrom_sort].
+pub(crate) fn sort_chroms(keys: &mut [&str], options: &SortOptions) -> Result<(), GtfSortError> {
+    match (&options.chrom_order, options.chrom_sort) {
+        (Some(order), _) => order.sort(keys),
+        (None, chrom_order::ChromSortOrder::Custom) => Err(GtfSortError::InvalidParameter(
+            "a custom chromosome order needs a chromosome order list",
+        )),
+        (None, preset) => {
+            preset.sort(keys);
+            Ok(())
+        }
+    }
+}
+
+/// Writes `index` as sorted output, chromosomes in natural order.
+pub fn write_index<W: Write>(output: W, index: &Index, newline: format::Newline) -> io::Result<()> {
+    let options = SortOptions {
+        newline,
+        ..Default::default()
+    };
+    write_index_with_options(output, index, &options).map_err(|e| match e {
+        GtfSortError::IoError(_, e) => e,
+        e => io::Error::other(e.to_string()),
+    })
+}
+
+/// Writes `index` as sorted output, chromosomes ordered as [sort_chroms] orders them
+/// under `options`, lines terminated by [SortOptions::newline].
+pub fn write_index_with_options<W: Write>(
+    output: W,
+    index: &Index,
+    options: &SortOptions,
+) -> Result<(), GtfSortError> {
+    let mut keys: Vec<&str> = index.keys().copied().collect();
+    sort_chroms(&mut keys, options)?;
+    let keys = keys.into_iter().map(|k| (k, 0)).collect();
+
+    write_obj_sequential(
+        output,
+        index,
+        keys,
+        &Header::default(),
+        &mut None,
+        options.newline,
+        &PhaseProgress::disabled(),
+        &Watchdog::disabled(),
+    )
+    .map_err(|e| GtfSortError::IoError("writing output", e))
+}
+
+/// Where [sort_string_job] writes to.
+enum StringOutput<'o, OF> {
+    /// Byte chunks of the sorted output, see [ChunkWriter].
+    Chunks(&'o mut OF),
+    /// One call per transcript, see [sort_annotations_string_by_transcript].
+    Transcripts(&'o mut dyn FnMut(&TranscriptBlock) -> io::Result<()>),
+    /// One call per gene, see [sort_annotations_string_by_gene].
+    Genes(&'o mut dyn FnMut(&GeneBlock) -> io::Result<()>),
+    /// Kept in memory, see [sort_annotations_iter].
+    Sorted(&'o mut SortedAnnotations),
+}
+
+/// Sorts the annotation section of `input` (see [SortOptions::section]), writing the text
+/// before and after it unchanged around the sorted section.
+fn sort_string_section<'a, const SEP: u8, OF: FnMut(&[u8]) -> io::Result<usize>>(
+    input: &'a str,
+    output: StringOutput<'_, OF>,
+    threads: usize,
+    options: &SortOptions,
+    markers: &section::SectionMarkers,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    let StringOutput::Chunks(output) = output else {
+        return Err(GtfSortError::InvalidParameter(
+            "the text around an annotation section has no transcript blocks",
+        ));
+    };
+    if options.export.is_some() {
+        return Err(GtfSortError::InvalidParameter(
+            "an export format cannot be written between the text around an annotation section",
+        ));
+    }
+
+    let sections = section::split(input, markers)?;
+    log::info!(
+        "Sorting the annotation section ({} bytes), {} lines before and {} after it are copied through",
+        sections.body.len(),
+        sections.head.lines().count(),
+        sections.tail.lines().count()
+    );
+
+    ChunkWriter::new(output)
+        .write_all(sections.head.as_bytes())
+        .map_err(|e| GtfSortError::IoError("writing the text before the section", e))?;
+    let inner = SortOptions {
+        section: None,
+        ..options.clone()
+    };
+    let head_lines = sections.head.lines().count();
+    let mut ret =
+        sort_string_job::<SEP, OF>(sections.body, StringOutput::Chunks(output), threads, &inner)
+            .map_err(|e| e.shift_location(head_lines, sections.head.len()))?;
+    ret.invalid_lines.shift(head_lines);
+    ChunkWriter::new(output)
+        .write_all(sections.tail.as_bytes())
+        .map_err(|e| GtfSortError::IoError("writing the text after the section", e))?;
+
+    ret.lines.surrounding = sections.surrounding_lines();
+    Ok(ret)
+}
+
+/// Runs [sort_string_job] handing the output to `output` compressed, see
+/// [sort_compressed].
+fn sort_string_compressed<'a, const SEP: u8, OF: FnMut(&[u8]) -> io::Result<usize>>(
+    input: &'a str,
+    output: &mut OF,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    compress::check_options(options)?;
+    let tp = job_pool(threads, options)?;
+    let inner = SortOptions {
+        output_compression: compress::OutputCompression::None,
+        thread_pool: Some(Arc::clone(&tp)),
+        ..options.clone()
+    };
+
+    let mut out = compress::CompressedWriter::with_pool(
+        ChunkWriter::new(output),
+        options.output_compression,
+        Some(tp),
+    )
+    .map_err(|e| GtfSortError::IoError("writing output file", e))?;
+    let mut write: &mut dyn FnMut(&[u8]) -> io::Result<usize> =
+        &mut |b: &[u8]| out.write_all(b).map(|_| b.len());
+    let mut ret =
+        sort_string_job::<SEP, _>(input, StringOutput::Chunks(&mut write), threads, &inner)?;
+    ret.output_bytes = out
+        .finish()
+        .map_err(|e| GtfSortError::IoError("writing output file", e).in_phase("writing output"))?;
+    Ok(ret)
+}
+
+fn sort_string_job<'a, const SEP: u8, OF: FnMut(&[u8]) -> io::Result<usize>>(
+    input: &'a str,
+    output: StringOutput<'_, OF>,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    if options.index.is_some() {
+        return Err(GtfSortError::InvalidParameter(
+            "an index is written next to the output file, it cannot be written for a string job",
+        ));
+    }
+    let mut output = match output {
+        StringOutput::Chunks(output) if options.output_compression.is_compressed() => {
+            return sort_string_compressed::<SEP, OF>(input, output, threads, options);
+        }
+        output => output,
+    };
+    if let Some(markers) = &options.section {
+        return sort_string_section::<SEP, OF>(input, output, threads, options, markers);
+    }
+
+    let mut ret = SortAnnotationsJobResult {
+        input: "[string]",
+        output: "[callback]",
+        threads,
         input_mmaped: false,
         output_mmaped: false,
         parsing_secs: f64::NAN,
@@ -277,80 +1789,1839 @@ pub fn sort_annotations_string<'a, const SEP: u8, OF: FnMut(&[u8]) -> io::Result
         writing_secs: f64::NAN,
         start_mem_mb: None,
         end_mem_mb: None,
+        already_sorted: false,
+        quality: None,
+        resources: None,
+        format: Some(match SEP {
+            b'=' => AnnotationFormat::Gff3,
+            _ => AnnotationFormat::Gtf,
+        }),
+        format_detection: None,
+        unknown_features: Vec::new(),
+        reused_genes: None,
+        lines: LineCounts::default(),
+        histogram: RecordHistogram::default(),
+        invalid_lines: InvalidLines::default(),
+        malformed_lines: 0,
+        renamed_lines: 0,
+        output_bytes: 0,
+        fallbacks: Vec::new(),
+        verification: None,
     };
 
-    let tp = rayon::ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build()
-        .expect("Failed to build thread pool");
+    sniff::check_text(&input.as_bytes()[..input.len().min(sniff::SNIFF_BYTES)])?;
+    let (input, fasta) = sniff::split_fasta(input);
+    ret.lines.sequence = fasta.lines().count();
+
+    export::check_options(options)?;
+    verify::check_options(options)?;
+    if options.export.is_some() && !matches!(output, StringOutput::Chunks(_)) {
+        return Err(GtfSortError::InvalidParameter(
+            "an export format has no transcript blocks",
+        ));
+    }
+    let tp = job_pool(threads, options)?;
+
+    let watchdog = Watchdog::spawn(options.max_rss_mb, options.timeout);
+    let sampler = ResourceSampler::spawn(options.sample_interval);
+
+    if let (true, StringOutput::Chunks(output)) = (
+        options.skip_if_sorted
+            && !options.alters_records()
+            && !options.drop_header
+            && options.rejects.is_none()
+            && options.gene_order.is_none()
+            && options.offset_index.is_none()
+            && !options.quality_metrics
+            && !options.verify
+            && timed("Checking input order", None, || {
+                presorted::is_sorted_with::<SEP>(input, &options.feature_map)
+            }),
+        &mut output,
+    ) {
+        log::info!("Input is already sorted, copying it through");
+
+        ChunkWriter::new(*output)
+            .write_all(input.as_bytes())
+            .map_err(|e| GtfSortError::IoError("copying sorted input", e))?;
+
+        ret.already_sorted = true;
+        ret.start_mem_mb = Some(max_mem_usage_mb());
+        ret.end_mem_mb = ret.start_mem_mb;
+        ret.resources = sampler.finish();
+        progress::report_done(options.progress.as_ref());
+        return Ok(ret);
+    }
+
+    let (rewritten, renamed) = rewrite_contents::<SEP>(input, options)?;
+    let input = rewritten.as_deref().unwrap_or(input);
+    ret.renamed_lines = renamed;
+
+    if options.coordinate_only {
+        ret.start_mem_mb = Some(max_mem_usage_mb());
+        match output {
+            StringOutput::Chunks(output) => sort_coordinates(
+                input,
+                ChunkWriter::new(output),
+                &tp,
+                options,
+                &mut ret,
+                &sampler,
+                &watchdog,
+            ),
+            StringOutput::Transcripts(_) | StringOutput::Genes(_) | StringOutput::Sorted(_) => Err(
+                GtfSortError::InvalidParameter("coordinate-only sorting has no transcript blocks"),
+            ),
+        }?;
+
+        ret.end_mem_mb = Some(max_mem_usage_mb());
+        ret.resources = sampler.finish();
+        progress::report_done(options.progress.as_ref());
+        return Ok(ret);
+    }
 
-    let index = DashMap::<&str, Layers>::new();
-    let keys = tp.install(|| {
+    let (index, keys, input_counts) = panic::Job::current().install(&tp, || {
         ret.start_mem_mb = Some(max_mem_usage_mb());
 
-        let records = timed("Parsing input", Some(&mut ret.parsing_secs), || {
-            parallel_parse::<SEP>(input).map_err(GtfSortError::ParseError)
+        let chroms = options.chrom_filter();
+        let parse_progress = PhaseProgress::begin_bytes(
+            options.progress.as_ref(),
+            Phase::Parse,
+            1,
+            Some(input.len() as u64),
+        );
+        let _ctx = panic::enter("parsing", None);
+        let (mut records, mut lines) = timed("Parsing input", Some(&mut ret.parsing_secs), || {
+            parallel_parse_tracked::<SEP>(input, &watchdog, chroms, &parse_progress)
+                .map_err(|e| GtfSortError::ParseError(e).in_phase("parsing"))
         })?;
+        parse_progress.finish();
+        lines.drop_duplicates(&mut records, options.dedup);
+        ret.histogram = RecordHistogram::from_records(&records);
+        ret.histogram.order_chroms(options);
+        let lines = ret.lines.merge(lines);
+        lines.log();
+        ret.lines = lines;
+        check_invalid_lines::<SEP>(input, options, &mut ret)?;
+        if let Some(chroms) = chroms {
+            chroms.warn_missing(records.keys().copied());
+        }
 
-        timed("Building index", Some(&mut ret.indexing_secs), || {
-            records.par_iter().for_each(|(chrom, lines)| {
-                let mut acc = Layers::default();
+        if let Some(path) = &options.offset_index {
+            write_offset_index(path, input, &records)?;
+        }
 
-                for line in lines {
-                    match line.feat {
-                        "gene" => {
-                            acc.layer.push(line.outer_layer());
-                        }
-                        "transcript" => {
-                            acc.mapper
-                                .entry(line.gene_id)
-                                .or_default()
-                                .push(line.transcript_id);
-                            acc.helper.entry(line.transcript_id).or_insert(line.line);
-                        }
-                        "CDS" | "exon" | "start_codon" | "stop_codon" => {
-                            let (exon_number, suffix) = line.inner_layer();
-                            acc.inner.entry(line.transcript_id).or_default().insert(
-                                CowNaturalSort::new(format!("{}{}", exon_number, suffix).into()),
-                                vec![line.line],
-                            );
+        watchdog.check()?;
+
+        sampler.enter(Phase::Index);
+        let _ctx = panic::enter("indexing", None);
+        let (mut index, unknown_features) =
+            timed("Building index", Some(&mut ret.indexing_secs), || {
+                build_index::<SEP>(&records, options, None, &watchdog)
+            })
+            .map_err(|e| e.in_phase("indexing"))?;
+        warn_unknown_features(&unknown_features);
+        ret.unknown_features = unknown_features;
+        if let Some(path) = &options.rejects {
+            write_rejects::<SEP>(path, input, &records, &index, options)?;
+        }
+        validate_output(&mut index, Some(input), options, &mut ret)?;
+
+        let mut keys: Vec<&str> = index.keys().copied().collect();
+        sort_chroms(&mut keys, options)?;
+
+        if options.quality_metrics {
+            ret.quality = Some(quality_metrics(&index, &keys));
+        }
+
+        let input_counts = options
+            .verify
+            .then(|| verify::FeatureCounts::from_records(&records));
+        Ok::<_, GtfSortError>((index, keys, input_counts))
+    })?;
+
+    watchdog.check()?;
+
+    sampler.enter(Phase::Write);
+    let _ctx = panic::enter("writing", None);
+    let header = job_header(input, options);
+    // only the sorted text written in chunks has a size known ahead
+    let sizes =
+        (matches!(output, StringOutput::Chunks(_)) && options.export.is_none()).then(|| {
+            keys.iter()
+                .map(|chr| {
+                    (
+                        *chr,
+                        index
+                            .get(chr)
+                            .unwrap()
+                            .count_line_size_with(options.newline),
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+    let output_size = sizes.as_ref().map(|sizes| {
+        sizes.iter().map(|(_, n)| *n as u64).sum::<u64>() + header.size_with(options.newline) as u64
+    });
+    let write_progress = PhaseProgress::begin_bytes(
+        options.progress.as_ref(),
+        Phase::Write,
+        keys.len(),
+        output_size,
+    );
+    let mut extra = export::ExtraOutputs::create(options, ret.format.unwrap())?;
+    let (written, extra_written) = std::thread::scope(|s| {
+        let extra_job = (!extra.is_empty()).then(|| {
+            s.spawn(|| {
+                timed("Writing extra outputs", None, || {
+                    extra.write_index(&index, &keys)
+                })
+            })
+        });
+        let written = match output {
+            StringOutput::Chunks(output) if options.export.is_some() => export::export(
+                &mut *options.export.unwrap().exporter(ret.format.unwrap()),
+                ChunkWriter::new(output),
+                &index,
+                &keys,
+                options.newline,
+            ),
+            StringOutput::Chunks(output) => write_obj_sequential(
+                &mut ChunkWriter::new(output),
+                &index,
+                sizes.unwrap(),
+                &header,
+                &mut None,
+                options.newline,
+                &write_progress,
+                &watchdog,
+            ),
+            StringOutput::Transcripts(on_transcript) => write_transcripts(
+                &index,
+                &keys,
+                on_transcript,
+                options.newline,
+                &write_progress,
+                &watchdog,
+            ),
+            StringOutput::Genes(on_gene) => write_genes(
+                &index,
+                &keys,
+                on_gene,
+                options.newline,
+                &write_progress,
+                &watchdog,
+            ),
+            StringOutput::Sorted(sorted) => {
+                sorted.push_index(&index, &keys, &header, options.newline);
+                Ok(())
+            }
+        };
+        let extra_written = extra_job.map_or(Ok(()), |job| {
+            job.join().unwrap_or_else(|p| std::panic::resume_unwind(p))
+        });
+        (written, extra_written)
+    });
+    written.map_err(|e| {
+        watchdog
+            .check()
+            .err()
+            .unwrap_or(GtfSortError::IoError("writing output file", e))
+            .in_phase("writing output")
+    })?;
+    extra_written.map_err(|e| e.in_phase("writing output"))?;
+    write_progress.finish();
+    extra.finish()?;
+
+    if let Some(input) = input_counts {
+        // the output goes to a callback, so the lines handed to it are counted
+        let verification =
+            verify::Verification::new(input, verify::FeatureCounts::from_index(&index));
+        ret.verification = Some(verification.clone());
+        verification
+            .check()
+            .map_err(|e| e.in_phase("verifying output"))?;
+    }
+
+    if let Some(path) = &options.gene_order {
+        write_gene_order(path, &index, &keys)?;
+    }
+
+    ret.end_mem_mb = Some(max_mem_usage_mb());
+    ret.resources = sampler.finish();
+    progress::report_done(options.progress.as_ref());
+
+    Ok(ret)
+}
+
+/// The comment lines leading `contents`, written at the top of the output unless
+/// [SortOptions::drop_header] is set. Exports have headers of their own.
+pub(crate) fn job_header(contents: &str, options: &SortOptions) -> Header {
+    match options.drop_header || options.export.is_some() {
+        true => Header::default(),
+        false => Header::leading(contents),
+    }
+}
+
+/// Rewrites the lines of `contents` per [SortOptions::chrom_rename],
+/// [SortOptions::dedup_attrs] and [SortOptions::attr_normalize], None if none is
+/// requested, with the number of renamed lines. Records are then parsed from the
+/// rewritten copy.
+fn rewrite_contents<const SEP: u8>(
+    contents: &str,
+    options: &SortOptions,
+) -> Result<(Option<String>, usize), GtfSortError> {
+    let (rename, policy) = (options.chrom_rename.as_ref(), options.dedup_attrs);
+    if rename.is_none() && policy.is_none() && !options.attr_normalize {
+        return Ok((None, 0));
+    }
+    if options.offset_index.is_some() {
+        return Err(GtfSortError::InvalidParameter(
+            "an offset index points into the input, it cannot be combined with rewriting its lines (attribute deduplication or normalization, chromosome renaming)",
+        ));
+    }
+
+    // the line rewritten by `f` if it changed it
+    fn then<'a>(line: Cow<'a, str>, f: impl FnOnce(&str) -> Cow<'_, str>) -> Cow<'a, str> {
+        let rewritten = match f(&line) {
+            Cow::Owned(rewritten) => Some(rewritten),
+            Cow::Borrowed(_) => None,
+        };
+        rewritten.map_or(line, Cow::Owned)
+    }
+
+    let renamed = AtomicUsize::new(0);
+    let rewritten = timed("Rewriting lines", None, || {
+        contents
+            .par_lines()
+            .map(|line| {
+                if line.starts_with('#') {
+                    // only ##sequence-region directives name a chromosome
+                    return rename.map_or(Cow::Borrowed(line), |r| r.rename_line(line));
+                }
+                let mut line = Cow::Borrowed(line);
+                if let Some(renamer) = rename {
+                    line = then(line, |l| renamer.rename_line(l));
+                    if matches!(line, Cow::Owned(_)) {
+                        renamed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                if let Some(policy) = policy {
+                    line = then(line, |l| gtf::dedup_line_attributes::<SEP>(l, policy));
+                }
+                if options.attr_normalize {
+                    line = then(line, gtf::normalize_line_attributes::<SEP>);
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    let renamed = renamed.into_inner();
+    if renamed > 0 {
+        log::info!("Renamed the chromosome of {} lines", renamed);
+    }
+    Ok((Some(rewritten), renamed))
+}
+
+/// Sorts `contents` by coordinates only into `output`, see [coords].
+fn sort_coordinates<W: Write>(
+    contents: &str,
+    mut output: W,
+    tp: &ThreadPool,
+    options: &SortOptions,
+    ret: &mut SortAnnotationsJobResult,
+    sampler: &ResourceSampler,
+    watchdog: &Watchdog,
+) -> Result<(), GtfSortError> {
+    if options.genes.is_some()
+        || options.transcripts.is_some()
+        || options.dedup != dedup::DedupMode::Off
+        || options.features.is_some()
+        || options.gene_biotypes.is_some()
+        || options.head_genes.is_some()
+        || options.synthesize_parents
+        || options.infer_genes
+        || options.skeleton
+        || options.offset_index.is_some()
+        || options.rejects.is_some()
+        || options.gene_order.is_some()
+        || options.manifest.is_some()
+        || options.incremental.is_some()
+        || options.quality_metrics
+        || options.validate_output.is_some()
+    {
+        return Err(GtfSortError::InvalidParameter(
+            "coordinate-only sorting cannot be combined with gene/transcript/feature/biotype selection, deduplication, parent synthesis, skeletons, offset indexes, rejects files, gene orders, manifests, quality metrics or output validation",
+        ));
+    }
+
+    let chroms = options.chrom_filter();
+    let parse_progress = PhaseProgress::begin(options.progress.as_ref(), Phase::Parse, 1);
+    let _ctx = panic::enter("parsing", None);
+    let (mut index, lines) = timed("Parsing input", Some(&mut ret.parsing_secs), || {
+        panic::Job::current().install(tp, || coords::parse_coords(contents, watchdog, chroms))
+    });
+    parse_progress.finish();
+    if let Some(chroms) = chroms {
+        chroms.warn_missing(index.keys().copied());
+    }
+    if let Some(region) = &options.region {
+        index.retain(|chrom, records| {
+            records.retain(|(start, end, _, _)| region.keep(chrom, *start, *end));
+            !records.is_empty()
+        });
+    }
+    ret.histogram = RecordHistogram::from_lines(
+        index
+            .iter()
+            .map(|(chrom, records)| (*chrom, records.iter().map(|(_, _, _, line)| *line))),
+    );
+    ret.histogram.order_chroms(options);
+    let lines = ret.lines.merge(lines);
+    lines.log();
+    ret.lines = lines;
+    if ret.lines.invalid > 0 {
+        ret.invalid_lines = invalid::scan(contents, chroms, options.parse_mode, true, |l| {
+            gtf::line_coords(l)
+                .is_none()
+                .then_some(Cow::Borrowed("no valid start and end columns"))
+        })?;
+        invalid::log_samples(&ret.invalid_lines);
+    }
+    watchdog.check()?;
+
+    sampler.enter(Phase::Index);
+    let _ctx = panic::enter("indexing", None);
+    timed(
+        "Sorting by coordinates",
+        Some(&mut ret.indexing_secs),
+        || coords::sort_coords(&mut index),
+    );
+    let mut keys: Vec<&str> = index.keys().copied().collect();
+    sort_chroms(&mut keys, options)?;
+    watchdog.check()?;
+
+    sampler.enter(Phase::Write);
+    let _ctx = panic::enter("writing", None);
+    let write_progress =
+        PhaseProgress::begin_bytes(options.progress.as_ref(), Phase::Write, keys.len(), None);
+    let header = job_header(contents, options);
+    timed("Writing output", Some(&mut ret.writing_secs), || {
+        header.write(&mut output, options.newline)?;
+        coords::write_coords(
+            &mut output,
+            &index,
+            &keys,
+            options.newline,
+            &write_progress,
+            watchdog,
+        )
+    })
+    .map_err(|e| {
+        watchdog
+            .check()
+            .err()
+            .unwrap_or(GtfSortError::IoError("writing output file", e))
+            .in_phase("writing output")
+    })?;
+    write_progress.finish();
+
+    Ok(())
+}
+
+/// Writes every transcript of `index` to `on_transcript`, chromosomes in `keys` order.
+fn write_transcripts(
+    index: &Index,
+    keys: &[&str],
+    on_transcript: &mut dyn FnMut(&TranscriptBlock) -> io::Result<()>,
+    newline: format::Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for chrom in keys {
+        watchdog_io_check(watchdog)?;
+        let _ctx = panic::enter("writing", Some(chrom));
+        let layers = index.get(chrom).unwrap();
+
+        for gene in layers.layer.iter() {
+            layers.for_each_transcript(gene, newline, &mut buf, |transcript_id, lines| {
+                on_transcript(&TranscriptBlock {
+                    chrom,
+                    gene_id: gene.1,
+                    transcript_id,
+                    gene_line: &gene.2,
+                    lines,
+                })
+            })?;
+        }
+
+        progress.step(Some(chrom));
+    }
+
+    Ok(())
+}
+
+fn write_genes(
+    index: &Index,
+    keys: &[&str],
+    on_gene: &mut dyn FnMut(&GeneBlock) -> io::Result<()>,
+    newline: format::Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for chrom in keys {
+        watchdog_io_check(watchdog)?;
+        let _ctx = panic::enter("writing", Some(chrom));
+        let layers = index.get(chrom).unwrap();
+
+        for gene in layers.layer.iter() {
+            buf.clear();
+            layers.write_gene(&mut buf, gene, newline)?;
+            on_gene(&GeneBlock {
+                chrom,
+                gene_id: gene.1,
+                lines: &buf,
+            })?;
+        }
+
+        progress.step(Some(chrom));
+    }
+
+    Ok(())
+}
+
+fn write_gene_order(path: &Path, index: &Index, keys: &[&str]) -> Result<(), GtfSortError> {
+    timed("Writing gene order", None, || {
+        let mut w = gene_order::GeneOrderWriter::create(path)?;
+        for chrom in keys {
+            w.push_chrom(chrom, index.get(chrom).unwrap())?;
+        }
+        w.finish().map(drop)
+    })
+    .map_err(|e| GtfSortError::IoError("writing gene order", e))
+}
+
+fn quality_metrics<'a>(index: &Index<'a>, keys: &[&'a str]) -> quality::QualityMetrics {
+    timed("Computing quality metrics", None, || {
+        keys.par_iter()
+            .map(|chr| quality::QualitySamples::from_layers(index.get(chr).unwrap()))
+            .reduce(
+                quality::QualitySamples::default,
+                quality::QualitySamples::merge,
+            )
+            .summarize()
+    })
+}
+
+fn write_offset_index(
+    path: &Path,
+    contents: &str,
+    records: &ChromRecord,
+) -> Result<(), GtfSortError> {
+    timed("Writing offset index", None, || {
+        offsets::OffsetIndex::from_records(contents, records).write_to(path)
+    })
+    .map_err(|e| GtfSortError::IoError("writing offset index", e))
+}
+
+/// Writes the records of `contents` left out of `index` to `path`, see [rejects].
+fn write_rejects<'a, const SEP: u8>(
+    path: &Path,
+    contents: &'a str,
+    records: &ChromRecord<'a>,
+    index: &Index<'a>,
+    options: &SortOptions,
+) -> Result<(), GtfSortError> {
+    let rejects = timed("Collecting rejected records", None, || {
+        rejects::collect::<SEP>(contents, records, index, options.chrom_filter())
+    });
+    rejects::write_file(path, &rejects, options.newline)?;
+    if !rejects.is_empty() {
+        log::warn!(
+            "{} {} records could not be placed in the output and were written to {}",
+            "Warning:".bright_yellow().bold(),
+            rejects.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Builds a pool of `threads` workers as used by sort jobs, to be shared between jobs
+/// through [SortOptions::thread_pool].
+pub fn build_thread_pool(threads: usize) -> Result<ThreadPool, GtfSortError> {
+    check_threads(threads)?;
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("{}-{}", panic::WORKER_THREAD_PREFIX, i))
+        .build()
+        .map_err(|e| GtfSortError::InvalidThreads(e.to_string()))
+}
+
+/// Returns the pool a job runs on: the shared one if set, a fresh one otherwise.
+///
+/// A fresh pool is local to the job, the global rayon pool is never touched, so the
+/// host may configure it however it likes.
+fn job_pool(threads: usize, options: &SortOptions) -> Result<Arc<ThreadPool>, GtfSortError> {
+    match &options.thread_pool {
+        Some(tp) => Ok(Arc::clone(tp)),
+        None => build_thread_pool(threads).map(Arc::new),
+    }
+}
+
+fn check_threads(threads: usize) -> Result<(), GtfSortError> {
+    if threads == 0 {
+        return Err(GtfSortError::InvalidThreads(
+            "number of threads must be greater than 0".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the manifest of a previous run and the output it describes, None (with a
+/// warning) if they cannot be reused for this job.
+fn load_previous_output(
+    path: &Path,
+    fingerprint: u64,
+    options: &SortOptions,
+) -> Result<Option<(manifest::Manifest, String)>, GtfSortError> {
+    let skip = |why: &str| {
+        log::warn!(
+            "{} not reusing {}: {}, sorting every gene",
+            "Warning:".bright_yellow().bold(),
+            path.display(),
+            why
+        );
+        Ok(None)
+    };
+
+    if let Some(why) = manifest::incremental_blocker(options) {
+        return skip(why);
+    }
+
+    let previous = manifest::Manifest::read_from(path)?;
+    if previous.fingerprint != fingerprint {
+        return skip("it was written by another version or with other output options");
+    }
+    let Some(output) = &previous.output else {
+        return skip("the previous run did not write to a file");
+    };
+    // read in full, the output may be overwritten by this run
+    match std::fs::read_to_string(output) {
+        Ok(contents) => Ok(Some((previous, contents))),
+        Err(e) => skip(&format!("cannot read {}: {}", output.display(), e)),
+    }
+}
+
+/// Groups parsed records into per-chromosome [Layers], checking `watchdog` between chromosomes.
+fn build_index<'a, const SEP: u8>(
+    records: &ChromRecord<'a>,
+    options: &SortOptions,
+    reuse: Option<&manifest::Reuse<'a>>,
+    watchdog: &Watchdog,
+) -> Result<(Index<'a>, Vec<(String, usize)>), GtfSortError> {
+    let synthesized = AtomicUsize::new(0);
+    let bare_groups = AtomicUsize::new(0);
+    let (orphan_transcripts, orphan_children) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let (recovered_transcripts, recovered_children) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let inferred_genes = AtomicUsize::new(0);
+    let genes = AtomicUsize::new(0);
+    let unknown = Mutex::new(HashMap::<&str, usize>::new());
+    let index_progress =
+        PhaseProgress::begin(options.progress.as_ref(), Phase::Index, records.len());
+
+    let job = panic::Job::current();
+    let index = records
+        .par_iter()
+        .map(|(chrom, lines)| {
+            let _ctx = job.enter("indexing", Some(chrom));
+            watchdog.check()?;
+
+            let mut acc = Layers::default();
+            let mut spans = ParentSpans::default();
+            let mut chrom_unknown = HashMap::<&str, usize>::new();
+
+            // bare GTF2.2 groups come without gene/transcript lines
+            let chrom_bare_groups = lines.iter().filter(|l| l.bare_group).count();
+            bare_groups.fetch_add(chrom_bare_groups, Ordering::Relaxed);
+            let synthesize_parents = options.synthesize_parents || chrom_bare_groups > 0;
+
+            // the transcript line of a transcript is its transcript-level line of the best
+            // ranked type, the first in the input on ties; the others (e.g. a miRNA
+            // sharing its transcript_id) are kept as its children
+            let mut transcript_lines = HashMap::<&str, (usize, usize)>::new();
+            for (i, line) in lines.iter().enumerate() {
+                if line.layer_by(&options.layer_map) == Layer::Transcript {
+                    let key = (features::transcript_rank(line.feat), i);
+                    transcript_lines
+                        .entry(line.transcript_id)
+                        .and_modify(|k| *k = key.min(*k))
+                        .or_insert(key);
+                }
+            }
+
+            for (i, line) in lines.iter().enumerate() {
+                let layer = match line.layer_by(&options.layer_map) {
+                    Layer::Transcript if transcript_lines[line.transcript_id].1 != i => {
+                        Layer::Child
+                    }
+                    layer => layer,
+                };
+                if layer == Layer::Child
+                    && options
+                        .features
+                        .as_ref()
+                        .is_some_and(|f| !f.keep(line.feat))
+                {
+                    continue;
+                }
+                if let Some(block) = reuse.and_then(|r| r.get(&(*chrom, line.gene_id))) {
+                    // copied as a whole from the previous output
+                    if layer == Layer::Gene {
+                        acc.layer.push(line.outer_layer());
+                        acc.reused.insert(line.gene_id, block);
+                    }
+                    continue;
+                }
+
+                if synthesize_parents {
+                    match layer {
+                        Layer::Gene => {}
+                        Layer::Transcript => spans.add_transcript(line),
+                        Layer::Child => spans.add_child(line),
+                    }
+                }
+
+                match layer {
+                    Layer::Gene => {
+                        acc.layer.push(line.outer_layer());
+                    }
+                    Layer::Transcript => {
+                        acc.mapper
+                            .entry(line.gene_id)
+                            .or_default()
+                            .push(line.transcript_id);
+                        acc.helper
+                            .entry(line.transcript_id)
+                            .or_insert(Cow::Borrowed(line.line));
+                    }
+                    // children sharing a key (e.g. the CDS segments of one exon_number,
+                    // or GFF3 segments without one) are all kept, see stabilize_inner
+                    Layer::Child => {
+                        if !gtf::is_known_feature(line.feat, &options.feature_map) {
+                            *chrom_unknown.entry(line.feat).or_default() += 1;
                         }
-                        _ => {
-                            acc.inner
-                                .entry(line.transcript_id)
-                                .or_default()
-                                .entry(CowNaturalSort::new(line.feat.into()))
-                                .and_modify(|e| {
-                                    e.push(line.line);
-                                })
-                                .or_insert(vec![line.line]);
+                        if line.is_direct_child() {
+                            acc.add_direct_children(line.gene_id);
                         }
+                        acc.inner
+                            .entry(line.transcript_id)
+                            .or_default()
+                            .entry(line.inner_key(&options.feature_map))
+                            .and_modify(|e| {
+                                e.push(line.line);
+                            })
+                            .or_insert(vec![line.line]);
                     }
                 }
+            }
 
-                acc.layer.par_sort_unstable_by_key(|x| x.0);
-                index.insert(chrom, acc);
-            });
-        });
+            if !chrom_unknown.is_empty() {
+                let mut unknown = unknown.lock().unwrap();
+                for (feat, n) in chrom_unknown {
+                    *unknown.entry(feat).or_default() += n;
+                }
+            }
+
+            acc.stabilize_inner();
+            if SEP == b'=' {
+                acc.group_segments();
+            }
+            acc.order_unnumbered(options.unnumbered_order);
+
+            if synthesize_parents {
+                let (transcripts, genes) = acc.synthesize_parents::<SEP>(spans);
+                synthesized.fetch_add(transcripts + genes, Ordering::Relaxed);
+            } else {
+                if options.recover_transcripts {
+                    let (transcripts, children) =
+                        acc.recover_transcripts::<SEP>(lines, options.infer_genes);
+                    recovered_transcripts.fetch_add(transcripts, Ordering::Relaxed);
+                    recovered_children.fetch_add(children, Ordering::Relaxed);
+                }
+                if options.infer_genes {
+                    inferred_genes.fetch_add(acc.infer_genes::<SEP>(), Ordering::Relaxed);
+                }
+            }
+
+            let (transcripts, children) = acc.orphans();
+            if options.rejects.is_some() && transcripts + children > 0 {
+                acc.orphaned = acc.orphan_records(lines);
+            }
+            orphan_transcripts.fetch_add(transcripts, Ordering::Relaxed);
+            orphan_children.fetch_add(children, Ordering::Relaxed);
+            genes.fetch_add(acc.layer.len(), Ordering::Relaxed);
+
+            if let Some(genes) = &options.genes {
+                acc.layer.retain(|(_, gene_id, _)| genes.keep(gene_id));
+            }
+
+            if let Some(biotypes) = &options.gene_biotypes {
+                acc.layer.retain(|(_, _, line)| {
+                    gtf::gene_biotype::<SEP>(line).is_some_and(|b| biotypes.keep(b))
+                });
+            }
+
+            if let Some(region) = &options.region {
+                acc.layer.retain(|(_, _, line)| {
+                    gtf::line_coords(line)
+                        .is_some_and(|(start, end)| region.keep(chrom, start, end))
+                });
+            }
+
+            if let Some(transcripts) = &options.transcripts {
+                acc.retain_transcripts(transcripts, options.recompute_gene_bounds);
+            }
+
+            if options.skeleton {
+                acc.inner.values_mut().for_each(|children| children.clear());
+            }
+
+            acc.sort_transcripts(options.transcript_order);
+            acc.sort_genes::<SEP>(&options.gene_sort_key, options.stable);
+            if let Some(n) = options.head_genes {
+                acc.layer.truncate(n);
+            }
+            index_progress.step(Some(chrom));
+
+            Ok::<_, GtfSortError>((*chrom, acc))
+        })
+        .collect::<Result<Index, _>>()?;
+    index_progress.finish();
+
+    let bare_groups = bare_groups.into_inner();
+    if bare_groups > 0 {
+        log::warn!(
+            "{} {} records have a bare GTF2.2 group column instead of gene_id/transcript_id, each group is sorted as one gene with a single transcript and its gene/transcript lines are synthesized",
+            "Warning:".bright_yellow().bold(),
+            bare_groups
+        );
+    }
+
+    if options.synthesize_parents || bare_groups > 0 {
+        log::info!(
+            "Synthesized {} missing transcript/gene lines",
+            synthesized.into_inner()
+        );
+    }
+
+    let recovered_transcripts = recovered_transcripts.into_inner();
+    if recovered_transcripts > 0 {
+        log::warn!(
+            "{} {} transcripts have no transcript line, their lines were synthesized from the span of their {} records and flagged with {}",
+            "Warning:".bright_yellow().bold(),
+            recovered_transcripts,
+            recovered_children.into_inner(),
+            synth::SYNTHESIZED_TAG
+        );
+    }
+
+    let inferred_genes = inferred_genes.into_inner();
+    if inferred_genes > 0 {
+        log::warn!(
+            "{} {} genes have no gene line, their lines were synthesized from the span of their transcripts and flagged with {}",
+            "Warning:".bright_yellow().bold(),
+            inferred_genes,
+            synth::SYNTHESIZED_TAG
+        );
+    }
+
+    warn_orphans(
+        genes.into_inner(),
+        orphan_transcripts.into_inner(),
+        orphan_children.into_inner(),
+    );
+
+    let mut unknown = unknown
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|(feat, n)| (feat.to_string(), n))
+        .collect::<Vec<_>>();
+    unknown.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    Ok((index, unknown))
+}
 
-        let mut keys: Vec<&str> = index.iter().map(|x| *x.key()).collect();
-        keys.sort_by(|a, b| natord::compare(a, b));
+/// Checks the lines `index` will write if [SortOptions::validate_output] is set, see
+/// [validate::validate_index]. `input` is the buffer the lines were parsed from.
+fn validate_output(
+    index: &mut Index,
+    input: Option<&str>,
+    options: &SortOptions,
+    ret: &mut SortAnnotationsJobResult,
+) -> Result<(), GtfSortError> {
+    let Some(policy) = options.validate_output else {
+        return Ok(());
+    };
+
+    let report = timed("Validating output", None, || {
+        validate::validate_index(index, policy, input)
+    });
+    ret.malformed_lines = report.malformed;
+    report.finish(policy)
+}
+
+/// Tells which lines of `contents` the parser counted as invalid, failing at the first
+/// one with [ParseMode::Strict]. See [invalid::scan].
+fn check_invalid_lines<const SEP: u8>(
+    contents: &str,
+    options: &SortOptions,
+    ret: &mut SortAnnotationsJobResult,
+) -> Result<(), GtfSortError> {
+    if ret.lines.invalid == 0 {
+        return Ok(());
+    }
 
-        Ok(keys)
+    let chroms = options.chrom_filter();
+    ret.invalid_lines = timed("Scanning invalid lines", None, || {
+        invalid::scan(contents, chroms, options.parse_mode, true, |l| {
+            Record::parse::<SEP>(l).err()
+        })
     })?;
+    invalid::log_samples(&ret.invalid_lines);
+    Ok(())
+}
 
-    let mut writer = ChunkWriter::new(output);
-    write_obj_sequential(
-        &mut writer,
-        &index,
-        keys.iter()
-            .map(|chr| (*chr, index.get(chr).unwrap().count_line_size()))
-            .collect::<Vec<_>>(),
-        &mut None,
-    )
-    .map_err(|e| GtfSortError::IoError("writing output file", e))?;
+/// Warns about the records [build_index] left out for lack of a gene or transcript line,
+/// see [Layers::orphans].
+fn warn_orphans(genes: usize, transcripts: usize, children: usize) {
+    if transcripts + children == 0 {
+        return;
+    }
 
-    ret.end_mem_mb = Some(max_mem_usage_mb());
+    if genes == 0 {
+        log::warn!(
+            "{} the input has no gene lines, so its {} transcripts and {} other records were left out and the output is empty. Use --synthesize-parents to build the missing genes and transcripts from their children",
+            "Warning:".bright_yellow().bold(),
+            transcripts,
+            children
+        );
+    } else {
+        log::warn!(
+            "{} {} transcripts without a gene line and {} records without a written transcript were left out of the output. Use --synthesize-parents, --infer-genes or --recover-transcripts to keep them",
+            "Warning:".bright_yellow().bold(),
+            transcripts,
+            children
+        );
+    }
+}
 
-    Ok(ret)
+/// Warns once about the feature types counted by [build_index].
+fn warn_unknown_features(unknown: &[(String, usize)]) {
+    if unknown.is_empty() {
+        return;
+    }
+
+    let mut list = unknown
+        .iter()
+        .take(10)
+        .map(|(feat, n)| format!("{} ({})", feat, n))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if unknown.len() > 10 {
+        list.push_str(", ...");
+    }
+    log::warn!(
+        "{} {} unrecognized feature types were written as generic children of their transcript, after its exons, CDS and codons: {}. If this is unexpected, check that --format matches the input",
+        "Warning:".bright_yellow().bold(),
+        unknown.len(),
+        list
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::sort_to_string;
+
+    const GTF: &str = "chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+        chr2\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr2\ts\texon\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+
+    #[test]
+    fn writer_output_matches_string_output() {
+        let path = temp::TempPath::file(None, "writer_test", ".gtf").unwrap();
+        std::fs::write(&path, GTF).unwrap();
+
+        let mut from_file = Vec::new();
+        let ret =
+            sort_annotations_to_writer(&path, &mut from_file, 2, &SortOptions::default()).unwrap();
+        assert_eq!(ret.output, "[writer]");
+
+        let mut from_string = Vec::new();
+        sort_annotations_string::<b' ', _>(
+            GTF,
+            &mut |b| {
+                from_string.extend_from_slice(b);
+                Ok(b.len())
+            },
+            2,
+        )
+        .unwrap();
+
+        assert_eq!(from_file, from_string);
+        assert!(from_file.starts_with(b"chr1\t"));
+    }
+
+    #[test]
+    fn staged_output_matches_direct_output() {
+        let dir = temp::TempPath::dir(None, "stage_job_test").unwrap();
+        let stage_dir = temp::TempPath::dir(None, "stage_dir_test").unwrap();
+        let input = dir.join("in.gtf");
+        std::fs::write(&input, GTF).unwrap();
+
+        let (direct, staged) = (dir.join("direct.gtf"), dir.join("staged.gtf"));
+        sort_annotations(&input, &direct, 1).unwrap();
+        let options = SortOptions {
+            stage_dir: Some(stage_dir.to_path_buf()),
+            ..Default::default()
+        };
+        sort_annotations_with_options(&input, &staged, 1, &options).unwrap();
+
+        assert_eq!(
+            std::fs::read(direct).unwrap(),
+            std::fs::read(staged).unwrap()
+        );
+        assert_eq!(std::fs::read_dir(&stage_dir).unwrap().count(), 0);
+    }
+
+    #[cfg(all(unix, feature = "mmap"))]
+    #[test]
+    fn fallbacks_are_recorded() {
+        let dir = temp::TempPath::dir(None, "fallback_job_test").unwrap();
+        let input = dir.join("in.gtf");
+        std::fs::write(&input, GTF).unwrap();
+
+        let direct = dir.join("direct.gtf");
+        let job = sort_annotations(&input, &direct, 1).unwrap();
+        assert!(job.fallbacks.is_empty());
+        assert_eq!(job.output_bytes, std::fs::metadata(&direct).unwrap().len());
+        assert_eq!(job.write_amplification(), Some(1.0));
+
+        // devices cannot be memory mapped, the sequential writer takes over
+        let job = sort_annotations(&input, Path::new("/dev/null"), 1).unwrap();
+        assert!(!job.output_mmaped);
+        assert_eq!(job.fallbacks.len(), 1);
+        assert_eq!(job.fallbacks[0].what, "output memory map");
+        assert!(job
+            .to_json()
+            .contains("\"fallbacks\":[{\"what\":\"output memory map\""));
+    }
+
+    #[test]
+    fn compressed_lines_match_direct_output() {
+        let dir = temp::TempPath::dir(None, "packed_job_test").unwrap();
+        let input = dir.join("in.gtf");
+        std::fs::write(&input, GTF).unwrap();
+
+        let (direct, packed) = (dir.join("direct.gtf"), dir.join("packed.gtf"));
+        sort_annotations(&input, &direct, 1).unwrap();
+        let options = SortOptions {
+            compress_lines: packed::LineCompression::Always,
+            ..Default::default()
+        };
+        let ret = sort_annotations_with_options(&input, &packed, 1, &options).unwrap();
+
+        assert!(!ret.input_mmaped);
+        assert_eq!(
+            std::fs::read(direct).unwrap(),
+            std::fs::read(packed).unwrap()
+        );
+    }
+
+    #[test]
+    fn spilled_lines_match_direct_output() {
+        let dir = temp::TempPath::dir(None, "spill_job_test").unwrap();
+        let input = dir.join("in.gtf");
+        std::fs::write(&input, GTF).unwrap();
+
+        let (direct, spilled) = (dir.join("direct.gtf"), dir.join("spilled.gtf"));
+        sort_annotations(&input, &direct, 1).unwrap();
+        let options = SortOptions {
+            low_memory: true,
+            tmpdir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let ret = sort_annotations_with_options(&input, &spilled, 1, &options).unwrap();
+
+        assert!(!ret.input_mmaped);
+        assert_eq!(
+            std::fs::read(direct).unwrap(),
+            std::fs::read(spilled).unwrap()
+        );
+    }
+
+    #[test]
+    fn memory_budget_picks_a_backend() {
+        let dir = temp::TempPath::dir(None, "budget_job_test").unwrap();
+        let input = dir.join("in.gtf");
+        std::fs::write(&input, GTF).unwrap();
+
+        let direct = dir.join("direct.gtf");
+        sort_annotations(&input, &direct, 1).unwrap();
+        for (budget, mmaped) in [(1024.0, cfg!(feature = "mmap")), (0.001, false)] {
+            let output = dir.join(format!("{}.gtf", budget));
+            let options = SortOptions {
+                max_mem_mb: Some(budget),
+                tmpdir: Some(dir.to_path_buf()),
+                ..Default::default()
+            };
+            let ret = sort_annotations_with_options(&input, &output, 1, &options).unwrap();
+            assert_eq!(ret.input_mmaped, mmaped, "{}", budget);
+            assert_eq!(
+                std::fs::read(&direct).unwrap(),
+                std::fs::read(output).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn renamed_chromosomes_sort_together() {
+        let dir = temp::TempPath::dir(None, "rename_job_test").unwrap();
+        let input = dir.join("in.gtf");
+        std::fs::write(
+            &input,
+            "chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+            1\ts\tgene\t5\t9\t.\t+\t.\tgene_id \"C\";\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+            MT\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"M\";\n",
+        )
+        .unwrap();
+
+        let expected = ["chr1\ts\tgene\t1", "chr1\ts\tgene\t5", "chr2", "chrM"];
+        for low_memory in [false, true] {
+            let output = dir.join(format!("{}.gtf", low_memory));
+            let options = SortOptions {
+                chrom_rename: Some(ChromRenamer::new(Some(ChromNaming::Ucsc))),
+                low_memory,
+                tmpdir: Some(dir.to_path_buf()),
+                ..Default::default()
+            };
+            let ret = sort_annotations_with_options(&input, &output, 2, &options).unwrap();
+            assert_eq!(ret.renamed_lines, 2, "{}", low_memory);
+            assert!(ret.to_json().contains("\"renamed_lines\":2"));
+
+            let out = std::fs::read_to_string(&output).unwrap();
+            let lines = out.lines().collect::<Vec<_>>();
+            assert_eq!(lines.len(), expected.len());
+            for (line, prefix) in lines.iter().zip(expected) {
+                assert!(line.starts_with(prefix), "{} in\n{}", prefix, out);
+            }
+        }
+
+        // chromosomes are selected under their new name whatever the backend
+        let aliases = dir.join("aliases.txt");
+        std::fs::write(&aliases, "scaf1\tchrUn_x\n").unwrap();
+        std::fs::write(
+            &input,
+            "##sequence-region scaf1 1 9\n\
+            chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+            scaf1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"U\";\n",
+        )
+        .unwrap();
+        let options = SortOptions {
+            chrom_rename: Some(ChromRenamer::new(None).with_alias_file(&aliases).unwrap()),
+            chromosomes: Some(filter::ChromFilter::from_list("chrUn_x")),
+            tmpdir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let mut backends = vec![
+            ("whole", options.clone()),
+            (
+                "low_memory",
+                SortOptions {
+                    low_memory: true,
+                    ..options.clone()
+                },
+            ),
+            (
+                "compressed",
+                SortOptions {
+                    compress_lines: packed::LineCompression::Always,
+                    ..options.clone()
+                },
+            ),
+        ];
+        if cfg!(feature = "mmap") {
+            backends.push((
+                "window",
+                SortOptions {
+                    mmap_window: Some(64),
+                    ..options.clone()
+                },
+            ));
+        }
+        for (backend, options) in backends {
+            let output = dir.join(format!("{}.gtf", backend));
+            sort_annotations_with_options(&input, &output, 2, &options).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&output).unwrap(),
+                "##sequence-region chrUn_x 1 9\n\
+                chrUn_x\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"U\";\n",
+                "{}",
+                backend
+            );
+        }
+    }
+
+    /// Records of `text` (non-comment lines), sorted, to compare as multisets.
+    fn record_lines(text: &str) -> Vec<&str> {
+        let mut lines = text
+            .lines()
+            .filter(|l| !l.starts_with('#') && !l.is_empty())
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines
+    }
+
+    #[test]
+    fn attributes_are_written_as_read() {
+        let dir = temp::TempPath::dir(None, "attr_preserve_test").unwrap();
+        for (name, fixture) in [
+            ("in.gtf", include_str!("../ci/data/attr_dialects.gtf")),
+            ("in.gff3", include_str!("../ci/data/attr_dialects.gff3")),
+        ] {
+            let input = dir.join(name);
+            // repeated entries and unquoted values, on more than one chromosome
+            let fixture = format!(
+                "{}{}",
+                fixture,
+                fixture.replace("chr1\t", "chr10\t").replace("G", "H")
+            );
+            std::fs::write(&input, &fixture).unwrap();
+
+            for (backend, options) in [
+                ("whole", SortOptions::default()),
+                (
+                    "low_memory",
+                    SortOptions {
+                        low_memory: true,
+                        tmpdir: Some(dir.to_path_buf()),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "compressed",
+                    SortOptions {
+                        compress_lines: packed::LineCompression::Always,
+                        ..Default::default()
+                    },
+                ),
+            ] {
+                let output = dir.join(format!("{}.{}", backend, name));
+                sort_annotations_with_options(&input, &output, 2, &options).unwrap();
+                let out = std::fs::read_to_string(&output).unwrap();
+                assert_eq!(
+                    record_lines(&out),
+                    record_lines(&fixture),
+                    "{} {}",
+                    backend,
+                    name
+                );
+            }
+
+            let output = dir.join(format!("normalized.{}", name));
+            let options = SortOptions {
+                attr_normalize: true,
+                ..Default::default()
+            };
+            sort_annotations_with_options(&input, &output, 2, &options).unwrap();
+            let out = std::fs::read_to_string(&output).unwrap();
+            assert_eq!(record_lines(&out).len(), record_lines(&fixture).len());
+            for line in record_lines(&out) {
+                let normalized = match name.ends_with(".gtf") {
+                    true => gtf::normalize_line_attributes::<b' '>(line),
+                    false => gtf::normalize_line_attributes::<b'='>(line),
+                };
+                assert!(matches!(normalized, Cow::Borrowed(_)), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn crlf_newlines_in_both_writers() {
+        let options = SortOptions {
+            newline: format::Newline::Crlf,
+            ..Default::default()
+        };
+
+        let expected = sort_to_string::<b' '>(GTF, &SortOptions::default()).replace('\n', "\r\n");
+        assert_eq!(sort_to_string::<b' '>(GTF, &options), expected);
+
+        // the file job sizes its output map from the line sizes
+        let dir = temp::TempPath::dir(None, "crlf_test").unwrap();
+        let (input, output) = (dir.join("in.gtf"), dir.join("out.gtf"));
+        std::fs::write(&input, GTF).unwrap();
+        sort_annotations_with_options(&input, &output, 2, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), expected);
+    }
+
+    #[test]
+    fn head_genes_keeps_first_genes_per_chromosome() {
+        let input = format!(
+            "{}chr1\ts\tgene\t20\t29\t.\t+\t.\tgene_id \"C\";\n\
+             chr1\ts\ttranscript\t20\t29\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+             chr1\ts\texon\t20\t29\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\"; exon_number \"1\";\n",
+            GTF
+        );
+
+        let mut output = Vec::new();
+        sort_annotations_string_with_options::<b' ', _>(
+            &input,
+            &mut |b| {
+                output.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+            &SortOptions {
+                head_genes: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 6);
+        assert!(!output.contains("gene_id \"C\""));
+    }
+
+    #[test]
+    fn genes_sharing_a_start_sort_by_end_and_id() {
+        let gene = |id: &str, end: u32| {
+            format!(
+                "chr1\ts\tgene\t10\t{end}\t.\t+\t.\tgene_id \"{id}\";\n\
+                 chr1\ts\ttranscript\t10\t{end}\t.\t+\t.\tgene_id \"{id}\"; transcript_id \"{id}.1\";\n"
+            )
+        };
+        let input = (0..64)
+            .rev()
+            .map(|i| gene(&format!("G{:02}", i % 32), 100 + i / 32))
+            .collect::<String>();
+
+        let sort = |threads| {
+            let mut output = Vec::new();
+            sort_annotations_string_with_options::<b' ', _>(
+                &input,
+                &mut |b| {
+                    output.extend_from_slice(b);
+                    Ok(b.len())
+                },
+                threads,
+                &SortOptions::default(),
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let output = sort(1);
+        assert_eq!(output, sort(4));
+        let genes = output
+            .lines()
+            .filter(|l| l.contains("\tgene\t"))
+            .map(|l| l.rsplit('"').nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(genes[0], "G00");
+        assert_eq!(genes[31], "G31");
+        assert_eq!(genes[32], "G00");
+        assert!(output.lines().nth(64).unwrap().contains("\t101\t"));
+        assert!(presorted::is_sorted::<b' '>(&output));
+    }
+
+    #[test]
+    fn skeleton_keeps_genes_and_transcripts() {
+        let output = sort_to_string::<b' '>(
+            GTF,
+            &SortOptions {
+                skeleton: true,
+                ..Default::default()
+            },
+        );
+        let features = output
+            .lines()
+            .map(|l| l.split('\t').nth(2).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(features, ["gene", "transcript", "gene", "transcript"]);
+    }
+
+    #[test]
+    fn unknown_features_are_counted() {
+        let input = "chr1\ts\tgene\t1\t100\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tUTR\t1\t10\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tmiRNA\t1\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tregion\t1\t30\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tregion\t40\t50\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+
+        let mut output = Vec::new();
+        let ret = sort_annotations_string::<b' ', _>(
+            input,
+            &mut |b| {
+                output.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            ret.unknown_features,
+            [("region".to_string(), 2), ("miRNA".to_string(), 1)]
+        );
+        // known and unknown children alike follow the exons by feature name
+        let features = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| l.split('\t').nth(2).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            features,
+            [
+                "gene",
+                "transcript",
+                "exon",
+                "UTR",
+                "miRNA",
+                "region",
+                "region"
+            ]
+        );
+        assert!(ret
+            .to_json()
+            .ends_with(",\"unknown_features\":{\"region\":2,\"miRNA\":1}}"));
+    }
+
+    #[test]
+    fn run_summary_counts_records_per_chromosome_and_feature() {
+        let input = "chr10\ts\tgene\t1\t100\t.\t+\t.\tgene_id \"B\";\n\
+            chr2\ts\tgene\t1\t100\t.\t+\t.\tgene_id \"A\";\n\
+            chr2\ts\ttranscript\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr2\ts\texon\t1\t40\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr2\ts\texon\t60\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            not a record\n";
+
+        let ret = sort_annotations_string::<b' ', _>(input, &mut |b| Ok(b.len()), 1).unwrap();
+        assert_eq!(
+            ret.histogram.chromosomes,
+            [("chr2".to_string(), 4), ("chr10".to_string(), 1)]
+        );
+        assert_eq!(ret.histogram.features[0], ("exon".to_string(), 2));
+        assert!(ret
+            .to_json()
+            .contains(",\"chromosomes\":{\"chr2\":4,\"chr10\":1},\"features\":{\"exon\":2,"));
+
+        let tsv = ret.to_tsv();
+        for row in [
+            "lines.records\t5",
+            "lines.invalid\t1",
+            "chromosomes.chr10\t1",
+            "features.gene\t2",
+        ] {
+            assert!(
+                tsv.lines().any(|l| l == row),
+                "{} missing from\n{}",
+                row,
+                tsv
+            );
+        }
+    }
+
+    #[test]
+    fn sort_many_keeps_input_order() {
+        let inputs = [GTF, "", &GTF.replace("\"A", "\"Z")];
+        let results = sort_many::<b' ', _>(inputs.iter().copied(), 2).unwrap();
+        assert_eq!(results.len(), 3);
+
+        for (input, result) in inputs.iter().zip(results) {
+            let mut expected = Vec::new();
+            sort_annotations_string::<b' ', _>(
+                input,
+                &mut |b| {
+                    expected.extend_from_slice(b);
+                    Ok(b.len())
+                },
+                1,
+            )
+            .unwrap();
+            assert_eq!(result.unwrap().output, expected);
+        }
+
+        assert!(sort_many::<b' ', _>([GTF], 0).is_err());
+    }
+
+    #[test]
+    fn combined_chunk_indexes_match_a_single_sort() {
+        let input = "chr1\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t50\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t30\t50\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\";\n\
+            chr1\ts\tgene\t200\t300\t.\t+\t.\tgene_id \"C\";\n\
+            chr1\ts\ttranscript\t200\t300\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+            chr1\ts\texon\t200\t300\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\"; exon_number \"1\";\n\
+            chr1\ts\texon\t1\t10\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+            chr1\ts\ttranscript\t1\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.2\";\n\
+            chr1\ts\texon\t1\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.2\"; exon_number \"1\";\n\
+            chr1\ts\tgene\t1\t40\t.\t+\t.\tgene_id \"D\";\n\
+            chr1\ts\ttranscript\t1\t40\t.\t+\t.\tgene_id \"D\"; transcript_id \"D.1\";\n\
+            chr1\ts\texon\t1\t40\t.\t+\t.\tgene_id \"D\"; transcript_id \"D.1\"; exon_number \"1\";\n\
+            chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+            chr2\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+            chr2\ts\texon\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n";
+
+        // gene A and transcript A.1 are split across the chunks, gene D of the second
+        // shares the start of gene A but ends first
+        let split = input.match_indices('\n').nth(5).unwrap().0 + 1;
+        let by_position = SortOptions {
+            transcript_order: ord::TranscriptOrder::Position,
+            ..Default::default()
+        };
+        for options in [SortOptions::default(), by_position] {
+            let mut whole = Vec::new();
+            sort_annotations_string_with_options::<b' ', _>(
+                input,
+                &mut |b| {
+                    whole.extend_from_slice(b);
+                    Ok(b.len())
+                },
+                1,
+                &options,
+            )
+            .unwrap();
+
+            let mut index =
+                index_records::<b' '>(&parallel_parse::<b' '>(&input[..split]).unwrap(), &options)
+                    .unwrap();
+            let second =
+                index_records::<b' '>(&parallel_parse::<b' '>(&input[split..]).unwrap(), &options)
+                    .unwrap();
+            combine_index::<b' '>(&mut index, second, &options);
+            assert_eq!(index["chr1"].layer.len(), 3);
+
+            let mut combined = Vec::new();
+            write_index_with_options(&mut combined, &index, &options).unwrap();
+            assert_eq!(
+                String::from_utf8(combined).unwrap(),
+                String::from_utf8(whole).unwrap(),
+                "{:?}",
+                options.transcript_order
+            );
+        }
+        let options = SortOptions::default();
+        let index =
+            index_records::<b' '>(&parallel_parse::<b' '>(input).unwrap(), &options).unwrap();
+
+        // chromosomes as listed
+        let listed = SortOptions {
+            chrom_order: Some(chrom_order::ChromOrder::new(["chr2", "chr1"]).unwrap()),
+            ..Default::default()
+        };
+        let mut reordered = Vec::new();
+        write_index_with_options(&mut reordered, &index, &listed).unwrap();
+        assert!(String::from_utf8(reordered).unwrap().starts_with("chr2\t"));
+        let mut natural = Vec::new();
+        write_index(&mut natural, &index, format::Newline::Lf).unwrap();
+        assert!(String::from_utf8(natural).unwrap().starts_with("chr1\t"));
+    }
+
+    #[test]
+    fn sections_are_sorted_in_place() {
+        let input = format!("Report\n--- begin\n{}--- end\nDone\n", GTF);
+        let options = SortOptions {
+            section: Some(section::SectionMarkers {
+                start: Some("--- begin".to_string()),
+                end: Some("--- end".to_string()),
+            }),
+            ..Default::default()
+        };
+
+        let sorted = sort_to_string::<b' '>(GTF, &SortOptions::default());
+        let mut output = Vec::new();
+        let job = sort_annotations_string_with_options::<b' ', _>(
+            &input,
+            &mut |b| {
+                output.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("Report\n--- begin\n{}--- end\nDone\n", sorted)
+        );
+        assert_eq!(job.lines.surrounding, 4);
+        assert_eq!(job.lines.total(), input.lines().count());
+
+        // the file sorter writes the same document
+        let dir = temp::TempPath::dir(None, "section_job_test").unwrap();
+        let (path, out) = (dir.join("report.txt"), dir.join("sorted.txt"));
+        std::fs::write(&path, &input).unwrap();
+        let options = SortOptions {
+            format: Some(AnnotationFormat::Gtf),
+            ..options
+        };
+        sort_annotations_with_options(&path, &out, 1, &options).unwrap();
+        assert!(std::fs::read_to_string(&out)
+            .unwrap()
+            .starts_with("Report\n--- begin\nchr"));
+    }
+
+    #[test]
+    fn sparse_inputs_do_not_fail() {
+        let sort = |input: &str, options: &SortOptions| {
+            let mut output = Vec::new();
+            let job = sort_annotations_string_with_options::<b' ', _>(
+                input,
+                &mut |b| {
+                    output.extend_from_slice(b);
+                    Ok(b.len())
+                },
+                1,
+                options,
+            )
+            .unwrap();
+            (job.lines.records, String::from_utf8(output).unwrap())
+        };
+
+        assert_eq!(
+            sort(
+                "#!genome-build x
+
+",
+                &Default::default()
+            ),
+            (0, "#!genome-build x\n".to_string())
+        );
+
+        // without gene lines nothing can be placed, unless parents are synthesized
+        let orphans = GTF
+            .lines()
+            .filter(|l| !l.contains("\tgene\t"))
+            .map(|l| format!("{}\n", l))
+            .collect::<String>();
+        assert_eq!(sort(&orphans, &Default::default()), (4, String::new()));
+        let options = SortOptions {
+            synthesize_parents: true,
+            ..Default::default()
+        };
+        assert_eq!(sort(&orphans, &options).1.lines().count(), 6);
+
+        // a gene without transcripts, a transcript without children
+        let lone = "chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\tgene\t5\t9\t.\t+\t.\tgene_id \"B\";\n\
+            chr1\ts\ttranscript\t5\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n";
+        let (_, output) = sort(lone, &Default::default());
+        assert_eq!(output, lone);
+    }
+
+    #[test]
+    fn every_input_line_is_accounted_for() {
+        let input = format!(
+            "#!genome-build x\n{}not a record\n   \n\t\n#\n\n##FASTA\n>chr1\nACGT\n",
+            GTF
+        );
+        let job = sort_annotations_string::<b' ', _>(&input, &mut |b| Ok(b.len()), 1).unwrap();
+
+        assert_eq!(
+            job.lines,
+            LineCounts {
+                records: GTF.lines().count(),
+                comments: 2,
+                blank: 3,
+                skipped: 0,
+                invalid: 1,
+                duplicates: 0,
+                sequence: 3,
+                surrounding: 0,
+            }
+        );
+        assert_eq!(job.lines.total(), input.lines().count());
+        assert!(job.to_json().contains("\"lines\":{\"records\":"));
+    }
+
+    #[test]
+    fn transcript_blocks_reassemble_the_output() {
+        let mut expected = Vec::new();
+        sort_annotations_string::<b' ', _>(
+            GTF,
+            &mut |b| {
+                expected.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+        )
+        .unwrap();
+
+        let (mut blocks, mut reassembled) = (Vec::new(), Vec::new());
+        sort_annotations_string_by_transcript::<b' ', _>(
+            GTF,
+            &mut |block| {
+                if blocks.last().map(|(_, g, _)| g) != Some(&block.gene_id.to_string()) {
+                    reassembled.extend_from_slice(block.gene_line.as_bytes());
+                    reassembled.push(b'\n');
+                }
+                reassembled.extend_from_slice(block.lines);
+                blocks.push((
+                    block.chrom.to_string(),
+                    block.gene_id.to_string(),
+                    block.transcript_id.to_string(),
+                ));
+                Ok(())
+            },
+            1,
+            &SortOptions::default(),
+        )
+        .unwrap();
+
+        assert!(!blocks.is_empty());
+        assert_eq!(reassembled, expected);
+    }
+
+    #[test]
+    fn gene_blocks_reassemble_the_output() {
+        // a gene without transcripts is a block too
+        let input = format!("{}chr3\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"C\";\n", GTF);
+        let mut expected = Vec::new();
+        sort_annotations_string::<b' ', _>(
+            &input,
+            &mut |b| {
+                expected.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+        )
+        .unwrap();
+
+        let (mut genes, mut reassembled) = (Vec::new(), Vec::new());
+        sort_annotations_string_by_gene::<b' ', _>(
+            &input,
+            &mut |block| {
+                reassembled.extend_from_slice(block.lines);
+                genes.push((block.chrom.to_string(), block.gene_id.to_string()));
+                Ok(())
+            },
+            1,
+            &SortOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            genes,
+            [("chr1", "A"), ("chr2", "B"), ("chr3", "C")]
+                .map(|(c, g)| (c.to_string(), g.to_string()))
+        );
+        assert_eq!(reassembled, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn shared_pool_is_reused_across_jobs() {
+        // a configured global pool must not get in the way
+        let _ = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build_global();
+
+        let options = SortOptions {
+            thread_pool: Some(Arc::new(build_thread_pool(2).unwrap())),
+            ..Default::default()
+        };
+
+        let mut outputs = Vec::new();
+        for _ in 0..3 {
+            let mut output = Vec::new();
+            sort_annotations_string_with_options::<b' ', _>(
+                GTF,
+                &mut |b| {
+                    output.extend_from_slice(b);
+                    Ok(b.len())
+                },
+                2,
+                &options,
+            )
+            .unwrap();
+            outputs.push(output);
+        }
+
+        assert!(outputs.windows(2).all(|w| w[0] == w[1]));
+        assert_eq!(Arc::strong_count(options.thread_pool.as_ref().unwrap()), 1);
+        assert!(build_thread_pool(0).is_err());
+    }
+
+    #[test]
+    fn broken_pipe_is_reported_as_such() {
+        struct ClosedPipe;
+
+        impl Write for ClosedPipe {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let path = temp::TempPath::file(None, "pipe_test", ".gtf").unwrap();
+        std::fs::write(&path, GTF).unwrap();
+
+        let err = sort_annotations_to_writer(&path, ClosedPipe, 1, &SortOptions::default())
+            .err()
+            .unwrap();
+
+        assert!(err.is_broken_pipe());
+        assert!(!GtfSortError::InvalidParameter("x").is_broken_pipe());
+    }
+
+    #[test]
+    fn outputs_are_removed_when_the_input_changes() {
+        let dir = temp::TempPath::dir(None, "changed_test").unwrap();
+        let (input, output) = (dir.join("in.gtf"), dir.join("out.gtf"));
+        std::fs::write(&input, GTF).unwrap();
+
+        let appended = {
+            let input = input.clone();
+            let once = std::sync::Once::new();
+            progress::Progress::new(move |_| {
+                once.call_once(|| {
+                    let mut f = std::fs::OpenOptions::new()
+                        .append(true)
+                        .open(&input)
+                        .unwrap();
+                    f.write_all(GTF.lines().next().unwrap().as_bytes()).unwrap();
+                })
+            })
+        };
+        let options = SortOptions {
+            rejects: Some(dir.join("rejects.gtf")),
+            gene_order: Some(dir.join("genes.tsv")),
+            manifest: Some(dir.join("manifest.tsv")),
+            extra_outputs: vec![(dir.join("out.bed"), export::ExportFormat::Bed12)],
+            progress: Some(appended),
+            ..Default::default()
+        };
+
+        let Err(err) = sort_annotations_with_options(&input, &output, 1, &options) else {
+            panic!("the change of the input went unnoticed");
+        };
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        for path in std::iter::once(output.as_path()).chain(options.side_outputs()) {
+            assert!(!path.exists(), "{:?} was left behind", path);
+        }
+    }
 }
@@ -0,0 +1,556 @@
+//! Comparison of an annotation with its liftover to another assembly.
+//!
+//! Genes are matched by gene_id and their transcripts by transcript_id. Each side is
+//! parsed and indexed like a sort, then reduced to one [GeneSpan] per gene and per
+//! transcript, so the comparison only looks at where they landed and how many distinct
+//! exons they kept.
+
+use std::{fmt, ops::Range};
+
+use hashbrown::HashMap;
+
+use crate::{
+    gtf::{line_coords, line_strand},
+    index_records, parallel_parse, sniff, sort_chroms, GtfSortError, Index, SortOptions,
+};
+
+/// Location and exon count of a gene or transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneSpan<'a> {
+    pub chrom: &'a str,
+    pub start: u32,
+    pub end: u32,
+    /// `+`, `-` or `.`
+    pub strand: char,
+    /// Distinct (start, end) exons, over all transcripts for a gene.
+    pub exons: usize,
+}
+
+impl GeneSpan<'_> {
+    /// Column of [LiftoverReport::write_tsv] for a span found in `old` but changed in `new`.
+    fn status(&self, new: &Self) -> &'static str {
+        if self.chrom != new.chrom {
+            "moved_chrom"
+        } else if self.strand != new.strand {
+            "flipped_strand"
+        } else if new.start as i64 - self.start as i64 != new.end as i64 - self.end as i64 {
+            "resized"
+        } else if self.exons != new.exons {
+            "exon_count"
+        } else {
+            "shifted"
+        }
+    }
+}
+
+impl fmt::Display for GeneSpan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.chrom, self.start, self.end, self.strand
+        )
+    }
+}
+
+/// The genes of an annotation, in output order, with their transcripts.
+#[derive(Debug, Default)]
+pub struct GeneSpans<'a> {
+    pub genes: Vec<(&'a str, GeneSpan<'a>)>,
+    /// Transcripts of every gene, in the order of `genes`.
+    pub transcripts: Vec<(&'a str, GeneSpan<'a>)>,
+    // gene index -> its transcripts
+    ranges: Vec<Range<usize>>,
+    by_id: HashMap<&'a str, usize>,
+}
+
+impl<'a> GeneSpans<'a> {
+    /// Parses and indexes `contents` up to any `##FASTA` section, see [index_records].
+    pub fn parse<const SEP: u8>(
+        contents: &'a str,
+        options: &SortOptions,
+    ) -> Result<Self, GtfSortError> {
+        let (contents, _) = sniff::split_fasta(contents);
+        let records = parallel_parse::<SEP>(contents)
+            .map_err(|e| GtfSortError::ParseError(e).in_phase("parsing"))?;
+        let index = index_records::<SEP>(&records, options).map_err(|e| e.in_phase("indexing"))?;
+        Self::from_index(&index, options)
+    }
+
+    /// Genes of `index`, chromosomes in the output order of `options`. A gene_id found on
+    /// several chromosomes (e.g. in both pseudoautosomal regions) keeps its first location.
+    /// A transcript without its own line spans its exons.
+    pub fn from_index(index: &Index<'a>, options: &SortOptions) -> Result<Self, GtfSortError> {
+        let mut keys = index.keys().copied().collect::<Vec<_>>();
+        sort_chroms(&mut keys, options)?;
+
+        let mut spans = Self::default();
+        let (mut exons, mut gene_exons) = (Vec::new(), Vec::new());
+        for chrom in keys {
+            let layers = &index[chrom];
+            for (_, gene_id, line) in layers.layer.iter() {
+                if spans.by_id.contains_key(gene_id) {
+                    continue;
+                }
+                let (start, end) = line_coords(line).unwrap_or_default();
+                let strand = strand_of(line);
+
+                gene_exons.clear();
+                let first = spans.transcripts.len();
+                for tx in layers.mapper.get(gene_id).into_iter().flatten() {
+                    exons.clear();
+                    exons.extend(
+                        layers
+                            .inner
+                            .get(tx)
+                            .into_iter()
+                            .flat_map(|c| c.values().flatten())
+                            .filter(|l| l.split('\t').nth(2) == Some("exon"))
+                            .filter_map(|l| line_coords(l)),
+                    );
+                    exons.sort_unstable();
+                    exons.dedup();
+                    gene_exons.extend_from_slice(&exons);
+
+                    let tx_line = layers.helper.get(tx);
+                    let (tx_start, tx_end) = tx_line
+                        .and_then(|l| line_coords(l))
+                        .or_else(|| Some((exons.first()?.0, exons.iter().map(|e| e.1).max()?)))
+                        .unwrap_or((start, end));
+                    spans.transcripts.push((
+                        tx,
+                        GeneSpan {
+                            chrom,
+                            start: tx_start,
+                            end: tx_end,
+                            strand: tx_line.map_or(strand, |l| strand_of(l)),
+                            exons: exons.len(),
+                        },
+                    ));
+                }
+                gene_exons.sort_unstable();
+                gene_exons.dedup();
+
+                spans.by_id.insert(gene_id, spans.genes.len());
+                spans.ranges.push(first..spans.transcripts.len());
+                spans.genes.push((
+                    gene_id,
+                    GeneSpan {
+                        chrom,
+                        start,
+                        end,
+                        strand,
+                        exons: gene_exons.len(),
+                    },
+                ));
+            }
+        }
+        Ok(spans)
+    }
+
+    pub fn get(&self, gene_id: &str) -> Option<&GeneSpan<'a>> {
+        self.by_id.get(gene_id).map(|i| &self.genes[*i].1)
+    }
+
+    /// Transcripts of `gene_id`, empty if it is not found.
+    pub fn transcripts_of(&self, gene_id: &str) -> &[(&'a str, GeneSpan<'a>)] {
+        match self.by_id.get(gene_id) {
+            Some(i) => &self.transcripts[self.ranges[*i].clone()],
+            None => &[],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.genes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.genes.is_empty()
+    }
+}
+
+fn strand_of(line: &str) -> char {
+    match line_strand(line) {
+        Some("+") => '+',
+        Some("-") => '-',
+        _ => '.',
+    }
+}
+
+/// A gene found in both annotations whose location or exon count differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneChange<'a> {
+    pub gene_id: &'a str,
+    pub old: GeneSpan<'a>,
+    pub new: GeneSpan<'a>,
+}
+
+impl GeneChange<'_> {
+    /// Shift of the start coordinate, meaningless if the gene changed chromosome.
+    pub fn start_shift(&self) -> i64 {
+        self.new.start as i64 - self.old.start as i64
+    }
+
+    pub fn end_shift(&self) -> i64 {
+        self.new.end as i64 - self.old.end as i64
+    }
+
+    pub fn moved_chrom(&self) -> bool {
+        self.old.chrom != self.new.chrom
+    }
+
+    pub fn flipped_strand(&self) -> bool {
+        self.old.strand != self.new.strand
+    }
+
+    /// The gene length changed, i.e. the liftover did not move it as one block.
+    pub fn resized(&self) -> bool {
+        self.start_shift() != self.end_shift()
+    }
+
+    pub fn exon_count_change(&self) -> i64 {
+        self.new.exons as i64 - self.old.exons as i64
+    }
+}
+
+/// A transcript missing from either annotation or whose location or exon count differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptChange<'a> {
+    pub gene_id: &'a str,
+    /// `.` for a gene without transcripts, which then stands for the gene itself.
+    pub transcript_id: &'a str,
+    pub old: Option<GeneSpan<'a>>,
+    pub new: Option<GeneSpan<'a>>,
+}
+
+impl TranscriptChange<'_> {
+    /// `missing`, `added`, or how a transcript found in both changed.
+    pub fn status(&self) -> &'static str {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => old.status(new),
+            (Some(_), None) => "missing",
+            _ => "added",
+        }
+    }
+}
+
+/// Differences between an annotation and its liftover, see [compare_liftover].
+#[derive(Debug, Default)]
+pub struct LiftoverReport<'a> {
+    /// Genes of the old annotation.
+    pub genes: usize,
+    /// Genes found at the same location with the same exon count.
+    pub unchanged: usize,
+    /// Genes of the old annotation not found in the new one, in old order.
+    pub missing: Vec<(&'a str, GeneSpan<'a>)>,
+    /// Genes only found in the new annotation, in new order.
+    pub added: Vec<(&'a str, GeneSpan<'a>)>,
+    /// Genes found in both that changed, in old order.
+    pub changed: Vec<GeneChange<'a>>,
+    /// Transcripts of the old annotation.
+    pub transcripts: usize,
+    /// Missing, added and changed transcripts, those of each gene in old then new order,
+    /// genes in old order followed by the added ones.
+    pub transcript_changes: Vec<TranscriptChange<'a>>,
+}
+
+impl LiftoverReport<'_> {
+    pub fn moved_chrom(&self) -> usize {
+        self.changed.iter().filter(|c| c.moved_chrom()).count()
+    }
+
+    pub fn resized(&self) -> usize {
+        self.changed
+            .iter()
+            .filter(|c| !c.moved_chrom() && c.resized())
+            .count()
+    }
+
+    pub fn exon_count_changed(&self) -> usize {
+        self.changed
+            .iter()
+            .filter(|c| c.exon_count_change() != 0)
+            .count()
+    }
+
+    /// Columns of [Self::write_tsv].
+    pub const TSV_HEADER: &'static str =
+        "gene_id\ttranscript_id\tstatus\told_location\tnew_location\tstart_shift\tend_shift\told_exons\tnew_exons";
+
+    /// Writes one line per missing, added or changed transcript, see
+    /// [Self::transcript_changes].
+    pub fn write_tsv<W: std::io::Write>(&self, mut out: W) -> std::io::Result<()> {
+        writeln!(out, "{}", Self::TSV_HEADER)?;
+        let or_dot = |v: Option<String>| v.unwrap_or_else(|| ".".to_string());
+        for c in self.transcript_changes.iter() {
+            let shifts = match (&c.old, &c.new) {
+                (Some(old), Some(new)) if old.chrom == new.chrom => Some((
+                    new.start as i64 - old.start as i64,
+                    new.end as i64 - old.end as i64,
+                )),
+                _ => None,
+            };
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                c.gene_id,
+                c.transcript_id,
+                c.status(),
+                or_dot(c.old.map(|s| s.to_string())),
+                or_dot(c.new.map(|s| s.to_string())),
+                or_dot(shifts.map(|s| s.0.to_string())),
+                or_dot(shifts.map(|s| s.1.to_string())),
+                or_dot(c.old.map(|s| s.exons.to_string())),
+                or_dot(c.new.map(|s| s.exons.to_string())),
+            )?;
+        }
+        out.flush()
+    }
+}
+
+impl fmt::Display for LiftoverReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} genes: {} unchanged, {} changed, {} missing, {} added ({} moved chromosome, {} resized, {} changed exon count); {} transcripts: {} missing, added or changed",
+            self.genes,
+            self.unchanged,
+            self.changed.len(),
+            self.missing.len(),
+            self.added.len(),
+            self.moved_chrom(),
+            self.resized(),
+            self.exon_count_changed(),
+            self.transcripts,
+            self.transcript_changes.len()
+        )
+    }
+}
+
+/// Matches the genes of `old` and `new` by gene_id, then the transcripts of each gene by
+/// transcript_id.
+pub fn compare_liftover<'a>(old: &GeneSpans<'a>, new: &GeneSpans<'a>) -> LiftoverReport<'a> {
+    let mut report = LiftoverReport {
+        genes: old.len(),
+        transcripts: old.transcripts.len(),
+        ..Default::default()
+    };
+
+    for (id, span) in old.genes.iter() {
+        let lifted = new.get(id);
+        match lifted {
+            None => report.missing.push((id, *span)),
+            Some(lifted) if lifted == span => report.unchanged += 1,
+            Some(lifted) => report.changed.push(GeneChange {
+                gene_id: id,
+                old: *span,
+                new: *lifted,
+            }),
+        }
+        compare_transcripts(
+            &mut report.transcript_changes,
+            id,
+            (Some(span), lifted),
+            old.transcripts_of(id),
+            new.transcripts_of(id),
+        );
+    }
+    report.added = new
+        .genes
+        .iter()
+        .filter(|(id, _)| old.get(id).is_none())
+        .copied()
+        .collect();
+    for (id, span) in report.added.iter() {
+        compare_transcripts(
+            &mut report.transcript_changes,
+            id,
+            (None, Some(span)),
+            &[],
+            new.transcripts_of(id),
+        );
+    }
+
+    report
+}
+
+/// Pushes the differences between the transcripts of `gene_id` in both annotations, or
+/// between `genes` if it has none on either side.
+fn compare_transcripts<'a>(
+    changes: &mut Vec<TranscriptChange<'a>>,
+    gene_id: &'a str,
+    genes: (Option<&GeneSpan<'a>>, Option<&GeneSpan<'a>>),
+    old: &[(&'a str, GeneSpan<'a>)],
+    new: &[(&'a str, GeneSpan<'a>)],
+) {
+    if old.is_empty() && new.is_empty() {
+        if genes.0 != genes.1 {
+            changes.push(TranscriptChange {
+                gene_id,
+                transcript_id: ".",
+                old: genes.0.copied(),
+                new: genes.1.copied(),
+            });
+        }
+        return;
+    }
+
+    let find = |spans: &[(&str, GeneSpan<'a>)], id: &str| {
+        spans.iter().find(|(tx, _)| *tx == id).map(|(_, s)| *s)
+    };
+    for (tx, span) in old.iter() {
+        let lifted = find(new, tx);
+        if lifted != Some(*span) {
+            changes.push(TranscriptChange {
+                gene_id,
+                transcript_id: tx,
+                old: Some(*span),
+                new: lifted,
+            });
+        }
+    }
+    for (tx, span) in new.iter().filter(|(tx, _)| find(old, tx).is_none()) {
+        changes.push(TranscriptChange {
+            gene_id,
+            transcript_id: tx,
+            old: None,
+            new: Some(*span),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gene(
+        chrom: &str,
+        id: &str,
+        start: u32,
+        end: u32,
+        strand: char,
+        exons: &[(u32, u32)],
+    ) -> String {
+        let mut s = format!(
+            "{chrom}\ts\tgene\t{start}\t{end}\t.\t{strand}\t.\tgene_id \"{id}\";\n\
+            {chrom}\ts\ttranscript\t{start}\t{end}\t.\t{strand}\t.\tgene_id \"{id}\"; transcript_id \"{id}.1\";\n"
+        );
+        for (n, (s_, e)) in exons.iter().enumerate() {
+            s += &format!(
+                "{chrom}\ts\texon\t{s_}\t{e}\t.\t{strand}\t.\tgene_id \"{id}\"; transcript_id \"{id}.1\"; exon_number \"{}\";\n",
+                n + 1
+            );
+        }
+        s
+    }
+
+    #[test]
+    fn genes_are_matched_by_id() {
+        let old = [
+            gene("chr1", "A", 100, 200, '+', &[(100, 120), (180, 200)]),
+            gene("chr1", "B", 300, 400, '-', &[(300, 400)]),
+            gene("chr1", "C", 500, 600, '+', &[(500, 600)]),
+            gene("chr2", "D", 100, 200, '+', &[(100, 150), (160, 200)]),
+            gene("chr2", "E", 700, 800, '+', &[(700, 800)]),
+        ]
+        .concat();
+        let new = [
+            gene("chr1", "A", 100, 200, '+', &[(100, 120), (180, 200)]),
+            gene("chr1", "B", 1300, 1400, '-', &[(1300, 1400)]),
+            gene("chr3", "C", 500, 600, '+', &[(500, 600)]),
+            gene("chr2", "D", 110, 200, '+', &[(110, 200)]),
+            gene("chr2", "F", 10, 20, '+', &[(10, 20)]),
+        ]
+        .concat();
+
+        let options = SortOptions::default();
+        let old = GeneSpans::parse::<b' '>(&old, &options).unwrap();
+        let new = GeneSpans::parse::<b' '>(&new, &options).unwrap();
+        let report = compare_liftover(&old, &new);
+
+        assert_eq!(report.genes, 5);
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(
+            report.missing.iter().map(|g| g.0).collect::<Vec<_>>(),
+            ["E"]
+        );
+        assert_eq!(report.added.iter().map(|g| g.0).collect::<Vec<_>>(), ["F"]);
+        assert_eq!(
+            report.changed.iter().map(|c| c.gene_id).collect::<Vec<_>>(),
+            ["B", "C", "D"]
+        );
+        assert_eq!(report.changed[0].start_shift(), 1000);
+        assert!(!report.changed[0].resized());
+        assert_eq!(
+            (
+                report.moved_chrom(),
+                report.resized(),
+                report.exon_count_changed()
+            ),
+            (1, 1, 1)
+        );
+
+        let mut tsv = Vec::new();
+        report.write_tsv(&mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert_eq!(
+            tsv.lines().skip(1).collect::<Vec<_>>(),
+            [
+                "B\tB.1\tshifted\tchr1:300-400:-\tchr1:1300-1400:-\t1000\t1000\t1\t1",
+                "C\tC.1\tmoved_chrom\tchr1:500-600:+\tchr3:500-600:+\t.\t.\t1\t1",
+                "D\tD.1\tresized\tchr2:100-200:+\tchr2:110-200:+\t10\t0\t2\t1",
+                "E\tE.1\tmissing\tchr2:700-800:+\t.\t.\t.\t1\t.",
+                "F\tF.1\tadded\t.\tchr2:10-20:+\t.\t.\t.\t1",
+            ]
+        );
+    }
+
+    #[test]
+    fn transcripts_are_matched_within_genes() {
+        let transcript = |id: &str, start: u32, end: u32, exons: &[(u32, u32)]| {
+            gene("chr1", "G", start, end, '+', exons)
+                .lines()
+                .skip(1)
+                .map(|l| l.replace("G.1", id) + "\n")
+                .collect::<String>()
+        };
+        let old = [
+            "chr1\ts\tgene\t100\t900\t.\t+\t.\tgene_id \"G\";\n".to_string(),
+            transcript("G.1", 100, 900, &[(100, 200), (800, 900)]),
+            transcript("G.2", 100, 500, &[(100, 200), (400, 500)]),
+            transcript("G.3", 300, 900, &[(300, 400), (800, 900)]),
+        ]
+        .concat();
+        let new = [
+            "chr1\ts\tgene\t100\t900\t.\t+\t.\tgene_id \"G\";\n".to_string(),
+            transcript("G.1", 100, 900, &[(100, 200), (800, 900)]),
+            transcript("G.2", 100, 500, &[(100, 500)]),
+            transcript("G.4", 600, 900, &[(600, 900)]),
+        ]
+        .concat();
+
+        let options = SortOptions::default();
+        let old = GeneSpans::parse::<b' '>(&old, &options).unwrap();
+        let new = GeneSpans::parse::<b' '>(&new, &options).unwrap();
+        let report = compare_liftover(&old, &new);
+
+        // the gene kept its location and exon count, but not its transcripts
+        assert_eq!((report.unchanged, report.changed.len()), (1, 0));
+        assert_eq!(report.transcripts, 3);
+        assert_eq!(
+            report
+                .transcript_changes
+                .iter()
+                .map(|c| (c.transcript_id, c.status()))
+                .collect::<Vec<_>>(),
+            [("G.2", "exon_count"), ("G.3", "missing"), ("G.4", "added")]
+        );
+
+        let mut tsv = Vec::new();
+        report.write_tsv(&mut tsv).unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap().lines().nth(1),
+            Some("G\tG.2\texon_count\tchr1:100-500:+\tchr1:100-500:+\t0\t0\t2\t1")
+        );
+    }
+}
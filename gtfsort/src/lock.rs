@@ -0,0 +1,207 @@
+//! Coordination with other processes using the same input, e.g. array jobs sorting one
+//! shared reference into different outputs.
+//!
+//! Concurrent readers need no coordination: the input is only ever opened read-only.
+//! What breaks a sort is a process writing the input while it is read, which
+//! [InputSnapshot] detects after the fact by comparing the file metadata before and after
+//! the job. Cooperating writers that take an exclusive `flock` can be waited for with a
+//! [SharedLock] (see [crate::SortOptions::lock_input]).
+
+use std::{fs::File, path::Path, time::SystemTime};
+
+use crate::{color::Colorize, GtfSortError};
+
+/// A shared advisory lock on a file, released when dropped. Any number of readers hold it
+/// at once, a writer holding an exclusive lock on the file is waited for.
+///
+/// On filesystems without lock support, and on platforms other than unix, the lock is
+/// skipped with a warning.
+#[derive(Debug)]
+pub struct SharedLock {
+    file: Option<File>,
+}
+
+impl SharedLock {
+    pub fn acquire(path: &Path) -> Result<Self, GtfSortError> {
+        let file = File::open(path).map_err(|e| GtfSortError::IoError("opening input file", e))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            let fd = file.as_raw_fd();
+            let try_lock = |op| match unsafe { libc::flock(fd, op) } {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            };
+
+            let locked = match try_lock(libc::LOCK_SH | libc::LOCK_NB) {
+                Err(e) if e.raw_os_error() == Some(libc::EWOULDBLOCK) => {
+                    log::info!(
+                        "Waiting for the writer holding an exclusive lock on {}",
+                        path.display()
+                    );
+                    try_lock(libc::LOCK_SH)
+                }
+                result => result,
+            };
+            match locked {
+                Ok(()) => {
+                    log::info!("Holding a shared lock on {}", path.display());
+                    Ok(Self { file: Some(file) })
+                }
+                Err(e)
+                    if matches!(
+                        e.raw_os_error(),
+                        Some(libc::ENOLCK | libc::EOPNOTSUPP | libc::ENOSYS)
+                    ) =>
+                {
+                    log::warn!(
+                        "{} {} does not support locks ({}), reading it unlocked",
+                        "Warning:".bright_yellow().bold(),
+                        path.display(),
+                        e
+                    );
+                    Ok(Self { file: None })
+                }
+                Err(e) => Err(GtfSortError::IoError("locking input file", e)),
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            drop(file);
+            log::warn!(
+                "{} input locks are only supported on unix, reading {} unlocked",
+                "Warning:".bright_yellow().bold(),
+                path.display()
+            );
+            Ok(Self { file: None })
+        }
+    }
+
+    /// Whether the lock is actually held.
+    pub fn is_held(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+/// Metadata of a file that changes when it is written to or replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputSnapshot {
+    len: u64,
+    modified: Option<SystemTime>,
+    /// (device, inode, ctime, ctime nanoseconds), which `touch` cannot fake.
+    #[cfg(unix)]
+    node: (u64, u64, i64, i64),
+}
+
+/// How a file differs from an earlier [InputSnapshot].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputChange {
+    /// Written to in place.
+    Modified,
+    /// Replaced by another file, e.g. renamed over. Open handles keep the old contents.
+    Replaced,
+    Removed,
+}
+
+impl InputSnapshot {
+    pub fn take(path: &Path) -> Option<Self> {
+        let m = std::fs::metadata(path).ok()?;
+        Some(Self {
+            len: m.len(),
+            modified: m.modified().ok(),
+            #[cfg(unix)]
+            node: {
+                use std::os::unix::fs::MetadataExt;
+                (m.dev(), m.ino(), m.ctime(), m.ctime_nsec())
+            },
+        })
+    }
+
+    /// How `path` changed since the snapshot was taken, if at all.
+    pub fn changes(&self, path: &Path) -> Option<InputChange> {
+        let Some(now) = Self::take(path) else {
+            return Some(InputChange::Removed);
+        };
+        #[cfg(unix)]
+        if (now.node.0, now.node.1) != (self.node.0, self.node.1) {
+            return Some(InputChange::Replaced);
+        }
+        (now != *self).then_some(InputChange::Modified)
+    }
+
+    /// Fails if `path` was written to since the snapshot, as the output may then mix old
+    /// and new contents, and warns if it was replaced or removed.
+    pub fn verify(&self, path: &Path) -> Result<(), GtfSortError> {
+        match self.changes(path) {
+            None => Ok(()),
+            Some(InputChange::Modified) => Err(GtfSortError::InvalidInput(format!(
+                "{} was modified while it was being sorted, another process is writing it; the output may be inconsistent and was discarded, consider --lock-input if the writer takes a lock",
+                path.display()
+            ))),
+            Some(change) => {
+                log::warn!(
+                    "{} {} was {} while it was being sorted, the output reflects its previous contents",
+                    "Warning:".bright_yellow().bold(),
+                    path.display(),
+                    match change {
+                        InputChange::Replaced => "replaced",
+                        _ => "removed",
+                    }
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempPath;
+    use std::io::Write;
+
+    #[test]
+    fn writes_are_detected() {
+        let path = TempPath::file(None, "snapshot", ".gtf").unwrap();
+        std::fs::write(path.path(), "chr1\n").unwrap();
+        let snapshot = InputSnapshot::take(path.path()).unwrap();
+        assert_eq!(snapshot.changes(path.path()), None);
+
+        let lock = SharedLock::acquire(path.path()).unwrap();
+        // readers do not exclude each other
+        let other = SharedLock::acquire(path.path()).unwrap();
+        assert_eq!(lock.is_held(), cfg!(unix));
+        drop((lock, other));
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(path.path())
+            .and_then(|mut f| f.write_all(b"chr2\n"))
+            .unwrap();
+        assert_eq!(snapshot.changes(path.path()), Some(InputChange::Modified));
+        assert!(snapshot.verify(path.path()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writers_are_waited_for() {
+        use std::os::unix::io::AsRawFd;
+
+        let path = TempPath::file(None, "lock", ".gtf").unwrap();
+        let writer = File::create(path.path()).unwrap();
+        assert_eq!(unsafe { libc::flock(writer.as_raw_fd(), libc::LOCK_EX) }, 0);
+
+        let reader = {
+            let path = path.path().to_path_buf();
+            std::thread::spawn(move || SharedLock::acquire(&path).map(|l| l.is_held()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!reader.is_finished());
+
+        drop(writer);
+        assert!(reader.join().unwrap().unwrap());
+    }
+}
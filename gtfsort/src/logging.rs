@@ -0,0 +1,86 @@
+use std::{fmt, sync::Arc};
+
+use log::{Level, LevelFilter, Log};
+
+/// How a sort job should set up logging if the host has not configured a logger yet.
+#[derive(Clone)]
+pub enum LoggerSetup {
+    /// Install the default console logger at this level.
+    Level(Level),
+    /// Install a caller provided logger, e.g. one forwarding to the host's logging system.
+    Custom(Arc<dyn Log>, LevelFilter),
+}
+
+impl fmt::Debug for LoggerSetup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Level(level) => f.debug_tuple("Level").field(level).finish(),
+            Self::Custom(_, filter) => f.debug_tuple("Custom").field(filter).finish(),
+        }
+    }
+}
+
+impl LoggerSetup {
+    /// Installs the logger, returns false (and changes nothing) if a global logger exists.
+    pub fn install(&self) -> bool {
+        match self {
+            Self::Level(level) => try_init_logger(*level),
+            Self::Custom(logger, filter) => {
+                let ok = log::set_boxed_logger(Box::new(SharedLogger(Arc::clone(logger)))).is_ok();
+                if ok {
+                    log::set_max_level(*filter);
+                }
+                ok
+            }
+        }
+    }
+}
+
+struct SharedLogger(Arc<dyn Log>);
+
+impl Log for SharedLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.log(record)
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Installs the default console logger at `level`.
+///
+/// Unlike `simple_logger::init_with_level`, this never panics: if the host already set a
+/// global logger it is left alone and false is returned.
+pub fn try_init_logger(level: Level) -> bool {
+    simple_logger::init_with_level(level).is_ok()
+}
+
+/// Parses a log level name (trace, debug, info, warn, error), case-insensitively,
+/// falling back to info.
+pub fn parse_level(s: &str) -> Level {
+    s.trim().parse().unwrap_or(Level::Info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        assert_eq!(parse_level("DEBUG"), Level::Debug);
+        assert_eq!(parse_level(" warn "), Level::Warn);
+        assert_eq!(parse_level("bogus"), Level::Info);
+    }
+
+    #[test]
+    fn init_is_idempotent() {
+        try_init_logger(Level::Info);
+        assert!(!try_init_logger(Level::Debug));
+        assert!(!LoggerSetup::Level(Level::Trace).install());
+    }
+}
@@ -22,37 +22,92 @@
 //! ```
 
 use clap::{self, Parser};
-use colored::Colorize;
+use gtfsort::color::Colorize;
 use log::Level;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use gtfsort::*;
 
+/// Exit code of a run stopped by --timeout, matching coreutils `timeout`.
+const EXIT_TIMEOUT: i32 = 124;
+
+/// Exit code when the consumer of the output closed the pipe early (e.g. `| head`),
+/// the code a shell reports for a process killed by SIGPIPE.
+///
+/// Rust ignores SIGPIPE, so an early close surfaces as an EPIPE write error instead of
+/// killing the process half-way through.
+const EXIT_BROKEN_PIPE: i32 = 141;
+
+/// Printed after a job stopped by --max-rss.
+const MEMORY_LIMIT_HINT: &str = "the job was stopped before the system ran out of memory, try --low-memory (or a --max-mem budget, which picks it when needed), fewer --threads or a larger --max-rss";
+
+/// Value of --format.
+#[derive(Debug, Clone, Copy)]
+enum FormatArg {
+    /// Sniff the format from the attribute columns.
+    Auto,
+    Fixed(AnnotationFormat),
+}
+
+fn parse_format(s: &str) -> Result<FormatArg, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        Ok(FormatArg::Auto)
+    } else {
+        s.parse().map(FormatArg::Fixed)
+    }
+}
+
 #[derive(Parser, Debug)]
 #[clap(
     name = "gtfsort",
     version = "0.2.3",
     author = "alejandrogzi <alejandrxgzi@gmail.com>, eternal-flame-AD <yume@yumechi.jp>",
-    about = "An optimized chr/pos/feature GTF2.5-3 sorter using a lexicographic-based index ordering algorithm written in Rust."
+    about = "An optimized chr/pos/feature GTF2.5-3 sorter using a lexicographic-based index ordering algorithm written in Rust.",
+    subcommand_negates_reqs = true,
+    group(clap::ArgGroup::new("id_lists").args(["genes", "transcripts"]).multiple(true))
 )]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(
+        long = "version-json",
+        help = "Print the version, git commit, enabled features and supported formats of this build as JSON and exit"
+    )]
+    version_json: bool,
+
     #[clap(
         short = 'i',
         long = "input",
-        help = "Path to unsorted GTF file",
+        help = "Path to unsorted GTF file, plain or gzip/bgzip compressed, - for stdin (its format is then detected unless --format is given). Repeat to merge several inputs of the same format into one sorted output",
         value_name = "UNSORTED",
-        required = true
+        required_unless_present = "version_json"
     )]
-    input: PathBuf,
+    input: Vec<PathBuf>,
+
+    #[clap(
+        long = "tag-source",
+        help = "Record the input each line came from in this attribute (e.g. source_file), most useful with several -i",
+        value_name = "KEY"
+    )]
+    tag_source: Option<String>,
 
     #[clap(
         short = 'o',
         long = "output",
-        help = "Path to output sorted GTF file",
-        value_name = "OUTPUT",
-        required = true
+        help = "Path to output sorted GTF file, - (the default) for stdout. Repeat to write several formats from one parse: .saf, .refflat and .bed12/.bed outputs are exported from the sorted index and a .json output takes the run report",
+        value_name = "OUTPUT"
+    )]
+    output: Vec<PathBuf>,
+
+    #[clap(
+        short = 'f',
+        long = "format",
+        help = "Input format (gtf, gff3, or auto to detect it from the attribute columns), guessed from the file extension if not set",
+        value_name = "FORMAT",
+        value_parser = parse_format
     )]
-    output: PathBuf,
+    format: Option<FormatArg>,
 
     #[clap(
         short = 't',
@@ -62,51 +117,702 @@ struct Args {
         default_value_t = num_cpus::get()
     )]
     threads: usize,
+
+    #[clap(
+        long = "max-rss",
+        help = "Abort gracefully once resident memory goes above this size (e.g. 8G, 512M)",
+        value_name = "SIZE",
+        value_parser = parse_mem_size_mb
+    )]
+    max_rss: Option<f64>,
+
+    #[clap(
+        long = "max-mem",
+        help = "Memory budget (e.g. 4G): sort in memory, with compressed input lines or with the input spilled to --tmpdir, whichever is expected to fit, instead of aborting like --max-rss",
+        value_name = "SIZE",
+        value_parser = parse_mem_size_mb
+    )]
+    max_mem: Option<f64>,
+
+    #[clap(
+        long = "timeout",
+        help = "Abort the run (removing any partial output) once it runs longer than this (e.g. 30m, 2h)",
+        value_name = "DURATION",
+        value_parser = parse_duration
+    )]
+    timeout: Option<std::time::Duration>,
+
+    #[clap(
+        long = "genes",
+        help = "Only write the gene_ids listed in this file (one per line) and their children",
+        value_name = "GENES"
+    )]
+    genes: Option<PathBuf>,
+
+    #[clap(
+        long = "transcripts",
+        help = "Only write the transcript_ids listed in this file (one per line), keeping their parent genes",
+        value_name = "TRANSCRIPTS"
+    )]
+    transcripts: Option<PathBuf>,
+
+    #[clap(
+        long = "chromosomes",
+        visible_alias = "chrom",
+        help = "Only parse and write these chromosomes (comma-separated, e.g. chr1,chr2,chrX); a \"chr\" prefix is optional and chrM matches MT",
+        value_name = "LIST"
+    )]
+    chromosomes: Option<String>,
+
+    #[clap(
+        long = "feature",
+        help = "Only write children of these feature types (comma-separated, e.g. exon,CDS), keeping the gene and transcript lines around them",
+        value_name = "LIST"
+    )]
+    feature: Option<String>,
+
+    #[clap(
+        long = "gene-biotype",
+        help = "Only write genes of these biotypes (comma-separated, e.g. protein_coding,lncRNA) and their children, read from gene_biotype, gene_type or biotype",
+        value_name = "LIST"
+    )]
+    gene_biotype: Option<String>,
+
+    #[clap(
+        long = "region",
+        help = "Only write the genes overlapping this region (e.g. chr1:1,000,000-2,000,000, 1-based and inclusive), whole with all their transcripts and children",
+        value_name = "REGION"
+    )]
+    region: Option<Region>,
+
+    #[clap(
+        long = "region-overlap",
+        help = "How genes must overlap --region: any (at least one base) or within (entirely inside)",
+        value_name = "MODE",
+        default_value = "any",
+        requires = "region"
+    )]
+    region_overlap: RegionOverlap,
+
+    #[clap(
+        long = "dedup",
+        help = "Leave out duplicate records, keeping the first: exact (identical lines), coordinates (the same feature, start, end and strand under the same gene and transcript, e.g. a gene defined by two sources) or off (the default)",
+        value_name = "MODE",
+        default_value_t = dedup::DedupMode::Off
+    )]
+    dedup: dedup::DedupMode,
+
+    #[clap(
+        long = "recompute-gene-bounds",
+        help = "Shrink gene lines to the span of the transcripts kept by --transcripts",
+        requires = "transcripts"
+    )]
+    recompute_gene_bounds: bool,
+
+    #[clap(
+        long = "invert",
+        help = "Exclude the listed IDs instead of keeping them",
+        requires = "id_lists"
+    )]
+    invert: bool,
+
+    #[clap(
+        long = "head-genes",
+        help = "Only write the first N genes of each chromosome (after sorting), e.g. for quick previews",
+        value_name = "N"
+    )]
+    head_genes: Option<usize>,
+
+    #[clap(
+        long = "synthesize-parents",
+        help = "Synthesize missing transcript and gene lines from their children (e.g. CDS-only models)"
+    )]
+    synthesize_parents: bool,
+
+    #[clap(
+        long = "infer-genes",
+        help = "Synthesize gene lines spanning their transcripts for gene_ids without a gene line (e.g. TAIR or PacBio pipelines)"
+    )]
+    infer_genes: bool,
+
+    #[clap(
+        long = "recover-transcripts",
+        help = "Synthesize missing transcript lines from the span of their records when their gene has a gene line, instead of leaving the records out"
+    )]
+    recover_transcripts: bool,
+
+    #[clap(
+        long = "skeleton",
+        help = "Only write gene and transcript lines, e.g. for lightweight tracks or debugging the hierarchy"
+    )]
+    skeleton: bool,
+
+    #[clap(
+        long = "coordinate-only",
+        help = "Ignore the gene hierarchy and sort lines by chromosome, start and end only (like sort -k1,1 -k4,4n -k5,5n), enough for bedtools and tabix"
+    )]
+    coordinate_only: bool,
+
+    #[clap(
+        long = "newline",
+        help = "Line terminator of the output (lf or crlf)",
+        value_name = "STYLE",
+        default_value = "lf"
+    )]
+    newline: format::Newline,
+
+    #[clap(
+        long = "output-compression",
+        help = "Compress the output as it is written: bgzf (bgzip-compatible blocks, indexable by tabix, deflated in parallel), gzip or none",
+        value_name = "CODEC"
+    )]
+    output_compression: Option<OutputCompression>,
+
+    #[clap(
+        long = "index",
+        help = "Write a tabix index next to the output (tbi, the default, or csi for chromosomes over 512 Mb); implies --coordinate-only and bgzf output",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "tbi"
+    )]
+    index: Option<tabix::IndexFormat>,
+
+    #[clap(
+        long = "drop-header",
+        help = "Leave out the comment lines leading the input (e.g. ##gff-version 3), which are otherwise written at the top of the output"
+    )]
+    drop_header: bool,
+
+    #[clap(
+        long = "dedup-attrs",
+        help = "Collapse repeated attribute keys (e.g. several tag entries) into one, joining their values with commas (join, the default) or keeping the first (first)",
+        value_name = "POLICY",
+        num_args = 0..=1,
+        default_missing_value = "join"
+    )]
+    dedup_attrs: Option<gtf::DedupAttrs>,
+
+    #[clap(
+        long = "attr-normalize",
+        help = "Rewrite every attribute column into a canonical form before sorting: ID, Parent, gene_id, transcript_id and exon_number first, GTF values quoted, single spaces and no repeated entries (e.g. the same tag twice), e.g. to diff two annotations"
+    )]
+    attr_normalize: bool,
+
+    #[clap(
+        long = "rename-chroms",
+        help = "Write numbered, sex and mitochondrial chromosomes under one naming scheme: ucsc (chr1, chrX, chrM) or ensembl (1, X, MT)",
+        value_name = "NAMING"
+    )]
+    rename_chroms: Option<ChromNaming>,
+
+    #[clap(
+        long = "chrom-aliases",
+        help = "Two-column file renaming chromosomes before sorting, the input name and its replacement per line (e.g. a UCSC chromAlias table); applied before --rename-chroms",
+        value_name = "FILE"
+    )]
+    chrom_aliases: Option<PathBuf>,
+
+    #[clap(
+        long = "unnumbered-order",
+        help = "Order of exons, CDS and codons without exon_number within their transcript: input (the default), coordinate, or transcription (5' to 3' along the strand)",
+        value_name = "ORDER",
+        default_value_t = ord::UnnumberedOrder::Input
+    )]
+    unnumbered_order: ord::UnnumberedOrder,
+
+    #[clap(
+        long = "gene-sort-key",
+        help = "Order of the genes within a chromosome: start (the default), length (longest first) or attr:NAME (highest numeric attribute first, e.g. attr:TPM, taken from the gene line or else the highest of its transcripts)",
+        value_name = "KEY",
+        default_value_t = ord::GeneSortKey::Start
+    )]
+    gene_sort_key: ord::GeneSortKey,
+
+    #[clap(
+        long = "stable",
+        help = "Keep genes tied on the sort key, start, end and gene_id (e.g. a gene defined twice) in their input order"
+    )]
+    stable: bool,
+
+    #[clap(
+        long = "transcript-order",
+        help = "Order of the transcripts within their gene: input (the default), position (by start, end and transcript_id) or id (by transcript_id)",
+        value_name = "ORDER",
+        default_value_t = ord::TranscriptOrder::Input
+    )]
+    transcript_order: ord::TranscriptOrder,
+
+    #[clap(
+        long = "feature-map",
+        help = "File listing the exon-level features (exon, CDS, codons...) one per line, in the order they are written within an exon",
+        value_name = "FILE"
+    )]
+    feature_map: Option<PathBuf>,
+
+    #[clap(
+        long = "feature-layers",
+        help = "File mapping feature types to the gene, transcript or child layer, one 'TYPE LAYER' per line, on top of the Sequence Ontology defaults (mRNA, lnc_RNA, pseudogene...)",
+        value_name = "FILE"
+    )]
+    feature_layers: Option<PathBuf>,
+
+    #[clap(
+        long = "chr-order-list",
+        visible_alias = "chrom-order",
+        help = "File giving the chromosome order of the output: one name per line, a .fai, chrom.sizes or bedtools .genome file (first column), or a Picard .dict; unlisted chromosomes are written last",
+        value_name = "FILE"
+    )]
+    chr_order_list: Option<PathBuf>,
+
+    #[clap(
+        long = "sort-order",
+        help = "Order of the chromosomes: natural (the default, chr2 before chr10), lex (byte order, as sort and igvtools), karyotype (numbered, then X, Y, M, scaffolds, and alt/patch contigs last, as GATK references) or custom (the --chr-order-list file)",
+        value_name = "ORDER",
+        default_value_t = chrom_order::ChromSortOrder::Natural
+    )]
+    sort_order: chrom_order::ChromSortOrder,
+
+    #[clap(
+        long = "strict",
+        help = "Fail at the first line that does not parse as a record, with its line number, instead of skipping it"
+    )]
+    strict: bool,
+
+    #[clap(
+        long = "strict-order",
+        help = "Fail if the input has chromosomes missing from --chr-order-list",
+        requires = "chr_order_list"
+    )]
+    strict_order: bool,
+
+    #[clap(
+        long = "mmap-window",
+        help = "Read the input through memory-mapped windows of this size (e.g. 256M) instead of mapping it whole, sorting one chromosome at a time",
+        value_name = "SIZE",
+        value_parser = parse_mem_size_mb
+    )]
+    mmap_window: Option<f64>,
+
+    #[clap(
+        long = "no-mmap",
+        help = "Read the input and write the output with plain IO instead of memory maps, e.g. on network filesystems",
+        conflicts_with = "mmap_window"
+    )]
+    no_mmap: bool,
+
+    #[clap(
+        long = "compress-lines",
+        help = "Read the input as a stream and keep its lines LZ4-compressed in memory, sorting one chromosome at a time: auto (the default, when sorting the whole input would likely go above --max-mem or --max-rss), always or never",
+        value_name = "WHEN",
+        default_value_t = packed::LineCompression::Auto
+    )]
+    compress_lines: packed::LineCompression,
+
+    #[clap(
+        long = "low-memory",
+        help = "Partition the input by chromosome into temporary files (see --tmpdir) and sort one chromosome at a time, for files larger than memory"
+    )]
+    low_memory: bool,
+
+    #[clap(
+        long = "manifest",
+        help = "Write per-gene content hashes of this run to this file, for a later --incremental run",
+        value_name = "TSV"
+    )]
+    manifest: Option<PathBuf>,
+
+    #[clap(
+        long = "incremental",
+        help = "Copy genes whose input lines did not change from the output described by this manifest of a previous run",
+        value_name = "MANIFEST"
+    )]
+    incremental: Option<PathBuf>,
+
+    #[clap(
+        long = "offset-index",
+        help = "Write a TSV mapping each gene_id to the byte ranges of its lines in the unsorted input",
+        value_name = "TSV"
+    )]
+    offset_index: Option<PathBuf>,
+
+    #[clap(
+        long = "rejects",
+        help = "Copy records that cannot be placed in the output (invalid lines, unresolvable parents, orphans) to this file, each after a comment with its line number and the reason",
+        value_name = "FILE"
+    )]
+    rejects: Option<PathBuf>,
+
+    #[clap(
+        long = "gene-order",
+        help = "Write a TSV of gene_id, chrom, start, end and rank of every gene in the sorted output, e.g. to order expression matrices",
+        value_name = "TSV"
+    )]
+    gene_order: Option<PathBuf>,
+
+    #[clap(
+        long = "quality-report",
+        help = "Write annotation quality metrics (span violations, exon gap quantiles) as JSON to this file",
+        value_name = "JSON"
+    )]
+    quality_report: Option<PathBuf>,
+
+    #[clap(
+        long = "resource-report",
+        help = "Write per-phase RSS and CPU usage samples as JSON to this file",
+        value_name = "JSON"
+    )]
+    resource_report: Option<PathBuf>,
+
+    #[clap(
+        long = "sample-interval",
+        help = "How often --resource-report samples RSS and CPU usage [default: 100ms]",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        requires = "resource_report"
+    )]
+    sample_interval: Option<std::time::Duration>,
+
+    #[clap(
+        long = "report",
+        visible_alias = "stats-out",
+        help = "Write a summary of the run (format detection, timings, memory, line counts, records per chromosome and feature type, requested reports) to this file, as key/value TSV if it ends in .tsv and JSON otherwise",
+        value_name = "FILE"
+    )]
+    report: Option<PathBuf>,
+
+    #[clap(
+        long = "progress",
+        help = "Show progress on stderr: bytes parsed, chromosomes indexed and bytes written",
+        conflicts_with = "progress_json"
+    )]
+    progress: bool,
+
+    #[clap(
+        long = "progress-json",
+        help = "Print newline-delimited JSON progress events (phase, chrom, fraction, eta, done/total steps, bytes) to stderr"
+    )]
+    progress_json: bool,
+
+    #[clap(
+        long = "check-ids",
+        help = "Only verify that transcript and exon IDs are unique within their parents, reporting collisions"
+    )]
+    check_ids: bool,
+
+    #[clap(
+        long = "skip-if-sorted",
+        help = "Copy the input through unchanged if it is already sorted"
+    )]
+    skip_if_sorted: bool,
+
+    #[clap(
+        long = "lock-input",
+        help = "Hold a shared flock on the input while reading it, waiting for writers that hold an exclusive one (unix only)"
+    )]
+    lock_input: bool,
+
+    #[clap(
+        long = "section",
+        help = "Sort only the annotation section of a file that wraps it in other text (e.g. a report), copying the text around it through"
+    )]
+    section: bool,
+
+    #[clap(
+        long = "section-start",
+        value_name = "TEXT",
+        allow_hyphen_values = true,
+        help = "The annotation section starts after the first line starting with TEXT, implies --section"
+    )]
+    section_start: Option<String>,
+
+    #[clap(
+        long = "section-end",
+        value_name = "TEXT",
+        allow_hyphen_values = true,
+        help = "The annotation section ends before the first line starting with TEXT, implies --section"
+    )]
+    section_end: Option<String>,
+
+    #[clap(
+        long = "validate-output",
+        help = "Check every output line for 9 columns and start <= end before writing: error (default), drop or keep malformed lines",
+        value_name = "POLICY",
+        num_args = 0..=1,
+        default_missing_value = "error"
+    )]
+    validate_output: Option<validate::ValidatePolicy>,
+
+    #[clap(
+        long = "verify",
+        help = "Count the records of each feature type in the input and again in the written output, failing if any went missing"
+    )]
+    verify: bool,
+
+    #[clap(
+        long = "tmpdir",
+        help = "Directory for temporary files, defaults to $TMPDIR",
+        value_name = "DIR"
+    )]
+    tmpdir: Option<PathBuf>,
+
+    #[clap(
+        long = "stage-dir",
+        help = "Write the output to this local directory first, then move it into place (checksum-verified across filesystems)",
+        value_name = "DIR"
+    )]
+    stage_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "to",
+        help = "Write this format derived from the sorted index instead of the sorted annotation: saf (distinct exons per gene, for featureCounts -F SAF), saf-gene (gene spans), refflat (one line per transcript, for Picard) or bed12 (one line per transcript)",
+        value_name = "FORMAT"
+    )]
+    to: Option<export::ExportFormat>,
+}
+
+/// The -o files of a run, see [Args::outputs].
+#[derive(Debug, Default)]
+struct Outputs {
+    /// The output of the job, with the export written to it if not the sorted annotation.
+    main: Option<(PathBuf, Option<export::ExportFormat>)>,
+    /// Exports written alongside, see [SortOptions::extra_outputs].
+    extra: Vec<(PathBuf, export::ExportFormat)>,
+    /// Receives the run report, like --report.
+    report: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Compare an annotation with its liftover: genes are matched by gene_id, transcripts by
+    /// transcript_id, and coordinate shifts, missing transcripts and exon count changes are
+    /// reported
+    #[clap(name = "compare-liftover")]
+    CompareLiftover {
+        #[clap(help = "Annotation before the liftover", value_name = "OLD")]
+        old: PathBuf,
+
+        #[clap(help = "Lifted annotation", value_name = "NEW")]
+        new: PathBuf,
+
+        #[clap(
+            short = 'o',
+            long = "output",
+            help = "Write the per-transcript report (TSV) to this file instead of stdout",
+            value_name = "REPORT"
+        )]
+        output: Option<PathBuf>,
+    },
+
+    /// Explain how an input is interpreted without sorting it: detected format, role of
+    /// each feature type, attribute keys used, records dropped for missing parents and
+    /// chromosome order
+    Explain {
+        #[clap(help = "Annotation to explain", value_name = "INPUT")]
+        input: PathBuf,
+
+        #[clap(
+            long = "format",
+            help = "Input format (gtf, gff3, or auto to detect it from the attribute columns), guessed from the file extension if not set",
+            value_name = "FORMAT",
+            value_parser = parse_format
+        )]
+        format: Option<FormatArg>,
+
+        #[clap(
+            long = "feature-map",
+            help = "File listing the exon-level features one per line, as for sorting",
+            value_name = "FILE"
+        )]
+        feature_map: Option<PathBuf>,
+
+        #[clap(
+            long = "feature-layers",
+            help = "File mapping feature types to layers, as for sorting",
+            value_name = "FILE"
+        )]
+        feature_layers: Option<PathBuf>,
+
+        #[clap(
+            long = "synthesize-parents",
+            help = "Explain the input as sorted with --synthesize-parents"
+        )]
+        synthesize_parents: bool,
+
+        #[clap(
+            long = "infer-genes",
+            help = "Explain the input as sorted with --infer-genes"
+        )]
+        infer_genes: bool,
+
+        #[clap(
+            long = "recover-transcripts",
+            help = "Explain the input as sorted with --recover-transcripts"
+        )]
+        recover_transcripts: bool,
+
+        #[clap(
+            short = 'o',
+            long = "output",
+            help = "Write the explanation to this file instead of stdout",
+            value_name = "REPORT"
+        )]
+        output: Option<PathBuf>,
+    },
+    /// Check an annotation for structural problems without sorting it: orphan
+    /// transcripts, genes without transcripts, transcripts without exons, end before start,
+    /// non-numeric exon_number and mixed chromosome naming. Prints a JSON report and exits
+    /// with 2 if any problem is found
+    Check {
+        #[clap(help = "Annotation to check", value_name = "INPUT")]
+        input: PathBuf,
+
+        #[clap(
+            long = "format",
+            help = "Input format (gtf, gff3, or auto to detect it from the attribute columns), guessed from the file extension if not set",
+            value_name = "FORMAT",
+            value_parser = parse_format
+        )]
+        format: Option<FormatArg>,
+
+        #[clap(
+            long = "feature-layers",
+            help = "File mapping feature types to layers, as for sorting",
+            value_name = "FILE"
+        )]
+        feature_layers: Option<PathBuf>,
+
+        #[clap(
+            short = 'o',
+            long = "output",
+            help = "Write the JSON report to this file instead of stdout",
+            value_name = "REPORT"
+        )]
+        output: Option<PathBuf>,
+    },
 }
 
 impl Args {
+    /// The first input file, always set unless a subcommand or --version-json is run.
+    fn input(&self) -> &Path {
+        self.input
+            .first()
+            .expect("--input is required without a subcommand or --version-json")
+    }
+
+    /// Whether the inputs are merged, see [gtfsort::merge].
+    fn merged(&self) -> bool {
+        self.input.len() > 1 || self.tag_source.is_some()
+    }
+
     /// Checks all the arguments for validity using validate_args()
     pub fn check(&self) -> Result<(), GtfSortError> {
         self.validate_args()
     }
 
-    /// Checks the input file for validity. The file must exist and be a GTF or GFF3 file.
-    /// If the file does not exist, an GtfSortError is returned.
+    /// Checks the input files for validity. Each file must exist and be readable, an empty
+    /// file sorts into an empty output. Unless --format is given, it must also have a GTF or GFF3 extension,
+    /// optionally followed by .gz. Gzip and bgzip inputs are decompressed, other binary inputs are rejected.
+    /// If any of these checks fail, an GtfSortError is returned.
     fn check_input(&self) -> Result<(), GtfSortError> {
-        if !self.input.exists() {
-            let err = format!("file {:?} does not exist", self.input);
-            Err(GtfSortError::InvalidInput(err))
-        } else if !self.input.extension().unwrap().eq("gff")
-            & !self.input.extension().unwrap().eq("gtf")
-            & !self.input.extension().unwrap().eq("gff3")
-        {
-            let err = format!(
-                "file {:?} is not a GTF or GFF3 file, please specify the correct format",
-                self.input
-            );
-            return Err(GtfSortError::InvalidInput(err));
-        } else if std::fs::metadata(&self.input).unwrap().len() == 0 {
-            let err = format!("file {:?} is empty", self.input);
-            return Err(GtfSortError::InvalidInput(err));
-        } else {
-            Ok(())
+        if self.input.len() > 1 && self.check_ids {
+            return Err(GtfSortError::InvalidParameter(
+                "--check-ids takes a single input",
+            ));
+        }
+        self.input.iter().try_for_each(|input| {
+            if is_stdio(input) {
+                // nothing to check before it is read
+                Ok(())
+            } else if !input.exists() {
+                let err = format!("file {:?} does not exist", input);
+                Err(GtfSortError::InvalidInput(err))
+            } else if let Err(e) = std::fs::File::open(input) {
+                let err = format!("file {:?} is not readable: {}", input, e);
+                Err(GtfSortError::InvalidInput(err))
+            } else if self.format.is_none() && AnnotationFormat::from_extension(input).is_none() {
+                let err = format!(
+                    "{}, please specify the format with --format gtf|gff3 or --format auto",
+                    AnnotationFormat::unknown_extension_reason(input)
+                );
+                Err(GtfSortError::InvalidInput(err))
+            } else {
+                sniff::check_file(input)
+            }
+        })
+    }
+
+    /// Sorts the -o files by what they receive: the first takes --to if given, files
+    /// named after an export format take that export, a .json file the run report and
+    /// the rest the sorted annotation, which must have a GTF or GFF3 extension unless
+    /// --format is given or it is - (stdout). The sorted annotation, or the first export
+    /// if there is none, is the main output of the job. Without any -o, it goes to stdout.
+    fn outputs(&self) -> Result<Outputs, GtfSortError> {
+        let mut outputs = Outputs::default();
+        let mut annotation: Option<&PathBuf> = None;
+        let mut exports = Vec::new();
+
+        let stdout = [PathBuf::from(STDIO_PATH)];
+        let paths = match self.output.is_empty() {
+            true => &stdout[..],
+            false => &self.output[..],
+        };
+        for (i, path) in paths.iter().enumerate() {
+            let export = match self.to {
+                Some(to) if i == 0 => Some(to),
+                _ if is_stdio(path) => None,
+                _ => export::ExportFormat::from_extension(path),
+            };
+            let json = path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+
+            if let Some(format) = export {
+                exports.push((path.clone(), format));
+            } else if json {
+                if self.report.is_some() || outputs.report.is_some() {
+                    let err = format!("{:?}: only one run report can be written", path);
+                    return Err(GtfSortError::InvalidOutput(err));
+                }
+                outputs.report = Some(path.clone());
+            } else if self.format.is_some()
+                || is_stdio(path)
+                || AnnotationFormat::from_extension(path).is_some()
+            {
+                if let Some(first) = annotation {
+                    let err = format!(
+                        "{:?} and {:?} would both hold the sorted annotation",
+                        first, path
+                    );
+                    return Err(GtfSortError::InvalidOutput(err));
+                }
+                annotation = Some(path);
+            } else {
+                let err = format!(
+                    "{}, please name the output .gtf/.gff3 (or .saf, .refflat, .bed12 or .json for derived outputs) or specify the format with --format gtf|gff3",
+                    AnnotationFormat::unknown_extension_reason(path)
+                );
+                return Err(GtfSortError::InvalidOutput(err));
+            }
         }
+
+        let mut exports = exports.into_iter();
+        outputs.main = match annotation {
+            Some(path) => Some((path.clone(), None)),
+            None => exports.next().map(|(path, format)| (path, Some(format))),
+        };
+        outputs.extra = exports.collect();
+        Ok(outputs)
     }
 
-    /// Checks the output file for validity. If the file is not a BED file, an GtfSortError is returned.
+    /// Checks the output files for validity, see [Self::outputs].
     fn check_output(&self) -> Result<(), GtfSortError> {
-        if !self.output.extension().unwrap().eq("gtf")
-            & !self.output.extension().unwrap().eq("gff3")
-            & !self.output.extension().unwrap().eq("gff")
-        {
-            let err = format!(
-                "file {:?} is not a GTF/GFF file, please specify the correct output format",
-                self.output
-            );
-            Err(GtfSortError::InvalidOutput(err))
-        } else {
-            Ok(())
+        let outputs = self.outputs()?;
+        if outputs.main.is_none() {
+            return Err(GtfSortError::InvalidOutput(
+                "a run report is not an output on its own, please add a sorted annotation or export output".to_string(),
+            ));
         }
+        Ok(())
     }
 
     /// Checks the number of threads for validity. The number of threads must be greater than 0
@@ -124,23 +830,105 @@ impl Args {
         }
     }
 
+    /// Checks that temporary files can be created in --tmpdir, or $TMPDIR if not given.
+    fn check_tmpdir(&self) -> Result<(), GtfSortError> {
+        temp::TempPath::file(self.tmpdir.as_deref(), "probe", "")
+            .map(drop)
+            .map_err(|e| GtfSortError::IoError("creating a file in the temporary directory", e))
+    }
+
+    /// Checks that the output can be staged in --stage-dir, if given.
+    fn check_stage_dir(&self) -> Result<(), GtfSortError> {
+        match &self.stage_dir {
+            Some(dir) => stage::staging_file(dir)
+                .map(drop)
+                .map_err(|e| GtfSortError::IoError("creating a file in the staging directory", e)),
+            None => Ok(()),
+        }
+    }
+
     /// Validates all the arguments
     fn validate_args(&self) -> Result<(), GtfSortError> {
         self.check_input()?;
         self.check_output()?;
         self.check_threads()?;
+        self.check_tmpdir()?;
+        self.check_stage_dir()?;
+        self.check_sort_order()?;
         Ok(())
     }
+
+    fn check_sort_order(&self) -> Result<(), GtfSortError> {
+        use chrom_order::ChromSortOrder;
+        match (self.sort_order, &self.chr_order_list) {
+            (ChromSortOrder::Custom, None) => Err(GtfSortError::InvalidParameter(
+                "--sort-order custom needs --chr-order-list",
+            )),
+            (ChromSortOrder::Lex | ChromSortOrder::Karyotype, Some(_)) => {
+                Err(GtfSortError::InvalidParameter(
+                    "--chr-order-list sets the chromosome order, it cannot be combined with --sort-order lex or karyotype",
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 fn main() {
-    simple_logger::init_with_level(Level::Info).unwrap();
+    logging::try_init_logger(Level::Info);
     let args = Args::parse();
+    if args.version_json {
+        println!("{}", version::to_json());
+        return;
+    }
+    match args.command {
+        Some(Command::CompareLiftover { old, new, output }) => {
+            compare_liftover(&old, &new, output.as_deref());
+            return;
+        }
+        Some(Command::Explain {
+            input,
+            format,
+            feature_map,
+            feature_layers,
+            synthesize_parents,
+            infer_genes,
+            recover_transcripts,
+            output,
+        }) => {
+            explain(
+                &input,
+                format,
+                feature_map.as_deref(),
+                feature_layers.as_deref(),
+                synthesize_parents,
+                infer_genes,
+                recover_transcripts,
+                output.as_deref(),
+            );
+            return;
+        }
+        Some(Command::Check {
+            input,
+            format,
+            feature_layers,
+            output,
+        }) => {
+            check(&input, format, feature_layers.as_deref(), output.as_deref());
+            return;
+        }
+        None => {}
+    }
     args.check().unwrap_or_else(|e| {
         log::error!("{:?}", e);
         std::process::exit(1);
     });
 
+    if args.check_ids {
+        check_ids(args);
+        return;
+    }
+
     run(args);
 
     log::info!(
@@ -155,15 +943,449 @@ fn run(args: Args) {
 
     let start = std::time::Instant::now();
 
-    let job_info = sort_annotations(&args.input, &args.output, args.threads).unwrap_or_else(|e| {
+    let read_ids = |path: &PathBuf| {
+        IdFilter::from_file(path, args.invert).unwrap_or_else(|e| {
+            log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+            std::process::exit(1);
+        })
+    };
+
+    let outputs = args.outputs().expect("outputs are validated");
+    let (output, export) = outputs
+        .main
+        .as_ref()
+        .expect("output is required unless --check-ids");
+
+    let options = SortOptions {
+        format: match args.format {
+            Some(FormatArg::Fixed(format)) => Some(format),
+            _ => None,
+        },
+        detect_format: matches!(args.format, Some(FormatArg::Auto)),
+        max_rss_mb: args.max_rss,
+        max_mem_mb: args.max_mem,
+        source_tag: args.tag_source.clone(),
+        timeout: args.timeout,
+        genes: args.genes.as_ref().map(read_ids),
+        transcripts: args.transcripts.as_ref().map(read_ids),
+        chromosomes: args.chromosomes.as_deref().map(ChromFilter::from_list),
+        features: args.feature.as_deref().map(IdFilter::from_comma_list),
+        gene_biotypes: args.gene_biotype.as_deref().map(IdFilter::from_comma_list),
+        region: args
+            .region
+            .clone()
+            .map(|r| r.with_overlap(args.region_overlap)),
+        dedup: args.dedup,
+        recompute_gene_bounds: args.recompute_gene_bounds,
+        head_genes: args.head_genes,
+        synthesize_parents: args.synthesize_parents,
+        infer_genes: args.infer_genes,
+        recover_transcripts: args.recover_transcripts,
+        skeleton: args.skeleton,
+        // tabix needs the records ordered by start
+        coordinate_only: args.coordinate_only || args.index.is_some(),
+        newline: args.newline,
+        output_compression: args.output_compression.unwrap_or(match args.index {
+            Some(_) => OutputCompression::Bgzf,
+            None => OutputCompression::None,
+        }),
+        index: args.index,
+        drop_header: args.drop_header,
+        dedup_attrs: args.dedup_attrs,
+        attr_normalize: args.attr_normalize,
+        chrom_rename: match (args.rename_chroms, &args.chrom_aliases) {
+            (None, None) => None,
+            (naming, aliases) => {
+                let renamer = ChromRenamer::new(naming);
+                Some(match aliases {
+                    Some(path) => renamer.with_alias_file(path).unwrap_or_else(|e| {
+                        log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+                        std::process::exit(1);
+                    }),
+                    None => renamer,
+                })
+            }
+        },
+        unnumbered_order: args.unnumbered_order,
+        gene_sort_key: args.gene_sort_key.clone(),
+        stable: args.stable,
+        transcript_order: args.transcript_order,
+        feature_map: args
+            .feature_map
+            .as_ref()
+            .map(|path| {
+                FeatureMap::from_file(path).unwrap_or_else(|e| {
+                    log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+                    std::process::exit(1);
+                })
+            })
+            .unwrap_or_default(),
+        layer_map: args
+            .feature_layers
+            .as_ref()
+            .map(|path| {
+                LayerMap::from_file(path).unwrap_or_else(|e| {
+                    log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+                    std::process::exit(1);
+                })
+            })
+            .unwrap_or_default(),
+        chrom_order: args.chr_order_list.as_ref().map(|path| {
+            chrom_order::ChromOrder::from_file(path)
+                .unwrap_or_else(|e| {
+                    log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+                    std::process::exit(1);
+                })
+                .strict(args.strict_order)
+        }),
+        chrom_sort: args.sort_order,
+        validate_output: args.validate_output,
+        verify: args.verify,
+        parse_mode: match args.strict {
+            true => ParseMode::Strict,
+            false => ParseMode::Lenient,
+        },
+        offset_index: args.offset_index.clone(),
+        rejects: args.rejects.clone(),
+        gene_order: args.gene_order.clone(),
+        mmap_window: args.mmap_window.map(|mb| (mb * 1024.0 * 1024.0) as usize),
+        no_mmap: args.no_mmap,
+        compress_lines: args.compress_lines,
+        low_memory: args.low_memory,
+        manifest: args.manifest.clone(),
+        incremental: args.incremental.clone(),
+        progress: if args.progress_json {
+            Some(progress::Progress::ndjson(std::io::stderr()))
+        } else if args.progress {
+            use std::io::IsTerminal;
+            let overwrite = std::io::stderr().is_terminal();
+            Some(progress::Progress::text(std::io::stderr(), overwrite))
+        } else {
+            None
+        },
+        skip_if_sorted: args.skip_if_sorted,
+        lock_input: args.lock_input,
+        section: (args.section || args.section_start.is_some() || args.section_end.is_some()).then(
+            || section::SectionMarkers {
+                start: args.section_start.clone(),
+                end: args.section_end.clone(),
+            },
+        ),
+        tmpdir: args.tmpdir.clone(),
+        stage_dir: args.stage_dir.clone(),
+        export: *export,
+        extra_outputs: outputs.extra.clone(),
+        quality_metrics: args.quality_report.is_some(),
+        sample_interval: args.resource_report.is_some().then(|| {
+            args.sample_interval
+                .unwrap_or(sampler::DEFAULT_SAMPLE_INTERVAL)
+        }),
+        ..Default::default()
+    };
+
+    let job_info = match args.merged() {
+        true => {
+            let inputs = args.input.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+            sort_annotations_merged(&inputs, output, args.threads, &options)
+        }
+        false => sort_annotations_with_options(args.input(), output, args.threads, &options),
+    }
+    .unwrap_or_else(|e| {
+        if e.is_broken_pipe() {
+            log::debug!("output closed early: {}", e);
+            std::process::exit(EXIT_BROKEN_PIPE);
+        }
         log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+        if e.code() == ErrorCode::MemoryLimitExceeded {
+            log::error!("{} {}", "Hint:".bright_yellow().bold(), MEMORY_LIMIT_HINT);
+        }
+        if e.code() == ErrorCode::Timeout {
+            std::process::exit(EXIT_TIMEOUT);
+        }
         std::process::exit(1);
     });
 
+    if let (Some(path), Some(quality)) = (&args.quality_report, &job_info.quality) {
+        log::info!(
+            "Quality: {} genes, {} transcripts, {} span violations",
+            quality.genes,
+            quality.transcripts,
+            quality.violations()
+        );
+        std::fs::write(path, quality.to_json() + "\n").unwrap_or_else(|e| {
+            log::error!(
+                "{}: {}",
+                "Fatal GtfSortError".bright_red().bold(),
+                GtfSortError::IoError("writing quality report", e)
+            );
+            std::process::exit(1);
+        });
+    }
+
+    if let (Some(path), Some(resources)) = (&args.resource_report, &job_info.resources) {
+        for p in resources.phases() {
+            log::info!(
+                "Resources: {} phase, {:.0}% CPU on average, {:.1} MB peak RSS",
+                p.phase,
+                p.mean_cpu_percent,
+                p.peak_rss_mb
+            );
+        }
+        std::fs::write(path, resources.to_json() + "\n").unwrap_or_else(|e| {
+            log::error!(
+                "{}: {}",
+                "Fatal GtfSortError".bright_red().bold(),
+                GtfSortError::IoError("writing resource report", e)
+            );
+            std::process::exit(1);
+        });
+    }
+
+    if let Some(path) = args.report.as_ref().or(outputs.report.as_ref()) {
+        let summary = if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("tsv"))
+        {
+            job_info.to_tsv()
+        } else {
+            job_info.to_json() + "\n"
+        };
+        std::fs::write(path, summary).unwrap_or_else(|e| {
+            log::error!(
+                "{}: {}",
+                "Fatal GtfSortError".bright_red().bold(),
+                GtfSortError::IoError("writing run report", e)
+            );
+            std::process::exit(1);
+        });
+    }
+
     let elapsed = start.elapsed().as_secs_f32();
     log::info!("Elapsed time: {:.4} seconds", elapsed);
     log::info!(
         "Memory usage: {:.4} MB",
         job_info.end_mem_mb.unwrap_or(f64::NAN) - job_info.start_mem_mb.unwrap_or(f64::NAN)
     );
+
+    if !job_info.fallbacks.is_empty() {
+        let lost: f64 = job_info.fallbacks.iter().map(|f| f.lost_secs).sum();
+        log::warn!(
+            "{} {} fallback(s) cost {:.2}s of the elapsed time, write amplification {:.2}x; this usually comes from the filesystem, not the sort",
+            "Warning:".bright_yellow().bold(),
+            job_info.fallbacks.len(),
+            lost,
+            job_info.write_amplification().unwrap_or(1.0)
+        );
+        for f in &job_info.fallbacks {
+            log::warn!("  {}", f);
+        }
+    }
+}
+
+/// Reports transcript/exon ID collisions in the input without sorting it, exiting with 1
+/// if any are found.
+fn check_ids(args: Args) {
+    let fatal = |e: GtfSortError| -> ! {
+        log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+        std::process::exit(1);
+    };
+
+    let contents = match is_stdio(args.input()) {
+        true => std::io::read_to_string(std::io::stdin().lock())
+            .unwrap_or_else(|e| fatal(GtfSortError::IoError("reading stdin", e))),
+        false => gzip::read_to_string(args.input()).unwrap_or_else(|e| fatal(e)),
+    };
+
+    let format = match args.format {
+        Some(FormatArg::Fixed(format)) => Some(format),
+        Some(FormatArg::Auto) => {
+            let detection = AnnotationFormat::detect(&contents);
+            log::info!("Detected {}", detection);
+            detection.format
+        }
+        None => None,
+    }
+    .or_else(|| AnnotationFormat::from_extension(args.input()))
+    .unwrap_or_else(|| {
+        fatal(GtfSortError::InvalidInput(
+            "could not detect the input format, please set --format".to_string(),
+        ))
+    });
+
+    let collisions = match format {
+        AnnotationFormat::Gff3 => ids::find_id_collisions::<b'='>(&contents),
+        AnnotationFormat::Gtf => ids::find_id_collisions::<b' '>(&contents),
+    };
+
+    for c in collisions.iter() {
+        log::warn!("{} {}", "ID collision:".bright_yellow().bold(), c);
+    }
+
+    if collisions.is_empty() {
+        log::info!(
+            "{} {}",
+            "Success:".bright_green().bold(),
+            "all transcript and exon IDs are unique"
+        );
+    } else {
+        log::error!(
+            "{} found {} ID collisions",
+            "Error:".bright_red().bold(),
+            collisions.len()
+        );
+        std::process::exit(1);
+    }
+}
+
+fn check(
+    input: &Path,
+    format: Option<FormatArg>,
+    feature_layers: Option<&Path>,
+    output: Option<&Path>,
+) {
+    let fatal = |e: GtfSortError| -> ! {
+        log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+        std::process::exit(1);
+    };
+
+    sniff::check_file(input).unwrap_or_else(|e| fatal(e));
+    let contents = gzip::read_to_string(input).unwrap_or_else(|e| fatal(e));
+    let options = SortOptions {
+        format: match format {
+            Some(FormatArg::Fixed(format)) => Some(format),
+            Some(FormatArg::Auto) => None,
+            None => AnnotationFormat::from_extension(input),
+        },
+        layer_map: feature_layers
+            .map(|path| LayerMap::from_file(path).unwrap_or_else(|e| fatal(e)))
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    let report = gtfsort::check::check(&contents, &options).unwrap_or_else(|e| fatal(e));
+
+    match output {
+        Some(path) => std::fs::write(path, report.to_json() + "\n"),
+        None => std::io::Write::write_all(
+            &mut std::io::stdout().lock(),
+            (report.to_json() + "\n").as_bytes(),
+        ),
+    }
+    .unwrap_or_else(|e| fatal(GtfSortError::IoError("writing check report", e)));
+
+    for issue in report.found() {
+        log::warn!(
+            "{} {} ({}), e.g. {}",
+            "Problem:".bright_yellow().bold(),
+            issue.kind,
+            issue.count,
+            issue.examples.join(", ")
+        );
+    }
+    if report.is_ok() {
+        log::info!("{} {}", "Success:".bright_green().bold(), report);
+    } else {
+        log::error!("{} {}", "Error:".bright_red().bold(), report);
+        std::process::exit(2);
+    }
+}
+
+/// Format of a file given to a subcommand, from its extension or its attribute columns.
+fn sniff_format(path: &Path, contents: &str) -> Result<AnnotationFormat, GtfSortError> {
+    AnnotationFormat::from_extension(path)
+        .or_else(|| AnnotationFormat::detect(contents).format)
+        .ok_or_else(|| {
+            GtfSortError::InvalidInput(format!("could not detect the format of {}", path.display()))
+        })
+}
+
+fn compare_liftover(old: &Path, new: &Path, output: Option<&Path>) {
+    let fatal = |e: GtfSortError| -> ! {
+        log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+        std::process::exit(1);
+    };
+    let read = |path: &Path| {
+        sniff::check_file(path).unwrap_or_else(|e| fatal(e));
+        gzip::read_to_string(path).unwrap_or_else(|e| fatal(e))
+    };
+    let options = SortOptions::default();
+    let spans = |path: &Path, contents| {
+        match sniff_format(path, contents) {
+            Ok(AnnotationFormat::Gtf) => liftover::GeneSpans::parse::<b' '>(contents, &options),
+            Ok(AnnotationFormat::Gff3) => liftover::GeneSpans::parse::<b'='>(contents, &options),
+            Err(e) => Err(e),
+        }
+        .unwrap_or_else(|e| fatal(e))
+    };
+
+    let (old_contents, new_contents) = (read(old), read(new));
+    let report = liftover::compare_liftover(&spans(old, &old_contents), &spans(new, &new_contents));
+
+    match output {
+        Some(path) => {
+            std::fs::File::create(path).and_then(|f| report.write_tsv(std::io::BufWriter::new(f)))
+        }
+        None => report.write_tsv(std::io::stdout().lock()),
+    }
+    .unwrap_or_else(|e| fatal(GtfSortError::IoError("writing report", e)));
+
+    log::info!("{} {}", "Liftover:".bright_green().bold(), report);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn explain(
+    input: &Path,
+    format: Option<FormatArg>,
+    feature_map: Option<&Path>,
+    feature_layers: Option<&Path>,
+    synthesize_parents: bool,
+    infer_genes: bool,
+    recover_transcripts: bool,
+    output: Option<&Path>,
+) {
+    let fatal = |e: GtfSortError| -> ! {
+        log::error!("{}: {}", "Fatal GtfSortError".bright_red().bold(), e);
+        std::process::exit(1);
+    };
+
+    sniff::check_file(input).unwrap_or_else(|e| fatal(e));
+    let contents = gzip::read_to_string(input).unwrap_or_else(|e| fatal(e));
+    let options = SortOptions {
+        format: match format {
+            Some(FormatArg::Fixed(format)) => Some(format),
+            Some(FormatArg::Auto) => None,
+            None => AnnotationFormat::from_extension(input),
+        },
+        feature_map: feature_map
+            .map(|path| FeatureMap::from_file(path).unwrap_or_else(|e| fatal(e)))
+            .unwrap_or_default(),
+        layer_map: feature_layers
+            .map(|path| LayerMap::from_file(path).unwrap_or_else(|e| fatal(e)))
+            .unwrap_or_default(),
+        synthesize_parents,
+        infer_genes,
+        recover_transcripts,
+        ..Default::default()
+    };
+    let explanation = gtfsort::explain::explain(&contents, &options).unwrap_or_else(|e| fatal(e));
+
+    match output {
+        Some(path) => std::fs::File::create(path)
+            .and_then(|f| explanation.write_report(std::io::BufWriter::new(f))),
+        None => explanation.write_report(std::io::stdout().lock()),
+    }
+    .unwrap_or_else(|e| fatal(GtfSortError::IoError("writing explanation", e)));
+
+    log::info!("{} {}", "Explain:".bright_green().bold(), explanation);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_hint_suggests_the_low_memory_mode_first() {
+        let low_memory = MEMORY_LIMIT_HINT.find("--low-memory").unwrap();
+        assert!(MEMORY_LIMIT_HINT.contains("--max-mem"));
+        assert!(MEMORY_LIMIT_HINT.find("--threads").unwrap() > low_memory);
+    }
 }
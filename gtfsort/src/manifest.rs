@@ -0,0 +1,428 @@
+use hashbrown::HashMap;
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    format::{AnnotationFormat, Newline},
+    par::*,
+    Chrom, ChromRecord, ErrorContext, GtfSortError, Index, Layer, LayerMap, Record, SortOptions,
+};
+
+/// First line of a manifest, with the version of its layout.
+pub const MANIFEST_HEADER: &str = "#gtfsort-manifest\t1";
+
+/// Content hash of the input lines of each gene, keyed by (chrom, gene_id).
+pub type InputHashes<'a> = HashMap<(Chrom<'a>, &'a str), u64>;
+
+/// Sorted blocks of a previous output that can be copied as they are, keyed by
+/// (chrom, gene_id).
+pub type Reuse<'a> = HashMap<(Chrom<'a>, &'a str), &'a str>;
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64-bit FNV-1a, stable across platforms and releases unlike std's hasher.
+#[inline(always)]
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Hashes and counts everything written to it.
+struct HashWriter {
+    hash: u64,
+    len: u64,
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hash = fnv1a(self.hash, buf);
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where the sorted block of one gene sits in an output, and what it was sorted from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub chrom: String,
+    pub gene_id: String,
+    /// Hash of the input lines of the gene, in input order.
+    pub input_hash: u64,
+    /// Byte offset of the block in the output.
+    pub offset: u64,
+    pub len: u64,
+    /// Hash of the block as written, to catch outputs edited after the fact.
+    pub output_hash: u64,
+}
+
+/// Per-gene content hashes of a sort run, used by [SortOptions::incremental] to copy
+/// unchanged genes from the previous output instead of sorting them again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Hash of everything besides the input that shapes the output, see [fingerprint].
+    pub fingerprint: u64,
+    /// The output the blocks point into, None if the job wrote to a stream.
+    pub output: Option<PathBuf>,
+    /// In output order.
+    pub genes: Vec<ManifestEntry>,
+}
+
+/// Hashes the version and the options a gene block depends on; manifests with a
+/// different fingerprint are never reused.
+pub fn fingerprint(format: AnnotationFormat, options: &SortOptions) -> u64 {
+    let mut key = String::new();
+    write!(
+        key,
+        "{}\t{}\t{}\t{}\t{:?}\t{}\t{}",
+        env!("CARGO_PKG_VERSION"),
+        format,
+        options.newline,
+        options.skeleton,
+        options.dedup_attrs,
+        options.attr_normalize,
+        options.recover_transcripts
+    )
+    .unwrap();
+    for feat in options.feature_map.features() {
+        write!(key, "\t{}", feat).unwrap();
+    }
+    for (feat, layer) in options.layer_map.layers() {
+        write!(key, "\t{}={:?}", feat, layer).unwrap();
+    }
+    fnv1a(FNV_OFFSET, key.as_bytes())
+}
+
+/// Returns why blocks of a previous output cannot stand for genes of this job, if so:
+/// selections, synthesized lines and orderings depend on more than the lines of each
+/// gene. The options hashed into the [fingerprint] only rewrite each gene on its own.
+pub fn incremental_blocker(options: &SortOptions) -> Option<&'static str> {
+    let unhashed = SortOptions {
+        newline: Newline::Lf,
+        skeleton: false,
+        dedup_attrs: None,
+        attr_normalize: false,
+        recover_transcripts: false,
+        layer_map: LayerMap::default(),
+        ..options.clone()
+    };
+    if unhashed.alters_records() {
+        Some(
+            "it cannot be combined with options that select, synthesize, rename or reorder records",
+        )
+    } else if options.quality_metrics {
+        Some("quality metrics need every gene to be indexed")
+    } else {
+        None
+    }
+}
+
+/// Hashes the input lines of every gene that has a gene line (see [LayerMap]), in input
+/// order.
+pub fn input_hashes<'a>(records: &ChromRecord<'a>, layers: &LayerMap) -> InputHashes<'a> {
+    records
+        .par_iter()
+        .flat_map_iter(|(chrom, lines)| {
+            let mut genes: HashMap<&str, (bool, Vec<&Record>)> = HashMap::new();
+            for record in lines {
+                let gene = genes.entry(record.gene_id).or_default();
+                gene.0 |= record.layer_by(layers) == Layer::Gene;
+                gene.1.push(record);
+            }
+
+            genes
+                .into_iter()
+                .filter(|(_, (has_gene, _))| *has_gene)
+                .map(move |(gene_id, (_, mut lines))| {
+                    lines.sort_unstable_by_key(|r| r.ordinal);
+                    let hash = lines
+                        .iter()
+                        .fold(FNV_OFFSET, |h, r| fnv1a(fnv1a(h, r.line.as_bytes()), b"\n"));
+                    ((*chrom, gene_id), hash)
+                })
+        })
+        .collect()
+}
+
+impl Manifest {
+    /// Describes the output written from `index`, chromosomes in `keys` order, after a
+    /// header of `header_size` bytes.
+    pub fn from_index<'a>(
+        fingerprint: u64,
+        output: Option<&Path>,
+        index: &Index<'a>,
+        keys: &[Chrom<'a>],
+        hashes: &InputHashes<'a>,
+        header_size: u64,
+        newline: crate::format::Newline,
+    ) -> Self {
+        let mut genes = Vec::new();
+        let mut offset = header_size;
+
+        for chrom in keys {
+            let chr = index.get(chrom).unwrap();
+            for gene in chr.layer.iter() {
+                let mut w = HashWriter {
+                    hash: FNV_OFFSET,
+                    len: 0,
+                };
+                chr.write_gene(&mut w, gene, newline).unwrap();
+
+                if let Some(input_hash) = hashes.get(&(*chrom, gene.1)) {
+                    genes.push(ManifestEntry {
+                        chrom: chrom.to_string(),
+                        gene_id: gene.1.to_string(),
+                        input_hash: *input_hash,
+                        offset,
+                        len: w.len,
+                        output_hash: w.hash,
+                    });
+                }
+                offset += w.len;
+            }
+        }
+
+        Self {
+            fingerprint,
+            output: output.map(Path::to_path_buf),
+            genes,
+        }
+    }
+
+    /// Writes the manifest as TSV, `chrom  gene_id  input_hash  offset  len  output_hash`
+    /// under a few `#key\tvalue` header lines.
+    pub fn write_tsv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "{}", MANIFEST_HEADER)?;
+        writeln!(w, "#fingerprint\t{:016x}", self.fingerprint)?;
+        if let Some(output) = &self.output {
+            writeln!(w, "#output\t{}", output.display())?;
+        }
+        writeln!(w, "#chrom\tgene_id\tinput_hash\toffset\tlen\toutput_hash")?;
+        for g in self.genes.iter() {
+            writeln!(
+                w,
+                "{}\t{}\t{:016x}\t{}\t{}\t{:016x}",
+                g.chrom, g.gene_id, g.input_hash, g.offset, g.len, g.output_hash
+            )?;
+        }
+        w.flush()
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_tsv(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, GtfSortError> {
+        let invalid = |n: usize, what: &str| {
+            GtfSortError::InvalidInput(format!("manifest: {}", what))
+                .with_context(ErrorContext::phase("reading manifest").with_line(n + 1))
+        };
+        let hex =
+            |n: usize, v: &str| u64::from_str_radix(v, 16).map_err(|_| invalid(n, "bad hash"));
+        let dec = |n: usize, v: &str| v.parse::<u64>().map_err(|_| invalid(n, "bad offset"));
+
+        let mut lines = contents.lines().enumerate();
+        match lines.next() {
+            Some((_, MANIFEST_HEADER)) => {}
+            _ => {
+                return Err(invalid(
+                    0,
+                    "not a gtfsort manifest (or an unsupported version)",
+                ))
+            }
+        }
+
+        let mut manifest = Self::default();
+        for (n, line) in lines {
+            if let Some(header) = line.strip_prefix('#') {
+                match header.split_once('\t') {
+                    Some(("fingerprint", v)) => manifest.fingerprint = hex(n, v)?,
+                    Some(("output", v)) => manifest.output = Some(PathBuf::from(v)),
+                    _ => {}
+                }
+                continue;
+            }
+
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let [chrom, gene_id, input_hash, offset, len, output_hash] = fields[..] else {
+                return Err(invalid(n, "expected 6 columns"));
+            };
+            manifest.genes.push(ManifestEntry {
+                chrom: chrom.to_string(),
+                gene_id: gene_id.to_string(),
+                input_hash: hex(n, input_hash)?,
+                offset: dec(n, offset)?,
+                len: dec(n, len)?,
+                output_hash: hex(n, output_hash)?,
+            });
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Self, GtfSortError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| GtfSortError::IoError("reading manifest", e))?;
+        Self::parse(&contents)
+    }
+
+    /// Picks the blocks of `previous` (the output this manifest describes) whose gene
+    /// has the same input lines in `hashes`, skipping blocks that no longer match their
+    /// output hash.
+    pub fn reusable<'a>(&self, hashes: &InputHashes<'a>, previous: &'a str) -> Reuse<'a> {
+        let entries = self
+            .genes
+            .iter()
+            .map(|g| ((g.chrom.as_str(), g.gene_id.as_str()), g))
+            .collect::<HashMap<_, _>>();
+
+        hashes
+            .iter()
+            .filter_map(|(key, input_hash)| {
+                let g = entries.get(key).filter(|g| g.input_hash == *input_hash)?;
+                let block = previous.get(g.offset as usize..(g.offset + g.len) as usize)?;
+                (fnv1a(FNV_OFFSET, block.as_bytes()) == g.output_hash).then_some((*key, block))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sort_annotations_with_options;
+
+    const GTF: &str = "chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+        chr2\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr2\ts\texon\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr1\ts\tgene\t20\t29\t.\t+\t.\tgene_id \"C\";\n\
+        chr1\ts\ttranscript\t20\t29\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+        chr1\ts\texon\t20\t29\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\"; exon_number \"1\";\n\
+        chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+
+    #[test]
+    fn round_trips() {
+        let manifest = Manifest {
+            fingerprint: 42,
+            output: Some(PathBuf::from("out.gtf")),
+            genes: vec![ManifestEntry {
+                chrom: "chr1".to_string(),
+                gene_id: "A".to_string(),
+                input_hash: u64::MAX,
+                offset: 0,
+                len: 10,
+                output_hash: 7,
+            }],
+        };
+
+        let mut tsv = Vec::new();
+        manifest.write_tsv(&mut tsv).unwrap();
+        assert_eq!(
+            Manifest::parse(std::str::from_utf8(&tsv).unwrap()).unwrap(),
+            manifest
+        );
+        assert!(Manifest::parse("chr1\tA\n").is_err());
+    }
+
+    #[test]
+    fn unchanged_genes_are_copied() {
+        let dir = crate::temp::TempPath::dir(None, "manifest_test").unwrap();
+        let (input, output, manifest) = (
+            dir.join("in.gtf"),
+            dir.join("out.gtf"),
+            dir.join("manifest.tsv"),
+        );
+
+        std::fs::write(&input, GTF).unwrap();
+        let first = SortOptions {
+            manifest: Some(manifest.clone()),
+            ..Default::default()
+        };
+        sort_annotations_with_options(&input, &output, 2, &first).unwrap();
+        let full = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(Manifest::read_from(&manifest).unwrap().genes.len(), 3);
+
+        // edit gene C only
+        let edited = GTF.replace("\t29\t", "\t30\t");
+        std::fs::write(&input, &edited).unwrap();
+        let incremental = SortOptions {
+            incremental: Some(manifest.clone()),
+            manifest: Some(manifest.clone()),
+            ..Default::default()
+        };
+        let ret = sort_annotations_with_options(&input, &output, 2, &incremental).unwrap();
+        assert_eq!(ret.reused_genes, Some(2));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            full.replace("\t29\t", "\t30\t")
+        );
+
+        // a different newline style invalidates the whole manifest
+        let crlf = SortOptions {
+            newline: Newline::Crlf,
+            ..incremental
+        };
+        let ret = sort_annotations_with_options(&input, &output, 2, &crlf).unwrap();
+        assert_eq!(ret.reused_genes, Some(0));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            full.replace("\t29\t", "\t30\t").replace('\n', "\r\n")
+        );
+    }
+
+    #[test]
+    fn record_altering_options_block_reuse() {
+        let skeleton = SortOptions {
+            skeleton: true,
+            newline: Newline::Crlf,
+            ..Default::default()
+        };
+        assert_eq!(incremental_blocker(&skeleton), None);
+        for options in [
+            SortOptions {
+                synthesize_parents: true,
+                ..skeleton.clone()
+            },
+            SortOptions {
+                gene_sort_key: crate::GeneSortKey::Length,
+                ..skeleton.clone()
+            },
+        ] {
+            assert!(incremental_blocker(&options).is_some());
+        }
+    }
+
+    #[test]
+    fn fingerprint_covers_feature_maps() {
+        let base = fingerprint(AnnotationFormat::Gtf, &SortOptions::default());
+        let features = SortOptions {
+            feature_map: crate::features::FeatureMap::new(["CDS", "exon"]).unwrap(),
+            ..Default::default()
+        };
+        let layers = SortOptions {
+            layer_map: LayerMap::from_list("mRNA gene\n").unwrap(),
+            ..Default::default()
+        };
+        assert_ne!(fingerprint(AnnotationFormat::Gtf, &features), base);
+        assert_ne!(fingerprint(AnnotationFormat::Gtf, &layers), base);
+        assert_eq!(incremental_blocker(&layers), None);
+
+        let recover = SortOptions {
+            recover_transcripts: true,
+            ..Default::default()
+        };
+        assert_ne!(fingerprint(AnnotationFormat::Gtf, &recover), base);
+        assert_eq!(incremental_blocker(&recover), None);
+    }
+}
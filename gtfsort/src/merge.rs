@@ -0,0 +1,405 @@
+//! Sorting several inputs as one, e.g. per-chromosome files or a reference annotation
+//! and custom additions, see [sort_annotations_merged].
+//!
+//! The inputs are streamed line by line, tagged with [SortOptions::source_tag] if set,
+//! one after the other to a file in [SortOptions::tmpdir] that is sorted like any input,
+//! so every backend and output of a file sort is available and no input is held in
+//! memory. Only the leading comments of the first input make it to the output header,
+//! and the `##FASTA` sections ending GFF3 inputs are set aside and moved after the last
+//! annotation.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufRead, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    format::{AnnotationFormat, DETECT_SAMPLE_LINES},
+    gzip, is_stdio, sniff, temp, timed, GtfSortError, SortAnnotationsJobResult, SortOptions,
+};
+
+/// Input label of a merged job, see [SortAnnotationsJobResult::input].
+pub const MERGED_INPUT: &str = "[merged]";
+
+/// Sorts `inputs`, all GTF or all GFF3, into one `output` as if they were a single file.
+///
+/// The format is [SortOptions::format] if set, else guessed from each extension or, if
+/// missing or with [SortOptions::detect_format], detected from each input. Inputs of
+/// different formats fail the job. An input of [crate::STDIO_PATH] is read from stdin.
+/// [SortOptions::lock_input] is not honoured, the inputs are only read once, and
+/// [SortOptions::section] and [SortOptions::offset_index] are rejected.
+pub fn sort_annotations_merged<'a>(
+    inputs: &[&Path],
+    output: &'a Path,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    if inputs.is_empty() {
+        return Err(GtfSortError::InvalidParameter("no inputs to merge"));
+    }
+    if inputs.iter().filter(|p| is_stdio(p)).count() > 1 {
+        return Err(GtfSortError::InvalidParameter(
+            "stdin can only be merged once",
+        ));
+    }
+    if options.section.is_some() || options.offset_index.is_some() {
+        // both refer to positions in the one input
+        return Err(GtfSortError::InvalidParameter(
+            "merged inputs cannot be combined with annotation sections or offset indexes",
+        ));
+    }
+    if let Some(logger) = &options.logger {
+        logger.install();
+    }
+
+    let spooled = temp::TempPath::file(options.tmpdir.as_deref(), "merged", "")
+        .map_err(|e| GtfSortError::IoError("creating a file in the temporary directory", e))?;
+    let format = timed("Merging inputs", None, || spool(inputs, &spooled, options))?;
+    log::info!("Merged {} {} inputs", inputs.len(), format);
+
+    let options = SortOptions {
+        format: Some(format),
+        detect_format: false,
+        // nobody else writes to the spooled copy
+        lock_input: false,
+        source_tag: None,
+        ..options.clone()
+    };
+    let output_label = output.to_str().unwrap_or_default();
+    let ret = crate::sort_annotations_with_options(&spooled, output, threads, &options)?;
+    Ok(ret.relabel(MERGED_INPUT, output_label))
+}
+
+/// Writes the annotations of `inputs` to `path`, followed by their sequences, returning
+/// their common format.
+fn spool(
+    inputs: &[&Path],
+    path: &Path,
+    options: &SortOptions,
+) -> Result<AnnotationFormat, GtfSortError> {
+    let tmp_err = |e| GtfSortError::IoError("creating a file in the temporary directory", e);
+    let mut out = io::BufWriter::new(File::create(path).map_err(tmp_err)?);
+    let sequences =
+        temp::TempPath::file(options.tmpdir.as_deref(), "merged_fasta", "").map_err(tmp_err)?;
+    let mut fasta = Spooled::new(File::create(&sequences).map_err(tmp_err)?);
+
+    let mut format = None;
+    for (i, input) in inputs.iter().enumerate() {
+        let input_format = spool_input(input, options, &mut out, &mut fasta)?;
+        match format {
+            None => format = Some(input_format),
+            Some(first) if first != input_format => {
+                return Err(GtfSortError::InvalidInput(format!(
+                    "{:?} is {} but {:?} is {}, merged inputs must share their format",
+                    inputs[i], input_format, inputs[0], first
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let write_err = |e| GtfSortError::IoError("writing merged inputs", e);
+    if fasta.directive {
+        fasta.out.flush().map_err(write_err)?;
+        let mut sequences = File::open(&sequences).map_err(write_err)?;
+        io::copy(&mut sequences, &mut out).map_err(write_err)?;
+    }
+    out.flush().map_err(write_err)?;
+    Ok(format.expect("at least one input"))
+}
+
+/// The sequence sections of the inputs, a single one with the directive of the first.
+struct Spooled {
+    out: io::BufWriter<File>,
+    /// Whether the `##FASTA` directive was written.
+    directive: bool,
+}
+
+impl Spooled {
+    fn new(file: File) -> Self {
+        Self {
+            out: io::BufWriter::new(file),
+            directive: false,
+        }
+    }
+}
+
+/// Writes the annotation of `input` to `out`, tagged with [SortOptions::source_tag], and
+/// its sequence section to `fasta`, returning its format.
+fn spool_input<W: Write>(
+    input: &Path,
+    options: &SortOptions,
+    out: &mut W,
+    fasta: &mut Spooled,
+) -> Result<AnnotationFormat, GtfSortError> {
+    let (reader, what): (Box<dyn Read + Send>, _) = match is_stdio(input) {
+        true => (Box::new(io::stdin()), "reading stdin"),
+        false => {
+            sniff::check_file(input)?;
+            (gzip::open(input)?, "reading input file")
+        }
+    };
+    let mut reader = io::BufReader::new(reader);
+    let read_err = |e| GtfSortError::IoError(what, e);
+    let write_err = |e| GtfSortError::IoError("writing merged inputs", e);
+
+    // the format is detected from the leading records, held until it is known
+    let mut sample = String::new();
+    let mut records = 0;
+    while records < DETECT_SAMPLE_LINES {
+        let start = sample.len();
+        if reader.read_line(&mut sample).map_err(read_err)? == 0 {
+            break;
+        }
+        let line = &sample[start..];
+        if line.starts_with(sniff::FASTA_DIRECTIVE) {
+            break;
+        }
+        if !line.starts_with('#') && !line.trim().is_empty() {
+            records += 1;
+        }
+    }
+    let format = input_format(input, &sample, options)?;
+    let attribute = options
+        .source_tag
+        .as_ref()
+        .map(|key| source_attribute(format, key, &input.to_string_lossy()));
+
+    let (mut in_fasta, mut ended) = (false, true);
+    let (mut line, mut tagged) = (String::new(), String::new());
+    let mut sample_lines = sample.split_inclusive('\n');
+    loop {
+        let next = match sample_lines.next() {
+            Some(l) => l,
+            None => {
+                line.clear();
+                if reader.read_line(&mut line).map_err(read_err)? == 0 {
+                    break;
+                }
+                &line
+            }
+        };
+        if !in_fasta && next.starts_with(sniff::FASTA_DIRECTIVE) {
+            in_fasta = true;
+            if std::mem::replace(&mut fasta.directive, true) {
+                continue;
+            }
+        }
+        let (to, text): (&mut dyn Write, &str) = match (in_fasta, &attribute) {
+            (true, _) => (&mut fasta.out, next),
+            (false, Some(attribute)) => {
+                tagged.clear();
+                tag_line(next, format, attribute, &mut tagged);
+                (&mut *out, &tagged)
+            }
+            (false, None) => (&mut *out, next),
+        };
+        to.write_all(text.as_bytes()).map_err(write_err)?;
+        ended = text.ends_with('\n');
+    }
+    if !ended {
+        let to: &mut dyn Write = match in_fasta {
+            true => &mut fasta.out,
+            false => out,
+        };
+        to.write_all(b"\n").map_err(write_err)?;
+    }
+    Ok(format)
+}
+
+fn input_format(
+    input: &Path,
+    contents: &str,
+    options: &SortOptions,
+) -> Result<AnnotationFormat, GtfSortError> {
+    if let Some(format) = options.format {
+        return Ok(format);
+    }
+    let from_extension = (!options.detect_format && !is_stdio(input))
+        .then(|| AnnotationFormat::from_extension(input))
+        .flatten();
+    from_extension
+        .or_else(|| AnnotationFormat::detect(contents).format)
+        .ok_or_else(|| {
+            GtfSortError::InvalidInput(format!(
+                "could not detect the format of {:?}, please set it explicitly",
+                input
+            ))
+        })
+}
+
+/// The attribute tagging the records of the input named `value` with `key`.
+fn source_attribute(format: AnnotationFormat, key: &str, value: &str) -> String {
+    // characters that would end or split the attribute
+    let value = value.chars().fold(String::new(), |mut s, c| {
+        match c {
+            ';' | '=' | ',' | '"' | '%' | '&' | '\t' | '\r' | '\n' => {
+                write!(s, "%{:02X}", c as u32).unwrap()
+            }
+            c => s.push(c),
+        }
+        s
+    });
+    match format {
+        AnnotationFormat::Gtf => format!("{} \"{}\";", key, value),
+        AnnotationFormat::Gff3 => format!("{}={}", key, value),
+    }
+}
+
+/// Appends `line`, with `attribute` (see [source_attribute]) added if it is a record, to
+/// `out`. The line ending of `line` is kept.
+fn tag_line(line: &str, format: AnnotationFormat, attribute: &str, out: &mut String) {
+    let record = line.trim_end_matches(['\n', '\r']);
+    let columns = record.matches('\t').count() + 1;
+    if record.trim().is_empty() || record.starts_with('#') || columns < 8 {
+        out.push_str(line);
+        return;
+    }
+
+    let (fields, attributes) = match columns {
+        8 => (record, ""),
+        _ => record.rsplit_once('\t').unwrap(),
+    };
+    out.push_str(fields);
+    out.push('\t');
+    match attributes.trim_end() {
+        "" | "." => {}
+        a if format == AnnotationFormat::Gtf && a.ends_with(';') => {
+            out.push_str(a);
+            out.push(' ');
+        }
+        a if format == AnnotationFormat::Gtf => {
+            out.push_str(a);
+            out.push_str("; ");
+        }
+        a if a.ends_with(';') => out.push_str(a),
+        a => {
+            out.push_str(a);
+            out.push(';');
+        }
+    }
+    out.push_str(attribute);
+    out.push_str(&line[record.len()..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag_lines(annotation: &str, format: AnnotationFormat, key: &str, value: &str) -> String {
+        let attribute = source_attribute(format, key, value);
+        let mut out = String::new();
+        for line in annotation.split_inclusive('\n') {
+            tag_line(line, format, &attribute, &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn records_are_tagged_with_their_source() {
+        let gtf = "#!genome-build x\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"\r\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\n";
+        assert_eq!(
+            tag_lines(gtf, AnnotationFormat::Gtf, "source_file", "a;b.gtf"),
+            "#!genome-build x\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\"; source_file \"a%3Bb.gtf\";\n\
+            chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; source_file \"a%3Bb.gtf\";\r\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tsource_file \"a%3Bb.gtf\";\n"
+        );
+
+        let gff = "chr1\ts\tgene\t1\t9\t.\t+\t.\tID=A\nchr1\ts\tregion\t1\t9\t.\t+\t.\t.";
+        assert_eq!(
+            tag_lines(gff, AnnotationFormat::Gff3, "source_file", "b.gff3"),
+            "chr1\ts\tgene\t1\t9\t.\t+\t.\tID=A;source_file=b.gff3\n\
+            chr1\ts\tregion\t1\t9\t.\t+\t.\tsource_file=b.gff3"
+        );
+    }
+
+    #[test]
+    fn inputs_sort_as_one() {
+        let dir = temp::TempPath::dir(None, "merge_test").unwrap();
+        let (a, b) = (dir.join("a.gtf"), dir.join("b.gtf"));
+        std::fs::write(
+            &a,
+            "#!genome-build x\n\
+            chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &b,
+            "#!custom\n\
+            chr1\ts\tgene\t5\t9\t.\t+\t.\tgene_id \"C\";\n\
+            chr2\ts\tgene\t1\t5\t.\t+\t.\tgene_id \"A\";",
+        )
+        .unwrap();
+
+        let output = dir.join("out.gtf");
+        let options = SortOptions {
+            source_tag: Some("source_file".to_string()),
+            ..Default::default()
+        };
+        let ret =
+            sort_annotations_merged(&[a.as_path(), b.as_path()], &output, 2, &options).unwrap();
+        assert_eq!(ret.input, MERGED_INPUT);
+        assert_eq!(ret.lines.records, 3);
+
+        let out = std::fs::read_to_string(&output).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "#!genome-build x");
+        assert!(
+            lines[1].contains("gene_id \"C\"")
+                && lines[1].ends_with(&format!("source_file \"{}\";", b.display()))
+        );
+        assert!(lines[2].contains("gene_id \"A\""));
+        assert!(lines[3].contains("gene_id \"B\"") && lines[3].contains("a.gtf"));
+
+        let gff = dir.join("c.gff3");
+        std::fs::write(&gff, "chr1\ts\tgene\t1\t9\t.\t+\t.\tID=D\n").unwrap();
+        match sort_annotations_merged(&[a.as_path(), gff.as_path()], &output, 1, &options) {
+            Err(e) => assert!(e.to_string().contains("must share their format"), "{}", e),
+            Ok(_) => panic!("a GTF and a GFF3 input were merged"),
+        }
+    }
+
+    #[test]
+    fn sequences_follow_every_annotation() {
+        let dir = temp::TempPath::dir(None, "merge_test").unwrap();
+        let (a, b, spooled) = (dir.join("a.gff3"), dir.join("b.gff3"), dir.join("spooled"));
+        std::fs::write(
+            &a,
+            "##gff-version 3\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tID=A\n\
+            ##FASTA\n>chr1\nACGT",
+        )
+        .unwrap();
+        std::fs::write(
+            &b,
+            "chr2\ts\tgene\t1\t9\t.\t+\t.\tID=B\n##FASTA\n>chr2\nTTTT\n",
+        )
+        .unwrap();
+
+        let options = SortOptions {
+            source_tag: Some("src".to_string()),
+            tmpdir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let format = spool(&[a.as_path(), b.as_path()], &spooled, &options).unwrap();
+        assert_eq!(format, AnnotationFormat::Gff3);
+        assert_eq!(
+            std::fs::read_to_string(&spooled).unwrap(),
+            format!(
+                "##gff-version 3\n\
+                chr1\ts\tgene\t1\t9\t.\t+\t.\tID=A;src={}\n\
+                chr2\ts\tgene\t1\t9\t.\t+\t.\tID=B;src={}\n\
+                ##FASTA\n>chr1\nACGT\n>chr2\nTTTT\n",
+                a.display(),
+                b.display()
+            )
+        );
+    }
+}
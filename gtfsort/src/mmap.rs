@@ -31,6 +31,26 @@ pub enum Madvice {
 
 type CleanupFn<S> = Box<dyn FnOnce(&mut S) -> std::io::Result<()>>;
 
+/// The alignment of the offsets of [MemoryMap::from_file_at] and
+/// [MemoryMap::from_handle_at]: the page size on Unix, the allocation granularity on
+/// Windows.
+#[cfg(unix)]
+pub fn allocation_granularity() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        n if n > 0 => n as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(windows)]
+pub fn allocation_granularity() -> usize {
+    use windows::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+
+    let mut info = SYSTEM_INFO::default();
+    unsafe { GetSystemInfo(&mut info) };
+    info.dwAllocationGranularity as usize
+}
+
 pub struct MemoryMap<'a, T> {
     ptr: *const T,
     size: usize,
@@ -105,6 +125,23 @@ impl<'a, T> MemoryMap<'a, T> {
     /// The file descriptor must be open and readable.
     /// Size must be a valid size for the file descriptor.
     pub unsafe fn from_file<F>(fd: &'a F, size: usize) -> Result<Self, std::io::Error>
+    where
+        F: std::os::unix::io::AsRawFd,
+    {
+        Self::from_file_at(fd, 0, size)
+    }
+
+    #[cfg(unix)]
+    /// Creates a new MemoryMap instance of `size` bytes starting at `offset` in the file.
+    ///
+    /// # Safety
+    /// Same as [MemoryMap::from_file], offset must be a multiple of
+    /// [allocation_granularity] and offset + size must be within the file.
+    pub unsafe fn from_file_at<F>(
+        fd: &'a F,
+        offset: u64,
+        size: usize,
+    ) -> Result<Self, std::io::Error>
     where
         F: std::os::unix::io::AsRawFd,
     {
@@ -123,7 +160,7 @@ impl<'a, T> MemoryMap<'a, T> {
             libc::PROT_READ,
             libc::MAP_SHARED,
             fd.as_raw_fd(),
-            0,
+            offset as libc::off_t,
         );
 
         if ptr == libc::MAP_FAILED {
@@ -155,6 +192,23 @@ impl<'a, T> MemoryMap<'a, T> {
     /// The file handle must be open and readable.
     /// Size must be a valid size for the file handle.
     pub unsafe fn from_handle<F>(handle: &'a F, size: usize) -> Result<Self, std::io::Error>
+    where
+        F: std::os::windows::io::AsRawHandle,
+    {
+        Self::from_handle_at(handle, 0, size)
+    }
+
+    #[cfg(windows)]
+    /// Creates a new MemoryMap instance of `size` bytes starting at `offset` in the file.
+    ///
+    /// # Safety
+    /// Same as [MemoryMap::from_handle], offset must be a multiple of
+    /// [allocation_granularity] and offset + size must be within the file.
+    pub unsafe fn from_handle_at<F>(
+        handle: &'a F,
+        offset: u64,
+        size: usize,
+    ) -> Result<Self, std::io::Error>
     where
         F: std::os::windows::io::AsRawHandle,
     {
@@ -176,12 +230,13 @@ impl<'a, T> MemoryMap<'a, T> {
         }
 
         unsafe {
+            let end = offset + size as u64;
             let handle = CreateFileMappingW(
                 HANDLE(handle.as_raw_handle()),
                 None,
                 PAGE_READONLY,
-                high32!(size),
-                low32!(size),
+                high32!(end),
+                low32!(end),
                 PCWSTR::null(),
             )?;
 
@@ -189,7 +244,7 @@ impl<'a, T> MemoryMap<'a, T> {
                 return Err(std::io::Error::last_os_error());
             }
 
-            let ptr = MapViewOfFile(handle, FILE_MAP_READ, 0, 0, size);
+            let ptr = MapViewOfFile(handle, FILE_MAP_READ, high32!(offset), low32!(offset), size);
 
             if ptr.Value.is_null() {
                 return Err(std::io::Error::last_os_error());
@@ -489,6 +544,21 @@ mod tests {
         assert_eq!(std::fs::read_to_string(path).unwrap(), "hello world");
     }
 
+    #[test]
+    fn test_mmap_at_offset() {
+        let page = allocation_granularity();
+        let mut data = vec![b'a'; page];
+        data.extend_from_slice(b"hello world");
+        let (_, file) = tempfile_ro(&data);
+
+        #[cfg(unix)]
+        let mmap = unsafe { MemoryMap::<u8>::from_file_at(&file, page as u64, 5).unwrap() };
+        #[cfg(windows)]
+        let mmap = unsafe { MemoryMap::<u8>::from_handle_at(&file, page as u64, 5).unwrap() };
+
+        assert_eq!(mmap.as_slice(), b"hello");
+    }
+
     #[test]
     fn test_mmap_mut() {
         let (path, file) = tempfile_rw(b"hello world");
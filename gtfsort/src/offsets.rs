@@ -0,0 +1,138 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use hashbrown::HashMap;
+
+use crate::ChromRecord;
+
+/// Half-open range of bytes of the input.
+pub type ByteRange = (usize, usize);
+
+/// Where the lines of one gene sit in the unsorted input.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GeneOffsets<'a> {
+    pub gene_id: &'a str,
+    pub chrom: &'a str,
+    /// Ranges cover whole lines without their line terminator, consecutive lines are
+    /// merged into one range.
+    pub ranges: Vec<ByteRange>,
+}
+
+/// Where the lines of each gene sit in the unsorted input.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OffsetIndex<'a> {
+    /// In input order of the first line of each gene.
+    pub genes: Vec<GeneOffsets<'a>>,
+}
+
+impl<'a> OffsetIndex<'a> {
+    /// Builds the index from the output of the parse pass. `contents` must be the buffer
+    /// the records were parsed from.
+    pub fn from_records(contents: &'a str, records: &ChromRecord<'a>) -> Self {
+        let mut genes: HashMap<&str, GeneOffsets> = HashMap::new();
+
+        for (chrom, lines) in records.iter() {
+            for record in lines {
+                let start = record.ordinal;
+                genes
+                    .entry(record.gene_id)
+                    .or_insert_with(|| GeneOffsets {
+                        gene_id: record.gene_id,
+                        chrom,
+                        ranges: Vec::new(),
+                    })
+                    .ranges
+                    .push((start, start + record.line.len()));
+            }
+        }
+
+        let mut genes = genes
+            .into_values()
+            .map(|mut gene| {
+                gene.ranges.sort_unstable();
+                gene.ranges = merge_lines(contents, std::mem::take(&mut gene.ranges));
+                gene
+            })
+            .collect::<Vec<_>>();
+        genes.sort_unstable_by_key(|gene| gene.ranges[0].0);
+
+        Self { genes }
+    }
+
+    /// Writes the index as TSV: `gene_id  chrom  start  end`, one row per byte range.
+    pub fn write_tsv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "#gene_id\tchrom\tstart\tend")?;
+        for gene in self.genes.iter() {
+            for (start, end) in gene.ranges.iter() {
+                writeln!(w, "{}\t{}\t{}\t{}", gene.gene_id, gene.chrom, start, end)?;
+            }
+        }
+        w.flush()
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_tsv(BufWriter::new(File::create(path)?))
+    }
+}
+
+/// Merges ranges only separated by line terminators.
+fn merge_lines(contents: &str, ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last)
+                if contents.as_bytes()[last.1..start]
+                    .iter()
+                    .all(|b| *b == b'\n' || *b == b'\r') =>
+            {
+                last.1 = end;
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parallel_parse;
+
+    #[test]
+    fn ranges_point_back_to_the_input() {
+        let input = "#header\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\r\n\
+            chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+            chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+
+        let records = parallel_parse::<b' '>(input).unwrap();
+        let index = OffsetIndex::from_records(input, &records);
+
+        assert_eq!(
+            index
+                .genes
+                .iter()
+                .map(|g| (g.gene_id, g.chrom))
+                .collect::<Vec<_>>(),
+            [("A", "chr1"), ("B", "chr2")]
+        );
+
+        let a = &index.genes[0].ranges;
+        assert_eq!(a.len(), 2);
+        let lines = a
+            .iter()
+            .flat_map(|(s, e)| input[*s..*e].lines())
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|l| l.contains("gene_id \"A\"")));
+
+        let mut tsv = Vec::new();
+        index.write_tsv(&mut tsv).unwrap();
+        assert_eq!(String::from_utf8(tsv).unwrap().lines().count(), 4);
+    }
+}
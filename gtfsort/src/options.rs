@@ -0,0 +1,258 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{
+    chrom_order::{ChromOrder, ChromSortOrder},
+    compress::OutputCompression,
+    dedup::DedupMode,
+    export::ExportFormat,
+    features::{FeatureMap, LayerMap},
+    filter::{ChromFilter, IdFilter, Region},
+    format::{AnnotationFormat, Newline},
+    gtf::DedupAttrs,
+    invalid::ParseMode,
+    logging::LoggerSetup,
+    ord::{GeneSortKey, TranscriptOrder, UnnumberedOrder},
+    packed::LineCompression,
+    progress::Progress,
+    rename::ChromRenamer,
+    section::SectionMarkers,
+    tabix::IndexFormat,
+    validate::ValidatePolicy,
+};
+
+/// Optional knobs for a sort job.
+///
+/// The defaults reproduce the behavior of [crate::sort_annotations] and
+/// [crate::sort_annotations_string].
+#[derive(Debug, Clone, Default)]
+pub struct SortOptions {
+    /// Input format, guessed from the file extension if not set.
+    pub format: Option<AnnotationFormat>,
+    /// If `format` is not set, sniff it from the attribute columns instead of the file
+    /// extension (see [AnnotationFormat::detect]).
+    pub detect_format: bool,
+    /// Abort the job once the resident set size goes above this many MB.
+    pub max_rss_mb: Option<f64>,
+    /// Try to keep the resident set size of a file sort within this many MB by choosing
+    /// how the input is held from its size (see [crate::budget]), without aborting.
+    pub max_mem_mb: Option<f64>,
+    /// Abort the job once it has been running for this long.
+    pub timeout: Option<Duration>,
+    /// Only emit these genes (or all but these, if inverted) together with their children.
+    pub genes: Option<IdFilter>,
+    /// Only emit these transcripts (or all but these, if inverted) together with their parent genes.
+    pub transcripts: Option<IdFilter>,
+    /// Only parse and emit records on these chromosomes, other lines are skipped before parsing.
+    pub chromosomes: Option<ChromFilter>,
+    /// Only emit children (exons, CDS, UTRs, ...) of these feature types; gene and
+    /// transcript lines are kept around them.
+    pub features: Option<IdFilter>,
+    /// Only emit genes of these biotypes (see [crate::gtf::gene_biotype]) together with
+    /// their children.
+    pub gene_biotypes: Option<IdFilter>,
+    /// Only emit the genes overlapping this window, whole with all their transcripts and
+    /// children; other chromosomes are skipped before parsing. With `coordinate_only`
+    /// records are kept one by one instead.
+    pub region: Option<Region>,
+    /// Leave out records duplicating an earlier one of their chromosome, counted in
+    /// [crate::LineCounts::duplicates].
+    pub dedup: DedupMode,
+    /// Shrink the bounds of gene lines to the span of the transcripts kept by `transcripts`.
+    pub recompute_gene_bounds: bool,
+    /// Only emit the first N genes (after sorting) of each chromosome, e.g. for previews.
+    pub head_genes: Option<usize>,
+    /// Synthesize missing transcript and gene lines from the span of their children,
+    /// flagging them with [crate::synth::SYNTHESIZED_TAG].
+    pub synthesize_parents: bool,
+    /// Synthesize gene lines for transcripts whose gene has none, spanning their transcripts
+    /// and flagged with [crate::synth::SYNTHESIZED_TAG] (see [crate::Layers::infer_genes]).
+    pub infer_genes: bool,
+    /// Synthesize the missing transcript line of children whose gene has a gene line,
+    /// flagged with [crate::synth::SYNTHESIZED_TAG], instead of leaving them out (see
+    /// [crate::Layers::recover_transcripts]).
+    pub recover_transcripts: bool,
+    /// Only emit gene and transcript lines, dropping exons, CDS and other children.
+    pub skeleton: bool,
+    /// Ignore the gene hierarchy and order lines by (chrom, start, end) only, as
+    /// bedtools and tabix need. Attributes are not parsed (see [crate::coords]).
+    pub coordinate_only: bool,
+    /// Read the input through memory-mapped windows of about this many bytes instead of
+    /// mapping it whole, sorting one chromosome at a time (see [crate::window]). Needs the
+    /// `mmap` feature.
+    pub mmap_window: Option<usize>,
+    /// Read the input and write the output with plain IO instead of memory maps, e.g. on
+    /// network filesystems where mapping is slow or unreliable.
+    pub no_mmap: bool,
+    /// Read the input as a stream and keep its lines LZ4-compressed in memory, sorting
+    /// one chromosome at a time (see [crate::packed]). By default only when sorting the
+    /// whole input at once would likely go above `max_mem_mb` or `max_rss_mb`. Ignored
+    /// with `mmap_window`.
+    pub compress_lines: LineCompression,
+    /// Partition the input lines by chromosome into files under `tmpdir` and sort one
+    /// chromosome at a time, for inputs larger than memory (see [crate::spill]). The
+    /// largest chromosome still has to fit in memory, its lines are not merged from sorted
+    /// runs. Takes precedence over `compress_lines`, ignored with `mmap_window`.
+    pub low_memory: bool,
+    /// Write a [crate::offsets::OffsetIndex] of the unsorted input to this path, mapping
+    /// each gene_id to the byte ranges of its lines.
+    pub offset_index: Option<PathBuf>,
+    /// Skip the lines that do not parse as a record, reporting them in
+    /// [crate::SortAnnotationsJobResult::invalid_lines], or fail at the first one.
+    pub parse_mode: ParseMode,
+    /// Copy the records that cannot be placed in the output (invalid lines, unresolvable
+    /// GFF3 parents, transcripts and children without a gene or transcript line) to this
+    /// path, each after a comment with its line number and the reason, see
+    /// [crate::rejects].
+    pub rejects: Option<PathBuf>,
+    /// Write the genes of the sorted output with their rank to this path, see
+    /// [crate::gene_order::GeneOrderWriter].
+    pub gene_order: Option<PathBuf>,
+    /// Record the input each line came from in this attribute (e.g. `source_file`) when
+    /// merging several inputs, see [crate::merge].
+    pub source_tag: Option<String>,
+    /// Line terminator of the output.
+    pub newline: Newline,
+    /// Compress the output as it is written, e.g. as BGZF for tabix. See [crate::compress].
+    pub output_compression: OutputCompression,
+    /// Index the output for tabix once it is written, next to it (e.g. `out.gtf.gz.tbi`).
+    /// Needs a [OutputCompression::Bgzf] output sorted with `coordinate_only`. See
+    /// [crate::tabix].
+    pub index: Option<IndexFormat>,
+    /// Leave out the comment lines leading the input (e.g. `##gff-version 3`), which are
+    /// otherwise written at the top of the output, see [crate::header].
+    pub drop_header: bool,
+    /// Collapse keys repeated in an attribute column (e.g. several `tag` entries) before
+    /// sorting, see [crate::gtf::dedup_attributes].
+    pub dedup_attrs: Option<DedupAttrs>,
+    /// Rewrite every attribute column into a canonical form before sorting (key order,
+    /// quoting, spacing, no repeated entries), e.g. to diff two annotations, see
+    /// [crate::gtf::attr_rewrite].
+    pub attr_normalize: bool,
+    /// Rename chromosomes by an alias table and naming scheme before sorting, so the output
+    /// uses consistent names, see [crate::rename].
+    pub chrom_rename: Option<ChromRenamer>,
+    /// Check every output line for 9 columns and start <= end before writing, handling
+    /// malformed lines per [crate::validate::ValidatePolicy].
+    pub validate_output: Option<ValidatePolicy>,
+    /// Count the records of each feature type in the input and again in the written
+    /// output, failing the job if any went missing, see [crate::verify].
+    pub verify: bool,
+    /// Write a [crate::manifest::Manifest] of per-gene content hashes of this run to
+    /// this path.
+    pub manifest: Option<PathBuf>,
+    /// Manifest of a previous run: genes whose input lines did not change are copied from
+    /// the output it describes instead of being sorted again.
+    pub incremental: Option<PathBuf>,
+    /// Compute [crate::quality::QualityMetrics] on the sorted annotation.
+    pub quality_metrics: bool,
+    /// Sample RSS and CPU usage at this interval, reported per phase as a
+    /// [crate::sampler::ResourceReport].
+    pub sample_interval: Option<Duration>,
+    /// Receives [crate::progress::ProgressEvent]s while the job runs.
+    pub progress: Option<Progress>,
+    /// Run on this pool instead of building one per job, e.g. to sort many files in a row
+    /// without respawning threads. See [crate::build_thread_pool].
+    pub thread_pool: Option<Arc<crate::par::ThreadPool>>,
+    /// Logger installed when the job starts, unless the host already set a global logger.
+    pub logger: Option<LoggerSetup>,
+    /// Copy the input through unchanged if it already follows the sorted layout. Ignored
+    /// when records are altered or side outputs built from the index are asked for
+    /// (gene order, offset index, quality metrics, verification, rejects, manifest).
+    pub skip_if_sorted: bool,
+    /// Directory for temporary files, `$TMPDIR` if not set. See [crate::temp].
+    pub tmpdir: Option<PathBuf>,
+    /// Write the output into this directory first and move it to its destination once
+    /// complete, verifying copies across filesystems by checksum. Speeds up writing to
+    /// network filesystems from a local scratch or tmpfs directory. See [crate::stage].
+    pub stage_dir: Option<PathBuf>,
+    /// Order of the exons, CDS and codons that carry no exon_number within their
+    /// transcript. They are written after the numbered ones either way.
+    pub unnumbered_order: UnnumberedOrder,
+    /// Order of the genes within a chromosome, by start unless set.
+    pub gene_sort_key: GeneSortKey,
+    /// Keep genes tied on `gene_sort_key`, start, end and gene_id in their input order.
+    /// Other ties are broken by those keys, so the output never depends on the thread count.
+    pub stable: bool,
+    /// Order of the transcripts within a gene, their input order unless set.
+    pub transcript_order: TranscriptOrder,
+    /// Order of the exon-level features (exon, CDS, codons) sharing an exon_number.
+    pub feature_map: FeatureMap,
+    /// Layer of each feature type (e.g. `mRNA` is a transcript), for records not placed by
+    /// their GFF3 `Parent` chain.
+    pub layer_map: LayerMap,
+    /// Write this format, derived from the sorted index, instead of the sorted annotation.
+    /// See [crate::export].
+    pub export: Option<ExportFormat>,
+    /// Also write these formats, derived from the same sorted index, to these paths while
+    /// the output is written, so that one run serves several consumers without parsing
+    /// the input again. Not staged through `stage_dir`.
+    pub extra_outputs: Vec<(PathBuf, ExportFormat)>,
+    /// Hold a shared advisory `flock` on the input while it is read, waiting for writers
+    /// that hold an exclusive one. Unix only, see [crate::lock::SharedLock]. Writes by
+    /// processes that take no lock are still detected and fail the job.
+    pub lock_input: bool,
+    /// Sort only the annotation section of a document that wraps it in other text (e.g. a
+    /// tool report), copying the text before and after it through unchanged. The output
+    /// is written sequentially. See [crate::section].
+    pub section: Option<SectionMarkers>,
+    /// Order of the chromosomes in the output, [Self::chrom_sort] if not set.
+    pub chrom_order: Option<ChromOrder>,
+    /// Preset order of the chromosomes in the output, natural by default.
+    pub chrom_sort: ChromSortOrder,
+}
+
+impl SortOptions {
+    /// Returns true if the options select or rewrite records, so the output may differ from
+    /// the input even when it is already sorted.
+    pub fn alters_records(&self) -> bool {
+        self.genes.is_some()
+            || self.transcripts.is_some()
+            || self.chromosomes.is_some()
+            || self.features.is_some()
+            || self.gene_biotypes.is_some()
+            || self.region.is_some()
+            || self.dedup != DedupMode::Off
+            || self.head_genes.is_some()
+            || self.synthesize_parents
+            || self.infer_genes
+            || self.recover_transcripts
+            || self.skeleton
+            || self.coordinate_only
+            || self.dedup_attrs.is_some()
+            || self.attr_normalize
+            || self.chrom_rename.is_some()
+            || self.validate_output.is_some()
+            || self.unnumbered_order != UnnumberedOrder::Input
+            || self.gene_sort_key != GeneSortKey::Start
+            || self.transcript_order != TranscriptOrder::Input
+            || self.chrom_order.is_some()
+            || self.chrom_sort != ChromSortOrder::Natural
+            || self.export.is_some()
+            || !self.extra_outputs.is_empty()
+            || self.newline != Newline::Lf
+            || self.layer_map != LayerMap::default()
+    }
+
+    /// Files a job writes besides its output: the offset index, rejects, gene order,
+    /// manifest and extra outputs.
+    pub fn side_outputs(&self) -> impl Iterator<Item = &std::path::Path> {
+        [
+            &self.offset_index,
+            &self.rejects,
+            &self.gene_order,
+            &self.manifest,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.extra_outputs.iter().map(|(path, _)| path))
+        .map(PathBuf::as_path)
+    }
+
+    /// The chromosomes to parse: [Self::chromosomes] if set, or else the chromosome of
+    /// [Self::region].
+    pub fn chrom_filter(&self) -> Option<&ChromFilter> {
+        self.chromosomes
+            .as_ref()
+            .or(self.region.as_ref().map(Region::chrom_filter))
+    }
+}
@@ -1,4 +1,6 @@
-use std::{borrow::Cow, cmp::Ordering, fmt::Debug, ops::Deref};
+use std::{borrow::Cow, cmp::Ordering, fmt, fmt::Debug, ops::Deref, str::FromStr};
+
+use crate::par::*;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct CowNaturalSort<'a>(pub Cow<'a, str>);
@@ -32,3 +34,297 @@ impl Ord for CowNaturalSort<'_> {
         natord::compare(&self.0, &other.0)
     }
 }
+
+/// Sort key of a child feature within its transcript, see [crate::gtf::Record::inner_key].
+///
+/// Variants are ordered as declared: exons, CDS and codons with a numeric exon_number
+/// come first, then those with another exon_number, then those without one, then any
+/// other feature by name. The `u8` is the rank of the feature within one exon, see
+/// [crate::features::FeatureMap].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChildKey<'a> {
+    Numbered(u32, u8),
+    /// exon_number is not a number (e.g. `3a`), compared naturally.
+    Labeled(CowNaturalSort<'a>, u8),
+    /// No exon_number; the lines sharing the key are ordered by [UnnumberedOrder].
+    Unnumbered(u8),
+    Feature(CowNaturalSort<'a>),
+}
+
+impl ChildKey<'_> {
+    /// Whether the key carries an exon_number, numeric or not.
+    pub fn is_numbered(&self) -> bool {
+        matches!(self, Self::Numbered(..) | Self::Labeled(..))
+    }
+}
+
+/// Order of the exons, CDS and codons without exon_number of one transcript, which share a
+/// [ChildKey::Unnumbered] slot.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnnumberedOrder {
+    /// Input order, except that GFF3 segments sharing an ID are kept together and ordered
+    /// by coordinate.
+    #[default]
+    Input,
+    /// By start.
+    Coordinate,
+    /// 5' to 3', i.e. by start on the + strand and by descending end on the - strand, as
+    /// exon_number would count them.
+    Transcription,
+}
+
+impl FromStr for UnnumberedOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "input" => Ok(Self::Input),
+            "coordinate" => Ok(Self::Coordinate),
+            "transcription" => Ok(Self::Transcription),
+            _ => Err(format!(
+                "unknown order {:?}, expected input, coordinate or transcription",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for UnnumberedOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Input => "input",
+            Self::Coordinate => "coordinate",
+            Self::Transcription => "transcription",
+        })
+    }
+}
+
+/// Order of the transcripts of a gene.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptOrder {
+    /// The order their transcript lines come in the input.
+    #[default]
+    Input,
+    /// By start, end and then transcript_id.
+    Position,
+    /// By transcript_id, compared naturally.
+    Id,
+}
+
+impl FromStr for TranscriptOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "input" => Ok(Self::Input),
+            "position" => Ok(Self::Position),
+            "id" => Ok(Self::Id),
+            _ => Err(format!(
+                "unknown transcript order {:?}, expected input, position or id",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TranscriptOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Input => "input",
+            Self::Position => "position",
+            Self::Id => "id",
+        })
+    }
+}
+
+/// Order of the genes of a chromosome.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum GeneSortKey {
+    /// By start.
+    #[default]
+    Start,
+    /// By span, longest first.
+    Length,
+    /// By a numeric attribute, highest first: that of the gene line, or else the highest
+    /// among its transcripts (e.g. the `TPM` StringTie sets on transcripts). Genes without
+    /// a numeric value follow, by start.
+    Attribute(String),
+}
+
+impl FromStr for GeneSortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((kind, attr)) if kind.eq_ignore_ascii_case("attr") && !attr.is_empty() => {
+                Ok(Self::Attribute(attr.to_string()))
+            }
+            _ => match s.to_ascii_lowercase().as_str() {
+                "start" => Ok(Self::Start),
+                "length" => Ok(Self::Length),
+                _ => Err(format!(
+                    "unknown gene sort key {:?}, expected start, length or attr:NAME",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for GeneSortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Start => f.write_str("start"),
+            Self::Length => f.write_str("length"),
+            Self::Attribute(attr) => write!(f, "attr:{}", attr),
+        }
+    }
+}
+
+/// An f64 ordered from highest to lowest, for sort keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Descending(pub f64);
+
+impl Eq for Descending {}
+
+impl PartialOrd for Descending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Descending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+/// A string encoded once into bytes that compare (as plain bytes) like
+/// [natord::compare] compares the string, so that sorting many names (e.g. the scaffolds
+/// of a draft assembly) runs on memcmp instead of re-scanning both strings on every
+/// comparison.
+///
+/// natord skips whitespace and compares digit runs by value, except runs with a leading
+/// zero, which compare digit by digit (as fractions). Characters are written as UTF-8,
+/// whose byte order is code point order, and digit runs as:
+///
+/// - leading zero: the digits, then a 0 byte, so a shorter run is less than a longer one
+///   it is a prefix of whatever follows it
+/// - otherwise: `1`, the run length (u32, big endian), the digits
+///
+/// Both start with a digit and no other character sorts between digits, so a run against
+/// a character, or a zero run against a non-zero one, is decided by the first byte as in
+/// natord.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NaturalKey(Vec<u8>);
+
+impl NaturalKey {
+    pub fn new(s: &str) -> Self {
+        let mut key = Vec::with_capacity(s.len() + 8);
+        let bytes = s.as_bytes();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let b = bytes[i];
+            if b.is_ascii_digit() {
+                let run = bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+                let digits = &bytes[i..i + run];
+                if b == b'0' {
+                    key.extend_from_slice(digits);
+                    key.push(0);
+                } else {
+                    key.push(b'1');
+                    key.extend_from_slice(&(run as u32).to_be_bytes());
+                    key.extend_from_slice(digits);
+                }
+                i += run;
+            } else if b.is_ascii() {
+                // char::is_whitespace, which natord uses, also counts vertical tabs
+                if !(b.is_ascii_whitespace() || b == 0x0b) {
+                    key.push(b);
+                }
+                i += 1;
+            } else {
+                // i is on a char boundary, only ASCII bytes were skipped
+                let c = s[i..].chars().next().unwrap();
+                if !c.is_whitespace() {
+                    key.extend_from_slice(&bytes[i..i + c.len_utf8()]);
+                }
+                i += c.len_utf8();
+            }
+        }
+
+        Self(key)
+    }
+}
+
+/// Sorts chromosome names in natural order (see [NaturalKey]), names that compare equal
+/// (e.g. differing only in whitespace) by their bytes.
+pub fn sort_natural(keys: &mut [&str]) {
+    keys.par_sort_by_cached_key(|k| (NaturalKey::new(k), *k));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_key_orders_like_natord() {
+        let names = [
+            "chr1",
+            "chr10",
+            "chr2",
+            "chrX",
+            "chrM",
+            "chr1_KI270706v1_random",
+            "chrUn_GL000195v1",
+            "1",
+            "01",
+            "001",
+            "10",
+            "010",
+            "2",
+            "0",
+            "00",
+            "",
+            "a",
+            "A",
+            "a1",
+            "a01",
+            "a 1",
+            "a10b",
+            "a10a",
+            "a9z",
+            "scaffold_100000",
+            "scaffold_99999",
+            "contig007",
+            "contig7",
+            "contig70",
+            "chr1.2",
+            "chr1.10",
+            "chr1.02",
+            "HLA-A*01:01",
+            "chré1",
+            "Ⅻ",
+        ];
+
+        for a in names {
+            for b in names {
+                assert_eq!(
+                    NaturalKey::new(a).cmp(&NaturalKey::new(b)),
+                    natord::compare(a, b),
+                    "{:?} vs {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+
+        let mut keys = vec!["chr10", "chrX", "chr2", "chr1", "scaffold_10", "scaffold_9"];
+        sort_natural(&mut keys);
+        assert_eq!(
+            keys,
+            ["chr1", "chr2", "chr10", "chrX", "scaffold_9", "scaffold_10"]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, Once},
+};
+
+use crate::{par::ThreadPool, GtfSortError};
+
+/// Prefix of the names given to the worker threads of a sort job.
+pub const WORKER_THREAD_PREFIX: &str = "gtfsort-worker";
+
+const BUG_REPORT_URL: &str = "https://github.com/alejandrogzi/gtfsort/issues";
+
+/// What a thread was working on, attached to the report of a panic on that thread.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PanicContext {
+    pub phase: Option<&'static str>,
+    pub chrom: Option<String>,
+}
+
+/// A panic caught at the job boundary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicReport {
+    pub message: String,
+    pub location: Option<String>,
+    pub context: PanicContext,
+}
+
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        if let Some(phase) = self.context.phase {
+            write!(f, " while {}", phase)?;
+        }
+        if let Some(chrom) = &self.context.chrom {
+            write!(f, " chromosome {:?}", chrom)?;
+        }
+        write!(
+            f,
+            ". This is a bug in gtfsort, please report it at {} together with the command you ran",
+            BUG_REPORT_URL
+        )
+    }
+}
+
+thread_local! {
+    static CONTEXT: RefCell<PanicContext> = RefCell::new(PanicContext::default());
+    static JOB: RefCell<Job> = RefCell::new(Job::default());
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// The sort job a thread works for, which receives the report of a panic on that thread.
+///
+/// [catch_job] sets it on the calling thread, work handed to other threads takes it
+/// along with [Job::install] or [Job::enter]. Panics on threads working for no job are
+/// reported from their payload alone.
+#[derive(Debug, Clone, Default)]
+pub struct Job(Option<Arc<Mutex<Option<PanicReport>>>>);
+
+impl Job {
+    /// The job of the current thread.
+    pub fn current() -> Self {
+        JOB.with(|j| j.borrow().clone())
+    }
+
+    /// Same as [enter], for this job.
+    pub fn enter(&self, phase: &'static str, chrom: Option<&str>) -> ContextGuard {
+        let mut guard = enter(phase, chrom);
+        guard.job = Some(JOB.with(|j| j.replace(self.clone())));
+        guard
+    }
+
+    /// Runs `f` on `pool` for this job.
+    pub fn install<R: Send, F: FnOnce() -> R + Send>(&self, pool: &ThreadPool, f: F) -> R {
+        pool.install(|| {
+            let _job = ContextGuard {
+                context: None,
+                job: Some(JOB.with(|j| j.replace(self.clone()))),
+            };
+            f()
+        })
+    }
+
+    fn record(&self, report: PanicReport) {
+        if let Some(mut slot) = self.0.as_ref().and_then(|s| s.lock().ok()) {
+            slot.get_or_insert(report);
+        }
+    }
+
+    fn take(&self) -> Option<PanicReport> {
+        self.0.as_ref()?.lock().ok()?.take()
+    }
+}
+
+/// Restores the previous [PanicContext] and [Job] of the thread when dropped.
+pub struct ContextGuard {
+    context: Option<PanicContext>,
+    job: Option<Job>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(prev) = self.context.take() {
+            CONTEXT.with(|c| *c.borrow_mut() = prev);
+        }
+        if let Some(prev) = self.job.take() {
+            JOB.with(|j| *j.borrow_mut() = prev);
+        }
+    }
+}
+
+/// Records that the current thread is in `phase`, optionally on `chrom`, until the
+/// returned guard is dropped. The thread keeps its [Job], closures run on other threads
+/// enter through [Job::enter] instead.
+pub fn enter(phase: &'static str, chrom: Option<&str>) -> ContextGuard {
+    let next = PanicContext {
+        phase: Some(phase),
+        chrom: chrom.map(str::to_string),
+    };
+    ContextGuard {
+        context: Some(CONTEXT.with(|c| c.replace(next))),
+        job: None,
+    }
+}
+
+fn in_job() -> bool {
+    let worker = std::thread::current()
+        .name()
+        .is_some_and(|n| n.starts_with(WORKER_THREAD_PREFIX));
+    worker || CONTEXT.with(|c| c.borrow().phase.is_some())
+}
+
+fn payload_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Installs (once per process) a panic hook recording panics raised inside sort jobs
+/// instead of printing them, so they can be surfaced as [GtfSortError::Internal] by the
+/// [Job] of the panicking thread.
+///
+/// Panics outside of sort jobs are forwarded to the previously installed hook.
+pub fn install_panic_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !in_job() {
+                return prev(info);
+            }
+
+            let report = PanicReport {
+                message: payload_message(info.payload()),
+                location: info.location().map(|l| l.to_string()),
+                context: CONTEXT.with(|c| c.borrow().clone()),
+            };
+            // built with panic = "abort", nothing will catch it and the process ends now
+            if cfg!(panic = "abort") {
+                eprintln!("gtfsort: aborting on panic: {}", report);
+                return;
+            }
+            log::debug!("caught panic: {}", report);
+            JOB.with(|j| j.borrow().record(report));
+        }));
+    });
+}
+
+/// Runs a sort job, turning a panic anywhere in it (including rayon workers) into
+/// [GtfSortError::Internal].
+pub fn catch_job<T, F: FnOnce() -> Result<T, GtfSortError>>(f: F) -> Result<T, GtfSortError> {
+    install_panic_hook();
+
+    let job = Job(Some(Arc::default()));
+    let _guard = job.enter("starting the job", None);
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+    match result {
+        Ok(r) => r,
+        Err(payload) => {
+            let report = job.take().unwrap_or_else(|| PanicReport {
+                message: payload_message(payload.as_ref()),
+                location: None,
+                context: PanicContext::default(),
+            });
+
+            Err(GtfSortError::Internal(Box::new(report)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::par::*;
+
+    #[test]
+    fn panics_become_internal_errors() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .thread_name(|i| format!("{}-{}", WORKER_THREAD_PREFIX, i))
+            .build()
+            .unwrap();
+
+        let err = catch_job(|| {
+            let job = Job::current();
+            job.install(&pool, || {
+                ["chr1", "chr2"].par_iter().for_each(|chrom| {
+                    let _ctx = job.enter("indexing", Some(chrom));
+                    if *chrom == "chr2" {
+                        panic!("boom");
+                    }
+                })
+            });
+            Ok(())
+        })
+        .unwrap_err();
+
+        match err {
+            GtfSortError::Internal(report) => {
+                assert_eq!(report.message, "boom");
+                assert_eq!(report.context.chrom.as_deref(), Some("chr2"));
+                assert!(report.to_string().contains(BUG_REPORT_URL));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn concurrent_jobs_keep_their_own_report() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(4)
+            .thread_name(|i| format!("{}-{}", WORKER_THREAD_PREFIX, i))
+            .build()
+            .unwrap();
+        let barrier = std::sync::Barrier::new(2);
+
+        let chroms = std::thread::scope(|s| {
+            let jobs = ["chr1", "chr2"].map(|chrom| {
+                let (pool, barrier) = (&pool, &barrier);
+                s.spawn(move || {
+                    let err = catch_job::<(), _>(|| {
+                        let job = Job::current();
+                        barrier.wait();
+                        job.install(pool, || {
+                            (0..8).into_par_iter().for_each(|i| {
+                                let _ctx = job.enter("indexing", Some(chrom));
+                                if i == 7 {
+                                    panic!("boom on {}", chrom);
+                                }
+                            })
+                        });
+                        Ok(())
+                    })
+                    .unwrap_err();
+                    match err {
+                        GtfSortError::Internal(report) => {
+                            assert_eq!(report.message, format!("boom on {}", chrom));
+                            report.context.chrom
+                        }
+                        e => panic!("unexpected error: {:?}", e),
+                    }
+                })
+            });
+            jobs.map(|j| j.join().unwrap())
+        });
+
+        assert_eq!(chroms, [Some("chr1".into()), Some("chr2".into())]);
+    }
+
+    #[test]
+    fn errors_pass_through() {
+        let err = catch_job::<(), _>(|| Err(GtfSortError::InvalidParameter("x"))).unwrap_err();
+        assert!(matches!(err, GtfSortError::InvalidParameter("x")));
+    }
+}
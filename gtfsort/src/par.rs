@@ -0,0 +1,195 @@
+//! Parallel iteration and thread pools, backed by rayon with the `parallel` feature and
+//! by serial stand-ins without it.
+//!
+//! The serial stand-ins only cover the subset of the rayon API used by this crate, with
+//! the same names and signatures, so call sites read the same in both builds.
+
+#[cfg(feature = "parallel")]
+pub use rayon::{current_num_threads, prelude::*, ThreadPool, ThreadPoolBuilder};
+
+#[cfg(not(feature = "parallel"))]
+pub use serial::*;
+
+#[cfg(not(feature = "parallel"))]
+mod serial {
+    use std::convert::Infallible;
+
+    /// Runs everything on the calling thread.
+    #[derive(Debug)]
+    pub struct ThreadPool {
+        threads: usize,
+    }
+
+    impl ThreadPool {
+        pub fn install<R, F: FnOnce() -> R>(&self, f: F) -> R {
+            f()
+        }
+
+        /// The number of threads requested, even though only the calling one is used.
+        pub fn current_num_threads(&self) -> usize {
+            self.threads
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct ThreadPoolBuilder {
+        threads: usize,
+    }
+
+    impl ThreadPoolBuilder {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn num_threads(mut self, threads: usize) -> Self {
+            self.threads = threads;
+            self
+        }
+
+        pub fn thread_name<F: FnMut(usize) -> String + 'static>(self, _: F) -> Self {
+            self
+        }
+
+        pub fn build(self) -> Result<ThreadPool, Infallible> {
+            Ok(ThreadPool {
+                threads: self.threads.max(1),
+            })
+        }
+    }
+
+    /// Threads of the current pool, always 1 since everything runs on the calling thread.
+    pub fn current_num_threads() -> usize {
+        1
+    }
+
+    /// A serial iterator exposing the rayon adapters whose signatures differ from
+    /// [Iterator]'s.
+    pub struct Serial<I>(I);
+
+    impl<I: Iterator> Iterator for Serial<I> {
+        type Item = I::Item;
+
+        #[inline(always)]
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.next()
+        }
+    }
+
+    impl<I: Iterator> Serial<I> {
+        pub fn map<B, F: FnMut(I::Item) -> B>(self, f: F) -> Serial<std::iter::Map<I, F>> {
+            Serial(self.0.map(f))
+        }
+
+        pub fn filter<P: FnMut(&I::Item) -> bool>(self, p: P) -> Serial<std::iter::Filter<I, P>> {
+            Serial(self.0.filter(p))
+        }
+
+        pub fn filter_map<B, F: FnMut(I::Item) -> Option<B>>(
+            self,
+            f: F,
+        ) -> Serial<std::iter::FilterMap<I, F>> {
+            Serial(self.0.filter_map(f))
+        }
+
+        pub fn flat_map_iter<U: IntoIterator, F: FnMut(I::Item) -> U>(
+            self,
+            f: F,
+        ) -> Serial<std::iter::FlatMap<I, U, F>> {
+            Serial(self.0.flat_map(f))
+        }
+
+        pub fn take_any_while<P: FnMut(&I::Item) -> bool>(
+            self,
+            p: P,
+        ) -> Serial<std::iter::TakeWhile<I, P>> {
+            Serial(self.0.take_while(p))
+        }
+
+        /// Folds everything into a single accumulator.
+        pub fn fold<T, ID: Fn() -> T, F: FnMut(T, I::Item) -> T>(
+            self,
+            identity: ID,
+            f: F,
+        ) -> Serial<std::iter::Once<T>> {
+            Serial(std::iter::once(self.0.fold(identity(), f)))
+        }
+
+        pub fn reduce<ID: Fn() -> I::Item, F: FnMut(I::Item, I::Item) -> I::Item>(
+            self,
+            identity: ID,
+            f: F,
+        ) -> I::Item {
+            self.0.fold(identity(), f)
+        }
+    }
+
+    pub trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Serial<Self::IntoIter> {
+            Serial(self.into_iter())
+        }
+    }
+
+    impl<T: IntoIterator> IntoParallelIterator for T {}
+
+    pub trait IntoParallelRefIterator<'a> {
+        type Iter: Iterator;
+
+        fn par_iter(&'a self) -> Serial<Self::Iter>;
+    }
+
+    impl<'a, T: 'a + ?Sized> IntoParallelRefIterator<'a> for T
+    where
+        &'a T: IntoIterator,
+    {
+        type Iter = <&'a T as IntoIterator>::IntoIter;
+
+        fn par_iter(&'a self) -> Serial<Self::Iter> {
+            Serial(self.into_iter())
+        }
+    }
+
+    pub trait IntoParallelRefMutIterator<'a> {
+        type Iter: Iterator;
+
+        fn par_iter_mut(&'a mut self) -> Serial<Self::Iter>;
+    }
+
+    impl<'a, T: 'a + ?Sized> IntoParallelRefMutIterator<'a> for T
+    where
+        &'a mut T: IntoIterator,
+    {
+        type Iter = <&'a mut T as IntoIterator>::IntoIter;
+
+        fn par_iter_mut(&'a mut self) -> Serial<Self::Iter> {
+            Serial(self.into_iter())
+        }
+    }
+
+    pub trait ParallelString {
+        fn par_lines(&self) -> Serial<std::str::Lines<'_>>;
+    }
+
+    impl ParallelString for str {
+        fn par_lines(&self) -> Serial<std::str::Lines<'_>> {
+            Serial(self.lines())
+        }
+    }
+
+    pub trait ParallelSliceMut<T> {
+        fn par_sort_by_key<K: Ord, F: Fn(&T) -> K>(&mut self, f: F);
+        fn par_sort_unstable_by_key<K: Ord, F: Fn(&T) -> K>(&mut self, f: F);
+        fn par_sort_by_cached_key<K: Ord, F: Fn(&T) -> K>(&mut self, f: F);
+    }
+
+    impl<T> ParallelSliceMut<T> for [T] {
+        fn par_sort_by_key<K: Ord, F: Fn(&T) -> K>(&mut self, f: F) {
+            self.sort_by_key(f)
+        }
+        fn par_sort_unstable_by_key<K: Ord, F: Fn(&T) -> K>(&mut self, f: F) {
+            self.sort_unstable_by_key(f)
+        }
+        fn par_sort_by_cached_key<K: Ord, F: Fn(&T) -> K>(&mut self, f: F) {
+            self.sort_by_cached_key(f)
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use hashbrown::HashSet;
+use std::cmp::Ordering;
+
+use crate::{features::FeatureMap, gtf::Record, ord::ChildKey};
+
+/// Streaming check of whether `contents` already follows the layout written by the sorter.
+///
+/// The check walks the input once and stops at the first violation:
+/// - chromosomes form contiguous blocks in natural order;
+/// - genes are ordered by start, end and gene_id within a chromosome;
+/// - transcripts follow their gene and children follow their transcript;
+/// - children are ordered by exon_number/feature within a transcript.
+///
+/// Comment lines are only accepted before the first record.
+pub fn is_sorted<const SEP: u8>(contents: &str) -> bool {
+    is_sorted_with::<SEP>(contents, &FeatureMap::default())
+}
+
+/// [is_sorted] with exon-level features ranked by `features`.
+pub fn is_sorted_with<const SEP: u8>(contents: &str, features: &FeatureMap) -> bool {
+    let mut seen_chroms = HashSet::new();
+    let mut chrom: Option<&str> = None;
+    let mut gene: Option<&str> = None;
+    let mut transcript: Option<&str> = None;
+    let mut last_gene = (0, 0, "");
+    // with the start, which orders children sharing an exon_number
+    let mut last_key: Option<(ChildKey, u32)> = None;
+    let mut in_header = true;
+
+    for line in contents.lines() {
+        if line.starts_with('#') {
+            if in_header {
+                continue;
+            }
+            return false;
+        }
+        in_header = false;
+
+        let record = match Record::parse::<SEP>(line) {
+            // GFF3 records known only by their Parent need the full hierarchy pass
+            Ok(r) if r.gene_id.is_empty() => return false,
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+
+        if chrom != Some(record.chrom) {
+            if !seen_chroms.insert(record.chrom) {
+                return false;
+            }
+            if let Some(prev) = chrom {
+                if natord::compare(prev, record.chrom) == Ordering::Greater {
+                    return false;
+                }
+            }
+            chrom = Some(record.chrom);
+            gene = None;
+            transcript = None;
+            last_gene = (0, 0, "");
+        }
+
+        match record.feat {
+            "gene" => {
+                let key = (record.start, record.end, record.gene_id);
+                if key < last_gene {
+                    return false;
+                }
+                last_gene = key;
+                gene = Some(record.gene_id);
+                transcript = None;
+            }
+            "transcript" => {
+                if gene != Some(record.gene_id) {
+                    return false;
+                }
+                transcript = Some(record.transcript_id);
+                last_key = None;
+            }
+            _ => {
+                if gene != Some(record.gene_id) || transcript != Some(record.transcript_id) {
+                    return false;
+                }
+
+                let key = record.inner_key(features);
+                let out_of_order = match &last_key {
+                    Some((prev, _)) if key < *prev => true,
+                    Some((prev, start)) => {
+                        key == *prev && key.is_numbered() && record.start < *start
+                    }
+                    None => false,
+                };
+                if out_of_order {
+                    return false;
+                }
+                last_key = Some((key, record.start));
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SORTED: &str = "#!genome-build test\n\
+        chr1\ts\tgene\t10\t20\t.\t+\t.\tgene_id \"B\";\n\
+        chr1\ts\ttranscript\t10\t20\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr1\ts\texon\t10\t12\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr1\ts\tCDS\t11\t12\t.\t+\t0\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr1\ts\texon\t15\t20\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"2\";\n\
+        chr1\ts\tthree_prime_utr\t19\t20\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr2\ts\tgene\t100\t200\t.\t+\t.\tgene_id \"A\";\n\
+        chr2\ts\ttranscript\t100\t200\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr2\ts\texon\t100\t200\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+
+    #[test]
+    fn sorted_input() {
+        assert!(is_sorted::<b' '>(SORTED));
+        assert!(is_sorted::<b' '>(""));
+    }
+
+    #[test]
+    fn unsorted_inputs() {
+        let lines = SORTED.lines().collect::<Vec<_>>();
+
+        let mut swapped_exons = lines.clone();
+        swapped_exons.swap(3, 5);
+        assert!(!is_sorted::<b' '>(&swapped_exons.join("\n")));
+
+        let mut swapped_chroms = lines[6..].to_vec();
+        swapped_chroms.extend_from_slice(&lines[1..6]);
+        assert!(!is_sorted::<b' '>(&swapped_chroms.join("\n")));
+
+        let mut orphan = lines.clone();
+        orphan.remove(2);
+        assert!(!is_sorted::<b' '>(&orphan.join("\n")));
+
+        // two CDS segments of exon 1, the later one first
+        let mut split_cds = lines.clone();
+        split_cds.insert(
+            4,
+            "chr1\ts\tCDS\t12\t12\t.\t+\t0\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";",
+        );
+        assert!(!is_sorted::<b' '>(&split_cds.join("\n")));
+        split_cds.swap(4, 5);
+        assert!(is_sorted::<b' '>(&split_cds.join("\n")));
+
+        let mut trailing_comment = lines.clone();
+        trailing_comment.push("# trailing");
+        assert!(!is_sorted::<b' '>(&trailing_comment.join("\n")));
+    }
+}
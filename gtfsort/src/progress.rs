@@ -0,0 +1,449 @@
+use std::{
+    fmt::{self, Write as _},
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::json;
+
+/// A stage of a sort job, as reported in [ProgressEvent]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Phase {
+    Parse,
+    Index,
+    Write,
+    Done,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::Index => "index",
+            Self::Write => "write",
+            Self::Done => "done",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A progress update, `fraction` is the completed fraction of the current `phase`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressEvent<'a> {
+    pub phase: Phase,
+    /// The chromosome that was just completed, if the phase works per chromosome.
+    pub chrom: Option<&'a str>,
+    pub fraction: f64,
+    /// Estimated seconds left in the current phase.
+    pub eta_secs: Option<f64>,
+    /// Completed steps of the phase out of `total`, chromosomes when indexing and writing.
+    pub done: usize,
+    pub total: usize,
+    /// Input bytes parsed or output bytes written so far, None if the phase does not
+    /// count bytes.
+    pub bytes: Option<u64>,
+}
+
+impl ProgressEvent<'_> {
+    /// Serializes the event as a single line of JSON.
+    ///
+    /// The schema is stable, fields are never removed or renamed:
+    /// `{"event":"progress","phase":"index","chrom":"chr1","fraction":0.5,"eta_secs":1.25,
+    /// "done":1,"total":2,"bytes":null}`, where `chrom`, `eta_secs` and `bytes` may be
+    /// `null`.
+    pub fn to_json(&self) -> String {
+        let mut s = String::with_capacity(96);
+        write!(
+            s,
+            "{{\"event\":\"progress\",\"phase\":\"{}\",\"chrom\":",
+            self.phase
+        )
+        .unwrap();
+        match self.chrom {
+            Some(chrom) => json::string(&mut s, chrom),
+            None => s.push_str("null"),
+        }
+        write!(s, ",\"fraction\":{:.4},\"eta_secs\":", self.fraction).unwrap();
+        match self.eta_secs {
+            Some(eta) => write!(s, "{:.3}", eta).unwrap(),
+            None => s.push_str("null"),
+        }
+        write!(
+            s,
+            ",\"done\":{},\"total\":{},\"bytes\":",
+            self.done, self.total
+        )
+        .unwrap();
+        match self.bytes {
+            Some(bytes) => write!(s, "{}", bytes).unwrap(),
+            None => s.push_str("null"),
+        }
+        s.push('}');
+        s
+    }
+
+    /// Describes the event on one line for a terminal, e.g.
+    /// `write  40.0%  3/8 chromosomes  1.2 GB  chr5  eta 3s`.
+    pub fn to_text(&self) -> String {
+        let mut s = format!("{:<5} {:>5.1}%", self.phase, self.fraction * 100.0);
+        if matches!(self.phase, Phase::Index | Phase::Write) {
+            write!(s, "  {}/{} chromosomes", self.done, self.total).unwrap();
+        }
+        if let Some(bytes) = self.bytes {
+            write!(s, "  {}", human_bytes(bytes)).unwrap();
+        }
+        if let Some(chrom) = self.chrom {
+            write!(s, "  {}", chrom).unwrap();
+        }
+        if let Some(eta) = self.eta_secs.filter(|_| self.fraction < 1.0) {
+            write!(s, "  eta {:.0}s", eta).unwrap();
+        }
+        s
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut v = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while v >= 1024.0 && unit + 1 < UNITS.len() {
+        v /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", v, UNITS[unit])
+}
+
+/// A shareable sink for [ProgressEvent]s, called from the worker threads.
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(&ProgressEvent) + Send + Sync>);
+
+impl Progress {
+    pub fn new<F: Fn(&ProgressEvent) + Send + Sync + 'static>(f: F) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Writes every event as one line of JSON (NDJSON) to `w`, write errors are ignored.
+    pub fn ndjson<W: Write + Send + 'static>(w: W) -> Self {
+        let w = Mutex::new(w);
+        Self::new(move |e| {
+            if let Ok(mut w) = w.lock() {
+                let _ = writeln!(w, "{}", e.to_json()).and_then(|_| w.flush());
+            }
+        })
+    }
+
+    /// Writes events as readable lines to `w`, at most every 200ms within a phase and
+    /// always when a phase starts or ends. If `overwrite`, e.g. when `w` is a terminal,
+    /// each line replaces the previous one of its phase.
+    pub fn text<W: Write + Send + 'static>(w: W, overwrite: bool) -> Self {
+        const EVERY: Duration = Duration::from_millis(200);
+        // the last phase shown, when and whether its end was
+        let state = Mutex::new((w, None::<(Phase, Instant, bool)>));
+        Self::new(move |e| {
+            let Ok(mut state) = state.lock() else {
+                return;
+            };
+            let (w, last) = &mut *state;
+            let end = e.fraction >= 1.0;
+            match *last {
+                Some((phase, _, true)) if phase == e.phase => return,
+                Some((phase, at, _)) if phase == e.phase && !end && at.elapsed() < EVERY => return,
+                _ => *last = Some((e.phase, Instant::now(), end)),
+            }
+            let line = e.to_text();
+            let _ = if !overwrite {
+                writeln!(w, "{}", line)
+            } else if end {
+                write!(w, "\r\x1b[2K{}\n", line)
+            } else {
+                write!(w, "\r\x1b[2K{}", line)
+            }
+            .and_then(|_| w.flush());
+        })
+    }
+
+    pub fn emit(&self, event: &ProgressEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Progress(..)")
+    }
+}
+
+/// Tracks the completion of one [Phase] made of `total` steps, reporting each step.
+pub struct PhaseProgress<'p> {
+    progress: Option<&'p Progress>,
+    phase: Phase,
+    total: usize,
+    done: AtomicUsize,
+    /// Bytes parsed or written, None if the phase does not count them.
+    bytes: Option<AtomicU64>,
+    /// If known, the fraction follows the bytes instead of the steps.
+    total_bytes: Option<u64>,
+    started: Instant,
+}
+
+impl<'p> PhaseProgress<'p> {
+    /// Starts `phase`, reporting a fraction of 0.
+    pub fn begin(progress: Option<&'p Progress>, phase: Phase, total: usize) -> Self {
+        Self::start(progress, phase, total, None, None)
+    }
+
+    /// Starts `phase`, also counting the bytes it parses or writes out of `total_bytes`
+    /// if known.
+    pub fn begin_bytes(
+        progress: Option<&'p Progress>,
+        phase: Phase,
+        total: usize,
+        total_bytes: Option<u64>,
+    ) -> Self {
+        Self::start(progress, phase, total, Some(AtomicU64::new(0)), total_bytes)
+    }
+
+    fn start(
+        progress: Option<&'p Progress>,
+        phase: Phase,
+        total: usize,
+        bytes: Option<AtomicU64>,
+        total_bytes: Option<u64>,
+    ) -> Self {
+        let p = Self {
+            progress,
+            phase,
+            total,
+            done: AtomicUsize::new(0),
+            bytes,
+            total_bytes,
+            started: Instant::now(),
+        };
+        p.report(None);
+        p
+    }
+
+    /// A tracker that never reports.
+    pub fn disabled() -> Self {
+        Self::begin(None, Phase::Done, 0)
+    }
+
+    /// Marks one step, usually a chromosome, as done.
+    pub fn step(&self, chrom: Option<&str>) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        self.report(chrom);
+    }
+
+    /// Marks one step that parsed or wrote `bytes` as done.
+    pub fn step_bytes(&self, chrom: Option<&str>, bytes: u64) {
+        self.count_bytes(bytes);
+        self.step(chrom);
+    }
+
+    /// Reports `bytes` more parsed or written within the current step.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.count_bytes(bytes);
+        self.report(None);
+    }
+
+    fn count_bytes(&self, bytes: u64) {
+        if let Some(b) = &self.bytes {
+            b.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Reports the phase as complete.
+    pub fn finish(self) {
+        self.done.store(self.total, Ordering::Relaxed);
+        self.emit(None, 1.0);
+    }
+
+    fn report(&self, chrom: Option<&str>) {
+        let fraction = match (&self.bytes, self.total_bytes) {
+            (Some(bytes), Some(total)) if total > 0 => {
+                bytes.load(Ordering::Relaxed) as f64 / total as f64
+            }
+            _ => self.done.load(Ordering::Relaxed) as f64 / self.total.max(1) as f64,
+        };
+        self.emit(chrom, fraction.min(1.0));
+    }
+
+    fn emit(&self, chrom: Option<&str>, fraction: f64) {
+        let Some(progress) = self.progress else {
+            return;
+        };
+
+        let eta_secs = (fraction > 0.0)
+            .then(|| self.started.elapsed().as_secs_f64() * (1.0 - fraction) / fraction);
+
+        progress.emit(&ProgressEvent {
+            phase: self.phase,
+            chrom,
+            fraction,
+            eta_secs,
+            done: self.done.load(Ordering::Relaxed).min(self.total),
+            total: self.total,
+            bytes: self.bytes.as_ref().map(|b| b.load(Ordering::Relaxed)),
+        });
+    }
+}
+
+/// Reports that the whole job is complete.
+pub fn report_done(progress: Option<&Progress>) {
+    if let Some(progress) = progress {
+        progress.emit(&ProgressEvent {
+            phase: Phase::Done,
+            chrom: None,
+            fraction: 1.0,
+            eta_secs: Some(0.0),
+            done: 0,
+            total: 0,
+            bytes: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, SortOptions};
+
+    #[test]
+    fn event_json() {
+        let e = ProgressEvent {
+            phase: Phase::Index,
+            chrom: Some("chr\"1"),
+            fraction: 0.5,
+            eta_secs: Some(1.25),
+            done: 1,
+            total: 2,
+            bytes: None,
+        };
+        assert_eq!(
+            e.to_json(),
+            "{\"event\":\"progress\",\"phase\":\"index\",\"chrom\":\"chr\\\"1\",\"fraction\":0.5000,\"eta_secs\":1.250,\"done\":1,\"total\":2,\"bytes\":null}"
+        );
+        assert_eq!(e.to_text(), "index  50.0%  1/2 chromosomes  chr\"1  eta 1s");
+
+        let e = ProgressEvent {
+            phase: Phase::Parse,
+            chrom: None,
+            fraction: 0.0,
+            eta_secs: None,
+            done: 0,
+            total: 1,
+            bytes: Some(3 * 1024 * 1024 / 2),
+        };
+        assert_eq!(
+            e.to_json(),
+            "{\"event\":\"progress\",\"phase\":\"parse\",\"chrom\":null,\"fraction\":0.0000,\"eta_secs\":null,\"done\":0,\"total\":1,\"bytes\":1572864}"
+        );
+        assert_eq!(e.to_text(), "parse   0.0%  1.5 MB");
+    }
+
+    #[test]
+    fn text_shows_phase_bounds() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let out = Shared::default();
+        let progress = Progress::text(out.clone(), false);
+        let parse = PhaseProgress::begin_bytes(Some(&progress), Phase::Parse, 1, Some(2048));
+        // within 200ms of the start, only the end of the phase is shown, once
+        parse.add_bytes(1024);
+        parse.add_bytes(1024);
+        parse.finish();
+        let index = PhaseProgress::begin(Some(&progress), Phase::Index, 1);
+        index.step(Some("chr1"));
+        index.finish();
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            vec![
+                "parse   0.0%  0 B",
+                "parse 100.0%  2.0 KB",
+                "index   0.0%  0/1 chromosomes",
+                "index 100.0%  1/1 chromosomes  chr1",
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_reports_every_phase() {
+        let input = "chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+            chr2\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+            chr2\ts\texon\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let options = SortOptions {
+            progress: Some(Progress::new(move |e| {
+                sink.lock().unwrap().push((
+                    e.phase,
+                    e.chrom.map(str::to_string),
+                    e.fraction,
+                    e.bytes,
+                ))
+            })),
+            ..Default::default()
+        };
+
+        sort_annotations_string_with_options::<b' ', _>(input, &mut |b| Ok(b.len()), 2, &options)
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        let phases = events.iter().map(|e| e.0).collect::<Vec<_>>();
+        for phase in [Phase::Parse, Phase::Index, Phase::Write] {
+            let fractions = events
+                .iter()
+                .filter(|e| e.0 == phase)
+                .map(|e| e.2)
+                .collect::<Vec<_>>();
+            assert_eq!(fractions.first(), Some(&0.0), "{}", phase);
+            assert_eq!(fractions.last(), Some(&1.0), "{}", phase);
+        }
+        assert_eq!(phases.last(), Some(&Phase::Done));
+
+        let written = events
+            .iter()
+            .filter(|e| e.0 == Phase::Write)
+            .filter_map(|e| e.1.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(written, vec!["chr1", "chr2"]);
+
+        // the whole input is parsed and the whole output written
+        let last_bytes = |phase| events.iter().rfind(|e| e.0 == phase).unwrap().3;
+        assert_eq!(last_bytes(Phase::Parse), Some(input.len() as u64));
+        assert_eq!(last_bytes(Phase::Index), None);
+        assert_eq!(last_bytes(Phase::Write), Some(input.len() as u64));
+    }
+}
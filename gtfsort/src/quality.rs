@@ -0,0 +1,229 @@
+use std::fmt::Write as _;
+
+use crate::{gtf::line_coords, Layers};
+
+/// Nearest-rank summary of a distribution of base pair lengths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quantiles {
+    pub count: usize,
+    pub min: u32,
+    pub p25: u32,
+    pub p50: u32,
+    pub p75: u32,
+    pub p95: u32,
+    pub max: u32,
+}
+
+impl Quantiles {
+    /// Summarizes `values`, sorting them in place. Empty inputs give all zeros.
+    pub fn from_values(values: &mut [u32]) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+
+        values.sort_unstable();
+        let rank =
+            |p: f64| values[((p * values.len() as f64).ceil() as usize).clamp(1, values.len()) - 1];
+
+        Self {
+            count: values.len(),
+            min: values[0],
+            p25: rank(0.25),
+            p50: rank(0.50),
+            p75: rank(0.75),
+            p95: rank(0.95),
+            max: values[values.len() - 1],
+        }
+    }
+
+    fn write_json(&self, s: &mut String) {
+        write!(
+            s,
+            "{{\"count\":{},\"min\":{},\"p25\":{},\"p50\":{},\"p75\":{},\"p95\":{},\"max\":{}}}",
+            self.count, self.min, self.p25, self.p50, self.p75, self.p95, self.max
+        )
+        .unwrap();
+    }
+}
+
+/// Raw observations of one or more chromosomes, merged before summarizing.
+#[derive(Debug, Default)]
+pub struct QualitySamples {
+    genes: usize,
+    transcripts: usize,
+    children: usize,
+    transcripts_beyond_gene: usize,
+    children_beyond_transcript: usize,
+    overhangs: Vec<u32>,
+    exon_gaps: Vec<u32>,
+}
+
+impl QualitySamples {
+    /// Collects span violations and exon gaps from the sorted [Layers] of one chromosome.
+    pub fn from_layers(layers: &Layers) -> Self {
+        let mut q = Self::default();
+
+        for (_, gene_id, gene_line) in layers.layer.iter() {
+            q.genes += 1;
+            let gene = line_coords(gene_line);
+
+            for transcript_id in layers.mapper.get(gene_id).into_iter().flatten() {
+                q.transcripts += 1;
+                let transcript = layers
+                    .helper
+                    .get(transcript_id)
+                    .and_then(|l| line_coords(l));
+
+                if let (Some(outer), Some(inner)) = (gene, transcript) {
+                    if let Some(o) = overhang(outer, inner) {
+                        q.transcripts_beyond_gene += 1;
+                        q.overhangs.push(o);
+                    }
+                }
+
+                let mut exons = Vec::new();
+                let children = layers.inner.get(transcript_id).into_iter().flatten();
+                for line in children.flat_map(|(_, lines)| lines.iter()) {
+                    q.children += 1;
+                    let child = match line_coords(line) {
+                        Some(c) => c,
+                        None => continue,
+                    };
+
+                    if let Some(o) = transcript.and_then(|t| overhang(t, child)) {
+                        q.children_beyond_transcript += 1;
+                        q.overhangs.push(o);
+                    }
+
+                    if line.split('\t').nth(2) == Some("exon") {
+                        exons.push(child);
+                    }
+                }
+
+                exons.sort_unstable();
+                q.exon_gaps.extend(
+                    exons
+                        .windows(2)
+                        .map(|w| w[1].0.saturating_sub(w[0].1).saturating_sub(1)),
+                );
+            }
+        }
+
+        q
+    }
+
+    pub fn merge(mut self, mut other: Self) -> Self {
+        self.genes += other.genes;
+        self.transcripts += other.transcripts;
+        self.children += other.children;
+        self.transcripts_beyond_gene += other.transcripts_beyond_gene;
+        self.children_beyond_transcript += other.children_beyond_transcript;
+        self.overhangs.append(&mut other.overhangs);
+        self.exon_gaps.append(&mut other.exon_gaps);
+        self
+    }
+
+    pub fn summarize(mut self) -> QualityMetrics {
+        QualityMetrics {
+            genes: self.genes,
+            transcripts: self.transcripts,
+            children: self.children,
+            transcripts_beyond_gene: self.transcripts_beyond_gene,
+            children_beyond_transcript: self.children_beyond_transcript,
+            overhang_bp: Quantiles::from_values(&mut self.overhangs),
+            exon_gap_bp: Quantiles::from_values(&mut self.exon_gaps),
+        }
+    }
+}
+
+/// How many bases `inner` sticks out of `outer`, if any.
+fn overhang(outer: (u32, u32), inner: (u32, u32)) -> Option<u32> {
+    let o = outer.0.saturating_sub(inner.0) + inner.1.saturating_sub(outer.1);
+    (o > 0).then_some(o)
+}
+
+/// An annotation quality snapshot computed on the sorted output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QualityMetrics {
+    pub genes: usize,
+    pub transcripts: usize,
+    /// Exons, CDS, UTRs, ... written under a transcript.
+    pub children: usize,
+    /// Transcripts starting before or ending after their gene.
+    pub transcripts_beyond_gene: usize,
+    /// Children starting before or ending after their transcript.
+    pub children_beyond_transcript: usize,
+    /// Bases outside the parent, for every violation above.
+    pub overhang_bp: Quantiles,
+    /// Distance between consecutive exons of a transcript (0 when they touch or overlap).
+    pub exon_gap_bp: Quantiles,
+}
+
+impl QualityMetrics {
+    pub fn violations(&self) -> usize {
+        self.transcripts_beyond_gene + self.children_beyond_transcript
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::with_capacity(384);
+        write!(
+            s,
+            "{{\"genes\":{},\"transcripts\":{},\"children\":{},\"transcripts_beyond_gene\":{},\"children_beyond_transcript\":{},\"overhang_bp\":",
+            self.genes,
+            self.transcripts,
+            self.children,
+            self.transcripts_beyond_gene,
+            self.children_beyond_transcript
+        )
+        .unwrap();
+        self.overhang_bp.write_json(&mut s);
+        s.push_str(",\"exon_gap_bp\":");
+        self.exon_gap_bp.write_json(&mut s);
+        s.push('}');
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, SortOptions};
+
+    #[test]
+    fn quantiles() {
+        let mut v = (1..=100).rev().collect::<Vec<u32>>();
+        let q = Quantiles::from_values(&mut v);
+        assert_eq!(
+            (q.count, q.min, q.p25, q.p50, q.p95, q.max),
+            (100, 1, 25, 50, 95, 100)
+        );
+        assert_eq!(Quantiles::from_values(&mut []), Quantiles::default());
+    }
+
+    #[test]
+    fn span_violations_and_gaps() {
+        let input = "chr1\ts\tgene\t100\t200\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t90\t200\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t90\t120\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+            chr1\ts\texon\t151\t205\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\";\n";
+
+        let ret = sort_annotations_string_with_options::<b' ', _>(
+            input,
+            &mut |b| Ok(b.len()),
+            1,
+            &SortOptions {
+                quality_metrics: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let q = ret.quality.unwrap();
+        assert_eq!((q.genes, q.transcripts, q.children), (1, 1, 2));
+        assert_eq!(q.transcripts_beyond_gene, 1);
+        assert_eq!(q.children_beyond_transcript, 1);
+        assert_eq!((q.overhang_bp.min, q.overhang_bp.max), (5, 10));
+        assert_eq!((q.exon_gap_bp.count, q.exon_gap_bp.p50), (1, 30));
+        assert!(q.to_json().starts_with("{\"genes\":1,"));
+    }
+}
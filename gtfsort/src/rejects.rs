@@ -0,0 +1,263 @@
+//! Records the sorter cannot place in the output, copied verbatim to a sidecar file so
+//! that nothing is left out without a trace, see [crate::SortOptions::rejects].
+//!
+//! A record is rejected when it does not parse, when its GFF3 `Parent` chain does not
+//! lead to a top-level feature, or when its gene or transcript has no line of its own
+//! (see [crate::utils::Layers::orphan_lines]). Each one is written after a comment
+//! giving its line number and the reason.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, Write},
+    path::Path,
+};
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::{
+    filter::ChromFilter,
+    format::Newline,
+    gtf::Record,
+    utils::{lines_at, write_line, ChromRecord, Index},
+    GtfSortError, LineCounts,
+};
+
+/// Why a parsed record has no place in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orphan<'a> {
+    /// A transcript whose gene has no gene line.
+    NoGene { gene_id: &'a str },
+    /// A child of a transcript left out for lack of a gene line.
+    DroppedTranscript {
+        transcript_id: &'a str,
+        gene_id: &'a str,
+    },
+    /// A child whose transcript has no transcript line.
+    NoTranscript { transcript_id: &'a str },
+}
+
+impl fmt::Display for Orphan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoGene { gene_id } => write!(f, "gene {:?} has no gene line", gene_id),
+            Self::DroppedTranscript {
+                transcript_id,
+                gene_id,
+            } => write!(
+                f,
+                "its transcript {:?} was left out, gene {:?} has no gene line",
+                transcript_id, gene_id
+            ),
+            Self::NoTranscript { transcript_id } => {
+                write!(f, "transcript {:?} has no transcript line", transcript_id)
+            }
+        }
+    }
+}
+
+/// A record left out of the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reject<'a> {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    pub reason: Cow<'a, str>,
+    pub line: &'a str,
+}
+
+/// The records of `contents` that are neither in `records` nor placed in `index`, in
+/// input order, told apart by their [Record::ordinal]. `records` and `index` must have
+/// been built from `contents` with the rejects requested, `chroms` is the filter they
+/// were parsed with.
+pub fn collect<'a, const SEP: u8>(
+    contents: &'a str,
+    records: &ChromRecord<'a>,
+    index: &Index<'a>,
+    chroms: Option<&ChromFilter>,
+) -> Vec<Reject<'a>> {
+    let orphans = index
+        .values()
+        .flat_map(|layers| layers.orphaned.iter())
+        .map(|(ordinal, orphan)| (*ordinal, orphan))
+        .collect::<HashMap<_, _>>();
+    // GFF3 records without a resolvable parent are dropped right after parsing
+    let parsed = (SEP == b'=').then(|| {
+        records
+            .values()
+            .flatten()
+            .map(|r| r.ordinal)
+            .collect::<HashSet<_>>()
+    });
+
+    let mut counts = LineCounts::default();
+    let mut rejects = Vec::new();
+    for (i, (ordinal, line)) in lines_at(contents, 0).enumerate() {
+        if counts.count_non_record(line, chroms) {
+            continue;
+        }
+        let reason = match Record::parse::<SEP>(line) {
+            Err(e) => Cow::Owned(format!("invalid record: {}", e)),
+            Ok(record) => match orphans.get(&ordinal) {
+                Some(orphan) => Cow::Owned(orphan.to_string()),
+                None if parsed.as_ref().is_some_and(|p| !p.contains(&ordinal)) => {
+                    Cow::Owned(format!(
+                        "Parent {:?} does not lead to a top-level feature",
+                        record.parent
+                    ))
+                }
+                None => continue,
+            },
+        };
+        rejects.push(Reject {
+            line_number: i + 1,
+            reason,
+            line,
+        });
+    }
+
+    rejects
+}
+
+/// Writes each reject as a `# line N: reason` comment followed by the record as read.
+pub fn write<W: Write>(mut w: W, rejects: &[Reject], newline: Newline) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for reject in rejects {
+        buf.clear();
+        let comment = format!("# line {}: {}", reject.line_number, reject.reason);
+        write_line(&mut buf, &comment, newline)?;
+        write_line(&mut buf, reject.line, newline)?;
+        w.write_all(&buf)?;
+    }
+    w.flush()
+}
+
+pub fn write_file<P: AsRef<Path>>(
+    path: P,
+    rejects: &[Reject],
+    newline: Newline,
+) -> Result<(), GtfSortError> {
+    std::fs::File::create(path.as_ref())
+        .and_then(|f| write(io::BufWriter::new(f), rejects, newline))
+        .map_err(|e| GtfSortError::IoError("writing rejected records", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{index_records, parallel_parse, SortOptions};
+
+    const GTF: &str = "chr1\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr1\ts\texon\t1\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr1\ts\texon\t1\tx\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\tCDS\t1\t90\t.\t+\t0\tgene_id \"A\"; transcript_id \"A.2\"; exon_number \"1\";\n";
+
+    #[test]
+    fn unplaced_records_are_rejected() {
+        let options = SortOptions {
+            rejects: Some("rejects.gtf".into()),
+            ..Default::default()
+        };
+        let records = parallel_parse::<b' '>(GTF).unwrap();
+        let index = index_records::<b' '>(&records, &options).unwrap();
+        let rejects = collect::<b' '>(GTF, &records, &index, None);
+
+        assert_eq!(
+            rejects
+                .iter()
+                .map(|r| (r.line_number, r.reason.as_ref()))
+                .collect::<Vec<_>>(),
+            [
+                (4, "gene \"B\" has no gene line"),
+                (
+                    5,
+                    "its transcript \"B.1\" was left out, gene \"B\" has no gene line"
+                ),
+                (6, "invalid record: Invalid end"),
+                (7, "transcript \"A.2\" has no transcript line"),
+            ]
+        );
+
+        let mut out = Vec::new();
+        write(&mut out, &rejects[..1], Newline::Lf).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "# line 4: gene \"B\" has no gene line\n{}\n",
+                GTF.lines().nth(3).unwrap()
+            )
+        );
+
+        let gff = "chr1\ts\tgene\t1\t90\t.\t+\t.\tID=g1\n\
+            chr1\ts\ttranscript\t1\t90\t.\t+\t.\tID=t1;Parent=g1\n\
+            chr1\ts\texon\t1\t90\t.\t+\t.\tParent=t9\n";
+        let records = parallel_parse::<b'='>(gff).unwrap();
+        let index = index_records::<b'='>(&records, &options).unwrap();
+        let rejects = collect::<b'='>(gff, &records, &index, None);
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].line_number, 3);
+        assert!(
+            rejects[0].reason.contains("\"t9\""),
+            "{}",
+            rejects[0].reason
+        );
+    }
+
+    #[test]
+    fn identical_lines_keep_their_own_line_numbers() {
+        let options = SortOptions {
+            rejects: Some("rejects.gtf".into()),
+            ..Default::default()
+        };
+        let transcript =
+            "chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";";
+        let input = format!(
+            "chr1\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\";\r\n{t}\r\n# comment\r\n{t}\r\n",
+            t = transcript
+        );
+        let records = parallel_parse::<b' '>(&input).unwrap();
+        let index = index_records::<b' '>(&records, &options).unwrap();
+        let rejects = collect::<b' '>(&input, &records, &index, None);
+
+        assert_eq!(
+            rejects
+                .iter()
+                .map(|r| (r.line_number, r.reason.as_ref(), r.line))
+                .collect::<Vec<_>>(),
+            [
+                (2, "gene \"B\" has no gene line", transcript),
+                (
+                    4,
+                    "its transcript \"B.1\" was left out, gene \"B\" has no gene line",
+                    transcript
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn sorted_input_is_not_skipped() {
+        let dir = crate::temp::TempPath::dir(None, "rejects_test").unwrap();
+        let path = dir.join("rejects.gtf");
+        let input = "chr1\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+        assert!(crate::presorted::is_sorted::<b' '>(input));
+
+        crate::sort_annotations_string_with_options::<b' ', _>(
+            input,
+            &mut |b| Ok(b.len()),
+            1,
+            &SortOptions {
+                rejects: Some(path.clone()),
+                skip_if_sorted: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    }
+}
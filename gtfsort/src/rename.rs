@@ -0,0 +1,254 @@
+//! Chromosome renaming, e.g. `chr1` ↔ `1` and `chrM` ↔ `MT`, so that the sorted output
+//! uses one naming scheme whatever the input mixes, see [crate::SortOptions::chrom_rename].
+//!
+//! Lines are rewritten before they are parsed, `##sequence-region` directives included: a
+//! name found in the alias table is replaced by its alias, and a numbered, sex or mitochondrial chromosome is otherwise
+//! converted to the [ChromNaming] if one is set. Scaffolds are named differently by each
+//! source (`KI270728.1` against `chr1_KI270728v1_random`) and need an alias file, e.g.
+//! one of the UCSC `chromAlias` tables.
+
+use std::{borrow::Cow, fmt, path::Path, str::FromStr};
+
+use hashbrown::HashMap;
+
+use crate::GtfSortError;
+
+/// The GFF3 directive declaring the extent of a sequence, whose seqid is renamed.
+const SEQUENCE_REGION: &str = "##sequence-region";
+
+/// A chromosome naming scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromNaming {
+    /// `chr1`, `chrX`, `chrM`.
+    Ucsc,
+    /// `1`, `X`, `MT`.
+    Ensembl,
+}
+
+impl FromStr for ChromNaming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ucsc" | "chr" => Ok(Self::Ucsc),
+            "ensembl" | "ncbi" => Ok(Self::Ensembl),
+            _ => Err(format!(
+                "unknown chromosome naming {:?}, expected ucsc or ensembl",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ChromNaming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ucsc => "ucsc",
+            Self::Ensembl => "ensembl",
+        })
+    }
+}
+
+/// Whether `chrom` starts with "chr", in any case.
+pub(crate) fn has_chr_prefix(chrom: &str) -> bool {
+    chrom
+        .get(..3)
+        .is_some_and(|p| p.eq_ignore_ascii_case("chr"))
+}
+
+/// Whether `chrom` is a numbered, sex or mitochondrial chromosome, with or without a
+/// "chr" prefix. Scaffolds are named differently by each source and are not compared.
+pub(crate) fn is_primary(chrom: &str) -> bool {
+    let name = if has_chr_prefix(chrom) {
+        &chrom[3..]
+    } else {
+        chrom
+    };
+    (!name.is_empty() && name.len() <= 2 && name.bytes().all(|b| b.is_ascii_digit()))
+        || ["X", "Y", "M", "MT", "W", "Z"]
+            .iter()
+            .any(|n| name.eq_ignore_ascii_case(n))
+}
+
+/// Renames chromosomes by an alias table, then by a [ChromNaming].
+#[derive(Debug, Clone, Default)]
+pub struct ChromRenamer {
+    aliases: HashMap<String, String>,
+    naming: Option<ChromNaming>,
+}
+
+impl ChromRenamer {
+    pub fn new(naming: Option<ChromNaming>) -> Self {
+        Self {
+            aliases: HashMap::new(),
+            naming,
+        }
+    }
+
+    /// Adds `(from, to)` aliases, later ones replacing earlier ones.
+    pub fn with_aliases<I: IntoIterator<Item = (S, S)>, S: Into<String>>(
+        mut self,
+        aliases: I,
+    ) -> Self {
+        self.aliases
+            .extend(aliases.into_iter().map(|(a, b)| (a.into(), b.into())));
+        self
+    }
+
+    /// Reads aliases from a file of two whitespace-separated columns, the name to replace
+    /// and its replacement. Empty lines and lines starting with '#' are skipped.
+    pub fn with_alias_file(self, path: &Path) -> Result<Self, GtfSortError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| GtfSortError::IoError("reading chromosome alias file", e))?;
+        let mut aliases = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [from, to] => aliases.push((from.to_string(), to.to_string())),
+                _ => {
+                    return Err(GtfSortError::InvalidInput(format!(
+                        "{:?} line {}: expected two columns, the name and its alias, got {:?}",
+                        path,
+                        i + 1,
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(self.with_aliases(aliases))
+    }
+
+    /// The name `chrom` is written under.
+    pub fn rename<'a>(&'a self, chrom: &'a str) -> Cow<'a, str> {
+        if let Some(alias) = self.aliases.get(chrom) {
+            return Cow::Borrowed(alias);
+        }
+        match self.naming {
+            Some(naming) if is_primary(chrom) => convert(chrom, naming),
+            _ => Cow::Borrowed(chrom),
+        }
+    }
+
+    /// `line` with its first column renamed, borrowed if it is unchanged. The sequence of
+    /// a `##sequence-region` directive is renamed too, other comments and lines without a
+    /// tab are returned as they are.
+    pub fn rename_line<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if let Some(rest) = line.strip_prefix(SEQUENCE_REGION) {
+            return self.rename_sequence_region(line, rest);
+        }
+        if line.starts_with('#') {
+            return Cow::Borrowed(line);
+        }
+        let Some((chrom, rest)) = line.split_once('\t') else {
+            return Cow::Borrowed(line);
+        };
+        match self.rename(chrom) {
+            renamed if renamed == chrom => Cow::Borrowed(line),
+            renamed => Cow::Owned(format!("{}\t{}", renamed, rest)),
+        }
+    }
+
+    /// `##sequence-region seqid start end` with `seqid` renamed, `rest` following the
+    /// directive in `line`.
+    fn rename_sequence_region<'a>(&self, line: &'a str, rest: &'a str) -> Cow<'a, str> {
+        let body = rest.trim_start();
+        let space = &rest[..rest.len() - body.len()];
+        let seqid = body.split_whitespace().next().unwrap_or_default();
+        if space.is_empty() || seqid.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        match self.rename(seqid) {
+            renamed if renamed == seqid => Cow::Borrowed(line),
+            renamed => Cow::Owned(format!(
+                "{}{}{}{}",
+                SEQUENCE_REGION,
+                space,
+                renamed,
+                &body[seqid.len()..]
+            )),
+        }
+    }
+}
+
+/// Converts a primary chromosome name to `naming`.
+fn convert(chrom: &str, naming: ChromNaming) -> Cow<'_, str> {
+    let bare = if has_chr_prefix(chrom) {
+        &chrom[3..]
+    } else {
+        chrom
+    };
+    let mito = bare.eq_ignore_ascii_case("m") || bare.eq_ignore_ascii_case("mt");
+    match naming {
+        ChromNaming::Ensembl if mito => Cow::Borrowed("MT"),
+        ChromNaming::Ensembl => Cow::Borrowed(bare),
+        ChromNaming::Ucsc if mito => Cow::Borrowed("chrM"),
+        ChromNaming::Ucsc if chrom.starts_with("chr") => Cow::Borrowed(chrom),
+        ChromNaming::Ucsc => Cow::Owned(format!("chr{}", bare)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primary_chromosomes_follow_the_naming() {
+        let ucsc = ChromRenamer::new(Some(ChromNaming::Ucsc));
+        for (from, to) in [
+            ("1", "chr1"),
+            ("X", "chrX"),
+            ("MT", "chrM"),
+            ("chrM", "chrM"),
+            ("chr2", "chr2"),
+            ("KI270728.1", "KI270728.1"),
+        ] {
+            assert_eq!(ucsc.rename(from), to, "{}", from);
+        }
+
+        let ensembl = ChromRenamer::new(Some(ChromNaming::Ensembl));
+        for (from, to) in [
+            ("chr1", "1"),
+            ("chrX", "X"),
+            ("chrM", "MT"),
+            ("M", "MT"),
+            ("1", "1"),
+            ("chr1_KI270706v1_random", "chr1_KI270706v1_random"),
+        ] {
+            assert_eq!(ensembl.rename(from), to, "{}", from);
+        }
+    }
+
+    #[test]
+    fn aliases_come_first() {
+        let renamer = ChromRenamer::new(Some(ChromNaming::Ucsc))
+            .with_aliases([("KI270728.1", "chr16_KI270728v1_random"), ("1", "chr01")]);
+        assert_eq!(renamer.rename("KI270728.1"), "chr16_KI270728v1_random");
+        assert_eq!(renamer.rename("1"), "chr01");
+        assert_eq!(renamer.rename("2"), "chr2");
+
+        let line = "1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"1\";";
+        assert_eq!(
+            renamer.rename_line(line),
+            "chr01\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"1\";"
+        );
+        assert!(matches!(
+            renamer.rename_line("#!genome-build 1"),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(
+            renamer.rename_line("chr3\ts\tgene"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            renamer.rename_line("##sequence-region   1 1 248956422"),
+            "##sequence-region   chr01 1 248956422"
+        );
+        assert!(matches!(
+            renamer.rename_line("##sequence-region chr3 1 9"),
+            Cow::Borrowed(_)
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{current_mem_usage_mb, json, process_cpu_secs, progress::Phase};
+
+/// Sampling interval used by the command line when --sample-interval is not given.
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Resource usage of the process over one sampling interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ResourceSample {
+    /// The phase the job was in when the sample was taken.
+    pub phase: Phase,
+    /// Seconds since the sampler started.
+    pub elapsed_secs: f64,
+    pub rss_mb: f64,
+    /// CPU time over wall time since the previous sample; 100 per fully busy core, so a
+    /// CPU-bound phase on 8 threads reads close to 800 and an IO-bound one stays low.
+    pub cpu_percent: f64,
+}
+
+/// Aggregate of the samples of one phase.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PhaseUsage {
+    pub phase: Phase,
+    pub samples: usize,
+    pub mean_cpu_percent: f64,
+    pub peak_rss_mb: f64,
+}
+
+/// Time series of the resource usage of a sort job.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ResourceReport {
+    pub interval_ms: u64,
+    pub samples: Vec<ResourceSample>,
+}
+
+impl ResourceReport {
+    /// Summarizes the samples per phase, in the order the phases ran.
+    pub fn phases(&self) -> Vec<PhaseUsage> {
+        let mut phases: Vec<PhaseUsage> = Vec::new();
+
+        for s in self.samples.iter() {
+            let usage = match phases.iter_mut().find(|p| p.phase == s.phase) {
+                Some(p) => p,
+                None => {
+                    phases.push(PhaseUsage {
+                        phase: s.phase,
+                        samples: 0,
+                        mean_cpu_percent: 0.0,
+                        peak_rss_mb: 0.0,
+                    });
+                    phases.last_mut().unwrap()
+                }
+            };
+
+            usage.samples += 1;
+            usage.mean_cpu_percent +=
+                (s.cpu_percent - usage.mean_cpu_percent) / usage.samples as f64;
+            usage.peak_rss_mb = usage.peak_rss_mb.max(s.rss_mb);
+        }
+
+        phases
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::with_capacity(128 + self.samples.len() * 80);
+        write!(s, "{{\"interval_ms\":{},\"phases\":[", self.interval_ms).unwrap();
+        for (i, p) in self.phases().iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "{{\"phase\":\"{}\",\"samples\":{},\"mean_cpu_percent\":",
+                p.phase, p.samples
+            )
+            .unwrap();
+            json::number(&mut s, p.mean_cpu_percent, 3);
+            s.push_str(",\"peak_rss_mb\":");
+            json::number(&mut s, p.peak_rss_mb, 3);
+            s.push('}');
+        }
+
+        s.push_str("],\"samples\":[");
+        for (i, sample) in self.samples.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(s, "{{\"phase\":\"{}\",\"elapsed_secs\":", sample.phase).unwrap();
+            json::number(&mut s, sample.elapsed_secs, 3);
+            s.push_str(",\"rss_mb\":");
+            json::number(&mut s, sample.rss_mb, 3);
+            s.push_str(",\"cpu_percent\":");
+            json::number(&mut s, sample.cpu_percent, 3);
+            s.push('}');
+        }
+        s.push_str("]}");
+        s
+    }
+}
+
+/// Writes `v` with 3 decimals, or `null` if it is not finite (e.g. not sampled).
+fn phase_from_u8(n: u8) -> Phase {
+    match n {
+        0 => Phase::Parse,
+        1 => Phase::Index,
+        2 => Phase::Write,
+        _ => Phase::Done,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    stop: AtomicBool,
+    phase: AtomicU8,
+}
+
+/// A lightweight sampler thread recording RSS and CPU usage of the process, tagged
+/// with the phase the job is in.
+#[derive(Debug)]
+pub struct ResourceSampler {
+    shared: Arc<Shared>,
+    interval: Duration,
+    handle: Option<JoinHandle<Vec<ResourceSample>>>,
+}
+
+impl ResourceSampler {
+    /// Creates a sampler that records nothing.
+    pub fn disabled() -> Self {
+        Self {
+            shared: Arc::new(Shared::default()),
+            interval: Duration::ZERO,
+            handle: None,
+        }
+    }
+
+    /// Spawns a sampler taking a sample every `interval`, if set.
+    pub fn spawn(interval: Option<Duration>) -> Self {
+        let interval = match interval {
+            Some(i) if !i.is_zero() => i,
+            _ => return Self::disabled(),
+        };
+
+        let shared = Arc::new(Shared::default());
+        let handle = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("gtfsort-sampler".to_string())
+                .spawn(move || {
+                    let start = Instant::now();
+                    let mut last = (start, process_cpu_secs());
+                    let mut samples = Vec::new();
+
+                    loop {
+                        std::thread::park_timeout(interval);
+                        let stopping = shared.stop.load(Ordering::Acquire);
+
+                        let (now, cpu) = (Instant::now(), process_cpu_secs());
+                        let wall = now.duration_since(last.0).as_secs_f64();
+                        if wall > 0.0 {
+                            samples.push(ResourceSample {
+                                phase: phase_from_u8(shared.phase.load(Ordering::Relaxed)),
+                                elapsed_secs: now.duration_since(start).as_secs_f64(),
+                                rss_mb: current_mem_usage_mb(),
+                                cpu_percent: (cpu - last.1) / wall * 100.0,
+                            });
+                        }
+                        last = (now, cpu);
+
+                        if stopping {
+                            break samples;
+                        }
+                    }
+                })
+        };
+
+        match handle {
+            Ok(handle) => Self {
+                shared,
+                interval,
+                handle: Some(handle),
+            },
+            Err(e) => {
+                log::warn!("failed to spawn resource sampler thread: {}", e);
+                Self::disabled()
+            }
+        }
+    }
+
+    /// Tags the following samples with `phase`.
+    #[inline(always)]
+    pub fn enter(&self, phase: Phase) {
+        self.shared.phase.store(phase as u8, Ordering::Relaxed);
+    }
+
+    fn stop(&mut self) -> Option<Vec<ResourceSample>> {
+        self.shared.stop.store(true, Ordering::Release);
+        let handle = self.handle.take()?;
+        handle.thread().unpark();
+        handle.join().ok()
+    }
+
+    /// Takes a last sample and returns the report, None if the sampler is disabled.
+    pub fn finish(mut self) -> Option<ResourceReport> {
+        let samples = self.stop()?;
+        Some(ResourceReport {
+            interval_ms: self.interval.as_millis() as u64,
+            samples,
+        })
+    }
+}
+
+impl Drop for ResourceSampler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, SortOptions};
+
+    #[test]
+    fn disabled_records_nothing() {
+        assert!(ResourceSampler::spawn(None).finish().is_none());
+    }
+
+    #[test]
+    fn samples_are_tagged_by_phase() {
+        let sampler = ResourceSampler::spawn(Some(Duration::from_millis(5)));
+        std::thread::sleep(Duration::from_millis(30));
+        sampler.enter(Phase::Write);
+        std::thread::sleep(Duration::from_millis(30));
+
+        let report = sampler.finish().unwrap();
+        let phases = report.phases();
+        assert_eq!(phases.first().unwrap().phase, Phase::Parse);
+        assert_eq!(phases.last().unwrap().phase, Phase::Write);
+        assert!(report.samples.iter().all(|s| s.rss_mb > 0.0));
+
+        let json = report.to_json();
+        assert!(json.starts_with("{\"interval_ms\":5,\"phases\":[{\"phase\":\"parse\""));
+        assert!(json.ends_with("}]}"));
+    }
+
+    #[test]
+    fn sort_reports_resources() {
+        let input = "chr1\ts\tgene\t1\t100\t.\t+\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t1\t100\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+        let ret = sort_annotations_string_with_options::<b' ', _>(
+            input,
+            &mut |b| Ok(b.len()),
+            1,
+            &SortOptions {
+                sample_interval: Some(Duration::from_millis(1)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(!ret.resources.unwrap().samples.is_empty());
+    }
+}
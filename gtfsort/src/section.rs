@@ -0,0 +1,152 @@
+//! Annotations embedded in other text, e.g. a tool report wrapping a GTF/GFF3 block in
+//! commentary. Only the annotation section is sorted, the text before and after it is
+//! copied through unchanged, see [crate::SortOptions::section].
+
+use crate::{gtf::line_coords, GtfSortError};
+
+/// Where the annotation section of a document starts and ends.
+///
+/// A marker matches the first line starting with it (leading whitespace ignored); the
+/// marker lines themselves stay with the surrounding text. Without a start marker the
+/// section starts at the first record, together with the `#` lines right before it (e.g.
+/// `##gff-version 3`), and without an end marker it ends after the last record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SectionMarkers {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+/// A document split around its annotation section, the three parts concatenated give
+/// back the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sections<'a> {
+    pub head: &'a str,
+    pub body: &'a str,
+    pub tail: &'a str,
+}
+
+impl Sections<'_> {
+    /// Lines outside the annotation section.
+    pub fn surrounding_lines(&self) -> usize {
+        self.head.lines().count() + self.tail.lines().count()
+    }
+}
+
+/// Looks like a GTF/GFF3 record: nine tab separated columns with numeric coordinates.
+fn is_record(line: &str) -> bool {
+    !line.starts_with('#') && line.matches('\t').count() >= 8 && line_coords(line).is_some()
+}
+
+fn matches(line: &str, marker: &str) -> bool {
+    line.trim_start().starts_with(marker)
+}
+
+/// Splits `contents` into the text before the annotation section, the section and the
+/// text after it. Fails if a marker is given but not found.
+pub fn split<'a>(
+    contents: &'a str,
+    markers: &SectionMarkers,
+) -> Result<Sections<'a>, GtfSortError> {
+    // (byte offset, line) of every line, line breaks included
+    let lines = contents
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let at = *offset;
+            *offset += line.len();
+            Some((at, line))
+        })
+        .collect::<Vec<_>>();
+    let offset = |i: usize| lines.get(i).map_or(contents.len(), |(at, _)| *at);
+
+    let start = match &markers.start {
+        Some(marker) => {
+            lines
+                .iter()
+                .position(|(_, l)| matches(l, marker))
+                .ok_or_else(|| {
+                    GtfSortError::InvalidInput(format!(
+                        "the start marker of the annotation section ({:?}) was not found",
+                        marker
+                    ))
+                })?
+                + 1
+        }
+        None => match lines.iter().position(|(_, l)| is_record(l)) {
+            Some(mut i) => {
+                while i > 0 && lines[i - 1].1.starts_with('#') {
+                    i -= 1;
+                }
+                i
+            }
+            None => lines.len(),
+        },
+    };
+
+    let end = match &markers.end {
+        Some(marker) => {
+            start
+                + lines[start..]
+                    .iter()
+                    .position(|(_, l)| matches(l, marker))
+                    .ok_or_else(|| {
+                        GtfSortError::InvalidInput(format!(
+                            "the end marker of the annotation section ({:?}) was not found after its start",
+                            marker
+                        ))
+                    })?
+        }
+        None => lines[start..]
+            .iter()
+            .rposition(|(_, l)| is_record(l))
+            .map_or(start, |i| start + i + 1),
+    };
+
+    let (head, rest) = contents.split_at(offset(start));
+    let (body, tail) = rest.split_at(offset(end) - offset(start));
+    Ok(Sections { head, body, tail })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = "Report of the annotation\n\
+        genes: 2\n\
+        ##gff-version 3\n\
+        chr1\ts\tgene\t5\t9\t.\t+\t.\tID=B\n\
+        chr1\ts\tgene\t1\t9\t.\t+\t.\tID=A\n\
+        \n\
+        end of report\n";
+
+    #[test]
+    fn sections_are_found() {
+        let auto = split(REPORT, &SectionMarkers::default()).unwrap();
+        assert_eq!(auto.head, "Report of the annotation\ngenes: 2\n");
+        assert!(auto.body.starts_with("##gff-version 3\n"));
+        assert!(auto.body.ends_with("ID=A\n"));
+        assert_eq!(auto.tail, "\nend of report\n");
+        assert_eq!(auto.surrounding_lines(), 4);
+
+        let marked = split(
+            REPORT,
+            &SectionMarkers {
+                start: Some("genes:".to_string()),
+                end: Some("end of".to_string()),
+            },
+        )
+        .unwrap();
+        assert_eq!(marked.head, "Report of the annotation\ngenes: 2\n");
+        assert!(marked.body.ends_with("ID=A\n\n"));
+        assert_eq!(marked.tail, "end of report\n");
+        assert_eq!([marked.head, marked.body, marked.tail].concat(), REPORT);
+
+        let missing = SectionMarkers {
+            start: None,
+            end: Some("genes:".to_string()),
+        };
+        assert!(split(REPORT, &missing).is_err());
+
+        let none = split("just text\n", &SectionMarkers::default()).unwrap();
+        assert_eq!((none.head, none.body, none.tail), ("just text\n", "", ""));
+    }
+}
@@ -0,0 +1,164 @@
+//! Checks run on the first bytes of an input before it is parsed, so that a BAM,
+//! compressed or otherwise binary file renamed to .gtf fails fast with a clear message
+//! instead of being parsed as gigabytes of invalid lines. Also splits off the `##FASTA`
+//! section that may end a GFF3 file.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use crate::{gzip, ErrorContext, GtfSortError};
+
+/// Bytes of the start of an input that are checked.
+pub(crate) const SNIFF_BYTES: usize = 64 * 1024;
+
+/// Directive starting the sequences at the end of a GFF3 file, see [split_fasta].
+pub const FASTA_DIRECTIVE: &str = "##FASTA";
+
+/// Magic bytes of the binary formats most often passed by mistake.
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x1f\x8b", "gzip-compressed data"),
+    (b"BAM\x01", "BAM"),
+    (b"CRAM", "CRAM"),
+    (b"BCF\x02", "BCF"),
+    (b"BZh", "bzip2-compressed data"),
+    (b"\xfd7zXZ\x00", "xz-compressed data"),
+    (b"\x28\xb5\x2f\xfd", "zstd-compressed data"),
+    (b"\x04\x22\x4d\x18", "LZ4-compressed data"),
+    (b"PK\x03\x04", "a zip archive"),
+    (b"\x89HDF\r\n\x1a\n", "HDF5"),
+    (b"SQLite format 3\x00", "an SQLite database"),
+    (b"%PDF-", "a PDF document"),
+    (b"\x7fELF", "an ELF executable"),
+];
+
+/// Returns an error if `head`, the first bytes of an input, look like a binary file: a
+/// known magic number, a NUL or control byte, or invalid UTF-8. A multi-byte character
+/// cut at the end of `head` is accepted.
+pub fn check_text(head: &[u8]) -> Result<(), GtfSortError> {
+    let context = |offset: usize| ErrorContext {
+        line: Some(head[..offset].iter().filter(|b| **b == b'\n').count() + 1),
+        byte_offset: Some(offset as u64),
+        ..ErrorContext::phase("sniffing input")
+    };
+
+    if let Some((magic, kind)) = MAGIC.iter().find(|(m, _)| head.starts_with(m)) {
+        // BAM and BCF files are BGZF, i.e. gzip with a BC extra field
+        let kind = match head {
+            [0x1f, 0x8b, _, flags, ..] if flags & 4 != 0 && head.get(12..14) == Some(b"BC") => {
+                "BGZF-compressed data (BAM, BCF or a bgzipped file)"
+            }
+            _ => kind,
+        };
+        let hex = magic
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        return Err(GtfSortError::InvalidInput(format!(
+            "input looks like {} (magic bytes {}), not GTF/GFF3 text",
+            kind, hex
+        ))
+        .with_context(context(0)));
+    }
+
+    let valid = match std::str::from_utf8(head) {
+        Ok(_) => head.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(e) => {
+            return Err(GtfSortError::InvalidInput(format!(
+                "input looks binary (invalid UTF-8 byte {:#04x}), not GTF/GFF3 text",
+                head[e.valid_up_to()]
+            ))
+            .with_context(context(e.valid_up_to())))
+        }
+    };
+
+    match head[..valid]
+        .iter()
+        .position(|b| b.is_ascii_control() && !matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c'))
+    {
+        Some(i) => Err(GtfSortError::InvalidInput(format!(
+            "input looks binary (control byte {:#04x}), not GTF/GFF3 text",
+            head[i]
+        ))
+        .with_context(context(i))),
+        None => Ok(()),
+    }
+}
+
+/// [check_text] on the first [SNIFF_BYTES] of the file at `path`, after decompression if
+/// it is gzip-compressed (see [crate::gzip]).
+pub fn check_file(path: &Path) -> Result<(), GtfSortError> {
+    let mut head = Vec::with_capacity(SNIFF_BYTES);
+    File::open(path)
+        .and_then(|f| f.take(SNIFF_BYTES as u64).read_to_end(&mut head))
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => GtfSortError::IoError("opening input file", e),
+            _ => GtfSortError::IoError("reading input file", e),
+        })?;
+    if gzip::is_gzip(&head) {
+        head.clear();
+        gzip::open(path)?
+            .take(SNIFF_BYTES as u64)
+            .read_to_end(&mut head)
+            .map_err(|e| GtfSortError::IoError("decompressing input file", e))?;
+    }
+    check_text(&head)
+}
+
+/// Splits `contents` at its `##FASTA` directive, returning the annotation and the
+/// sequence section, which is empty if there is none.
+pub fn split_fasta(contents: &str) -> (&str, &str) {
+    let at = match contents.starts_with(FASTA_DIRECTIVE) {
+        true => Some(0),
+        false => contents
+            .match_indices(FASTA_DIRECTIVE)
+            .map(|(i, _)| i)
+            .find(|i| contents.as_bytes()[i - 1] == b'\n'),
+    };
+    match at {
+        Some(i) => contents.split_at(i),
+        None => (contents, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_inputs_are_named() {
+        let bam = b"\x1f\x8b\x08\x04\x00\x00\x00\x00\x00\xff\x06\x00BC\x02\x00";
+        let err = check_text(bam).unwrap_err();
+        assert!(err.to_string().contains("BGZF"), "{}", err);
+        assert!(err.to_string().contains("magic bytes 1f 8b"), "{}", err);
+
+        let err = check_text(b"\x28\xb5\x2f\xfd\x04").unwrap_err();
+        assert!(err.to_string().contains("zstd"), "{}", err);
+
+        let err = check_text(b"chr1\ts\tgene\n\xff\xfe").unwrap_err();
+        assert_eq!(err.context().line, Some(2));
+        assert_eq!(err.context().byte_offset, Some(12));
+
+        let err = check_text(b"chr1\x00\x00").unwrap_err();
+        assert!(err.to_string().contains("control byte 0x00"), "{}", err);
+
+        // a character cut by the sample boundary is fine
+        let text = "chr1\ts\tgene\t1\t2\t.\t+\t.\tgene_name \"Ä\";\n".as_bytes();
+        assert!(check_text(&text[..text.len() - 4]).is_ok());
+        assert!(check_text(b"").is_ok());
+    }
+
+    #[test]
+    fn fasta_section_is_split_off() {
+        let gff = "##gff-version 3\nchr1\ts\tgene\t1\t9\t.\t+\t.\tID=g;Note=##FASTA\n##FASTA\n>chr1\nACGT\n";
+        let (annotation, fasta) = split_fasta(gff);
+        assert!(annotation.ends_with("Note=##FASTA\n"));
+        assert_eq!(fasta, "##FASTA\n>chr1\nACGT\n");
+        assert_eq!(split_fasta("##FASTA\n>c\n"), ("", "##FASTA\n>c\n"));
+        assert_eq!(split_fasta("chr1\n"), ("chr1\n", ""));
+    }
+}
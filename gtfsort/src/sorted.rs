@@ -0,0 +1,330 @@
+//! The sorted output of a string sort kept in memory, for Rust tools that consume the
+//! records directly instead of through a file or a write callback, see
+//! [SortedAnnotations].
+//!
+//! The output is held as one text, the same bytes a file sort would write, with the
+//! spans of its chromosomes, genes and transcripts alongside. Every line handed out
+//! borrows that text, without its line terminator.
+
+use std::ops::Range;
+
+use crate::{
+    sort_string_job, GtfSortError, Header, Index, SortAnnotationsJobResult, SortOptions,
+    StringOutput,
+};
+
+#[derive(Debug, Clone)]
+struct ChromSpan {
+    name: Box<str>,
+    genes: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct GeneSpan {
+    id: Box<str>,
+    /// The gene line, terminator included.
+    line: Range<usize>,
+    transcripts: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct TranscriptSpan {
+    id: Box<str>,
+    /// The transcript line, terminator included, None if it has none.
+    line: Option<Range<usize>>,
+    /// The transcript line and its children.
+    block: Range<usize>,
+}
+
+/// A sorted annotation in memory, built by [sort_annotations_iter].
+///
+/// Iterate over it for every line in output order, leading comments included, or walk
+/// its [SortedAnnotations::chromosomes] down to genes and transcripts. Children hanging
+/// directly off a gene (see [crate::Record::is_direct_child]) come as a transcript
+/// without a line, named after the gene.
+#[derive(Debug, Clone, Default)]
+pub struct SortedAnnotations {
+    text: String,
+    header: Range<usize>,
+    chroms: Vec<ChromSpan>,
+    genes: Vec<GeneSpan>,
+    transcripts: Vec<TranscriptSpan>,
+}
+
+/// Sorts `input`, GTF if `SEP` is `b' '` and GFF3 if it is `b'='`, into a
+/// [SortedAnnotations] instead of writing it.
+///
+/// [SortOptions::skip_if_sorted] is ignored. Exports, coordinate-only sorting, output
+/// compression and annotation sections have no records to walk and fail the job.
+pub fn sort_annotations_iter<'a, const SEP: u8>(
+    input: &'a str,
+    threads: usize,
+    options: &SortOptions,
+) -> Result<(SortedAnnotations, SortAnnotationsJobResult<'a>), GtfSortError> {
+    crate::check_threads(threads)?;
+    if let Some(logger) = &options.logger {
+        logger.install();
+    }
+    if options.output_compression.is_compressed() {
+        return Err(GtfSortError::InvalidParameter(
+            "sorted annotations are kept as text, they cannot be compressed",
+        ));
+    }
+    let mut sorted = SortedAnnotations::default();
+    let job = crate::panic::catch_job(|| {
+        sort_string_job::<SEP, fn(&[u8]) -> std::io::Result<usize>>(
+            input,
+            StringOutput::Sorted(&mut sorted),
+            threads,
+            options,
+        )
+        .map(|r| r.relabel("[string]", "[memory]"))
+    })?;
+    Ok((sorted, job))
+}
+
+impl SortedAnnotations {
+    /// Every line in output order.
+    pub fn lines(&self) -> std::str::Lines<'_> {
+        self.text.lines()
+    }
+
+    /// The output as it would be written to a file.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The comment lines leading the output, see [crate::header].
+    pub fn header(&self) -> std::str::Lines<'_> {
+        self.text[self.header.clone()].lines()
+    }
+
+    pub fn chromosomes(&self) -> impl ExactSizeIterator<Item = SortedChrom<'_>> {
+        self.chroms
+            .iter()
+            .map(move |span| SortedChrom { all: self, span })
+    }
+
+    /// The genes of all chromosomes in output order.
+    pub fn genes(&self) -> impl ExactSizeIterator<Item = SortedGene<'_>> {
+        self.genes
+            .iter()
+            .map(move |span| SortedGene { all: self, span })
+    }
+
+    /// Appends the chromosomes `keys` of `index`, in that order, after `header`.
+    pub(crate) fn push_index(
+        &mut self,
+        index: &Index,
+        keys: &[&str],
+        header: &Header,
+        newline: crate::format::Newline,
+    ) {
+        let newline = std::str::from_utf8(newline.as_bytes()).unwrap();
+        let push_line = |text: &mut String, line: &str| {
+            let start = text.len();
+            text.push_str(line);
+            text.push_str(newline);
+            start..text.len()
+        };
+
+        let start = self.text.len();
+        for line in &header.lines {
+            push_line(&mut self.text, line);
+        }
+        self.header = start..self.text.len();
+
+        for chrom in keys {
+            let layers = index.get(chrom).unwrap();
+            let first_gene = self.genes.len();
+            for (_, gene_id, line) in layers.layer.iter() {
+                let line = push_line(&mut self.text, line);
+                let first_transcript = self.transcripts.len();
+                for transcript_id in layers.transcripts(gene_id) {
+                    let start = self.text.len();
+                    let line = layers
+                        .helper
+                        .get(transcript_id)
+                        .map(|l| push_line(&mut self.text, l));
+                    layers.children(transcript_id).for_each(|l| {
+                        push_line(&mut self.text, l);
+                    });
+                    self.transcripts.push(TranscriptSpan {
+                        id: (*transcript_id).into(),
+                        line,
+                        block: start..self.text.len(),
+                    });
+                }
+                self.genes.push(GeneSpan {
+                    id: (*gene_id).into(),
+                    line,
+                    transcripts: first_transcript..self.transcripts.len(),
+                });
+            }
+            self.chroms.push(ChromSpan {
+                name: (*chrom).into(),
+                genes: first_gene..self.genes.len(),
+            });
+        }
+    }
+}
+
+impl<'s> IntoIterator for &'s SortedAnnotations {
+    type Item = &'s str;
+    type IntoIter = std::str::Lines<'s>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lines()
+    }
+}
+
+/// One chromosome of a [SortedAnnotations].
+#[derive(Debug, Clone, Copy)]
+pub struct SortedChrom<'s> {
+    all: &'s SortedAnnotations,
+    span: &'s ChromSpan,
+}
+
+impl<'s> SortedChrom<'s> {
+    pub fn name(&self) -> &'s str {
+        &self.span.name
+    }
+
+    pub fn genes(&self) -> impl ExactSizeIterator<Item = SortedGene<'s>> {
+        let all = self.all;
+        all.genes[self.span.genes.clone()]
+            .iter()
+            .map(move |span| SortedGene { all, span })
+    }
+
+    /// Every line of the chromosome in output order.
+    pub fn lines(&self) -> std::str::Lines<'s> {
+        let genes = &self.all.genes[self.span.genes.clone()];
+        match (genes.first(), genes.last()) {
+            (Some(first), Some(last)) => {
+                let end = SortedGene {
+                    all: self.all,
+                    span: last,
+                }
+                .end();
+                self.all.text[first.line.start..end].lines()
+            }
+            _ => "".lines(),
+        }
+    }
+}
+
+/// One gene of a [SortedAnnotations].
+#[derive(Debug, Clone, Copy)]
+pub struct SortedGene<'s> {
+    all: &'s SortedAnnotations,
+    span: &'s GeneSpan,
+}
+
+impl<'s> SortedGene<'s> {
+    pub fn id(&self) -> &'s str {
+        &self.span.id
+    }
+
+    /// The gene line.
+    pub fn line(&self) -> &'s str {
+        self.all.text[self.span.line.clone()].trim_end_matches(['\n', '\r'])
+    }
+
+    pub fn transcripts(&self) -> impl ExactSizeIterator<Item = SortedTranscript<'s>> {
+        let all = self.all;
+        all.transcripts[self.span.transcripts.clone()]
+            .iter()
+            .map(move |span| SortedTranscript { all, span })
+    }
+
+    /// The gene line followed by those of its transcripts.
+    pub fn lines(&self) -> std::str::Lines<'s> {
+        self.all.text[self.span.line.start..self.end()].lines()
+    }
+
+    fn end(&self) -> usize {
+        self.all.transcripts[self.span.transcripts.clone()]
+            .last()
+            .map_or(self.span.line.end, |t| t.block.end)
+    }
+}
+
+/// One transcript of a [SortedAnnotations].
+#[derive(Debug, Clone, Copy)]
+pub struct SortedTranscript<'s> {
+    all: &'s SortedAnnotations,
+    span: &'s TranscriptSpan,
+}
+
+impl<'s> SortedTranscript<'s> {
+    pub fn id(&self) -> &'s str {
+        &self.span.id
+    }
+
+    /// The transcript line, None for the children hanging directly off a gene.
+    pub fn line(&self) -> Option<&'s str> {
+        self.span
+            .line
+            .clone()
+            .map(|r| self.all.text[r].trim_end_matches(['\n', '\r']))
+    }
+
+    /// Exons, CDS, UTRs and other children in output order.
+    pub fn children(&self) -> std::str::Lines<'s> {
+        let start = self
+            .span
+            .line
+            .as_ref()
+            .map_or(self.span.block.start, |l| l.end);
+        self.all.text[start..self.span.block.end].lines()
+    }
+
+    /// The transcript line followed by its children.
+    pub fn lines(&self) -> std::str::Lines<'s> {
+        self.all.text[self.span.block.clone()].lines()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::sort_to_string;
+
+    const GFF: &str = "##gff-version 3\n\
+        chr2\ts\tgene\t5\t90\t.\t+\t.\tID=C\n\
+        chr1\ts\texon\t60\t90\t.\t+\t.\tID=e2;Parent=b1\n\
+        chr1\ts\tmRNA\t50\t90\t.\t+\t.\tID=b1;Parent=B\n\
+        chr1\ts\tgene\t50\t90\t.\t+\t.\tID=B\n\
+        chr1\ts\tgene\t10\t40\t.\t+\t.\tID=A\n\
+        chr1\ts\texon\t50\t55\t.\t+\t.\tID=e1;Parent=b1\n\
+        chr1\ts\tmRNA\t10\t40\t.\t+\t.\tID=a1;Parent=A\n";
+
+    #[test]
+    fn sorted_annotations_match_the_written_output() {
+        let options = SortOptions::default();
+        let (sorted, job) = sort_annotations_iter::<b'='>(GFF, 1, &options).unwrap();
+        assert_eq!(job.output, "[memory]");
+
+        assert_eq!(sorted.as_str(), sort_to_string::<b'='>(GFF, &options));
+        assert_eq!(sorted.lines().count(), 8);
+        assert_eq!(sorted.header().collect::<Vec<_>>(), vec!["##gff-version 3"]);
+
+        let chroms = sorted.chromosomes().map(|c| c.name()).collect::<Vec<_>>();
+        assert_eq!(chroms, vec!["chr1", "chr2"]);
+        let chr1 = sorted.chromosomes().next().unwrap();
+        assert_eq!(chr1.lines().count(), 6);
+        let ids = chr1.genes().map(|g| g.id()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["A", "B"]);
+
+        let b = chr1.genes().nth(1).unwrap();
+        assert!(b.line().ends_with("ID=B"));
+        let b1 = b.transcripts().next().unwrap();
+        assert_eq!(b1.id(), "b1");
+        assert!(b1.line().unwrap().ends_with("Parent=B"));
+        let exons = b1.children().collect::<Vec<_>>();
+        assert_eq!(exons.len(), 2);
+        assert!(exons[0].ends_with("ID=e1;Parent=b1"));
+        assert_eq!(sorted.genes().last().unwrap().transcripts().len(), 0);
+    }
+}
@@ -0,0 +1,300 @@
+//! A reusable sorter configured once through [SorterBuilder], so that callers (bindings,
+//! downstream crates) reach every [SortOptions] without a free function per option.
+//!
+//! ```no_run
+//! use gtfsort::{chrom_order::ChromSortOrder, ChromFilter, SorterBuilder};
+//! use std::path::Path;
+//!
+//! let sorter = SorterBuilder::new()
+//!     .threads(4)
+//!     .sort_order(ChromSortOrder::Karyotype)
+//!     .chromosomes(ChromFilter::from_list("chr1,chr2"))
+//!     .on_progress(|event| eprintln!("{}", event.to_text()))
+//!     .build()
+//!     .unwrap();
+//! for name in ["a", "b"] {
+//!     let input = format!("{}.gtf", name);
+//!     let output = format!("{}.sorted.gtf", name);
+//!     sorter.sort_file(Path::new(&input), Path::new(&output)).unwrap();
+//! }
+//! ```
+//!
+//! The thread pool is built once by [SorterBuilder::build] and shared by every job of the
+//! [Sorter], unless [SortOptions::thread_pool] was given.
+
+use std::{io::Write, path::Path, sync::Arc};
+
+use crate::{
+    build_thread_pool, check_threads,
+    chrom_order::{ChromOrder, ChromSortOrder},
+    filter::{ChromFilter, IdFilter, Region},
+    progress::{Progress, ProgressEvent},
+    AnnotationFormat, GtfSortError, ParseMode, SortAnnotationsJobResult, SortOptions,
+};
+
+/// Configures a [Sorter], see [crate::sorter].
+#[derive(Debug, Clone)]
+pub struct SorterBuilder {
+    threads: usize,
+    options: SortOptions,
+}
+
+impl Default for SorterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SorterBuilder {
+    /// Default options on every CPU.
+    pub fn new() -> Self {
+        Self::from_options(SortOptions::default())
+    }
+
+    /// Starts from `options`, on every CPU.
+    pub fn from_options(options: SortOptions) -> Self {
+        Self {
+            threads: num_cpus::get(),
+            options,
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Input format, guessed from the file extension (or detected, for strings) if not
+    /// set.
+    pub fn format(mut self, format: AnnotationFormat) -> Self {
+        self.options.format = Some(format);
+        self
+    }
+
+    /// Whether inputs and outputs may be memory mapped, see [SortOptions::no_mmap].
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.options.no_mmap = !mmap;
+        self
+    }
+
+    /// Preset order of the chromosomes, see [SortOptions::chrom_sort].
+    pub fn sort_order(mut self, order: ChromSortOrder) -> Self {
+        self.options.chrom_sort = order;
+        self
+    }
+
+    /// Explicit order of the chromosomes, see [SortOptions::chrom_order].
+    pub fn chrom_order(mut self, order: ChromOrder) -> Self {
+        self.options.chrom_order = Some(order);
+        self
+    }
+
+    pub fn genes(mut self, genes: IdFilter) -> Self {
+        self.options.genes = Some(genes);
+        self
+    }
+
+    pub fn transcripts(mut self, transcripts: IdFilter) -> Self {
+        self.options.transcripts = Some(transcripts);
+        self
+    }
+
+    pub fn chromosomes(mut self, chromosomes: ChromFilter) -> Self {
+        self.options.chromosomes = Some(chromosomes);
+        self
+    }
+
+    pub fn features(mut self, features: IdFilter) -> Self {
+        self.options.features = Some(features);
+        self
+    }
+
+    pub fn gene_biotypes(mut self, biotypes: IdFilter) -> Self {
+        self.options.gene_biotypes = Some(biotypes);
+        self
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.options.region = Some(region);
+        self
+    }
+
+    /// Calls `f` with every progress event of a job, see [Progress].
+    pub fn on_progress<F: Fn(&ProgressEvent) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.options.progress = Some(Progress::new(f));
+        self
+    }
+
+    /// Fail at the first line that does not parse instead of skipping it, see
+    /// [ParseMode].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.parse_mode = match strict {
+            true => ParseMode::Strict,
+            false => ParseMode::Lenient,
+        };
+        self
+    }
+
+    /// Sets any other option, e.g. one added after this builder.
+    pub fn configure<F: FnOnce(&mut SortOptions)>(mut self, f: F) -> Self {
+        f(&mut self.options);
+        self
+    }
+
+    /// Checks the thread count and builds the thread pool shared by the jobs of the
+    /// [Sorter].
+    pub fn build(self) -> Result<Sorter, GtfSortError> {
+        check_threads(self.threads)?;
+        let mut options = self.options;
+        if options.thread_pool.is_none() {
+            options.thread_pool = Some(Arc::new(build_thread_pool(self.threads)?));
+        }
+        Ok(Sorter {
+            threads: self.threads,
+            options,
+        })
+    }
+}
+
+/// Sorts any number of inputs with the same options, see [crate::sorter].
+#[derive(Debug, Clone)]
+pub struct Sorter {
+    threads: usize,
+    options: SortOptions,
+}
+
+impl Sorter {
+    pub fn builder() -> SorterBuilder {
+        SorterBuilder::new()
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn options(&self) -> &SortOptions {
+        &self.options
+    }
+
+    /// Sorts a file, see [crate::sort_annotations_with_options].
+    pub fn sort_file<'a>(
+        &self,
+        input: &'a Path,
+        output: &'a Path,
+    ) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+        crate::sort_annotations_with_options(input, output, self.threads, &self.options)
+    }
+
+    /// Sorts a file into `w`, see [crate::sort_annotations_to_writer].
+    pub fn sort_to_writer<'a, W: Write + Send>(
+        &self,
+        input: &'a Path,
+        w: W,
+    ) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+        crate::sort_annotations_to_writer(input, w, self.threads, &self.options)
+    }
+
+    /// Sorts several files into one output, see [crate::sort_annotations_merged].
+    pub fn sort_merged<'a>(
+        &self,
+        inputs: &[&Path],
+        output: &'a Path,
+    ) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+        crate::sort_annotations_merged(inputs, output, self.threads, &self.options)
+    }
+
+    /// Sorts an annotation held in memory, handing the output to `output` in chunks, see
+    /// [crate::sort_annotations_string_with_options]. The format is
+    /// [SortOptions::format] if set, else detected.
+    pub fn sort_string<'a, OF: FnMut(&[u8]) -> std::io::Result<usize>>(
+        &self,
+        input: &'a str,
+        output: &mut OF,
+    ) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+        let format = match self.options.format {
+            Some(format) => format,
+            None => AnnotationFormat::detect(input).format.ok_or_else(|| {
+                GtfSortError::InvalidInput(
+                    "could not detect the format of the input, please set it explicitly"
+                        .to_string(),
+                )
+            })?,
+        };
+        match format {
+            AnnotationFormat::Gtf => crate::sort_annotations_string_with_options::<b' ', _>(
+                input,
+                output,
+                self.threads,
+                &self.options,
+            ),
+            AnnotationFormat::Gff3 => crate::sort_annotations_string_with_options::<b'=', _>(
+                input,
+                output,
+                self.threads,
+                &self.options,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorter_is_reusable() {
+        let events = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = Arc::clone(&events);
+        let sorter = SorterBuilder::new()
+            .threads(2)
+            .sort_order(ChromSortOrder::Lex)
+            .chromosomes(ChromFilter::from_list("chr1,chr2,chr10"))
+            .mmap(false)
+            .strict(true)
+            .on_progress(move |_| {
+                counted.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            })
+            .build()
+            .unwrap();
+
+        let gtf = "chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+            chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+            chr10\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"C\";\n\
+            chr3\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"D\";\n";
+        for _ in 0..2 {
+            let mut out = Vec::new();
+            let ret = sorter
+                .sort_string(gtf, &mut |b| {
+                    out.extend_from_slice(b);
+                    Ok(b.len())
+                })
+                .unwrap();
+            let out = String::from_utf8(out).unwrap();
+            let chroms = out
+                .lines()
+                .map(|l| l.split('\t').next().unwrap())
+                .collect::<Vec<_>>();
+            // byte order, chr10 before chr2
+            assert_eq!(chroms, ["chr1", "chr10", "chr2"]);
+            let counted = ret
+                .histogram
+                .chromosomes
+                .iter()
+                .map(|(c, _)| c.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(counted, chroms);
+        }
+        assert!(events.load(std::sync::atomic::Ordering::Relaxed) > 0);
+
+        let dir = crate::temp::TempPath::dir(None, "sorter_test").unwrap();
+        let (input, output) = (dir.join("in.gtf"), dir.join("out.gtf"));
+        std::fs::write(&input, gtf).unwrap();
+        let ret = sorter.sort_file(&input, &output).unwrap();
+        assert!(!ret.input_mmaped && !ret.output_mmaped);
+        assert!(ret.fallbacks.is_empty());
+
+        let invalid = format!("{}chr1\ts\tgene\tx\t9\t.\t+\t.\tgene_id \"D\";\n", gtf);
+        assert!(sorter.sort_string(&invalid, &mut |b| Ok(b.len())).is_err());
+        assert!(SorterBuilder::new().threads(0).build().is_err());
+    }
+}
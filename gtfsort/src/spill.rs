@@ -0,0 +1,261 @@
+//! An input backend for files larger than memory, see [crate::SortOptions::low_memory].
+//!
+//! The input is read as a stream and its lines are partitioned by chromosome into files
+//! under [crate::SortOptions::tmpdir]. Lines are buffered per chromosome, and once the
+//! buffers hold [SPILL_BUFFER] bytes the largest ones are appended to their partition, so
+//! neither memory nor open files grow with the number of chromosomes. The partitions are
+//! then sorted one at a time and written in chromosome order (see [crate::bucketed]), so
+//! the resident input is the largest chromosome instead of the whole file, at the cost of
+//! writing it to disk once.
+//!
+//! There is no k-way merge of sorted runs: the output is grouped by chromosome, so the
+//! sorted partitions are simply written one after the other. A single chromosome is still
+//! sorted whole in memory, so one larger than memory cannot be sorted in this mode.
+
+use hashbrown::HashMap;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bucketed::{self, ChromKeep},
+    format::AnnotationFormat,
+    gzip,
+    packed::{read_lines, sample_format},
+    temp::TempPath,
+    timed, GtfSortError, Header, LineCounts, OutputTarget, Phase, PhaseProgress, ResourceSampler,
+    SortAnnotationsJobResult, SortOptions, Watchdog,
+};
+
+/// Bytes of lines buffered over all chromosomes before some are spilled.
+pub(crate) const SPILL_BUFFER: usize = 64 << 20;
+
+/// Read buffer of the input stream.
+const READ_BUFFER: usize = 1 << 20;
+
+struct Partition {
+    path: PathBuf,
+    pending: Vec<u8>,
+    spilled: u64,
+}
+
+/// The lines of each chromosome, in a file per chromosome plus a buffer of the lines not
+/// yet spilled to it.
+pub struct SpilledLines {
+    dir: TempPath,
+    chroms: HashMap<String, Partition>,
+    budget: usize,
+    pending_bytes: usize,
+    raw_bytes: u64,
+    spilled_bytes: u64,
+}
+
+impl SpilledLines {
+    /// Partitions in a new directory under `tmpdir`, buffering up to `budget` bytes.
+    pub fn new(tmpdir: Option<&Path>, budget: usize) -> io::Result<Self> {
+        Ok(Self {
+            dir: TempPath::dir(tmpdir, "spill")?,
+            chroms: HashMap::new(),
+            budget,
+            pending_bytes: 0,
+            raw_bytes: 0,
+            spilled_bytes: 0,
+        })
+    }
+
+    /// Appends `line`, without its terminator, to the lines of `chrom`.
+    pub fn push_line(&mut self, chrom: &str, line: &str) -> io::Result<()> {
+        let n = self.chroms.len();
+        let dir = &self.dir;
+        // chromosome names may not be valid file names
+        let c = self.chroms.entry_ref(chrom).or_insert_with(|| Partition {
+            path: dir.join(format!("{}.part", n)),
+            pending: Vec::new(),
+            spilled: 0,
+        });
+        c.pending.extend_from_slice(line.as_bytes());
+        c.pending.push(b'\n');
+        self.pending_bytes += line.len() + 1;
+        self.raw_bytes += line.len() as u64 + 1;
+
+        if self.pending_bytes > self.budget {
+            self.spill_largest()?;
+        }
+        Ok(())
+    }
+
+    /// Spills the largest buffers until at most half the budget is left buffered.
+    fn spill_largest(&mut self) -> io::Result<()> {
+        let mut by_size = self
+            .chroms
+            .values_mut()
+            .filter(|c| !c.pending.is_empty())
+            .collect::<Vec<_>>();
+        by_size.sort_unstable_by_key(|c| std::cmp::Reverse(c.pending.len()));
+
+        for c in by_size {
+            if self.pending_bytes <= self.budget / 2 {
+                break;
+            }
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&c.path)
+                .and_then(|mut f| f.write_all(&c.pending))?;
+            let n = c.pending.len();
+            c.spilled += n as u64;
+            self.spilled_bytes += n as u64;
+            self.pending_bytes -= n;
+            // release the buffer, most chromosomes are not appended to again
+            c.pending = Vec::new();
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of `buf` with the lines of `chrom`, each terminated by a
+    /// newline.
+    pub fn read(&self, chrom: &str, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.clear();
+        let Some(c) = self.chroms.get(chrom) else {
+            return Ok(());
+        };
+        if c.spilled > 0 {
+            buf.reserve(c.spilled as usize + c.pending.len());
+            File::open(&c.path)?.read_to_end(buf)?;
+        }
+        buf.extend_from_slice(&c.pending);
+        Ok(())
+    }
+
+    pub fn chroms(&self) -> impl Iterator<Item = &str> {
+        self.chroms.keys().map(String::as_str)
+    }
+
+    /// Size of the lines pushed, with their terminators.
+    pub fn raw_bytes(&self) -> u64 {
+        self.raw_bytes
+    }
+
+    /// Size of the lines written to the partition files.
+    pub fn spilled_bytes(&self) -> u64 {
+        self.spilled_bytes
+    }
+}
+
+/// Sorts `input` through per-chromosome partitions on disk, see the module documentation.
+pub(crate) fn sort_spilled<'a, W: Write + Send>(
+    input: &'a Path,
+    output: OutputTarget<'a, W>,
+    threads: usize,
+    options: &SortOptions,
+    declared_format: Option<AnnotationFormat>,
+    mut ret: SortAnnotationsJobResult<'a>,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    ret.start_mem_mb = Some(crate::max_mem_usage_mb());
+    log::info!(
+        "Using {} threads, spilling the input to per-chromosome partitions",
+        threads
+    );
+
+    let watchdog = Watchdog::spawn(options.max_rss_mb, options.timeout);
+    let sampler = ResourceSampler::spawn(options.sample_interval);
+
+    // decompressed while read if gzipped
+    let file = gzip::open(input)?;
+    let mut spilled = SpilledLines::new(options.tmpdir.as_deref(), SPILL_BUFFER)
+        .map_err(|e| GtfSortError::IoError("creating a directory in the temporary directory", e))?;
+
+    sampler.enter(Phase::Parse);
+    let parse_progress = PhaseProgress::begin(options.progress.as_ref(), Phase::Parse, 1);
+    let mut lines = LineCounts::default();
+    let mut header = Header::default();
+    let mut parsing_secs = 0.0;
+    let format = timed("Partitioning input", Some(&mut parsing_secs), || {
+        read_lines(
+            BufReader::with_capacity(READ_BUFFER, file),
+            &mut lines,
+            &mut ChromKeep::new(options),
+            options.parse_mode,
+            |sample| sample_format(declared_format, sample, input, &mut ret),
+            &mut header,
+            &watchdog,
+            |chrom, line| {
+                spilled
+                    .push_line(chrom, line)
+                    .map_err(|e| GtfSortError::IoError("spilling input lines", e))
+            },
+        )
+    })?;
+    parse_progress.finish();
+    watchdog.check()?;
+    log::info!(
+        "Partitioned {} bytes of lines over {} chromosomes, {} bytes spilled to disk",
+        spilled.raw_bytes(),
+        spilled.chroms().count(),
+        spilled.spilled_bytes()
+    );
+
+    ret.format = Some(format);
+
+    let keys = spilled.chroms().collect::<Vec<_>>();
+    let mut read_chrom = |chrom: &str, buf: &mut Vec<u8>| {
+        spilled
+            .read(chrom, buf)
+            .map_err(|e| GtfSortError::IoError("reading spilled input lines", e))
+    };
+    bucketed::sort_buckets(
+        &keys,
+        &mut read_chrom,
+        output,
+        options,
+        ret,
+        lines,
+        parsing_secs,
+        header,
+        watchdog,
+        sampler,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitions_round_trip_through_disk() {
+        let mut spilled = SpilledLines::new(None, 64).unwrap();
+        for i in 0..20 {
+            spilled
+                .push_line("chr1", &format!("chr1\t{}\tsome record", i))
+                .unwrap();
+            spilled
+                .push_line("chr/2", &format!("chr/2\t{}", i))
+                .unwrap();
+        }
+        assert!(spilled.spilled_bytes() > 0);
+        assert!(spilled.pending_bytes <= 64);
+
+        let mut buf = b"stale".to_vec();
+        spilled.read("chr1", &mut buf).unwrap();
+        let text = String::from_utf8(buf.clone()).unwrap();
+        assert!(text
+            .lines()
+            .enumerate()
+            .all(|(i, l)| l == format!("chr1\t{}\tsome record", i)));
+        assert_eq!(text.lines().count(), 20);
+
+        spilled.read("chr/2", &mut buf).unwrap();
+        assert_eq!(
+            buf.len(),
+            10 * "chr/2\t0\n".len() + 10 * "chr/2\t10\n".len()
+        );
+        spilled.read("chrX", &mut buf).unwrap();
+        assert!(buf.is_empty());
+
+        let dir = spilled.dir.path().to_path_buf();
+        drop(spilled);
+        assert!(!dir.exists());
+    }
+}
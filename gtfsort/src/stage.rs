@@ -0,0 +1,156 @@
+//! Output staging, see [crate::SortOptions::stage_dir].
+//!
+//! Memory mapped writes flushed with msync can be very slow on network filesystems. A
+//! staged job writes the output to a local scratch directory first and moves the
+//! finished file to its destination in one sequential pass.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    manifest::{fnv1a, FNV_OFFSET},
+    temp::TempPath,
+};
+
+const COPY_BUFFER: usize = 1 << 20;
+
+/// Creates the file a staged job writes to, in `dir`.
+pub fn staging_file(dir: &Path) -> io::Result<TempPath> {
+    TempPath::file(Some(dir), "stage", "")
+}
+
+/// Moves the finished `staged` file to `dest`, returning its FNV-1a checksum if it had
+/// to be copied.
+///
+/// A rename is used if both are on the same filesystem. Otherwise the file is copied
+/// next to `dest` while hashing it, synced, read back and compared, and only then renamed
+/// over `dest`, so `dest` never holds a partial or corrupted copy.
+pub fn publish(staged: TempPath, dest: &Path) -> io::Result<Option<u64>> {
+    publish_with(staged, dest, |from, to| fs::rename(from, to))
+}
+
+/// [publish] moving the file with `rename`, so the copy across filesystems can be tested.
+fn publish_with<R: FnOnce(&Path, &Path) -> io::Result<()>>(
+    staged: TempPath,
+    dest: &Path,
+    rename: R,
+) -> io::Result<Option<u64>> {
+    // devices and pipes (e.g. /dev/stdout) can only be written to
+    if fs::metadata(dest).is_ok_and(|m| !m.is_file()) {
+        let hash = copy_hashed(&staged, &mut File::options().write(true).open(dest)?)?;
+        return Ok(Some(hash));
+    }
+
+    match rename(&staged, dest) {
+        Ok(()) => {
+            staged.keep();
+            return Ok(None);
+        }
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        Err(e) => return Err(e),
+    }
+
+    let dir = match dest.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let partial = TempPath::file_in(dir, "partial", "")?;
+    let mut out = File::options().write(true).open(&partial)?;
+    let written = copy_hashed(&staged, &mut out)?;
+    out.sync_all()?;
+    drop(out);
+
+    let read_back = hash_file(&partial)?;
+    if read_back != written {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "checksum of the copy at {} ({:016x}) does not match the staged output ({:016x})",
+                dest.display(),
+                read_back,
+                written
+            ),
+        ));
+    }
+
+    partial.persist(dest)?;
+    Ok(Some(written))
+}
+
+/// Copies `src` into `dest`, returning the checksum of what was read.
+fn copy_hashed<W: Write>(src: &Path, dest: &mut W) -> io::Result<u64> {
+    let mut r = BufReader::with_capacity(COPY_BUFFER, File::open(src)?);
+    let mut w = BufWriter::with_capacity(COPY_BUFFER, dest);
+    let mut buf = vec![0; COPY_BUFFER];
+    let mut hash = FNV_OFFSET;
+
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buf[..n]);
+        w.write_all(&buf[..n])?;
+    }
+
+    w.flush()?;
+    Ok(hash)
+}
+
+/// FNV-1a checksum of the contents of `path`.
+pub fn hash_file(path: &Path) -> io::Result<u64> {
+    copy_hashed(path, &mut io::sink())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_are_verified_and_renames_are_used_when_possible() {
+        let dir = TempPath::dir(None, "stage_test").unwrap();
+        let dest = dir.join("sorted.gtf");
+
+        let staged = staging_file(&dir).unwrap();
+        fs::write(&staged, "chr1\tline\n").unwrap();
+        let staged_path = staged.to_path_buf();
+        assert_eq!(publish(staged, &dest).unwrap(), None);
+        assert!(!staged_path.exists());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "chr1\tline\n");
+
+        let mut out = Vec::new();
+        let hash = copy_hashed(&dest, &mut out).unwrap();
+        assert_eq!(out, b"chr1\tline\n");
+        assert_eq!(hash, hash_file(&dest).unwrap());
+        assert_ne!(hash, FNV_OFFSET);
+    }
+
+    #[test]
+    fn copies_across_filesystems_replace_the_destination() {
+        let dir = TempPath::dir(None, "stage_test").unwrap();
+        let dest = dir.join("sorted.gtf");
+        fs::write(&dest, "stale\n").unwrap();
+
+        let staged = staging_file(&dir).unwrap();
+        fs::write(&staged, "chr1\tline\n").unwrap();
+        let staged_path = staged.to_path_buf();
+        let exdev = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::CrossesDevices));
+        let hash = publish_with(staged, &dest, exdev).unwrap();
+
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "chr1\tline\n");
+        assert_eq!(hash, Some(hash_file(&dest).unwrap()));
+        assert!(!staged_path.exists());
+        // only the destination is left, no partial copy
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // other errors are not worked around
+        let staged = staging_file(&dir).unwrap();
+        let denied = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        let e = publish_with(staged, &dest, denied).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "chr1\tline\n");
+    }
+}
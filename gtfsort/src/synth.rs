@@ -0,0 +1,337 @@
+use hashbrown::{HashMap, HashSet};
+use std::borrow::Cow;
+
+use crate::{
+    gtf::{line_coords, Record},
+    Layers,
+};
+
+/// Attribute flagging lines that were not present in the input but synthesized by gtfsort.
+pub const SYNTHESIZED_TAG: &str = "gtfsort_synthesized";
+
+/// The span covered by the children of a missing parent.
+#[derive(Debug, Clone, Copy)]
+pub struct Span<'a> {
+    /// gene_id of the children.
+    pub gene_id: &'a str,
+    /// A child line used as template for seqname, source and strand.
+    pub template: &'a str,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl<'a> Span<'a> {
+    fn extend(&mut self, start: u32, end: u32) {
+        self.start = self.start.min(start);
+        self.end = self.end.max(end);
+    }
+}
+
+/// Collects the spans of transcripts and genes from their children while indexing.
+#[derive(Debug, Default)]
+pub struct ParentSpans<'a> {
+    transcripts: HashMap<&'a str, Span<'a>>,
+    genes: HashMap<&'a str, Span<'a>>,
+}
+
+impl<'a> ParentSpans<'a> {
+    /// Records a transcript line, contributing to the span of its gene.
+    pub fn add_transcript(&mut self, record: &Record<'a>) {
+        Self::add(&mut self.genes, record.gene_id, record);
+    }
+
+    /// Records a child line (exon, CDS, ...), contributing to the span of its transcript.
+    pub fn add_child(&mut self, record: &Record<'a>) {
+        Self::add(&mut self.transcripts, record.transcript_id, record);
+    }
+
+    fn add(map: &mut HashMap<&'a str, Span<'a>>, key: &'a str, record: &Record<'a>) {
+        map.entry(key)
+            .and_modify(|s| s.extend(record.start, record.end))
+            .or_insert(Span {
+                gene_id: record.gene_id,
+                template: record.line,
+                start: record.start,
+                end: record.end,
+            });
+    }
+}
+
+/// Builds a parent line from a child `template`, keeping seqname, source and strand.
+///
+/// The attribute column only carries the IDs plus the [SYNTHESIZED_TAG] flag.
+pub fn synthesize_line<const SEP: u8>(
+    template: &str,
+    feature: &str,
+    start: u32,
+    end: u32,
+    gene_id: &str,
+    transcript_id: Option<&str>,
+) -> String {
+    let mut fields = template.split('\t');
+    let (chrom, source) = (fields.next().unwrap_or("."), fields.next().unwrap_or("."));
+    let strand = template.split('\t').nth(6).unwrap_or(".");
+
+    let attr = |k: &str, v: &str| match SEP {
+        b'=' => format!("{}={};", k, v),
+        _ => format!("{} \"{}\"; ", k, v),
+    };
+
+    let mut attrs = attr("gene_id", gene_id);
+    if let Some(transcript_id) = transcript_id {
+        attrs.push_str(&attr("transcript_id", transcript_id));
+    }
+    attrs.push_str(&attr(SYNTHESIZED_TAG, "true"));
+
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t.\t{}\t.\t{}",
+        chrom,
+        source,
+        feature,
+        start,
+        end,
+        strand,
+        attrs.trim_end()
+    )
+}
+
+impl<'a> Layers<'a> {
+    /// Adds transcript lines for children without one and gene lines for transcripts
+    /// without one, spanning their children. Returns (transcripts, genes) synthesized.
+    pub fn synthesize_parents<const SEP: u8>(&mut self, spans: ParentSpans<'a>) -> (usize, usize) {
+        let ParentSpans {
+            transcripts,
+            mut genes,
+        } = spans;
+
+        let missing = transcripts
+            .into_iter()
+            .filter(|(id, _)| !self.has_parent_line(id))
+            .collect::<Vec<_>>();
+        let missing = self.insert_transcripts::<SEP>(missing);
+        for (_, span) in missing.iter() {
+            genes
+                .entry(span.gene_id)
+                .and_modify(|g| g.extend(span.start, span.end))
+                .or_insert(*span);
+        }
+
+        let known_genes = self.layer.iter().map(|g| g.1).collect::<HashSet<_>>();
+        let mut new_genes = 0;
+        for gene_id in self.mapper.keys() {
+            if known_genes.contains(gene_id) {
+                continue;
+            }
+            if let Some(span) = genes.get(gene_id) {
+                let line = synthesize_line::<SEP>(
+                    span.template,
+                    "gene",
+                    span.start,
+                    span.end,
+                    gene_id,
+                    None,
+                );
+                self.layer.push((span.start, gene_id, Cow::Owned(line)));
+                new_genes += 1;
+            }
+        }
+
+        (missing.len(), new_genes)
+    }
+
+    /// Adds gene lines for transcripts whose gene has no gene line, spanning their
+    /// transcript lines. Returns the number of genes synthesized.
+    pub fn infer_genes<const SEP: u8>(&mut self) -> usize {
+        let known_genes = self.layer.iter().map(|g| g.1).collect::<HashSet<_>>();
+        let mut new_genes = self
+            .mapper
+            .iter()
+            .filter(|(gene_id, _)| !known_genes.contains(*gene_id))
+            .filter_map(|(gene_id, transcripts)| {
+                let lines = transcripts.iter().filter_map(|t| self.helper.get(t));
+                let (template, start, end) = lines
+                    .filter_map(|l| line_coords(l).map(|(s, e)| (l, s, e)))
+                    .reduce(|(t, s1, e1), (_, s2, e2)| (t, s1.min(s2), e1.max(e2)))?;
+                let line = synthesize_line::<SEP>(template, "gene", start, end, gene_id, None);
+                Some((start, *gene_id, Cow::Owned(line)))
+            })
+            .collect::<Vec<_>>();
+
+        // independent of the iteration order of mapper
+        new_genes.sort_unstable_by_key(|(start, gene_id, _)| (*start, *gene_id));
+        let n = new_genes.len();
+        self.layer.extend(new_genes);
+        n
+    }
+
+    /// Adds transcript lines for children without one whose gene has a gene line, spanning
+    /// their children, so they are written instead of left out. `lines` are the records
+    /// the layers were built from, only read again if some transcript line is missing.
+    /// With `infer_genes`, genes without a gene line but with other transcripts count as
+    /// having one, see [Layers::infer_genes].
+    /// Returns (transcripts synthesized, their children).
+    pub fn recover_transcripts<const SEP: u8>(
+        &mut self,
+        lines: &[Record<'a>],
+        infer_genes: bool,
+    ) -> (usize, usize) {
+        let missing = self
+            .inner
+            .keys()
+            .filter(|id| !self.has_parent_line(id))
+            .copied()
+            .collect::<HashSet<_>>();
+        if missing.is_empty() {
+            return (0, 0);
+        }
+
+        let mut genes = self.layer.iter().map(|g| g.1).collect::<HashSet<_>>();
+        if infer_genes {
+            genes.extend(self.mapper.keys().copied());
+        }
+        let mut spans = ParentSpans::default();
+        lines
+            .iter()
+            .filter(|l| missing.contains(l.transcript_id) && genes.contains(l.gene_id))
+            .for_each(|l| spans.add_child(l));
+
+        let recovered = self.insert_transcripts::<SEP>(spans.transcripts.into_iter().collect());
+        let children = recovered
+            .iter()
+            .map(|(id, _)| self.children(id).count())
+            .sum();
+        (recovered.len(), children)
+    }
+
+    /// Inserts a transcript line for each span of `missing` under its gene, in order of
+    /// their start. Returns `missing` in that order.
+    fn insert_transcripts<const SEP: u8>(
+        &mut self,
+        mut missing: Vec<(&'a str, Span<'a>)>,
+    ) -> Vec<(&'a str, Span<'a>)> {
+        missing.sort_unstable_by_key(|(id, s)| (s.start, *id));
+
+        for (transcript_id, span) in missing.iter() {
+            let line = synthesize_line::<SEP>(
+                span.template,
+                "transcript",
+                span.start,
+                span.end,
+                span.gene_id,
+                Some(transcript_id),
+            );
+            self.helper.insert(transcript_id, Cow::Owned(line));
+            self.mapper
+                .entry(span.gene_id)
+                .or_default()
+                .push(transcript_id);
+        }
+
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::sort_to_string, SortOptions};
+
+    #[test]
+    fn synthesized_gtf_line() {
+        let template =
+            "chr1\taugustus\tCDS\t10\t20\t.\t-\t0\tgene_id \"g1\"; transcript_id \"g1.t1\";";
+        assert_eq!(
+            synthesize_line::<b' '>(template, "transcript", 5, 30, "g1", Some("g1.t1")),
+            "chr1\taugustus\ttranscript\t5\t30\t.\t-\t.\tgene_id \"g1\"; transcript_id \"g1.t1\"; gtfsort_synthesized \"true\";"
+        );
+        assert_eq!(
+            synthesize_line::<b'='>(template, "gene", 5, 30, "g1", None),
+            "chr1\taugustus\tgene\t5\t30\t.\t-\t.\tgene_id=g1;gtfsort_synthesized=true;"
+        );
+    }
+
+    #[test]
+    fn cds_only_models_are_reconstructed() {
+        let input = "chr1\taugustus\tCDS\t300\t400\t.\t+\t0\tgene_id \"g1\"; transcript_id \"g1.t1\"; exon_number \"2\";\n\
+            chr1\taugustus\tCDS\t100\t200\t.\t+\t0\tgene_id \"g1\"; transcript_id \"g1.t1\"; exon_number \"1\";\n\
+            chr1\taugustus\tCDS\t150\t250\t.\t+\t0\tgene_id \"g1\"; transcript_id \"g1.t2\";\n";
+
+        let output = sort_to_string::<b' '>(
+            input,
+            &SortOptions {
+                synthesize_parents: true,
+                ..Default::default()
+            },
+        );
+        let features = output
+            .lines()
+            .map(|l| l.split('\t').nth(2).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            features,
+            vec!["gene", "transcript", "CDS", "CDS", "transcript", "CDS"]
+        );
+        assert!(output.lines().next().unwrap().contains("\t100\t400\t"));
+        assert_eq!(output.matches(SYNTHESIZED_TAG).count(), 3);
+    }
+
+    #[test]
+    fn missing_transcript_lines_are_recovered() {
+        let input = "chr1\ts\tgene\t100\t900\t.\t-\t.\tgene_id \"g1\";\n\
+            chr1\ts\texon\t500\t900\t.\t-\t.\tgene_id \"g1\"; transcript_id \"g1.t1\"; exon_number \"1\";\n\
+            chr1\ts\texon\t100\t200\t.\t-\t.\tgene_id \"g1\"; transcript_id \"g1.t1\"; exon_number \"2\";\n\
+            chr1\ts\texon\t300\t400\t.\t-\t.\tgene_id \"g2\"; transcript_id \"g2.t1\"; exon_number \"1\";\n";
+        let sort = |options: &SortOptions| sort_to_string::<b' '>(input, options);
+
+        // left out unless recovered
+        assert_eq!(sort(&Default::default()).lines().count(), 1);
+
+        // g2 has no gene line, so its exon is still left out
+        let output = sort(&SortOptions {
+            recover_transcripts: true,
+            ..Default::default()
+        });
+        assert_eq!(output.lines().count(), 4);
+        let transcript = output.lines().nth(1).unwrap();
+        assert!(transcript.starts_with("chr1\ts\ttranscript\t100\t900\t.\t-\t"));
+        assert!(transcript.contains(SYNTHESIZED_TAG));
+    }
+
+    #[test]
+    fn missing_gene_lines_are_inferred() {
+        let input = "chr1\ts\ttranscript\t300\t800\t.\t+\t.\tgene_id \"g1\"; transcript_id \"g1.t2\";\n\
+            chr1\ts\texon\t300\t800\t.\t+\t.\tgene_id \"g1\"; transcript_id \"g1.t2\"; exon_number \"1\";\n\
+            chr1\ts\ttranscript\t100\t500\t.\t+\t.\tgene_id \"g1\"; transcript_id \"g1.t1\";\n\
+            chr1\ts\texon\t150\t250\t.\t+\t.\tgene_id \"g1\"; transcript_id \"g1.t3\"; exon_number \"1\";\n";
+
+        let output = sort_to_string::<b' '>(
+            input,
+            &SortOptions {
+                infer_genes: true,
+                recover_transcripts: true,
+                ..Default::default()
+            },
+        );
+        let gene = output.lines().next().unwrap();
+        assert!(gene.starts_with("chr1\ts\tgene\t100\t800\t.\t+\t"));
+        // g1.t3 gets a transcript line too, as its gene is inferred
+        assert_eq!(output.lines().count(), 6);
+        assert_eq!(output.matches(SYNTHESIZED_TAG).count(), 2);
+    }
+
+    #[test]
+    fn bare_gtf22_groups_are_sorted() {
+        let input = "chr1\tsrc\texon\t500\t600\t.\t+\t.\tAB000002\n\
+            chr1\tsrc\texon\t100\t200\t.\t-\t.\tAB000001\n\
+            chr1\tsrc\tCDS\t120\t180\t.\t-\t0\tAB000001\n";
+
+        let output = sort_to_string::<b' '>(input, &SortOptions::default());
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].contains("\tgene\t100\t200\t") && lines[0].contains("AB000001"));
+        assert!(lines[2].ends_with("\tAB000001") && lines[3].ends_with("\tAB000001"));
+        assert!(lines[4].contains("\tgene\t500\t600\t"));
+        assert!(lines[6].ends_with("\tAB000002"));
+    }
+}
@@ -0,0 +1,504 @@
+//! Tabix (`.tbi`) and CSI indexes of a BGZF output, see [crate::SortOptions::index].
+//!
+//! The index is built once the output is written, by reading its BGZF blocks back as
+//! tabix does with the `gff` preset: sequence name in column 1, 1-based start and end in
+//! columns 4 and 5, `#` lines skipped. Tabix needs the records of a chromosome in one run
+//! and ordered by start, so only a [crate::SortOptions::coordinate_only] output can be
+//! indexed; gene blocks put the exons of minus-strand transcripts in descending order.
+//! Needs the `gzip` feature.
+// only write_index reads BGZF, the builder is left unused without the gzip feature
+#![cfg_attr(not(feature = "gzip"), allow(dead_code, unused_imports))]
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{compress::OutputCompression, GtfSortError, SortOptions};
+
+/// Width of the windows of the linear index, and of the smallest bins.
+const MIN_SHIFT: u32 = 14;
+/// Levels of bins of a `.tbi`, which covers positions below 2^29.
+const TBI_DEPTH: u32 = 5;
+/// Levels of bins of a `.csi`, as tabix writes them, which covers positions below 2^32.
+const CSI_DEPTH: u32 = 6;
+
+/// The kind of index written next to the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndexFormat {
+    /// `.tbi`, read by every tabix-aware tool, for chromosomes shorter than 512 Mb.
+    #[default]
+    Tbi,
+    /// `.csi`, for longer chromosomes (e.g. some plant genomes).
+    Csi,
+}
+
+impl IndexFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Tbi => "tbi",
+            Self::Csi => "csi",
+        }
+    }
+
+    /// Where the index of `output` goes, e.g. `out.gtf.gz.tbi`.
+    pub fn path_of(self, output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".");
+        path.push(self.extension());
+        PathBuf::from(path)
+    }
+
+    fn depth(self) -> u32 {
+        match self {
+            Self::Tbi => TBI_DEPTH,
+            Self::Csi => CSI_DEPTH,
+        }
+    }
+}
+
+impl FromStr for IndexFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tbi" | "tabix" => Ok(Self::Tbi),
+            "csi" => Ok(Self::Csi),
+            _ => Err(format!("unknown index format {:?}, expected tbi or csi", s)),
+        }
+    }
+}
+
+impl fmt::Display for IndexFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Fails if `options` ask for an index of an output that cannot be indexed.
+pub fn check_options(options: &SortOptions) -> Result<(), GtfSortError> {
+    if options.index.is_none() {
+        return Ok(());
+    }
+    if options.output_compression != OutputCompression::Bgzf {
+        return Err(GtfSortError::InvalidParameter(
+            "a tabix index needs a BGZF output, set the output compression to bgzf",
+        ));
+    }
+    if !options.coordinate_only || options.export.is_some() {
+        return Err(GtfSortError::InvalidParameter(
+            "a tabix index needs the records ordered by start, sort with --coordinate-only",
+        ));
+    }
+    Ok(())
+}
+
+/// Indexes the BGZF file at `path`, writing the index next to it, and returns where.
+#[cfg(feature = "gzip")]
+pub fn write_index(path: &Path, format: IndexFormat) -> Result<PathBuf, GtfSortError> {
+    let index_path = format.path_of(path);
+    let mut builder = IndexBuilder::new(format);
+    let file = std::fs::File::open(path)
+        .map_err(|e| GtfSortError::IoError("reading back output for indexing", e))?;
+    let mut blocks = BgzfBlocks {
+        input: BufReader::new(file),
+        offset: 0,
+    };
+
+    // virtual offset of the start of the current line, None between lines
+    let mut line_start = None;
+    let mut line = Vec::new();
+    while let Some((offset, size, data)) = blocks
+        .next_block()
+        .map_err(|e| GtfSortError::IoError("reading back output for indexing", e))?
+    {
+        let mut pos = 0;
+        while pos < data.len() {
+            let start = *line_start.get_or_insert(offset << 16 | pos as u64);
+            match data[pos..].iter().position(|b| *b == b'\n') {
+                Some(i) => {
+                    line.extend_from_slice(&data[pos..pos + i]);
+                    pos += i + 1;
+                    let end = match pos == data.len() {
+                        true => (offset + size) << 16,
+                        false => offset << 16 | pos as u64,
+                    };
+                    builder.push(&line, start, end)?;
+                    line.clear();
+                    line_start = None;
+                }
+                None => {
+                    line.extend_from_slice(&data[pos..]);
+                    pos = data.len();
+                }
+            }
+        }
+    }
+    if let Some(start) = line_start {
+        builder.push(&line, start, blocks.offset << 16)?;
+    }
+
+    std::fs::File::create(&index_path)
+        .and_then(|f| {
+            let mut out = crate::compress::BgzfWriter::new(io::BufWriter::new(f));
+            out.write_all(&builder.finish())?;
+            out.finish()?.flush()
+        })
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&index_path);
+        })
+        .map_err(|e| GtfSortError::IoError("writing index", e))?;
+    Ok(index_path)
+}
+
+#[cfg(not(feature = "gzip"))]
+pub fn write_index(_: &Path, _: IndexFormat) -> Result<PathBuf, GtfSortError> {
+    Err(GtfSortError::InvalidParameter(
+        "a tabix index needs the gzip feature, which this build lacks",
+    ))
+}
+
+/// Reads a BGZF file one block at a time.
+#[cfg(feature = "gzip")]
+struct BgzfBlocks<R> {
+    input: R,
+    /// Offset of the next block in the file.
+    offset: u64,
+}
+
+#[cfg(feature = "gzip")]
+impl<R: Read> BgzfBlocks<R> {
+    /// The offset, compressed size and contents of the next block, None at the end.
+    fn next_block(&mut self) -> io::Result<Option<(u64, u64, Vec<u8>)>> {
+        let mut header = [0; 18];
+        match self.input.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] || header[12..14] != *b"BC" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a BGZF file, only bgzf outputs can be indexed",
+            ));
+        }
+        let size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+        let mut rest = vec![0; size as usize - header.len()];
+        self.input.read_exact(&mut rest)?;
+        let deflated = &rest[..rest.len() - 8];
+        let mut data = Vec::with_capacity(crate::compress::BGZF_BLOCK_SIZE);
+        flate2::read::DeflateDecoder::new(deflated).read_to_end(&mut data)?;
+
+        let offset = self.offset;
+        self.offset += size;
+        Ok(Some((offset, size, data)))
+    }
+}
+
+/// The bins, linear index and counts of one chromosome.
+#[derive(Debug, Default)]
+struct RefIndex {
+    name: String,
+    /// Chunks of virtual offsets (begin, end) per bin.
+    bins: BTreeMap<u32, Vec<(u64, u64)>>,
+    /// Smallest virtual offset of a record overlapping each window, u64::MAX if none.
+    linear: Vec<u64>,
+    off_beg: u64,
+    off_end: u64,
+    records: u64,
+    last_beg: u64,
+}
+
+/// Collects the records of a coordinate-sorted output in file order.
+struct IndexBuilder {
+    format: IndexFormat,
+    refs: Vec<RefIndex>,
+    lines: usize,
+}
+
+impl IndexBuilder {
+    fn new(format: IndexFormat) -> Self {
+        Self {
+            format,
+            refs: Vec::new(),
+            lines: 0,
+        }
+    }
+
+    /// Adds the line spanning virtual offsets `offset..next`.
+    fn push(&mut self, line: &[u8], offset: u64, next: u64) -> Result<(), GtfSortError> {
+        self.lines += 1;
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() || line[0] == b'#' {
+            return Ok(());
+        }
+        let invalid = |reason: &str| {
+            GtfSortError::InvalidInput(format!(
+                "cannot index line {} of the output: {}",
+                self.lines, reason
+            ))
+        };
+        let mut fields = line.split(|b| *b == b'\t');
+        let chrom = fields.next().unwrap_or_default();
+        let position = |field: Option<&[u8]>, what: &str| {
+            field
+                .and_then(|f| std::str::from_utf8(f).ok())
+                .and_then(|f| f.parse::<u64>().ok())
+                .ok_or_else(|| invalid(&format!("no {} position", what)))
+        };
+        let start_pos = position(fields.nth(2), "start")?;
+        let end_pos = position(fields.next(), "end")?;
+        let beg = start_pos.saturating_sub(1);
+        let end = end_pos.max(beg + 1);
+        let max = 1u64 << (MIN_SHIFT + 3 * self.format.depth());
+        if end > max {
+            return Err(invalid(&format!(
+                "position {} is beyond what a .{} covers, use a csi index",
+                end_pos, self.format
+            )));
+        }
+
+        let chrom = std::str::from_utf8(chrom).map_err(|_| invalid("invalid UTF-8"))?;
+        if self.refs.last().is_none_or(|r| r.name != chrom) {
+            if self.refs.iter().any(|r| r.name == chrom) {
+                return Err(invalid(&format!(
+                    "the lines of {} are not contiguous",
+                    chrom
+                )));
+            }
+            self.refs.push(RefIndex {
+                name: chrom.to_string(),
+                off_beg: offset,
+                ..Default::default()
+            });
+        }
+        let r = self.refs.last_mut().unwrap();
+        if beg < r.last_beg {
+            return Err(invalid("records are not ordered by start"));
+        }
+        r.last_beg = beg;
+        r.off_end = next;
+        r.records += 1;
+
+        let chunks = r
+            .bins
+            .entry(reg2bin(beg, end, self.format.depth()))
+            .or_default();
+        match chunks.last_mut() {
+            // reading the rest of a block costs nothing
+            Some(last) if last.1 >> 16 == offset >> 16 => last.1 = next,
+            _ => chunks.push((offset, next)),
+        }
+
+        let (first, last) = (
+            (beg >> MIN_SHIFT) as usize,
+            ((end - 1) >> MIN_SHIFT) as usize,
+        );
+        if r.linear.len() <= last {
+            r.linear.resize(last + 1, u64::MAX);
+        }
+        r.linear[first..=last]
+            .iter_mut()
+            .filter(|o| **o == u64::MAX)
+            .for_each(|o| *o = offset);
+        Ok(())
+    }
+
+    /// The uncompressed index.
+    fn finish(mut self) -> Vec<u8> {
+        let depth = self.format.depth();
+        let meta_bin = bins(depth) + 1;
+        for r in &mut self.refs {
+            // windows without records point at the previous record
+            let mut previous = r.off_beg;
+            for o in &mut r.linear {
+                if *o == u64::MAX {
+                    *o = previous;
+                }
+                previous = *o;
+            }
+        }
+
+        let mut names = Vec::new();
+        for r in &self.refs {
+            names.extend_from_slice(r.name.as_bytes());
+            names.push(0);
+        }
+        // generic format, 1-based; sequence, start and end columns; meta char; skip
+        let mut conf = Vec::new();
+        for v in [0, 1, 4, 5, b'#' as i32, 0, names.len() as i32] {
+            conf.extend_from_slice(&v.to_le_bytes());
+        }
+        conf.extend_from_slice(&names);
+
+        let mut out = Vec::new();
+        match self.format {
+            IndexFormat::Tbi => {
+                out.extend_from_slice(b"TBI\x01");
+                out.extend_from_slice(&(self.refs.len() as i32).to_le_bytes());
+                out.extend_from_slice(&conf);
+            }
+            IndexFormat::Csi => {
+                out.extend_from_slice(b"CSI\x01");
+                for v in [MIN_SHIFT as i32, depth as i32, conf.len() as i32] {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+                out.extend_from_slice(&conf);
+                out.extend_from_slice(&(self.refs.len() as i32).to_le_bytes());
+            }
+        }
+
+        for r in &self.refs {
+            out.extend_from_slice(&(r.bins.len() as i32 + 1).to_le_bytes());
+            let meta = [(r.off_beg, r.off_end), (r.records, 0)];
+            let bins = r
+                .bins
+                .iter()
+                .map(|(bin, chunks)| (*bin, &chunks[..]))
+                .chain(std::iter::once((meta_bin, &meta[..])));
+            for (bin, chunks) in bins {
+                out.extend_from_slice(&bin.to_le_bytes());
+                if self.format == IndexFormat::Csi {
+                    let loff = match bin == meta_bin {
+                        true => 0,
+                        false => r.linear.get(bin_bot(bin, depth)).copied().unwrap_or(0),
+                    };
+                    out.extend_from_slice(&loff.to_le_bytes());
+                }
+                out.extend_from_slice(&(chunks.len() as i32).to_le_bytes());
+                for (beg, end) in chunks {
+                    out.extend_from_slice(&beg.to_le_bytes());
+                    out.extend_from_slice(&end.to_le_bytes());
+                }
+            }
+            if self.format == IndexFormat::Tbi {
+                out.extend_from_slice(&(r.linear.len() as i32).to_le_bytes());
+                r.linear
+                    .iter()
+                    .for_each(|o| out.extend_from_slice(&o.to_le_bytes()));
+            }
+        }
+        // records without coordinates
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out
+    }
+}
+
+/// Number of bins of an index of `depth` levels.
+fn bins(depth: u32) -> u32 {
+    ((1 << (3 * (depth + 1))) - 1) / 7
+}
+
+/// The smallest bin holding the 0-based `beg..end`, as htslib's `hts_reg2bin`.
+fn reg2bin(beg: u64, end: u64, depth: u32) -> u32 {
+    let end = end - 1;
+    let (mut shift, mut first) = (MIN_SHIFT, bins(depth - 1));
+    for level in (1..=depth).rev() {
+        if beg >> shift == end >> shift {
+            return first + (beg >> shift) as u32;
+        }
+        shift += 3;
+        first -= 1 << (3 * (level - 1));
+    }
+    0
+}
+
+/// The first window covered by `bin`, as htslib's `hts_bin_bot`.
+fn bin_bot(bin: u32, depth: u32) -> usize {
+    let (mut level, mut b) = (0, bin);
+    while b > 0 {
+        level += 1;
+        b = (b - 1) >> 3;
+    }
+    let first = ((1 << (3 * level)) - 1) / 7;
+    ((bin - first) as usize) << (3 * (depth - level))
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_with_options, temp::TempPath};
+
+    const GTF: &str = "##description: test\n\
+        chr2\ts\texon\t40000\t40100\t.\t-\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+        chr1\ts\tgene\t20000\t90000\t.\t-\t.\tgene_id \"B\";\n\
+        chr1\ts\texon\t80000\t90000\t.\t-\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr1\ts\ttranscript\t20000\t90000\t.\t-\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr1\ts\texon\t20000\t21000\t.\t-\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr1\ts\tgene\t10\t40\t.\t+\t.\tgene_id \"A\";\n\
+        chr2\ts\tgene\t40000\t40100\t.\t-\t.\tgene_id \"C\";\n\
+        chr2\ts\ttranscript\t40000\t40100\t.\t-\t.\tgene_id \"C\"; transcript_id \"C.1\";\n";
+
+    fn read_index(path: &Path) -> Vec<u8> {
+        let mut index = Vec::new();
+        flate2::read::MultiGzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_end(&mut index)
+            .unwrap();
+        index
+    }
+
+    fn i32_at(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn bins_match_htslib() {
+        assert_eq!(bins(TBI_DEPTH), 37449);
+        assert_eq!(reg2bin(0, 1, TBI_DEPTH), 4681);
+        assert_eq!(reg2bin(19_999, 90_000, TBI_DEPTH), 585);
+        assert_eq!(reg2bin(0, 1 << 29, TBI_DEPTH), 0);
+        assert_eq!(reg2bin(0, 1, CSI_DEPTH), 37449);
+        assert_eq!(bin_bot(4681 + 5, TBI_DEPTH), 5);
+        assert_eq!(bin_bot(585, TBI_DEPTH), 0);
+        assert_eq!(bin_bot(73 + 1, TBI_DEPTH), 64);
+    }
+
+    #[test]
+    fn coordinate_sorted_output_is_indexed() {
+        let dir = TempPath::dir(None, "tabix_test").unwrap();
+        let (input, output) = (dir.join("in.gtf"), dir.join("out.gtf.gz"));
+        std::fs::write(&input, GTF).unwrap();
+        let options = SortOptions {
+            output_compression: OutputCompression::Bgzf,
+            index: Some(IndexFormat::Tbi),
+            ..Default::default()
+        };
+        let err = sort_annotations_with_options(&input, &output, 1, &options)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--coordinate-only"), "{}", err);
+
+        let options = SortOptions {
+            coordinate_only: true,
+            ..options
+        };
+        sort_annotations_with_options(&input, &output, 1, &options).unwrap();
+        let index = read_index(&IndexFormat::Tbi.path_of(&output));
+        assert_eq!(index[..4], *b"TBI\x01");
+        assert_eq!(i32_at(&index, 4), 2);
+        assert_eq!(i32_at(&index, 24), b'#' as i32);
+        assert_eq!(index[36..46], *b"chr1\0chr2\0");
+        // chr1: bin 585 (B, its transcript and the exon spanning windows 4 and 5), 4681
+        // (A) and 4682 (the first exon of B), then the meta bin
+        assert_eq!(i32_at(&index, 46), 4);
+        assert_eq!(i32_at(&index, 50), 585);
+        assert_eq!(i32_at(&index, 54), 1);
+
+        sort_annotations_with_options(
+            &input,
+            &output,
+            1,
+            &SortOptions {
+                index: Some(IndexFormat::Csi),
+                ..options
+            },
+        )
+        .unwrap();
+        let index = read_index(&IndexFormat::Csi.path_of(&output));
+        assert_eq!(index[..4], *b"CSI\x01");
+        assert_eq!((i32_at(&index, 4), i32_at(&index, 8)), (14, 6));
+        assert_eq!(i32_at(&index, 16 + i32_at(&index, 12) as usize), 2);
+    }
+}
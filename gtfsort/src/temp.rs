@@ -0,0 +1,204 @@
+//! Temporary files and directories shared by the subsystems that spill to disk.
+//!
+//! Everything lives under [SortOptions::tmpdir](crate::SortOptions::tmpdir), or `$TMPDIR`
+//! if it is not set. Names carry the PID and a random suffix and are created exclusively,
+//! so concurrent jobs (e.g. a SLURM array on a shared scratch) never reuse each other's
+//! files.
+
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+/// Environment variable read when no directory is given.
+pub const TMPDIR_ENV: &str = "TMPDIR";
+
+/// Prefix of every temporary name, so stale files are easy to find and clean up.
+pub const TEMP_PREFIX: &str = "gtfsort";
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Directory for temporary files: `dir` if given, `$TMPDIR` otherwise.
+pub fn temp_dir(dir: Option<&Path>) -> PathBuf {
+    match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => std::env::var_os(TMPDIR_ENV)
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir),
+    }
+}
+
+/// A random 64-bit suffix, different for every call within the process.
+fn random_suffix() -> u64 {
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if let Ok(t) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        h.write_u128(t.as_nanos());
+    }
+    h.finish()
+}
+
+/// `gtfsort_<label>_<pid>_<random><ext>`, `ext` including its leading dot if any.
+fn temp_name(label: &str, ext: &str) -> String {
+    format!(
+        "{}_{}_{}_{:016x}{}",
+        TEMP_PREFIX,
+        label,
+        std::process::id(),
+        random_suffix(),
+        ext
+    )
+}
+
+/// A temporary file or directory, removed when dropped unless [TempPath::keep] or
+/// [TempPath::persist] was called.
+#[derive(Debug)]
+pub struct TempPath {
+    path: PathBuf,
+    dir: bool,
+    keep: bool,
+}
+
+impl TempPath {
+    /// Creates an empty file in [temp_dir] of `dir`.
+    pub fn file(dir: Option<&Path>, label: &str, ext: &str) -> io::Result<Self> {
+        Self::file_in(&temp_dir(dir), label, ext)
+    }
+
+    /// Creates an empty file in `dir`, e.g. next to an output that will replace it.
+    pub fn file_in(dir: &Path, label: &str, ext: &str) -> io::Result<Self> {
+        Self::create(dir, label, ext, false, |p| {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(p)
+                .map(drop)
+        })
+    }
+
+    /// Creates an empty directory in [temp_dir] of `dir`, removed recursively on drop.
+    pub fn dir(dir: Option<&Path>, label: &str) -> io::Result<Self> {
+        Self::create(&temp_dir(dir), label, "", true, |p| fs::create_dir(p))
+    }
+
+    fn create(
+        dir: &Path,
+        label: &str,
+        ext: &str,
+        is_dir: bool,
+        make: fn(&Path) -> io::Result<()>,
+    ) -> io::Result<Self> {
+        loop {
+            let path = dir.join(temp_name(label, ext));
+            match make(&path) {
+                Ok(()) => {
+                    return Ok(Self {
+                        path,
+                        dir: is_dir,
+                        keep: false,
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leaves the path on disk when dropped, e.g. to inspect it after a failure.
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        self.path.clone()
+    }
+
+    /// Moves the file to `to`, atomically if both are on the same filesystem.
+    pub fn persist<P: AsRef<Path>>(mut self, to: P) -> io::Result<()> {
+        fs::rename(&self.path, to)?;
+        self.keep = true;
+        Ok(())
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        let res = if self.dir {
+            fs::remove_dir_all(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        };
+        if let Err(e) = res.or_else(|e| match e.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        }) {
+            log::warn!("could not remove temporary {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_do_not_collide_and_are_cleaned_up() {
+        let dir = TempPath::dir(None, "test").unwrap();
+        let files = (0..64)
+            .map(|_| TempPath::file(Some(&dir), "spill", ".gtf").unwrap())
+            .collect::<Vec<_>>();
+
+        let mut names = files
+            .iter()
+            .map(|f| f.path().to_path_buf())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), files.len());
+        assert!(names.iter().all(|p| p.starts_with(dir.path())));
+        assert!(names[0]
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .contains(&format!("_spill_{}_", std::process::id())));
+
+        let kept = TempPath::file(Some(&dir), "out", "").unwrap();
+        let target = dir.join("sorted.gtf");
+        kept.persist(&target).unwrap();
+        drop(files);
+
+        assert_eq!(
+            fs::read_dir(dir.path()).unwrap().count(),
+            1,
+            "only the persisted file is left"
+        );
+        let root = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!root.exists());
+    }
+}
@@ -2,12 +2,16 @@
 
 use std::{
     collections::VecDeque,
+    fmt::Write as _,
     io::{BufRead, BufReader, Read},
     ops::Deref,
-    path::{Path, PathBuf},
+    path::PathBuf,
     sync::Once,
 };
+#[cfg(feature = "testing")]
+use std::{path::Path, sync::OnceLock};
 
+#[cfg(feature = "testing")]
 use flate2::read::GzDecoder;
 use log::Level;
 
@@ -62,18 +66,53 @@ impl Drop for TempFile {
     }
 }
 
+/// Path of a local copy of the remote test dataset, used instead of downloading it
+/// (e.g. offline CI or clusters). Gzipped and plain files are both accepted.
+pub const TEST_FILE_ENV: &str = "GTFSORT_TEST_FILE";
+
+/// Comma separated chromosomes overriding the default subset of the test dataset.
+/// Output checksums are not compared for custom subsets.
+pub const TEST_CHROMOSOMES_ENV: &str = "GTFSORT_TEST_CHROMOSOMES";
+
+/// Returns the chromosomes set in [TEST_CHROMOSOMES_ENV], if any.
+pub fn test_chromosomes_override() -> Option<Vec<String>> {
+    let list = std::env::var(TEST_CHROMOSOMES_ENV).ok()?;
+    let chroms = list
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    (!chroms.is_empty()).then_some(chroms)
+}
+
+/// Decompresses `r` if it starts with the gzip magic bytes, passes it through otherwise.
+#[cfg(feature = "testing")]
+pub fn maybe_gz(r: Box<dyn Read>) -> Box<dyn Read> {
+    let mut r = BufReader::new(r);
+    let gz = r
+        .fill_buf()
+        .map(|b| b.starts_with(&[0x1f, 0x8b]))
+        .unwrap_or(false);
+    if gz {
+        Box::new(GzDecoder::new(r))
+    } else {
+        Box::new(r)
+    }
+}
+
 pub struct OnlyChromosomes<R> {
     inner: BufReader<R>,
     buf: Option<VecDeque<u8>>,
-    chrom: &'static [&'static str],
+    chrom: Vec<String>,
 }
 
 impl<R: Read> OnlyChromosomes<R> {
-    pub fn new(inner: R, chrom: &'static [&'static str]) -> Self {
+    pub fn new<S: AsRef<str>>(inner: R, chrom: &[S]) -> Self {
         Self {
             inner: BufReader::new(inner),
             buf: None,
-            chrom,
+            chrom: chrom.iter().map(|c| c.as_ref().to_string()).collect(),
         }
     }
     pub fn buffer_more(&mut self) -> std::io::Result<usize> {
@@ -90,7 +129,7 @@ impl<R: Read> OnlyChromosomes<R> {
                 || self
                     .chrom
                     .iter()
-                    .any(|&c| line.split(|c| *c == b'\t').next() == Some(c.as_bytes()))
+                    .any(|c| line.split(|c| *c == b'\t').next() == Some(c.as_bytes()))
             {
                 let line = line.into_iter().collect::<VecDeque<_>>();
                 self.buf = Some(line);
@@ -131,41 +170,201 @@ impl<R: Read> Read for OnlyChromosomes<R> {
     }
 }
 
+#[cfg(feature = "testing")]
 pub const TEST_FILE_GFF3_GENCODE_MOUSE_M35_FILENAME: &str =
     "gencode.vM35.chr_patch_hapl_scaff.basic.annotation.gff3";
+#[cfg(feature = "testing")]
 pub const TEST_FILE_GFF3_GENCODE_MOUSE_M35_URL: &str  = "https://ftp.ebi.ac.uk/pub/databases/gencode/Gencode_mouse/release_M35/gencode.vM35.chr_patch_hapl_scaff.basic.annotation.gff3.gz";
-static TEST_FILE_GFF3_GENCODE_MOUSE_M35_CELL: Once = Once::new();
-static mut TEST_FILE_GFF3_GENCODE_MOUSE_M35: Option<TestFile> = None;
-pub const TEST_FILE_GFF3_GENCODE_MOUSE_M35_TRANSFORMER: &dyn Fn(Box<dyn Read>) -> Box<dyn Read> =
-    &|r| {
-        Box::new(OnlyChromosomes::new(
-            GzDecoder::new(r),
-            &[
-                "chr1",
-                "chr2",
-                "chr3",
-                "chr5",
-                "GL456221.1",
-                "chrM",
-                "ch11",
-                "ch17",
-            ],
-        ))
-    };
+#[cfg(feature = "testing")]
+static TEST_FILE_GFF3_GENCODE_MOUSE_M35: OnceLock<TestFile> = OnceLock::new();
+#[cfg(feature = "testing")]
+pub const TEST_FILE_GFF3_GENCODE_MOUSE_M35_CHROMOSOMES: &[&str] = &[
+    "chr1",
+    "chr2",
+    "chr3",
+    "chr5",
+    "GL456221.1",
+    "chrM",
+    "ch11",
+    "ch17",
+];
+#[cfg(feature = "testing")]
 pub const TEST_FILE_GFF3_GENCODE_MOUSE_M35_EXPECT_OUTPUT_CKSUM: [&str; 1] = ["f6f3eb1d"];
+#[cfg(feature = "testing")]
 pub fn get_test_file_gff3_gencode_mouse_m35() -> &'static TestFile {
-    TEST_FILE_GFF3_GENCODE_MOUSE_M35_CELL.call_once(|| unsafe {
-        TEST_FILE_GFF3_GENCODE_MOUSE_M35 = Some(TestFile::from_url(
+    TEST_FILE_GFF3_GENCODE_MOUSE_M35.get_or_init(|| match test_chromosomes_override() {
+        Some(chroms) => TestFile::from_url(
+            &format!(
+                "{}.{}",
+                TEST_FILE_GFF3_GENCODE_MOUSE_M35_FILENAME,
+                chroms.join("_")
+            ),
+            TEST_FILE_GFF3_GENCODE_MOUSE_M35_URL,
+            &|r| Box::new(OnlyChromosomes::new(maybe_gz(r), &chroms)),
+            &[],
+        ),
+        None => TestFile::from_url(
             TEST_FILE_GFF3_GENCODE_MOUSE_M35_FILENAME,
             TEST_FILE_GFF3_GENCODE_MOUSE_M35_URL,
-            &TEST_FILE_GFF3_GENCODE_MOUSE_M35_TRANSFORMER,
+            &|r| {
+                Box::new(OnlyChromosomes::new(
+                    maybe_gz(r),
+                    TEST_FILE_GFF3_GENCODE_MOUSE_M35_CHROMOSOMES,
+                ))
+            },
             &TEST_FILE_GFF3_GENCODE_MOUSE_M35_EXPECT_OUTPUT_CKSUM,
-        ));
-    });
+        ),
+    })
+}
+
+/// Passes lines through while `keep` holds, then ends the stream, e.g. to drop the
+/// `##FASTA` section of a GFF3 file.
+pub struct TakeWhileLines<R, F> {
+    inner: BufReader<R>,
+    keep: F,
+    line: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read, F: FnMut(&[u8]) -> bool> TakeWhileLines<R, F> {
+    pub fn new(inner: R, keep: F) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            keep,
+            line: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read, F: FnMut(&[u8]) -> bool> Read for TakeWhileLines<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.line.len() && !self.done {
+            self.line.clear();
+            self.pos = 0;
+            if self.inner.read_until(b'\n', &mut self.line)? == 0 || !(self.keep)(&self.line) {
+                self.line.clear();
+                self.done = true;
+            }
+        }
+
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Ends a GFF3 stream at its `##FASTA` directive.
+pub fn stop_at_fasta<R: Read>(inner: R) -> TakeWhileLines<R, impl FnMut(&[u8]) -> bool> {
+    TakeWhileLines::new(inner, |l: &[u8]| !l.starts_with(b"##FASTA"))
+}
+
+/// A public annotation in a format dialect gtfsort must keep sorting, cut down to a few
+/// chromosomes and downloaded once into the temporary directory.
+#[cfg(feature = "testing")]
+pub struct ReferenceDataset {
+    /// Cache file name, its extension gives the format.
+    pub name: &'static str,
+    pub url: &'static str,
+    /// Path of a local copy used instead of downloading `url`.
+    pub local_env: &'static str,
+    pub format: crate::AnnotationFormat,
+    pub chromosomes: &'static [&'static str],
+    /// CRC-32 (cksum) of the sorted subset, see [ReferenceDataset::execute_test].
+    pub expect_output_cksum: &'static [&'static str],
+    file: OnceLock<TestFile>,
+}
+
+#[cfg(feature = "testing")]
+impl ReferenceDataset {
+    /// Downloads (or reads from `local_env`) and caches the subset on first use.
+    pub fn get(&'static self) -> &'static TestFile {
+        self.file.get_or_init(|| {
+            TestFile::from_url_or_env(
+                self.name,
+                self.url,
+                self.local_env,
+                &|r| {
+                    Box::new(OnlyChromosomes::new(
+                        stop_at_fasta(maybe_gz(r)),
+                        self.chromosomes,
+                    ))
+                },
+                self.expect_output_cksum,
+            )
+        })
+    }
+
+    /// Runs `f` on the subset like [TestFile::execute_test], failing if no checksum was
+    /// recorded for it yet unless [RECORD_CKSUM_ENV] is set, in which case the checksum
+    /// is only printed so it can be added to the dataset.
+    pub fn execute_test<F: FnOnce(&str) -> String>(&'static self, f: F) {
+        let file = self.get();
+        if !self.expect_output_cksum.is_empty() {
+            return file.execute_test(self.name, f);
+        }
 
-    unsafe { TEST_FILE_GFF3_GENCODE_MOUSE_M35.as_ref().unwrap() }
+        let output_cksum = f(&file.name);
+        assert!(
+            std::env::var_os(RECORD_CKSUM_ENV).is_some(),
+            "{}: no checksum recorded, got: {} (set {} to only print it)",
+            self.name,
+            output_cksum,
+            RECORD_CKSUM_ENV
+        );
+        eprintln!("{}: recorded cksum: {}", self.name, output_cksum);
+    }
 }
 
+/// Lets the reference tests pass for datasets without a recorded checksum, printing it.
+pub const RECORD_CKSUM_ENV: &str = "GTFSORT_RECORD_CKSUM";
+
+#[cfg(feature = "testing")]
+pub static REFERENCE_GTF_GENCODE_HUMAN_46: ReferenceDataset = ReferenceDataset {
+    name: "gencode.v46.basic.annotation.chr21_chr22_chrM.gtf",
+    url: "https://ftp.ebi.ac.uk/pub/databases/gencode/Gencode_human/release_46/gencode.v46.basic.annotation.gtf.gz",
+    local_env: "GTFSORT_TEST_FILE_GENCODE_HUMAN_46",
+    format: crate::AnnotationFormat::Gtf,
+    chromosomes: &["chr21", "chr22", "chrM"],
+    expect_output_cksum: &[],
+    file: OnceLock::new(),
+};
+
+#[cfg(feature = "testing")]
+pub static REFERENCE_GFF3_REFSEQ_GRCH38: ReferenceDataset = ReferenceDataset {
+    name: "GCF_000001405.40_GRCh38.p14_genomic.chr21_chrM.gff3",
+    url: "https://ftp.ncbi.nlm.nih.gov/genomes/all/GCF/000/001/405/GCF_000001405.40_GRCh38.p14/GCF_000001405.40_GRCh38.p14_genomic.gff.gz",
+    local_env: "GTFSORT_TEST_FILE_REFSEQ_GRCH38",
+    format: crate::AnnotationFormat::Gff3,
+    chromosomes: &["NC_000021.9", "NC_012920.1"],
+    expect_output_cksum: &[],
+    file: OnceLock::new(),
+};
+
+#[cfg(feature = "testing")]
+pub static REFERENCE_GFF_TAIR10: ReferenceDataset = ReferenceDataset {
+    name: "TAIR10_GFF3_genes.Chr4_ChrC_ChrM.gff",
+    url: "https://www.arabidopsis.org/download_files/Genes/TAIR10_genome_release/TAIR10_gff3/TAIR10_GFF3_genes.gff",
+    local_env: "GTFSORT_TEST_FILE_TAIR10",
+    format: crate::AnnotationFormat::Gff3,
+    chromosomes: &["Chr4", "ChrC", "ChrM"],
+    expect_output_cksum: &[],
+    file: OnceLock::new(),
+};
+
+/// Datasets checked by `ci/reference_test.rs`, only built with the `reference_datasets`
+/// feature.
+#[cfg(feature = "testing")]
+pub static REFERENCE_DATASETS: [&ReferenceDataset; 3] = [
+    &REFERENCE_GTF_GENCODE_HUMAN_46,
+    &REFERENCE_GFF3_REFSEQ_GRCH38,
+    &REFERENCE_GFF_TAIR10,
+];
+
+#[cfg(feature = "testing")]
 pub fn crc32_hex<R: Read>(mut r: R) -> String {
     use crc::{Crc, CRC_32_CKSUM};
 
@@ -185,11 +384,13 @@ pub fn crc32_hex<R: Read>(mut r: R) -> String {
     format!("{:08x}", digest.finalize())
 }
 
+#[cfg(feature = "testing")]
 pub struct TestFile {
     pub name: String,
     expect_output_cksum: Vec<&'static str>,
 }
 
+#[cfg(feature = "testing")]
 impl TestFile {
     pub fn new_fs(name: &str, expect_output_cksum: &[&'static str]) -> Self {
         Self {
@@ -202,24 +403,46 @@ impl TestFile {
         url: &str,
         pipe: &F,
         expect_output_cksum: &[&'static str],
+    ) -> Self {
+        Self::from_url_or_env(cache_name, url, TEST_FILE_ENV, pipe, expect_output_cksum)
+    }
+    /// Like [TestFile::from_url], reading a local copy from `local_env` instead of
+    /// [TEST_FILE_ENV], so each dataset can be overridden separately.
+    pub fn from_url_or_env<RO: Read + ?Sized, F: Fn(Box<dyn Read>) -> Box<RO>>(
+        cache_name: &str,
+        url: &str,
+        local_env: &str,
+        pipe: &F,
+        expect_output_cksum: &[&'static str],
     ) -> Self {
         let tmpdir = std::env::temp_dir();
+        let local = std::env::var_os(local_env);
 
+        // a local copy may change between runs, so it is never served from the cache
+        let cache_name = match local {
+            Some(_) => format!("local.{}", cache_name),
+            None => cache_name.to_string(),
+        };
         let name = tmpdir.join(cache_name).to_string_lossy().to_string();
         let path = Path::new(&name);
 
-        if path.exists() {
+        if path.exists() && local.is_none() {
             return Self {
                 name: path.to_str().unwrap().to_string(),
                 expect_output_cksum: expect_output_cksum.to_vec(),
             };
         }
 
-        let mut file = std::fs::File::create(path).unwrap();
-
-        let resp = reqwest::blocking::get(url).unwrap();
+        let source: Box<dyn Read> = match local {
+            Some(local) => Box::new(
+                std::fs::File::open(&local)
+                    .unwrap_or_else(|e| panic!("{} {:?}: {}", local_env, local, e)),
+            ),
+            None => Box::new(reqwest::blocking::get(url).unwrap()),
+        };
 
-        std::io::copy(&mut pipe(Box::new(resp)), &mut file).unwrap();
+        let mut file = std::fs::File::create(path).unwrap();
+        std::io::copy(&mut pipe(source), &mut file).unwrap();
 
         Self::new_fs(name.as_str(), expect_output_cksum)
     }
@@ -239,6 +462,122 @@ impl TestFile {
     }
 }
 
+/// Generates a deterministic, shuffled GTF with `genes` genes on each of `chroms`, each
+/// gene having one transcript of three exons with CDS and codons.
+///
+/// The same arguments always produce the same text, so it can back golden tests without
+/// any download.
+pub fn synthetic_gtf(chroms: &[&str], genes: usize, seed: u64) -> String {
+    let mut lines = Vec::new();
+    for chrom in chroms {
+        for g in 0..genes {
+            let start = 1000 + g as u32 * 5000;
+            let gene = format!("{}_G{}", chrom, g);
+            let strand = if g % 2 == 0 { '+' } else { '-' };
+            let line = |feat: &str, s: u32, e: u32, attrs: &str| {
+                format!(
+                    "{}\tsynthetic\t{}\t{}\t{}\t.\t{}\t.\tgene_id \"{}\";{}",
+                    chrom, feat, s, e, strand, gene, attrs
+                )
+            };
+
+            let tx = format!(" transcript_id \"{}.1\";", gene);
+            lines.push(line("gene", start, start + 3000, ""));
+            lines.push(line("transcript", start, start + 3000, &tx));
+            for e in 0..3u32 {
+                let (s, end) = (start + e * 1000, start + e * 1000 + 500);
+                let attrs = format!("{} exon_number \"{}\";", tx, e + 1);
+                lines.push(line("exon", s, end, &attrs));
+                lines.push(line("CDS", s + 10, end - 10, &attrs));
+            }
+            let first = format!("{} exon_number \"1\";", tx);
+            let last = format!("{} exon_number \"3\";", tx);
+            lines.push(line("start_codon", start + 10, start + 12, &first));
+            lines.push(line("stop_codon", start + 2488, start + 2490, &last));
+        }
+    }
+
+    shuffled_lines(lines, seed)
+}
+
+/// Generates a deterministic, shuffled GTF with one single-exon gene on each of
+/// `scaffolds` contigs, like a draft assembly where ordering the contig names is a
+/// visible part of the runtime.
+pub fn synthetic_scaffolds_gtf(scaffolds: usize, seed: u64) -> String {
+    let mut lines = Vec::with_capacity(scaffolds * 3);
+    for s in 0..scaffolds {
+        // a few naming schemes, zero padded or not, as assemblers write them
+        let chrom = match s % 3 {
+            0 => format!("scaffold_{}", s),
+            1 => format!("contig{:07}", s),
+            _ => format!("ptg{:06}l.{}", s, s % 7),
+        };
+        let gene = format!("S{}", s);
+        for (feat, attrs) in [
+            ("gene", format!("gene_id \"{}\";", gene)),
+            (
+                "transcript",
+                format!("gene_id \"{0}\"; transcript_id \"{0}.1\";", gene),
+            ),
+            (
+                "exon",
+                format!(
+                    "gene_id \"{0}\"; transcript_id \"{0}.1\"; exon_number \"1\";",
+                    gene
+                ),
+            ),
+        ] {
+            lines.push(format!(
+                "{}\tsynthetic\t{}\t100\t900\t.\t+\t.\t{}",
+                chrom, feat, attrs
+            ));
+        }
+    }
+
+    shuffled_lines(lines, seed)
+}
+
+/// Joins `lines` in an order shuffled by `seed`.
+fn shuffled_lines(mut lines: Vec<String>, seed: u64) -> String {
+    // Fisher-Yates with a xorshift generator, stable across platforms and releases
+    let mut state = seed.max(1);
+    for i in (1..lines.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        lines.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+
+    let mut out = String::new();
+    for l in lines {
+        writeln!(out, "{}", l).unwrap();
+    }
+    out
+}
+
+/// Writes [synthetic_gtf] to a [TempFile] named `name`, removed when dropped.
+pub fn synthetic_gtf_file(name: &str, chroms: &[&str], genes: usize, seed: u64) -> TempFile {
+    let file = TempFile::new(name, true);
+    std::fs::write(&*file, synthetic_gtf(chroms, genes, seed)).unwrap();
+    file
+}
+
+/// Sorts `input` in memory on one thread and returns the output, panicking on errors.
+pub fn sort_to_string<const SEP: u8>(input: &str, options: &crate::SortOptions) -> String {
+    let mut out = Vec::new();
+    crate::sort_annotations_string_with_options::<SEP, _>(
+        input,
+        &mut |b| {
+            out.extend_from_slice(b);
+            Ok(b.len())
+        },
+        1,
+        options,
+    )
+    .unwrap();
+    String::from_utf8(out).unwrap()
+}
+
 static TEST_LOGGER_INIT: Once = Once::new();
 
 pub fn ensure_logger_initialized() {
@@ -1,34 +1,57 @@
 use hashbrown::HashMap;
-use rayon::prelude::*;
 
-use colored::Colorize;
-
-use dashmap::DashMap;
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::{self, Write};
 use std::path::Path;
 
-use indoc::indoc;
 use log::info;
 
-use crate::gtf::Record;
-use crate::ord::CowNaturalSort;
+use crate::color::Colorize;
+use crate::dedup::DedupMode;
+use crate::filter::{ChromFilter, IdFilter};
+use crate::format::Newline;
+use crate::gtf::{line_coords, line_strand, numeric_attribute, pairs, replace_line_coords, Record};
+use crate::header::Header;
+use crate::options::SortOptions;
+use crate::ord::{
+    ChildKey, CowNaturalSort, Descending, GeneSortKey, TranscriptOrder, UnnumberedOrder,
+};
+use crate::par::*;
+use crate::progress::PhaseProgress;
+use crate::rejects::Orphan;
+use crate::sniff;
+use crate::version::VERSION;
+use crate::watchdog::Watchdog;
 use crate::SortAnnotationsJobResult;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-
 pub type Chrom<'a> = &'a str;
 pub type ChromRecord<'a> = HashMap<Chrom<'a>, Vec<Record<'a>>>;
+/// Layers of each chromosome.
+pub type Index<'a> = HashMap<Chrom<'a>, Layers<'a>>;
+
+/// Upper bound of bytes handed to the output callback of a [ChunkWriter] in a single call.
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
 
+/// Adapts an output callback into a [Write].
+///
+/// Each call to the callback receives at most `max_chunk` bytes, so arbitrarily long lines
+/// (e.g. megabyte-long attribute columns) are streamed in pieces instead of in one call.
 pub struct ChunkWriter<'f, F: FnMut(&[u8]) -> io::Result<usize>> {
     f: &'f mut F,
+    max_chunk: usize,
 }
 
 impl<'f, F: FnMut(&[u8]) -> io::Result<usize>> ChunkWriter<'f, F> {
     pub fn new(f: &'f mut F) -> Self {
-        Self { f }
+        Self::with_chunk_size(f, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(f: &'f mut F, max_chunk: usize) -> Self {
+        assert!(max_chunk > 0, "chunk size must be greater than 0");
+        Self { f, max_chunk }
     }
 }
 
@@ -37,7 +60,8 @@ where
     F: FnMut(&[u8]) -> io::Result<usize>,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        (self.f)(buf)
+        let n = buf.len().min(self.max_chunk);
+        (self.f)(&buf[..n])
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -59,31 +83,487 @@ pub fn timed<T, F: FnOnce() -> T>(key: &str, output: Option<&mut f64>, f: F) ->
 #[derive(Debug)]
 pub struct Layers<'a> {
     // (start, gene_id, line)
-    pub layer: Vec<(u32, &'a str, &'a str)>,
+    pub layer: Vec<(u32, &'a str, Cow<'a, str>)>,
     // gene_id -> [transcript_id, transcript_id, ...]
     pub mapper: HashMap<&'a str, Vec<&'a str>>,
     // transcript_id -> {feat -> line}
-    pub inner: HashMap<&'a str, BTreeMap<CowNaturalSort<'a>, Vec<&'a str>>>,
+    pub inner: HashMap<&'a str, BTreeMap<ChildKey<'a>, Vec<&'a str>>>,
     // transcript_id -> line
-    pub helper: HashMap<&'a str, &'a str>,
+    pub helper: HashMap<&'a str, Cow<'a, str>>,
+    // gene_id -> sorted block copied from a previous output, see crate::manifest
+    pub reused: HashMap<&'a str, &'a str>,
+    // Record::ordinal of the lines left out as orphans, only kept with SortOptions::rejects
+    pub orphaned: Vec<(usize, Orphan<'a>)>,
 }
 
 impl<'a> Layers<'a> {
     pub fn count_line_size(&self) -> usize {
+        self.count_line_size_with(Newline::Lf)
+    }
+
+    /// Size in bytes of the sorted output, with lines terminated by `newline`.
+    pub fn count_line_size_with(&self, newline: Newline) -> usize {
+        let nl = newline.width();
         let mut total = 0;
 
         for i in self.layer.iter() {
-            total += i.2.len() + 1;
-            let transcripts = self.mapper.get(&i.1).unwrap();
-            for j in transcripts.iter() {
-                total += self.helper.get(j).unwrap().len() + 1;
-                let exons = self.inner.get(j).unwrap();
-                total += exons.values().flatten().map(|x| x.len() + 1).sum::<usize>();
+            if let Some(block) = self.reused.get(&i.1) {
+                total += block.len();
+                continue;
+            }
+            total += i.2.len() + nl;
+            for j in self.transcripts(i.1) {
+                total += self.helper.get(j).map_or(0, |l| l.len() + nl);
+                total += self.children(j).map(|x| x.len() + nl).sum::<usize>();
             }
         }
 
         total
     }
+
+    /// Writes the block of one gene of `layer`: the gene line, then each transcript
+    /// followed by its children.
+    pub fn write_gene<W: Write>(
+        &self,
+        output: &mut W,
+        (_, gene_id, line): &(u32, &'a str, Cow<'a, str>),
+        newline: Newline,
+    ) -> io::Result<()> {
+        if let Some(block) = self.reused.get(gene_id) {
+            return output.write_all(block.as_bytes());
+        }
+
+        write_line(output, line, newline)?;
+        for j in self.transcripts(gene_id) {
+            if let Some(line) = self.helper.get(j) {
+                write_line(output, line, newline)?;
+            }
+            self.children(j)
+                .try_for_each(|x| write_line(output, x, newline))?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets children whose transcript is `gene_id` itself be written right after the gene
+    /// line, see [Record::is_direct_child].
+    pub fn add_direct_children(&mut self, gene_id: &'a str) {
+        let transcripts = self.mapper.entry(gene_id).or_default();
+        if !transcripts.contains(&gene_id) {
+            transcripts.push(gene_id);
+        }
+    }
+
+    /// Whether the children of `transcript_id` have a line to follow: their transcript
+    /// line, or their gene line for children added by [Layers::add_direct_children].
+    pub fn has_parent_line(&self, transcript_id: &str) -> bool {
+        self.helper.contains_key(transcript_id)
+            || self
+                .mapper
+                .get(transcript_id)
+                .is_some_and(|t| t.contains(&transcript_id))
+    }
+
+    /// Transcripts of `gene_id`, none for a gene line without transcripts.
+    pub fn transcripts(&self, gene_id: &str) -> impl Iterator<Item = &&'a str> {
+        self.mapper.get(gene_id).into_iter().flatten()
+    }
+
+    /// Children of `transcript_id` in output order, none for a transcript line without
+    /// children.
+    pub fn children(&self, transcript_id: &str) -> impl Iterator<Item = &&'a str> {
+        self.inner
+            .get(transcript_id)
+            .into_iter()
+            .flat_map(|children| children.values().flatten())
+    }
+
+    /// Transcripts whose gene has no gene line and children whose transcript has no
+    /// transcript line, which are not written. Returns (transcripts, their children plus
+    /// the children without transcript).
+    pub fn orphans(&self) -> (usize, usize) {
+        let genes = self
+            .layer
+            .iter()
+            .map(|(_, gene_id, _)| *gene_id)
+            .collect::<hashbrown::HashSet<_>>();
+        let orphan_transcripts = self
+            .mapper
+            .iter()
+            .filter(|(gene_id, _)| !genes.contains(*gene_id))
+            .flat_map(|(_, transcripts)| transcripts.iter())
+            .collect::<Vec<_>>();
+        let children = orphan_transcripts
+            .iter()
+            .map(|t| self.children(t).count())
+            .sum::<usize>()
+            + self
+                .inner
+                .iter()
+                .filter(|(t, _)| !self.has_parent_line(t))
+                .map(|(t, _)| self.children(t).count())
+                .sum::<usize>();
+
+        (orphan_transcripts.len(), children)
+    }
+
+    /// The lines counted by [Layers::orphans], with why each was left out.
+    pub fn orphan_lines(&self) -> Vec<(&'a str, Orphan<'a>)> {
+        let genes = self
+            .layer
+            .iter()
+            .map(|(_, gene_id, _)| *gene_id)
+            .collect::<hashbrown::HashSet<_>>();
+        let mut lines = Vec::new();
+
+        for (gene_id, transcripts) in self.mapper.iter() {
+            if genes.contains(gene_id) {
+                continue;
+            }
+            for transcript_id in transcripts {
+                // synthesized lines are not part of the input
+                if let Some(Cow::Borrowed(line)) = self.helper.get(transcript_id) {
+                    lines.push((*line, Orphan::NoGene { gene_id }));
+                }
+                lines.extend(self.children(transcript_id).map(|line| {
+                    let orphan = Orphan::DroppedTranscript {
+                        transcript_id,
+                        gene_id,
+                    };
+                    (*line, orphan)
+                }));
+            }
+        }
+        for transcript_id in self.inner.keys() {
+            if !self.has_parent_line(transcript_id) {
+                lines.extend(
+                    self.children(transcript_id)
+                        .map(|line| (*line, Orphan::NoTranscript { transcript_id })),
+                );
+            }
+        }
+
+        lines
+    }
+
+    /// [Self::orphan_lines] as the [Record::ordinal] of each line, `records` being those
+    /// the layers were built from. Identical lines are matched in input order, which is
+    /// the order they were indexed in.
+    pub fn orphan_records(&self, records: &[Record<'a>]) -> Vec<(usize, Orphan<'a>)> {
+        let mut ordinals: HashMap<&str, std::collections::VecDeque<usize>> = HashMap::new();
+        for record in records {
+            ordinals
+                .entry(record.line)
+                .or_default()
+                .push_back(record.ordinal);
+        }
+
+        let mut orphans = self
+            .orphan_lines()
+            .into_iter()
+            .filter_map(|(line, orphan)| Some((ordinals.get_mut(line)?.pop_front()?, orphan)))
+            .collect::<Vec<_>>();
+        orphans.sort_unstable_by_key(|(ordinal, _)| *ordinal);
+        orphans
+    }
+
+    /// Calls `f` with each transcript of one gene of `layer`, as the transcript line followed
+    /// by its children, in output order. The gene line itself is not part of any block.
+    pub fn for_each_transcript<F: FnMut(&'a str, &[u8]) -> io::Result<()>>(
+        &self,
+        (_, gene_id, _): &(u32, &'a str, Cow<'a, str>),
+        newline: Newline,
+        buf: &mut Vec<u8>,
+        mut f: F,
+    ) -> io::Result<()> {
+        for j in self.transcripts(gene_id) {
+            buf.clear();
+            if let Some(line) = self.helper.get(j) {
+                write_line(buf, line, newline)?;
+            }
+            self.children(j)
+                .try_for_each(|x| write_line(buf, x, newline))?;
+            f(j, buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Orders children sharing the same key (e.g. two Selenocysteine features with the same
+    /// exon_number) by their position in the input, so that their output order does not
+    /// depend on how the parallel parser split and merged the input. Those with an
+    /// exon_number (e.g. the CDS segments of one exon) are ordered by start first.
+    ///
+    /// Children must have been added in the order of their [Record::ordinal], as
+    /// [parallel_parse] returns them; the sort is stable so ties keep that order.
+    pub fn stabilize_inner(&mut self) {
+        self.inner
+            .values_mut()
+            .flat_map(|children| children.iter_mut())
+            .filter(|(key, lines)| key.is_numbered() && lines.len() > 1)
+            .for_each(|(_, lines)| lines.sort_by_key(|l| line_coords(l).map_or(0, |c| c.0)));
+    }
+
+    /// Orders GFF3 children sharing a sort key (e.g. the segments of a CDS, which carry
+    /// no exon_number but one common ID) by coordinate, keeping segments with the same ID
+    /// contiguous. Groups follow the start of their first segment, ties keep input order.
+    pub fn group_segments(&mut self) {
+        let groups = self
+            .inner
+            .values_mut()
+            .flat_map(|children| children.values_mut())
+            .filter(|lines| lines.len() > 1);
+
+        for lines in groups {
+            let mut keyed = lines
+                .iter()
+                .map(|l| (segment_id(l), line_coords(l).map_or(0, |c| c.0), *l))
+                .collect::<Vec<_>>();
+
+            let mut group_start: HashMap<&str, u32> = HashMap::new();
+            for (id, start, _) in keyed.iter() {
+                group_start
+                    .entry(*id)
+                    .and_modify(|s| *s = (*s).min(*start))
+                    .or_insert(*start);
+            }
+
+            keyed.sort_by_key(|(id, start, _)| (group_start[id], *id, *start));
+            *lines = keyed.into_iter().map(|(_, _, l)| l).collect();
+        }
+    }
+
+    /// Orders the children without exon_number of each transcript (see
+    /// [ChildKey::Unnumbered]) following `order`, after [Self::stabilize_inner] and
+    /// [Self::group_segments] have put them in input order.
+    pub fn order_unnumbered(&mut self, order: UnnumberedOrder) {
+        if order == UnnumberedOrder::Input {
+            return;
+        }
+
+        let slots = self
+            .inner
+            .values_mut()
+            .flat_map(|children| children.iter_mut())
+            .filter(|(key, lines)| matches!(key, ChildKey::Unnumbered(_)) && lines.len() > 1);
+
+        for (_, lines) in slots {
+            let minus = order == UnnumberedOrder::Transcription
+                && lines.first().and_then(|l| line_strand(l)) == Some("-");
+            // stable, ties keep input order
+            if minus {
+                lines.sort_by_key(|l| std::cmp::Reverse(line_coords(l).map_or(0, |c| c.1)));
+            } else {
+                lines.sort_by_key(|l| line_coords(l).map_or(0, |c| c.0));
+            }
+        }
+    }
+
+    /// Orders the genes following `key`, ties broken by start, end and then gene_id. Genes
+    /// still tied (the same gene defined twice) keep their input order if `stable`.
+    pub fn sort_genes<const SEP: u8>(&mut self, key: &GeneSortKey, stable: bool) {
+        let end = |start: u32, line: &str| line_coords(line).map_or(start, |c| c.1);
+        match key {
+            GeneSortKey::Start => {
+                let key = |(start, gene_id, line): &(u32, &'a str, Cow<'a, str>)| {
+                    (*start, end(*start, line), *gene_id)
+                };
+                match stable {
+                    true => self.layer.par_sort_by_key(key),
+                    false => self.layer.par_sort_unstable_by_key(key),
+                }
+            }
+            GeneSortKey::Length => {
+                let key = |(start, gene_id, line): &(u32, &'a str, Cow<'a, str>)| {
+                    (
+                        std::cmp::Reverse(end(*start, line).saturating_sub(*start)),
+                        *start,
+                        *gene_id,
+                    )
+                };
+                match stable {
+                    true => self.layer.par_sort_by_key(key),
+                    false => self.layer.par_sort_unstable_by_key(key),
+                }
+            }
+            // stable either way
+            GeneSortKey::Attribute(attr) => {
+                let (mapper, helper) = (&self.mapper, &self.helper);
+                self.layer.par_sort_by_cached_key(|(start, gene_id, line)| {
+                    let value = numeric_attribute::<SEP>(line, attr).or_else(|| {
+                        mapper
+                            .get(gene_id)
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|t| helper.get(t))
+                            .filter_map(|l| numeric_attribute::<SEP>(l, attr))
+                            .max_by(f64::total_cmp)
+                    });
+                    let missing = value.is_none();
+                    (
+                        missing,
+                        Descending(value.unwrap_or_default()),
+                        *start,
+                        end(*start, line),
+                        *gene_id,
+                    )
+                });
+            }
+        }
+    }
+
+    /// Orders the transcripts of each gene following `order`. A transcript without a
+    /// transcript line (see [Self::add_direct_children]) is placed by its first child.
+    pub fn sort_transcripts(&mut self, order: TranscriptOrder) {
+        let (helper, inner) = (&self.helper, &self.inner);
+        let span = |t: &str| {
+            helper
+                .get(t)
+                .map(|l| l.as_ref())
+                .or_else(|| inner.get(t)?.values().flatten().next().copied())
+                .and_then(line_coords)
+                .unwrap_or((0, 0))
+        };
+        match order {
+            TranscriptOrder::Input => {}
+            TranscriptOrder::Position => self.mapper.values_mut().for_each(|transcripts| {
+                transcripts.sort_by_cached_key(|t| {
+                    let (start, end) = span(t);
+                    (start, end, CowNaturalSort::new(Cow::Borrowed(*t)))
+                })
+            }),
+            TranscriptOrder::Id => self
+                .mapper
+                .values_mut()
+                .for_each(|transcripts| transcripts.sort_by(|a, b| natord::compare(a, b))),
+        }
+    }
+
+    /// Merges the genes of `other` into `self`, e.g. the [Layers] of one chromosome indexed
+    /// from separate files or chunks with the same `options`, and orders the result as the
+    /// index of a single input would be (see [Self::reorder]).
+    ///
+    /// - a gene_id present in both keeps the gene line of `self`, the transcripts of both
+    ///   are written under it, those of `self` first in input order
+    /// - a transcript_id present in both keeps the transcript line of `self`, its children
+    ///   are merged by sort key, those of `self` first where their order is tied
+    ///
+    /// No line is deduplicated, identical children from both sides are written twice.
+    pub fn combine<const SEP: u8>(&mut self, other: Layers<'a>, options: &SortOptions) {
+        let mut genes = self
+            .layer
+            .iter()
+            .map(|(_, gene_id, _)| *gene_id)
+            .collect::<hashbrown::HashSet<_>>();
+        self.layer.extend(
+            other
+                .layer
+                .into_iter()
+                .filter(|(_, gene_id, _)| genes.insert(gene_id)),
+        );
+
+        for (gene_id, transcripts) in other.mapper {
+            let known = self.mapper.entry(gene_id).or_default();
+            for t in transcripts {
+                if !known.contains(&t) {
+                    known.push(t);
+                }
+            }
+        }
+        for (transcript_id, line) in other.helper {
+            self.helper.entry(transcript_id).or_insert(line);
+        }
+        for (transcript_id, children) in other.inner {
+            let known = self.inner.entry(transcript_id).or_default();
+            for (key, lines) in children {
+                known.entry(key).or_default().extend(lines);
+            }
+        }
+        for (gene_id, block) in other.reused {
+            self.reused.entry(gene_id).or_insert(block);
+        }
+        self.orphaned.extend(other.orphaned);
+        self.reorder::<SEP>(options);
+    }
+
+    /// Orders genes, transcripts and children merged by [Self::combine] as the index orders
+    /// them under `options`. Children are in input order within each side, those sharing
+    /// an exon_number are ordered by start again, ties keeping `self` first, before the
+    /// passes the index applies after [Self::stabilize_inner].
+    fn reorder<const SEP: u8>(&mut self, options: &SortOptions) {
+        self.inner
+            .values_mut()
+            .flat_map(|children| children.iter_mut())
+            .filter(|(key, lines)| key.is_numbered() && lines.len() > 1)
+            .for_each(|(_, lines)| lines.sort_by_key(|l| line_coords(l).map_or(0, |c| c.0)));
+        if SEP == b'=' {
+            self.group_segments();
+        }
+        self.order_unnumbered(options.unnumbered_order);
+        self.sort_transcripts(options.transcript_order);
+        self.sort_genes::<SEP>(&options.gene_sort_key, options.stable);
+        if let Some(n) = options.head_genes {
+            self.layer.truncate(n);
+        }
+    }
+
+    /// Keeps only the transcripts selected by `filter`, dropping genes left without transcripts.
+    ///
+    /// If `recompute_gene_bounds` is set, the start/end of each remaining gene line is
+    /// shrunk to the span of its remaining transcripts.
+    pub fn retain_transcripts(&mut self, filter: &IdFilter, recompute_gene_bounds: bool) {
+        for transcripts in self.mapper.values_mut() {
+            transcripts.retain(|t| filter.keep(t));
+        }
+
+        let (mapper, helper) = (&self.mapper, &self.helper);
+        self.layer.retain_mut(|(start, gene_id, line)| {
+            let transcripts = match mapper.get(gene_id) {
+                Some(t) if !t.is_empty() => t,
+                _ => return false,
+            };
+
+            if recompute_gene_bounds {
+                let bounds = transcripts
+                    .iter()
+                    .filter_map(|t| helper.get(t).and_then(|l| line_coords(l)))
+                    .reduce(|(s1, e1), (s2, e2)| (s1.min(s2), e1.max(e2)));
+
+                if let Some((new_start, new_end)) = bounds {
+                    *line = Cow::Owned(replace_line_coords(line, new_start, new_end));
+                    *start = new_start;
+                }
+            }
+
+            true
+        });
+    }
+}
+
+/// GFF3 `ID` of a line, empty if not set.
+fn segment_id(line: &str) -> &str {
+    line.splitn(9, '\t')
+        .nth(8)
+        .and_then(|attrs| pairs::<b'='>(attrs).find(|(k, _)| *k == "ID"))
+        .and_then(|(_, v)| v)
+        .unwrap_or("")
+}
+
+/// Merges `other` into `into` chromosome by chromosome, both indexed with `options`, see
+/// [Layers::combine].
+pub fn combine_index<'a, const SEP: u8>(
+    into: &mut Index<'a>,
+    other: Index<'a>,
+    options: &SortOptions,
+) {
+    for (chrom, layers) in other {
+        match into.get_mut(chrom) {
+            Some(known) => known.combine::<SEP>(layers, options),
+            None => {
+                into.insert(chrom, layers);
+            }
+        }
+    }
 }
 
 impl<'a> Default for Layers<'a> {
@@ -93,17 +573,24 @@ impl<'a> Default for Layers<'a> {
             mapper: HashMap::new(),
             inner: HashMap::new(),
             helper: HashMap::new(),
+            reused: HashMap::new(),
+            orphaned: Vec::new(),
         }
     }
 }
 
 #[cfg(not(feature = "mmap"))]
 #[inline(always)]
+#[allow(clippy::too_many_arguments)]
 pub fn write_obj<'a, P: AsRef<Path> + Debug>(
     file: P,
-    obj: &DashMap<&'a str, Layers>,
+    obj: &Index<'a>,
     keys: Vec<(&'a str, usize)>,
+    header: &Header,
     job: &mut Option<&mut SortAnnotationsJobResult>,
+    newline: Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
 ) -> Result<(), io::Error> {
     let f = match File::create(file) {
         Ok(f) => f,
@@ -113,25 +600,53 @@ pub fn write_obj<'a, P: AsRef<Path> + Debug>(
         }
     };
 
-    write_obj_sequential(f, obj, keys, job)
+    write_obj_sequential(f, obj, keys, header, job, newline, progress, watchdog)
 }
 
 #[cfg(feature = "mmap")]
 #[inline(always)]
+#[allow(clippy::too_many_arguments)]
 pub fn write_obj<'a, P: AsRef<Path> + Debug>(
     file: P,
-    obj: &DashMap<&'a str, Layers>,
+    obj: &Index<'a>,
     keys: Vec<(&'a str, usize)>,
+    header: &Header,
     job: &mut Option<&mut SortAnnotationsJobResult>,
+    newline: Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
 ) -> Result<(), io::Error> {
-    write_obj_mmaped(&file, obj, keys.clone(), job).or_else(move |e| {
+    let start = std::time::Instant::now();
+    let written = std::sync::atomic::AtomicU64::new(0);
+    write_obj_mmaped_counted(
+        &file,
+        obj,
+        keys.clone(),
+        header,
+        job,
+        newline,
+        progress,
+        watchdog,
+        &written,
+    )
+    .or_else(move |e| {
+        let lost_secs = start.elapsed().as_secs_f64();
         log::warn!(
-            "{} {}",
+            "{} {} ({:.2}s lost)",
             "Error in mmaped output, falling back to sequential:"
                 .bright_yellow()
                 .bold(),
-            e
+            e,
+            lost_secs
         );
+        if let Some(j) = job.as_deref_mut() {
+            j.fallbacks.push(crate::Fallback {
+                what: "output memory map",
+                reason: e.to_string(),
+                lost_secs,
+                extra_bytes: written.into_inner(),
+            });
+        }
 
         let f = match File::create(file) {
             Ok(f) => f,
@@ -141,36 +656,47 @@ pub fn write_obj<'a, P: AsRef<Path> + Debug>(
             }
         };
 
-        write_obj_sequential(f, obj, keys, job)
+        write_obj_sequential(f, obj, keys, header, job, newline, progress, watchdog)
     })
 }
 
+#[inline(always)]
+pub(crate) fn write_line<W: Write + ?Sized>(
+    output: &mut W,
+    line: &str,
+    newline: Newline,
+) -> io::Result<()> {
+    output.write_all(line.as_bytes())?;
+    output.write_all(newline.as_bytes())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn write_obj_sequential<'a, W: Write>(
     file: W,
-    obj: &DashMap<&'a str, Layers>,
+    obj: &Index<'a>,
     keys: Vec<(&'a str, usize)>,
+    header: &Header,
     _job: &mut Option<&mut SortAnnotationsJobResult>,
+    newline: Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
 ) -> Result<(), io::Error> {
     use std::io::BufWriter;
 
     let mut output = BufWriter::new(file);
+    header.write(&mut output, newline)?;
+    progress.add_bytes(header.size_with(newline) as u64);
 
-    for (k, _) in keys {
+    for (k, size) in keys {
+        watchdog_io_check(watchdog)?;
+        let _ctx = crate::panic::enter("writing", Some(k));
         let chr = obj.get(k).unwrap();
 
         for i in chr.layer.iter() {
-            writeln!(output, "{}", i.2)?;
-
-            let transcripts = chr.mapper.get(&i.1).unwrap();
-            for j in transcripts.iter() {
-                writeln!(output, "{}", chr.helper.get(j).unwrap())?;
-                let exons = chr.inner.get(j).unwrap();
-                exons
-                    .values()
-                    .flatten()
-                    .try_for_each(|x| writeln!(output, "{}", x))?;
-            }
+            chr.write_gene(&mut output, i, newline)?;
         }
+
+        progress.step_bytes(Some(k), size as u64);
     }
 
     output.flush()?;
@@ -179,11 +705,37 @@ pub fn write_obj_sequential<'a, W: Write>(
 }
 
 #[cfg(feature = "mmap")]
+#[allow(clippy::too_many_arguments)]
 pub fn write_obj_mmaped<'a, P: AsRef<Path> + Debug>(
     file: P,
-    obj: &DashMap<&'a str, Layers>,
+    obj: &Index<'a>,
     keys: Vec<(&'a str, usize)>,
+    header: &Header,
     job: &mut Option<&mut SortAnnotationsJobResult>,
+    newline: Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
+) -> Result<(), io::Error> {
+    let written = std::sync::atomic::AtomicU64::new(0);
+    write_obj_mmaped_counted(
+        file, obj, keys, header, job, newline, progress, watchdog, &written,
+    )
+}
+
+/// [write_obj_mmaped], adding the bytes of every chromosome written to `written`, so
+/// that a failed attempt can tell how much it wrote.
+#[cfg(feature = "mmap")]
+#[allow(clippy::too_many_arguments)]
+fn write_obj_mmaped_counted<'a, P: AsRef<Path> + Debug>(
+    file: P,
+    obj: &Index<'a>,
+    keys: Vec<(&'a str, usize)>,
+    header: &Header,
+    job: &mut Option<&mut SortAnnotationsJobResult>,
+    newline: Newline,
+    progress: &PhaseProgress,
+    watchdog: &Watchdog,
+    written: &std::sync::atomic::AtomicU64,
 ) -> Result<(), io::Error> {
     use std::{fs::OpenOptions, io::Cursor};
 
@@ -196,7 +748,8 @@ pub fn write_obj_mmaped<'a, P: AsRef<Path> + Debug>(
         .truncate(true)
         .open(file)?;
 
-    let size = keys.iter().map(|(_, i)| *i as u64).sum();
+    let header_size = header.size_with(newline);
+    let size = keys.iter().map(|(_, i)| *i as u64).sum::<u64>() + header_size as u64;
 
     if size == 0 {
         return Ok(());
@@ -217,13 +770,18 @@ pub fn write_obj_mmaped<'a, P: AsRef<Path> + Debug>(
         }
     }
 
-    let mut output = output_map.as_mut_slice();
+    let output = output_map.as_mut_slice();
 
     log::info!(
         "Successfully mapped output file, size: {} bytes",
         output.len()
     );
 
+    let (header_slice, mut output) = output.split_at_mut(header_size);
+    header.write(&mut Cursor::new(header_slice), newline)?;
+    written.fetch_add(header_size as u64, std::sync::atomic::Ordering::Relaxed);
+    progress.add_bytes(header_size as u64);
+
     let mut output_slices = Vec::new();
     for (_, s) in keys.iter() {
         let (a, b) = output.split_at_mut(*s);
@@ -231,27 +789,20 @@ pub fn write_obj_mmaped<'a, P: AsRef<Path> + Debug>(
         output = b;
     }
 
+    let panic_job = crate::panic::Job::current();
     keys.into_iter()
         .zip(output_slices)
         .collect::<Vec<_>>()
         .into_par_iter()
         .try_for_each(|((k, size_expected), output)| {
+            watchdog_io_check(watchdog)?;
+            let _ctx = panic_job.enter("writing", Some(k));
             let chr = obj.get(k).unwrap();
 
             let mut output = Cursor::new(output);
 
             for i in chr.layer.iter() {
-                writeln!(output, "{}", i.2)?;
-
-                let transcripts = chr.mapper.get(&i.1).unwrap();
-                for j in transcripts.iter() {
-                    writeln!(output, "{}", chr.helper.get(j).unwrap())?;
-                    let exons = chr.inner.get(j).unwrap();
-                    exons
-                        .values()
-                        .flatten()
-                        .try_for_each(|x| writeln!(output, "{}", x))?;
-                }
+                chr.write_gene(&mut output, i, newline)?;
             }
 
             assert_eq!(
@@ -259,6 +810,9 @@ pub fn write_obj_mmaped<'a, P: AsRef<Path> + Debug>(
                 size_expected as u64,
                 "Output buffer not empty, something went wrong"
             );
+            written.fetch_add(size_expected as u64, std::sync::atomic::Ordering::Relaxed);
+
+            progress.step_bytes(Some(k), size_expected as u64);
 
             Ok::<_, io::Error>(())
         })?;
@@ -272,23 +826,435 @@ pub fn write_obj_mmaped<'a, P: AsRef<Path> + Debug>(
     Ok(())
 }
 
-pub fn parallel_parse<const SEP: u8>(s: &str) -> Result<ChromRecord<'_>, &'static str> {
-    let x = s
-        .par_lines()
-        .filter(|line| !line.starts_with('#'))
-        .filter_map(|line| Record::parse::<SEP>(line).ok())
-        .fold(HashMap::new, |mut acc: ChromRecord, record| {
-            acc.entry(record.chrom).or_default().push(record);
-            acc
-        })
-        .reduce(HashMap::new, |mut acc, map| {
-            for (k, v) in map {
-                acc.entry(k).or_default().extend(v);
+/// Stops a writer with an [io::ErrorKind::Interrupted] error once `watchdog` tripped,
+/// the caller is expected to turn it back into the watchdog error.
+#[inline(always)]
+pub(crate) fn watchdog_io_check(watchdog: &Watchdog) -> io::Result<()> {
+    match watchdog.tripped() {
+        Some(t) => Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            format!("stopped by watchdog: {:?}", t),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// How the lines of an input were accounted for, so that they add up to its line count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineCounts {
+    /// Parsed records.
+    pub records: usize,
+    /// Lines starting with '#', including stray '#' markers.
+    pub comments: usize,
+    /// Empty or whitespace-only lines.
+    pub blank: usize,
+    /// Lines on chromosomes not selected by [crate::SortOptions::chromosomes].
+    pub skipped: usize,
+    /// Lines that could not be parsed as a record, or placed in the GFF3 hierarchy.
+    pub invalid: usize,
+    /// Records dropped as duplicates of an earlier one, see [crate::SortOptions::dedup].
+    pub duplicates: usize,
+    /// Lines of the `##FASTA` section ending a GFF3 file, see [crate::sniff::split_fasta].
+    pub sequence: usize,
+    /// Lines around the annotation section, copied through unsorted, see
+    /// [crate::SortOptions::section].
+    pub surrounding: usize,
+}
+
+impl LineCounts {
+    pub fn total(&self) -> usize {
+        self.records
+            + self.comments
+            + self.blank
+            + self.skipped
+            + self.invalid
+            + self.duplicates
+            + self.sequence
+            + self.surrounding
+    }
+
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            records: self.records + other.records,
+            comments: self.comments + other.comments,
+            blank: self.blank + other.blank,
+            skipped: self.skipped + other.skipped,
+            invalid: self.invalid + other.invalid,
+            duplicates: self.duplicates + other.duplicates,
+            sequence: self.sequence + other.sequence,
+            surrounding: self.surrounding + other.surrounding,
+        }
+    }
+
+    /// Drops the duplicate records of `records` (see [crate::dedup]), moving them from
+    /// [Self::records] to [Self::duplicates].
+    pub fn drop_duplicates(&mut self, records: &mut ChromRecord, mode: DedupMode) {
+        let dropped = crate::dedup::drop_duplicates(records, mode);
+        self.records -= dropped;
+        self.duplicates += dropped;
+    }
+
+    /// Counts `line` if it is a comment, blank or outside `chroms`, returning false for
+    /// candidate records. Lines read in order from a `##FASTA` directive on are counted as
+    /// [Self::sequence].
+    #[inline(always)]
+    pub fn count_non_record(&mut self, line: &str, chroms: Option<&ChromFilter>) -> bool {
+        if self.sequence > 0 || line.starts_with(sniff::FASTA_DIRECTIVE) {
+            self.sequence += 1;
+        } else if line.trim().is_empty() {
+            self.blank += 1;
+        } else if line.trim_start().starts_with('#') {
+            self.comments += 1;
+        } else if chroms.is_some_and(|c| !c.keep_line(line)) {
+            self.skipped += 1;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Logs the accounting, warning about lines that could not be parsed.
+    pub fn log(&self) {
+        log::info!(
+            "Input lines: {} records, {} comments, {} blank, {} skipped, {} invalid, {} sequence",
+            self.records,
+            self.comments,
+            self.blank,
+            self.skipped,
+            self.invalid,
+            self.sequence
+        );
+        if self.sequence > 0 {
+            log::warn!(
+                "{} the ##FASTA section ({} lines) is not part of the annotation and was left out of the output",
+                "Warning:".bright_yellow().bold(),
+                self.sequence
+            );
+        }
+        if self.duplicates > 0 {
+            log::warn!(
+                "{} {} duplicate records were left out of the output",
+                "Warning:".bright_yellow().bold(),
+                self.duplicates
+            );
+        }
+        if self.invalid > 0 {
+            log::warn!(
+                "{} {} of {} lines could not be parsed and were left out of the output",
+                "Warning:".bright_yellow().bold(),
+                self.invalid,
+                self.total()
+            );
+        }
+        if self.records == 0 {
+            log::warn!(
+                "{} the input has no records{}, the output is empty",
+                "Warning:".bright_yellow().bold(),
+                match self.skipped {
+                    0 => String::new(),
+                    n => format!(" on the selected chromosomes ({} lines skipped)", n),
+                }
+            );
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"records\":{},\"comments\":{},\"blank\":{},\"skipped\":{},\"invalid\":{},\"duplicates\":{},\"sequence\":{},\"surrounding\":{}}}",
+            self.records,
+            self.comments,
+            self.blank,
+            self.skipped,
+            self.invalid,
+            self.duplicates,
+            self.sequence,
+            self.surrounding
+        )
+    }
+}
+
+/// Parsed records per chromosome and per feature type, for the run summary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordHistogram {
+    /// In natural order, or in the output order after [RecordHistogram::order_chroms].
+    pub chromosomes: Vec<(String, usize)>,
+    /// Most frequent first.
+    pub features: Vec<(String, usize)>,
+}
+
+impl RecordHistogram {
+    pub fn from_records(records: &ChromRecord) -> Self {
+        let mut features = HashMap::<&str, usize>::new();
+        for record in records.values().flatten() {
+            *features.entry(record.feat).or_default() += 1;
+        }
+        Self::from_counts(records.iter().map(|(chrom, r)| (*chrom, r.len())), features)
+    }
+
+    /// Counts the lines of each chromosome, taking the feature type from the third column.
+    pub fn from_lines<'a, I: IntoIterator<Item = (&'a str, L)>, L: IntoIterator<Item = &'a str>>(
+        chroms: I,
+    ) -> Self {
+        let mut features = HashMap::<&str, usize>::new();
+        let chroms = chroms
+            .into_iter()
+            .map(|(chrom, lines)| {
+                let mut n = 0;
+                for line in lines {
+                    n += 1;
+                    *features
+                        .entry(line.split('\t').nth(2).unwrap_or_default())
+                        .or_default() += 1;
+                }
+                (chrom, n)
+            })
+            .collect::<Vec<_>>();
+        Self::from_counts(chroms, features)
+    }
+
+    fn from_counts<'a, I: IntoIterator<Item = (&'a str, usize)>>(
+        chroms: I,
+        features: HashMap<&'a str, usize>,
+    ) -> Self {
+        let mut histogram = Self {
+            chromosomes: chroms
+                .into_iter()
+                .map(|(chrom, n)| (chrom.to_string(), n))
+                .collect(),
+            features: features
+                .into_iter()
+                .map(|(feat, n)| (feat.to_string(), n))
+                .collect(),
+        };
+        histogram.sort();
+        histogram
+    }
+
+    /// Sums the counts of `parts`, e.g. those of each chromosome.
+    pub fn combine<I: IntoIterator<Item = RecordHistogram>>(parts: I) -> Self {
+        let (mut chroms, mut features) = (HashMap::new(), HashMap::new());
+        for part in parts {
+            for (chrom, n) in part.chromosomes {
+                *chroms.entry(chrom).or_default() += n;
             }
-            acc
-        });
+            for (feat, n) in part.features {
+                *features.entry(feat).or_default() += n;
+            }
+        }
+        let mut histogram = Self {
+            chromosomes: chroms.into_iter().collect(),
+            features: features.into_iter().collect(),
+        };
+        histogram.sort();
+        histogram
+    }
+
+    /// Orders [Self::chromosomes] as the output of a job with `options` writes them (see
+    /// [crate::SortOptions::chrom_order] and [crate::SortOptions::chrom_sort]), without
+    /// failing or warning about chromosomes missing from a chromosome order.
+    pub fn order_chroms(&mut self, options: &SortOptions) {
+        let mut keys = self
+            .chromosomes
+            .iter()
+            .map(|(chrom, _)| chrom.as_str())
+            .collect::<Vec<_>>();
+        match &options.chrom_order {
+            Some(order) => {
+                // unlisted ones last, in natural order
+                crate::ord::sort_natural(&mut keys);
+                keys.sort_by_key(|k| order.rank(k).unwrap_or(usize::MAX));
+            }
+            None => options.chrom_sort.sort(&mut keys),
+        }
+        let position = keys
+            .into_iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), i))
+            .collect::<HashMap<_, _>>();
+        self.chromosomes
+            .sort_unstable_by_key(|(chrom, _)| position[chrom.as_str()]);
+    }
+
+    fn sort(&mut self) {
+        self.chromosomes
+            .sort_unstable_by(|a, b| natord::compare(&a.0, &b.0));
+        self.features
+            .sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    }
+}
+
+/// Bounds of the byte ranges [line_chunks] splits an input into.
+pub(crate) const MIN_PARSE_CHUNK: usize = 64 * 1024;
+pub(crate) const MAX_PARSE_CHUNK: usize = 16 * 1024 * 1024;
+
+/// Chunks per thread, so that a thread that drew chunks of unusually long lines (e.g.
+/// GFF3 records with huge `Ontology_term` attributes) is balanced out by the others.
+const CHUNKS_PER_THREAD: usize = 16;
+
+/// Bytes per chunk when parsing `len` bytes on `threads` threads.
+pub fn parse_chunk_size(len: usize, threads: usize) -> usize {
+    (len / (threads.max(1) * CHUNKS_PER_THREAD)).clamp(MIN_PARSE_CHUNK, MAX_PARSE_CHUNK)
+}
 
-    Ok(x)
+/// Splits `s` into consecutive ranges of whole lines of at least [parse_chunk_size]
+/// bytes (the last one may be shorter), to be parsed in parallel. Unlike splitting by
+/// lines, the work per range stays even when line lengths vary a lot.
+pub fn line_chunks(s: &str, threads: usize) -> Vec<&str> {
+    let size = parse_chunk_size(s.len(), threads);
+    let bytes = s.as_bytes();
+    let mut chunks = Vec::with_capacity(s.len() / size + 1);
+    let mut start = 0;
+    while start < s.len() {
+        let end = bytes
+            .get(start + size..)
+            .and_then(|rest| rest.iter().position(|b| *b == b'\n'))
+            .map_or(s.len(), |i| start + size + i + 1);
+        chunks.push(&s[start..end]);
+        start = end;
+    }
+    chunks
+}
+
+/// [line_chunks] of `s`, each with the byte offset in `s` it starts at.
+pub fn line_chunks_at(s: &str, threads: usize) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+    line_chunks(s, threads)
+        .into_iter()
+        .map(|chunk| {
+            offset += chunk.len();
+            (offset - chunk.len(), chunk)
+        })
+        .collect()
+}
+
+/// The lines of `chunk` as [str::lines] splits them, each with its byte offset for a
+/// `chunk` starting at `offset`.
+pub fn lines_at(chunk: &str, offset: usize) -> impl Iterator<Item = (usize, &str)> {
+    chunk.split_inclusive('\n').scan(offset, |offset, raw| {
+        *offset += raw.len();
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        Some((*offset - raw.len(), line.strip_suffix('\r').unwrap_or(line)))
+    })
+}
+
+pub fn parallel_parse<const SEP: u8>(s: &str) -> Result<ChromRecord<'_>, &'static str> {
+    parallel_parse_until::<SEP>(s, &Watchdog::disabled())
+}
+
+/// Same as [parallel_parse], but stops early (returning partial records) once `watchdog`
+/// tripped, the caller is expected to check it afterwards.
+pub fn parallel_parse_until<'a, const SEP: u8>(
+    s: &'a str,
+    watchdog: &Watchdog,
+) -> Result<ChromRecord<'a>, &'static str> {
+    parallel_parse_chroms::<SEP>(s, watchdog, None)
+}
+
+/// Same as [parallel_parse_until], but only parses the lines on the chromosomes selected
+/// by `chroms`, the rest are skipped by their first column.
+pub fn parallel_parse_chroms<'a, const SEP: u8>(
+    s: &'a str,
+    watchdog: &Watchdog,
+    chroms: Option<&ChromFilter>,
+) -> Result<ChromRecord<'a>, &'static str> {
+    parallel_parse_counted::<SEP>(s, watchdog, chroms).map(|(records, _)| records)
+}
+
+/// Same as [parallel_parse_chroms], also accounting for every line of `s`.
+pub fn parallel_parse_counted<'a, const SEP: u8>(
+    s: &'a str,
+    watchdog: &Watchdog,
+    chroms: Option<&ChromFilter>,
+) -> Result<(ChromRecord<'a>, LineCounts), &'static str> {
+    parallel_parse_tracked::<SEP>(s, watchdog, chroms, &PhaseProgress::disabled())
+}
+
+/// Same as [parallel_parse_counted], reporting the bytes of `s` parsed to `progress`.
+pub fn parallel_parse_tracked<'a, const SEP: u8>(
+    s: &'a str,
+    watchdog: &Watchdog,
+    chroms: Option<&ChromFilter>,
+    progress: &PhaseProgress,
+) -> Result<(ChromRecord<'a>, LineCounts), &'static str> {
+    let (mut x, mut counts) = line_chunks_at(s, current_num_threads())
+        .into_par_iter()
+        .fold(
+            || (ChromRecord::new(), LineCounts::default()),
+            |(mut acc, mut counts), (offset, chunk)| {
+                for (ordinal, line) in
+                    lines_at(chunk, offset).take_while(|_| watchdog.tripped().is_none())
+                {
+                    if !counts.count_non_record(line, chroms) {
+                        match Record::parse::<SEP>(line) {
+                            Ok(record) => {
+                                counts.records += 1;
+                                acc.entry(record.chrom)
+                                    .or_default()
+                                    .push(Record { ordinal, ..record });
+                            }
+                            Err(_) => counts.invalid += 1,
+                        }
+                    }
+                }
+                progress.add_bytes(chunk.len() as u64);
+                (acc, counts)
+            },
+        )
+        .reduce(
+            || (ChromRecord::new(), LineCounts::default()),
+            |(mut acc, counts), (map, n)| {
+                for (k, v) in map {
+                    acc.entry(k).or_default().extend(v);
+                }
+                (acc, counts.merge(n))
+            },
+        );
+
+    // keep each chromosome in input order whichever way the chunks were merged, the
+    // index relies on it to order children it cannot tell apart otherwise
+    x.par_iter_mut()
+        .for_each(|(_, records)| records.par_sort_unstable_by_key(|r| r.ordinal));
+
+    if SEP == b'=' {
+        let dropped = x
+            .par_iter_mut()
+            .map(|(chrom, records)| {
+                let dropped = crate::hierarchy::resolve_parents(records);
+                if dropped > 0 {
+                    log::warn!(
+                        "{} records on {} have no resolvable Parent and were skipped",
+                        dropped,
+                        chrom
+                    );
+                }
+                dropped
+            })
+            .sum::<usize>();
+        counts.records -= dropped;
+        counts.invalid += dropped;
+    }
+
+    Ok((x, counts))
+}
+
+/// User plus system CPU time consumed by the process so far, in seconds.
+#[cfg(not(windows))]
+pub fn process_cpu_secs() -> f64 {
+    let rusage = unsafe {
+        let mut rusage = std::mem::MaybeUninit::uninit();
+        if libc::getrusage(libc::RUSAGE_SELF, rusage.as_mut_ptr()) < 0 {
+            return f64::NAN;
+        }
+        rusage.assume_init()
+    };
+    let secs = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+    secs(rusage.ru_utime) + secs(rusage.ru_stime)
+}
+
+/// CPU time is not sampled on Windows.
+#[cfg(windows)]
+pub fn process_cpu_secs() -> f64 {
+    f64::NAN
 }
 
 #[cfg(not(windows))]
@@ -338,16 +1304,395 @@ pub fn max_mem_usage_mb() -> f64 {
     }
 }
 
+/// Returns the resident set size of the current process in MB.
+///
+/// Falls back to the peak resident set size on platforms where the current
+/// value is not cheaply available.
+#[cfg(target_os = "linux")]
+pub fn current_mem_usage_mb() -> f64 {
+    let statm = match std::fs::read_to_string("/proc/self/statm") {
+        Ok(s) => s,
+        Err(_) => return max_mem_usage_mb(),
+    };
+
+    let resident_pages = match statm.split_whitespace().nth(1).map(str::parse::<f64>) {
+        Some(Ok(p)) => p,
+        _ => return max_mem_usage_mb(),
+    };
+
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as f64;
+    resident_pages * page_size / 1024.0 / 1024.0
+}
+
+#[cfg(windows)]
+pub fn current_mem_usage_mb() -> f64 {
+    use windows::Win32::System::{
+        ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+        Threading::GetCurrentProcess,
+    };
+
+    unsafe {
+        let h_proc = GetCurrentProcess();
+
+        let mut pps = PROCESS_MEMORY_COUNTERS::default();
+        if GetProcessMemoryInfo(
+            h_proc,
+            &mut pps,
+            std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+        )
+        .is_err()
+        {
+            return f64::NAN;
+        }
+
+        pps.WorkingSetSize as f64 / 1024.0 / 1024.0
+    }
+}
+
+#[cfg(all(not(target_os = "linux"), not(windows)))]
+pub fn current_mem_usage_mb() -> f64 {
+    max_mem_usage_mb()
+}
+
+/// Parses a human readable duration (e.g. `250ms`, `90s`, `30m`, `2h`, `1d`).
+///
+/// Plain numbers are interpreted as seconds.
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+
+    let num: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration: {:?}", s))?;
+
+    let secs = match unit.trim().to_ascii_lowercase().as_str() {
+        "ms" => num / 1000.0,
+        "s" | "sec" => num,
+        "m" | "min" => num * 60.0,
+        "h" => num * 3600.0,
+        "d" => num * 86400.0,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in {:?}, use ms, s, m, h or d",
+                s
+            ))
+        }
+    };
+
+    if secs.is_finite() && secs > 0.0 {
+        Ok(std::time::Duration::from_secs_f64(secs))
+    } else {
+        Err(format!("duration must be positive, got {:?}", s))
+    }
+}
+
+/// Parses a human readable memory size (e.g. `512M`, `16G`, `2048`) into MB.
+///
+/// Plain numbers are interpreted as MB.
+pub fn parse_mem_size_mb(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "M"),
+    };
+
+    let num: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid memory size: {:?}", s))?;
+
+    let mb = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "K" => num / 1024.0,
+        "M" | "" => num,
+        "G" => num * 1024.0,
+        "T" => num * 1024.0 * 1024.0,
+        _ => return Err(format!("invalid memory unit in {:?}, use K, M, G or T", s)),
+    };
+
+    if mb.is_finite() && mb > 0.0 {
+        Ok(mb)
+    } else {
+        Err(format!("memory size must be positive, got {:?}", s))
+    }
+}
+
+/// Prints the banner to stderr, keeping stdout free for sorted output.
 pub fn msg() {
-    println!(
-        "{}\n{}\n{}",
+    eprintln!(
+        "{}\n\
+        The fastest chr/pos/feature GTF/GFF sorter you'll see.\n\
+        Repo: github.com/alejandrogzi/gtfsort\n\
+        Feel free to contact the developer if any issue/bug is found.\n\n\
+        Version: {}",
         "\n##### GTFSORT #####".bright_purple().bold(),
-        indoc!(
-            "The fastest chr/pos/feature GTF/GFF sorter you'll see.
-        Repo: github.com/alejandrogzi/gtfsort
-        Feel free to contact the developer if any issue/bug is found.
-        "
-        ),
-        format!("Version: {}", VERSION)
+        VERSION
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::sort_to_string;
+
+    #[test]
+    fn chunk_writer_splits_long_writes() {
+        let mut chunks = Vec::new();
+        let mut f = |b: &[u8]| {
+            chunks.push(b.len());
+            Ok(b.len())
+        };
+
+        let mut writer = ChunkWriter::with_chunk_size(&mut f, 4);
+        writer.write_all(b"0123456789").unwrap();
+
+        assert_eq!(chunks, vec![4, 4, 2]);
+    }
+
+    #[test]
+    fn line_chunks_end_at_line_breaks() {
+        assert_eq!(parse_chunk_size(0, 8), MIN_PARSE_CHUNK);
+        assert_eq!(parse_chunk_size(usize::MAX / 2, 1), MAX_PARSE_CHUNK);
+        assert!(line_chunks("", 4).is_empty());
+
+        // short lines mixed with lines longer than a chunk
+        let input = (0..2000)
+            .map(|i| match i % 100 {
+                0 => format!("chr1\t{}\n", "x".repeat(3 * MIN_PARSE_CHUNK / 2)),
+                _ => format!("chr1\t{}\n", i),
+            })
+            .collect::<String>()
+            + "last line without break";
+        let chunks = line_chunks(&input, 64);
+        assert!(chunks.len() > 10);
+        assert_eq!(chunks.concat(), input);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= MIN_PARSE_CHUNK);
+            assert!(chunk.ends_with('\n'));
+        }
+        assert_eq!(
+            chunks.iter().map(|c| c.lines().count()).sum::<usize>(),
+            input.lines().count()
+        );
+    }
+
+    #[test]
+    fn long_attribute_lines_are_streamed() {
+        let long_attr = "x".repeat(4 * 1024 * 1024);
+        let input = format!(
+            "chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\"; ont \"{}\";\n\
+             chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+             chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; ont \"{}\";\n",
+            long_attr, long_attr
+        );
+
+        let mut output = Vec::new();
+        let mut largest_chunk = 0;
+        crate::sort_annotations_string::<b' ', _>(
+            &input,
+            &mut |b| {
+                largest_chunk = largest_chunk.max(b.len());
+                output.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+        )
+        .unwrap();
+
+        assert!(largest_chunk <= DEFAULT_CHUNK_SIZE);
+        assert_eq!(output.len(), input.len());
+    }
+
+    #[test]
+    fn transcripts_sort_by_position_or_id() {
+        let mut layers = Layers::default();
+        layers.mapper.insert("A", vec!["T10", "T2", "T1", "A"]);
+        for (t, start, end) in [("T10", 50, 90), ("T2", 10, 40), ("T1", 10, 30)] {
+            let line = format!("chr1\ts\ttranscript\t{start}\t{end}\t.\t+\t.\tx");
+            layers.helper.insert(t, Cow::Owned(line));
+        }
+        // children hanging directly off the gene stand for a transcript without a line
+        layers.inner.entry("A").or_default().insert(
+            ChildKey::Unnumbered(0),
+            vec!["chr1\ts\texon\t20\t25\t.\t+\t.\tx"],
+        );
+
+        layers.sort_transcripts(TranscriptOrder::Position);
+        assert_eq!(layers.mapper["A"], ["T1", "T2", "A", "T10"]);
+        layers.sort_transcripts(TranscriptOrder::Id);
+        assert_eq!(layers.mapper["A"], ["A", "T1", "T2", "T10"]);
+        layers.sort_transcripts(TranscriptOrder::Input);
+        assert_eq!(layers.mapper["A"], ["A", "T1", "T2", "T10"]);
+    }
+
+    #[test]
+    fn equal_keys_keep_input_order() {
+        let input =
+            "chr1\ts\tSelenocysteine\t5\t7\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\r\n\
+            # comment\n\
+            chr1\ts\tSelenocysteine\t2\t4\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tSelenocysteine\t8\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n";
+        let records = &parallel_parse::<b' '>(input).unwrap()["chr1"];
+
+        let ordinals = records.iter().map(|r| r.ordinal).collect::<Vec<_>>();
+        let expected = input
+            .match_indices("chr1")
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        assert_eq!(ordinals, expected);
+        assert!(records.iter().all(|r| !r.line.ends_with('\r')));
+
+        let mut layers = Layers::default();
+        let children = layers.inner.entry("A.1").or_default();
+        for record in records {
+            children
+                .entry(record.inner_key(&crate::FeatureMap::default()))
+                .or_default()
+                .push(record.line);
+        }
+
+        layers.stabilize_inner();
+
+        let lines = layers.inner["A.1"]
+            .values()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        let expected = records.iter().map(|r| r.line).collect::<Vec<_>>();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn children_sharing_an_exon_number_are_kept() {
+        let input = "chr1\ts\tgene\t1\t100\t.\t-\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t100\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tCDS\t60\t70\t.\t-\t0\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+            chr1\ts\texon\t10\t70\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+            chr1\ts\tCDS\t10\t20\t.\t-\t2\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n";
+
+        let mut output = Vec::new();
+        crate::sort_annotations_string::<b' ', _>(
+            input,
+            &mut |b| {
+                output.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let children = output
+            .lines()
+            .skip(2)
+            .map(|l| {
+                let fields = l.split('\t').collect::<Vec<_>>();
+                (fields[2], fields[3])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(children, vec![("exon", "10"), ("CDS", "10"), ("CDS", "60")]);
+    }
+
+    #[test]
+    fn gff3_segments_are_grouped_by_id() {
+        let input = "chr1\ts\tgene\t1\t100\t.\t+\t.\tID=A;gene_id=A\n\
+            chr1\ts\ttranscript\t1\t100\t.\t+\t.\tID=A.1;Parent=A;gene_id=A;transcript_id=A.1\n\
+            chr1\ts\tCDS\t60\t70\t.\t+\t0\tID=cds:A.1;Parent=A.1;gene_id=A;transcript_id=A.1\n\
+            chr1\ts\tCDS\t30\t35\t.\t+\t0\tID=cds:B.1;Parent=A.1;gene_id=A;transcript_id=A.1\n\
+            chr1\ts\tCDS\t10\t20\t.\t+\t0\tID=cds:A.1;Parent=A.1;gene_id=A;transcript_id=A.1\n\
+            chr1\ts\tCDS\t40\t50\t.\t+\t0\tID=cds:A.1;Parent=A.1;gene_id=A;transcript_id=A.1\n";
+
+        let mut output = Vec::new();
+        crate::sort_annotations_string::<b'=', _>(
+            input,
+            &mut |b| {
+                output.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let cds = output
+            .lines()
+            .filter(|l| l.contains("\tCDS\t"))
+            .map(|l| line_coords(l).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(cds, [10, 40, 60, 30]);
+    }
+
+    #[test]
+    fn unnumbered_children_follow_numbered_ones() {
+        let input = "chr1\ts\tgene\t1\t100\t.\t-\t.\tgene_id \"A\";\n\
+            chr1\ts\ttranscript\t1\t100\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\tSelenocysteine\t50\t52\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t1\t20\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t80\t100\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+            chr1\ts\texon\t40\t60\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"10\";\n\
+            chr1\ts\texon\t30\t35\t.\t-\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\";\n";
+
+        let sort = |order| {
+            let output = sort_to_string::<b' '>(
+                input,
+                &crate::SortOptions {
+                    unnumbered_order: order,
+                    ..Default::default()
+                },
+            );
+            output
+                .lines()
+                .skip(2)
+                .map(|l| line_coords(l).unwrap().0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sort(UnnumberedOrder::Input), [30, 40, 1, 80, 50]);
+        assert_eq!(sort(UnnumberedOrder::Coordinate), [30, 40, 1, 80, 50]);
+        assert_eq!(sort(UnnumberedOrder::Transcription), [30, 40, 80, 1, 50]);
+    }
+
+    #[test]
+    fn genes_follow_the_sort_key() {
+        let gene = |id: &str, start: u32, end: u32, attrs: &[&str]| {
+            let mut s = format!("chr1\ts\tgene\t{start}\t{end}\t.\t+\t.\tgene_id \"{id}\";\n");
+            for (i, attr) in attrs.iter().enumerate() {
+                s += &format!(
+                    "chr1\ts\ttranscript\t{start}\t{end}\t.\t+\t.\tgene_id \"{id}\"; transcript_id \"{id}.{i}\"; {attr};\n\
+                    chr1\ts\texon\t{start}\t{end}\t.\t+\t.\tgene_id \"{id}\"; transcript_id \"{id}.{i}\"; exon_number \"1\";\n"
+                );
+            }
+            s
+        };
+        let input = [
+            gene("A", 100, 200, &["TPM \"1.5\""]),
+            gene("B", 10, 50, &["TPM \"0.5\"", "TPM \"30\""]),
+            gene("C", 300, 900, &["TPM \"NA\""]),
+            gene("D", 1000, 1100, &["TPM \"7\""]),
+        ]
+        .concat();
+
+        let genes = |key: &str| {
+            let output = sort_to_string::<b' '>(
+                &input,
+                &crate::SortOptions {
+                    gene_sort_key: key.parse().unwrap(),
+                    ..Default::default()
+                },
+            );
+            output
+                .lines()
+                .filter(|l| l.contains("\tgene\t"))
+                .map(|l| l[l.len() - 3..l.len() - 2].to_string())
+                .collect::<String>()
+        };
+
+        assert_eq!(genes("start"), "BACD");
+        assert_eq!(genes("length"), "CADB");
+        assert_eq!(genes("attr:TPM"), "BDAC");
+        assert!("attr:".parse::<GeneSortKey>().is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Re-validation of the sorted lines before they are written, see
+//! [crate::SortOptions::validate_output].
+//!
+//! The parser only needs the columns it sorts by, so a line with an empty column or a
+//! start after its end goes through unnoticed, as do lines rewritten on the way (gene
+//! bounds, synthesized parents, deduplicated attributes). Checking the index right
+//! before writing reports them where the cause is still known.
+
+use std::{fmt, str::FromStr};
+
+use crate::{error, par::*, ErrorContext, GtfSortError, Index, Layers};
+
+/// Number of malformed lines reported in full, the rest are only counted.
+pub(crate) const MAX_REPORTED_LINES: usize = 10;
+
+/// What to do with a malformed output line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ValidatePolicy {
+    /// Fail the job, reporting the first malformed lines.
+    #[default]
+    Error,
+    /// Leave the line out. A malformed gene or transcript line drops its whole block.
+    Drop,
+    /// Write the line anyway, warning about it.
+    Keep,
+}
+
+impl FromStr for ValidatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "drop" => Ok(Self::Drop),
+            "keep" => Ok(Self::Keep),
+            _ => Err(format!(
+                "unknown validation policy {:?}, expected error, drop or keep",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ValidatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Drop => "drop",
+            Self::Keep => "keep",
+        })
+    }
+}
+
+/// Checks that `line` has 9 tab-separated columns, the first 8 not empty, and numeric
+/// start and end with start <= end. Tabs inside the attribute column are accepted, as
+/// by the parser.
+pub fn check_line(line: &str) -> Result<(), &'static str> {
+    let mut fields = line.splitn(9, '\t');
+    let (mut start, mut end) = ("", "");
+    for i in 0..9 {
+        match fields.next() {
+            None => return Err("expected 9 tab-separated columns"),
+            Some("") if i < 8 => return Err("empty column"),
+            Some(f) if i == 3 => start = f,
+            Some(f) if i == 4 => end = f,
+            Some(_) => {}
+        }
+    }
+
+    let start = start.parse::<u32>().map_err(|_| "start is not a number")?;
+    let end = end.parse::<u32>().map_err(|_| "end is not a number")?;
+    if start > end {
+        return Err("start is after end");
+    }
+
+    Ok(())
+}
+
+/// A line that failed [check_line].
+#[derive(Debug, Clone)]
+pub struct MalformedLine {
+    pub chrom: String,
+    /// 1-based line number in the input, None for lines not taken verbatim from it
+    /// (rewritten or synthesized lines, or windowed input).
+    pub input_line: Option<usize>,
+    /// Offset of the line in the input, in bytes, known when `input_line` is.
+    pub byte_offset: Option<u64>,
+    pub reason: &'static str,
+    pub line: String,
+}
+
+impl fmt::Display for MalformedLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.input_line {
+            Some(n) => write!(f, "line {} ({}): {}: ", n, self.chrom, self.reason)?,
+            None => write!(f, "{}: {}: ", self.chrom, self.reason)?,
+        }
+        match self.line.char_indices().nth(120) {
+            Some((i, _)) => write!(f, "{}...", &self.line[..i]),
+            None => f.write_str(&self.line),
+        }
+    }
+}
+
+/// Malformed lines found by [validate_index].
+#[derive(Debug, Default, Clone)]
+pub struct ValidationReport {
+    /// Number of malformed lines, dropped lines included.
+    pub malformed: usize,
+    /// The first [MAX_REPORTED_LINES] malformed lines, in input order where known.
+    pub lines: Vec<MalformedLine>,
+}
+
+impl ValidationReport {
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        self.malformed += other.malformed;
+        self.lines.extend(other.lines);
+        self.lines
+            .sort_by_key(|l| (l.input_line.is_none(), l.input_line, l.chrom.clone()));
+        self.lines.truncate(MAX_REPORTED_LINES);
+        self
+    }
+
+    fn push(&mut self, chrom: &str, input: Option<&str>, line: &str, reason: &'static str) {
+        self.malformed += 1;
+        if self.lines.len() < MAX_REPORTED_LINES {
+            let location = input.and_then(|input| error::locate(input, line));
+            self.lines.push(MalformedLine {
+                chrom: chrom.to_string(),
+                input_line: location.map(|(n, _)| n),
+                byte_offset: location.map(|(_, offset)| offset as u64),
+                reason,
+                line: line.to_string(),
+            });
+        }
+    }
+
+    /// Logs the report, failing under [ValidatePolicy::Error] if a line is malformed.
+    pub fn finish(&self, policy: ValidatePolicy) -> Result<(), GtfSortError> {
+        if self.malformed == 0 {
+            return Ok(());
+        }
+
+        let listed = self
+            .lines
+            .iter()
+            .map(|l| format!("\n  {}", l))
+            .collect::<String>();
+        let more = match self.malformed - self.lines.len() {
+            0 => String::new(),
+            n => format!("\n  ... and {} more", n),
+        };
+
+        match policy {
+            ValidatePolicy::Error => {
+                // the first line listed, to point bindings at a record
+                let first = &self.lines[0];
+                let context = ErrorContext {
+                    line: first.input_line,
+                    byte_offset: first.byte_offset,
+                    ..ErrorContext::phase("validating output").with_chrom(&first.chrom)
+                };
+                Err(GtfSortError::InvalidInput(format!(
+                    "{} output lines are malformed, use --validate-output drop or keep to write the rest anyway:{}{}",
+                    self.malformed, listed, more
+                ))
+                .with_context(context))
+            }
+            ValidatePolicy::Drop => {
+                log::warn!(
+                    "{} malformed output lines were dropped:{}{}",
+                    self.malformed,
+                    listed,
+                    more
+                );
+                Ok(())
+            }
+            ValidatePolicy::Keep => {
+                log::warn!(
+                    "{} output lines are malformed and were written anyway:{}{}",
+                    self.malformed,
+                    listed,
+                    more
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Checks every line `index` would write with [check_line], dropping malformed lines
+/// (and the blocks of malformed gene and transcript lines) under [ValidatePolicy::Drop].
+///
+/// `input` is the buffer the lines were parsed from, used to report line numbers. Genes
+/// copied from a previous output are not checked.
+pub fn validate_index(
+    index: &mut Index,
+    policy: ValidatePolicy,
+    input: Option<&str>,
+) -> ValidationReport {
+    index
+        .par_iter_mut()
+        .map(|(chrom, layers)| validate_layers(chrom, layers, policy, input))
+        .reduce(ValidationReport::default, ValidationReport::merge)
+}
+
+fn validate_layers(
+    chrom: &str,
+    layers: &mut Layers,
+    policy: ValidatePolicy,
+    input: Option<&str>,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let drop = policy == ValidatePolicy::Drop;
+    let Layers {
+        layer,
+        mapper,
+        inner,
+        helper,
+        reused,
+        ..
+    } = layers;
+
+    layer.retain(|(_, gene_id, line)| {
+        if reused.contains_key(gene_id) {
+            return true;
+        }
+        let mut keep = true;
+        if let Err(reason) = check_line(line) {
+            report.push(chrom, input, line, reason);
+            keep = !drop;
+        }
+
+        let Some(transcripts) = mapper.get_mut(gene_id) else {
+            return keep;
+        };
+        transcripts.retain(|t| {
+            let mut keep_transcript = true;
+            if let Some(line) = helper.get(t) {
+                if let Err(reason) = check_line(line) {
+                    report.push(chrom, input, line, reason);
+                    keep_transcript = !drop;
+                }
+            }
+            for lines in inner.get_mut(t).into_iter().flat_map(|c| c.values_mut()) {
+                lines.retain(|line| match check_line(line) {
+                    Ok(()) => true,
+                    Err(reason) => {
+                        report.push(chrom, input, line, reason);
+                        !drop
+                    }
+                });
+            }
+            keep_transcript
+        });
+
+        keep
+    });
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, SortOptions};
+
+    const GTF: &str = "chr1\ts\tgene\t1\t90\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t1\t10\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\texon\t50\t30\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"2\";\n\
+        chr1\ts\texon\t60\t90\t.\t\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"3\";\n";
+
+    fn sort(policy: ValidatePolicy) -> Result<String, GtfSortError> {
+        let mut out = Vec::new();
+        sort_annotations_string_with_options::<b' ', _>(
+            GTF,
+            &mut |b| {
+                out.extend_from_slice(b);
+                Ok(b.len())
+            },
+            1,
+            &SortOptions {
+                validate_output: Some(policy),
+                ..Default::default()
+            },
+        )?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn checks_columns_and_coordinates() {
+        assert!(check_line(GTF.lines().next().unwrap()).is_ok());
+        assert_eq!(
+            check_line("chr1\ts\texon\t1\t9\t.\t+\t."),
+            Err("expected 9 tab-separated columns")
+        );
+        assert_eq!(
+            check_line("chr1\ts\texon\tx\t9\t.\t+\t.\tgene_id \"A\";"),
+            Err("start is not a number")
+        );
+        assert_eq!(
+            check_line("chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\";\textra"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn policies_fail_drop_or_keep_malformed_lines() {
+        let err = sort(ValidatePolicy::Error).unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::InvalidInput);
+        let context = err.context();
+        assert_eq!(context.phase, Some("validating output"));
+        assert_eq!(context.chrom.as_deref(), Some("chr1"));
+        assert_eq!(context.line, Some(4));
+        assert_eq!(
+            context.byte_offset,
+            Some(GTF.lines().take(3).map(|l| l.len() as u64 + 1).sum())
+        );
+        let err = err.to_string();
+        assert!(err.contains("2 output lines are malformed"), "{}", err);
+        assert!(err.contains("line 4 (chr1): start is after end"), "{}", err);
+        assert!(err.contains("line 5 (chr1): empty column"), "{}", err);
+
+        assert_eq!(
+            sort(ValidatePolicy::Drop).unwrap(),
+            GTF.lines()
+                .take(3)
+                .map(|l| format!("{}\n", l))
+                .collect::<String>()
+        );
+        assert_eq!(sort(ValidatePolicy::Keep).unwrap().lines().count(), 5);
+    }
+}
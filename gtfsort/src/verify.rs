@@ -0,0 +1,301 @@
+//! Check that every input record made it to the output, see [crate::SortOptions::verify].
+//!
+//! The records of each feature type are counted as parsed and again once the output is
+//! written: read back from the output file when there is one, or else from the lines
+//! handed to the writer. Lines synthesized by gtfsort (see [crate::synth]) are counted
+//! apart, so a sort that only adds parents still verifies.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use crate::{
+    gzip, json,
+    synth::SYNTHESIZED_TAG,
+    utils::{ChromRecord, Index},
+    GtfSortError, Layers, SortOptions,
+};
+
+/// Number of records of each feature type.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureCounts {
+    pub features: BTreeMap<String, usize>,
+    /// Lines flagged with [SYNTHESIZED_TAG], not counted in `features`.
+    pub synthesized: usize,
+}
+
+impl FeatureCounts {
+    /// Counts the parsed records of one or more chromosomes.
+    pub fn from_records(records: &ChromRecord) -> Self {
+        let mut counts = Self::default();
+        for record in records.values().flatten() {
+            counts.add_record(record.line, record.feat);
+        }
+        counts
+    }
+
+    /// Counts the lines `index` writes, as [Layers::write_gene] writes them.
+    pub fn from_index(index: &Index) -> Self {
+        let mut counts = Self::default();
+        index.values().for_each(|layers| counts.add_layers(layers));
+        counts
+    }
+
+    /// Counts the record lines of a written output, decompressed if it is gzipped,
+    /// skipping comments and blank lines.
+    pub fn from_output(path: &Path) -> Result<Self, GtfSortError> {
+        let mut counts = Self::default();
+        for line in BufReader::new(gzip::open(path)?).lines() {
+            let line =
+                line.map_err(|e| GtfSortError::IoError("reading back output for verification", e))?;
+            counts.add_line(&line);
+        }
+        Ok(counts)
+    }
+
+    pub fn records(&self) -> usize {
+        self.features.values().sum()
+    }
+
+    /// Adds the counts of `other`, e.g. those of another chromosome.
+    pub fn merge(&mut self, other: FeatureCounts) {
+        for (feat, n) in other.features {
+            *self.features.entry(feat).or_default() += n;
+        }
+        self.synthesized += other.synthesized;
+    }
+
+    fn add_layers(&mut self, layers: &Layers) {
+        for (_, gene_id, line) in layers.layer.iter() {
+            if let Some(block) = layers.reused.get(gene_id) {
+                block.lines().for_each(|l| self.add_line(l));
+                continue;
+            }
+            self.add_line(line);
+            for transcript_id in layers.transcripts(gene_id) {
+                if let Some(line) = layers.helper.get(transcript_id) {
+                    self.add_line(line);
+                }
+                layers
+                    .children(transcript_id)
+                    .for_each(|l| self.add_line(l));
+            }
+        }
+    }
+
+    fn add_line(&mut self, line: &str) {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            return;
+        }
+        self.add_record(line, line.split('\t').nth(2).unwrap_or_default());
+    }
+
+    /// Counts a record line of feature `feat`, or as synthesized if it is flagged, the
+    /// same way for input and output so that a sorted output verifies when sorted again.
+    fn add_record(&mut self, line: &str, feat: &str) {
+        if line.contains(SYNTHESIZED_TAG) {
+            self.synthesized += 1;
+            return;
+        }
+        match self.features.get_mut(feat) {
+            Some(n) => *n += 1,
+            None => {
+                self.features.insert(feat.to_string(), 1);
+            }
+        }
+    }
+}
+
+/// Input and output record counts of a job run with [crate::SortOptions::verify].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verification {
+    pub input: FeatureCounts,
+    pub output: FeatureCounts,
+}
+
+impl Verification {
+    pub fn new(input: FeatureCounts, output: FeatureCounts) -> Self {
+        Self { input, output }
+    }
+
+    /// Feature types whose number of records differs, as (feature, input, output), then
+    /// ("synthesized", input, output) if synthesized input lines were lost. A job may
+    /// synthesize more lines than its input had.
+    pub fn differences(&self) -> Vec<(&str, usize, usize)> {
+        let (input, output) = (&self.input.features, &self.output.features);
+        let mut feats = input.keys().chain(output.keys()).collect::<Vec<_>>();
+        feats.sort_unstable();
+        feats.dedup();
+        let mut differences = feats
+            .into_iter()
+            .map(|feat| {
+                let n = |counts: &BTreeMap<String, usize>| counts.get(feat).copied();
+                (feat.as_str(), n(input).unwrap_or(0), n(output).unwrap_or(0))
+            })
+            .filter(|(_, i, o)| i != o)
+            .collect::<Vec<_>>();
+        if self.output.synthesized < self.input.synthesized {
+            differences.push((
+                "synthesized",
+                self.input.synthesized,
+                self.output.synthesized,
+            ));
+        }
+        differences
+    }
+
+    /// Fails if the output does not hold every input record, exactly once.
+    pub fn check(&self) -> Result<(), GtfSortError> {
+        if self.differences().is_empty() {
+            log::info!("Verified output: {}", self);
+            return Ok(());
+        }
+        Err(GtfSortError::VerificationFailed(self.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        fn histogram(s: &mut String, counts: &FeatureCounts) {
+            write!(
+                s,
+                "{{\"records\":{},\"synthesized\":{},\"features\":{{",
+                counts.records(),
+                counts.synthesized
+            )
+            .unwrap();
+            for (i, (feat, n)) in counts.features.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
+                }
+                json::string(s, feat);
+                write!(s, ":{}", n).unwrap();
+            }
+            s.push_str("}}");
+        }
+
+        let mut s = String::from("{\"input\":");
+        histogram(&mut s, &self.input);
+        s.push_str(",\"output\":");
+        histogram(&mut s, &self.output);
+        write!(s, ",\"ok\":{}}}", self.differences().is_empty()).unwrap();
+        s
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} input records, {} output records ({} synthesized lines besides)",
+            self.input.records(),
+            self.output.records(),
+            self.output.synthesized
+        )?;
+        for (feat, input, output) in self.differences() {
+            write!(f, "; {}: {} in, {} out", feat, input, output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fails if `options` leave records out on purpose, so the output cannot be verified.
+pub fn check_options(options: &SortOptions) -> Result<(), GtfSortError> {
+    if options.verify
+        && (options.genes.is_some()
+            || options.transcripts.is_some()
+            || options.features.is_some()
+            || options.gene_biotypes.is_some()
+            || options.region.is_some()
+            || options.head_genes.is_some()
+            || options.skeleton
+            || options.coordinate_only
+            || options.export.is_some())
+    {
+        return Err(GtfSortError::InvalidParameter(
+            "output verification cannot be combined with gene/transcript/feature/biotype/region selection, skeletons, coordinate-only sorting or exports",
+        ));
+    }
+    Ok(())
+}
+
+/// Counts the output of a job, read back from `path` if it is a regular file or else
+/// from the lines of `index`.
+pub fn output_counts(
+    path: Option<&Path>,
+    index: impl FnOnce() -> FeatureCounts,
+) -> Result<FeatureCounts, GtfSortError> {
+    match path.filter(|p| std::fs::metadata(p).is_ok_and(|m| m.is_file())) {
+        Some(path) => FeatureCounts::from_output(path),
+        None => Ok(index()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sort_annotations_string_with_options, test_utils::sort_to_string, ErrorCode};
+
+    const GTF: &str = "chr1\ts\tgene\t10\t90\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\texon\t10\t20\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\";\n\
+        chr1\ts\ttranscript\t10\t90\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t30\t40\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n";
+
+    fn sort(options: &SortOptions) -> Result<Option<Verification>, GtfSortError> {
+        sort_annotations_string_with_options::<b' ', _>(GTF, &mut |b| Ok(b.len()), 1, options)
+            .map(|ret| ret.verification)
+    }
+
+    #[test]
+    fn lost_records_fail_verification() {
+        let options = SortOptions {
+            verify: true,
+            ..Default::default()
+        };
+        // the exon of B has no gene line and is left out
+        let err = sort(&options).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::VerificationFailed);
+        assert!(err.to_string().contains("exon: 2 in, 1 out"), "{}", err);
+
+        let verification = sort(&SortOptions {
+            synthesize_parents: true,
+            ..options
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(verification.input.records(), 4);
+        assert_eq!(verification.output.records(), 4);
+        assert_eq!(verification.output.synthesized, 2);
+        assert!(verification.to_json().ends_with(",\"ok\":true}"));
+
+        assert_eq!(sort(&SortOptions::default()).unwrap(), None);
+    }
+
+    #[test]
+    fn synthesized_output_verifies_when_sorted_again() {
+        let synthesized = sort_to_string::<b' '>(
+            GTF,
+            &SortOptions {
+                synthesize_parents: true,
+                ..Default::default()
+            },
+        );
+
+        let ret = sort_annotations_string_with_options::<b' ', _>(
+            &synthesized,
+            &mut |b| Ok(b.len()),
+            1,
+            &SortOptions {
+                verify: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let verification = ret.verification.unwrap();
+        assert_eq!(verification.input.records(), 4);
+        assert_eq!(verification.input.synthesized, 2);
+        assert_eq!(verification.output, verification.input);
+    }
+}
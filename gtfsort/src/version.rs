@@ -0,0 +1,120 @@
+//! Version and capabilities of this build, for pipelines and bug reports to record what
+//! exactly ran. See [to_json].
+
+use std::fmt::Write as _;
+
+use crate::{export::ExportFormat, export::SafLevel, json, AnnotationFormat};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit the crate was built from, `-dirty` if tracked files had changes, or `unknown`
+/// outside a git checkout.
+pub const GIT_COMMIT: &str = env!("GTFSORT_GIT_COMMIT");
+
+/// Target triple of the build, e.g. `x86_64-unknown-linux-gnu`.
+pub const TARGET: &str = env!("GTFSORT_TARGET");
+
+/// `debug` or `release`.
+pub const PROFILE: &str = env!("GTFSORT_PROFILE");
+
+/// Annotation formats read and written.
+pub const ANNOTATION_FORMATS: [AnnotationFormat; 2] =
+    [AnnotationFormat::Gtf, AnnotationFormat::Gff3];
+
+/// Formats of [crate::SortOptions::export].
+pub const EXPORT_FORMATS: [ExportFormat; 4] = [
+    ExportFormat::Saf(SafLevel::Exon),
+    ExportFormat::Saf(SafLevel::Gene),
+    ExportFormat::RefFlat,
+    ExportFormat::Bed12,
+];
+
+/// Cargo features compiled in, every entry of the `[features]` table that is enabled
+/// (`default` aside), sorted by name.
+pub fn features() -> Vec<&'static str> {
+    env!("GTFSORT_FEATURES")
+        .split(',')
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+/// Everything above as one JSON object.
+pub fn to_json() -> String {
+    let mut s = String::with_capacity(256);
+    s.push_str("{\"name\":\"gtfsort\"");
+    for (key, value) in [
+        ("version", VERSION),
+        ("git_commit", GIT_COMMIT),
+        ("target", TARGET),
+        ("profile", PROFILE),
+    ] {
+        write!(s, ",\"{}\":", key).unwrap();
+        json::string(&mut s, value);
+    }
+    s.push_str(",\"features\":");
+    json::strings(&mut s, features());
+    s.push_str(",\"input_formats\":");
+    json::strings(&mut s, ANNOTATION_FORMATS.map(|f| f.to_string()));
+    s.push_str(",\"output_formats\":");
+    json::strings(
+        &mut s,
+        ANNOTATION_FORMATS
+            .iter()
+            .map(ToString::to_string)
+            .chain(EXPORT_FORMATS.iter().map(ToString::to_string)),
+    );
+    s.push_str(",\"line_compression\":\"lz4\"}");
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lists_the_build() {
+        let json = to_json();
+        assert!(json.starts_with(&format!(
+            "{{\"name\":\"gtfsort\",\"version\":\"{}\",\"git_commit\":\"",
+            VERSION
+        )));
+        assert!(json.contains(
+            "\"input_formats\":[\"gtf\",\"gff3\"],\"output_formats\":[\"gtf\",\"gff3\",\"saf\",\"saf-gene\",\"refflat\",\"bed12\"]"
+        ));
+        assert_eq!(
+            json.contains("\"mmap\""),
+            cfg!(feature = "mmap"),
+            "{}",
+            json
+        );
+        assert!(!GIT_COMMIT.is_empty() && !TARGET.is_empty());
+    }
+
+    #[test]
+    fn features_come_from_the_manifest() {
+        let manifest = include_str!("../Cargo.toml");
+        let declared = manifest
+            .split("[features]")
+            .nth(1)
+            .unwrap()
+            .lines()
+            .take_while(|l| !l.starts_with('['))
+            .filter_map(|l| l.split_once(" = ").map(|(k, _)| k))
+            .collect::<Vec<_>>();
+        assert!(declared.contains(&"ffi_abort"), "{:?}", declared);
+
+        let features = features();
+        assert!(
+            features.iter().all(|f| declared.contains(f)),
+            "{:?}",
+            features
+        );
+        for (name, enabled) in [
+            ("parallel", cfg!(feature = "parallel")),
+            ("testing", cfg!(feature = "testing")),
+            ("serde_json", cfg!(feature = "serde_json")),
+        ] {
+            assert_eq!(features.contains(&name), enabled, "{}", name);
+        }
+    }
+}
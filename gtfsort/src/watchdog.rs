@@ -0,0 +1,189 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{current_mem_usage_mb, GtfSortError};
+
+/// How often the watchdog samples the resident set size and the clock.
+pub const WATCHDOG_SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The reason a watchdog asked the running job to stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogTrip {
+    /// Resident memory went above the configured limit: (used_mb, limit_mb).
+    MemoryLimit(f64, f64),
+    /// The job ran longer than the configured timeout, in seconds.
+    Timeout(f64),
+}
+
+impl From<WatchdogTrip> for GtfSortError {
+    fn from(t: WatchdogTrip) -> Self {
+        match t {
+            WatchdogTrip::MemoryLimit(used, limit) => {
+                GtfSortError::MemoryLimitExceeded(used, limit)
+            }
+            WatchdogTrip::Timeout(secs) => GtfSortError::Timeout(secs),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    stop: AtomicBool,
+    trip: OnceLock<WatchdogTrip>,
+}
+
+/// A lightweight sampler thread that watches the process while a job runs.
+///
+/// The watchdog never interrupts the job by itself, the pipeline is expected to call
+/// [Watchdog::check] at convenient points and bail out with the returned error.
+#[derive(Debug)]
+pub struct Watchdog {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Creates a watchdog that never trips.
+    pub fn disabled() -> Self {
+        Self {
+            shared: Arc::new(Shared::default()),
+            handle: None,
+        }
+    }
+
+    /// Spawns a watchdog tripping once the resident set size goes above `max_rss_mb`
+    /// or once `timeout` has elapsed.
+    ///
+    /// If neither is set, no thread is spawned and the watchdog never trips.
+    pub fn spawn(max_rss_mb: Option<f64>, timeout: Option<Duration>) -> Self {
+        if max_rss_mb.is_none() && timeout.is_none() {
+            return Self::disabled();
+        }
+
+        let shared = Arc::new(Shared::default());
+        let deadline = timeout.map(|t| (Instant::now() + t, t.as_secs_f64()));
+
+        let sample = move || -> Option<WatchdogTrip> {
+            if let Some(limit_mb) = max_rss_mb {
+                let used_mb = current_mem_usage_mb();
+                if used_mb > limit_mb {
+                    log::debug!("watchdog: rss {:.1} MB > limit {:.1} MB", used_mb, limit_mb);
+                    return Some(WatchdogTrip::MemoryLimit(used_mb, limit_mb));
+                }
+            }
+
+            match deadline {
+                Some((at, secs)) if Instant::now() >= at => Some(WatchdogTrip::Timeout(secs)),
+                _ => None,
+            }
+        };
+
+        if let Some(trip) = sample() {
+            let _ = shared.trip.set(trip);
+            return Self {
+                shared,
+                handle: None,
+            };
+        }
+
+        let handle = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("gtfsort-watchdog".to_string())
+                .spawn(move || {
+                    while !shared.stop.load(Ordering::Acquire) {
+                        if let Some(trip) = sample() {
+                            let _ = shared.trip.set(trip);
+                            break;
+                        }
+                        std::thread::park_timeout(WATCHDOG_SAMPLE_INTERVAL);
+                    }
+                })
+        };
+
+        match handle {
+            Ok(handle) => Self {
+                shared,
+                handle: Some(handle),
+            },
+            Err(e) => {
+                log::warn!(
+                    "failed to spawn watchdog thread, memory limit and timeout disabled: {}",
+                    e
+                );
+                Self::disabled()
+            }
+        }
+    }
+
+    /// Returns the reason the watchdog tripped, if any.
+    #[inline(always)]
+    pub fn tripped(&self) -> Option<WatchdogTrip> {
+        self.shared.trip.get().copied()
+    }
+
+    /// Returns an error if the watchdog tripped.
+    #[inline(always)]
+    pub fn check(&self) -> Result<(), GtfSortError> {
+        match self.tripped() {
+            Some(t) => Err(t.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_never_trips() {
+        let w = Watchdog::spawn(None, None);
+        std::thread::sleep(WATCHDOG_SAMPLE_INTERVAL * 2);
+        assert!(w.check().is_ok());
+    }
+
+    #[test]
+    fn trips_on_tiny_limit() {
+        let w = Watchdog::spawn(Some(f64::MIN_POSITIVE), None);
+        for _ in 0..100 {
+            if w.tripped().is_some() {
+                break;
+            }
+            std::thread::sleep(WATCHDOG_SAMPLE_INTERVAL);
+        }
+        assert!(matches!(
+            w.check(),
+            Err(GtfSortError::MemoryLimitExceeded(_, _))
+        ));
+    }
+
+    #[test]
+    fn trips_on_timeout() {
+        let w = Watchdog::spawn(None, Some(WATCHDOG_SAMPLE_INTERVAL * 4));
+        assert!(w.check().is_ok());
+        for _ in 0..100 {
+            if w.tripped().is_some() {
+                break;
+            }
+            std::thread::sleep(WATCHDOG_SAMPLE_INTERVAL);
+        }
+        assert!(matches!(w.check(), Err(GtfSortError::Timeout(_))));
+    }
+}
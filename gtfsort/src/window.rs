@@ -0,0 +1,334 @@
+//! An alternative input backend for inputs too large to map at once: the file is read
+//! through memory-mapped windows of bounded size, aligned to line boundaries, and sorted
+//! one chromosome at a time.
+//!
+//! A first pass walks the windows and records where the lines of each chromosome are;
+//! the second pass copies the lines of one chromosome at a time into memory, sorts them
+//! and appends them to the output. Virtual memory stays within a window plus the largest
+//! chromosome, which matters on 32-bit systems, and the smaller mappings ease TLB
+//! pressure on machines without huge pages.
+
+use hashbrown::HashMap;
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    bucketed::{self, ChromKeep},
+    format::AnnotationFormat,
+    invalid::{self, ParseMode},
+    mmap::{self, Madvice, MemoryMap},
+    timed, ErrorContext, GtfSortError, Header, LineCounts, OutputTarget, Phase, PhaseProgress,
+    ResourceSampler, SortAnnotationsJobResult, SortOptions, Watchdog,
+};
+
+/// Byte ranges of the lines of one chromosome, runs of consecutive lines merged.
+type Runs = Vec<(u64, u64)>;
+
+/// A read-only mapping of `len` bytes at any `offset` of a file.
+struct Window<'a> {
+    map: MemoryMap<'a, u8>,
+    skip: usize,
+}
+
+impl<'a> Window<'a> {
+    fn open(file: &'a File, offset: u64, len: usize) -> io::Result<Self> {
+        let aligned = offset - offset % mmap::allocation_granularity() as u64;
+        let skip = (offset - aligned) as usize;
+
+        #[cfg(unix)]
+        let map = unsafe { MemoryMap::from_file_at(file, aligned, skip + len)? };
+        #[cfg(windows)]
+        let map = unsafe { MemoryMap::from_handle_at(file, aligned, skip + len)? };
+
+        if let Err(e) = map.madvise(&[Madvice::Sequential]) {
+            log::debug!("madvise on input window: {}", e);
+        }
+
+        Ok(Self { map, skip })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.map.as_slice()[self.skip..]
+    }
+}
+
+/// Walks `file` in windows of about `window` bytes, each ending at a line boundary (a
+/// window grows if a single line does not fit), calling `f` with the offset and the
+/// contents of each.
+fn for_each_window<F>(file: &File, size: u64, window: usize, mut f: F) -> Result<(), GtfSortError>
+where
+    F: FnMut(u64, &str) -> Result<(), GtfSortError>,
+{
+    let mut start = 0;
+    let mut len = window.max(1);
+
+    while start < size {
+        let end = (start + len as u64).min(size);
+        let w = Window::open(file, start, (end - start) as usize)
+            .map_err(|e| GtfSortError::IoError("mapping input window", e))?;
+        let bytes = w.as_bytes();
+
+        let take = match bytes.iter().rposition(|b| *b == b'\n') {
+            _ if end == size => bytes.len(),
+            Some(i) => i + 1,
+            None => {
+                len *= 2;
+                continue;
+            }
+        };
+
+        let text = std::str::from_utf8(&bytes[..take]).map_err(|e| {
+            GtfSortError::InvalidInput("input is not valid UTF-8".to_string()).with_context(
+                ErrorContext {
+                    byte_offset: Some(start + e.valid_up_to() as u64),
+                    ..ErrorContext::phase("reading input")
+                },
+            )
+        })?;
+        f(start, text)?;
+
+        start += take as u64;
+        len = window.max(1);
+    }
+
+    Ok(())
+}
+
+/// Records the byte ranges of the lines of each chromosome, skipping blank lines and
+/// comments. `n` is the number of lines before `text`. With a `strict` format the record
+/// lines are checked, failing at the first invalid one (see [ParseMode::Strict]).
+fn bucket_lines(
+    buckets: &mut HashMap<String, Runs>,
+    counts: &mut LineCounts,
+    chroms: &mut ChromKeep,
+    strict: Option<AnnotationFormat>,
+    n: &mut usize,
+    offset: u64,
+    text: &str,
+) -> Result<(), GtfSortError> {
+    let mut pos = offset;
+    for line in text.split_inclusive('\n') {
+        let next = pos + line.len() as u64;
+        let content = line.trim_end_matches(['\n', '\r']);
+        *n += 1;
+
+        if !counts.count_non_record(content, None) {
+            let chrom = content.split('\t').next().unwrap_or_default();
+            if !chroms.keep(chrom) {
+                counts.skipped += 1;
+                pos = next;
+                continue;
+            }
+            if let Some(reason) = strict.and_then(|f| invalid::check_line(f, content)) {
+                let context = ErrorContext {
+                    line: Some(*n),
+                    byte_offset: Some(pos),
+                    ..ErrorContext::phase("parsing")
+                };
+                return Err(invalid::strict_error(reason, content, context));
+            }
+            let runs = buckets.entry_ref(chrom).or_default();
+            match runs.last_mut() {
+                Some(last) if last.1 == pos => last.1 = next,
+                _ => runs.push((pos, next)),
+            }
+        }
+
+        pos = next;
+    }
+    Ok(())
+}
+
+/// Copies the lines in `runs` to `buf`, through windows of at most `window` bytes.
+fn read_runs(file: &File, runs: &Runs, window: usize, buf: &mut Vec<u8>) -> io::Result<()> {
+    buf.clear();
+    for &(start, end) in runs {
+        let mut pos = start;
+        while pos < end {
+            let len = ((end - pos) as usize).min(window.max(1));
+            buf.extend_from_slice(Window::open(file, pos, len)?.as_bytes());
+            pos += len as u64;
+        }
+        // the last line of the file may lack its terminator
+        if buf.last() != Some(&b'\n') {
+            buf.push(b'\n');
+        }
+    }
+    Ok(())
+}
+
+/// Sorts `input` through windows of `window` bytes, see the module documentation.
+pub(crate) fn sort_windowed<'a, W: Write + Send>(
+    input: &'a Path,
+    output: OutputTarget<'a, W>,
+    threads: usize,
+    options: &SortOptions,
+    declared_format: Option<AnnotationFormat>,
+    mut ret: SortAnnotationsJobResult<'a>,
+    window: usize,
+) -> Result<SortAnnotationsJobResult<'a>, GtfSortError> {
+    if !bucketed::supports(options) {
+        return Err(GtfSortError::InvalidParameter(
+            "a windowed input cannot be combined with offset indexes, rejects files, manifests, quality metrics or coordinate-only sorting",
+        ));
+    }
+
+    ret.start_mem_mb = Some(crate::max_mem_usage_mb());
+    log::info!(
+        "Using {} threads, reading the input through {} byte windows",
+        threads,
+        window
+    );
+
+    let watchdog = Watchdog::spawn(options.max_rss_mb, options.timeout);
+    let sampler = ResourceSampler::spawn(options.sample_interval);
+
+    let file = File::open(input).map_err(|e| GtfSortError::IoError("opening input file", e))?;
+    let size = file
+        .metadata()
+        .map_err(|e| GtfSortError::IoError("getting input file metadata", e))?
+        .len();
+
+    let parse_progress = PhaseProgress::begin(options.progress.as_ref(), Phase::Parse, 1);
+    let mut buckets = HashMap::new();
+    let mut lines = LineCounts::default();
+    let mut chroms = ChromKeep::new(options);
+    let mut n = 0;
+    let mut format = declared_format;
+    let mut header = Header::default();
+    let mut parsing_secs = 0.0;
+    timed("Bucketing input lines", Some(&mut parsing_secs), || {
+        for_each_window(&file, size, window, |offset, text| {
+            watchdog.check()?;
+            if format.is_none() {
+                let detection = AnnotationFormat::detect(text);
+                log::info!("Detected {}", detection);
+                ret.format_detection = Some(detection);
+                format = Some(detection.or_fallback(input).ok_or_else(|| {
+                    GtfSortError::InvalidInput(
+                        "Could not detect the input format, please set it explicitly".to_string(),
+                    )
+                })?);
+            }
+            header.push_text(text);
+            let strict = format.filter(|_| options.parse_mode == ParseMode::Strict);
+            bucket_lines(
+                &mut buckets,
+                &mut lines,
+                &mut chroms,
+                strict,
+                &mut n,
+                offset,
+                text,
+            )
+        })
+    })?;
+    parse_progress.finish();
+    ret.input_mmaped = true;
+    ret.format = format;
+
+    let keys = buckets.keys().map(String::as_str).collect::<Vec<_>>();
+    let mut read_chrom = |chrom: &str, buf: &mut Vec<u8>| {
+        read_runs(&file, &buckets[chrom], window, buf)
+            .map_err(|e| GtfSortError::IoError("reading input window", e))
+    };
+    bucketed::sort_buckets(
+        &keys,
+        &mut read_chrom,
+        output,
+        options,
+        ret,
+        lines,
+        parsing_secs,
+        header,
+        watchdog,
+        sampler,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sort_annotations_with_options;
+
+    const GTF: &str = "chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"B\";\n\
+        #a comment\n\
+        chr1\ts\tgene\t20\t29\t.\t+\t.\tgene_id \"C\";\n\
+        chr2\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\";\n\
+        chr1\ts\ttranscript\t20\t29\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\";\n\
+        chr2\ts\texon\t1\t9\t.\t+\t.\tgene_id \"B\"; transcript_id \"B.1\"; exon_number \"1\";\n\
+        chr1\ts\texon\t20\t29\t.\t+\t.\tgene_id \"C\"; transcript_id \"C.1\"; exon_number \"1\";\n\
+        chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id \"A\";\n\
+        chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\";\n\
+        chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id \"A\"; transcript_id \"A.1\"; exon_number \"1\"";
+
+    #[test]
+    fn windows_end_at_lines() {
+        let path = crate::temp::TempPath::file(None, "windows", ".gtf").unwrap();
+        std::fs::write(&path, GTF).unwrap();
+        let file = File::open(&path).unwrap();
+
+        // smaller than a line, windows have to grow
+        let mut texts = Vec::new();
+        for_each_window(&file, GTF.len() as u64, 16, |offset, text| {
+            assert_eq!(&GTF[offset as usize..offset as usize + text.len()], text);
+            texts.push(text.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(texts.concat(), GTF);
+        assert!(texts.iter().rev().skip(1).all(|t| t.ends_with('\n')));
+
+        let (mut buckets, mut counts) = (HashMap::new(), LineCounts::default());
+        let options = SortOptions::default();
+        let (mut chroms, mut n) = (ChromKeep::new(&options), 0);
+        for_each_window(&file, GTF.len() as u64, 64, |offset, text| {
+            bucket_lines(
+                &mut buckets,
+                &mut counts,
+                &mut chroms,
+                None,
+                &mut n,
+                offset,
+                text,
+            )
+        })
+        .unwrap();
+        assert_eq!(n, 10);
+        assert_eq!(buckets["chr2"].len(), 3);
+        assert!(!buckets.contains_key("#a comment"));
+        assert_eq!((counts.comments, counts.blank), (1, 0));
+
+        let mut buf = Vec::new();
+        read_runs(&file, &buckets["chr1"], 7, &mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap().lines().count(), 6);
+    }
+
+    #[test]
+    fn windowed_sort_matches_whole_file_sort() {
+        let dir = crate::temp::TempPath::dir(None, "windowed").unwrap();
+        let (input, whole, windowed) = (dir.join("in.gtf"), dir.join("a.gtf"), dir.join("b.gtf"));
+        std::fs::write(&input, GTF).unwrap();
+
+        sort_annotations_with_options(&input, &whole, 2, &SortOptions::default()).unwrap();
+        let ret = sort_annotations_with_options(
+            &input,
+            &windowed,
+            2,
+            &SortOptions {
+                mmap_window: Some(100),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(ret.input_mmaped);
+        assert_eq!(
+            std::fs::read_to_string(&windowed).unwrap(),
+            std::fs::read_to_string(&whole).unwrap()
+        );
+    }
+}
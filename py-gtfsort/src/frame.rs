@@ -0,0 +1,187 @@
+//! Sorted annotations as a table, see [sort_to_frame].
+//!
+//! The table is built as an Arrow record batch and handed to pyarrow through the Arrow C
+//! data interface, so pandas and polars get it without the output being written and
+//! parsed again.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arrow_array::builder::{Float64Builder, LargeStringBuilder, UInt64Builder, UInt8Builder};
+use arrow_array::{Array, ArrayRef, RecordBatch, StructArray};
+use arrow_data::ffi::FFI_ArrowArray;
+use arrow_schema::ffi::FFI_ArrowSchema;
+use arrow_schema::{ArrowError, Field, Schema};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use gtfsort::{sorted::sort_annotations_iter, AnnotationFormat, SortOptions};
+
+use crate::to_py_err;
+
+/// Columns of the table, in this order.
+pub const COLUMNS: [&str; 11] = [
+    "seqname",
+    "source",
+    "feature",
+    "start",
+    "end",
+    "score",
+    "strand",
+    "frame",
+    "gene_id",
+    "transcript_id",
+    "attributes",
+];
+
+#[derive(Default)]
+struct Columns {
+    seqname: LargeStringBuilder,
+    source: LargeStringBuilder,
+    feature: LargeStringBuilder,
+    start: UInt64Builder,
+    end: UInt64Builder,
+    score: Float64Builder,
+    strand: LargeStringBuilder,
+    frame: UInt8Builder,
+    gene_id: LargeStringBuilder,
+    transcript_id: LargeStringBuilder,
+    attributes: LargeStringBuilder,
+}
+
+impl Columns {
+    fn push(&mut self, line: &str, gene_id: &str, transcript_id: Option<&str>) {
+        let mut fields = line.splitn(9, '\t');
+        let mut next = || fields.next().unwrap_or(".");
+        let missing = |v: &str| v.is_empty() || v == ".";
+
+        self.seqname.append_value(next());
+        self.source.append_value(next());
+        self.feature.append_value(next());
+        self.start.append_option(next().parse().ok());
+        self.end.append_option(next().parse().ok());
+        self.score.append_option(next().parse().ok());
+        self.strand.append_value(next());
+        self.frame.append_option(next().parse().ok());
+        self.gene_id.append_value(gene_id);
+        self.transcript_id.append_option(transcript_id);
+        match next() {
+            a if missing(a) => self.attributes.append_null(),
+            a => self.attributes.append_value(a),
+        }
+    }
+
+    fn finish(mut self) -> Result<RecordBatch, ArrowError> {
+        let columns: [ArrayRef; 11] = [
+            Arc::new(self.seqname.finish()),
+            Arc::new(self.source.finish()),
+            Arc::new(self.feature.finish()),
+            Arc::new(self.start.finish()),
+            Arc::new(self.end.finish()),
+            Arc::new(self.score.finish()),
+            Arc::new(self.strand.finish()),
+            Arc::new(self.frame.finish()),
+            Arc::new(self.gene_id.finish()),
+            Arc::new(self.transcript_id.finish()),
+            Arc::new(self.attributes.finish()),
+        ];
+        let fields = COLUMNS
+            .iter()
+            .zip(columns.iter())
+            .map(|(name, c)| Field::new(*name, c.data_type().clone(), c.null_count() > 0))
+            .collect::<Vec<_>>();
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns.to_vec())
+    }
+}
+
+/// One row per record of `input` in sorted order. Comments are left out.
+fn sorted_batch<const SEP: u8>(
+    input: &str,
+    threads: usize,
+) -> Result<Result<RecordBatch, ArrowError>, gtfsort::GtfSortError> {
+    let (sorted, _) = sort_annotations_iter::<SEP>(input, threads, &SortOptions::default())?;
+    let mut columns = Columns::default();
+    for gene in sorted.genes() {
+        columns.push(gene.line(), gene.id(), None);
+        for transcript in gene.transcripts() {
+            // children hanging directly off the gene belong to no transcript
+            let transcript_id = transcript.line().map(|_| transcript.id());
+            for line in transcript.lines() {
+                columns.push(line, gene.id(), transcript_id);
+            }
+        }
+    }
+    Ok(columns.finish())
+}
+
+/// Hands `batch` to pyarrow, which takes ownership of its buffers.
+fn to_pyarrow(py: Python, batch: RecordBatch) -> PyResult<PyObject> {
+    let to_py = |e: ArrowError| PyValueError::new_err(e.to_string());
+    let schema = FFI_ArrowSchema::try_from(batch.schema().as_ref()).map_err(to_py)?;
+    let array = FFI_ArrowArray::new(&StructArray::from(batch).to_data());
+
+    let pyarrow = py.import("pyarrow")?;
+    let batch = pyarrow.getattr("RecordBatch")?.call_method1(
+        "_import_from_c",
+        (
+            &array as *const FFI_ArrowArray as usize,
+            &schema as *const FFI_ArrowSchema as usize,
+        ),
+    )?;
+    let table = pyarrow
+        .getattr("Table")?
+        .call_method1("from_batches", (vec![batch],))?;
+    Ok(table.into_py(py))
+}
+
+/// Sorts the GTF/GFF3 file at `path` (gzip or not) and returns its records as a table
+/// with the columns seqname, source, feature, start, end, score, strand, frame, gene_id,
+/// transcript_id and attributes.
+///
+/// `to` is "pandas" (default), "polars" or "arrow" for a pyarrow Table; pyarrow is
+/// needed in every case. Missing scores, frames, transcripts and attributes are nulls.
+#[pyfunction]
+pub fn sort_to_frame(
+    py: Python,
+    path: PathBuf,
+    threads: Option<usize>,
+    to: Option<&str>,
+) -> PyResult<PyObject> {
+    let to = to.unwrap_or("pandas");
+    if !["pandas", "polars", "arrow"].contains(&to) {
+        return Err(PyValueError::new_err(format!(
+            "unknown frame {:?}, expected pandas, polars or arrow",
+            to
+        )));
+    }
+    let threads = threads.filter(|t| *t > 0).unwrap_or_else(num_cpus::get);
+
+    let batch = py
+        .allow_threads(|| {
+            let input = gtfsort::gzip::read_to_string(&path)?;
+            let format = AnnotationFormat::from_extension(&path)
+                .or_else(|| AnnotationFormat::detect(&input).format)
+                .ok_or_else(|| {
+                    gtfsort::GtfSortError::InvalidInput(format!(
+                        "could not detect the format of {:?}",
+                        path
+                    ))
+                })?;
+            match format {
+                AnnotationFormat::Gtf => sorted_batch::<b' '>(&input, threads),
+                AnnotationFormat::Gff3 => sorted_batch::<b'='>(&input, threads),
+            }
+        })
+        .map_err(|e| to_py_err(py, e))?
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let table = to_pyarrow(py, batch)?;
+    match to {
+        "pandas" => table.call_method0(py, "to_pandas"),
+        "polars" => Ok(py
+            .import("polars")?
+            .call_method1("from_arrow", (table,))?
+            .into_py(py)),
+        _ => Ok(table),
+    }
+}
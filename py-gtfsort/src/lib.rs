@@ -1,14 +1,61 @@
-use pyo3::exceptions::PyValueError;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::PyBytes;
 use pyo3::wrap_pyfunction;
 
 use num_cpus;
 
+mod frame;
+
+use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, Receiver};
 use std::sync::{Arc, Mutex};
 
-use gtfsort::{sort_annotations, sort_annotations_string};
+use gtfsort::{
+    chunks::SortedChunks, sort_annotations, sort_annotations_string,
+    sort_annotations_string_by_gene, sort_annotations_string_by_transcript, AnnotationFormat,
+    ErrorCode, SortOptions,
+};
+
+create_exception!(
+    gtfsortpy,
+    GtfSortError,
+    PyValueError,
+    "A sort job failed. `code` is the name of the stable error code (e.g. 'invalid_input'), `phase`, `chrom`, `line` and `byte_offset` tell where it happened and are None when unknown."
+);
+
+/// Converts an error of a job into a [GtfSortError] carrying its code and context, or a
+/// RuntimeError for internal errors (bugs).
+fn to_py_err(py: Python, e: gtfsort::GtfSortError) -> PyErr {
+    if e.code() == ErrorCode::Internal {
+        return PyRuntimeError::new_err(e.to_string());
+    }
+
+    let context = e.context();
+    let err = GtfSortError::new_err(e.to_string());
+    let value = err.value(py);
+    let set = || -> PyResult<()> {
+        value.setattr("code", e.code().name())?;
+        value.setattr("phase", context.phase)?;
+        value.setattr("chrom", context.chrom.as_deref())?;
+        value.setattr("line", context.line)?;
+        value.setattr("byte_offset", context.byte_offset)?;
+        Ok(())
+    };
+    match set() {
+        Ok(()) => err,
+        Err(e) => e,
+    }
+}
+
+/// Transcript blocks buffered ahead of the Python consumer.
+const TRANSCRIPT_BLOCKS_AHEAD: usize = 64;
+
+/// Gene blocks buffered ahead of the Python consumer.
+const GENE_BLOCKS_AHEAD: usize = 64;
 
 #[cfg(feature = "test")]
 use gtfsort::test_utils::get_test_file_gff3_gencode_mouse_m35;
@@ -18,19 +65,14 @@ fn sort(py: Python, input: PyObject, output: PyObject, threads: Option<usize>) -
     let input = PathBuf::from(input.extract::<String>(py)?);
     let output = PathBuf::from(output.extract::<String>(py)?);
 
-    let job_info = sort_annotations(&input, &output, threads.unwrap_or(num_cpus::get()));
-
-    match job_info {
-        Ok(_) => Ok(format!(
+    match sort_annotations(&input, &output, threads.unwrap_or(num_cpus::get())) {
+        Ok(info) => Ok(format!(
             "File succesfully sorted! Result at {}. Used {} Mb. Elapsed: {}",
             &output.to_string_lossy(),
-            job_info.as_ref().unwrap().end_mem_mb.unwrap_or(f64::NAN)
-                - job_info.as_ref().unwrap().start_mem_mb.unwrap_or(f64::NAN),
-            job_info.as_ref().unwrap().parsing_secs
-                + job_info.as_ref().unwrap().indexing_secs
-                + job_info.as_ref().unwrap().writing_secs
+            info.end_mem_mb.unwrap_or(f64::NAN) - info.start_mem_mb.unwrap_or(f64::NAN),
+            info.parsing_secs + info.indexing_secs + info.writing_secs
         )),
-        Err(e) => Ok(format!("Error: {}", e)),
+        Err(e) => Err(to_py_err(py, e)),
     }
 }
 
@@ -63,10 +105,252 @@ fn sort_from_string<'a>(
             output_callback.call1(py, (py_bytes,))?;
             Ok(())
         }
-        Err(e) => Err(PyValueError::new_err(format!("Error: {:?}", e))),
+        Err(e) => Err(to_py_err(py, e)),
+    }
+}
+
+/// (chrom, gene_id, transcript_id, lines) of one sorted transcript, or the error that ended the job.
+type TranscriptItem = Result<(String, String, String, Vec<u8>), gtfsort::GtfSortError>;
+
+/// Iterator over the transcripts of a sort running in the background, see [sort_transcripts].
+#[pyclass]
+struct TranscriptBlocks {
+    rx: Receiver<TranscriptItem>,
+}
+
+#[pymethods]
+impl TranscriptBlocks {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        let rx = &mut self.rx;
+        match py.allow_threads(move || rx.recv()) {
+            Ok(Ok((chrom, gene_id, transcript_id, lines))) => Ok(Some(
+                (chrom, gene_id, transcript_id, PyBytes::new(py, &lines)).into_py(py),
+            )),
+            Ok(Err(e)) => Err(to_py_err(py, e)),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Sorts `input` in the background, yielding one (chrom, gene_id, transcript_id, lines)
+/// tuple per transcript, where `lines` holds the transcript line followed by its children.
+///
+/// `fmt` is "gtf" (default) or "gff3", as for [sort_iter].
+#[pyfunction]
+fn sort_transcripts(
+    input: String,
+    threads: Option<usize>,
+    fmt: Option<&str>,
+) -> PyResult<TranscriptBlocks> {
+    let format = match fmt {
+        Some(fmt) => AnnotationFormat::from_str(fmt).map_err(PyValueError::new_err)?,
+        None => AnnotationFormat::Gtf,
+    };
+    let threads = threads.filter(|t| *t > 0).unwrap_or_else(num_cpus::get);
+    let (tx, rx) = sync_channel::<TranscriptItem>(TRANSCRIPT_BLOCKS_AHEAD);
+
+    std::thread::spawn(move || {
+        let mut on_transcript = |block: &gtfsort::TranscriptBlock| {
+            tx.send(Ok((
+                block.chrom.to_string(),
+                block.gene_id.to_string(),
+                block.transcript_id.to_string(),
+                block.lines.to_vec(),
+            )))
+            // the iterator was dropped, stop sorting
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        };
+        let options = SortOptions::default();
+        let job = match format {
+            AnnotationFormat::Gtf => sort_annotations_string_by_transcript::<b' ', _>(
+                &input,
+                &mut on_transcript,
+                threads,
+                &options,
+            ),
+            AnnotationFormat::Gff3 => sort_annotations_string_by_transcript::<b'=', _>(
+                &input,
+                &mut on_transcript,
+                threads,
+                &options,
+            ),
+        };
+        if let Err(e) = job {
+            if !e.is_broken_pipe() {
+                let _ = tx.send(Err(e));
+            }
+        }
+    });
+
+    Ok(TranscriptBlocks { rx })
+}
+
+/// (chrom, gene_id, lines) of one sorted gene, or the error that ended the job.
+type GeneItem = Result<(String, String, Vec<u8>), gtfsort::GtfSortError>;
+
+enum OutputKind {
+    Chunks(SortedChunks),
+    Lines {
+        chunks: SortedChunks,
+        /// Start of a line cut at the end of the last chunk.
+        carry: Vec<u8>,
+        lines: VecDeque<String>,
+    },
+    Genes(Receiver<GeneItem>),
+}
+
+/// Iterator over the output of a sort running in the background, see [sort_iter].
+#[pyclass]
+struct SortedOutput {
+    kind: OutputKind,
+}
+
+#[pymethods]
+impl SortedOutput {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        match &mut self.kind {
+            OutputKind::Chunks(chunks) => match py.allow_threads(|| chunks.next_chunk()) {
+                Ok(chunk) => Ok(chunk.map(|c| PyBytes::new(py, &c).into_py(py))),
+                Err(e) => Err(to_py_err(py, e)),
+            },
+            OutputKind::Lines {
+                chunks,
+                carry,
+                lines,
+            } => loop {
+                if let Some(line) = lines.pop_front() {
+                    return Ok(Some(line.into_py(py)));
+                }
+                match py.allow_threads(|| chunks.next_chunk()) {
+                    Ok(Some(chunk)) => {
+                        carry.extend_from_slice(&chunk);
+                        // chunks are cut anywhere, only split complete lines
+                        let Some(end) = carry.iter().rposition(|b| *b == b'\n') else {
+                            continue;
+                        };
+                        let rest = carry.split_off(end + 1);
+                        let complete = std::mem::replace(carry, rest);
+                        lines.extend(
+                            String::from_utf8_lossy(&complete)
+                                .lines()
+                                .map(str::to_string),
+                        );
+                    }
+                    Ok(None) if carry.is_empty() => return Ok(None),
+                    Ok(None) => {
+                        let last = String::from_utf8_lossy(&std::mem::take(carry)).into_owned();
+                        return Ok(Some(last.trim_end_matches('\r').into_py(py)));
+                    }
+                    Err(e) => return Err(to_py_err(py, e)),
+                }
+            },
+            OutputKind::Genes(rx) => match py.allow_threads(move || rx.recv()) {
+                Ok(Ok((chrom, gene_id, lines))) => {
+                    Ok(Some((chrom, gene_id, PyBytes::new(py, &lines)).into_py(py)))
+                }
+                Ok(Err(e)) => Err(to_py_err(py, e)),
+                Err(_) => Ok(None),
+            },
+        }
+    }
+}
+
+/// Sorts `text` in the background, yielding its output lazily so that it is never held
+/// whole in memory.
+///
+/// `fmt` is "gtf" (default) or "gff3". With `mode` "chunks" (default) the output is
+/// yielded as bytes of about 64 KiB, cut anywhere; with "lines" as str lines without
+/// their line break; with "genes" as one (chrom, gene_id, lines) tuple per gene, where
+/// `lines` holds the gene line followed by its transcripts and their children. Only
+/// records are yielded in "genes" mode: the header comments and any `##FASTA` section are
+/// left out, use "chunks" or "lines" to keep them.
+#[pyfunction]
+fn sort_iter(
+    text: String,
+    fmt: Option<&str>,
+    threads: Option<usize>,
+    mode: Option<&str>,
+) -> PyResult<SortedOutput> {
+    let format = match fmt {
+        Some(fmt) => AnnotationFormat::from_str(fmt).map_err(PyValueError::new_err)?,
+        None => AnnotationFormat::Gtf,
+    };
+    let threads = threads.filter(|t| *t > 0).unwrap_or_else(num_cpus::get);
+
+    let kind = match mode.unwrap_or("chunks") {
+        "chunks" => OutputKind::Chunks(spawn_chunks(text, format, threads)),
+        "lines" => OutputKind::Lines {
+            chunks: spawn_chunks(text, format, threads),
+            carry: Vec::new(),
+            lines: VecDeque::new(),
+        },
+        "genes" => OutputKind::Genes(spawn_genes(text, format, threads)),
+        mode => {
+            return Err(PyValueError::new_err(format!(
+                "unknown mode {:?}, expected chunks, lines or genes",
+                mode
+            )))
+        }
+    };
+    Ok(SortedOutput { kind })
+}
+
+fn spawn_chunks(text: String, format: AnnotationFormat, threads: usize) -> SortedChunks {
+    let options = SortOptions::default();
+    match format {
+        AnnotationFormat::Gtf => SortedChunks::spawn::<b' '>(text, threads, options),
+        AnnotationFormat::Gff3 => SortedChunks::spawn::<b'='>(text, threads, options),
     }
 }
 
+fn spawn_genes(text: String, format: AnnotationFormat, threads: usize) -> Receiver<GeneItem> {
+    let (tx, rx) = sync_channel::<GeneItem>(GENE_BLOCKS_AHEAD);
+
+    std::thread::spawn(move || {
+        let mut on_gene = |block: &gtfsort::GeneBlock| {
+            tx.send(Ok((
+                block.chrom.to_string(),
+                block.gene_id.to_string(),
+                block.lines.to_vec(),
+            )))
+            // the iterator was dropped, stop sorting
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        };
+        let options = SortOptions::default();
+        let job = match format {
+            AnnotationFormat::Gtf => {
+                sort_annotations_string_by_gene::<b' ', _>(&text, &mut on_gene, threads, &options)
+            }
+            AnnotationFormat::Gff3 => {
+                sort_annotations_string_by_gene::<b'=', _>(&text, &mut on_gene, threads, &options)
+            }
+        };
+        if let Err(e) = job {
+            if !e.is_broken_pipe() {
+                let _ = tx.send(Err(e));
+            }
+        }
+    });
+
+    rx
+}
+
+/// Installs the gtfsort console logger at `level` (trace, debug, info, warn, error).
+///
+/// Returns False if a logger is already configured, in which case nothing changes.
+#[pyfunction]
+fn init_logger(level: Option<&str>) -> bool {
+    gtfsort::logging::try_init_logger(gtfsort::logging::parse_level(level.unwrap_or("info")))
+}
+
 #[cfg(feature = "test")]
 #[pyfunction]
 fn get_test_file() -> PyResult<PathBuf> {
@@ -76,9 +360,16 @@ fn get_test_file() -> PyResult<PathBuf> {
 
 #[pymodule]
 #[pyo3(name = "gtfsortpy")]
-fn gtfsortpy(_py: Python, m: &PyModule) -> PyResult<()> {
+fn gtfsortpy(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("GtfSortError", py.get_type::<GtfSortError>())?;
     m.add_function(wrap_pyfunction!(sort, m)?)?;
     m.add_function(wrap_pyfunction!(sort_from_string, m)?)?;
+    m.add_function(wrap_pyfunction!(sort_transcripts, m)?)?;
+    m.add_class::<TranscriptBlocks>()?;
+    m.add_function(wrap_pyfunction!(sort_iter, m)?)?;
+    m.add_class::<SortedOutput>()?;
+    m.add_function(wrap_pyfunction!(frame::sort_to_frame, m)?)?;
+    m.add_function(wrap_pyfunction!(init_logger, m)?)?;
 
     #[cfg(feature = "test")]
     m.add_function(wrap_pyfunction!(get_test_file, m)?)?;
@@ -2,6 +2,7 @@ import gtfsortpy
 import os
 import pandas as pd
 import pytest
+import tempfile
 from typing import Tuple
 import unittest
 
@@ -41,3 +42,84 @@ class TestGtf(unittest.TestCase):
         chrom_order = pd.read_csv(self.out, sep='\t', usecols=[0], header=None)[0].unique().tolist()
 
         self.assertEqual(chrom_order, rule)
+
+class TestTranscripts(unittest.TestCase):
+
+    GTF = (
+        'chr2\ts\tgene\t1\t9\t.\t+\t.\tgene_id "B";\n'
+        'chr2\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id "B"; transcript_id "B.1";\n'
+        'chr2\ts\texon\t1\t9\t.\t+\t.\tgene_id "B"; transcript_id "B.1"; exon_number "1";\n'
+        'chr1\ts\tgene\t1\t9\t.\t+\t.\tgene_id "A";\n'
+        'chr1\ts\ttranscript\t1\t9\t.\t+\t.\tgene_id "A"; transcript_id "A.1";\n'
+        'chr1\ts\texon\t1\t9\t.\t+\t.\tgene_id "A"; transcript_id "A.1"; exon_number "1";\n'
+    )
+
+    def test_blocks_follow_output_order(self):
+        blocks = list(gtfsortpy.sort_transcripts(self.GTF, NTHREADS))
+
+        self.assertEqual([b[:3] for b in blocks], [('chr1', 'A', 'A.1'), ('chr2', 'B', 'B.1')])
+        for _, _, _, lines in blocks:
+            self.assertEqual(lines.count(b'\n'), 2)
+            self.assertIn(b'\ttranscript\t', lines.split(b'\n', 1)[0])
+
+    def test_gff3_blocks(self):
+        gff3 = (
+            '##gff-version 3\n'
+            'chr1\ts\texon\t1\t9\t.\t+\t.\tID=e1;Parent=A.1\n'
+            'chr1\ts\tmRNA\t1\t9\t.\t+\t.\tID=A.1;Parent=A\n'
+            'chr1\ts\tgene\t1\t9\t.\t+\t.\tID=A\n'
+        )
+        blocks = list(gtfsortpy.sort_transcripts(gff3, NTHREADS, 'gff3'))
+
+        self.assertEqual([b[:3] for b in blocks], [('chr1', 'A', 'A.1')])
+        self.assertTrue(blocks[0][3].startswith(b'chr1\ts\tmRNA\t'))
+        with self.assertRaises(ValueError):
+            gtfsortpy.sort_transcripts(gff3, NTHREADS, 'bed')
+
+class TestSortIter(unittest.TestCase):
+
+    GTF = TestTranscripts.GTF
+
+    def test_modes_yield_the_same_output(self):
+        expected = b''.join(gtfsortpy.sort_iter(self.GTF, 'gtf', NTHREADS))
+
+        lines = list(gtfsortpy.sort_iter(self.GTF, 'gtf', NTHREADS, 'lines'))
+        self.assertEqual(''.join(l + '\n' for l in lines).encode(), expected)
+
+        genes = list(gtfsortpy.sort_iter(self.GTF, 'gtf', NTHREADS, 'genes'))
+        self.assertEqual([g[:2] for g in genes], [('chr1', 'A'), ('chr2', 'B')])
+        self.assertEqual(b''.join(g[2] for g in genes), expected)
+
+    def test_genes_leave_the_header_out(self):
+        text = '#!genome-build test\n' + self.GTF
+        lines = list(gtfsortpy.sort_iter(text, 'gtf', NTHREADS, 'lines'))
+        self.assertEqual(lines[0], '#!genome-build test')
+
+        genes = list(gtfsortpy.sort_iter(text, 'gtf', NTHREADS, 'genes'))
+        self.assertEqual(b''.join(g[2] for g in genes), b''.join(l.encode() + b'\n' for l in lines[1:]))
+
+    def test_unknown_mode(self):
+        with self.assertRaises(ValueError):
+            gtfsortpy.sort_iter(self.GTF, 'gtf', NTHREADS, 'rows')
+
+class TestSortToFrame(unittest.TestCase):
+
+    def test_rows_follow_output_order(self):
+        pytest.importorskip('pyarrow')
+        with tempfile.TemporaryDirectory() as tmp:
+            path = os.path.join(tmp, 'frame.gtf')
+            with open(path, 'w') as f:
+                f.write(TestTranscripts.GTF)
+
+            frame = gtfsortpy.sort_to_frame(path, NTHREADS)
+
+        self.assertEqual(list(frame.columns), [
+            'seqname', 'source', 'feature', 'start', 'end', 'score', 'strand', 'frame',
+            'gene_id', 'transcript_id', 'attributes',
+        ])
+        self.assertEqual(frame['seqname'].tolist(), ['chr1'] * 3 + ['chr2'] * 3)
+        self.assertEqual(frame['feature'].tolist(), ['gene', 'transcript', 'exon'] * 2)
+        self.assertEqual(frame['gene_id'].tolist(), ['A'] * 3 + ['B'] * 3)
+        self.assertTrue(frame['transcript_id'].isna()[0])
+        self.assertEqual(frame['transcript_id'][1], 'A.1')
+        self.assertEqual(frame['start'].tolist(), [1] * 6)